@@ -0,0 +1,113 @@
+use actix_web::{web, Responder};
+use chrono::Utc;
+use futures::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use log::{info, error};
+use crate::lib::api_auth::hash_key;
+use crate::lib::config::Config;
+use crate::lib::constants::COLL_API_KEYS;
+use crate::lib::errors::ApiError;
+use crate::lib::mongodb::get_collection;
+use crate::lib::response::{ok_json, to_normalized_value};
+use crate::structs::api_keys::{ApiKey, ApiKeyScope};
+
+
+/// Characters of the key kept in `prefix`
+const KEY_PREFIX_LEN: usize = 12;
+
+
+/// Body of an API key request
+#[derive(Debug, Deserialize)]
+pub struct ApiKeyRequest {
+    pub name: String,
+    pub scope: ApiKeyScope,
+}
+
+
+/// POST /apiKeys
+///
+/// Creates an API key with the given name and scope (`read-only`, `deploy` or `admin`). The
+/// key itself is only returned in this response, only its hash is stored.
+pub async fn create_api_key(config: web::Data<Config>, body: web::Json<ApiKeyRequest>) -> Result<impl Responder, ApiError> {
+    let body = body.into_inner();
+    let name = body.name.trim();
+    if name.is_empty() {
+        return Err(ApiError::bad_request("name must not be empty"));
+    }
+
+    let key = format!("wasmiot_{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple());
+    let mut api_key = ApiKey {
+        id: None,
+        name: name.to_string(),
+        key_hash: hash_key(&key),
+        prefix: key[..KEY_PREFIX_LEN].to_string(),
+        scope: body.scope,
+        created_at: Utc::now(),
+        last_used_at: None,
+        revoked_at: None,
+    };
+    let collection = get_collection::<ApiKey>(&config.database, COLL_API_KEYS).await;
+    let result = collection.insert_one(&api_key).await.map_err(|e| {
+        error!("Failed to save API key: {}", e);
+        ApiError::db("Failed to save API key")
+    })?;
+    api_key.id = result.inserted_id.as_object_id();
+    info!("🔑 API key '{}' created with the {} scope", api_key.name, api_key.scope.as_str());
+    ok_json(&json!({
+        "message": "API key created, store it now as it is not shown again",
+        "key": key,
+        "apiKey": without_hash(&api_key)?,
+    }))
+}
+
+
+/// GET /apiKeys
+///
+/// Lists the API keys, revoked ones included, without their hashes.
+pub async fn get_api_keys(config: web::Data<Config>) -> Result<impl Responder, ApiError> {
+    let collection = get_collection::<ApiKey>(&config.database, COLL_API_KEYS).await;
+    let keys: Vec<ApiKey> = collection
+        .find(doc! {})
+        .sort(doc! { "createdAt": 1 })
+        .await
+        .map_err(ApiError::db)?
+        .try_collect()
+        .await
+        .map_err(ApiError::db)?;
+    let keys = keys.iter().map(without_hash).collect::<Result<Vec<_>, _>>()?;
+    ok_json(&keys)
+}
+
+
+/// DELETE /apiKeys/{key_id}
+///
+/// Revokes an API key. The key is kept in the listing with `revokedAt` set, so that audit log
+/// entries made with it can still be traced to it.
+pub async fn revoke_api_key(config: web::Data<Config>, path: web::Path<String>) -> Result<impl Responder, ApiError> {
+    let key_id = path.into_inner();
+    let oid = ObjectId::parse_str(&key_id)
+        .map_err(|_| ApiError::bad_request("Invalid key id (expected ObjectId hex string)"))?;
+    let collection = get_collection::<ApiKey>(&config.database, COLL_API_KEYS).await;
+    let update = doc! { "$set": { "revokedAt": mongodb::bson::DateTime::from_chrono(Utc::now()) } };
+    let result = collection
+        .update_one(doc! { "_id": oid, "revokedAt": null }, update)
+        .await
+        .map_err(ApiError::db)?;
+    if result.matched_count == 0 {
+        return Err(ApiError::not_found(format!("API key with id {} not found or already revoked", key_id)));
+    }
+    info!("🔑 API key {} revoked", key_id);
+    ok_json(&json!({ "message": "API key revoked", "id": key_id }))
+}
+
+
+/// The key as JSON without its hash
+fn without_hash(api_key: &ApiKey) -> Result<Value, ApiError> {
+    let mut value = to_normalized_value(api_key)?;
+    if let Some(fields) = value.as_object_mut() {
+        fields.remove("keyHash");
+    }
+    Ok(value)
+}
@@ -0,0 +1,86 @@
+//! # audit_log.rs
+//!
+//! Listing of the audit log of mutating API calls (see lib/audit_log.rs).
+
+use std::collections::HashMap;
+use actix_web::{web, Responder};
+use chrono::{DateTime, Utc};
+use log::error;
+use mongodb::bson::{doc, Document};
+use crate::api::logs::split_values;
+use crate::lib::config::Config;
+use crate::lib::constants::COLL_AUDIT_LOG;
+use crate::lib::errors::ApiError;
+use crate::lib::mongodb::get_collection;
+use crate::lib::pagination::{find_page, page_response, Pagination};
+
+
+/// Sort keys accepted by the audit log listing
+const AUDIT_LOG_SORT_FIELDS: &[(&str, &str)] = &[
+    ("timestamp", "timestamp"),
+    ("actor", "actor"),
+    ("route", "route"),
+    ("status", "status"),
+];
+
+
+/// GET /auditLog
+///
+/// Lists the recorded POST, PUT, PATCH and DELETE calls, newest first. Supports `limit`,
+/// `offset` and `sort` (`timestamp`, `actor`, `route`, `status`), see `Pagination`. Filters:
+/// - `actor`, `method`, `route`, `targetId`, `outcome`: exact matches, several values can be
+///   given separated by commas
+/// - `status`: HTTP status code of the response
+/// - `requestId`: the `X-Request-Id` of the call
+/// - `after`, `before` (exclusive), `from`, `to` (inclusive): RFC 3339 bounds on when the
+///   call was made
+pub async fn get_audit_log(config: web::Data<Config>, query: web::Query<HashMap<String, String>>) -> Result<impl Responder, ApiError> {
+    let pagination = Pagination::from_query(&query, AUDIT_LOG_SORT_FIELDS, Some(doc! { "timestamp": -1 }))?;
+    let filter = audit_log_filter(&query)?;
+
+    let collection = get_collection::<Document>(&config.database, COLL_AUDIT_LOG).await;
+    match find_page(&collection, filter, &pagination).await {
+        Ok(page) => page_response(&page),
+        Err(e) => {
+            error!("❌ Failed to fetch the audit log: {}", e);
+            Err(ApiError::internal_error("Failed to fetch the audit log"))
+        }
+    }
+}
+
+
+/// Filter of GET /auditLog from its query parameters
+fn audit_log_filter(query: &HashMap<String, String>) -> Result<Document, ApiError> {
+    let mut filter = doc! {};
+    for (param, field) in [("actor", "actor"), ("route", "route"), ("targetId", "targetId"), ("outcome", "outcome")] {
+        if let Some(values) = query.get(param) {
+            filter.insert(field, doc! { "$in": split_values(values) });
+        }
+    }
+    if let Some(methods) = query.get("method") {
+        let methods: Vec<String> = split_values(methods).iter().map(|m| m.to_uppercase()).collect();
+        filter.insert("method", doc! { "$in": methods });
+    }
+    if let Some(status) = query.get("status") {
+        let status: i32 = status
+            .parse()
+            .map_err(|_| ApiError::bad_request(format!("invalid status '{}', expected a HTTP status code", status)))?;
+        filter.insert("status", status);
+    }
+    if let Some(request_id) = query.get("requestId") {
+        filter.insert("requestId", request_id.as_str());
+    }
+
+    let mut timestamp = doc! {};
+    for (param, op) in [("after", "$gt"), ("before", "$lt"), ("from", "$gte"), ("to", "$lte")] {
+        if let Some(value) = query.get(param) {
+            let dt = DateTime::parse_from_rfc3339(value)
+                .map_err(|_| ApiError::bad_request(format!("{} must be an RFC 3339 timestamp", param)))?;
+            timestamp.insert(op, mongodb::bson::DateTime::from_chrono(dt.with_timezone(&Utc)));
+        }
+    }
+    if !timestamp.is_empty() {
+        filter.insert("timestamp", timestamp);
+    }
+    Ok(filter)
+}
@@ -0,0 +1,107 @@
+use actix_web::{web, HttpRequest, Responder};
+use chrono::Utc;
+use futures::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId};
+use serde::Deserialize;
+use serde_json::json;
+use log::{info, error};
+use crate::api::device::device_filter;
+use crate::lib::card_auth::require_card_admin;
+use crate::lib::config::Config;
+use crate::lib::constants::{COLL_CARD_TOKENS, COLL_DEVICE};
+use crate::lib::errors::ApiError;
+use crate::lib::mongodb::{find_one, get_collection};
+use crate::lib::response::ok_json;
+use crate::structs::card_tokens::{CardToken, CardTokenScope};
+use crate::structs::device::DeviceDoc;
+
+
+/// Body of a card token request, exactly one of `device` and `zone` must be given
+#[derive(Debug, Deserialize)]
+pub struct CardTokenRequest {
+    pub device: Option<String>, // Device id or name
+    pub zone: Option<String>,
+    pub label: Option<String>,
+}
+
+
+/// POST /cardTokens
+///
+/// Creates a card token for a device (by id or name) or a zone. The token itself is only
+/// returned in this response, listings show just its last characters.
+pub async fn create_card_token(config: web::Data<Config>, req: HttpRequest, body: web::Json<CardTokenRequest>) -> Result<impl Responder, ApiError> {
+    require_card_admin(&config.cards, &req)?;
+    let body = body.into_inner();
+    let scope = match (body.device, body.zone) {
+        (Some(device_key), None) => {
+            let device = find_one::<DeviceDoc>(&config.database, COLL_DEVICE, device_filter(&device_key))
+                .await
+                .map_err(ApiError::db)?
+                .ok_or_else(|| ApiError::not_found(format!("no device matches '{}'", device_key)))?;
+            let device = device.id.ok_or_else(|| ApiError::db("device missing _id"))?;
+            CardTokenScope::Device { device }
+        }
+        (None, Some(zone)) if !zone.trim().is_empty() => CardTokenScope::Zone { zone: zone.trim().to_string() },
+        _ => return Err(ApiError::bad_request("expected exactly one of: device, zone")),
+    };
+
+    let mut token = CardToken {
+        id: None,
+        token: format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple()),
+        scope,
+        label: body.label,
+        created_at: Utc::now(),
+    };
+    let collection = get_collection::<CardToken>(&config.database, COLL_CARD_TOKENS).await;
+    let result = collection.insert_one(&token).await.map_err(|e| {
+        error!("Failed to save card token: {}", e);
+        ApiError::db("Failed to save card token")
+    })?;
+    token.id = result.inserted_id.as_object_id();
+    info!("🔑 Card token created for {:?}", token.scope);
+    ok_json(&json!({ "message": "Card token created, store it now as it is not shown again", "cardToken": token }))
+}
+
+
+/// GET /cardTokens
+///
+/// Lists the card tokens, with only the last characters of each token shown.
+pub async fn get_card_tokens(config: web::Data<Config>, req: HttpRequest) -> Result<impl Responder, ApiError> {
+    require_card_admin(&config.cards, &req)?;
+    let collection = get_collection::<CardToken>(&config.database, COLL_CARD_TOKENS).await;
+    let tokens: Vec<CardToken> = collection
+        .find(doc! {})
+        .sort(doc! { "createdAt": 1 })
+        .await
+        .map_err(ApiError::db)?
+        .try_collect()
+        .await
+        .map_err(ApiError::db)?;
+    let tokens: Vec<CardToken> = tokens
+        .into_iter()
+        .map(|mut t| {
+            let hint = t.token.len().saturating_sub(4);
+            t.token = format!("…{}", &t.token[hint..]);
+            t
+        })
+        .collect();
+    ok_json(&tokens)
+}
+
+
+/// DELETE /cardTokens/{token_id}
+///
+/// Revokes a card token. Cards already submitted with it are kept.
+pub async fn delete_card_token(config: web::Data<Config>, req: HttpRequest, path: web::Path<String>) -> Result<impl Responder, ApiError> {
+    require_card_admin(&config.cards, &req)?;
+    let token_id = path.into_inner();
+    let oid = ObjectId::parse_str(&token_id)
+        .map_err(|_| ApiError::bad_request("Invalid token id (expected ObjectId hex string)"))?;
+    let collection = get_collection::<CardToken>(&config.database, COLL_CARD_TOKENS).await;
+    let result = collection.delete_one(doc! { "_id": oid }).await.map_err(ApiError::db)?;
+    if result.deleted_count == 0 {
+        return Err(ApiError::not_found(format!("Card token with id {} not found", token_id)));
+    }
+    info!("🔑 Card token {} revoked", token_id);
+    ok_json(&json!({ "message": "Card token revoked", "id": token_id }))
+}
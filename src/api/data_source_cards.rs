@@ -1,12 +1,20 @@
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
 use chrono::{DateTime, Utc};
-use futures::TryStreamExt;
 use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::options::ReturnDocument;
+use serde::Deserialize;
 use serde_json::Value;
-use crate::lib::constants::COLL_DATASOURCE_CARDS;
-use crate::lib::mongodb::get_collection;
+use crate::lib::config::Config;
+use crate::lib::constants::{COLL_DATASOURCE_CARDS, COLL_DEVICE};
+use crate::lib::mongodb::{find_one, get_collection};
 use crate::structs::data_source_cards::DatasourceCard;
+use crate::structs::device::DeviceDoc;
 use crate::lib::errors::ApiError;
+use crate::api::revalidation::{trigger_revalidation, RevalidationScope};
+use crate::lib::response::ok_json;
+use crate::lib::pagination::{find_page, page_response, Pagination, CARD_SORT_FIELDS};
+use crate::lib::card_auth::{authenticate_card_submitter, authorize_card};
+use crate::lib::odrl::{allow_unregistered, check_reference, invalid_document_response, is_dry_run, parse_data_source_card};
 use log::{info, error};
 
 
@@ -14,78 +22,54 @@ use log::{info, error};
 /// 
 /// Takes a json document (odrl) and extracts relevant fields to create 
 /// a new data source card for the device/node specified in the json document.
-pub async fn create_data_source_card(card: web::Json<Value>) -> Result<impl Responder, ApiError> {
+/// With `?dryRun=true` the document is only validated. The referenced device must exist,
+/// unless `?allowUnregistered=true` is given. The card must be of a device the card token of
+/// the request covers, see `authorize_card`.
+pub async fn create_data_source_card(
+    config: web::Data<Config>,
+    req: HttpRequest,
+    card: web::Json<Value>,
+    query: web::Query<std::collections::HashMap<String, String>>,
+) -> Result<impl Responder, ApiError> {
     info!("Received datasourcecard data: {:?}", card);
+    let submitter = authenticate_card_submitter(&config, &req).await?;
 
-    // Extract the first item in "asset" array in the document.
-    // This is assumed to contain the required information, other
-    // items in the array are ignored.
-    let asset = match card
-        .get("asset")
-        .and_then(|a| a.as_array())
-        .and_then(|arr| arr.get(0))
-    {
-        Some(a) => a,
-        None => {
-            return Err(ApiError::bad_request("Invalid ODRL document: Asset not found"));
+    // Validate the document, and list every problem in it if invalid
+    let fields = match parse_data_source_card(&card) {
+        Ok(f) => f,
+        Err(errors) => {
+            error!("Invalid data source card document: {:?}", errors);
+            return Ok(invalid_document_response("data source card", &errors));
         }
     };
 
-    // Get relation title from asset.title
-    let name = asset
-        .get("title")
-        .and_then(|v| v.as_str())
-        .unwrap_or("unknown")
-        .to_string();
-
-    // Get the relations array from asset.relation
-    let relations = asset
-        .get("relation")
-        .and_then(|r| r.as_array())
-        .cloned()
-        .unwrap_or_default();
-
-    // Helper function to pick a value from relations by its "type" field
-    // and return its "value" field.
-    let pick = |key: &str| -> Option<&str> {
-        relations
-            .iter()
-            .find_map(|r| {
-                let is_key = r
-                    .get("type")
-                    .and_then(|t| t.as_str())
-                    .map(|t| t == key)
-                    .unwrap_or(false);
-                if !is_key {
-                    return None;
-                }
-                r.get("value").and_then(|v| v.as_str())
-            })
-    };
-
-    // Extract required fields, with defaults if not found
-    let ds_type = pick("type").unwrap_or("unknown").to_string();
-    let risk_level = pick("risk-level").unwrap_or("unknown").to_string();
-    let nodeid_str = pick("nodeid").unwrap_or("");
-
-    // Check that the given nodeid is a valid ObjectId
-    let nodeid = match ObjectId::parse_str(nodeid_str) {
-        Ok(oid) => oid,
-        Err(_) => {
-            return Err(ApiError::bad_request("Invalid nodeid (expected ObjectId hex string)"));
+    // Check that the referenced device exists, unless explicitly allowed not to
+    if !allow_unregistered(&query) {
+        if let Some(err) = check_reference(&config, COLL_DEVICE, doc! { "_id": fields.nodeid }, "asset[0].relation[type=nodeid].value", "device").await? {
+            return Ok(invalid_document_response("data source card", &[err]));
         }
-    };
+    }
+    authorize_card(&config, &submitter, &fields.nodeid.to_hex(), None).await?;
 
     // Create the new DatasourceCard document and save it to database
     let doc = DatasourceCard {
         id: None,
-        name,
-        r#type: ds_type,
-        risk_level,
-        nodeid,
+        name: fields.name,
+        r#type: fields.ds_type,
+        risk_level: fields.risk_level,
+        nodeid: fields.nodeid,
         date_received: Utc::now(),
+        last_updated: None,
+        submitted_by: Some(submitter),
     };
-    let collection = get_collection::<DatasourceCard>(COLL_DATASOURCE_CARDS).await;
+    if is_dry_run(&query) {
+        return ok_json(&serde_json::json!({
+            "message": "Datasource card is valid (dry run, not saved)",
+            "datasourceCard": doc
+        }));
+    }
+
+    let collection = get_collection::<DatasourceCard>(&config.database, COLL_DATASOURCE_CARDS).await;
     
     let filter = doc! { 
         "nodeid": &doc.nodeid, 
@@ -93,10 +77,13 @@ pub async fn create_data_source_card(card: web::Json<Value>) -> Result<impl Resp
     };
 
     match collection.find_one_and_replace(filter, &doc).upsert(true).await {
-        Ok(_) => Ok(HttpResponse::Ok().json(serde_json::json!({
-            "message": "Datasource card saved (created or updated)",
-            "datasourceCard": doc
-        }))),
+        Ok(_) => {
+            trigger_revalidation(&config, RevalidationScope::Device(doc.nodeid), "data source card update");
+            ok_json(&serde_json::json!({
+                "message": "Datasource card saved (created or updated)",
+                "datasourceCard": doc
+            }))
+        },
         Err(e) => {
             error!("Error creating/updating datasource card: {}", e);
             Err(ApiError::internal_error("Error creating/updating Datasource card"))
@@ -108,11 +95,15 @@ pub async fn create_data_source_card(card: web::Json<Value>) -> Result<impl Resp
 /// GET /dataSourceCards?after=<RFC3339>
 /// 
 /// Returns all data source cards. Can be given a date in RFC3339 format 
-/// to get only entries greater than that date/time.
+/// to get only entries greater than that date/time. Supports `limit`, `offset`
+/// and `sort` (`dateReceived`), see `Pagination`.
 pub async fn get_data_source_card(
+    config: web::Data<Config>,
     query: web::Query<std::collections::HashMap<String, String>>,
 ) -> Result<impl Responder, ApiError> {
     
+    let pagination = Pagination::from_query(&query, CARD_SORT_FIELDS, None)?;
+
     // Optional time filter
     let mut filter = doc! {};
     if let Some(after) = query.get("after") {
@@ -123,35 +114,24 @@ pub async fn get_data_source_card(
     }
 
     // Query, collect and return the cards
-    let collection = get_collection::<DatasourceCard>(COLL_DATASOURCE_CARDS).await;
-    let cursor = match collection.find(filter).await {
-        Ok(c) => c,
-        Err(e) => {
-            error!("Error querying data source cards: {}", e);
-            return Err(ApiError::db("Error querying data source cards"));
-        }
-    };
-    let results: Vec<DatasourceCard> = match cursor.try_collect().await {
-        Ok(v) => v,
-        Err(e) => {
-            error!("Failed to collect data source cards: {}", e);
-            return Err(ApiError::db("Failed to collect data source cards"));
-        }
-    };
-    let mut v = serde_json::to_value(&results).map_err(ApiError::internal_error)?;
-    crate::lib::utils::normalize_object_ids(&mut v);
-    Ok(HttpResponse::Ok().json(v))
+    let collection = get_collection::<DatasourceCard>(&config.database, COLL_DATASOURCE_CARDS).await;
+    let page = find_page(&collection, filter, &pagination).await.map_err(|e| {
+        error!("Error querying data source cards: {}", e);
+        ApiError::db("Error querying data source cards")
+    })?;
+    page_response(&page)
 }
 
 
 /// DELETE /dataSourceCards
 /// 
 /// Deletes all data source cards.
-pub async fn delete_all_data_source_cards() -> Result<impl Responder, ApiError> {
-    let collection = get_collection::<DatasourceCard>(COLL_DATASOURCE_CARDS).await;
+pub async fn delete_all_data_source_cards(config: web::Data<Config>) -> Result<impl Responder, ApiError> {
+    let collection = get_collection::<DatasourceCard>(&config.database, COLL_DATASOURCE_CARDS).await;
     match collection.delete_many(doc! {}).await {
         Ok(result) => {
             use serde_json::json;
+            trigger_revalidation(&config, RevalidationScope::All, "data source card deletion");
             Ok(HttpResponse::Ok().json(json!({ "deleted_count": result.deleted_count })))
         }
         Err(e) => {
@@ -165,7 +145,7 @@ pub async fn delete_all_data_source_cards() -> Result<impl Responder, ApiError>
 /// DELETE /dataSourceCards/{node_id}
 /// 
 /// Deletes a single data source card by its nodeid.
-pub async fn delete_data_source_card_by_nodeid(path: web::Path<String>) -> Result<impl Responder, ApiError> {
+pub async fn delete_data_source_card_by_nodeid(config: web::Data<Config>, path: web::Path<String>) -> Result<impl Responder, ApiError> {
 
     // Convert the given nodeid string to ObjectId
     let nodeid_hex = path.into_inner();
@@ -177,11 +157,12 @@ pub async fn delete_data_source_card_by_nodeid(path: web::Path<String>) -> Resul
     };
 
     // Find the matching document and delete it if it exists
-    let collection = get_collection::<DatasourceCard>(COLL_DATASOURCE_CARDS).await;
+    let collection = get_collection::<DatasourceCard>(&config.database, COLL_DATASOURCE_CARDS).await;
     match collection.delete_one(doc! { "nodeid": nodeid }).await {
         Ok(result) => {
             use serde_json::json;
             if result.deleted_count == 1 {
+                trigger_revalidation(&config, RevalidationScope::Device(nodeid), "data source card deletion");
                 Ok(HttpResponse::Ok().json(json!({
                     "message": "Data source card deleted",
                     "nodeid": nodeid_hex
@@ -196,3 +177,94 @@ pub async fn delete_data_source_card_by_nodeid(path: web::Path<String>) -> Resul
         }
     }
 }
+
+
+/// Fields of a data source card that can be changed after creation. Missing fields are left as they are.
+#[derive(Debug, Deserialize)]
+pub struct DatasourceCardUpdate {
+    pub name: Option<String>,
+    #[serde(rename = "type")]
+    pub r#type: Option<String>,
+    #[serde(rename = "risk-level")]
+    pub risk_level: Option<String>,
+}
+
+
+/// PUT /dataSourceCards/{card_id}
+/// 
+/// Partially updates a data source card by its card id (the `_id` of the card, not the nodeid).
+/// The received date is kept, and the time of the update is stored in `lastUpdated`.
+pub async fn update_data_source_card(
+    config: web::Data<Config>,
+    path: web::Path<String>,
+    body: web::Json<DatasourceCardUpdate>,
+) -> Result<impl Responder, ApiError> {
+    let card_id = path.into_inner();
+    let oid = match ObjectId::parse_str(&card_id) {
+        Ok(oid) => oid,
+        Err(_) => {
+            return Err(ApiError::bad_request("Invalid card id (expected ObjectId hex string)"));
+        }
+    };
+
+    let mut set = doc! {};
+    if let Some(name) = &body.name {
+        set.insert("name", name);
+    }
+    if let Some(ds_type) = &body.r#type {
+        set.insert("type", ds_type);
+    }
+    if let Some(risk_level) = &body.risk_level {
+        set.insert("risk-level", risk_level);
+    }
+    if set.is_empty() {
+        return Err(ApiError::bad_request("Nothing to update, expected at least one of: name, type, risk-level"));
+    }
+    set.insert("lastUpdated", mongodb::bson::DateTime::now());
+
+    let collection = get_collection::<DatasourceCard>(&config.database, COLL_DATASOURCE_CARDS).await;
+    match collection
+        .find_one_and_update(doc! { "_id": &oid }, doc! { "$set": set })
+        .return_document(ReturnDocument::After)
+        .await
+    {
+        Ok(Some(card)) => {
+            info!("Data source card {} updated", card_id);
+            trigger_revalidation(&config, RevalidationScope::Device(card.nodeid), "data source card update");
+            ok_json(&serde_json::json!({
+                "message": "Data source card updated",
+                "datasourceCard": card
+            }))
+        }
+        Ok(None) => Err(ApiError::not_found(format!("Data source card with id {} not found", card_id))),
+        Err(e) => {
+            error!("Failed to update data source card {}: {}", card_id, e);
+            Err(ApiError::db(format!("Failed to update data source card {}", card_id)))
+        }
+    }
+}
+
+
+/// GET /dataSourceCards/{card_id}
+/// 
+/// Returns a single data source card by its card id, along with the device it refers to
+/// (null if the device isnt registered).
+pub async fn get_data_source_card_by_id(config: web::Data<Config>, path: web::Path<String>) -> Result<impl Responder, ApiError> {
+    let card_id = path.into_inner();
+    let oid = match ObjectId::parse_str(&card_id) {
+        Ok(oid) => oid,
+        Err(_) => {
+            return Err(ApiError::bad_request("Invalid card id (expected ObjectId hex string)"));
+        }
+    };
+
+    let card = find_one::<DatasourceCard>(&config.database, COLL_DATASOURCE_CARDS, doc! { "_id": &oid })
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("Data source card with id {} not found", card_id)))?;
+    let device = find_one::<DeviceDoc>(&config.database, COLL_DEVICE, doc! { "_id": &card.nodeid })
+        .await
+        .map_err(ApiError::db)?;
+
+    ok_json(&serde_json::json!({ "datasourceCard": card, "device": device }))
+}
@@ -4,21 +4,35 @@ use mongodb::bson::oid::ObjectId;
 use mongodb::bson::doc;
 use serde_json;
 use futures::TryStreamExt;
-use crate::{api::deployment_certificates::{delete_all_deployment_certificates, delete_deployment_certificate}, lib::mongodb::{find_one, get_collection}};
-use reqwest;
+use crate::lib::mongodb::{find_one, get_collection};
 use futures::future::join_all;
 use serde_json::Value;
 use mongodb::bson;
 use serde_json::json;
 use actix_web::{
-    body::MessageBody, web::{self, Path}, HttpResponse, Responder
+    http::{header::ETAG, StatusCode}, web::{self, Path}, HttpRequest, HttpResponse, Responder
 };
-use log::{warn, debug, error};
+use log::{warn, debug, error, info};
+use crate::lib::config::Config;
 use crate::lib::zeroconf::get_listening_address;
+use crate::lib::outbound;
+use crate::lib::http_client::{self, Operation};
+use crate::lib::circuit_breaker;
+use crate::lib::compatibility;
+use crate::lib::revisions;
+use crate::lib::concurrency::{expected_revision, revision_etag, revision_of, stale_revision_response, update_with_revision, RevisionClaim};
+use crate::lib::telemetry::send_traced;
+use crate::lib::request_id;
+use crate::lib::utils::url_host;
+use crate::lib::resources::ResourceLedger;
+use crate::lib::openapi_refs::RefResolver;
+use crate::api::device::{device_filter, record_deployment_latency};
+use crate::lib::pagination::{find_page, Pagination};
 use crate::lib::constants::{
     COLL_DEVICE,
     COLL_MODULE,
     COLL_DEPLOYMENT,
+    COLL_DEPLOYMENT_CERTS,
     SUPPORTED_FILE_TYPES
 };
 use crate::structs::device::DeviceDoc;
@@ -42,22 +56,24 @@ use crate::structs::deployment::{
     MultipartMediaType,
     SchemaObject,
     SchemaProperty,
-    SequenceStep
+    SequenceStep,
+    PollingConfig
 };
 use crate::structs::openapi::{
     OpenApiPathItemObject,
     OpenApiOperation,
-    ResponseEnum,
     OpenApiSchemaObject,
     OpenApiSchemaEnum,
-    RequestBodyEnum,
-    OpenApiParameterEnum,
     OpenApiParameterIn,
     OpenApiFormat
 };
-use crate::api::deployment_certificates::validate_deployment_solution;
-use std::time::Duration;
+use crate::api::deployment_certificates::certify_deployment_solution;
+use crate::api::trash::trash_deployment;
+use crate::structs::deployment_certificates::DeploymentCertificate;
+use std::time::Instant;
 use crate::lib::errors::ApiError;
+use crate::lib::response::{ok_json, json_response, normalize_extended_json};
+use crate::lib::events::{self, Event};
 
 
 /// One step in the deployment sequence
@@ -66,6 +82,8 @@ pub struct ApiSequenceStep {
     pub device: String, // The _id of the device in mongodb, or "" for any device
     pub module: String, // The _id of the module in mongodb
     pub func: String, // The name of the function to call
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub method: Option<String>, // The operation (get, post etc) to call the function with, if it declares more than one
 }
 
 
@@ -77,6 +95,15 @@ pub struct Sequence {
     pub id: Option<String>, 
     pub name: String,
     pub sequence: Vec<ApiSequenceStep>,
+    // Key-value configuration delivered to the supervisors along with the deployment
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub config: Option<HashMap<String, String>>,
+    // How execution results of the deployment are polled
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub polling: Option<PollingConfig>,
+    // Time an execution of the deployment may take in total
+    #[serde(rename="executionTimeoutS", default, skip_serializing_if="Option::is_none")]
+    pub execution_timeout_s: Option<u64>,
 }
 
 
@@ -87,6 +114,7 @@ pub struct SequenceItemHydrated {
     pub device: Option<DeviceDoc>,
     pub module: ModuleDoc,
     pub func: String,
+    pub method: Option<String>,
 }
 
 
@@ -96,15 +124,20 @@ pub struct AssignedStep {
     pub device: DeviceDoc,
     pub module: ModuleDoc,
     pub func: String,
+    pub method: Option<String>,
 }
 
 
 /// The result of solving a deployment sequence. Either a new deployment was created (with its id),
-/// or an existing deployment was updated (with the full solution).
+/// an existing deployment was updated (with the full solution and its new revision), the solution
+/// failed validation in strict mode and was not stored (with the certificate describing why), or
+/// the deployment was changed by someone else while it was being solved (with its current revision).
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum SolveResult {
     DeploymentId(ObjectId),
-    Solution(CreateSolutionResult),
+    Solution(CreateSolutionResult, u64),
+    Rejected(DeploymentCertificate),
+    Stale { current: u64 },
 }
 
 
@@ -121,19 +154,18 @@ pub struct CreateSolutionResult {
 /// 
 /// Endpoint for fetching a specific deployment (by id)
 pub async fn get_deployment(
+    config: web::Data<Config>,
     path: Path<String>,
 ) -> Result<impl Responder, ApiError> {
     let deployment_id = path.into_inner();
-    let coll = get_collection::<DeploymentDoc>(COLL_DEPLOYMENT).await;
+    let coll = get_collection::<DeploymentDoc>(&config.database, COLL_DEPLOYMENT).await;
 
     let oid = ObjectId::parse_str(&deployment_id)
         .map_err(|_| ApiError::bad_request(format!("invalid deployment id '{}'", deployment_id)))?;
 
     match coll.find_one(doc! { "_id": &oid }).await.map_err(ApiError::db)? {
         Some(doc) => {
-            let mut v = serde_json::to_value(&doc).map_err(ApiError::internal_error)?;
-            crate::lib::utils::normalize_object_ids(&mut v);
-            Ok(HttpResponse::Ok().json(v))
+            ok_json(&doc)
         },
         None => Err(ApiError::not_found(format!("no deployment matches id '{}'", deployment_id))),
     }
@@ -142,17 +174,14 @@ pub async fn get_deployment(
 
 /// GET /file/manifest
 /// 
-/// Endpoint for fetching ALL deployments
-pub async fn get_deployments() -> Result<impl Responder, ApiError> {
-    let coll = get_collection::<DeploymentDoc>(COLL_DEPLOYMENT).await;
-    let mut cursor = coll.find(doc! {}).await.map_err(ApiError::db)?;
-    let mut out: Vec<DeploymentDoc> = Vec::new();
-    while let Some(doc) = cursor.try_next().await.map_err(ApiError::db)? {
-        out.push(doc);
-    }
-    let mut v = serde_json::to_value(&out).map_err(ApiError::internal_error)?;
-    crate::lib::utils::normalize_object_ids(&mut v);
-    Ok(HttpResponse::Ok().json(v))
+/// Endpoint for fetching ALL deployments. Supports `limit`, `offset` and `sort` (`name`, `active`),
+/// see `Pagination`.
+pub async fn get_deployments(config: web::Data<Config>, req: HttpRequest, query: web::Query<HashMap<String, String>>) -> Result<impl Responder, ApiError> {
+    let pagination = Pagination::from_query(&query, &[("name", "name"), ("active", "active")], None)?;
+    revisions::cached_json(&req, &[COLL_DEPLOYMENT], || async move {
+        let coll = get_collection::<DeploymentDoc>(&config.database, COLL_DEPLOYMENT).await;
+        find_page(&coll, doc! {}, &pagination).await
+    }).await
 }
 
 
@@ -182,26 +211,34 @@ fn validate_sequence(manifest: &Sequence) -> Result<(), String> {
 
 /// POST /file/manifest
 /// 
-/// Endpoint for creating a new deployment.
-pub async fn create_deployment(body: web::Json<Sequence>) -> Result<impl Responder, ApiError> {
+/// Endpoint for creating a new deployment. With strict validation (see `strict_validation`),
+/// deployments that fail validation are rejected with 403 and not stored.
+pub async fn create_deployment(
+    config: web::Data<Config>,
+    query: web::Query<HashMap<String, String>>,
+    body: web::Json<Sequence>,
+) -> Result<impl Responder, ApiError> {
 
     // Check that the sequence that was sent has valid format
     if let Err(msg) = validate_sequence(&body) {
         return Err(ApiError::bad_request(msg));
     }
+    let strict = strict_validation(&config, &query)?;
 
     // Get the url from which modules can be downloaded from (basically orchestrators address)
-    let (orchestrator_host, orchestrator_port) = get_listening_address();
-    let package_manager_base_url = std::env::var("PACKAGE_MANAGER_BASE_URL")
-            .unwrap_or_else(|_| format!("http://{}:{}", orchestrator_host, orchestrator_port));
+    let (orchestrator_host, _) = get_listening_address(&config.server);
+    let package_manager_base_url = config.server.package_manager_base_url(&orchestrator_host);
 
     // TODO: Is this kind of filtering based on file types even necessary really?
     let supported_file_types = SUPPORTED_FILE_TYPES.to_vec();
 
     // Create the deployment based on the sequence that was received
     let res = solve(
+        &config,
         &body,
         false,
+        None,
+        strict,
         &package_manager_base_url,
         &supported_file_types[..],
     ).await
@@ -217,8 +254,9 @@ pub async fn create_deployment(body: web::Json<Sequence>) -> Result<impl Respond
                 .content_type("text/plain; charset=utf-8")
                 .body(format!("\"{}\"", oid.to_hex())))
         },
+        Ok(SolveResult::Rejected(cert)) => rejected_response(&cert),
         // This shouldnt happen, it would mean the manifest was updated even though resolving was set to false
-        Ok(SolveResult::Solution(_)) => {
+        Ok(SolveResult::Solution(..) | SolveResult::Stale { .. }) => {
             let msg = "Failed constructing solution for manifest: manifest was updated instead.";
             error!("{}", msg);
             Err(ApiError::internal_error(msg))
@@ -234,10 +272,15 @@ pub async fn create_deployment(body: web::Json<Sequence>) -> Result<impl Respond
 /// 
 /// Endpoint for deploying an existing deployment. This sends the deployment document to the 
 /// necessary devices, which then will download the necessary resources (mounts and wasm files) from
-/// the orchestrator.
-pub async fn http_deploy(path: Path<String>) -> Result<impl Responder, ApiError> {
+/// the orchestrator. With strict validation, deployments that have failed validation are not deployed.
+pub async fn http_deploy(
+    config: web::Data<Config>,
+    path: Path<String>,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<impl Responder, ApiError> {
     let deployment_param = path.into_inner();
-    let coll = get_collection::<DeploymentDoc>(COLL_DEPLOYMENT).await;
+    let strict = strict_validation(&config, &query)?;
+    let coll = get_collection::<DeploymentDoc>(&config.database, COLL_DEPLOYMENT).await;
 
     // Try getting the deployment by id or name
     let filter = match ObjectId::parse_str(&deployment_param) {
@@ -268,8 +311,16 @@ pub async fn http_deploy(path: Path<String>) -> Result<impl Responder, ApiError>
         .cloned()
         .ok_or_else(|| ApiError::db("deployment missing _id"))?;
 
+    if strict {
+        if let Some(err) = &deployment.validation_error {
+            return json_response(StatusCode::FORBIDDEN, &json!({
+                "error": format!("deployment '{}' failed validation and cannot be deployed in strict mode: {}", deployment.name, err)
+            }));
+        }
+    }
+
     // Do the actual deployment, and if succesful, mark the deployment as "active" in database
-    match deploy(&deployment).await {
+    match deploy(&config, &deployment).await {
         Ok(device_responses) => {
             coll.update_one(
                 doc! { "_id": &dep_id },
@@ -277,6 +328,7 @@ pub async fn http_deploy(path: Path<String>) -> Result<impl Responder, ApiError>
             )
             .await
             .map_err(ApiError::db)?;
+            revisions::bump(COLL_DEPLOYMENT);
 
             Ok(HttpResponse::Ok().json(json!({ "deviceResponses": device_responses })))
         }
@@ -289,34 +341,32 @@ pub async fn http_deploy(path: Path<String>) -> Result<impl Responder, ApiError>
 
 /// DELETE /file/manifest
 /// 
-/// Endpoint for deleting all deployments.
-pub async fn delete_deployments() -> Result<impl Responder, ApiError> {
-    let coll = get_collection::<DeploymentDoc>(COLL_DEPLOYMENT).await;
-    let res = coll
-        .delete_many(doc! {})
+/// Endpoint for deleting all deployments. The deployments are moved to the trash with their
+/// certificates, and their execution outputs are removed when the trash is purged.
+pub async fn delete_deployments(config: web::Data<Config>) -> Result<impl Responder, ApiError> {
+    let deployments: Vec<bson::Document> = get_collection::<bson::Document>(&config.database, COLL_DEPLOYMENT).await
+        .find(doc! {})
+        .await
+        .map_err(ApiError::db)?
+        .try_collect()
         .await
         .map_err(ApiError::db)?;
 
+    let mut deleted_count = 0u64;
     let mut certificate_deletion_count = 0;
-    let response = delete_all_deployment_certificates().await;
-    if let Err(e) = response {
-        warn!("Failed deleting all deployment certificates: {}", e);
-    } else {
-        // Get the response from certificate deletion to see how many were deleted
-        if let Ok(responder) = response {
-            let r = responder.respond_to(&actix_web::test::TestRequest::default().to_http_request());
-            if let Ok(body) = r.into_body().try_into_bytes() {
-                if let Ok(json) = serde_json::from_slice::<serde_json::Value>(&body) {
-                    if let Some(count) = json.get("deletedCount").and_then(|v| v.as_u64()) {
-                        certificate_deletion_count = count;
-                    }
-                }
+    for deployment in deployments {
+        let name = deployment.get_str("name").unwrap_or_default().to_string();
+        match trash_deployment(&config, deployment).await {
+            Ok(certificates) => {
+                deleted_count += 1;
+                certificate_deletion_count += certificates;
             }
+            Err(e) => warn!("Failed deleting deployment '{}': {}", name, e),
         }
     }
 
     Ok(HttpResponse::Ok().json(json!({ 
-        "deletedCount": res.deleted_count,
+        "deletedCount": deleted_count,
         "certificateDeletedCount": certificate_deletion_count
     })))
 }
@@ -324,62 +374,49 @@ pub async fn delete_deployments() -> Result<impl Responder, ApiError> {
 
 /// DELETE /file/manifest/{deployment_id}
 /// 
-/// Endpoint for deleting a specific deployment (by its id)
-pub async fn delete_deployment(path: Path<String>) -> Result<impl Responder, ApiError> {
+/// Endpoint for deleting a specific deployment (by its id). The deployment is moved to the
+/// trash with its certificates, see api/trash.rs.
+pub async fn delete_deployment(config: web::Data<Config>, path: Path<String>) -> Result<impl Responder, ApiError> {
     let deployment_id = path.into_inner();
     let oid = ObjectId::parse_str(&deployment_id)
         .map_err(|_| ApiError::bad_request(format!("invalid deployment id '{}'", deployment_id)))?;
 
-    let coll = get_collection::<DeploymentDoc>(COLL_DEPLOYMENT).await;
-    let res = coll
-        .delete_one(doc! { "_id": oid })
+    let deployment = get_collection::<bson::Document>(&config.database, COLL_DEPLOYMENT).await
+        .find_one(doc! { "_id": oid })
         .await
-        .map_err(ApiError::db)?;
-
-    let mut certificate_deletion_count = 0;
-    if !deployment_id.is_empty() {
-        let resp = delete_deployment_certificate(web::Path::<String>::from(deployment_id.clone())).await;
-        if let Err(e) = resp {
-            warn!("Failed deleting deployment certificate for deployment '{}': {}", deployment_id, e);
-        } else {
-            // Get the response from certificate deletion to see how many were deleted
-            if let Ok(responder) = resp {
-                let r = responder.respond_to(&actix_web::test::TestRequest::default().to_http_request());
-                if let Ok(body) = r.into_body().try_into_bytes() {
-                    if let Ok(json) = serde_json::from_slice::<serde_json::Value>(&body) {
-                        if let Some(count) = json.get("deletedCount").and_then(|v| v.as_u64()) {
-                            certificate_deletion_count = count;
-                        }
-                    }
-                }
-            }
-        }
-    }
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("no deployment matches id '{}'", deployment_id)))?;
+    let certificate_deletion_count = trash_deployment(&config, deployment)
+        .await
+        .map_err(ApiError::internal_error)?;
 
-    if res.deleted_count == 0 {
-        Err(ApiError::not_found(format!("no deployment matches id '{}'", deployment_id)))
-    } else {
-        Ok(HttpResponse::Ok().json(json!({ 
-            "deletedCount": res.deleted_count,
-            "certificateDeletedCount": certificate_deletion_count
-        })))
-    }
+    Ok(HttpResponse::Ok().json(json!({ 
+        "deletedCount": 1,
+        "certificateDeletedCount": certificate_deletion_count
+    })))
 }
 
 
 /// PUT /file/manifest/{deployment_id}
 /// 
 /// Endpoint for updating an existing deployment. Requires that a deployment exists that has
-/// a matching id.
+/// a matching id. With strict validation, updates that fail validation are rejected with 403
+/// and the previous solution is kept. The revision read by the client can be sent in If-Match,
+/// see lib/concurrency.rs.
 pub async fn update_deployment(
+    config: web::Data<Config>,
+    req: HttpRequest,
     path: Path<String>,
+    query: web::Query<HashMap<String, String>>,
     body: web::Json<Sequence>,
 ) -> Result<impl Responder, ApiError> {
     let deployment_id = path.into_inner();
+    let strict = strict_validation(&config, &query)?;
+    let expected = expected_revision(&req)?;
     let oid = ObjectId::parse_str(&deployment_id)
         .map_err(|_| ApiError::bad_request(format!("invalid deployment id '{}'", deployment_id)))?;
 
-    let coll = get_collection::<bson::Document>(COLL_DEPLOYMENT).await;
+    let coll = get_collection::<bson::Document>(&config.database, COLL_DEPLOYMENT).await;
 
     let Some(old_raw) = coll
         .find_one(doc! { "_id": &oid })
@@ -392,6 +429,11 @@ pub async fn update_deployment(
         )));
     };
 
+    // Checked again when the new solution is stored, this only saves solving a stale update
+    if let Some(expected) = expected.filter(|e| *e != revision_of(&old_raw)) {
+        return Ok(stale_revision_response(expected, revision_of(&old_raw)));
+    }
+
     let was_active = old_raw.get_bool("active").unwrap_or(false);
     let old_name = old_raw
         .get_str("name")
@@ -400,17 +442,36 @@ pub async fn update_deployment(
     let mut new_manifest = body.into_inner();
     new_manifest.id = Some(oid.to_hex());
 
+    // Keep the previous configuration unless a new one was given
+    if new_manifest.config.is_none() {
+        new_manifest.config = old_raw
+            .get_document("config")
+            .ok()
+            .and_then(|c| bson::from_document(c.clone()).ok());
+    }
+    if new_manifest.polling.is_none() {
+        new_manifest.polling = old_raw
+            .get_document("polling")
+            .ok()
+            .and_then(|p| bson::from_document(p.clone()).ok());
+    }
+    if new_manifest.execution_timeout_s.is_none() {
+        new_manifest.execution_timeout_s = old_raw.get("executionTimeoutS").and_then(|t| bson::from_bson(t.clone()).ok());
+    }
+
     // Get the url from which modules can be downloaded from (basically orchestrators address)
-    let (orchestrator_host, orchestrator_port) = get_listening_address();
-    let package_manager_base_url = std::env::var("PACKAGE_MANAGER_BASE_URL")
-            .unwrap_or_else(|_| format!("http://{}:{}", orchestrator_host, orchestrator_port));
+    let (orchestrator_host, _) = get_listening_address(&config.server);
+    let package_manager_base_url = config.server.package_manager_base_url(&orchestrator_host);
 
     // TODO: Is this kind of filtering based on file types even necessary really?
     let supported_file_types = SUPPORTED_FILE_TYPES.to_vec();
 
     let res = solve(
+        &config,
         &new_manifest,
         true,
+        expected,
+        strict,
         &package_manager_base_url,
         &supported_file_types[..],
     )
@@ -420,8 +481,10 @@ pub async fn update_deployment(
         ApiError::internal_error(e)
     })?;
 
-    let solution = match res {
-        SolveResult::Solution(s) => s,
+    let (solution, revision) = match res {
+        SolveResult::Solution(s, revision) => (s, revision),
+        SolveResult::Rejected(cert) => return rejected_response(&cert),
+        SolveResult::Stale { current } => return Ok(stale_revision_response(expected.unwrap_or_default(), current)),
         _ => return Err(ApiError::internal_error("unexpected solver result (expected Solution)")),
     };
 
@@ -435,9 +498,14 @@ pub async fn update_deployment(
             validation_error: None,
             full_manifest: solution.full_manifest,
             active: Some(true),
+            config: new_manifest.config.clone().unwrap_or_default(),
+            polling: new_manifest.polling.clone(),
+            execution_timeout_s: new_manifest.execution_timeout_s,
+            last_executed_at: old_raw.get_datetime("lastExecutedAt").ok().copied(),
+            revision,
         };
 
-        match deploy(&updated_deployment_doc).await {
+        match deploy(&config, &updated_deployment_doc).await {
             Ok(device_responses) => {
                 coll.update_one(
                         doc! { "_id": &oid },
@@ -445,23 +513,85 @@ pub async fn update_deployment(
                     )
                     .await
                     .map_err(ApiError::db)?;
+                revisions::bump(COLL_DEPLOYMENT);
 
-                Ok(HttpResponse::Ok().json(json!({ "deviceResponses": device_responses })))
+                Ok(HttpResponse::Ok()
+                    .insert_header((ETAG, revision_etag(revision)))
+                    .json(json!({ "deviceResponses": device_responses, "revision": revision })))
             }
             Err(err) => {
                 Err(err)
             }
         }
     } else {
-        Ok(HttpResponse::NoContent().finish())
+        Ok(HttpResponse::NoContent().insert_header((ETAG, revision_etag(revision))).finish())
     }
 }
 
 
-/// Creates a new deployment or updates an existing one if resolving = true
+/// PUT /file/manifest/{deployment_id}/config
+/// 
+/// Replaces the key-value configuration of a deployment (a JSON object with string values),
+/// without solving the deployment again. If the deployment is active, the updated manifests
+/// are sent to its devices right away. The revision read by the client can be sent in
+/// If-Match, see lib/concurrency.rs.
+pub async fn update_deployment_config(
+    config: web::Data<Config>,
+    req: HttpRequest,
+    path: Path<String>,
+    body: web::Json<HashMap<String, String>>,
+) -> Result<impl Responder, ApiError> {
+    let deployment_id = path.into_inner();
+    let oid = ObjectId::parse_str(&deployment_id)
+        .map_err(|_| ApiError::bad_request(format!("invalid deployment id '{}'", deployment_id)))?;
+    let expected = expected_revision(&req)?;
+
+    let mut deployment = find_one::<DeploymentDoc>(&config.database, COLL_DEPLOYMENT, doc! { "_id": &oid })
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("no deployment matches ID '{}'", deployment_id)))?;
+
+    let deployment_config = body.into_inner();
+    let mut set_doc = doc! {
+        "config": bson::to_bson(&deployment_config).map_err(ApiError::internal_error)?,
+    };
+    for (device_id, node) in deployment.full_manifest.iter_mut() {
+        node.config = deployment_config.clone();
+        set_doc.insert(
+            format!("fullManifest.{}.config", device_id),
+            bson::to_bson(&deployment_config).map_err(ApiError::internal_error)?,
+        );
+    }
+    deployment.config = deployment_config;
+
+    let revision = match update_with_revision(&config, COLL_DEPLOYMENT, &oid, expected, doc! { "$set": set_doc }).await? {
+        RevisionClaim::Claimed(revision) => revision,
+        RevisionClaim::Stale { current } => return Ok(stale_revision_response(expected.unwrap_or_default(), current)),
+    };
+    deployment.revision = revision;
+    revisions::bump(COLL_DEPLOYMENT);
+    info!("Updated configuration of deployment '{}'", deployment.name);
+
+    let mut response = HttpResponse::Ok();
+    response.insert_header((ETAG, revision_etag(revision)));
+    if deployment.active == Some(true) {
+        let device_responses = deploy(&config, &deployment).await?;
+        return Ok(response.json(json!({ "config": deployment.config, "deviceResponses": device_responses, "revision": revision })));
+    }
+    Ok(response.json(json!({ "config": deployment.config, "revision": revision })))
+}
+
+
+/// Creates a new deployment or updates an existing one if resolving = true.
+/// If strict = true, a solution that fails validation is not stored. When resolving, the
+/// solution is stored only if the deployment still has `expected_revision` (if given), and its
+/// revision is increased in the same update, see lib/concurrency.rs.
 pub async fn solve(
+    config: &Config,
     deployment_sequence: &Sequence,
     resolving: bool,
+    expected_revision: Option<u64>,
+    strict: bool,
     package_manager_base_url: &str,
     supported_file_types: &[&str],
 ) -> Result<SolveResult, String> {
@@ -477,11 +607,7 @@ pub async fn solve(
         let device = if device_id.is_empty() || device_id == "any" || device_id == "null" {
             None
         } else {
-            let device_filter = match ObjectId::parse_str(&step.device) {
-                Ok(oid) => doc! { "_id": oid },
-                Err(_) => doc! { "name": &step.device },
-            };
-            let device = find_one::<DeviceDoc>(COLL_DEVICE, device_filter)
+            let device = find_one::<DeviceDoc>(&config.database, COLL_DEVICE, device_filter(&step.device))
                 .await
                 .map_err(|e| format!("device.findOne error for '{}': {e}", step.device))?
                 .ok_or_else(|| format!("device not found by id '{}'", step.device))?;
@@ -493,20 +619,36 @@ pub async fn solve(
             Ok(oid) => doc! { "_id": oid },
             Err(_) => doc! { "name": &step.module },
         };
-        let module = find_one::<ModuleDoc>(COLL_MODULE, module_filter)
+        let module = find_one::<ModuleDoc>(&config.database, COLL_MODULE, module_filter)
             .await
             .map_err(|e| format!("module.findOne error for '{}': {e}", step.module))?
             .ok_or_else(|| format!("module not found by id '{}'", step.module))?;
 
+        // Refuse modules whose files are gone, supervisors couldnt download them
+        if let Some(missing) = module.missing_files.as_ref().filter(|m| !m.is_empty()) {
+            return Err(format!("module '{}' cannot be deployed, its files are missing: {:?}", module.name, missing));
+        }
+
+        // Refuse functions whose description doesnt match the wasm export, calls to them would fail on the supervisor
+        if let Some(w) = module
+            .compatibility_warnings
+            .iter()
+            .flatten()
+            .find(|w| w.blocking && w.function == step.func)
+        {
+            return Err(format!("module '{}' cannot be deployed: {}", module.name, w.message));
+        }
+
         hydrated.push(SequenceItemHydrated {
             device,
             module,
             func: step.func.clone(),
+            method: step.method.as_ref().map(|m| m.to_ascii_lowercase()),
         });
     }
 
     // Check the device selection (add devices if they are missing and check requirements)
-    let assigned_sequence = check_device_selection(hydrated).await?;
+    let assigned_sequence = check_device_selection(config, hydrated).await?;
 
     // Save the assigned sequence, or if resolving (meaning we are updating an existing deployment) get the id of it
     let deployment_id = if resolving {
@@ -516,7 +658,7 @@ pub async fn solve(
         let oid = ObjectId::parse_str(given_id).map_err(|e| format!("Deployment id was not valid object id, error: {:?}", e))?;
         oid
     } else {
-        let deployment_collection = get_collection::<bson::Document>(COLL_DEPLOYMENT).await;
+        let deployment_collection = get_collection::<bson::Document>(&config.database, COLL_DEPLOYMENT).await;
         let mut doc_to_insert = bson::to_document(deployment_sequence)
             .map_err(|e| format!("serialize manifest failed: {e}"))?;
         doc_to_insert.remove("_id"); // Remove _id to prevent accidentally attempting to overwrite existing deployment
@@ -524,6 +666,7 @@ pub async fn solve(
             .insert_one(doc_to_insert)
             .await
             .map_err(|e| format!("insert deployment failed: {e}"))?;
+        revisions::bump(COLL_DEPLOYMENT);
         debug!("Inserted deployment, result: {:?}", res);
         res.inserted_id
             .as_object_id()
@@ -531,66 +674,156 @@ pub async fn solve(
     };
 
     // Build the actual manifest/deployment
-    let solution = create_solution(
+    let mut solution = create_solution(
         &deployment_id,
         &assigned_sequence,
         package_manager_base_url,
         supported_file_types,
     )?;
+    let deployment_config = deployment_sequence.config.clone().unwrap_or_default();
+    for node in solution.full_manifest.values_mut() {
+        node.config = deployment_config.clone();
+    }
 
     debug!("Created deployment: {:?}", solution);
 
-    // Validate the deployment, but dont stop execution if validation fails
-    if let Err(err) = validate_deployment_solution(&deployment_id, &solution).await {
-        let dep_coll = get_collection::<bson::Document>(COLL_DEPLOYMENT).await;
-        let _ = dep_coll
-            .update_one(
-                doc! { "_id": &deployment_id },
-                doc! { "$set": { "validationError": err.clone() } }
-            )
-            .await;
+    // Validate the deployment. Unless in strict mode, dont stop execution if validation fails
+    let validation_error = match certify_deployment_solution(config, &deployment_id, &solution).await {
+        Ok(cert) if cert.valid => None,
+        Ok(cert) if strict => {
+            emit_validation_failed(&deployment_id, &deployment_sequence.name, "Deployment validation failed.", "solve");
+            discard_unsolved_deployment(config, &deployment_id, resolving).await;
+            return Ok(SolveResult::Rejected(cert));
+        }
+        Ok(_) => Some("Deployment validation failed.".to_string()),
+        Err(err) if strict => {
+            discard_unsolved_deployment(config, &deployment_id, resolving).await;
+            return Err(format!("deployment could not be validated: {err}"));
+        }
+        Err(err) => Some(err),
+    };
+    if let Some(err) = &validation_error {
+        emit_validation_failed(&deployment_id, &deployment_sequence.name, err, "solve");
     }
 
-    let dep_coll = get_collection::<bson::Document>(COLL_DEPLOYMENT).await;
-    let set_doc = bson::to_document(&solution)
+    let mut set_doc = bson::to_document(&solution)
         .map_err(|e| format!("serialize solution failed: {e}"))?;
-    dep_coll
-        .update_one(doc! { "_id": &deployment_id }, doc! { "$set": set_doc })
-        .await
-        .map_err(|e| format!("update deployment with solution failed: {e}"))?;
+    set_doc.insert("config", bson::to_bson(&deployment_config).map_err(|e| format!("serialize config failed: {e}"))?);
+    if let Some(polling) = &deployment_sequence.polling {
+        set_doc.insert("polling", bson::to_bson(polling).map_err(|e| format!("serialize polling failed: {e}"))?);
+    }
+    if let Some(timeout_s) = deployment_sequence.execution_timeout_s {
+        set_doc.insert("executionTimeoutS", timeout_s as i64);
+    }
+    let update = match validation_error {
+        Some(err) => {
+            set_doc.insert("validationError", err);
+            doc! { "$set": set_doc }
+        }
+        // A valid solution clears the error an earlier one left behind
+        None => doc! { "$set": set_doc, "$unset": { "validationError": "" } },
+    };
+    if !resolving {
+        let dep_coll = get_collection::<bson::Document>(&config.database, COLL_DEPLOYMENT).await;
+        dep_coll
+            .update_one(doc! { "_id": &deployment_id }, update)
+            .await
+            .map_err(|e| format!("update deployment with solution failed: {e}"))?;
+        revisions::bump(COLL_DEPLOYMENT);
+        return Ok(SolveResult::DeploymentId(deployment_id));
+    }
 
-    Ok(if resolving {
-        SolveResult::Solution(solution)
-    } else {
-        SolveResult::DeploymentId(deployment_id)
+    let claim = update_with_revision(config, COLL_DEPLOYMENT, &deployment_id, expected_revision, update)
+        .await
+        .map_err(|e| format!("update deployment with solution failed: {}", e.msg))?;
+    Ok(match claim {
+        RevisionClaim::Claimed(revision) => {
+            revisions::bump(COLL_DEPLOYMENT);
+            SolveResult::Solution(solution, revision)
+        }
+        RevisionClaim::Stale { current } => SolveResult::Stale { current },
     })
 }
 
 
+/// Publishes that a deployment failed validation. `stage` tells whether it happened
+/// when solving the deployment or when revalidating it.
+pub fn emit_validation_failed(deployment_id: &ObjectId, deployment_name: &str, error: &str, stage: &str) {
+    events::publish(Event::DeploymentValidationFailed {
+        deployment_id: deployment_id.to_hex(),
+        deployment_name: deployment_name.to_string(),
+        error: error.to_string(),
+        stage: stage.to_string(),
+    });
+}
+
+
+/// Removes a deployment that was inserted by `solve` only to get an id for it, when its
+/// solution ends up not being stored, along with the certificate written when validating it.
+/// Existing deployments (resolving = true) are left as they are.
+async fn discard_unsolved_deployment(config: &Config, deployment_id: &ObjectId, resolving: bool) {
+    if resolving {
+        return;
+    }
+    let dep_coll = get_collection::<bson::Document>(&config.database, COLL_DEPLOYMENT).await;
+    if let Err(e) = dep_coll.delete_one(doc! { "_id": deployment_id }).await {
+        warn!("Failed to remove rejected deployment '{}': {}", deployment_id, e);
+    }
+    revisions::bump(COLL_DEPLOYMENT);
+    let cert_coll = get_collection::<bson::Document>(&config.database, COLL_DEPLOYMENT_CERTS).await;
+    if let Err(e) = cert_coll.delete_many(doc! { "deploymentId": deployment_id }).await {
+        warn!("Failed to remove the certificates of rejected deployment '{}': {}", deployment_id, e);
+    }
+    revisions::bump(COLL_DEPLOYMENT_CERTS);
+}
+
+
+/// Whether invalid deployments are rejected for this request. The `strict` query parameter
+/// (true/false) overrides the default of the deployment settings.
+pub fn strict_validation(config: &Config, query: &HashMap<String, String>) -> Result<bool, ApiError> {
+    match query.get("strict") {
+        Some(v) => v
+            .parse::<bool>()
+            .map_err(|_| ApiError::bad_request(format!("invalid value for strict '{}', expected true or false", v))),
+        None => Ok(config.deployments.validation_strict),
+    }
+}
+
+
+/// Builds the 403 response for a deployment that was rejected in strict mode
+pub fn rejected_response(cert: &DeploymentCertificate) -> Result<HttpResponse, ApiError> {
+    json_response(StatusCode::FORBIDDEN, &json!({
+        "error": "deployment failed validation and was not stored",
+        "certificate": cert,
+    }))
+}
+
+
 /// Helper function that sends the deployment document to given devices.
-pub async fn message_device_deploy(device: &DeviceDoc, manifest: &DeploymentNode) -> Result<Value, String> {
-    let ip = device
+pub async fn message_device_deploy(config: &Config, device: &DeviceDoc, manifest: &DeploymentNode) -> Result<Value, String> {
+    let base_url = device
         .communication
-        .addresses
-        .get(0)
-        .map(|s| s.as_str())
+        .base_url()
         .ok_or_else(|| format!("device '{}' has no ip address", device.name))?;
-    let url = format!("http://{}:{}{}", ip, device.communication.port, "/deploy");
-
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(20))
-        .build()
-        .map_err(|e| format!("http client build error for device '{}': {e}", device.name))?;
+    let url = format!("{}{}", base_url, "/deploy");
 
+    circuit_breaker::check(config, device)?;
+    let _permit = outbound::acquire(&config.http_client, "deployment request").await;
     let mut payload = serde_json::to_value(manifest)
         .map_err(|e| format!("serialize manifest for device '{}': {e}", device.name))?;
-    crate::lib::utils::normalize_object_ids(&mut payload);
+    normalize_extended_json(&mut payload);
 
-    let resp = client
+    let request = http_client::client(&config.http_client)
         .post(url)
         .json(&payload)
-        .send()
-        .await
+        .timeout(http_client::timeout(&config.http_client, Operation::Deployment));
+    let started = Instant::now();
+    let result = send_traced(request_id::propagate(request), "deploy").await;
+    circuit_breaker::record(config, device, &result);
+    if result.is_ok() {
+        record_deployment_latency(config, device, started.elapsed().as_secs_f64() * 1000.0).await;
+    }
+    let resp = result
         .map_err(|e| format!("request error to device '{}': {e}", device.name))?;
 
     let status = resp.status();
@@ -615,7 +848,7 @@ pub async fn message_device_deploy(device: &DeviceDoc, manifest: &DeploymentNode
 
 
 /// Send the deployment docs to devices asynchronously
-pub async fn deploy(deployment: &DeploymentDoc) -> Result<HashMap<String, Value>, ApiError> {
+pub async fn deploy(config: &Config, deployment: &DeploymentDoc) -> Result<HashMap<String, Value>, ApiError> {
     let deployment_solution = &deployment.full_manifest;
 
     let mut tasks = Vec::with_capacity(deployment_solution.len());
@@ -624,16 +857,17 @@ pub async fn deploy(deployment: &DeploymentDoc) -> Result<HashMap<String, Value>
         let oid = ObjectId::parse_str(device_id_hex)
             .map_err(|e| ApiError::bad_request(format!("bad device id '{}': {e}", device_id_hex)))?;
 
-        let dev_opt = find_one::<DeviceDoc>(COLL_DEVICE, doc! { "_id": &oid })
+        let dev_opt = find_one::<DeviceDoc>(&config.database, COLL_DEVICE, doc! { "_id": &oid })
             .await
             .map_err(|e| ApiError::db(format!("device.findOne error for '{}': {e}", device_id_hex)))?;
 
         let device = dev_opt.ok_or_else(|| ApiError::not_found(format!("device not found: {}", device_id_hex)))?;
+        compatibility::check_supervisor(&config.deployments, &device, manifest)?;
         let manifest_clone = manifest.clone();
         let device_id_for_map = device_id_hex.clone();
 
         tasks.push(async move {
-            let res = message_device_deploy(&device, &manifest_clone).await;
+            let res = message_device_deploy(config, &device, &manifest_clone).await;
             (device_id_for_map, res)
         });
     }
@@ -647,6 +881,12 @@ pub async fn deploy(deployment: &DeploymentDoc) -> Result<HashMap<String, Value>
                 out.insert(device_id, val);
             }
             Err(e) => {
+                events::publish(Event::DeploymentFailed {
+                    deployment_id: deployment.id.map(|id| id.to_hex()),
+                    deployment_name: deployment.name.clone(),
+                    device: device_id,
+                    error: e.clone(),
+                });
                 return Err(ApiError::internal_error(format!("deployment failed: {}", e)));
             }
         }
@@ -656,6 +896,10 @@ pub async fn deploy(deployment: &DeploymentDoc) -> Result<HashMap<String, Value>
         return Err(ApiError::internal_error("deployment failed: empty response"));
     }
 
+    events::publish(Event::DeploymentDeployed {
+        deployment_id: deployment.id.map(|id| id.to_hex()),
+        deployment_name: deployment.name.clone(),
+    });
     Ok(out)
 }
 
@@ -674,24 +918,27 @@ fn device_id_hex(d: &DeviceDoc) -> Result<String, String> {
 }
 
 /// Takes a template of a server url (in form http://{serverIp}:{port}), and uses the given device document
-/// to fill out that url
+/// to fill out that url. The scheme of the template is replaced with the scheme of the device,
+/// IPv6 addresses are put in brackets, and the base path of the device follows the port.
 fn fill_server_url(template: &str, dev: &DeviceDoc) -> String {
     let ip = dev
         .communication
         .addresses
         .get(0)
-        .map(|s| s.as_str())
-        .unwrap_or("localhost");
-    template
-        .replace("{serverIp}", ip)
-        .replace("{port}", &dev.communication.port.to_string())
+        .map(|s| url_host(s))
+        .unwrap_or_else(|| "localhost".to_string());
+    let without_scheme = template
+        .split_once("://")
+        .map(|(_, rest)| rest)
+        .unwrap_or(template);
+    format!("{}://{}", dev.communication.scheme, without_scheme)
+        .replace("{serverIp}", &ip)
+        .replace("{port}", &format!("{}{}", dev.communication.port, dev.communication.path.as_deref().unwrap_or_default()))
 }
 
 
-/// Helper function that takes the first operation (if any) defined for a given path/endpoint, and returns it
-fn pick_single_operation<'a>(
-    item: &'a OpenApiPathItemObject,
-) -> Result<(&'static str, &'a OpenApiOperation), String> {
+/// Helper function that lists the operations defined for a given path/endpoint, by their method
+fn declared_operations(item: &OpenApiPathItemObject) -> Vec<(&'static str, &OpenApiOperation)> {
     let mut ops: Vec<(&'static str, &OpenApiOperation)> = Vec::new();
     if let Some(op) = &item.get { ops.push(("get", op)); }
     if let Some(op) = &item.put { ops.push(("put", op)); }
@@ -701,16 +948,123 @@ fn pick_single_operation<'a>(
     if let Some(op) = &item.head { ops.push(("head", op)); }
     if let Some(op) = &item.patch { ops.push(("patch", op)); }
     if let Some(op) = &item.trace { ops.push(("trace", op)); }
+    ops
+}
 
-    if ops.len() < 1 {
-        return Err(format!("Expected at least one operation on endpoint, found none"));
+
+/// Helper function that picks the operation a step calls from the ones defined for its path/endpoint:
+/// the one with the method the step asks for, or the first one if it doesnt ask for any
+fn pick_operation<'a>(
+    ops: &[(&'static str, &'a OpenApiOperation)],
+    method: Option<&str>,
+) -> Result<(&'static str, &'a OpenApiOperation), String> {
+    let first = *ops
+        .first()
+        .ok_or_else(|| "Expected at least one operation on endpoint, found none".to_string())?;
+    match method {
+        None => {
+            if ops.len() > 1 {
+                debug!("Endpoint has {} operations and no method was given, using '{}'", ops.len(), first.0);
+            }
+            Ok(first)
+        }
+        Some(method) => ops
+            .iter()
+            .find(|(m, _)| m.eq_ignore_ascii_case(method))
+            .copied()
+            .ok_or_else(|| {
+                let declared: Vec<&str> = ops.iter().map(|(m, _)| *m).collect();
+                format!("Endpoint has no '{}' operation, it declares {}", method, declared.join(", "))
+            }),
     }
-    // TODO: Currently orchestrator doesnt know what to do if an endpoint has more than one operation (get/post) defined
-    // even if the schema allows it. Update this part if in the future orchestrator has need of this.
-    if ops.len() > 1 {
-        warn!("Endpoint had more than one operation ({:?} total), defaulting to use first one", ops.len());
+}
+
+
+/// Helper function that builds the endpoint of a single operation (method) of the function of a step
+fn build_endpoint(
+    step: &AssignedStep,
+    deployment_id: &ObjectId,
+    method_str: &str,
+    op: &OpenApiOperation,
+) -> Result<Endpoint, String> {
+    // References to the components of the description are resolved along the way
+    let description = step
+        .module
+        .description
+        .as_ref()
+        .ok_or_else(|| format!("module.description is missing for '{}'", step.module.name))?;
+    let refs = RefResolver::new(description);
+
+    // Look for the "200" response. If it is not defined, return an error.
+    // TODO: If other responses need to be implemented, this part needs to change
+    let resp_200 = op
+        .responses
+        .get("200")
+        .ok_or_else(|| "Response '200' not defined".to_string())?;
+    let resp_200 = refs.response(resp_200).map_err(|e| format!("response 200: {e}"))?;
+
+    // Gather information for the "response" section under the "endpoint" section
+    let content = resp_200.content.as_ref()
+        .ok_or_else(|| "response 200 has no content".to_string())?;
+    // TODO: The content might have multiple entries, this would ignore them. They dont have that at the moment, but 
+    // if those are added some day this part needs to change.
+    let (response_media_type, media) = content.iter()
+        .next()
+        .ok_or_else(|| "response 200 content is empty".to_string())?;
+    let response_media = media.schema.as_ref().map(|schema| refs.schema(schema)).transpose()?;
+
+    // Get request body items if they happen to be present
+    let request_body_built: Option<RequestBody> = match &op.request_body {
+        None => None,
+        Some(request_body) => {
+            let rb = refs.request_body(request_body).map_err(|e| format!("requestBody: {e}"))?;
+            // TODO: Chooses the first entry. In future, if multiple are expected, change this.
+            match rb.content.iter().next() {
+                Some((mt, media)) => Some(RequestBody {
+                    media_type: mt.clone(),
+                    schema: media.schema.as_ref().map(|schema| refs.schema(schema)).transpose()?,
+                    encoding: media.encoding.clone(),
+                }),
+                None => None,
+            }
+        }
+    };
+
+    // Get the url of the first server object.
+    // TODO: If at some point orchestrator wants to do something like support several execution paths on a supervisor etc, this
+    // part will have to change.
+    let server_url_template = description
+        .servers
+        .as_ref()
+        .and_then(|v| v.get(0))
+        .ok_or_else(|| "module.servers is missing or empty".to_string())?
+        .url
+        .clone();
+    let url = fill_server_url(&server_url_template, &step.device);
+    let path = supervisor_execution_path(&step.module.name, &step.func)
+        .replace("{deployment}", &deployment_id.to_hex());
+
+    // Clear out the enum things from some openapi structs.
+    let mut parameter_list = Vec::new();
+    if let Some(params) = &op.parameters {
+        for p in params {
+            parameter_list.push(refs.parameter(p).map_err(|e| format!("parameter: {e}"))?);
+        }
     }
-    Ok(ops[0])
+
+    Ok(Endpoint {
+        url,
+        path,
+        method: method_str.to_string(),
+        request: OperationRequest {
+            parameters: parameter_list.clone(),
+            request_body: request_body_built,
+        },
+        response: OperationResponse {
+            media_type: response_media_type.clone(),
+            schema: response_media,
+        },
+    })
 }
 
 
@@ -735,8 +1089,10 @@ pub fn create_solution(
                 deployment_id: deployment_id.clone(),
                 modules: Vec::new(),
                 endpoints: HashMap::new(),
-                instructions: Instructions { modules: HashMap::new() },
+                instructions: Instructions { modules: HashMap::new(), operations: HashMap::new() },
                 mounts: HashMap::new(),
+                config: HashMap::new(),
+                operations: HashMap::new(),
             });
 
         // Add module metadata needed by the device (urls from where to retrieve necessary files)
@@ -763,122 +1119,26 @@ pub fn create_solution(
                 )
             })?;
 
-        // Pick a single method (get/post etc) that has been defined for the current endpoint/path 
-        let (method_str, op) = pick_single_operation(path_item)?;
-
-        // Look for the "200" response. If it is not defined, return an error.
-        // TODO: If other responses need to be implemented, this part needs to change
-        let resp_200 = op
-            .responses
-            .get("200")
-            .ok_or_else(|| "Response '200' not defined".to_string())?;
-
-        // Gather information for the "response" section under the "endpoint" section
-        let (response_media_type, response_media) = match resp_200 {
-            ResponseEnum::OpenApiResponseObject(obj) => {
-                let content = obj.content.as_ref()
-                    .ok_or_else(|| "response 200 has no content".to_string())?;
-                // TODO: The content might have multiple entries, this would ignore them. They dont have that at the moment, but 
-                // if those are added some day this part needs to change.
-                let (media_type, media) = content.iter()
-                    .next()
-                    .ok_or_else(|| "response 200 content is empty".to_string())?;
-
-                // Convert Option<OpenApiSchemaEnum> -> Option<OpenApiSchemaObject>
-                let schema_obj = match &media.schema {
-                    Some(OpenApiSchemaEnum::OpenApiSchemaObject(s)) => Some(s.clone()),
-                    Some(OpenApiSchemaEnum::OpenApiReferenceObject(r)) => {
-                        return Err(format!("response 200 schema is a $ref ({}), resolver not implemented", r.r#ref));
-                    }
-                    None => None,
-                };
-                (media_type.clone(), schema_obj)
-            }
-            ResponseEnum::OpenApiReferenceObject(obj) => {
-                return Err(format!("response 200 is a $ref ({}), resolver not implemented yet", obj.r#ref));
-            }
-        };
-
-        // Get request body items if they happen to be present
-        let request_body_built: Option<RequestBody> = match &op.request_body {
-            None => None,
-            Some(RequestBodyEnum::OpenApiReferenceObject(r)) => {
-                return Err(format!(
-                    "requestBody is a $ref ({}), resolver not implemented yet",
-                    r.r#ref
-                ));
-            }
-            Some(RequestBodyEnum::OpenApiRequestBodyObject(rb)) => {
-                // TODO: Chooses the first entry. In future, if multiple are expected, change this.
-                if let Some((mt, media)) = rb.content.iter().next() {
-                    let schema_obj = match &media.schema {
-                        None => None,
-                        Some(OpenApiSchemaEnum::OpenApiSchemaObject(s)) => Some(s.clone()),
-                        Some(OpenApiSchemaEnum::OpenApiReferenceObject(r)) => {
-                            return Err(format!(
-                                "requestBody schema is a $ref ({}), resolver not implemented yet",
-                                r.r#ref
-                            ));
-                        }
-                    };
-                    Some(RequestBody {
-                        media_type: mt.clone(),
-                        schema: schema_obj,
-                        encoding: media.encoding.clone(),
-                    })
-                } else {
-                    None
-                }
-            }
-        };
-
-        // Get the url of the first server object.
-        // TODO: If at some point orchestrator wants to do something like support several execution paths on a supervisor etc, this
-        // part will have to change.
-        let server_url_template = step
-            .module
-            .description
-            .as_ref()
-            .and_then(|desc| desc.servers.as_ref())
-            .and_then(|v| v.get(0))
-            .ok_or_else(|| "module.servers is missing or empty".to_string())?
-            .url
-            .clone();
-        let url = fill_server_url(&server_url_template, &step.device);
-        let path = supervisor_execution_path(&step.module.name, &step.func)
-            .replace("{deployment}", &deployment_id.to_hex());
-
-        // Clear out the enum things from some openapi structs.
-        let mut parameter_list = Vec::new();
-        if let Some(params) = &op.parameters {
-            for p in params {
-                match p {
-                    OpenApiParameterEnum::OpenApiParameterObject(po) => parameter_list.push(po.clone()),
-                    OpenApiParameterEnum::OpenApiReferenceObject(r) => {
-                        return Err(format!(
-                            "parameter is a $ref ({}), resolver not implemented yet",
-                            r.r#ref
-                        ));
-                    }
+        // Pick the method (get/post etc) the step calls, and build the endpoints of all the methods
+        // defined for the current endpoint/path
+        let ops = declared_operations(path_item);
+        let (method_str, op) = pick_operation(&ops, step.method.as_deref())?;
+        let endpoint = build_endpoint(step, deployment_id, method_str, op)?;
+        if ops.len() > 1 {
+            let mut by_method = HashMap::with_capacity(ops.len());
+            // Operations the step doesnt call dont stop the deployment, they are just left out
+            for (method, op) in &ops {
+                match build_endpoint(step, deployment_id, method, op) {
+                    Ok(other) => { by_method.insert(method.to_string(), other); }
+                    Err(e) => warn!("Leaving out the '{}' operation of '{}/{}': {}", method, step.module.name, step.func, e),
                 }
             }
+            node.operations
+                .entry(step.module.name.clone())
+                .or_default()
+                .insert(step.func.clone(), by_method);
         }
 
-        // Build the endpoint from all information gathered so far
-        let endpoint = Endpoint {
-            url,
-            path,
-            method: method_str.to_string(),
-            request: OperationRequest {
-                parameters: parameter_list.clone(),
-                request_body: request_body_built,
-            },
-            response: OperationResponse {
-                media_type: response_media_type.clone(),
-                schema: response_media,
-            },
-        };
-
         debug!("Endpoint constructed:\n{:?}", endpoint);
 
         let stage_mounts = mounts_for(&step.module, &step.func, &endpoint, supported_file_types)?;
@@ -934,6 +1194,19 @@ pub fn create_solution(
             .get_mut(&device_id_str)
             .expect("device node must exist when building instructions");
 
+        // Every declared operation of the function forwards its result to the next step the same way
+        if let Some(operations) = node.operations.get(module_name).and_then(|m| m.get(func_name)) {
+            let by_method: HashMap<String, Instruction> = operations
+                .iter()
+                .map(|(method, from)| (method.clone(), Instruction { from: from.clone(), to: forward_endpoint.clone() }))
+                .collect();
+            node.instructions
+                .operations
+                .entry(module_name.clone())
+                .or_default()
+                .insert(func_name.clone(), by_method);
+        }
+
         node.instructions
             .modules
             .entry(module_name.clone())
@@ -967,6 +1240,7 @@ pub fn create_solution(
             device: dev_id,
             module: mod_id,
             func: s.func.clone(),
+            method: s.method.clone(),
         });
     }
 
@@ -1011,7 +1285,7 @@ fn openapi_object_to_simple_schema(
             }
             OpenApiSchemaEnum::OpenApiReferenceObject(r) => {
                 return Err(format!(
-                    "multipart property '{}' is a $ref ({}), resolver not implemented",
+                    "multipart property '{}' is an unresolved $ref ({})",
                     name, r.r#ref
                 ));
             }
@@ -1198,12 +1472,13 @@ fn device_satisfies_module(d: &DeviceDoc, m: &ModuleDoc) -> bool {
 /// Helper function that checks that a device has been selected for
 /// each step in the sequence of a deployment. Selects if hasnt been already.
 /// Also checks that the selected device has all the necessary supervisor interfaces
-/// that the module needs.
-pub async fn check_device_selection(sequence: Vec<SequenceItemHydrated>) -> Result<Vec<AssignedStep>, String> {
+/// that the module needs, and enough memory and storage for the modules of all the
+/// steps placed on it (see `ResourceLedger`).
+pub async fn check_device_selection(config: &Config, sequence: Vec<SequenceItemHydrated>) -> Result<Vec<AssignedStep>, String> {
     
     // First fetch all devices, and remove orchestrator from the selection since its not capable of running wasm modules.
     // TODO: Better way to identify and remove orchestrator, name is not just "orchestrator" always.
-    let device_collection = get_collection::<DeviceDoc>(COLL_DEVICE).await;
+    let device_collection = get_collection::<DeviceDoc>(&config.database, COLL_DEVICE).await;
     let mut cursor = device_collection.find(doc! {}).await.map_err(|e| format!("Database error when trying to get all devices. Error: {:?}", e))?;
     let mut available_devices: Vec<DeviceDoc> = Vec::new();
     while let Some(doc) = cursor.try_next().await.map_err(|e| format!("Database error when trying to get all devices. Error: {:?}", e))? {
@@ -1212,7 +1487,16 @@ pub async fn check_device_selection(sequence: Vec<SequenceItemHydrated>) -> Resu
     if let Some(idx) = available_devices.iter().position(|d| d.name == "orchestrator") {
         available_devices.remove(idx);
     }
+    // Drained devices dont take new deployments
+    available_devices.retain(|d| !d.drain.as_ref().is_some_and(|drain| drain.excludes_device()));
+    // Automatic selection prefers the devices that answer health checks fastest, those without
+    // measurements come last
+    available_devices.sort_by(|a, b| {
+        let p95 = |d: &DeviceDoc| d.latency.as_ref().map(|l| l.p95_ms).unwrap_or(f64::INFINITY);
+        p95(a).total_cmp(&p95(b))
+    });
 
+    let mut ledger = ResourceLedger::new();
     let mut assigned: Vec<AssignedStep> = Vec::with_capacity(sequence.len());
     for step in sequence.into_iter() {
         let func_name = &step.func;
@@ -1229,18 +1513,29 @@ pub async fn check_device_selection(sequence: Vec<SequenceItemHydrated>) -> Resu
 
         // Either validate the user-specified device, or auto-pick one
         let chosen_device = if let Some(device) = step.device {
+            if device.drain.as_ref().is_some_and(|drain| drain.excludes_device()) {
+                return Err(format!("device '{}' is drained and doesnt take deployments", device.name));
+            }
             if !device_satisfies_module(&device, &module) {
                 return Err(format!(
                     "device '{}' does not satisfy module '{}' requirements",
                     device.name, module.name
                 ));
             }
+            ledger.check(&device, &module)?;
             device
         } else {
-            // Select first device that satisfies modules requirements
+            // Select the lowest latency device that satisfies modules requirements and still has room for it
             if let Some(device) = available_devices
                 .iter()
-                .find(|d| device_satisfies_module(d, &module))
+                .filter(|d| device_satisfies_module(d, &module))
+                .find(|d| match ledger.check(d, &module) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        debug!("Skipping device for automatic selection: {}", e);
+                        false
+                    }
+                })
                 .cloned()
             {
                 device
@@ -1253,16 +1548,19 @@ pub async fn check_device_selection(sequence: Vec<SequenceItemHydrated>) -> Resu
                 ));
             }
         };
+        ledger.reserve(&chosen_device, &module);
         assigned.push(AssignedStep {
             device: chosen_device,
             module: module,
             func: func_name.clone(),
+            method: step.method,
         });
     }
 
     if assigned.is_empty() {
         return Err("Error on deployment: no steps assigned".into());
     }
+    debug!("Resources reserved by the deployment per device: {:?}", ledger.reserved());
     Ok(assigned)
 }
 
@@ -1,19 +1,22 @@
-use chrono::Utc;
-use serde_json::json;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
 use std::collections::HashMap;
-use futures::TryStreamExt;
 use mongodb::bson::{doc, oid::ObjectId};
-use actix_web::{HttpResponse, Responder, web::Path};
+use actix_web::{HttpResponse, Responder, web::{Data, Json, Path, Query}};
 use crate::lib::mongodb::{get_collection, find_one, insert_one};
 use crate::api::deployment::CreateSolutionResult;
 use crate::structs::deployment_certificates::{DeploymentCertificate, ValidationLog};
 use crate::structs::node_cards::NodeCard;
 use crate::structs::data_source_cards::DatasourceCard;
-use crate::structs::zones::Zones;
+use crate::api::policy_engine::run_validators;
+use crate::api::zones_and_risk_levels::{ZoneDescription, ZonePolicies};
 use crate::structs::module_cards::ModuleCard;
+use crate::lib::config::Config;
 use crate::lib::errors::ApiError;
+use crate::lib::response::ok_json;
+use crate::lib::pagination::{find_page, page_response, Pagination};
 use crate::lib::constants::{
-    COLL_ZONES,
     COLL_MODULE_CARDS,
     COLL_NODE_CARDS,
     COLL_DATASOURCE_CARDS,
@@ -21,250 +24,368 @@ use crate::lib::constants::{
 };
 
 
-/// Validates that a given deployment fulfills all constraints (zones, node cards, module cards, data source cards).
+/// Validates that a given deployment fulfills all constraints (zones, node cards, module cards, data source cards,
+/// and any additional validators registered in the policy engine).
 pub async fn validate_deployment_solution(
+    config: &Config,
     deployment_id: &ObjectId,
     solution: &CreateSolutionResult,
 ) -> Result<(), String> {
+    let cert = certify_deployment_solution(config, deployment_id, solution).await?;
+    if !cert.valid {
+        return Err("Deployment validation failed.".into());
+    }
+    Ok(())
+}
 
-    // Build a map: zone_name -> allowed risk levels
-    let zones_coll = get_collection::<Zones>(COLL_ZONES).await;
-    let mut zone_allowed: HashMap<String, Vec<String>> = HashMap::new();
-    let mut cursor = zones_coll
-        .find(doc! {})
-        .await
-        .map_err(|e| format!("zones.find error: {e}"))?;
-    while let Some(z) = cursor
-        .try_next()
+
+/// Validates a deployment like `validate_deployment_solution`, and returns the issued certificate
+/// (containing the validation logs) regardless of whether the deployment was valid or not.
+/// Errors are only returned when the validation itself could not be completed.
+pub async fn certify_deployment_solution(
+    config: &Config,
+    deployment_id: &ObjectId,
+    solution: &CreateSolutionResult,
+) -> Result<DeploymentCertificate, String> {
+
+    // Run every registered validator, their logs are merged into the same certificate
+    let logs = run_validators(config, deployment_id, solution).await?;
+
+    // If any log was invalid, the whole deployment is invalid
+    let all_valid = logs.iter().all(|l| l.valid);
+    let mut cert = DeploymentCertificate {
+        id: None,
+        date: Utc::now(),
+        deployment_id: deployment_id.clone(),
+        valid: all_valid,
+        validation_logs: logs,
+    };
+    let inserted_id = insert_one(&config.database, COLL_DEPLOYMENT_CERTS, &cert)
         .await
-        .map_err(|e| format!("zones cursor error: {e}"))?
-    {
-        if let Some(name) = z.zone.clone() {
-            zone_allowed.insert(name, z.allowed_risk_levels.unwrap_or_default());
+        .map_err(|e| format!("insert certificate failed: {e}"))?;
+    cert.id = inserted_id.as_object_id();
+    Ok(cert)
+}
+
+
+/// Outcome of a single rule checked while validating a step
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleOutcome {
+    pub rule: String,
+    pub passed: bool,
+    pub detail: String,
+}
+
+
+/// Full trace of the validation of a single step: the validation log that ends up in the
+/// certificate, along with the cards and zone that were consulted and each rules outcome.
+#[derive(Debug, Clone, Serialize)]
+pub struct StepEvaluation {
+    pub log: ValidationLog,
+    #[serde(rename = "nodeCard")]
+    pub node_card: Option<NodeCard>,
+    #[serde(rename = "moduleCard")]
+    pub module_card: Option<ModuleCard>,
+    #[serde(rename = "datasourceCard")]
+    pub datasource_card: Option<DatasourceCard>,
+    pub zone: Option<ZoneDescription>,
+    pub rules: Vec<RuleOutcome>,
+    #[serde(skip)]
+    pub next_input_risk: String, // Risk level a following step with a temporary input inherits
+}
+
+impl StepEvaluation {
+    /// Records the outcome of a rule both in the trace and in the validation log
+    fn rule(&mut self, rule: &str, passed: bool, detail: String) {
+        if !passed {
+            self.log.valid = false;
         }
+        self.log.reasons.push(detail.clone());
+        self.rules.push(RuleOutcome { rule: rule.to_string(), passed, detail });
     }
+}
 
-    let mut output_risk = "none".to_string();
-    let mut logs: Vec<ValidationLog> = Vec::new();
 
-    // Validate each step in the deployment separately
-    for step in &solution.sequence {
-        let device_hex = step.device.to_hex();
-        let module_hex = step.module.to_hex();
+/// Validates a single (device, module) step against the cards and zone definitions.
+/// `datasource_type` overrides the input type of the module card, and `input_risk` is the
+/// risk level inherited by modules with a temporary input (output risk of the previous step).
+pub async fn evaluate_step(
+    config: &Config,
+    device: &ObjectId,
+    module: &ObjectId,
+    func: &str,
+    datasource_type: Option<&str>,
+    input_risk: &str,
+    zone_policies: &ZonePolicies,
+) -> Result<StepEvaluation, String> {
+    let device_hex = device.to_hex();
+    let module_hex = module.to_hex();
 
-        // Create log to store the validation results and reasoning for this step
-        let mut log = ValidationLog {
+    // Create log to store the validation results and reasoning for this step
+    let mut eval = StepEvaluation {
+        log: ValidationLog {
             device: device_hex.clone(),
             module: module_hex.clone(),
-            func: step.func.clone(),
+            func: func.to_string(),
             node_zone: "none".into(),
             module_risk: "none".into(),
             input_risk: "none".into(),
             output_risk: "none".into(),
             valid: true,
             reasons: vec![],
-        };
+            validator: None,
+        },
+        node_card: None,
+        module_card: None,
+        datasource_card: None,
+        zone: None,
+        rules: vec![],
+        next_input_risk: input_risk.to_string(),
+    };
 
-        if step.func.is_empty() {
-            return Err("Device, module, or function missing in the step.".into());
-        }
+    // Load module card and node card, and check that they exist and have valid format
+    let nodecard = find_one::<NodeCard>(&config.database, COLL_NODE_CARDS, doc! { "nodeid": device })
+        .await
+        .map_err(|e| format!("nodecards.findOne error: {e}"))?;
+    let Some(nodecard) = nodecard else {
+        eval.rule("nodeCardExists", false, format!("Node card not found for device {device_hex}"));
+        return Ok(eval);
+    };
+    eval.log.node_zone = nodecard.zone.clone();
+    eval.zone = Some(zone_policies.describe(&nodecard.zone));
+    eval.node_card = Some(nodecard.clone());
+    let modulecard = find_one::<ModuleCard>(&config.database, COLL_MODULE_CARDS, doc! { "moduleid": module })
+        .await
+        .map_err(|e| format!("modulecards.findOne error: {e}"))?;
+    let Some(modulecard) = modulecard else {
+        eval.rule("moduleCardExists", false, format!("Module card not found for module {module_hex}"));
+        return Ok(eval);
+    };
+    eval.module_card = Some(modulecard.clone());
+    let risk_level_module = if modulecard.risk_level.is_empty() {
+        return Err("Module card was missing risk level, failed to validate".to_string());
+    } else {
+        modulecard.risk_level.clone()
+    };
+    eval.log.module_risk = risk_level_module.clone();
 
-        // Load module card and node card, and check that they exist and have valid format
-        let nodecard = find_one::<NodeCard>(COLL_NODE_CARDS, doc! { "nodeid": step.device })
-            .await
-            .map_err(|e| format!("nodecards.findOne error: {e}"))?;
-        if nodecard.is_none() {
-            log.valid = false;
-            log.reasons
-                .push(format!("Node card not found for device {device_hex}"));
-            logs.push(log);
-            continue;
-        }
-        let nodecard = nodecard.unwrap();
-        log.node_zone = nodecard.zone.clone();
-        let modulecard =
-            find_one::<ModuleCard>(COLL_MODULE_CARDS, doc! { "moduleid": step.module })
-                .await
-                .map_err(|e| format!("modulecards.findOne error: {e}"))?;
-        if modulecard.is_none() {
-            log.valid = false;
-            log.reasons
-                .push(format!("Module card not found for module {module_hex}"));
-            logs.push(log);
-            continue;
-        }
-        let modulecard = modulecard.unwrap();
-        let risk_level_module = if modulecard.risk_level.is_empty() {
-            return Err("Module card was missing risk level, failed to validate".to_string());
-        } else {
-            modulecard.risk_level.clone()
-        };
-        log.module_risk = risk_level_module.clone();
-
-         // Check that module has a valid risk level given the zone of the node its deployed to
-        let allowed = zone_allowed
-            .get(&nodecard.zone)
-            .cloned()
-            .unwrap_or_default();
-        if !allowed.iter().any(|x| x == &risk_level_module) {
-            log.valid = false;
-            log.reasons.push(format!(
-                "Module risk level '{}' not allowed in zone '{}'",
-                risk_level_module, nodecard.zone
-            ));
-        } else {
-            log.reasons.push(format!(
-                "Module risk level '{}' allowed in zone '{}'",
-                risk_level_module, nodecard.zone
-            ));
-        }
+    // Check that module has a valid risk level given the zone of the node its deployed to
+    let allowed = zone_policies.allows(&nodecard.zone, &risk_level_module);
+    eval.rule("moduleRiskAllowedInZone", allowed, format!(
+        "Module risk level '{}' {} in zone '{}'",
+        risk_level_module, if allowed { "allowed" } else { "not allowed" }, nodecard.zone
+    ));
 
-        // Get input risk level
-        let mut datasource_risk: Option<String> = None;
-        let input_type_module = if modulecard.input_type.is_empty() {
+    // Get input risk level
+    let mut datasource_risk: Option<String> = None;
+    let input_type_module = match datasource_type {
+        Some(t) => t.to_string(),
+        None if modulecard.input_type.is_empty() => {
             return Err("Module card didnt have an input type, deployment failed to validate".to_string());
-        } else {
-            modulecard.input_type.clone()
-        };
-        if input_type_module != "temp" {
-            let ds = find_one::<DatasourceCard>(
-                COLL_DATASOURCE_CARDS,
-                doc! { "type": &input_type_module, "nodeid": step.device },
-            )
-            .await
-            .map_err(|e| format!("datasourcecards.findOne error: {e}"))?;
-
-            if let Some(ds_card) = ds {
-                log.input_risk = ds_card.risk_level.clone();
-                datasource_risk = Some(ds_card.risk_level.clone());
-                log.reasons.push(format!(
-                    "Data source risk level '{}' found for input type '{}'",
-                    log.input_risk, input_type_module
-                ));
-            } else {
-                log.valid = false;
-                log.reasons.push(format!(
-                    "Data source card not found for input type '{}' on device {}",
-                    input_type_module, device_hex
-                ));
-            }
-        } else {
-            log.input_risk = output_risk.clone();
-            log.reasons.push(format!(
-                "Input type is temporary, inheriting risk level '{}'",
-                log.input_risk
-            ));
         }
+        None => modulecard.input_type.clone(),
+    };
+    if input_type_module != "temp" {
+        let ds = find_one::<DatasourceCard>(
+            &config.database,
+            COLL_DATASOURCE_CARDS,
+            doc! { "type": &input_type_module, "nodeid": device },
+        )
+        .await
+        .map_err(|e| format!("datasourcecards.findOne error: {e}"))?;
 
-        // Check input risk against zone
-        if !allowed.iter().any(|x| x == &log.input_risk) {
-            log.valid = false;
-            log.reasons.push(format!(
-                "Input risk level '{}' not allowed in zone '{}'",
-                log.input_risk, nodecard.zone
+        if let Some(ds_card) = ds {
+            eval.log.input_risk = ds_card.risk_level.clone();
+            datasource_risk = Some(ds_card.risk_level.clone());
+            eval.rule("datasourceCardExists", true, format!(
+                "Data source risk level '{}' found for input type '{}'",
+                ds_card.risk_level, input_type_module
             ));
+            eval.datasource_card = Some(ds_card);
         } else {
-            log.reasons.push(format!(
-                "Input risk level '{}' allowed in zone '{}'",
-                log.input_risk, nodecard.zone
+            eval.rule("datasourceCardExists", false, format!(
+                "Data source card not found for input type '{}' on device {}",
+                input_type_module, device_hex
             ));
         }
+    } else {
+        eval.log.input_risk = input_risk.to_string();
+        eval.log.reasons.push(format!(
+            "Input type is temporary, inheriting risk level '{}'",
+            input_risk
+        ));
+    }
 
-        // Get output risk level
-        let output_risk_module_card = &modulecard.output_risk;
-        if output_risk_module_card == "inherit" {
-            if let Some(ds_risk) = datasource_risk {
-                output_risk = ds_risk;
-            }
-            log.reasons
-                .push(format!("Module output risk level inherited as '{}'", output_risk));
-        } else {
-            output_risk = output_risk_module_card.clone();
-            log.reasons
-                .push(format!("Module output risk level set to '{}'", output_risk));
-        }
-        log.output_risk = output_risk.clone();
-
-        // Check output risk against zone
-        if !allowed.iter().any(|x| x == &output_risk) {
-            log.valid = false;
-            log.reasons.push(format!(
-                "Output risk level '{}' not allowed in zone '{}'",
-                output_risk, nodecard.zone
-            ));
-        } else {
-            log.reasons.push(format!(
-                "Output risk level '{}' allowed in zone '{}'",
-                output_risk, nodecard.zone
-            ));
-        }
+    // Check input risk against zone
+    let allowed = zone_policies.allows(&nodecard.zone, &eval.log.input_risk);
+    eval.rule("inputRiskAllowedInZone", allowed, format!(
+        "Input risk level '{}' {} in zone '{}'",
+        eval.log.input_risk, if allowed { "allowed" } else { "not allowed" }, nodecard.zone
+    ));
 
-        if log.valid {
-            log.reasons.push("Step validated successfully.".into());
+    // Get output risk level
+    let mut output_risk = input_risk.to_string();
+    let output_risk_module_card = &modulecard.output_risk;
+    if output_risk_module_card == "inherit" {
+        if let Some(ds_risk) = datasource_risk {
+            output_risk = ds_risk;
         }
+        eval.log.reasons
+            .push(format!("Module output risk level inherited as '{}'", output_risk));
+    } else {
+        output_risk = output_risk_module_card.clone();
+        eval.log.reasons
+            .push(format!("Module output risk level set to '{}'", output_risk));
+    }
+    eval.log.output_risk = output_risk.clone();
 
-        logs.push(log);
+    // Check output risk against zone
+    let allowed = zone_policies.allows(&nodecard.zone, &output_risk);
+    eval.rule("outputRiskAllowedInZone", allowed, format!(
+        "Output risk level '{}' {} in zone '{}'",
+        output_risk, if allowed { "allowed" } else { "not allowed" }, nodecard.zone
+    ));
+    eval.next_input_risk = output_risk;
+
+    if eval.log.valid {
+        eval.log.reasons.push("Step validated successfully.".into());
     }
+    Ok(eval)
+}
 
-    // If any step was invalid, the whole deployment is invalid
-    let all_valid = logs.iter().all(|l| l.valid);
-    let cert = DeploymentCertificate {
-        id: None,
-        date: Utc::now(),
-        deployment_id: deployment_id.clone(),
-        valid: all_valid,
-        validation_logs: logs,
+
+/// POST /policies/explain
+///
+/// Evaluates a hypothetical step without creating a deployment or a certificate, and returns
+/// the full trace of the evaluation. Meant for debugging policies. Body fields:
+/// - `device` and `module`: ids of the device and module (required)
+/// - `datasource`: data source type to use instead of the input type in the module card
+/// - `inputRisk`: risk level inherited by modules with a temporary input (default `none`)
+pub async fn explain_policy(config: Data<Config>, body: Json<Value>) -> Result<impl Responder, ApiError> {
+    let parse_id = |field: &str| -> Result<ObjectId, ApiError> {
+        let value = body
+            .get(field)
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| ApiError::bad_request(format!("missing '{}'", field)))?;
+        ObjectId::parse_str(value)
+            .map_err(|_| ApiError::bad_request(format!("invalid {} id '{}'", field, value)))
     };
-    insert_one(COLL_DEPLOYMENT_CERTS, &cert)
+    let device = parse_id("device")?;
+    let module = parse_id("module")?;
+    let datasource = body.get("datasource").and_then(|v| v.as_str());
+    let input_risk = body.get("inputRisk").and_then(|v| v.as_str()).unwrap_or("none");
+
+    let zone_policies = ZonePolicies::load(&config).await.map_err(ApiError::internal_error)?;
+    let evaluation = evaluate_step(&config, &device, &module, "", datasource, input_risk, &zone_policies)
         .await
-        .map_err(|e| format!("insert certificate failed: {e}"))?;
-    if !all_valid {
-        return Err("Deployment validation failed.".into());
-    }
-    Ok(())
+        .map_err(ApiError::bad_request)?;
+
+    ok_json(&json!({
+        "valid": evaluation.log.valid,
+        "evaluation": evaluation,
+    }))
+}
+
+
+/// Returns the most recent certificate issued for the given deployment, if any.
+pub async fn latest_deployment_certificate(config: &Config, deployment_id: &ObjectId) -> Result<Option<DeploymentCertificate>, String> {
+    let coll = get_collection::<DeploymentCertificate>(&config.database, COLL_DEPLOYMENT_CERTS).await;
+    coll.find_one(doc! { "deploymentId": deployment_id })
+        .sort(doc! { "date": -1 })
+        .await
+        .map_err(|e| format!("deploymentcertificates.findOne error: {e}"))
 }
 
 
 /// GET /deploymentCertificates
-/// Returns all deployment certificates.
-pub async fn get_deployment_certificates() -> Result<impl Responder, ApiError> {
-    let coll = get_collection::<DeploymentCertificate>(COLL_DEPLOYMENT_CERTS).await;
-    let mut cursor = coll.find(doc! {}).await.map_err(ApiError::db)?;
-    let mut out: Vec<DeploymentCertificate> = Vec::new();
-    while let Some(doc) = cursor.try_next().await.map_err(ApiError::db)? {
-        out.push(doc);
+/// 
+/// Returns all deployment certificates, newest first. Supports optional filtering with query parameters:
+/// - `deploymentId`: only certificates of the given deployment
+/// - `valid`: `true` or `false`, only certificates with the given validation result
+/// - `after` / `before`: RFC3339 timestamps limiting the certificate creation date
+/// - `limit`, `offset` and `sort` (`date`), see `Pagination`
+pub async fn get_deployment_certificates(config: Data<Config>, query: Query<HashMap<String, String>>) -> Result<impl Responder, ApiError> {
+    let mut filter = doc! {};
+    if let Some(id) = query.get("deploymentId") {
+        let oid = ObjectId::parse_str(id)
+            .map_err(|_| ApiError::bad_request(format!("invalid deploymentId '{}'", id)))?;
+        filter.insert("deploymentId", oid);
+    }
+    if let Some(valid) = query.get("valid") {
+        let valid: bool = valid
+            .parse()
+            .map_err(|_| ApiError::bad_request(format!("invalid value for valid '{}', expected true or false", valid)))?;
+        filter.insert("valid", valid);
+    }
+    let mut date_filter = doc! {};
+    if let Some(after) = query.get("after") {
+        date_filter.insert("$gt", parse_date_param("after", after)?);
+    }
+    if let Some(before) = query.get("before") {
+        date_filter.insert("$lt", parse_date_param("before", before)?);
+    }
+    if !date_filter.is_empty() {
+        filter.insert("date", date_filter);
     }
 
+    let pagination = Pagination::from_query(&query, &[("date", "date")], Some(doc! { "date": -1 }))?;
+    let coll = get_collection::<DeploymentCertificate>(&config.database, COLL_DEPLOYMENT_CERTS).await;
+    let page = find_page(&coll, filter, &pagination).await?;
+
     // Normalize object ids before returning (UI compatibility)
-    let mut v = serde_json::to_value(&out).map_err(ApiError::db)?;
-    crate::lib::utils::normalize_object_ids(&mut v);
-    Ok(HttpResponse::Ok().json(v))
+    page_response(&page)
+}
+
+
+/// GET /deploymentCertificates/{certificate_id}
+/// 
+/// Returns a single deployment certificate by its id.
+pub async fn get_deployment_certificate(config: Data<Config>, path: Path<String>) -> Result<impl Responder, ApiError> {
+    let id = path.into_inner();
+    let oid = ObjectId::parse_str(&id)
+        .map_err(|_| ApiError::bad_request(format!("invalid deployment certificate id '{}'", id)))?;
+
+    let cert = find_one::<DeploymentCertificate>(&config.database, COLL_DEPLOYMENT_CERTS, doc! { "_id": &oid })
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("no deployment certificate matches id '{}'", id)))?;
+
+    ok_json(&cert)
+}
+
+
+/// Helper function that parses an RFC3339 query parameter into a bson datetime
+fn parse_date_param(name: &str, value: &str) -> Result<mongodb::bson::DateTime, ApiError> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|dt| mongodb::bson::DateTime::from_chrono(dt.with_timezone(&Utc)))
+        .map_err(|_| ApiError::bad_request(format!("invalid {} '{}', expected an RFC3339 timestamp", name, value)))
 }
 
 
 /// DELETE /deploymentCertificates
 /// 
 /// Endpoint for deleting all deployment certificates.
-pub async fn delete_all_deployment_certificates() -> Result<impl Responder, ApiError> {
-    let coll = get_collection::<DeploymentCertificate>(COLL_DEPLOYMENT_CERTS).await;
+pub async fn delete_all_deployment_certificates(config: Data<Config>) -> Result<impl Responder, ApiError> {
+    let coll = get_collection::<DeploymentCertificate>(&config.database, COLL_DEPLOYMENT_CERTS).await;
     let res = coll.delete_many(doc!{}).await.map_err(ApiError::db)?;
     Ok(HttpResponse::Ok().json(json!({ "deletedCount": res.deleted_count })))
 }
 
 
-/// DELETE /deploymentCertificates/{deployment_id}
+/// DELETE /deploymentCertificates/deployment/{deployment_id}
 /// 
-/// Endpoint for deleting a specific deployment certificate (by its deploymentId)
-pub async fn delete_deployment_certificate(path: Path<String>) -> Result<impl Responder, ApiError> {
+/// Endpoint for deleting all deployment certificates of a specific deployment (by its deploymentId).
+/// Under its own path, since `/deploymentCertificates/{certificate_id}` is the id of a certificate.
+pub async fn delete_deployment_certificates_of_deployment(config: Data<Config>, path: Path<String>) -> Result<impl Responder, ApiError> {
     let id = path.into_inner();
     let oid = ObjectId::parse_str(&id)
-        .map_err(|_| ApiError::bad_request(format!("invalid deployment certificate id '{}'", id)))?;
+        .map_err(|_| ApiError::bad_request(format!("invalid deployment id '{}'", id)))?;
 
-    let coll = get_collection::<DeploymentCertificate>(COLL_DEPLOYMENT_CERTS).await;
-    let res = coll.delete_one(doc!{ "deploymentId": &oid }).await.map_err(ApiError::db)?;
+    let coll = get_collection::<DeploymentCertificate>(&config.database, COLL_DEPLOYMENT_CERTS).await;
+    let res = coll.delete_many(doc!{ "deploymentId": &oid }).await.map_err(ApiError::db)?;
 
     if res.deleted_count == 0 {
-        Err(ApiError::not_found(format!("no deployment certificate matches id '{}'", id)))
+        Err(ApiError::not_found(format!("no deployment certificates found for deployment '{}'", id)))
     } else {
         Ok(HttpResponse::Ok().json(json!({ "deletedCount": res.deleted_count })))
     }
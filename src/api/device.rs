@@ -3,39 +3,66 @@
 //! Contains device related items, such as serving device descriptions
 //! and healthchecks.
 
-use actix_web::{HttpResponse, Responder, web};
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
 use log::{info, warn, debug, error};
 use serde_json::{json, Value};
-use sysinfo::System;
-use serde::Deserialize;
-use mongodb::{bson::Bson, bson::to_bson, bson::doc, bson};
+use sysinfo::{ProcessesToUpdate, System};
+use serde::{Deserialize, Serialize};
+use mongodb::{bson::Bson, bson::to_bson, bson::doc, bson, Collection};
+use mongodb::options::ReturnDocument;
 use reqwest;
 use chrono;
 use chrono::Utc;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use tokio::time::{sleep, Duration};
-use futures::stream::TryStreamExt;
+use std::sync::Arc;
+use tokio::time::{sleep, Duration, Instant};
+use once_cell::sync::Lazy;
+use futures::stream::{StreamExt, TryStreamExt};
 use crate::lib::constants::{
     CONFIG_PATH, 
-    DEVICE_HEALTHCHECK_FAILED_THRESHOLD, 
-    DEVICE_HEALTH_CHECK_INTERVAL_S,
-    COLL_DEVICE
+    DEVICE_LATENCY_WINDOW,
+    COLL_DATASOURCE_CARDS,
+    COLL_DEPLOYMENT,
+    COLL_DEVICE,
+    COLL_EXECUTION_OUTPUTS,
+    COLL_LOGS,
+    COLL_NODE_CARDS
 };
 use crate::lib::mongodb::{
     find_one, 
     insert_one, 
     update_field,
-    get_collection
+    get_collection,
+    is_duplicate_key
 };
+use crate::lib::config::Config;
 use crate::lib::zeroconf;
+use crate::lib::discovery;
+use crate::lib::outbound;
+use crate::lib::http_client::{self, Operation};
+use crate::lib::circuit_breaker;
+use crate::lib::compatibility::SUPERVISOR_VERSION_HEADER;
+use crate::lib::reachability::{order_by_reachability, reorder_device_addresses};
+use crate::lib::watchdog;
+use crate::lib::revisions;
+use crate::lib::shutdown;
+use crate::lib::connections::connection_counts;
+use crate::lib::telemetry::send_traced;
+use crate::lib::pagination::{find_page, Pagination};
+use crate::lib::events::{self, Event};
+use crate::api::node_cards::generate_node_card_for_new_device;
+use crate::api::health_history::record_health_sample;
 use crate::structs::device::{
     CpuInfo, 
     DeviceCommunication, 
     DeviceDescription, 
     DeviceDoc, 
+    DeviceUpdate, 
+    DiskUsage, 
     Health, 
     HealthReport, 
+    LoadAverage, 
     MemoryInfo, 
     NetworkInterfaceIpInfo, 
     NetworkInterfaceUsage, 
@@ -45,27 +72,64 @@ use crate::structs::device::{
     StatusLogEntry
 };
 use crate::lib::errors::ApiError;
-use crate::lib::utils::default_device_description;
+use crate::lib::response::ok_json;
+use crate::lib::utils::{base_path, base_url, default_device_description, escape_regex};
 use crate::lib::constants::{SYSTEM, NETWORKS, DISKS};
+use crate::structs::node_cards::NodeCard;
+use crate::structs::data_source_cards::DatasourceCard;
+use crate::api::deployment::strict_validation;
+use crate::api::device_drain::{active_deployments_on, migrate_deployments};
+use crate::structs::deployment::{DeviceModule, SequenceStep};
+
+/// How long the platform info of the device description is reused for
+const PLATFORM_INFO_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// Cached platform info and when it was gathered
+static PLATFORM_INFO: Lazy<parking_lot::Mutex<Option<(Instant, PlatformInfo)>>> = Lazy::new(|| parking_lot::Mutex::new(None));
+
+/// Network totals (received, sent) per interface at the previous health report
+static NETWORK_TOTALS: Lazy<parking_lot::Mutex<HashMap<String, (u64, u64)>>> = Lazy::new(|| parking_lot::Mutex::new(HashMap::new()));
 
 /// Struct used with manual device registrations
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ManualDeviceRegistration {
     pub name: Option<String>,
     pub addresses: Option<Vec<String>>,
     pub host: Option<String>,
     pub port: Option<u16>,
+    pub scheme: Option<String>,
     pub protocol: Option<String>,
     pub properties: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub probe: Option<bool>, // Whether the device description is fetched before registering, true by default
+}
+
+/// Part of a deployment document read when listing the deployments on a device
+#[derive(Debug, Clone, Deserialize)]
+struct DeviceDeploymentRow {
+    #[serde(rename = "_id")]
+    id: bson::oid::ObjectId,
+    name: String,
+    active: Option<bool>,
+    sequence: Vec<SequenceStep>,
+    #[serde(rename = "fullManifest")]
+    full_manifest: HashMap<String, DeviceDeploymentNode>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct DeviceDeploymentNode {
+    modules: Vec<DeviceModule>,
 }
 
 /// GET /health
 /// 
-/// Returns a system-level health report for the device.
+/// Returns a system-level health report for the device. Besides the fields reported by
+/// supervisors, includes usage per mount point, uptime of the orchestrator process, load
+/// average and the connections currently open to the orchestrator.
 pub async fn thingi_health() -> Result<impl Responder, ApiError> {
 
     // Get system info
-    let (cpu_usage, memory_usage, uptime) = {
+    let (cpu_usage, memory_usage, uptime, process_uptime) = {
         let uptime = System::uptime();
         let mut sys =  SYSTEM.lock();
         sys.refresh_cpu_usage();
@@ -74,47 +138,66 @@ pub async fn thingi_health() -> Result<impl Responder, ApiError> {
         let used = sys.used_memory() as f32;
         let total = sys.total_memory() as f32;
         let mem = if total > 0.0 { used / total } else { 0.0 };
-        (cpu, mem, uptime)
+        let process_uptime = sysinfo::get_current_pid().ok().and_then(|pid| {
+            sys.refresh_processes(ProcessesToUpdate::Some(&[pid]), false);
+            sys.process(pid).map(|p| p.run_time())
+        });
+        (cpu, mem, uptime, process_uptime)
     };
+    let load = System::load_average();
 
-    // Get network info, and handle possible poisoned mutex by reinitializing
+    // Get network info, with the traffic since the previous report. The deltas are computed from
+    // the totals, since the device description refreshes the same counters.
     let network_usage = {
         let mut networks =  NETWORKS.lock();
         networks.refresh(true);
+        let mut previous = NETWORK_TOTALS.lock();
         let mut network_usage = std::collections::HashMap::new();
         for (if_name, data) in networks.iter() {
+            let (down_bytes, up_bytes) = (data.total_received(), data.total_transmitted());
+            // Counters that went backwards were reset, e.g. the interface was recreated
+            let (down_bytes_delta, up_bytes_delta) = match previous.get(if_name) {
+                Some(&(down, up)) => (down_bytes.checked_sub(down), up_bytes.checked_sub(up)),
+                None => (None, None),
+            };
             network_usage.insert(
                 if_name.clone(),
-                NetworkInterfaceUsage {
-                    down_bytes: data.total_received(),
-                    up_bytes: data.total_transmitted(),
-                },
+                NetworkInterfaceUsage { down_bytes, up_bytes, down_bytes_delta, up_bytes_delta },
             );
         }
+        *previous = network_usage.iter().map(|(name, u)| (name.clone(), (u.down_bytes, u.up_bytes))).collect();
         network_usage
     };
 
-    // Get disk info
-    let storage_usage = {
+    // Get disk info, both per disk name (as reported by supervisors) and per mount point
+    let (storage_usage, disk_usage) = {
         let mut disks =  DISKS.lock();
+        // Also picks up disks mounted since the last report
         disks.refresh(true);
         let disk_list = disks.list();
         let mut storage_usage = std::collections::HashMap::new();
+        let mut disk_usage = Vec::with_capacity(disk_list.len());
         for disk in disk_list.iter() {
-            let disk_name = disk.name();
+            let disk_name = disk.name().to_string_lossy().to_string();
             let disk_total_bytes = disk.total_space();
             let disk_available_bytes = disk.available_space();
             let used_percentage = if disk_total_bytes > 0 {
-                (disk_total_bytes - disk_available_bytes) as f32 / disk_total_bytes as f32
+                disk_total_bytes.saturating_sub(disk_available_bytes) as f32 / disk_total_bytes as f32
             } else {
                 0.0
             };
-            storage_usage.insert(
-                disk_name.to_string_lossy().to_string(),
-                used_percentage
-            );
+            storage_usage.insert(disk_name.clone(), used_percentage);
+            disk_usage.push(DiskUsage {
+                mount_point: disk.mount_point().to_string_lossy().to_string(),
+                name: disk_name,
+                file_system: disk.file_system().to_string_lossy().to_string(),
+                total_bytes: disk_total_bytes,
+                available_bytes: disk_available_bytes,
+                usage: used_percentage,
+            });
         }
-        storage_usage
+        disk_usage.sort_by(|a, b| a.mount_point.cmp(&b.mount_point));
+        (storage_usage, disk_usage)
     };
 
     let report = HealthReport {
@@ -122,7 +205,11 @@ pub async fn thingi_health() -> Result<impl Responder, ApiError> {
         memory_usage,
         network_usage,
         uptime,
-        storage_usage
+        storage_usage,
+        disks: Some(disk_usage),
+        process_uptime,
+        load_average: Some(LoadAverage { one: load.one, five: load.five, fifteen: load.fifteen }),
+        open_connections: Some(connection_counts()),
     };
 
     debug!("✅ Orchestrator health check done");
@@ -154,6 +241,7 @@ pub fn get_device_description() -> DeviceDescription {
     DeviceDescription {
         platform: get_device_platform_info(),
         supervisor_interfaces: Vec::new(),
+        supervisor_version: None,
     }
 }
 
@@ -175,22 +263,35 @@ pub fn get_wot_td() -> Value {
 /// - Total memory
 /// - Network interfaces and IP addresses
 ///
-/// This data is used in the WasmIoT device description function.
+/// This data is used in the WasmIoT device description function. The result is cached for
+/// PLATFORM_INFO_CACHE_TTL, since it rarely changes.
 pub fn get_device_platform_info() -> PlatformInfo {
+    let mut cached = PLATFORM_INFO.lock();
+    if let Some((gathered_at, info)) = cached.as_ref() {
+        if gathered_at.elapsed() < PLATFORM_INFO_CACHE_TTL {
+            return info.clone();
+        }
+    }
+    let info = gather_platform_info();
+    *cached = Some((Instant::now(), info.clone()));
+    info
+}
 
 
+fn gather_platform_info() -> PlatformInfo {
     let (memory_bytes, cpu_name, cpu_architecture, clock_speed_hz, core_count,
          system_name, system_kernel, system_os, system_host) = {
         let mut sys =  SYSTEM.lock();
-        sys.refresh_all();
-        sys.refresh_cpu_all();
+        // Refreshing the CPU usage here too would shorten the interval /health measures it over
+        sys.refresh_cpu_frequency();
         sys.refresh_memory();
 
         let mem_bytes = sys.total_memory();
 
-        let cpu0 = &sys.cpus()[0];
-        let cpu_name = cpu0.brand().to_string();
-        let clock_speed_hz = cpu0.frequency() as u64 * 1_000_000;
+        let (cpu_name, clock_speed_hz) = sys.cpus()
+            .first()
+            .map(|cpu| (cpu.brand().to_string(), cpu.frequency() * 1_000_000))
+            .unwrap_or_default();
         let core_count = sys.cpus().len();
 
         let system_name   = System::name().unwrap_or_default();
@@ -253,76 +354,264 @@ pub fn get_device_platform_info() -> PlatformInfo {
 
 
 /// Check whether each discovered device is already in the database.
-/// If not, insert it and fetch its description + health asynchronously.
-pub async fn process_discovered_devices(devices: Vec<DeviceDoc>) {
-    for device in devices {
+/// If not, insert it and fetch its description + health asynchronously. Known devices whose
+/// advertised addresses have changed get the new addresses.
+pub async fn process_discovered_devices(devices: Vec<DeviceDoc>, config: Arc<Config>) {
+    for mut device in devices {
+        if device.communication.addresses.len() > 1 {
+            device.communication.addresses = order_by_reachability(&config.discovery, &device.communication.addresses, device.communication.port).await;
+        }
+
         // Check if device already exists
-        let exists = find_one::<DeviceDoc>(COLL_DEVICE, doc! { "name": &device.name })
+        let existing = find_one::<DeviceDoc>(&config.database, COLL_DEVICE, doc! { "name": &device.name })
             .await
-            .unwrap_or(None)
-            .is_some();
-        if exists {
+            .unwrap_or(None);
+        if let Some(existing) = existing {
+            update_discovered_addresses(&config, &existing, &device.communication.addresses).await;
+            update_discovered_properties(&config, &existing, &device).await;
+            if device.discovered_by.is_some() && existing.discovered_by != device.discovered_by {
+                let backend = device.discovered_by.clone().map(Bson::String).unwrap_or(Bson::Null);
+                let _ = update_field::<DeviceDoc>(&config.database, COLL_DEVICE, doc! { "name": &existing.name }, "discoveredBy", backend).await;
+            }
+            if existing.disappeared_at.is_some() {
+                clear_disappeared(&config, &existing.name).await;
+            }
             continue;
         }
 
         // If device did not exist, add it into database
-        if let Err(e) = insert_one(COLL_DEVICE, &device).await {
-            error!("❌ Saving new device failed for '{}': {:?}", device.name, e);
+        if let Err(e) = insert_one(&config.database, COLL_DEVICE, &device).await {
+            if is_duplicate_key(&e) {
+                // Registered by someone else (e.g. manually) since the check above
+                debug!("Device '{}' was already registered", device.name);
+            } else {
+                error!("❌ Saving new device failed for '{}': {:?}", device.name, e);
+            }
             continue;
         }
         info!("🆕 Found new device '{}'", device.name);
+        events::publish(Event::DeviceDiscovered {
+            device_name: device.name.clone(),
+            addresses: device.communication.addresses.clone(),
+            port: device.communication.port,
+        });
+
+        // Generate a default node card for the device, so that it can be used in deployments right away
+        if config.cards.auto_generate {
+            let name = device.name.clone();
+            let config = config.clone();
+            shutdown::spawn_tracked(async move {
+                generate_node_card_for_new_device(&config, &name).await;
+            });
+        }
 
         let device_clone = device.clone();
 
         // First register the orchestrator to new supervisor. Ignore errors
         // where the registration endpoint is not found, since some supervisors
         // might not have it implemented.
-        if let Err(e) = register_orchestrator(&device_clone).await {
+        if discovery::is_own_device(&device_clone.name) {
+            debug!("Device '{}' is this orchestrator, not registering it to itself", device_clone.name);
+        } else if let Err(e) = register_orchestrator(&device_clone, &config).await {
             warn!("❗️ Failed to register orchestrator for device '{}': {}", device_clone.name, e);
         } else {
             info!("✅ Registered orchestrator for device '{}'", device_clone.name);
         }
 
         // For the new device, get the device description and run first health check
-        if let Some(desc) = fetch_device_description(&device_clone).await {
+        if let Some(desc) = fetch_device_description(&config, &device_clone).await {
             let bson_desc = to_bson(&desc).unwrap_or(Bson::Null);
-            let _ = update_field::<DeviceDoc>(COLL_DEVICE, doc! { "name": &device_clone.name }, "description", bson_desc).await;
+            let _ = update_field::<DeviceDoc>(&config.database, COLL_DEVICE, doc! { "name": &device_clone.name }, "description", bson_desc).await;
             info!("📄 '{}' device description fetched", device_clone.name);
         }
 
-        if let Some(report) = fetch_device_health(&device_clone).await {
-            let health = Health {
-                report,
-                time_of_query: chrono::Utc::now(),
-            };
+        if let Some(health) = fetch_device_health(&device_clone, &config).await {
             let bson_health = to_bson(&health).unwrap_or(Bson::Null);
-            let _ = update_field::<DeviceDoc>(COLL_DEVICE, doc! { "name": &device_clone.name }, "health", bson_health).await;
+            let _ = update_field::<DeviceDoc>(&config.database, COLL_DEVICE, doc! { "name": &device_clone.name }, "health", bson_health).await;
             info!("📄 '{}' initial healthcheck done ", device_clone.name);
         }
     }
 }
 
 
+/// Stores the addresses a known device was discovered at, if they arent the ones it has
+async fn update_discovered_addresses(config: &Config, existing: &DeviceDoc, addresses: &[String]) {
+    let mut known = existing.communication.addresses.clone();
+    let mut discovered = addresses.to_vec();
+    known.sort();
+    discovered.sort();
+    if known == discovered {
+        return;
+    }
+    let value = Bson::Array(addresses.iter().cloned().map(Bson::String).collect());
+    match update_field::<DeviceDoc>(&config.database, COLL_DEVICE, doc! { "name": &existing.name }, "communication.addresses", value).await {
+        Ok(_) => info!("Device '{}' is now advertised at {:?}", existing.name, addresses),
+        Err(e) => error!("❌ Failed to update the addresses of device '{}': {:?}", existing.name, e),
+    }
+}
+
+
+/// Stores the properties a known device was discovered with, and the base path among them, if
+/// they arent the ones it has
+async fn update_discovered_properties(config: &Config, existing: &DeviceDoc, discovered: &DeviceDoc) {
+    if discovered.properties.is_none() || existing.properties == discovered.properties {
+        return;
+    }
+    let collection = get_collection::<DeviceDoc>(&config.database, COLL_DEVICE).await;
+    let update = doc! { "$set": {
+        "properties": to_bson(&discovered.properties).unwrap_or(Bson::Null),
+        "communication.path": to_bson(&discovered.communication.path).unwrap_or(Bson::Null),
+    } };
+    match collection.update_one(doc! { "name": &existing.name }, update).await {
+        Ok(_) => {
+            revisions::bump(COLL_DEVICE);
+            debug!("Device '{}' now advertises {:?}", existing.name, discovered.properties);
+        }
+        Err(e) => error!("❌ Failed to update the properties of device '{}': {:?}", existing.name, e),
+    }
+}
+
+
+/// Flags a discovered device that is no longer advertised as disappeared, and marks it inactive
+/// right away if it isnt already. The status changes back to active through the health checks
+/// once the device is back, as after failed health checks.
+pub async fn mark_device_disappeared(config: &Config, name: &str) -> mongodb::error::Result<()> {
+    let collection = get_collection::<DeviceDoc>(&config.database, COLL_DEVICE).await;
+    let now = Utc::now();
+    let flagged = collection
+        .find_one_and_update(
+            doc! { "name": name, "disappearedAt": { "$exists": false } },
+            doc! { "$set": { "disappearedAt": bson::to_bson(&now)? } },
+        )
+        .return_document(ReturnDocument::After)
+        .await?;
+    let Some(device) = flagged else {
+        return Ok(());
+    };
+    revisions::bump(COLL_DEVICE);
+    let device_id = device.id.map(|id| id.to_hex());
+    warn!("👻 Device '{}' is no longer advertised", name);
+    events::publish(Event::DeviceDisappeared { device_id: device_id.clone(), device_name: name.to_string(), time: now });
+
+    // Conditional, so that the transition is logged once even if a health check changes it at the same time
+    let entry = StatusLogEntry { status: StatusEnum::Inactive, time: now };
+    let changed = collection
+        .update_one(doc! { "name": name, "status": { "$ne": "inactive" } }, vec![doc! {
+            "$set": {
+                "status": bson::to_bson(&StatusEnum::Inactive)?,
+                "ok_health_check_count": 0,
+                "status_log": { "$concatArrays": [[{ "$literal": bson::to_bson(&entry)? }], { "$ifNull": ["$status_log", []] }] },
+            }
+        }])
+        .await?;
+    if changed.modified_count == 1 {
+        revisions::bump(COLL_DEVICE);
+        warn!("🔴 Device '{}' changed to inactive", name);
+        events::publish(Event::DeviceInactive { device_id, device_name: name.to_string(), time: now });
+    }
+    Ok(())
+}
+
+
+/// Registers a device listed in the inventory file (see lib/inventory.rs) the way discovery
+/// registers a new one. A device that is already known gets the communication details of the
+/// inventory and its description fetched again, so that entries that couldnt be reached when
+/// they were registered are completed once they answer.
+pub async fn bootstrap_inventory_device(device: DeviceDoc, config: Arc<Config>) {
+    let existing = find_one::<DeviceDoc>(&config.database, COLL_DEVICE, doc! { "name": &device.name })
+        .await
+        .unwrap_or(None);
+    let Some(existing) = existing else {
+        process_discovered_devices(vec![device], config).await;
+        return;
+    };
+
+    // The addresses are compared as sets, since they may have been reordered by reachability
+    let known = &existing.communication;
+    let listed = &device.communication;
+    let (mut known_addresses, mut listed_addresses) = (known.addresses.clone(), listed.addresses.clone());
+    known_addresses.sort();
+    listed_addresses.sort();
+    if known_addresses != listed_addresses || known.port != listed.port || known.scheme != listed.scheme || known.path != listed.path {
+        let value = to_bson(listed).unwrap_or(Bson::Null);
+        match update_field::<DeviceDoc>(&config.database, COLL_DEVICE, doc! { "name": &device.name }, "communication", value).await {
+            Ok(_) => info!("Device '{}' is now reached at {:?} as listed in the inventory", device.name, listed.base_url()),
+            Err(e) => error!("❌ Failed to update the communication details of device '{}': {:?}", device.name, e),
+        }
+    }
+    if let Some(desc) = fetch_device_description(&config, &device).await {
+        let bson_desc = to_bson(&desc).unwrap_or(Bson::Null);
+        let _ = update_field::<DeviceDoc>(&config.database, COLL_DEVICE, doc! { "name": &device.name }, "description", bson_desc).await;
+        debug!("📄 '{}' device description fetched", device.name);
+    }
+}
+
+
+/// Removes the disappeared flag of a device that is advertised again
+async fn clear_disappeared(config: &Config, name: &str) {
+    let collection = get_collection::<DeviceDoc>(&config.database, COLL_DEVICE).await;
+    match collection.update_one(doc! { "name": name }, doc! { "$unset": { "disappearedAt": "" } }).await {
+        Ok(_) => {
+            revisions::bump(COLL_DEVICE);
+            info!("Device '{}' is advertised again", name);
+        }
+        Err(e) => error!("❌ Failed to clear the disappeared flag of device '{}': {:?}", name, e),
+    }
+}
+
+
 /// Attempt to fetch the device description, and parse it into a DeviceDescription.
-async fn fetch_device_description(device: &DeviceDoc) -> Option<DeviceDescription> {
-    let addr = device.communication.addresses.get(0)?;
-    let url = format!(
-        "http://{}:{}/.well-known/wasmiot-device-description",
-        addr,
-        device.communication.port
-    );
+/// If the description lists no supervisor interfaces and interface probing is enabled in the
+/// discovery settings, they are probed from the supervisor (see `probe_supervisor_interfaces`).
+async fn fetch_device_description(config: &Config, device: &DeviceDoc) -> Option<DeviceDescription> {
+    let mut description = request_device_description(config, device).await?;
+    if description.supervisor_interfaces.is_empty() && config.discovery.interface_probe {
+        if let Some((interfaces, source)) = probe_supervisor_interfaces(config, device).await {
+            info!("🔎 Probed {} supervisor interfaces for device '{}' from its {}", interfaces.len(), device.name, source);
+            description.supervisor_interfaces = interfaces;
+        }
+    }
+    Some(description)
+}
+
 
-    match reqwest::get(&url).await {
+/// Requests the device description from the supervisor
+async fn request_device_description(config: &Config, device: &DeviceDoc) -> Option<DeviceDescription> {
+    let base_url = device.communication.base_url()?;
+    let url = format!("{}/.well-known/wasmiot-device-description", base_url);
+
+    if let Err(e) = circuit_breaker::check(config, device) {
+        warn!("Not fetching the description of device '{}': {}", device.name, e);
+        return None;
+    }
+    let _permit = outbound::acquire(&config.http_client, "device description request").await;
+    let request = http_client::client(&config.http_client)
+        .get(&url)
+        .timeout(http_client::timeout(&config.http_client, Operation::DeviceDescription));
+    let result = send_traced(request, "device description").await;
+    circuit_breaker::record(config, device, &result);
+    match result {
         Ok(res) if res.status().is_success() => {
+            // Supervisors that dont report their version in the description may report it in a header
+            let header_version = res
+                .headers()
+                .get(SUPERVISOR_VERSION_HEADER)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty());
             match res.json::<serde_json::Value>().await {
                 Ok(v) => {
-                    match serde_json::from_value::<DeviceDescription>(v) {
-                        Ok(dd) => Some(dd),
+                    let mut description = match serde_json::from_value::<DeviceDescription>(v) {
+                        Ok(dd) => dd,
                         Err(e) => {
                             warn!("Device '{}' description not in expected shape: {}. Using default.", device.name, e);
-                            Some(default_device_description())
+                            default_device_description()
                         }
+                    };
+                    if description.supervisor_version.is_none() {
+                        description.supervisor_version = header_version;
                     }
+                    Some(description)
                 }
                 Err(e) => {
                     warn!("Device '{}' description JSON error: {}", device.name, e);
@@ -342,31 +631,137 @@ async fn fetch_device_description(device: &DeviceDoc) -> Option<DeviceDescriptio
 }
 
 
-/// Do a healthcheck on a device.
-async fn fetch_device_health(device: &DeviceDoc) -> Option<HealthReport> {
+/// Finds out the supervisor interfaces of a device whose description doesnt list them. The
+/// capability endpoint (`interfaceProbePath` of the discovery settings) is asked first, and it may return either a
+/// list of interface names or an object with a `supervisorInterfaces` list. If that fails, the
+/// interfaces are inferred from the actions of the Web of Things description of the device.
+/// Returns the interfaces and where they were found, or None if neither gave any.
+async fn probe_supervisor_interfaces(config: &Config, device: &DeviceDoc) -> Option<(Vec<String>, &'static str)> {
+    let base_url = device.communication.base_url()?;
+
+    let capabilities = get_json(config, device, &format!("{}{}", base_url, config.discovery.interface_probe_path), "interface probe").await;
+    let listed = capabilities.as_ref().and_then(|v| {
+        v.as_array()
+            .or_else(|| v.get("supervisorInterfaces").and_then(Value::as_array))
+            .map(|arr| arr.iter().filter_map(Value::as_str).map(str::to_string).collect::<Vec<_>>())
+    });
+    if let Some(interfaces) = listed.filter(|i| !i.is_empty()) {
+        return Some((interfaces, "capability endpoint"));
+    }
+
+    let td = get_json(config, device, &format!("{}/.well-known/wot-thing-description", base_url), "interface probe").await?;
+    let actions: Vec<String> = td
+        .get("actions")
+        .and_then(Value::as_object)
+        .map(|a| a.keys().cloned().collect())
+        .unwrap_or_default();
+    (!actions.is_empty()).then_some((actions, "Web of Things description"))
+}
+
+
+/// Fetches a JSON document from a supervisor, None if the request fails for any reason
+async fn get_json(config: &Config, device: &DeviceDoc, url: &str, purpose: &str) -> Option<Value> {
+    if let Err(e) = circuit_breaker::check(config, device) {
+        debug!("{} to {} not sent: {}", purpose, url, e);
+        return None;
+    }
+    let _permit = outbound::acquire(&config.http_client, purpose).await;
+    let request = http_client::client(&config.http_client)
+        .get(url)
+        .timeout(http_client::timeout(&config.http_client, Operation::DeviceDescription));
+    let result = send_traced(request, purpose).await;
+    circuit_breaker::record(config, device, &result);
+    match result {
+        Ok(res) if res.status().is_success() => res.json::<Value>().await.ok(),
+        Ok(res) => {
+            debug!("{} to {} returned HTTP status code {}", purpose, url, res.status());
+            None
+        }
+        Err(e) => {
+            debug!("{} to {} failed: {}", purpose, url, e);
+            None
+        }
+    }
+}
+
+
+/// POST /file/device/{device_id}/interfaces/probe
+///
+/// Probes the supervisor interfaces of a device (by id or name) and stores them in its
+/// description, for devices whose description doesnt list them. Works regardless of
+/// DEVICE_INTERFACE_PROBE.
+pub async fn probe_device_interfaces(config: web::Data<Config>, path: web::Path<String>) -> Result<impl Responder, ApiError> {
+    let device_key = path.into_inner();
+    let collection = get_collection::<DeviceDoc>(&config.database, COLL_DEVICE).await;
+    let device = collection
+        .find_one(device_filter(&device_key))
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("no device matches '{}'", device_key)))?;
+
+    let Some((interfaces, source)) = probe_supervisor_interfaces(&config, &device).await else {
+        return Err(ApiError::not_found(format!("device '{}' didnt report any supervisor interfaces", device.name)));
+    };
+    collection
+        .update_one(
+            doc! { "name": &device.name },
+            doc! { "$set": { "description.supervisorInterfaces": interfaces.clone() } },
+        )
+        .await
+        .map_err(ApiError::db)?;
+    revisions::bump(COLL_DEVICE);
+    info!("🔎 Probed {} supervisor interfaces for device '{}' from its {}", interfaces.len(), device.name, source);
+
+    ok_json(&json!({
+        "device": device.name,
+        "supervisorInterfaces": interfaces,
+        "source": source,
+    }))
+}
+
+
+/// When the latest round of health checks finished
+static LAST_HEALTH_CHECK_ROUND: Lazy<parking_lot::Mutex<Option<chrono::DateTime<Utc>>>> = Lazy::new(|| parking_lot::Mutex::new(None));
+
+
+/// When the latest round of health checks finished, None if no round has finished since startup
+pub fn last_health_check_round() -> Option<chrono::DateTime<Utc>> {
+    *LAST_HEALTH_CHECK_ROUND.lock()
+}
+
+
+/// Attempt to fetch a health report from the device. The response latency of the check is
+/// stored along with the report. The shared client keeps connections to supervisors alive
+/// between checks, so reconnecting doesnt skew the latency measurements.
+async fn fetch_device_health(device: &DeviceDoc, config: &Config) -> Option<Health> {
     let h = reqwest::header::HeaderName::from_bytes(b"X-Forwarded-For").unwrap();
     let mut headers = reqwest::header::HeaderMap::new();
-    let public_host = std::env::var("PUBLIC_HOST").unwrap_or_else(|_| {
-        log::warn!("PUBLIC_HOST environment variable is not set. Using default value 'localhost'");
-        "localhost".to_string()
-    });
+    let public_host = zeroconf::public_host(&config.server);
     headers.insert(h, public_host.parse().unwrap());
-    let addr = device.communication.addresses.get(0)?;
-    let url = format!(
-        "http://{}:{}/health",
-        addr,
-        device.communication.port
-    );
+    let base_url = device.communication.base_url()?;
+    let url = format!("{}/health", base_url);
 
-    let client = reqwest::Client::new();
-    match client.get(&url).headers(headers).send().await {
+    // A device whose breaker is open fails the check right away
+    if let Err(e) = circuit_breaker::check(config, device) {
+        debug!("Healthcheck of device {} not sent: {}", device.name, e);
+        return None;
+    }
+    let _permit = outbound::acquire(&config.http_client, "health check").await;
+    let started = Instant::now();
+    let request = http_client::client(&config.http_client)
+        .get(&url)
+        .headers(headers)
+        .timeout(Duration::from_secs(config.health_checks.timeout_s));
+    let result = send_traced(request, "health check").await;
+    circuit_breaker::record(config, device, &result);
+    match result {
         Ok(res) if res.status().is_success() => {
             if let Some(header_value) = res.headers().get("Custom-Orchestrator-Set") {
                 if let Ok(value) = header_value.to_str() {
                     debug!("Custom-Orchestrator-Set header: {}", value);
                     if value == "false" {
                         info!("Device '{}' requested orchestrator registration", device.name);
-                        if let Err(e) = register_orchestrator(device).await {
+                        if let Err(e) = register_orchestrator(device, config).await {
                             warn!("❗️ Failed to register orchestrator for device '{}': {}", device.name, e);
                         } else {
                             info!("✅ Registered orchestrator for device '{}'", device.name);
@@ -375,7 +770,14 @@ async fn fetch_device_health(device: &DeviceDoc) -> Option<HealthReport> {
                 }
             }
             match res.json::<serde_json::Value>().await {
-                Ok(v) => serde_json::from_value::<HealthReport>(v).ok(),
+                Ok(v) => {
+                    let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+                    serde_json::from_value::<HealthReport>(v).ok().map(|report| Health {
+                        report,
+                        time_of_query: Utc::now(),
+                        latency_ms: Some(latency_ms),
+                    })
+                }
                 Err(e) => {
                     debug!("Invalid health JSON for {}: {}", device.name, e);
                     None
@@ -395,104 +797,275 @@ async fn fetch_device_health(device: &DeviceDoc) -> Option<HealthReport> {
 
 
 /// Continous loop for running health checks on known devices
-pub async fn run_health_check_loop() {
+pub async fn run_health_check_loop(config: Arc<Config>) {
     loop {  
-        if let Err(e) = perform_health_checks().await {
+        if let Err(e) = perform_health_checks(&config).await {
             error!("Health check loop failed: {}", e);
         } else {
             debug!("✅ Device healthchecks completed");
         }
-        sleep(Duration::from_secs(*DEVICE_HEALTH_CHECK_INTERVAL_S)).await;
+        watchdog::heartbeat(watchdog::LOOP_HEALTH_CHECKS);
+        sleep(Duration::from_secs(config.health_checks.interval_s)).await;
     }
 }
 
 
 /// Performs health checks on all known devices.
 /// Will mark devices as inactive if certain number of health checks are failed.
-async fn perform_health_checks() -> mongodb::error::Result<()>{
-    let collection = get_collection::<DeviceDoc>(COLL_DEVICE).await;
+/// The results are written with atomic updates (see `record_health_check`), so that
+/// discovery or registration writing to the same devices meanwhile isnt overwritten.
+/// Devices are checked concurrently, at most `concurrency` of the configuration at a time, and
+/// a device that doesnt answer within `timeout_s` counts as a failed check. Devices that have
+/// pushed a health report within `push_fresh_s` arent polled.
+async fn perform_health_checks(config: &Config) -> mongodb::error::Result<()>{
+    let collection = get_collection::<DeviceDoc>(&config.database, COLL_DEVICE).await;
     let devices: Vec<DeviceDoc> = collection.find(doc! {}).await?
         .try_collect()
         .await?;
 
+    let inactive_count = devices.iter().filter(|d| d.status == StatusEnum::Inactive).count();
+    let (pushed, devices): (Vec<DeviceDoc>, Vec<DeviceDoc>) = devices
+        .into_iter()
+        .partition(|d| has_fresh_pushed_health(d, config));
+    let timeout = Duration::from_secs(config.health_checks.timeout_s);
+    let threshold = config.health_checks.failed_threshold;
+    let results: Vec<(String, Option<f64>, Duration)> = futures::stream::iter(devices)
+        .map(|device| {
+            let collection = collection.clone();
+            async move {
+                let started = Instant::now();
+                let health = match tokio::time::timeout(timeout, fetch_device_health(&device, config)).await {
+                    Ok(health) => health,
+                    Err(_) => {
+                        debug!("Healthcheck of device {} timed out after {:?}", device.name, timeout);
+                        None
+                    }
+                };
+                if health.is_none() {
+                    // Another address of the device may work
+                    reorder_device_addresses(config, &device).await;
+                }
+                let latency_ms = health.as_ref().map(|h| h.latency_ms.unwrap_or(0.0));
+                record_health_check(config, &collection, &device, health, threshold).await?;
+                Ok::<_, mongodb::error::Error>((device.name, latency_ms, started.elapsed()))
+            }
+        })
+        .buffer_unordered(config.health_checks.concurrency)
+        .try_collect()
+        .await?;
+
+    let ok_count = results.iter().filter(|(_, latency, _)| latency.is_some()).count();
+    let fail_count = results.len() - ok_count;
+    let latency_sum: f64 = results.iter().filter_map(|(_, latency, _)| *latency).sum();
+    let average_latency_ms = if ok_count > 0 { latency_sum / ok_count as f64 } else { 0.0 };
+    let mut durations: Vec<String> = results
+        .iter()
+        .map(|(name, latency, took)| {
+            let outcome = if latency.is_some() { "ok" } else { "failed" };
+            format!("  {}: {} in {} ms", name, outcome, took.as_millis())
+        })
+        .collect();
+    durations.sort();
+    info!(
+        "\n❤️ Health check summary:\n {} succeeded, {} failed, {} pushed their health, {} inactive devices, average latency {:.1} ms\n{}",
+        ok_count, fail_count, pushed.len(), inactive_count, average_latency_ms, durations.join("\n")
+    );
+
+    *LAST_HEALTH_CHECK_ROUND.lock() = Some(Utc::now());
+    events::publish(Event::HealthChecksFinished { succeeded: ok_count, failed: fail_count, inactive: inactive_count });
+    Ok(())
+}
+
+
+/// Whether the supervisor of the device has pushed a health report recently enough that it
+/// doesnt need to be polled
+fn has_fresh_pushed_health(device: &DeviceDoc, config: &Config) -> bool {
+    let fresh = chrono::Duration::seconds(config.health_checks.push_fresh_s as i64);
+    config.health_checks.push_fresh_s > 0
+        && device.health_pushed_at.is_some_and(|pushed| Utc::now() - pushed < fresh)
+}
+
+
+/// POST /file/device/{device_id}/health
+/// 
+/// Health report pushed by the supervisor of a device (by id or name), in the format of its
+/// `GET /health`. The report is recorded like a successful health check, and the health check
+/// loop doesnt poll the device while its latest pushed report is newer than DEVICE_HEALTH_PUSH_FRESH_S.
+/// A supervisor that stops pushing is polled again, and marked inactive if it doesnt answer.
+pub async fn push_device_health(
+    config: web::Data<Config>,
+    path: web::Path<String>,
+    body: web::Json<HealthReport>,
+) -> Result<impl Responder, ApiError> {
+    let key = path.into_inner();
+    let collection = get_collection::<DeviceDoc>(&config.database, COLL_DEVICE).await;
+    let device = match collection.find_one(device_filter(&key)).await {
+        Ok(Some(device)) => device,
+        Ok(None) => return Err(ApiError::not_found("Device not found")),
+        Err(e) => {
+            error!("Failed to retrieve device '{}': {:?}", key, e);
+            return Err(ApiError::internal_error("Failed to retrieve device"));
+        }
+    };
+
     let now = Utc::now();
-    let mut ok_count = 0;
-    let mut fail_count = 0;
-    let mut inactive_count = 0;
+    let health = Health { report: body.into_inner(), time_of_query: now, latency_ms: None };
+    if let Err(e) = record_health_check(&config, &collection, &device, Some(health), config.health_checks.failed_threshold).await {
+        error!("❌ Failed to record the pushed health of device '{}': {:?}", device.name, e);
+        return Err(ApiError::internal_error("Failed to record the health report"));
+    }
+    let pushed_at = bson::to_bson(&now).unwrap_or(Bson::Null);
+    if let Err(e) = update_field::<DeviceDoc>(&config.database, COLL_DEVICE, doc! { "name": &device.name }, "healthPushedAt", pushed_at).await {
+        error!("❌ Failed to update the health push time of device '{}': {:?}", device.name, e);
+        return Err(ApiError::internal_error("Failed to record the health report"));
+    }
+    debug!("❤️ Device '{}' pushed its health", device.name);
+    Ok(HttpResponse::NoContent().finish())
+}
 
-    for mut device in devices {
 
-        if device.status == StatusEnum::Inactive {
-            inactive_count += 1;
-        }
-
-        match fetch_device_health(&device).await {
-            Some(report) => {
-                device.health = Some(Health {
-                    report,
-                    time_of_query: now,
-                });
-                device.failed_health_check_count = 0;
-                device.ok_health_check_count += 1;
-                ok_count += 1;
-
-                if device.status != StatusEnum::Active && device.ok_health_check_count >= *DEVICE_HEALTHCHECK_FAILED_THRESHOLD {
-                    device.status = StatusEnum::Active;
-                    let log = device.status_log.get_or_insert(Vec::new());
-                    log.insert(0, StatusLogEntry {
-                        status: StatusEnum::Active,
-                        time: now,
-                    });
-                    info!("✅ Device '{}' changed to active", device.name);
+/// Stores the result of a single health check. The counters and the latency window are
+/// updated in a single atomic update, and the status only changes through a conditional
+/// update on the updated counters, so each transition is logged exactly once.
+async fn record_health_check(
+    config: &Config,
+    collection: &Collection<DeviceDoc>,
+    device: &DeviceDoc,
+    health: Option<Health>,
+    failed_threshold: u32,
+) -> mongodb::error::Result<()> {
+    let filter = match device.id {
+        Some(id) => doc! { "_id": id },
+        None => doc! { "name": &device.name },
+    };
+    let threshold = failed_threshold as i64;
+
+    let (updated, new_status) = match health {
+        Some(health) => {
+            record_health_sample(config, device, device.health.as_ref(), &health).await;
+
+            // Pipeline update, so that the statistics can be computed from the updated window
+            let latency = health.latency_ms;
+            let mut pipeline = vec![doc! {
+                "$set": {
+                    "health": { "$literal": bson::to_bson(&health)? },
+                    "failed_health_check_count": 0,
+                    "ok_health_check_count": { "$add": [{ "$ifNull": ["$ok_health_check_count", 0] }, 1] },
                 }
+            }];
+            if let Some(latency_ms) = latency {
+                pipeline.extend(latency_window_stages("latency", latency_ms));
             }
-            None => {
-                device.ok_health_check_count = 0;
-                device.failed_health_check_count += 1;
-                fail_count += 1;
-                device.health = None;
-
-                if device.status != StatusEnum::Inactive && device.failed_health_check_count >= *DEVICE_HEALTHCHECK_FAILED_THRESHOLD {
-                    device.status = StatusEnum::Inactive;
-                    let log = device.status_log.get_or_insert(Vec::new());
-                    log.insert(0, StatusLogEntry {
-                        status: StatusEnum::Inactive,
-                        time: now,
-                    });
-                    warn!("🔴 Device '{}' changed to inactive", device.name);
-
-                    // TODO: Implement the deployment check logic thing here later
-                }
+            collection.update_one(filter.clone(), pipeline).await?;
+            (doc! { "status": { "$ne": "active" }, "ok_health_check_count": { "$gte": threshold } }, StatusEnum::Active)
+        }
+        None => {
+            collection
+                .update_one(filter.clone(), doc! {
+                    "$set": { "ok_health_check_count": 0, "health": Bson::Null },
+                    "$inc": { "failed_health_check_count": 1 },
+                })
+                .await?;
+            (doc! { "status": { "$ne": "inactive" }, "failed_health_check_count": { "$gte": threshold } }, StatusEnum::Inactive)
+        }
+    };
+    revisions::bump(COLL_DEVICE);
+
+    // Change the status only if the updated counters crossed the threshold
+    let mut transition_filter = filter;
+    transition_filter.extend(updated);
+    let entry = StatusLogEntry { status: new_status, time: Utc::now() };
+    let changed = collection
+        .find_one_and_update(transition_filter, vec![doc! {
+            "$set": {
+                "status": bson::to_bson(&new_status)?,
+                "status_log": { "$concatArrays": [[{ "$literal": bson::to_bson(&entry)? }], { "$ifNull": ["$status_log", []] }] },
             }
+        }])
+        .return_document(ReturnDocument::After)
+        .await?;
+    let Some(changed) = changed else {
+        return Ok(());
+    };
+    revisions::bump(COLL_DEVICE);
+
+    let (device_id, device_name, time) = (changed.id.map(|id| id.to_hex()), changed.name.clone(), entry.time);
+    match new_status {
+        StatusEnum::Active => {
+            info!("✅ Device '{}' changed to active", changed.name);
+            events::publish(Event::DeviceActive { device_id, device_name, time });
+        }
+        StatusEnum::Inactive => {
+            warn!("🔴 Device '{}' changed to inactive", changed.name);
+            events::publish(Event::DeviceInactive { device_id, device_name, time });
+
+            // TODO: Implement the deployment check logic thing here later
         }
+    }
+    Ok(())
+}
+
 
-        // Write updates back to mongo
-        let update = doc! {
+/// Adds the latency to the rolling window in `field` of the device document and updates the
+/// statistics of the window, as stages of a pipeline update
+fn latency_window_stages(field: &str, latency_ms: f64) -> Vec<bson::Document> {
+    let recent = format!("${}.recentMs", field);
+    vec![
+        doc! {
             "$set": {
-                "status": bson::to_bson(&device.status)?,
-                "failed_health_check_count": device.failed_health_check_count,
-                "ok_health_check_count": device.ok_health_check_count,
-                "status_log": bson::to_bson(&device.status_log)?,
-                "health": bson::to_bson(&device.health)?,
+                field: {
+                    "recentMs": {
+                        "$slice": [
+                            { "$concatArrays": [{ "$ifNull": [recent.as_str(), []] }, [latency_ms]] },
+                            -(DEVICE_LATENCY_WINDOW as i64),
+                        ]
+                    }
+                }
             }
-        };
-        collection.update_one(doc! { "name": &device.name }, update).await?;
-    }
+        },
+        doc! {
+            "$set": {
+                field: {
+                    "averageMs": { "$avg": recent.as_str() },
+                    "minMs": { "$min": recent.as_str() },
+                    // Nearest rank, the element at ceil(0.95 * n) - 1 of the sorted window
+                    "p95Ms": {
+                        "$let": {
+                            "vars": { "sorted": { "$sortArray": { "input": recent.as_str(), "sortBy": 1 } } },
+                            "in": {
+                                "$arrayElemAt": ["$$sorted", {
+                                    "$toInt": { "$subtract": [{ "$ceil": { "$multiply": [0.95, { "$size": "$$sorted" }] } }, 1] }
+                                }]
+                            }
+                        }
+                    },
+                }
+            }
+        },
+    ]
+}
 
-    info!(
-        "\n❤️ Health check summary:\n {} succeeded, {} failed, {} inactive devices",
-        ok_count, fail_count, inactive_count
-    );
 
-    Ok(())
+/// Records the round trip time of a deployment request to the device in its
+/// `deploymentLatency` window
+pub async fn record_deployment_latency(config: &Config, device: &DeviceDoc, latency_ms: f64) {
+    let filter = match device.id {
+        Some(id) => doc! { "_id": id },
+        None => doc! { "name": &device.name },
+    };
+    let collection = get_collection::<DeviceDoc>(&config.database, COLL_DEVICE).await;
+    match collection.update_one(filter, latency_window_stages("deploymentLatency", latency_ms)).await {
+        Ok(_) => revisions::bump(COLL_DEVICE),
+        Err(e) => warn!("Failed to record the deployment latency of device '{}': {}", device.name, e),
+    }
 }
 
 
 /// POST /file/device/discovery/reset
 /// 
 /// Handler for resetting device discovery
-pub async fn reset_device_discovery() -> Result<impl Responder, ApiError> {
-    match zeroconf::run_single_mdns_scan(5).await {
+pub async fn reset_device_discovery(config: web::Data<Config>) -> Result<impl Responder, ApiError> {
+    match discovery::run_single_scan(config.into_inner(), 5).await {
         Ok(_) => Ok(HttpResponse::NoContent().finish()),
         Err(e) => {
             error!("Failed to trigger device rescan: {}", e);
@@ -504,41 +1077,104 @@ pub async fn reset_device_discovery() -> Result<impl Responder, ApiError> {
 
 /// GET /file/device
 /// 
-/// Returns all known devices from the database.
-pub async fn get_all_devices() -> Result<impl Responder, ApiError> {
-    let collection = get_collection::<DeviceDoc>(COLL_DEVICE).await;
-
-    match collection.find(doc! {}).await {
-        Ok(cursor) => {
-            match cursor.try_collect::<Vec<DeviceDoc>>().await {
-                Ok(devices) => {
-                    let mut v = serde_json::to_value(&devices).map_err(ApiError::internal_error)?;
-                    crate::lib::utils::normalize_object_ids(&mut v);
-                    Ok(HttpResponse::Ok().json(v))
-                },
-                Err(e) => {
-                    error!("❌ Failed to collect devices: {:?}", e);
-                    Err(ApiError::internal_error("Failed to collect devices"))
-                }
-            }
-        }
-        Err(e) => {
-            error!("❌ Failed to query devices: {:?}", e);
-            Err(ApiError::internal_error("Failed to query devices"))
+/// Returns known devices from the database. Supports optional query parameters:
+/// - `status`: `active` or `inactive`
+/// - `namePrefix`: only devices whose name starts with the given string
+/// - `zone`: only devices that have a node card in the given zone
+/// - `interface`: only devices whose supervisor exposes the given interface
+/// - `maxLatencyMs`: only devices whose 95th percentile health check latency is at most this
+/// - `sort`: one of `name`, `status`, `latency` (average health check latency), `p95Latency` or
+///   `deploymentLatency` (average deployment latency), prefixed with `-` for descending order
+/// - `limit` / `offset`: pagination, the total count is returned in the `X-Total-Count` header
+pub async fn get_all_devices(config: web::Data<Config>, req: HttpRequest, query: web::Query<HashMap<String, String>>) -> Result<impl Responder, ApiError> {
+    let pagination = Pagination::from_query(&query, DEVICE_SORT_FIELDS, None)?;
+
+    // The zone filter depends on node cards, so changes to them also change the ETag
+    revisions::cached_json(&req, &[COLL_DEVICE, COLL_NODE_CARDS], || async move {
+        let filter = device_query_filter(&config, &query).await?;
+        let collection = get_collection::<DeviceDoc>(&config.database, COLL_DEVICE).await;
+        find_page(&collection, filter, &pagination).await.map_err(|e| {
+            error!("❌ Failed to query devices: {}", e);
+            ApiError::internal_error("Failed to query devices")
+        })
+    }).await
+}
+
+
+/// Sort keys accepted by the device listing, and the fields they sort by
+const DEVICE_SORT_FIELDS: &[(&str, &str)] = &[
+    ("name", "name"),
+    ("status", "status"),
+    ("latency", "latency.averageMs"),
+    ("p95Latency", "latency.p95Ms"),
+    ("deploymentLatency", "deploymentLatency.averageMs"),
+];
+
+
+/// Builds the database filter for the device listing query parameters
+async fn device_query_filter(config: &Config, query: &HashMap<String, String>) -> Result<bson::Document, ApiError> {
+    let mut filter = doc! {};
+    if let Some(status) = query.get("status") {
+        if status != "active" && status != "inactive" {
+            return Err(ApiError::bad_request(format!("invalid status '{}', expected active or inactive", status)));
         }
+        filter.insert("status", status);
+    }
+    if let Some(prefix) = query.get("namePrefix") {
+        filter.insert("name", doc! { "$regex": format!("^{}", escape_regex(prefix)) });
     }
+    if let Some(interface) = query.get("interface") {
+        filter.insert("description.supervisorInterfaces", interface);
+    }
+    if let Some(max) = query.get("maxLatencyMs") {
+        let max: f64 = max
+            .parse()
+            .map_err(|_| ApiError::bad_request(format!("invalid maxLatencyMs '{}', expected a number", max)))?;
+        filter.insert("latency.p95Ms", doc! { "$lte": max });
+    }
+    if let Some(zone) = query.get("zone") {
+        // Node cards refer to devices either by their id or by their name
+        let nodeids: Vec<String> = get_collection::<NodeCard>(&config.database, COLL_NODE_CARDS)
+            .await
+            .distinct("nodeid", doc! { "zone": zone })
+            .await
+            .map_err(ApiError::db)?
+            .into_iter()
+            .filter_map(|b| b.as_str().map(|s| s.to_string()))
+            .collect();
+        let oids: Vec<bson::oid::ObjectId> = nodeids
+            .iter()
+            .filter_map(|n| bson::oid::ObjectId::parse_str(n).ok())
+            .collect();
+        filter.insert("$or", vec![
+            doc! { "_id": { "$in": oids } },
+            doc! { "name": { "$in": nodeids } },
+        ]);
+    }
+    Ok(filter)
 }
 
 
 /// DELETE /file/device
 /// 
-/// Deletes all known devices from database
-pub async fn delete_all_devices() -> Result<impl Responder, ApiError> {
-    match get_collection::<DeviceDoc>(COLL_DEVICE).await
+/// Deletes all known devices from database. With `names` (comma-separated names or ids) only
+/// those devices are deleted, each as with DELETE /file/device/{device_id} (`cascade` and
+/// `strict` apply to all of them), and the response lists the outcome per device.
+pub async fn delete_all_devices(
+    config: web::Data<Config>,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<impl Responder, ApiError> {
+    if let Some(names) = query.get("names") {
+        return delete_devices_by_names(&config, names, &query).await;
+    }
+    match get_collection::<DeviceDoc>(&config.database, COLL_DEVICE).await
         .delete_many(doc! {})
         .await
     {
-        Ok(result) => Ok(HttpResponse::Ok().json(json!({ "deleted_count": result.deleted_count }))),
+        Ok(result) => {
+            revisions::bump(COLL_DEVICE);
+            Ok(HttpResponse::Ok().json(json!({ "deleted_count": result.deleted_count })))
+        },
         Err(e) => {
             error!("❌ Failed to delete all devices: {}", e);
             Err(ApiError::internal_error("Failed to delete devices"))
@@ -547,15 +1183,63 @@ pub async fn delete_all_devices() -> Result<impl Responder, ApiError> {
 }
 
 
+/// Deletes the listed devices one by one, so that one in use or missing doesnt stop the others
+async fn delete_devices_by_names(
+    config: &Config,
+    names: &str,
+    query: &HashMap<String, String>,
+) -> Result<HttpResponse, ApiError> {
+    let keys: Vec<&str> = names.split(',').map(str::trim).filter(|n| !n.is_empty()).collect();
+    if keys.is_empty() {
+        return Err(ApiError::bad_request("names is empty, expected comma-separated device names or ids"));
+    }
+    let cascade = cascade_option(query)?;
+    let strict = strict_validation(config, query)?;
+
+    let mut results = Vec::with_capacity(keys.len());
+    let mut deleted_count = 0;
+    for key in keys {
+        let result = match delete_device(config, key, cascade, strict).await {
+            Ok(DeviceDeletion::InUse { deployments }) => json!({
+                "name": key,
+                "status": 409,
+                "error": format!("device is used by {} active deployments, delete with cascade=true to move them", deployments.len()),
+                "deployments": deployments,
+            }),
+            Ok(DeviceDeletion::Deleted) => {
+                deleted_count += 1;
+                json!({ "name": key, "status": 204 })
+            }
+            Ok(DeviceDeletion::Cascaded(summary)) => {
+                deleted_count += 1;
+                json!({ "name": key, "status": 200, "cascade": summary })
+            }
+            Err(e) => json!({ "name": key, "status": e.status.as_u16(), "error": e.msg }),
+        };
+        results.push(result);
+    }
+    info!("Deleted {} of {} listed devices", deleted_count, results.len());
+    Ok(HttpResponse::Ok().json(json!({ "deleted_count": deleted_count, "results": results })))
+}
+
+
+/// Creates a filter for device queries based on the provided string.
+/// If the string is a valid ObjectId, it filters by `_id`, otherwise by `name`.
+pub fn device_filter(x: &str) -> bson::Document {
+    match bson::oid::ObjectId::parse_str(x) {
+        Ok(id) => doc! { "_id": id },
+        Err(_) => doc! { "name": x },
+    }
+}
+
+
 /// GET /file/device/{device_id}
 /// 
-/// Returns a single device by name
-pub async fn get_device_by_name(device_name: web::Path<String>) -> Result<impl Responder, ApiError> {
-    match find_one::<DeviceDoc>(COLL_DEVICE, doc! { "name": device_name.as_str() }).await {
+/// Returns a single device by its id or name
+pub async fn get_device_by_name(config: web::Data<Config>, device_name: web::Path<String>) -> Result<impl Responder, ApiError> {
+    match find_one::<DeviceDoc>(&config.database, COLL_DEVICE, device_filter(&device_name)).await {
         Ok(Some(device)) => {
-            let mut v = serde_json::to_value(&device).map_err(ApiError::internal_error)?;
-            crate::lib::utils::normalize_object_ids(&mut v);
-            Ok(HttpResponse::Ok().json(v))
+            ok_json(&device)
         },
         Ok(None) => Err(ApiError::not_found("Device not found")),
         Err(e) => {
@@ -566,35 +1250,332 @@ pub async fn get_device_by_name(device_name: web::Path<String>) -> Result<impl R
 }
 
 
-/// DELETE /file/device/{device_id}
+/// PATCH /file/device/{device_id}
 /// 
-/// Deletes a specific device from database (by its name)
-pub async fn delete_device_by_name(path: web::Path<String>) -> Result<impl Responder, ApiError> {
-    let name = path.into_inner();
+/// Renames a device (by id or name). The device keeps its id, status log and health, and the
+/// supervisor logs, execution outputs and node cards that refer to it by name are moved to the
+/// new name. Responds with 409 if another device already has the name. Note that a device found
+/// through mDNS is discovered again under the name it advertises.
+pub async fn update_device(
+    config: web::Data<Config>,
+    path: web::Path<String>,
+    body: web::Json<DeviceUpdate>,
+) -> Result<impl Responder, ApiError> {
+    let key = path.into_inner();
+    let Some(new_name) = body.name.as_deref().map(str::trim) else {
+        return Err(ApiError::bad_request("nothing to update, expected name"));
+    };
+    if new_name.is_empty() {
+        return Err(ApiError::bad_request("name cant be empty"));
+    }
+
+    let collection = get_collection::<DeviceDoc>(&config.database, COLL_DEVICE).await;
+    let device = collection
+        .find_one(device_filter(&key))
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("no device matches '{}'", key)))?;
+    let device_id = device
+        .id
+        .ok_or_else(|| ApiError::internal_error(format!("device '{}' has no id", device.name)))?;
+    if device.name == new_name {
+        return Ok(HttpResponse::Ok().json(json!({ "device": device })));
+    }
+    if collection.find_one(doc! { "name": new_name }).await.map_err(ApiError::db)?.is_some() {
+        return Err(ApiError::conflict(format!("a device named '{}' already exists", new_name)));
+    }
 
-    match get_collection::<DeviceDoc>(COLL_DEVICE).await
-        .delete_one(doc! { "name": name.clone() })
+    // Conditional on the old name, so that a concurrent rename isnt overwritten
+    let renamed = match collection
+        .find_one_and_update(doc! { "_id": device_id, "name": &device.name }, doc! { "$set": { "name": new_name } })
+        .return_document(ReturnDocument::After)
         .await
     {
-        Ok(result) => {
-            if result.deleted_count == 1 {
-                Ok(HttpResponse::NoContent().finish())
-            } else {
-                Err(ApiError::not_found(format!("Device '{}' not found", name)))
+        Ok(Some(renamed)) => renamed,
+        Ok(None) => return Err(ApiError::conflict(format!("device '{}' was changed while renaming it, try again", device.name))),
+        Err(e) if is_duplicate_key(&e) => return Err(ApiError::conflict(format!("a device named '{}' already exists", new_name))),
+        Err(e) => return Err(ApiError::db(e)),
+    };
+    revisions::bump(COLL_DEVICE);
+    circuit_breaker::rename(&device.name, new_name);
+
+    let references = rename_device_references(&config, &device_id, &device.name, new_name).await;
+    info!("✏️ Renamed device '{}' to '{}'", device.name, new_name);
+    Ok(HttpResponse::Ok().json(json!({ "device": renamed, "updatedReferences": references })))
+}
+
+
+/// Moves the documents that refer to a device by name to its new name. Returns how many of
+/// each were updated, failures are logged and left out.
+async fn rename_device_references(config: &Config, device_id: &bson::oid::ObjectId, old_name: &str, new_name: &str) -> Value {
+    let updates = [
+        ("supervisorLogs", COLL_LOGS, "deviceName"),
+        ("executionOutputs", COLL_EXECUTION_OUTPUTS, "device"),
+        ("nodeCards", COLL_NODE_CARDS, "nodeid"),
+    ];
+    let mut counts = serde_json::Map::new();
+    for (label, collection, field) in updates {
+        let result = get_collection::<bson::Document>(&config.database, collection).await
+            .update_many(doc! { field: old_name }, doc! { "$set": { field: new_name } })
+            .await;
+        match result {
+            Ok(result) => {
+                counts.insert(label.to_string(), json!(result.modified_count));
             }
+            Err(e) => error!(
+                "❌ Failed to rename device '{}' ({}) to '{}' in '{}': {}",
+                old_name, device_id, new_name, collection, e
+            ),
         }
-        Err(e) => {
-            error!("❌ Failed to delete device '{}': {}", name, e);
-            Err(ApiError::internal_error("Failed to delete device"))
+    }
+    revisions::bump(COLL_NODE_CARDS);
+    Value::Object(counts)
+}
+
+
+/// GET /file/device/{device_id}/deployments
+/// 
+/// Lists the deployments placed on the device (by id or name), with whether they are active
+/// and the functions of the sequence that run on the device. Optionally filtered with
+/// `active=true` or `active=false`.
+pub async fn get_device_deployments(
+    config: web::Data<Config>,
+    path: web::Path<String>,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<impl Responder, ApiError> {
+    let key = path.into_inner();
+    let active = match query.get("active") {
+        Some(v) => Some(v
+            .parse::<bool>()
+            .map_err(|_| ApiError::bad_request(format!("invalid value for active '{}', expected true or false", v)))?),
+        None => None,
+    };
+    let device = find_one::<DeviceDoc>(&config.database, COLL_DEVICE, device_filter(&key))
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("Device '{}' not found", key)))?;
+    let device_id = device
+        .id
+        .ok_or_else(|| ApiError::internal_error(format!("device '{}' has no id", device.name)))?;
+    let device_hex = device_id.to_hex();
+
+    // Only the part of the manifest for this device is read
+    let node_field = format!("fullManifest.{}", device_hex);
+    let mut filter = doc! { node_field.as_str(): { "$exists": true } };
+    match active {
+        Some(true) => { filter.insert("active", true); }
+        Some(false) => { filter.insert("active", doc! { "$ne": true }); }
+        None => {}
+    }
+    let rows: Vec<DeviceDeploymentRow> = get_collection::<DeviceDeploymentRow>(&config.database, COLL_DEPLOYMENT).await
+        .find(filter)
+        .projection(doc! { "name": 1, "active": 1, "sequence": 1, format!("{}.modules", node_field): 1 })
+        .sort(doc! { "name": 1 })
+        .await
+        .map_err(ApiError::db)?
+        .try_collect()
+        .await
+        .map_err(ApiError::db)?;
+
+    let deployments: Vec<Value> = rows.iter().map(|row| {
+        let modules = row.full_manifest.get(&device_hex).map(|n| n.modules.as_slice()).unwrap_or_default();
+        let functions: Vec<Value> = row.sequence.iter()
+            .enumerate()
+            .filter(|(_, step)| step.device == device_id)
+            .map(|(i, step)| json!({
+                "step": i,
+                "moduleId": step.module.to_hex(),
+                "module": modules.iter().find(|m| m.id == step.module).map(|m| m.name.as_str()),
+                "function": step.func,
+            }))
+            .collect();
+        json!({
+            "deploymentId": row.id.to_hex(),
+            "name": row.name,
+            "active": row.active.unwrap_or(false),
+            "functions": functions,
+        })
+    }).collect();
+
+    Ok(HttpResponse::Ok().json(json!({
+        "deviceId": device_hex,
+        "device": device.name,
+        "count": deployments.len(),
+        "deployments": deployments,
+    })))
+}
+
+
+/// DELETE /file/device/{device_id}
+/// 
+/// Deletes a device by its id or name. Responds with 409 and the dependent deployments if
+/// active deployments use the device. With `cascade=true` the device is deleted anyway: the
+/// deployments are solved again without it and redeployed (`strict` works as when updating a
+/// deployment), the ones that cant be moved are deactivated, and the node and datasource cards
+/// of the device are removed.
+pub async fn delete_device_by_name(
+    config: web::Data<Config>,
+    path: web::Path<String>,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, ApiError> {
+    let name = path.into_inner();
+    let cascade = cascade_option(&query)?;
+    let strict = strict_validation(&config, &query)?;
+    match delete_device(&config, &name, cascade, strict).await? {
+        DeviceDeletion::InUse { deployments } => Ok(HttpResponse::Conflict().json(json!({
+            "error": format!("conflict: device '{}' is used by {} active deployments, delete with cascade=true to move them", name, deployments.len()),
+            "deployments": deployments,
+        }))),
+        DeviceDeletion::Deleted => Ok(HttpResponse::NoContent().finish()),
+        DeviceDeletion::Cascaded(summary) => Ok(HttpResponse::Ok().json(summary)),
+    }
+}
+
+
+/// Outcome of deleting a device
+enum DeviceDeletion {
+    InUse { deployments: Vec<Value> }, // Not deleted, these active deployments use the device
+    Deleted,
+    Cascaded(Value), // Deleted along with moving its deployments, with a summary of what was done
+}
+
+
+/// The `cascade` query parameter of device deletion
+fn cascade_option(query: &HashMap<String, String>) -> Result<bool, ApiError> {
+    match query.get("cascade") {
+        Some(v) => v
+            .parse::<bool>()
+            .map_err(|_| ApiError::bad_request(format!("invalid value for cascade '{}', expected true or false", v))),
+        None => Ok(false),
+    }
+}
+
+
+/// Deletes a device by its id or name, see DELETE /file/device/{device_id}
+async fn delete_device(config: &Config, name: &str, cascade: bool, strict: bool) -> Result<DeviceDeletion, ApiError> {
+    let device = find_one::<DeviceDoc>(&config.database, COLL_DEVICE, device_filter(name))
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("Device '{}' not found", name)))?;
+    let device_id = device
+        .id
+        .ok_or_else(|| ApiError::internal_error(format!("device '{}' has no id", device.name)))?;
+
+    let dependents = active_deployments_on(config, &device_id).await.map_err(ApiError::db)?;
+    if !dependents.is_empty() && !cascade {
+        let deployments: Vec<Value> = dependents
+            .iter()
+            .map(|d| json!({ "deploymentId": d.id.map(|id| id.to_hex()), "name": d.name }))
+            .collect();
+        return Ok(DeviceDeletion::InUse { deployments });
+    }
+
+    // Deleted before the deployments are solved again, so that automatic selection skips it
+    if let Err(e) = get_collection::<DeviceDoc>(&config.database, COLL_DEVICE).await
+        .delete_one(doc! { "_id": device_id })
+        .await
+    {
+        error!("❌ Failed to delete device '{}': {}", name, e);
+        return Err(ApiError::internal_error("Failed to delete device"));
+    }
+    revisions::bump(COLL_DEVICE);
+    if !cascade {
+        return Ok(DeviceDeletion::Deleted);
+    }
+
+    let (migrated, failed) = migrate_deployments(config, &device_id, dependents, strict).await;
+    for failure in &failed {
+        warn!("Deactivating deployment '{}', it couldnt be moved off deleted device '{}': {}", failure.name, device.name, failure.error);
+        let Ok(oid) = bson::oid::ObjectId::parse_str(&failure.deployment_id) else { continue };
+        if let Err(e) = update_field::<bson::Document>(&config.database, COLL_DEPLOYMENT, doc! { "_id": oid }, "active", Bson::Boolean(false)).await {
+            error!("Failed to deactivate deployment '{}': {}", failure.name, e);
         }
     }
+
+    let node_cards = get_collection::<NodeCard>(&config.database, COLL_NODE_CARDS).await
+        .delete_many(doc! { "nodeid": { "$in": [device_id.to_hex(), device.name.as_str()] } })
+        .await
+        .map_err(ApiError::db)?;
+    let datasource_cards = get_collection::<DatasourceCard>(&config.database, COLL_DATASOURCE_CARDS).await
+        .delete_many(doc! { "nodeid": device_id })
+        .await
+        .map_err(ApiError::db)?;
+    revisions::bump(COLL_NODE_CARDS);
+    revisions::bump(COLL_DATASOURCE_CARDS);
+
+    info!(
+        "Deleted device '{}' with cascade, moved {} deployments and deactivated {}",
+        device.name, migrated.len(), failed.len()
+    );
+    Ok(DeviceDeletion::Cascaded(json!({
+        "migrated": migrated,
+        "deactivated": failed,
+        "nodeCardsDeleted": node_cards.deleted_count,
+        "datasourceCardsDeleted": datasource_cards.deleted_count,
+    })))
 }
 
 
 /// POST /file/device/discovery/register
 /// 
-/// Adds a device to known devices without depending on mdns mechanisms
-pub async fn register_device(info: web::Json<ManualDeviceRegistration>) -> Result<impl Responder, ApiError> {
+/// Adds a device to known devices without depending on mdns mechanisms. The supervisor is
+/// probed for its device description first unless `probe` is false in the body (502 if it
+/// doesnt answer), and a device with the same name or address and port is updated instead of
+/// registered twice.
+pub async fn register_device(
+    config: web::Data<Config>,
+    info: web::Json<ManualDeviceRegistration>,
+) -> Result<impl Responder, ApiError> {
+    register_manual_device(&config, &info).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+
+/// POST /file/device/discovery/register/batch
+/// 
+/// Registers an array of devices as with POST /file/device/discovery/register. Each device is
+/// registered on its own, and the response lists the outcome per device in the order they were
+/// sent (`status` 201 when registered, 200 when an existing device was updated, otherwise the
+/// error status and message).
+pub async fn register_devices_batch(
+    config: web::Data<Config>,
+    body: web::Json<Vec<ManualDeviceRegistration>>,
+) -> Result<impl Responder, ApiError> {
+    let registrations = body.into_inner();
+    if registrations.is_empty() {
+        return Err(ApiError::bad_request("no devices to register"));
+    }
+    let results: Vec<Value> = futures::stream::iter(registrations.iter().enumerate())
+        .map(|(index, info)| {
+            let config = &config;
+            async move {
+                match register_manual_device(config, info).await {
+                    Ok(RegisteredDevice { name, updated: true }) => json!({ "index": index, "name": name, "status": 200, "updated": true }),
+                    Ok(RegisteredDevice { name, updated: false }) => json!({ "index": index, "name": name, "status": 201 }),
+                    Err(e) => json!({ "index": index, "name": info.name, "status": e.status.as_u16(), "error": e.msg }),
+                }
+            }
+        })
+        .buffered(config.health_checks.concurrency)
+        .collect()
+        .await;
+    let registered = results.iter().filter(|r| r["status"] == 201 || r["status"] == 200).count();
+    info!("🆕 Batch registration registered {} of {} devices", registered, results.len());
+    Ok(HttpResponse::Ok().json(json!({ "registered": registered, "results": results })))
+}
+
+
+/// A device registered manually
+struct RegisteredDevice {
+    name: String,
+    updated: bool, // An existing device with the same name or address was updated
+}
+
+
+/// Registers a device given manually, and fetches its description and health. Unless `probe`
+/// is false, the supervisor must answer with its device description first. A device already
+/// known by the same name, or by one of the addresses with the same port, is updated in place.
+async fn register_manual_device(config: &Config, info: &ManualDeviceRegistration) -> Result<RegisteredDevice, ApiError> {
     let name = info.name.clone()
         .or_else(|| info.host.clone())
         .unwrap_or_else(|| "unknown-device".to_string());
@@ -603,12 +1584,31 @@ pub async fn register_device(info: web::Json<ManualDeviceRegistration>) -> Resul
         .or_else(|| info.host.clone().map(|h| vec![h]))
         .unwrap_or_else(|| vec!["127.0.0.1".to_string()]);
 
-    let port = info.port.unwrap_or(5000);
+    let port = info.port.unwrap_or(config.discovery.default_device_port);
 
-    let device = DeviceDoc {
+    let scheme = info.scheme.clone()
+        .map(|s| s.to_lowercase())
+        .unwrap_or_else(|| config.discovery.default_device_scheme.clone());
+    if scheme != "http" && scheme != "https" {
+        return Err(ApiError::bad_request(format!("Unsupported scheme '{}', expected 'http' or 'https'", scheme)));
+    }
+
+    // Properties as advertised over mDNS, non-string values are kept as JSON
+    let properties: Option<BTreeMap<String, String>> = match &info.properties {
+        None | Some(Value::Null) => None,
+        Some(Value::Object(map)) => Some(
+            map.iter()
+                .map(|(k, v)| (k.clone(), v.as_str().map(str::to_string).unwrap_or_else(|| v.to_string())))
+                .collect(),
+        ),
+        Some(_) => return Err(ApiError::bad_request("'properties' must be an object")),
+    };
+    let path = properties.as_ref().and_then(|p| p.get("path")).and_then(|p| base_path(p));
+
+    let mut device = DeviceDoc {
         id: None,
         name: name.clone(),
-        communication: DeviceCommunication { addresses: addresses.clone(), port },
+        communication: DeviceCommunication { addresses: addresses.clone(), port, scheme, path },
         description: default_device_description(),
         status: StatusEnum::Active,
         ok_health_check_count: 0,
@@ -618,48 +1618,106 @@ pub async fn register_device(info: web::Json<ManualDeviceRegistration>) -> Resul
             time: Utc::now(),
         }]),
         health: None,
+        latency: None,
+        deployment_latency: None,
+        drain: None,
+        circuit_breaker: None,
+        disappeared_at: None,
+        discovered_by: None,
+        properties: properties.filter(|p| !p.is_empty()),
+        health_pushed_at: None,
     };
 
-    if let Err(e) = insert_one(COLL_DEVICE, &device).await {
-        error!("❌ Manual registration failed for '{}': {:?}", device.name, e);
-        return Err(ApiError::internal_error("Failed to register device"));
+    // Unreachable hosts and addresses that arent supervisors are refused
+    let probed = info.probe.unwrap_or(true);
+    if probed {
+        match fetch_device_description(&config, &device).await {
+            Some(desc) => device.description = desc,
+            None => {
+                return Err(ApiError::bad_gateway(format!(
+                    "device '{}' at {} didnt answer with a device description, register with probe=false to skip this check",
+                    name,
+                    device.communication.base_url().unwrap_or_default()
+                )));
+            }
+        }
     }
 
-    info!("🆕 Manually registered device '{}'", name);
+    let collection = get_collection::<DeviceDoc>(&config.database, COLL_DEVICE).await;
+    let existing = collection
+        .find_one(doc! { "$or": [
+            { "name": &name },
+            { "communication.addresses": { "$in": addresses.clone() }, "communication.port": port as i32 },
+        ] })
+        .await
+        .map_err(ApiError::db)?;
+
+    let updated = match existing.and_then(|d| d.id) {
+        Some(id) => {
+            let mut set_doc = doc! {
+                "name": &name,
+                "communication": to_bson(&device.communication).map_err(ApiError::internal_error)?,
+                "status": to_bson(&StatusEnum::Active).map_err(ApiError::internal_error)?,
+            };
+            if let Some(properties) = &device.properties {
+                set_doc.insert("properties", to_bson(properties).map_err(ApiError::internal_error)?);
+            }
+            if probed {
+                set_doc.insert("description", to_bson(&device.description).map_err(ApiError::internal_error)?);
+            }
+            match collection.update_one(doc! { "_id": id }, doc! { "$set": set_doc }).await {
+                Ok(_) => {}
+                Err(e) if is_duplicate_key(&e) => {
+                    return Err(ApiError::conflict(format!("another device named '{}' is already registered", name)));
+                }
+                Err(e) => {
+                    error!("❌ Manual registration failed for '{}': {:?}", name, e);
+                    return Err(ApiError::internal_error("Failed to register device"));
+                }
+            }
+            revisions::bump(COLL_DEVICE);
+            info!("🔁 Manually registered device '{}' was already known, updated it", name);
+            true
+        }
+        None => {
+            if let Err(e) = insert_one(&config.database, COLL_DEVICE, &device).await {
+                if is_duplicate_key(&e) {
+                    return Err(ApiError::conflict(format!("a device named '{}' is already registered", device.name)));
+                }
+                error!("❌ Manual registration failed for '{}': {:?}", device.name, e);
+                return Err(ApiError::internal_error("Failed to register device"));
+            }
+            info!("🆕 Manually registered device '{}'", name);
+            false
+        }
+    };
 
     // Fetch description and health like mDNS logic
-    if let Some(desc) = fetch_device_description(&device).await {
-        let bson_desc = to_bson(&desc).unwrap_or(Bson::Null);
-        let _ = update_field::<DeviceDoc>(COLL_DEVICE, doc! { "name": &device.name }, "description", bson_desc).await;
-        info!("📄 '{}' device description fetched", device.name);
+    if !probed {
+        if let Some(desc) = fetch_device_description(&config, &device).await {
+            let bson_desc = to_bson(&desc).unwrap_or(Bson::Null);
+            let _ = update_field::<DeviceDoc>(&config.database, COLL_DEVICE, doc! { "name": &device.name }, "description", bson_desc).await;
+            info!("📄 '{}' device description fetched", device.name);
+        }
     }
 
-    if let Some(report) = fetch_device_health(&device).await {
-        let health = Health {
-            report,
-            time_of_query: Utc::now(),
-        };
+    if let Some(health) = fetch_device_health(&device, config).await {
         let bson_health = to_bson(&health).unwrap_or(Bson::Null);
-        let _ = update_field::<DeviceDoc>(COLL_DEVICE, doc! { "name": &device.name }, "health", bson_health).await;
+        let _ = update_field::<DeviceDoc>(&config.database, COLL_DEVICE, doc! { "name": &device.name }, "health", bson_health).await;
         info!("📄 '{}' initial healthcheck done", device.name);
     }
 
-    Ok(HttpResponse::NoContent().finish())
+    Ok(RegisteredDevice { name, updated })
 }
 
 
 /// Registers the orchestrator with the supervisor.
 /// This is used to inform the supervisor about the orchestrator's URL.
-pub async fn register_orchestrator(device: &DeviceDoc) -> Result<(), reqwest::Error> {
-    let public_host = std::env::var("PUBLIC_HOST").unwrap_or_else(|_| {
-        log::warn!("PUBLIC_HOST environment variable is not set. Using default value 'localhost'");
-        "localhost".to_string()
-    });
-    let public_port = std::env::var("PUBLIC_PORT").unwrap_or_else(|_| {
-        log::warn!("PUBLIC_PORT environment variable is not set. Using default value '3000'");
-        "3000".to_string()
-    });
-    let orchestrator_url = format!("http://{}:{}", public_host, public_port);
+pub async fn register_orchestrator(device: &DeviceDoc, config: &Config) -> Result<(), reqwest::Error> {
+    let server = &config.server;
+    let public_host = zeroconf::public_host(server);
+    let public_port = server.port.to_string();
+    let orchestrator_url = base_url(&server.url_scheme, &public_host, server.port);
 
     let addr = match device.communication.addresses.get(0) {
         Some(a) => a,
@@ -670,23 +1728,20 @@ pub async fn register_orchestrator(device: &DeviceDoc) -> Result<(), reqwest::Er
     };
 
     debug!("Registering orchestrator to supervisor with following url {:?}", orchestrator_url);
-    let url = format!(
-        "http://{}:{}/register",
-        addr,
-        device.communication.port
-    );
+    let url = format!("{}/register", device.communication.base_url().unwrap_or_default());
     if addr == &public_host && device.communication.port.to_string() == public_port {
         info!("Skipping orchestrator self-registration.");
         return Ok(());
     }
-    let client = reqwest::Client::new();
     let payload = json!({ "url": orchestrator_url });
-
-    let response = client
+    let request = http_client::client(&config.http_client)
         .post(&url)
         .json(&payload)
-        .send()
-        .await?;
+        .timeout(http_client::timeout(&config.http_client, Operation::Registration));
+
+    let result = send_traced(request, "register orchestrator").await;
+    circuit_breaker::record(config, device, &result);
+    let response = result?;
 
     if response.status().is_success() {
         log::info!("Successfully registered orchestrator at {}", url);
@@ -0,0 +1,245 @@
+//! # device_drain.rs
+//!
+//! Draining a device for maintenance. The active deployments that use the device are solved
+//! again with the device left out, and sent to the devices they now use. Once all of them have
+//! been moved the device is marked as drained, and it doesnt take new deployments until the
+//! drain is cancelled.
+
+use std::collections::HashMap;
+use actix_web::{web, HttpResponse, Responder};
+use chrono::Utc;
+use futures::TryStreamExt;
+use log::{error, info, warn};
+use mongodb::bson::{self, doc, oid::ObjectId};
+use serde::Serialize;
+use crate::api::deployment::{deploy, solve, strict_validation, ApiSequenceStep, Sequence, SolveResult};
+use crate::api::device::device_filter;
+use crate::lib::config::Config;
+use crate::lib::constants::{COLL_DEPLOYMENT, COLL_DEVICE, SUPPORTED_FILE_TYPES};
+use crate::lib::errors::ApiError;
+use crate::lib::events::{self, Event};
+use crate::lib::mongodb::get_collection;
+use crate::lib::revisions;
+use crate::lib::zeroconf::get_listening_address;
+use crate::structs::deployment::DeploymentDoc;
+use crate::structs::device::{DeviceDoc, DrainState, DrainStatus};
+
+
+/// A deployment moved off a drained or deleted device
+#[derive(Debug, Serialize)]
+pub struct MigratedDeployment {
+    #[serde(rename = "deploymentId")]
+    pub deployment_id: String,
+    pub name: String,
+    pub devices: Vec<String>, // Devices the deployment is now on
+}
+
+/// A deployment that couldnt be moved
+#[derive(Debug, Serialize)]
+pub struct FailedMigration {
+    #[serde(rename = "deploymentId")]
+    pub deployment_id: String,
+    pub name: String,
+    pub error: String,
+}
+
+
+/// POST /file/device/{device_id}/drain
+///
+/// Moves the active deployments that use the device (by id or name) to other devices, and
+/// marks the device as drained. Each deployment is solved again with the steps on the device
+/// left to automatic selection, and redeployed. The `strict` query parameter works as when
+/// updating a deployment. Responds with the moved deployments and with 500 if some of them
+/// couldnt be moved, in which case the drain is marked as failed and the device takes
+/// deployments again. Responds with 409 if the device is already being drained.
+pub async fn drain_device(
+    config: web::Data<Config>,
+    path: web::Path<String>,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<impl Responder, ApiError> {
+    let device_key = path.into_inner();
+    let strict = strict_validation(&config, &query)?;
+    let collection = get_collection::<DeviceDoc>(&config.database, COLL_DEVICE).await;
+    let device = collection
+        .find_one(device_filter(&device_key))
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("no device matches '{}'", device_key)))?;
+    let device_id = device
+        .id
+        .ok_or_else(|| ApiError::internal_error(format!("device '{}' has no id", device.name)))?;
+
+    // Conditional, so that two drains of the same device cant run at once
+    let draining = DrainStatus { state: DrainState::Draining, started_at: Utc::now(), finished_at: None, failed: Vec::new() };
+    let started = collection
+        .update_one(
+            doc! { "_id": device_id, "drain.state": { "$ne": "draining" } },
+            doc! { "$set": { "drain": bson::to_bson(&draining).map_err(ApiError::internal_error)? } },
+        )
+        .await
+        .map_err(ApiError::db)?;
+    if started.matched_count == 0 {
+        return Err(ApiError::conflict(format!("device '{}' is already being drained", device.name)));
+    }
+    revisions::bump(COLL_DEVICE);
+    info!("🚧 Draining device '{}'", device.name);
+
+    let (migrated, failed) = match active_deployments_on(&config, &device_id).await {
+        Ok(deployments) => migrate_deployments(&config, &device_id, deployments, strict).await,
+        Err(e) => {
+            error!("❌ Failed to drain device '{}': {}", device.name, e);
+            (Vec::new(), vec![FailedMigration { deployment_id: String::new(), name: String::new(), error: e }])
+        }
+    };
+
+    let state = if failed.is_empty() { DrainState::Drained } else { DrainState::Failed };
+    let finished = DrainStatus {
+        state,
+        started_at: draining.started_at,
+        finished_at: Some(Utc::now()),
+        failed: failed.iter().map(|f| f.deployment_id.clone()).filter(|id| !id.is_empty()).collect(),
+    };
+    collection
+        .update_one(
+            doc! { "_id": device_id },
+            doc! { "$set": { "drain": bson::to_bson(&finished).map_err(ApiError::internal_error)? } },
+        )
+        .await
+        .map_err(ApiError::db)?;
+    revisions::bump(COLL_DEVICE);
+
+    events::publish(Event::DeviceDrained {
+        device_id: Some(device_id.to_hex()),
+        device_name: device.name.clone(),
+        migrated: migrated.iter().map(|m| m.deployment_id.clone()).collect(),
+        failed: finished.failed.clone(),
+    });
+    let body = serde_json::json!({ "device": device.name, "drain": finished, "migrated": migrated, "failed": failed });
+    if state == DrainState::Drained {
+        info!("✅ Drained device '{}', moved {} deployments", device.name, migrated.len());
+        Ok(HttpResponse::Ok().json(body))
+    } else {
+        warn!("❗️ Draining device '{}' failed, {} deployments couldnt be moved", device.name, failed.len());
+        Ok(HttpResponse::InternalServerError().json(body))
+    }
+}
+
+
+/// DELETE /file/device/{device_id}/drain
+///
+/// Cancels the drain of the device (by id or name), so that it takes deployments again. The
+/// deployments moved off it stay where they are. Responds with 409 while the drain is in progress.
+pub async fn undrain_device(config: web::Data<Config>, path: web::Path<String>) -> Result<impl Responder, ApiError> {
+    let device_key = path.into_inner();
+    let collection = get_collection::<DeviceDoc>(&config.database, COLL_DEVICE).await;
+    let device = collection
+        .find_one(device_filter(&device_key))
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("no device matches '{}'", device_key)))?;
+    if device.drain.as_ref().is_some_and(|d| d.state == DrainState::Draining) {
+        return Err(ApiError::conflict(format!("device '{}' is being drained", device.name)));
+    }
+
+    let mut filter = device_filter(&device_key);
+    filter.insert("drain.state", doc! { "$ne": "draining" });
+    collection
+        .update_one(filter, doc! { "$unset": { "drain": "" } })
+        .await
+        .map_err(ApiError::db)?;
+    revisions::bump(COLL_DEVICE);
+    info!("Device '{}' takes deployments again", device.name);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+
+/// Active deployments that have a part on the device
+pub async fn active_deployments_on(config: &Config, device_id: &ObjectId) -> Result<Vec<DeploymentDoc>, String> {
+    get_collection::<DeploymentDoc>(&config.database, COLL_DEPLOYMENT)
+        .await
+        .find(doc! { "active": true, format!("fullManifest.{}", device_id.to_hex()): { "$exists": true } })
+        .await
+        .map_err(|e| format!("deployment.find error: {e}"))?
+        .try_collect()
+        .await
+        .map_err(|e| format!("deployment cursor error: {e}"))
+}
+
+
+/// Solves the deployments again without the device and redeploys them. The device must
+/// already be left out of automatic selection (drained or deleted). Returns the moved
+/// deployments and the ones that couldnt be moved.
+pub async fn migrate_deployments(
+    config: &Config,
+    device_id: &ObjectId,
+    deployments: Vec<DeploymentDoc>,
+    strict: bool,
+) -> (Vec<MigratedDeployment>, Vec<FailedMigration>) {
+    let (orchestrator_host, _) = get_listening_address(&config.server);
+    let package_manager_base_url = config.server.package_manager_base_url(&orchestrator_host);
+    let supported_file_types = SUPPORTED_FILE_TYPES.to_vec();
+
+    let mut migrated = Vec::new();
+    let mut failed = Vec::new();
+    for deployment in deployments {
+        let Some(id) = deployment.id else { continue };
+        let failure = |error: String| FailedMigration { deployment_id: id.to_hex(), name: deployment.name.clone(), error };
+
+        // Steps on the drained device are left to automatic selection, which skips it
+        let sequence = Sequence {
+            id: Some(id.to_hex()),
+            name: deployment.name.clone(),
+            sequence: deployment
+                .sequence
+                .iter()
+                .map(|step| ApiSequenceStep {
+                    device: if step.device == *device_id { String::new() } else { step.device.to_hex() },
+                    module: step.module.to_hex(),
+                    func: step.func.clone(),
+                    method: step.method.clone(),
+                })
+                .collect(),
+            config: Some(deployment.config.clone()),
+            polling: deployment.polling.clone(),
+            execution_timeout_s: deployment.execution_timeout_s,
+        };
+        let (solution, revision) = match solve(config, &sequence, true, None, strict, &package_manager_base_url, &supported_file_types[..]).await {
+            Ok(SolveResult::Solution(solution, revision)) => (solution, revision),
+            Ok(SolveResult::Rejected(_)) => {
+                failed.push(failure("the new solution failed validation".to_string()));
+                continue;
+            }
+            Ok(SolveResult::DeploymentId(_) | SolveResult::Stale { .. }) => {
+                failed.push(failure("unexpected solver result (expected Solution)".to_string()));
+                continue;
+            }
+            Err(e) => {
+                failed.push(failure(e));
+                continue;
+            }
+        };
+
+        let devices: Vec<String> = solution.full_manifest.keys().cloned().collect();
+        let moved = DeploymentDoc {
+            id: Some(id),
+            name: deployment.name.clone(),
+            sequence: solution.sequence,
+            validation_error: None,
+            full_manifest: solution.full_manifest,
+            active: Some(true),
+            config: deployment.config.clone(),
+            polling: deployment.polling.clone(),
+            execution_timeout_s: deployment.execution_timeout_s,
+            last_executed_at: deployment.last_executed_at,
+            revision,
+        };
+        match deploy(&config, &moved).await {
+            Ok(_) => {
+                info!("Moved deployment '{}' to {:?}", deployment.name, devices);
+                migrated.push(MigratedDeployment { deployment_id: id.to_hex(), name: deployment.name.clone(), devices });
+            }
+            Err(e) => failed.push(failure(e.to_string())),
+        }
+    }
+    (migrated, failed)
+}
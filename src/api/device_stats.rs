@@ -0,0 +1,238 @@
+//! # device_stats.rs
+//!
+//! Resource usage statistics of a device for the dashboard, aggregated from the health history
+//! (see health_history.rs). The samples of a time window are downsampled into a fixed number of
+//! points, and the network deltas of the samples are turned into transfer rates using the time
+//! between the samples. The summary of the window has the averages and peaks of CPU and memory
+//! usage, their trend as the least squares slope over the points, and the overall transfer rates.
+
+use std::collections::HashMap;
+use actix_web::{web, Responder};
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use mongodb::bson::{self, doc, Document};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use crate::api::device::device_filter;
+use crate::api::health_history::parse_time;
+use crate::lib::config::Config;
+use crate::lib::constants::{COLL_DEVICE, COLL_HEALTH_HISTORY};
+use crate::lib::errors::ApiError;
+use crate::lib::mongodb::{find_one, get_collection};
+use crate::lib::response::ok_json;
+use crate::structs::device::DeviceDoc;
+
+
+/// Window returned when the query doesnt give one
+const DEFAULT_WINDOW_S: u64 = 3600;
+const MAX_WINDOW_S: u64 = 366 * 24 * 3600;
+
+/// Number of points the window is downsampled into unless the query asks for another amount
+const DEFAULT_POINTS: u64 = 60;
+const MAX_POINTS: u64 = 1000;
+
+
+/// A step of the window as aggregated by the database
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Bucket {
+    #[serde(rename = "_id")]
+    time: bson::DateTime,
+    samples: f64,
+    cpu_usage: Option<f64>,
+    cpu_max: Option<f64>,
+    memory_usage: Option<f64>,
+    memory_max: Option<f64>,
+    latency_ms: Option<f64>,
+    network_down_bytes: f64,
+    network_up_bytes: f64,
+    interval_s: f64, // Seconds the network deltas of the step cover
+}
+
+/// A point of the downsampled series
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StatsPoint {
+    time: DateTime<Utc>,
+    samples: u64,
+    cpu_usage: Option<f64>,
+    cpu_max: Option<f64>,
+    memory_usage: Option<f64>,
+    memory_max: Option<f64>,
+    latency_ms: Option<f64>,
+    down_bytes_per_s: Option<f64>,
+    up_bytes_per_s: Option<f64>,
+}
+
+
+/// GET /file/device/{device_id}/stats
+///
+/// Returns the resource usage statistics of a device (by id or name). Supports query parameters:
+/// - `window`: length of the window, such as `90s`, `15m`, `1h` or `7d` (plain numbers are
+///   seconds), by default 1h
+/// - `to`: RFC3339 end of the window, by default now
+/// - `points`: number of points in the series, by default 60
+pub async fn get_device_stats(config: web::Data<Config>, path: web::Path<String>, query: web::Query<HashMap<String, String>>) -> Result<impl Responder, ApiError> {
+    let device_key = path.into_inner();
+    let device = find_one::<DeviceDoc>(&config.database, COLL_DEVICE, device_filter(&device_key))
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("no device matches '{}'", device_key)))?;
+    let device_id = device.id.ok_or_else(|| ApiError::internal_error("device has no id"))?;
+
+    let window_s = match query.get("window") {
+        Some(window) => parse_window(window)
+            .filter(|w| *w <= MAX_WINDOW_S)
+            .ok_or_else(|| ApiError::bad_request(format!("invalid window '{}', expected a duration such as 15m, 1h or 7d of at most 366d", window)))?,
+        None => DEFAULT_WINDOW_S,
+    };
+    let points = query
+        .get("points")
+        .map(|p| {
+            p.parse::<u64>()
+                .ok()
+                .filter(|p| (1..=MAX_POINTS).contains(p))
+                .ok_or_else(|| ApiError::bad_request(format!("points must be between 1 and {}", MAX_POINTS)))
+        })
+        .transpose()?
+        .unwrap_or(DEFAULT_POINTS);
+    let to = parse_time(&query, "to")?.unwrap_or_else(Utc::now);
+    let from = to - chrono::Duration::seconds(window_s as i64);
+    let step_s = window_s.div_ceil(points).max(1);
+
+    let step_ms = (step_s * 1000) as i64;
+    let bucket = doc! { "$toDate": { "$subtract": [{ "$toLong": "$time" }, { "$mod": [{ "$toLong": "$time" }, step_ms] }] } };
+    let pipeline = vec![
+        doc! { "$match": {
+            "deviceId": device_id,
+            "time": {
+                "$gte": bson::DateTime::from_chrono(from),
+                "$lte": bson::DateTime::from_chrono(to),
+            },
+        } },
+        doc! { "$group": {
+            "_id": bucket,
+            "samples": { "$sum": 1 },
+            "cpuUsage": { "$avg": "$cpuUsage" },
+            "cpuMax": { "$max": "$cpuUsage" },
+            "memoryUsage": { "$avg": "$memoryUsage" },
+            "memoryMax": { "$max": "$memoryUsage" },
+            "latencyMs": { "$avg": "$latencyMs" },
+            // Only samples with network deltas have an interval, so the rates are over the time the deltas cover
+            "networkDownBytes": { "$sum": "$networkDownBytes" },
+            "networkUpBytes": { "$sum": "$networkUpBytes" },
+            "intervalS": { "$sum": "$intervalS" },
+        } },
+        doc! { "$sort": { "_id": 1 } },
+    ];
+    let coll = get_collection::<Document>(&config.database, COLL_HEALTH_HISTORY).await;
+    let buckets: Vec<Document> = coll
+        .aggregate(pipeline)
+        .await
+        .map_err(ApiError::db)?
+        .try_collect()
+        .await
+        .map_err(ApiError::db)?;
+    let buckets: Vec<Bucket> = buckets
+        .into_iter()
+        .map(bson::from_document)
+        .collect::<Result<_, _>>()
+        .map_err(|e| ApiError::internal_error(format!("invalid health history aggregate: {}", e)))?;
+
+    let rate = |bytes: f64, interval_s: f64| (interval_s > 0.0).then(|| bytes / interval_s);
+    let series: Vec<StatsPoint> = buckets
+        .iter()
+        .map(|b| StatsPoint {
+            time: b.time.to_chrono(),
+            samples: b.samples as u64,
+            cpu_usage: b.cpu_usage,
+            cpu_max: b.cpu_max,
+            memory_usage: b.memory_usage,
+            memory_max: b.memory_max,
+            latency_ms: b.latency_ms,
+            down_bytes_per_s: rate(b.network_down_bytes, b.interval_s),
+            up_bytes_per_s: rate(b.network_up_bytes, b.interval_s),
+        })
+        .collect();
+
+    let samples: f64 = buckets.iter().map(|b| b.samples).sum();
+    let interval_s: f64 = buckets.iter().map(|b| b.interval_s).sum();
+    let down_bytes: f64 = buckets.iter().map(|b| b.network_down_bytes).sum();
+    let up_bytes: f64 = buckets.iter().map(|b| b.network_up_bytes).sum();
+    let usage_summary = |average: fn(&Bucket) -> Option<f64>, max: fn(&Bucket) -> Option<f64>| {
+        json!({
+            "average": weighted_average(&buckets, average),
+            "max": buckets.iter().filter_map(max).reduce(f64::max),
+            "trendPerHour": trend_per_hour(&buckets, average),
+        })
+    };
+
+    ok_json(&json!({
+        "device": device.name,
+        "deviceId": device_id.to_hex(),
+        "from": from,
+        "to": to,
+        "window": window_s,
+        "step": step_s,
+        "summary": {
+            "samples": samples as u64,
+            "cpuUsage": usage_summary(|b| b.cpu_usage, |b| b.cpu_max),
+            "memoryUsage": usage_summary(|b| b.memory_usage, |b| b.memory_max),
+            "latencyMs": weighted_average(&buckets, |b| b.latency_ms),
+            "network": {
+                "downBytes": down_bytes as u64,
+                "upBytes": up_bytes as u64,
+                "downBytesPerS": rate(down_bytes, interval_s),
+                "upBytesPerS": rate(up_bytes, interval_s),
+            },
+        },
+        "series": series,
+    }))
+}
+
+
+/// Parses a window like `90s`, `15m`, `1h` or `7d` into seconds. A plain number is seconds.
+fn parse_window(window: &str) -> Option<u64> {
+    let window = window.trim();
+    let (amount, unit) = match window.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => window.split_at(i),
+        None => (window, "s"),
+    };
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    amount.parse::<u64>().ok().filter(|a| *a > 0)?.checked_mul(multiplier)
+}
+
+
+/// Average of a value of the steps, weighted by the number of samples in each
+fn weighted_average(buckets: &[Bucket], value: fn(&Bucket) -> Option<f64>) -> Option<f64> {
+    let (sum, count) = buckets
+        .iter()
+        .filter_map(|b| value(b).map(|v| (v * b.samples, b.samples)))
+        .fold((0.0, 0.0), |(sum, count), (v, n)| (sum + v, count + n));
+    (count > 0.0).then(|| sum / count)
+}
+
+
+/// Least squares slope of a value of the steps, in units per hour. None with fewer than two steps.
+fn trend_per_hour(buckets: &[Bucket], value: fn(&Bucket) -> Option<f64>) -> Option<f64> {
+    let points: Vec<(f64, f64)> = buckets
+        .iter()
+        .filter_map(|b| value(b).map(|v| (b.time.timestamp_millis() as f64 / 3_600_000.0, v)))
+        .collect();
+    if points.len() < 2 {
+        return None;
+    }
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let (covariance, variance) = points.iter().fold((0.0, 0.0), |(c, v), (x, y)| {
+        (c + (x - mean_x) * (y - mean_y), v + (x - mean_x) * (x - mean_x))
+    });
+    (variance > 0.0).then(|| covariance / variance)
+}
@@ -0,0 +1,66 @@
+//! # events.rs
+//!
+//! Server-sent event stream of the internal event bus (see lib/events.rs).
+
+use std::collections::{HashMap, HashSet};
+use actix_web::{web, HttpResponse, Responder};
+use actix_web::http::header::CACHE_CONTROL;
+use log::warn;
+use tokio::sync::broadcast::error::RecvError;
+use crate::lib::errors::ApiError;
+use crate::lib::events::{self, EVENT_NAMES};
+use crate::lib::shutdown;
+use crate::lib::connections;
+
+
+/// GET /events
+///
+/// Streams the events published from now on as server-sent events, named by the event type and
+/// with the event as JSON in the data. `?types=` takes a comma-separated list of event types to
+/// limit the stream to. The stream ends when the orchestrator shuts down.
+pub async fn get_event_stream(query: web::Query<HashMap<String, String>>) -> Result<impl Responder, ApiError> {
+    let types: Option<HashSet<String>> = match query.get("types") {
+        Some(types) => {
+            let types: HashSet<String> = types.split(',').map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect();
+            if let Some(unknown) = types.iter().find(|t| !EVENT_NAMES.contains(&t.as_str())) {
+                return Err(ApiError::bad_request(format!("unknown event type '{}', expected one of {}", unknown, EVENT_NAMES.join(", "))));
+            }
+            Some(types)
+        }
+        None => None,
+    };
+
+    // The guard is kept in the stream state, so the client is counted until the stream is dropped
+    let state = (events::subscribe(), types, connections::event_stream_connected());
+    let stream = futures::stream::unfold(state, |(mut rx, types, connection)| async move {
+        loop {
+            let received = tokio::select! {
+                received = rx.recv() => received,
+                _ = shutdown::requested() => return None,
+            };
+            match received {
+                Ok(event) => {
+                    if types.as_ref().is_some_and(|t| !t.contains(event.name())) {
+                        continue;
+                    }
+                    let data = match serde_json::to_string(&event) {
+                        Ok(data) => data,
+                        Err(e) => {
+                            warn!("Failed to serialize event '{}': {}", event.name(), e);
+                            continue;
+                        }
+                    };
+                    let message = format!("event: {}\ndata: {}\n\n", event.name(), data);
+                    return Some((Ok::<_, actix_web::Error>(web::Bytes::from(message)), (rx, types, connection)));
+                }
+                Err(RecvError::Lagged(missed)) => warn!("Event stream client fell behind and missed {} events", missed),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .streaming(stream))
+}
@@ -1,25 +1,64 @@
 use std::collections::HashMap;
 use mongodb::bson::oid::ObjectId;
-use mongodb::bson::doc;
+use mongodb::bson::{self, doc, Bson};
 use serde_json;
 use futures::TryStreamExt;
-use crate::lib::mongodb::get_collection;
+use crate::lib::mongodb::{find_one, get_collection, update_field};
+use crate::lib::pagination::{find_page, page_response, Pagination};
+use crate::api::logs::LOG_SORT_FIELDS;
 use reqwest::{self, Url, Method};
 use reqwest::multipart::{Form, Part};
 use tokio::fs;
 use serde_json::Value;
 use serde_json::json;
 use actix_web::{web, HttpResponse, Responder};
-use actix_web::{HttpRequest};
+use actix_web::{HttpMessage, HttpRequest};
 use actix_web::http::header::CONTENT_TYPE;
+use actix_web::http::StatusCode;
 use actix_multipart::Multipart;
 use futures_util::{StreamExt as FutTryStreamExt};
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
 use tokio::io::AsyncWriteExt as _;
-use crate::structs::deployment::{DeploymentDoc, OperationRequest};
+use crate::structs::deployment::{DeploymentDoc, OperationRequest, PollingConfig};
 use crate::structs::openapi::OpenApiParameterIn;
 use crate::lib::errors::ApiError;
-use crate::lib::constants::COLL_DEPLOYMENT;
+use crate::lib::execution_queue::{self, ExecutionInfo, ExecutionSlot, ExecutionStep, QueueChangeError, StepError, StepRef};
+use crate::lib::events::{self, Event};
+use crate::lib::telemetry::send_traced;
+use crate::lib::request_id;
+use crate::lib::http_client::{self, Operation};
+use crate::lib::watchdog;
+use crate::lib::shutdown;
+use crate::api::execution_inputs::{load_execution_inputs, purge_execution_inputs, store_execution_inputs};
+use crate::api::execution_outputs::{archive_execution_outputs, sweep_execution_outputs};
+use crate::api::execution_results::{
+    purge_execution_results,
+    store_execution_result,
+    RESULT_CANCELLED,
+    RESULT_FAILED,
+    RESULT_SUCCEEDED,
+    RESULT_TIMED_OUT
+};
+use crate::structs::execution_results::ExecutionResultDoc;
+use crate::lib::response::json_response;
+use crate::lib::parameter_validation::{json_arguments, validate_execution_input};
+use crate::lib::result_callbacks::{self, CallbackError, ResultCallback, RESULT_CALLBACK_HEADER};
+use crate::lib::api_auth::{hash_key, AuthenticatedActor};
+use crate::lib::config::{Config, ExecutionConfig, HttpClientConfig};
+use crate::lib::utils::base_url;
+use crate::lib::zeroconf;
+use crate::api::deployment_certificates::latest_deployment_certificate;
+use crate::structs::api_keys::ApiKeyScope;
+use crate::structs::deployment_certificates::ValidationLog;
+use crate::lib::constants::{
+    COLL_DEPLOYMENT,
+    COLL_LOGS,
+    EXECUTION_INPUT_TMP_DIR
+};
+use log::{debug, info, warn, error};
 
 #[derive(Debug, Clone)]
 pub struct ScheduleFile {
@@ -28,11 +67,11 @@ pub struct ScheduleFile {
 }
 
 
-// TODO: These uploaded files should be also deleted at some point.
 // TODO: Current UI doesnt really allow testing this part
 /// Helper function that takes an uploaded file and saves it to disk
 /// Meant to be used for execution mounts that are directly uploaded through 
-/// execution UI
+/// execution UI. The files are removed once the execution completes, see
+/// remove_execution_inputs and run_execution_input_sweeper_loop.
 async fn save_upload_part(
     field: &mut actix_multipart::Field,
     dir: &std::path::Path,
@@ -67,7 +106,7 @@ async fn parse_multipart(
 ) -> Result<(HashMap<String, String>, Vec<ScheduleFile>), ApiError> {
     let mut fields: HashMap<String, String> = HashMap::new();
     let mut files: Vec<ScheduleFile> = Vec::new();
-    let base_dir = std::env::temp_dir().join("exec_inputs");
+    let base_dir = EXECUTION_INPUT_TMP_DIR.clone();
 
     while let Some(mut field) = mp.try_next().await.map_err(|e| {
         ApiError::bad_request(format!("multipart error: {e}"))
@@ -99,9 +138,11 @@ async fn parse_multipart(
 }
 
 
-/// Helper function to parse requests to execution endpoint that are not multipart requests
+/// Helper function to parse requests to execution endpoint that are not multipart requests.
+/// JSON bodies are mapped onto the parameters of the operation, see `json_arguments`.
 async fn parse_non_multipart_body(
     mut payload: web::Payload,
+    request: &OperationRequest,
 ) -> Result<HashMap<String, String>, ApiError> {
     let mut bytes = web::BytesMut::new();
     while let Some(chunk) = payload.next().await {
@@ -115,19 +156,7 @@ async fn parse_non_multipart_body(
 
     let v: Result<Value, _> = serde_json::from_slice(&bytes);
     match v {
-        Ok(Value::Object(map)) => {
-            let mut out = HashMap::new();
-            for (k, v) in map {
-                let s = match v {
-                    Value::Null => String::new(),
-                    Value::String(s) => s,
-                    other => other.to_string(),
-                };
-                out.insert(k, s);
-            }
-            Ok(out)
-        }
-        Ok(other) => Ok(HashMap::from([("body".into(), other.to_string())])),
+        Ok(value) => Ok(json_arguments(request, value)),
         Err(_) => Ok(HashMap::from([(
             "body".into(),
             String::from_utf8_lossy(&bytes).to_string(),
@@ -136,17 +165,93 @@ async fn parse_non_multipart_body(
 }
 
 
+/// Checks the latest deployment certificate before an execution is started. Returns a 403 response
+/// (with the failed validation steps) if the certificate is missing or invalid, or None if the execution
+/// may proceed. The check can be skipped by passing `override=true` as a query parameter, when the
+/// request was authenticated with the admin scope (see lib/api_auth.rs) or has the override token of
+/// the execution settings in the `X-Policy-Override-Token` header.
+async fn execution_policy_gate(
+    config: &Config,
+    deployment: &DeploymentDoc,
+    req: &HttpRequest,
+) -> Result<Option<HttpResponse>, ApiError> {
+    if !config.execution.policy_gate {
+        return Ok(None);
+    }
+    let deployment_id = deployment
+        .id
+        .ok_or_else(|| ApiError::db("deployment missing _id"))?;
+
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string())
+        .map_err(|e| ApiError::bad_request(format!("invalid query string: {e}")))?;
+    if query.get("override").map(|v| v == "true").unwrap_or(false) {
+        let is_admin = req
+            .extensions()
+            .get::<AuthenticatedActor>()
+            .is_some_and(|actor| actor.1 == ApiKeyScope::Admin);
+        let token = req
+            .headers()
+            .get("X-Policy-Override-Token")
+            .and_then(|v| v.to_str().ok());
+        // Compared by the hashes, like the admin key, so that the time taken doesnt tell how much
+        // of the token was guessed right
+        let token_matches = match (config.execution.policy_override_token.as_deref(), token) {
+            (Some(expected), Some(given)) => hash_key(expected) == hash_key(given),
+            _ => false,
+        };
+        if !is_admin && !token_matches {
+            return json_response(StatusCode::FORBIDDEN, &json!({
+                "error": "not authorized to override the execution policy check"
+            })).map(Some);
+        }
+        warn!("⚠️ Execution policy check overridden for deployment '{}'", deployment.name);
+        return Ok(None);
+    }
+
+    let cert = latest_deployment_certificate(config, &deployment_id)
+        .await
+        .map_err(ApiError::db)?;
+    match cert {
+        Some(cert) if cert.valid => Ok(None),
+        Some(cert) => {
+            let failed_steps: Vec<&ValidationLog> = cert.validation_logs.iter().filter(|l| !l.valid).collect();
+            info!("Refused execution of deployment '{}', its latest certificate is invalid", deployment.name);
+            json_response(StatusCode::FORBIDDEN, &json!({
+                "error": format!("deployment '{}' has not passed validation", deployment.name),
+                "certificateId": cert.id,
+                "failedSteps": failed_steps,
+            })).map(Some)
+        }
+        None => {
+            info!("Refused execution of deployment '{}', it has no certificate", deployment.name);
+            json_response(StatusCode::FORBIDDEN, &json!({
+                "error": format!("deployment '{}' has no deployment certificate", deployment.name),
+            })).map(Some)
+        }
+    }
+}
+
+
 /// POST /execute/{deployment_id}
 /// 
 /// Endpoint to handle executing a deployment. Assumes that a deployment has already been deployed to 
-/// the target devices.
+/// the target devices. Refuses to execute deployments that have not passed validation, see
+/// `execution_policy_gate`, and requests whose fields or files dont match the operation of the
+/// first step (see lib/parameter_validation.rs), listing each problem under `violations`.
+/// With `?trace=true` the result is returned under `result`, along
+/// with the requests made while polling for it under `pollingTrace`. The execution waits in the
+/// execution queue for its turn, and is refused with 503 if the queue is full. With `?async=true`
+/// the execution is queued in the background and the response (202) has its id and place in the
+/// queue, to be followed from `GET /execute/queue/{execution_id}`. The result of every execution
+/// is stored by its id, see `GET /execute/results/{job_id}`.
 pub async fn execute(
+    config: web::Data<Config>,
     path: web::Path<String>,
     req: HttpRequest,
     payload: web::Payload,
 ) -> Result<impl Responder, ApiError> {
     let deployment_param = path.into_inner();
-    let coll = get_collection::<DeploymentDoc>(COLL_DEPLOYMENT).await;
+    let coll = get_collection::<DeploymentDoc>(&config.database, COLL_DEPLOYMENT).await;
 
     let filter = match ObjectId::parse_str(&deployment_param) {
         Ok(oid) => doc! { "_id": oid },
@@ -161,6 +266,10 @@ pub async fn execute(
         return Ok(HttpResponse::NotFound().finish());
     };
 
+    if let Some(refused) = execution_policy_gate(&config, &deployment, &req).await? {
+        return Ok(refused);
+    }
+
     let (.., _, _, start_req) =
         crate::api::execution::get_start_endpoint(&deployment)
             .map_err(|e| ApiError::db(e))?;
@@ -198,10 +307,639 @@ pub async fn execute(
                 }
             }
         } else {
-            (parse_non_multipart_body(payload).await?, Vec::new())
+            (parse_non_multipart_body(payload, &start_req).await?, Vec::new())
         };
 
-    let exec_response = schedule(&deployment, &fields, &files)
+    start_execution(&config, &req, deployment, &start_req, fields, files, None).await
+}
+
+
+/// Validates the fields and files of an execution against the operation of the first step of
+/// the deployment and queues it, running it right away or in the background with `?async=true`.
+/// The inputs are stored by the job id of the execution, see api/execution_inputs.rs. Input
+/// files are removed once the execution completes.
+async fn start_execution(
+    config: &Arc<Config>,
+    req: &HttpRequest,
+    deployment: DeploymentDoc,
+    start_req: &OperationRequest,
+    fields: HashMap<String, String>,
+    files: Vec<ScheduleFile>,
+    rerun_of: Option<u64>,
+) -> Result<HttpResponse, ApiError> {
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string())
+        .map(|q| q.into_inner())
+        .unwrap_or_default();
+    let include_trace = query.get("trace").map(|v| v == "true").unwrap_or(false);
+    let priority = match query.get("priority").map(|p| p.parse::<i32>()) {
+        Some(Ok(p)) => p,
+        Some(Err(_)) => {
+            remove_execution_inputs(&files).await;
+            return Err(ApiError::bad_request("priority must be an integer"));
+        }
+        None => 0,
+    };
+    let timeout_s = match query.get("timeout").map(|t| t.parse::<u64>()) {
+        Some(Ok(t)) => Some(t),
+        Some(Err(_)) => {
+            remove_execution_inputs(&files).await;
+            return Err(ApiError::bad_request("timeout must be a whole number of seconds"));
+        }
+        None => None,
+    };
+    let timeout = execution_timeout(&config.execution, &deployment, timeout_s);
+
+    let file_names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+    let violations = validate_execution_input(start_req, &fields, &file_names);
+    if !violations.is_empty() {
+        remove_execution_inputs(&files).await;
+        info!("Refused execution of deployment '{}', {} invalid parameters", deployment.name, violations.len());
+        return json_response(StatusCode::BAD_REQUEST, &json!({
+            "error": format!("invalid parameters for deployment '{}'", deployment.name),
+            "violations": violations,
+        }));
+    }
+
+    // Queue the execution, see lib/execution_queue.rs
+    let mut devices: Vec<String> = deployment.sequence.iter().map(|s| s.device.to_hex()).collect();
+    devices.sort();
+    devices.dedup();
+    let run_async = query.get("async").map(|v| v == "true").unwrap_or(false);
+    let callback_base = result_callback_base(config);
+    let request_id = request_id::current();
+    let info = ExecutionInfo {
+        deployment_id: deployment.id.map(|id| id.to_hex()).unwrap_or_default(),
+        deployment_name: deployment.name.clone(),
+        devices,
+        priority,
+        request_id: request_id.clone(),
+        steps: execution_steps(&deployment),
+    };
+    let queued = match execution_queue::enqueue(&config.execution, info, !run_async) {
+        Ok(queued) => queued,
+        Err(full) => {
+            remove_execution_inputs(&files).await;
+            info!("Refused execution of deployment '{}', {} executions are already queued", deployment.name, full.pending);
+            let response = json_response(StatusCode::SERVICE_UNAVAILABLE, &json!({
+                "error": format!("the execution queue is full with {} pending executions, try again later", full.pending),
+            }))?;
+            return Ok(with_retry_after(response));
+        }
+    };
+    // Stored before the execution runs, so that it can be run again even if it never finishes
+    if let Err(e) = store_execution_inputs(config, queued.id(), &deployment, &fields, &files, rerun_of).await {
+        warn!("Failed to store the inputs of execution {}: {}", queued.id(), e);
+    }
+
+    // The result is left to the supervisors, the caller follows the execution from the queue
+    if run_async {
+        let (execution_id, position) = (queued.id(), queued.position());
+        let config = config.clone();
+        shutdown::spawn_tracked(request_id::scope(request_id, async move {
+            match queued.wait().await {
+                Ok(slot) => {
+                    if let Err(e) = run_execution(&config, slot, &deployment, &fields, &files, timeout, callback_base.as_deref()).await {
+                        warn!("Background execution {} of deployment '{}' failed: {}", execution_id, deployment.name, e.msg);
+                    }
+                }
+                Err(_) => info!("Background execution {} of deployment '{}' was dropped from the queue", execution_id, deployment.name),
+            }
+            remove_execution_inputs(&files).await;
+        }));
+        let mut accepted = json!({
+            "executionId": execution_id,
+            "state": if position.is_some() { "pending" } else { "running" },
+            "position": position,
+            "status": format!("/execute/queue/{}", execution_id),
+            "result": format!("/execute/results/{}", execution_id),
+        });
+        if let Some(rerun_of) = rerun_of {
+            accepted["rerunOf"] = json!(rerun_of);
+        }
+        return json_response(StatusCode::ACCEPTED, &accepted);
+    }
+
+    let Ok(slot) = queued.wait().await else {
+        remove_execution_inputs(&files).await;
+        return Err(ApiError::conflict(format!("execution of deployment '{}' was dropped from the queue", deployment.name)));
+    };
+    // Uploaded input files are not needed after the execution completes, regardless of the outcome
+    let result = run_execution(config, slot, &deployment, &fields, &files, timeout, callback_base.as_deref()).await;
+    remove_execution_inputs(&files).await;
+    result.map(|fetched| fetched.response(include_trace))
+}
+
+
+/// Base url supervisors can post execution results back to, None if `execution.resultCallbacks` is off
+pub fn result_callback_base(config: &Config) -> Option<String> {
+    config.execution.result_callbacks.then(|| base_url(&config.server.url_scheme, &zeroconf::public_host(&config.server), config.server.port))
+}
+
+
+/// Executes a deployment with a JSON body (see `json_arguments`) through the execution queue, like
+/// `POST /execute/{deployment_id}` does without files. For running deployments from other
+/// endpoints. Returns the execution id, and the status and body of the result.
+pub async fn execute_json(
+    config: &Config,
+    deployment: &DeploymentDoc,
+    body: Option<Value>,
+    timeout: Option<Duration>,
+    callback_base: Option<&str>,
+) -> Result<(u64, StatusCode, Value), ApiError> {
+    let (.., start_req) = get_start_endpoint(deployment).map_err(ApiError::db)?;
+    let fields = body.map(|body| json_arguments(&start_req, body)).unwrap_or_default();
+    let violations = validate_execution_input(&start_req, &fields, &[]);
+    if !violations.is_empty() {
+        let listed = violations
+            .iter()
+            .map(|v| format!("{} ({}): {}", v.name, v.location, v.message))
+            .collect::<Vec<_>>()
+            .join("; ");
+        return Err(ApiError::bad_request(format!("invalid parameters for deployment '{}': {}", deployment.name, listed)));
+    }
+
+    let mut devices: Vec<String> = deployment.sequence.iter().map(|s| s.device.to_hex()).collect();
+    devices.sort();
+    devices.dedup();
+    let info = ExecutionInfo {
+        deployment_id: deployment.id.map(|id| id.to_hex()).unwrap_or_default(),
+        deployment_name: deployment.name.clone(),
+        devices,
+        priority: 0,
+        request_id: request_id::current(),
+        steps: execution_steps(deployment),
+    };
+    let queued = execution_queue::enqueue(&config.execution, info, true).map_err(|full| {
+        ApiError::service_unavailable(format!("the execution queue is full with {} pending executions, try again later", full.pending))
+    })?;
+    if let Err(e) = store_execution_inputs(config, queued.id(), deployment, &fields, &[], None).await {
+        warn!("Failed to store the inputs of execution {}: {}", queued.id(), e);
+    }
+    let slot = queued
+        .wait()
+        .await
+        .map_err(|_| ApiError::conflict(format!("execution of deployment '{}' was dropped from the queue", deployment.name)))?;
+    let execution_id = slot.id();
+    let fetched = run_execution(config, slot, deployment, &fields, &[], timeout, callback_base).await?;
+    Ok((execution_id, fetched.status, fetched.result))
+}
+
+
+/// Steps of the sequence of the deployment, with the modules by the names the supervisors know them by
+fn execution_steps(deployment: &DeploymentDoc) -> Vec<ExecutionStep> {
+    deployment
+        .sequence
+        .iter()
+        .map(|step| {
+            let device = step.device.to_hex();
+            let module = deployment
+                .full_manifest
+                .get(&device)
+                .and_then(|node| node.modules.iter().find(|m| m.id == step.module))
+                .map(|m| m.name.clone())
+                .unwrap_or_else(|| step.module.to_hex());
+            ExecutionStep { device, module, function: step.func.clone() }
+        })
+        .collect()
+}
+
+
+/// Seconds a caller refused because the execution queue is full is asked to wait
+const QUEUE_FULL_RETRY_AFTER_S: u64 = 5;
+
+fn with_retry_after(mut response: HttpResponse) -> HttpResponse {
+    if let Ok(value) = actix_web::http::header::HeaderValue::from_str(&QUEUE_FULL_RETRY_AFTER_S.to_string()) {
+        response.headers_mut().insert(actix_web::http::header::RETRY_AFTER, value);
+    }
+    response
+}
+
+
+/// Time the execution may take in total: the `timeout` of the request, or the
+/// `executionTimeoutS` of the deployment, or `execution.timeoutS`. None if there is no limit.
+fn execution_timeout(config: &ExecutionConfig, deployment: &DeploymentDoc, requested_s: Option<u64>) -> Option<Duration> {
+    let timeout_s = requested_s.or(deployment.execution_timeout_s).unwrap_or(config.timeout_s);
+    (timeout_s > 0).then(|| Duration::from_secs(timeout_s))
+}
+
+
+/// Why an execution was stopped before it finished
+enum Stopped {
+    TimedOut(Duration),
+    Cancelled,
+}
+
+
+/// Runs an execution that has got its slot in the queue, publishes its start and end, and stores
+/// its result by the execution id. An execution that takes longer than `timeout`, or is cancelled
+/// through the queue, stops polling for its result, and the supervisor is asked to cancel it.
+async fn run_execution(
+    config: &Config,
+    mut slot: ExecutionSlot,
+    deployment: &DeploymentDoc,
+    fields: &HashMap<String, String>,
+    files: &[ScheduleFile],
+    timeout: Option<Duration>,
+    callback_base: Option<&str>,
+) -> Result<FetchedResult, ApiError> {
+    let deployment_id = deployment.id.map(|id| id.to_hex()).unwrap_or_default();
+    let request_id = request_id::current();
+    if let Some(oid) = deployment.id {
+        let database = config.database.clone();
+        shutdown::spawn_tracked(async move {
+            let now = Bson::DateTime(bson::DateTime::now());
+            if let Err(e) = update_field::<DeploymentDoc>(&database, COLL_DEPLOYMENT, doc! { "_id": oid }, "lastExecutedAt", now).await {
+                warn!("Failed to store the execution time of deployment '{}': {}", oid, e);
+            }
+        });
+    }
+    events::publish(Event::ExecutionStarted {
+        execution_id: slot.id(),
+        deployment_id: deployment_id.clone(),
+        deployment_name: deployment.name.clone(),
+        request_id: request_id.clone(),
+    });
+
+    let started = Instant::now();
+    let started_at = chrono::Utc::now();
+    let result_url = parking_lot::Mutex::new(None);
+    let mut callback = callback_base.map(|base| result_callbacks::register(slot.id(), base));
+    let deadline = async {
+        match timeout {
+            Some(timeout) => tokio::time::sleep(timeout).await,
+            None => std::future::pending::<()>().await,
+        }
+    };
+    let outcome = tokio::select! {
+        result = execute_and_fetch_result(config, deployment, fields, files, callback.as_mut(), &result_url) => Ok(result),
+        _ = deadline => Err(Stopped::TimedOut(timeout.unwrap_or_default())),
+        _ = slot.cancelled() => Err(Stopped::Cancelled),
+    };
+    let (result, status) = match outcome {
+        Ok(result) => {
+            slot.finish(result.as_ref().map(|_| ()).map_err(|e| e.msg.clone()));
+            let status = match &result {
+                Ok(fetched) if fetched.status.is_success() => RESULT_SUCCEEDED,
+                _ => RESULT_FAILED,
+            };
+            (result, status)
+        }
+        Err(stopped) => {
+            let result_url = result_url.lock().take();
+            cancel_on_supervisor(&config.http_client, deployment, result_url).await;
+            match stopped {
+                Stopped::TimedOut(timeout) => {
+                    let message = format!("execution of deployment '{}' didnt finish within {} s", deployment.name, timeout.as_secs());
+                    warn!("⏱️ {}", message);
+                    slot.finish(Err(message.clone()));
+                    (Err(ApiError::gateway_timeout(message)), RESULT_TIMED_OUT)
+                }
+                // Left without an outcome, so that it is listed as cancelled
+                Stopped::Cancelled => {
+                    info!("Execution {} of deployment '{}' was cancelled", slot.id(), deployment.name);
+                    (Err(ApiError::conflict(format!("execution of deployment '{}' was cancelled", deployment.name))), RESULT_CANCELLED)
+                }
+            }
+        }
+    };
+    let duration_ms = started.elapsed().as_millis() as u64;
+
+    // Stored before responding, so that the result can be fetched as soon as the execution is finished
+    let stored = ExecutionResultDoc {
+        id: None,
+        execution_id: slot.id(),
+        deployment_id: deployment.id,
+        deployment_name: deployment.name.clone(),
+        request_id: request_id.clone(),
+        status: status.to_string(),
+        status_code: Some(result.as_ref().map(|f| f.status).unwrap_or_else(|e| e.status).as_u16()),
+        result: result.as_ref().ok().map(|f| f.result.clone()),
+        result_path: None,
+        result_bytes: 0,
+        error: result.as_ref().err().map(|e| e.msg.clone()),
+        started_at,
+        finished_at: chrono::Utc::now(),
+        duration_ms,
+    };
+    if let Err(e) = store_execution_result(config, stored).await {
+        warn!("Failed to store the result of execution {}: {}", slot.id(), e);
+    }
+
+    events::publish(Event::ExecutionFinished {
+        execution_id: slot.id(),
+        deployment_id,
+        deployment_name: deployment.name.clone(),
+        request_id,
+        duration_ms,
+        error: result.as_ref().err().map(|e| e.msg.clone()),
+        supervisor_error: result.as_ref().is_err_and(|e| e.status.is_server_error()),
+    });
+    result
+}
+
+
+/// GET /execute/{deployment_id}/logs
+///
+/// Returns the supervisor logs of a single execution of the deployment, merged across devices
+/// and sorted by timestamp. The execution is picked with `requestId` (the `X-Request-Id` of the
+/// execution response) or `execution` (its id in the queue), and defaults to the latest
+/// execution. Supports `limit`, `offset` and `sort` like `GET /device/logs`.
+pub async fn get_execution_logs(
+    config: web::Data<Config>,
+    path: web::Path<String>,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<impl Responder, ApiError> {
+    let deployment_param = path.into_inner();
+    let filter = match ObjectId::parse_str(&deployment_param) {
+        Ok(oid) => doc! { "_id": oid },
+        Err(_) => doc! { "name": &deployment_param },
+    };
+    let deployment = find_one::<DeploymentDoc>(&config.database, COLL_DEPLOYMENT, filter)
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("deployment '{}' not found", deployment_param)))?;
+    let deployment_id = deployment.id.map(|id| id.to_hex()).unwrap_or_default();
+
+    let request_id = match (query.get("requestId"), query.get("execution")) {
+        (Some(request_id), _) => request_id.clone(),
+        (None, execution) => {
+            let execution_id = execution
+                .map(|id| id.parse::<u64>().map_err(|_| ApiError::bad_request("execution must be a queue id")))
+                .transpose()?;
+            execution_queue::execution_request_id(&deployment_id, execution_id).ok_or_else(|| {
+                ApiError::not_found(format!(
+                    "no execution of deployment '{}' found since the orchestrator was started, give its requestId instead",
+                    deployment.name
+                ))
+            })?
+        }
+    };
+
+    let pagination = Pagination::from_query(&query, LOG_SORT_FIELDS, Some(doc! { "timestamp": 1 }))?;
+    let logs = get_collection::<mongodb::bson::Document>(&config.database, COLL_LOGS).await;
+    let page = find_page(&logs, doc! { "request_id": &request_id }, &pagination).await?;
+    page_response(&page)
+}
+
+
+/// GET /execute/queue
+///
+/// Lists the running and pending executions, pending ones in the order they will be started,
+/// along with counts per deployment and per device.
+pub async fn get_execution_queue(config: web::Data<Config>) -> Result<impl Responder, ApiError> {
+    Ok(HttpResponse::Ok().json(execution_queue::snapshot(&config.execution)))
+}
+
+
+/// GET /execute/queue/{execution_id}
+///
+/// Returns a running or pending execution with its place in the queue, or the outcome of a
+/// recently finished one. Used to follow executions started with `?async=true`. Running
+/// executions have the `progress` of their steps as reported to `POST /postResult`.
+pub async fn get_queued_execution(path: web::Path<u64>) -> Result<impl Responder, ApiError> {
+    let id = path.into_inner();
+    match execution_queue::execution_state(id) {
+        Some(execution) => Ok(HttpResponse::Ok().json(execution)),
+        None => Err(ApiError::not_found(format!("no queued or recently finished execution with id {}", id))),
+    }
+}
+
+
+/// Changes to a pending execution. `front` moves it ahead of all other pending executions.
+#[derive(Debug, Deserialize)]
+pub struct QueueUpdate {
+    pub priority: Option<i32>,
+    #[serde(default)]
+    pub front: bool,
+}
+
+
+/// PUT /execute/queue/{execution_id}
+///
+/// Reorders a pending execution by changing its priority, or moving it to the front.
+pub async fn update_queued_execution(path: web::Path<u64>, body: web::Json<QueueUpdate>) -> Result<impl Responder, ApiError> {
+    let id = path.into_inner();
+    if body.priority.is_none() && !body.front {
+        return Err(ApiError::bad_request("Nothing to update, expected priority or front"));
+    }
+    match execution_queue::reprioritize(id, body.priority, body.front) {
+        Ok(execution) => Ok(HttpResponse::Ok().json(execution)),
+        Err(e) => Err(queue_change_error(id, e)),
+    }
+}
+
+
+/// DELETE /execute/queue/{execution_id}
+///
+/// Drops a pending execution from the queue. The request waiting for it gets a 409 response.
+pub async fn delete_queued_execution(path: web::Path<u64>) -> Result<impl Responder, ApiError> {
+    let id = path.into_inner();
+    match execution_queue::drop_queued(id) {
+        Ok(()) => {
+            info!("Execution {} dropped from the queue", id);
+            Ok(HttpResponse::NoContent().finish())
+        }
+        Err(e) => Err(queue_change_error(id, e)),
+    }
+}
+
+
+/// DELETE /execute/jobs/{job_id}
+///
+/// Cancels an execution by its id in the queue. A pending execution is dropped from the queue,
+/// and a running one stops polling for its result and the supervisor is asked to cancel it.
+/// The request waiting for the execution gets a 409 response.
+pub async fn cancel_execution(path: web::Path<u64>) -> Result<impl Responder, ApiError> {
+    let id = path.into_inner();
+    match execution_queue::cancel(id) {
+        Ok(()) => {
+            info!("Execution {} cancelled", id);
+            Ok(HttpResponse::NoContent().finish())
+        }
+        Err(e) => Err(queue_change_error(id, e)),
+    }
+}
+
+
+/// POST /execute/jobs/{job_id}/rerun
+///
+/// Runs an execution again with the fields and files it was started with, against the current
+/// version of its deployment. Behaves like `POST /execute/{deployment_id}` otherwise, including
+/// the query parameters and the policy check, and the new execution gets its own job id. The
+/// asynchronous response has the job id of the replayed execution under `rerunOf`.
+pub async fn rerun_execution(
+    config: web::Data<Config>,
+    path: web::Path<u64>,
+    req: HttpRequest,
+) -> Result<impl Responder, ApiError> {
+    let id = path.into_inner();
+    let (inputs, files) = load_execution_inputs(&config, id).await?;
+
+    let filter = match inputs.deployment_id {
+        Some(oid) => doc! { "_id": oid },
+        None => doc! { "name": &inputs.deployment_name },
+    };
+    let deployment = match find_one::<DeploymentDoc>(&config.database, COLL_DEPLOYMENT, filter).await {
+        Ok(Some(deployment)) => deployment,
+        Ok(None) => {
+            remove_execution_inputs(&files).await;
+            return Err(ApiError::not_found(format!("deployment '{}' of execution {} no longer exists", inputs.deployment_name, id)));
+        }
+        Err(e) => {
+            remove_execution_inputs(&files).await;
+            return Err(ApiError::db(e));
+        }
+    };
+    let start_req = match execution_policy_gate(&config, &deployment, &req).await {
+        Ok(None) => get_start_endpoint(&deployment).map(|(.., start_req)| start_req).map_err(ApiError::db),
+        Ok(Some(refused)) => {
+            remove_execution_inputs(&files).await;
+            return Ok(refused);
+        }
+        Err(e) => Err(e),
+    };
+    let start_req = match start_req {
+        Ok(start_req) => start_req,
+        Err(e) => {
+            remove_execution_inputs(&files).await;
+            return Err(e);
+        }
+    };
+
+    info!("🔁 Rerunning execution {} of deployment '{}'", id, deployment.name);
+    start_execution(&config, &req, deployment, &start_req, inputs.fields, files, Some(id)).await
+}
+
+
+/// POST /postResult/{job_id}
+///
+/// Endpoint where supervisors post the final result of an execution to, with the token of the
+/// callback url they were given when the execution was scheduled. The body is like the response
+/// of a supervisor to the scheduling request, with the result under `result` or an `error`.
+pub async fn post_execution_result(
+    path: web::Path<u64>,
+    query: web::Query<HashMap<String, String>>,
+    body: web::Json<Value>,
+) -> Result<impl Responder, ApiError> {
+    let id = path.into_inner();
+    let token = query.get("token").map(String::as_str).unwrap_or("");
+    match result_callbacks::deliver(id, token, body.into_inner()) {
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+        Err(CallbackError::NotWaiting) => Err(ApiError::not_found(format!("execution {} isnt waiting for its result", id))),
+        Err(CallbackError::InvalidToken) => Err(ApiError::forbidden(format!("invalid callback token for execution {}", id))),
+    }
+}
+
+
+/// A step of a chained execution reported as finished by a supervisor
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StepReport {
+    pub deployment_id: String,
+    pub execution_id: Option<u64>,
+    pub request_id: Option<String>, // Request id forwarded with the execution, the X-Request-Id of the report by default
+    pub step: Option<usize>, // Index of the step in the sequence of the deployment
+    pub module: Option<String>,
+    pub function: Option<String>,
+    pub error: Option<String>,
+}
+
+
+/// POST /postResult
+///
+/// Endpoint where supervisors report that a step of a chained execution has finished, either by
+/// its index in the sequence (`step`) or by its `function` (and `module`). The execution is given
+/// by `executionId`, or found among the running executions of `deploymentId` by the request id
+/// forwarded with the execution. Without a matching request id the only running execution of the
+/// deployment is used. The progress is shown on `GET /execute/queue/{execution_id}` and published
+/// as an `execution.progress` event.
+pub async fn post_step_result(report: web::Json<StepReport>) -> Result<impl Responder, ApiError> {
+    let report = report.into_inner();
+    let execution_id = match report.execution_id {
+        Some(id) => id,
+        None => {
+            let request_id = report.request_id.clone().or_else(request_id::current);
+            let mut running = execution_queue::running_execution(&report.deployment_id, request_id.as_deref());
+            // The header is a new id when the supervisor didnt forward the one of the execution
+            if running.is_none() && report.request_id.is_none() {
+                running = execution_queue::running_execution(&report.deployment_id, None);
+            }
+            running.ok_or_else(|| {
+                ApiError::not_found(format!("no running execution of deployment '{}' matches the report", report.deployment_id))
+            })?
+        }
+    };
+    let step = match (report.step, report.function.as_deref()) {
+        (Some(index), _) => StepRef::Index(index),
+        (None, Some(function)) => StepRef::Function { module: report.module.as_deref(), function },
+        (None, None) => return Err(ApiError::bad_request("expected the step index or the function of the finished step")),
+    };
+
+    let recorded = execution_queue::record_step(execution_id, &report.deployment_id, step).map_err(|e| match e {
+        StepError::NotRunning => ApiError::not_found(format!("execution {} of deployment '{}' is not running", execution_id, report.deployment_id)),
+        StepError::UnknownStep => ApiError::bad_request(format!("no such unfinished step in execution {}", execution_id)),
+    })?;
+    match &report.error {
+        Some(error) => warn!("Step {} of execution {} failed on device '{}': {}", recorded.index, execution_id, recorded.step.device, error),
+        None => debug!("Step {} of execution {} finished on device '{}'", recorded.index, execution_id, recorded.step.device),
+    }
+    events::publish(Event::ExecutionProgress {
+        execution_id,
+        deployment_id: recorded.deployment_id,
+        deployment_name: recorded.deployment_name,
+        request_id: recorded.request_id,
+        step: recorded.index,
+        steps: recorded.progress.steps,
+        device: recorded.step.device,
+        module: recorded.step.module,
+        function: recorded.step.function,
+        error: report.error,
+    });
+    Ok(HttpResponse::Ok().json(recorded.progress))
+}
+
+
+fn queue_change_error(id: u64, e: QueueChangeError) -> ApiError {
+    match e {
+        QueueChangeError::NotFound => ApiError::not_found(format!("no queued execution with id {}", id)),
+        QueueChangeError::AlreadyRunning => ApiError::conflict(format!("execution {} is already running", id)),
+    }
+}
+
+
+/// Asks the supervisor to cancel an execution that was stopped, by deleting the result url it
+/// was last polled at. Supervisors that dont support cancellation answer 404, 405 or 501.
+async fn cancel_on_supervisor(settings: &HttpClientConfig, deployment: &DeploymentDoc, result_url: Option<Url>) {
+    let Some(url) = result_url else {
+        debug!("Execution of deployment '{}' was stopped before a result url was known, nothing to cancel", deployment.name);
+        return;
+    };
+    let request = request_id::propagate(execution_request(settings, Method::DELETE, url.clone()));
+    match send_traced(request, "execution cancel").await {
+        Ok(res) if res.status().is_success() => info!("Supervisor cancelled the execution at {}", url),
+        Ok(res) if matches!(res.status().as_u16(), 404 | 405 | 501) => {
+            debug!("Supervisor doesnt support cancelling the execution at {} ({})", url, res.status());
+        }
+        Ok(res) => warn!("Supervisor refused to cancel the execution at {}: {}", url, res.status()),
+        Err(e) => warn!("Failed to cancel the execution at {}: {}", url, e),
+    }
+}
+
+
+/// Helper function that schedules the execution on the first device of the deployment, and
+/// waits for the final result to be posted to the callback if the supervisor accepted it (see
+/// lib/result_callbacks.rs). Otherwise, or if the result isnt posted in time, follows the result
+/// urls returned by supervisors until the final result is available. The url being polled is
+/// kept in `result_url`, so that the execution can be cancelled on the supervisor.
+async fn execute_and_fetch_result(
+    config: &Config,
+    deployment: &DeploymentDoc,
+    fields: &HashMap<String, String>,
+    files: &[ScheduleFile],
+    callback: Option<&mut ResultCallback>,
+    result_url: &parking_lot::Mutex<Option<Url>>,
+) -> Result<FetchedResult, ApiError> {
+    let exec_response = schedule(&config.http_client, deployment, fields, files, callback.as_ref().map(|c| c.url()))
         .await
         .map_err(|e| ApiError::db(format!("scheduling work failed: {e}")))?;
 
@@ -213,15 +951,32 @@ pub async fn execute(
         return Err(ApiError::db(format!("scheduling work failed: {}", txt)));
     }
 
-    let client = reqwest::Client::new();
-    let mut resp = exec_response;
-    let mut tries = 0usize;
-    let mut depth = 0usize;
+    let callback_accepted = exec_response.headers().contains_key(RESULT_CALLBACK_HEADER);
+    let mut json_res: Result<Value, String> = exec_response.json().await.map_err(|e| e.to_string());
     let mut status_code = 500;
     let mut _result: Value = json!({ "error": "undefined error" });
 
+    // The scheduling response may already have the final result, then there is nothing to wait for
+    let awaits_callback = callback_accepted && json_res.as_ref().is_ok_and(|json| !is_final_response(json));
+    if let (true, Some(callback)) = (awaits_callback, callback) {
+        // Known while waiting, so that the execution can still be cancelled on the supervisor
+        *result_url.lock() = json_res
+            .as_ref()
+            .ok()
+            .and_then(|json| json.get("result").or_else(|| json.get("resultUrl")))
+            .and_then(Value::as_str)
+            .and_then(|s| Url::parse(s).ok());
+        let wait_s = config.execution.result_callback_wait_s;
+        debug!("Waiting for the result of deployment '{}' to be posted to {}", deployment.name, callback.url());
+        match callback.wait((wait_s > 0).then(|| Duration::from_secs(wait_s))).await {
+            Some(posted) => json_res = Ok(posted),
+            None => warn!("Result of deployment '{}' wasnt posted within {} s, polling for it instead", deployment.name, wait_s),
+        }
+    }
+
+    let mut poller = ResultPoller::new(deployment.polling.clone().unwrap_or_default(), &config.execution);
+
     loop {
-        let json_res: Result<Value, _> = resp.json().await;
         let json = match json_res {
             Ok(v) => v,
             Err(e) => {
@@ -230,95 +985,269 @@ pub async fn execute(
             }
         };
 
+        // Either the final result, or an url where it will be available
+        let mut next_url = None;
         if let Some(res_val) = json.get("result") {
             if json.get("status").and_then(Value::as_str) != Some("error") {
-                if let Some(res_str) = res_val.as_str() {
-                    if let Ok(url) = Url::parse(res_str) {
-                        depth += 1;
-                        let next = client.get(url).send().await.map_err(|e| {
-                            ApiError::db(format!("fetching result failed: {e}"))
-                        })?;
-                        if !next.status().is_success() {
-                            if next.status().as_u16() == 404 && depth < 5 && tries < 5 {
-                                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
-                                tries += 1;
-                                resp = client
-                                    .get(next.url().clone())
-                                    .send()
-                                    .await
-                                    .map_err(|e| ApiError::db(format!("retry failed: {e}")))?;
-                                continue;
-                            } else {
-                                _result = json!({ "error": format!("fetching result failed: {}", next.status()) });
-                                break;
-                            }
-                        }
-                        resp = next;
-                        continue;
+                match res_val.as_str().and_then(|s| Url::parse(s).ok()) {
+                    Some(url) => next_url = Some(url),
+                    None => {
+                        _result = res_val.clone();
+                        status_code = 200;
+                        break;
                     }
                 }
-                _result = res_val.clone();
-                status_code = 200;
+            }
+        }
+        if next_url.is_none() {
+            if let Some(err) = json.get("error") {
+                _result = json!({ "error": err });
                 break;
             }
+            next_url = json.get("resultUrl").and_then(Value::as_str).and_then(|s| Url::parse(s).ok());
         }
-
-        if let Some(err) = json.get("error") {
-            _result = json!({ "error": err });
+        let Some(url) = next_url else {
+            _result = json!({ "error": "unexpected execution response shape" });
             break;
+        };
+        *result_url.lock() = Some(url.clone());
+
+        match poller.follow(&config.http_client, url).await {
+            Ok(next) => json_res = next.json().await.map_err(|e| e.to_string()),
+            Err(e) => {
+                _result = json!({ "error": e });
+                break;
+            }
         }
+    }
 
-        if let Some(url_val) = json.get("resultUrl").and_then(Value::as_str) {
-            if let Ok(url) = Url::parse(url_val) {
-                depth += 1;
-                let next = client.get(url).send().await.map_err(|e| {
-                    ApiError::db(format!("fetching result failed: {e}"))
-                })?;
-                if !next.status().is_success() {
-                    if next.status().as_u16() == 404 && depth < 5 && tries < 5 {
-                        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
-                        tries += 1;
-                        resp = client
-                            .get(next.url().clone())
-                            .send()
-                            .await
-                            .map_err(|e| ApiError::db(format!("retry failed: {e}")))?;
-                        continue;
-                    } else {
-                        _result =
-                            json!({ "error": format!("fetching result failed: {}", next.status()) });
-                        break;
-                    }
+    debug!("Result polling of deployment '{}': {:?}", deployment.name, poller.trace);
+    Ok(FetchedResult {
+        status: StatusCode::from_u16(status_code).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+        result: _result,
+        trace: poller.trace,
+    })
+}
+
+
+/// Whether a response of a supervisor has the final result or an error, instead of an url
+/// where the result will be available
+fn is_final_response(json: &Value) -> bool {
+    match json.get("result") {
+        Some(result) if json.get("status").and_then(Value::as_str) != Some("error") => {
+            result.as_str().and_then(|s| Url::parse(s).ok()).is_none()
+        }
+        _ => json.get("error").is_some(),
+    }
+}
+
+
+/// Final result of an execution, and the requests made while polling for it
+struct FetchedResult {
+    status: StatusCode,
+    result: Value,
+    trace: Vec<PollAttempt>,
+}
+
+impl FetchedResult {
+    /// The response to the caller, with the polling trace if it was asked for
+    fn response(self, include_trace: bool) -> HttpResponse {
+        let body = if include_trace {
+            json!({ "result": self.result, "pollingTrace": self.trace })
+        } else {
+            self.result
+        };
+        HttpResponse::build(self.status).json(body)
+    }
+}
+
+
+/// A single request made while polling for an execution result
+#[derive(Debug, Clone, Serialize)]
+struct PollAttempt {
+    url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(rename = "elapsedMs")]
+    elapsed_ms: u64, // Time since polling started
+}
+
+
+/// Follows result urls according to the polling configuration of a deployment, or the polling
+/// settings of the executions where the deployment has none, and keeps a trace of the requests
+/// it makes.
+struct ResultPoller {
+    max_retries: u32,
+    interval: Duration,
+    backoff: f64,
+    deadline: Option<Duration>,
+    max_depth: u32,
+    started: Instant,
+    tries: u32,
+    depth: u32,
+    trace: Vec<PollAttempt>,
+}
+
+impl ResultPoller {
+    fn new(polling: PollingConfig, defaults: &ExecutionConfig) -> Self {
+        let deadline_s = polling.deadline_s.unwrap_or(defaults.poll_deadline_s);
+        ResultPoller {
+            max_retries: polling.max_retries.unwrap_or(defaults.poll_max_retries),
+            interval: Duration::from_millis(polling.interval_ms.unwrap_or(defaults.poll_interval_ms)),
+            backoff: polling.backoff.unwrap_or(defaults.poll_backoff).max(1.0),
+            deadline: (deadline_s > 0).then(|| Duration::from_secs(deadline_s)),
+            max_depth: polling.max_depth.unwrap_or(defaults.poll_max_depth),
+            started: Instant::now(),
+            tries: 0,
+            depth: 0,
+            trace: Vec::new(),
+        }
+    }
+
+    /// Fetches the given result url, retrying while the result is not yet available (404).
+    /// Returns the successful response, or a description of why polling was given up.
+    async fn follow(&mut self, settings: &HttpClientConfig, url: Url) -> Result<reqwest::Response, String> {
+        self.depth += 1;
+        if self.depth > self.max_depth {
+            return Err(format!("fetching result failed: more than {} result urls to follow", self.max_depth));
+        }
+        let mut interval = self.interval;
+        loop {
+            let res = send_traced(request_id::propagate(execution_request(settings, Method::GET, url.clone())), "execution result").await;
+            let status = res.as_ref().ok().map(|r| r.status());
+            self.trace.push(PollAttempt {
+                url: url.to_string(),
+                status: status.map(|s| s.as_u16()),
+                error: res.as_ref().err().map(|e| e.to_string()),
+                elapsed_ms: self.started.elapsed().as_millis() as u64,
+            });
+            let resp = res.map_err(|e| format!("fetching result failed: {e}"))?;
+            if resp.status().is_success() {
+                return Ok(resp);
+            }
+            if resp.status() != reqwest::StatusCode::NOT_FOUND || self.tries >= self.max_retries {
+                return Err(format!("fetching result failed: {}", resp.status()));
+            }
+            if let Some(deadline) = self.deadline {
+                if self.started.elapsed() + interval > deadline {
+                    return Err(format!("fetching result failed: not available within {} s", deadline.as_secs()));
                 }
-                resp = next;
+            }
+            tokio::time::sleep(interval).await;
+            self.tries += 1;
+            interval = interval.mul_f64(self.backoff);
+        }
+    }
+}
+
+
+/// Deletes the uploaded input files of a single execution from disk.
+async fn remove_execution_inputs(files: &[ScheduleFile]) {
+    for f in files {
+        match fs::remove_file(&f.path).await {
+            Ok(()) => debug!("🗑️ Deleted execution input: {}", f.path.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to delete execution input '{}': {}", f.path.display(), e),
+        }
+    }
+}
+
+
+/// Deletes execution input files that are older than the given age. Catches files left
+/// behind by executions that never completed (for example if the orchestrator was restarted).
+/// Returns the number of deleted files.
+pub async fn sweep_execution_inputs(max_age: std::time::Duration) -> std::io::Result<usize> {
+    let mut entries = match fs::read_dir(&*EXECUTION_INPUT_TMP_DIR).await {
+        Ok(it) => it,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let now = std::time::SystemTime::now();
+    let mut deleted = 0usize;
+    while let Some(entry) = entries.next_entry().await? {
+        let meta = match entry.metadata().await {
+            Ok(m) => m,
+            Err(e) => {
+                warn!("Failed to read metadata of '{}': {}", entry.path().display(), e);
                 continue;
             }
+        };
+        if !meta.is_file() {
+            continue;
+        }
+        let age = meta
+            .modified()
+            .ok()
+            .and_then(|m| now.duration_since(m).ok())
+            .unwrap_or_default();
+        if age < max_age {
+            continue;
         }
+        match fs::remove_file(entry.path()).await {
+            Ok(()) => deleted += 1,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to delete stale execution input '{}': {}", entry.path().display(), e),
+        }
+    }
+    Ok(deleted)
+}
+
 
-        _result = json!({ "error": "unexpected execution response shape" });
-        break;
+/// Continous loop for removing stale execution input files, and old execution outputs and results
+pub async fn run_execution_input_sweeper_loop(config: Arc<Config>) {
+    loop {
+        match sweep_execution_inputs(std::time::Duration::from_secs(config.execution.input_max_age_s)).await {
+            Ok(0) => debug!("✅ Execution input sweep done, nothing to delete"),
+            Ok(n) => info!("🗑️ Execution input sweep deleted {} stale files", n),
+            Err(e) => error!("Execution input sweep failed: {}", e),
+        }
+        if let Err(e) = archive_execution_outputs(&config).await {
+            error!("Execution output archiving failed: {}", e);
+        }
+        if let Err(e) = sweep_execution_outputs(&config).await {
+            error!("Execution output sweep failed: {}", e);
+        }
+        if let Err(e) = purge_execution_results(&config).await {
+            error!("Execution result purge failed: {}", e);
+        }
+        if let Err(e) = purge_execution_inputs(&config).await {
+            error!("Execution input purge failed: {}", e);
+        }
+        watchdog::heartbeat(watchdog::LOOP_EXECUTION_SWEEPER);
+        tokio::time::sleep(std::time::Duration::from_secs(config.execution.input_sweep_interval_s)).await;
     }
+}
+
 
-    Ok(HttpResponse::build(
-        actix_web::http::StatusCode::from_u16(status_code).unwrap_or(actix_web::http::StatusCode::INTERNAL_SERVER_ERROR),
-    )
-    .json(_result))
+/// Request to a supervisor taking part in an execution, with the execution timeout
+fn execution_request(config: &HttpClientConfig, method: Method, url: Url) -> reqwest::RequestBuilder {
+    http_client::client(config)
+        .request(method, url)
+        .timeout(http_client::timeout(config, Operation::Execution))
 }
 
 
-/// Start execution on the first device of the deployment chain.
+/// Start execution on the first device of the deployment chain. The callback url is passed on
+/// for the supervisor to post the final result to.
 pub async fn schedule(
+    settings: &HttpClientConfig,
     deployment: &DeploymentDoc,
     body: &HashMap<String, String>,
     files: &[ScheduleFile],
+    callback_url: Option<&str>,
 ) -> Result<reqwest::Response, String> {
     let (mut url, mut path, method_str, request) = get_start_endpoint(deployment)?;
 
     for param in &request.parameters {
         let name = &param.name;
-        let val = body.get(name).ok_or_else(|| {
-            format!("parameter missing: name='{}' in='{:?}' on path '{}'", name, param.r#in, path)
-        })?;
+        let val = match body.get(name) {
+            Some(val) => val,
+            // Optional query parameters are left out of the url
+            None if !param.required && matches!(param.r#in, OpenApiParameterIn::Query) => continue,
+            None => return Err(format!("parameter missing: name='{}' in='{:?}' on path '{}'", name, param.r#in, path)),
+        };
         match param.r#in {
             OpenApiParameterIn::Path => {
                 let with_braces = format!("{{{}}}", name);
@@ -337,7 +1266,6 @@ pub async fn schedule(
 
     url.set_path(&path);
 
-    let client = reqwest::Client::new();
     let method = match method_str.to_ascii_lowercase().as_str() {
         "get" => Method::GET,
         "head" => Method::HEAD,
@@ -350,7 +1278,10 @@ pub async fn schedule(
         m => return Err(format!("unsupported HTTP method '{}'", m)),
     };
 
-    let mut req = client.request(method.clone(), url);
+    let mut req = request_id::propagate(execution_request(settings, method.clone(), url));
+    if let Some(callback_url) = callback_url {
+        req = req.header(RESULT_CALLBACK_HEADER, callback_url);
+    }
 
     if method != Method::GET && method != Method::HEAD {
         if request.request_body.is_some() {
@@ -368,7 +1299,7 @@ pub async fn schedule(
         }
     }
 
-    req.send()
+    send_traced(req, "execution start")
         .await
         .map_err(|e| format!("request failed: {e}"))
 }
@@ -0,0 +1,147 @@
+//! # execution_inputs.rs
+//!
+//! Inputs of executions, stored by their job id (see lib/execution_queue.rs) so that an execution
+//! can be run again with the same fields and files, see `POST /execute/jobs/{job_id}/rerun`.
+//! Uploaded files are copied to EXECUTION_INPUT_DIR, as the uploads themselves are removed once
+//! the execution completes. Inputs are kept as long as results, `execution.resultRetentionDays`.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use chrono::Utc;
+use futures::TryStreamExt;
+use log::{debug, info, warn};
+use mongodb::bson::{self, doc};
+use tokio::fs;
+use crate::api::execution::ScheduleFile;
+use crate::lib::config::Config;
+use crate::lib::constants::{COLL_EXECUTION_INPUTS, EXECUTION_INPUT_DIR, EXECUTION_INPUT_TMP_DIR};
+use crate::lib::errors::ApiError;
+use crate::lib::mongodb::{find_one, get_collection};
+use crate::structs::deployment::DeploymentDoc;
+use crate::structs::execution_inputs::{ExecutionInputDoc, StoredInputFile};
+
+
+/// Directory the input files of an execution are kept in
+fn input_dir(execution_id: u64) -> PathBuf {
+    Path::new(EXECUTION_INPUT_DIR).join(execution_id.to_string())
+}
+
+
+/// Stores the fields and files an execution was started with. The files are copied, so the
+/// uploads can be removed as usual.
+pub async fn store_execution_inputs(
+    config: &Config,
+    execution_id: u64,
+    deployment: &DeploymentDoc,
+    fields: &HashMap<String, String>,
+    files: &[ScheduleFile],
+    rerun_of: Option<u64>,
+) -> Result<(), String> {
+    let mut stored_files = Vec::with_capacity(files.len());
+    if !files.is_empty() {
+        let dir = input_dir(execution_id);
+        fs::create_dir_all(&dir)
+            .await
+            .map_err(|e| format!("create input dir failed: {e}"))?;
+        for (i, file) in files.iter().enumerate() {
+            let safe = file.name.replace(['/', '\\', '\0'], "_");
+            let path = dir.join(format!("{i}_{safe}"));
+            let size = fs::copy(&file.path, &path)
+                .await
+                .map_err(|e| format!("copy of input '{}' failed: {e}", file.path.display()))?;
+            stored_files.push(StoredInputFile {
+                name: file.name.clone(),
+                path: path.to_string_lossy().to_string(),
+                size,
+            });
+        }
+    }
+
+    let inputs = ExecutionInputDoc {
+        id: None,
+        execution_id,
+        deployment_id: deployment.id,
+        deployment_name: deployment.name.clone(),
+        fields: fields.clone(),
+        files: stored_files,
+        rerun_of,
+        stored_at: Utc::now(),
+    };
+    let coll = get_collection::<ExecutionInputDoc>(&config.database, COLL_EXECUTION_INPUTS).await;
+    coll.replace_one(doc! { "executionId": execution_id as i64 }, &inputs)
+        .upsert(true)
+        .await
+        .map_err(|e| format!("inputs.replace error: {e}"))?;
+    debug!("Stored the inputs of execution {} ({} fields, {} files)", execution_id, inputs.fields.len(), inputs.files.len());
+    Ok(())
+}
+
+
+/// The stored inputs of an execution, with its files copied back to EXECUTION_INPUT_TMP_DIR like
+/// fresh uploads, so that they are removed after the new execution like any other uploads
+pub async fn load_execution_inputs(config: &Config, execution_id: u64) -> Result<(ExecutionInputDoc, Vec<ScheduleFile>), ApiError> {
+    let inputs = find_one::<ExecutionInputDoc>(&config.database, COLL_EXECUTION_INPUTS, doc! { "executionId": execution_id as i64 })
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("no stored inputs for execution {}", execution_id)))?;
+
+    let dir = EXECUTION_INPUT_TMP_DIR.clone();
+    fs::create_dir_all(&dir)
+        .await
+        .map_err(|e| ApiError::internal_error(format!("create upload dir failed: {e}")))?;
+    let ts = Utc::now().timestamp_micros();
+    let mut files: Vec<ScheduleFile> = Vec::with_capacity(inputs.files.len());
+    for (i, file) in inputs.files.iter().enumerate() {
+        let path = dir.join(format!("{ts}_{i}_rerun"));
+        if let Err(e) = fs::copy(&file.path, &path).await {
+            for copied in &files {
+                let _ = fs::remove_file(&copied.path).await;
+            }
+            return Err(match e.kind() {
+                std::io::ErrorKind::NotFound => ApiError::not_found(format!("input file '{}' of execution {} not found on disk", file.name, execution_id)),
+                _ => ApiError::internal_error(format!("copy of input '{}' failed: {e}", file.path)),
+            });
+        }
+        files.push(ScheduleFile { path, name: file.name.clone() });
+    }
+    Ok((inputs, files))
+}
+
+
+/// Deletes inputs stored more than `execution.resultRetentionDays` ago, along with their files.
+/// Does nothing if the retention is 0. Returns the number of deleted inputs.
+pub async fn purge_execution_inputs(config: &Config) -> Result<u64, String> {
+    if config.execution.result_retention_days == 0 {
+        return Ok(0);
+    }
+    let cutoff = Utc::now() - chrono::Duration::days(config.execution.result_retention_days as i64);
+    let filter = doc! { "storedAt": { "$lt": bson::DateTime::from_chrono(cutoff) } };
+    let coll = get_collection::<ExecutionInputDoc>(&config.database, COLL_EXECUTION_INPUTS).await;
+
+    let mut file_filter = filter.clone();
+    file_filter.insert("files.0", doc! { "$exists": true });
+    let with_files: Vec<ExecutionInputDoc> = coll
+        .find(file_filter)
+        .await
+        .map_err(|e| format!("inputs.find error: {e}"))?
+        .try_collect()
+        .await
+        .map_err(|e| format!("inputs cursor error: {e}"))?;
+    for inputs in &with_files {
+        let dir = input_dir(inputs.execution_id);
+        match fs::remove_dir_all(&dir).await {
+            Ok(()) => debug!("🗑️ Deleted execution input files: {}", dir.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to delete execution input files '{}': {}", dir.display(), e),
+        }
+    }
+
+    let res = coll
+        .delete_many(filter)
+        .await
+        .map_err(|e| format!("inputs.delete error: {e}"))?;
+    if res.deleted_count > 0 {
+        info!("🗑️ Purged the inputs of {} executions older than {} days", res.deleted_count, config.execution.result_retention_days);
+    }
+    Ok(res.deleted_count)
+}
@@ -0,0 +1,396 @@
+//! # execution_outputs.rs
+//!
+//! Endpoints where supervisors can push the output files they produce during execution
+//! directly to the orchestrators storage, instead of serving them themselves (which only
+//! lasts until the supervisor reboots). Stored outputs count towards the storage quota,
+//! are removed together with their deployment and are swept once they get old enough,
+//! or moved to the archive directory first if one is configured. Outputs uploaded with the
+//! job id of their execution are also listed with its stored result, see `GET /execute/results/{job_id}`.
+
+use std::collections::HashMap;
+
+use std::path::{Path, PathBuf};
+use actix_files::NamedFile;
+use actix_multipart::Multipart;
+use actix_web::{web, HttpResponse, Responder};
+use chrono::Utc;
+use futures::TryStreamExt;
+use log::{debug, info, warn};
+use mongodb::bson::{doc, oid::ObjectId};
+use serde_json::json;
+use tokio::fs;
+use tokio::io::AsyncWriteExt as _;
+use crate::api::storage::{check_quota, storage_report};
+use crate::lib::constants::{
+    COLL_DEPLOYMENT,
+    COLL_DEVICE,
+    COLL_EXECUTION_OUTPUTS,
+    COLL_EXECUTION_RESULTS,
+    EXECUTION_OUTPUT_DIR
+};
+use crate::lib::config::Config;
+use crate::lib::errors::ApiError;
+use crate::api::device::device_filter;
+use crate::lib::mongodb::{find_one, get_collection};
+use crate::lib::response::ok_json;
+use crate::structs::deployment::DeploymentDoc;
+use crate::structs::device::DeviceDoc;
+use crate::structs::execution_outputs::ExecutionOutputDoc;
+use crate::structs::execution_results::ExecutionResultDoc;
+
+
+/// Helper function that checks that the device (by id or name) and deployment exist, and that
+/// the device takes part in the deployment. Returns the deployment id and the device name.
+async fn check_device_in_deployment(config: &Config, device_key: &str, deployment_id: &str) -> Result<(ObjectId, String), ApiError> {
+    let deployment_oid = ObjectId::parse_str(deployment_id)
+        .map_err(|_| ApiError::bad_request(format!("invalid deployment id '{}'", deployment_id)))?;
+
+    let device = find_one::<DeviceDoc>(&config.database, COLL_DEVICE, device_filter(device_key))
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("no device matches '{}'", device_key)))?;
+    let deployment = find_one::<DeploymentDoc>(&config.database, COLL_DEPLOYMENT, doc! { "_id": &deployment_oid })
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("no deployment matches id '{}'", deployment_id)))?;
+
+    let device_hex = device.id.map(|id| id.to_hex()).unwrap_or_default();
+    if !deployment.full_manifest.contains_key(&device_hex) {
+        return Err(ApiError::bad_request(format!(
+            "device '{}' is not part of deployment '{}'",
+            device.name, deployment_id
+        )));
+    }
+    Ok((deployment_oid, device.name))
+}
+
+
+/// Helper function that parses the optional `executionId` query parameter of an output upload. If
+/// the result of the execution is already stored, it must be of the same deployment. A result
+/// that isnt stored yet is fine, as outputs are usually uploaded while the execution is running.
+async fn linked_execution_id(
+    config: &Config,
+    query: &HashMap<String, String>,
+    deployment_id: &ObjectId,
+) -> Result<Option<u64>, ApiError> {
+    let Some(raw) = query.get("executionId") else {
+        return Ok(None);
+    };
+    let execution_id = raw
+        .parse::<u64>()
+        .map_err(|_| ApiError::bad_request(format!("invalid executionId '{}'", raw)))?;
+    let result = find_one::<ExecutionResultDoc>(&config.database, COLL_EXECUTION_RESULTS, doc! { "executionId": execution_id as i64 })
+        .await
+        .map_err(ApiError::db)?;
+    if result.is_some_and(|r| r.deployment_id.as_ref() != Some(deployment_id)) {
+        return Err(ApiError::bad_request(format!(
+            "execution {} is not an execution of deployment '{}'",
+            execution_id,
+            deployment_id.to_hex()
+        )));
+    }
+    Ok(Some(execution_id))
+}
+
+
+/// Outputs are stored under the device name. Resolves the name of a device given by its id,
+/// falling back to the given string so that outputs of removed devices can still be reached.
+async fn device_name_for(config: &Config, device_key: &str) -> Result<String, ApiError> {
+    if ObjectId::parse_str(device_key).is_err() {
+        return Ok(device_key.to_string());
+    }
+    let device = find_one::<DeviceDoc>(&config.database, COLL_DEVICE, device_filter(device_key))
+        .await
+        .map_err(ApiError::db)?;
+    Ok(device.map(|d| d.name).unwrap_or_else(|| device_key.to_string()))
+}
+
+
+/// Directory where outputs of a device in a deployment are stored
+fn output_dir(deployment_id: &ObjectId, device_name: &str) -> PathBuf {
+    let safe_device = device_name.replace(['/', '\\', '\0'], "_");
+    Path::new(EXECUTION_OUTPUT_DIR)
+        .join(deployment_id.to_hex())
+        .join(safe_device)
+}
+
+
+/// POST /file/device/{device_name}/outputs/{deployment_id}
+///
+/// Endpoint where supervisors can upload output files (as multipart/form-data) produced
+/// while executing the given deployment. With `?executionId=<job id>` the files are linked to
+/// that execution and listed with its stored result. Returns the stored outputs.
+pub async fn upload_execution_outputs(
+    config: web::Data<Config>,
+    path: web::Path<(String, String)>,
+    query: web::Query<HashMap<String, String>>,
+    mut payload: Multipart,
+) -> Result<impl Responder, ApiError> {
+    let (device_key, deployment_id) = path.into_inner();
+    let (deployment_oid, device_name) = check_device_in_deployment(&config, &device_key, &deployment_id).await?;
+    let execution_id = linked_execution_id(&config, &query, &deployment_oid).await?;
+
+    let used_bytes = storage_report(&config.storage).await?.total_bytes;
+    check_quota(&config.storage, used_bytes, 0)?;
+
+    let dir = output_dir(&deployment_oid, &device_name);
+    fs::create_dir_all(&dir)
+        .await
+        .map_err(|e| ApiError::internal_error(format!("create output dir failed: {e}")))?;
+
+    let mut saved: Vec<ExecutionOutputDoc> = Vec::new();
+    let mut written_bytes = 0u64;
+    while let Some(mut field) = payload.try_next().await.map_err(|e| {
+        ApiError::bad_request(format!("multipart error: {e}"))
+    })? {
+        let Some(filename) = field
+            .content_disposition()
+            .and_then(|cd| cd.get_filename())
+            .map(|f| f.to_string())
+        else {
+            debug!("Ignoring non-file field '{}' in output upload", field.name().unwrap_or(""));
+            continue;
+        };
+        let name = field.name().unwrap_or("").to_string();
+        let content_type = field
+            .content_type()
+            .map(|m| m.to_string())
+            .unwrap_or_else(|| mime_guess::from_path(&filename).first_or_octet_stream().to_string());
+
+        let ts = Utc::now().timestamp_micros();
+        let safe = filename.replace(['/', '\\', '\0'], "_");
+        let filepath = dir.join(format!("{ts}_{safe}"));
+        let mut f = fs::File::create(&filepath)
+            .await
+            .map_err(|e| ApiError::internal_error(format!("open output file failed: {e}")))?;
+
+        let mut size = 0u64;
+        while let Some(chunk) = field.try_next().await.map_err(|e| {
+            ApiError::bad_request(format!("reading file chunk failed: {e}"))
+        })? {
+            size += chunk.len() as u64;
+            written_bytes += chunk.len() as u64;
+            if let Err(e) = check_quota(&config.storage, used_bytes, written_bytes) {
+                drop(f);
+                let _ = fs::remove_file(&filepath).await;
+                for s in &saved {
+                    let _ = fs::remove_file(&s.path).await;
+                }
+                warn!("⚠️ Output upload from device '{}' rejected due to storage quota", device_name);
+                return Err(e);
+            }
+            f.write_all(&chunk)
+                .await
+                .map_err(|e| ApiError::internal_error(format!("write output failed: {e}")))?;
+        }
+
+        saved.push(ExecutionOutputDoc {
+            id: None,
+            deployment_id: deployment_oid,
+            device: device_name.clone(),
+            execution_id,
+            name,
+            filename,
+            path: filepath.to_string_lossy().to_string(),
+            size,
+            content_type,
+            date_received: Utc::now(),
+            archived_at: None,
+        });
+    }
+
+    if saved.is_empty() {
+        return Err(ApiError::bad_request("no files in output upload"));
+    }
+
+    let coll = get_collection::<ExecutionOutputDoc>(&config.database, COLL_EXECUTION_OUTPUTS).await;
+    let res = coll.insert_many(&saved).await.map_err(ApiError::db)?;
+    for (i, output) in saved.iter_mut().enumerate() {
+        output.id = res.inserted_ids.get(&i).and_then(|id| id.as_object_id());
+    }
+    match execution_id {
+        Some(id) => info!("📄 Stored {} output file(s) from device '{}' for execution {} of deployment '{}'", saved.len(), device_name, id, deployment_id),
+        None => info!("📄 Stored {} output file(s) from device '{}' for deployment '{}'", saved.len(), device_name, deployment_id),
+    }
+
+    ok_json(&saved)
+}
+
+
+/// GET /file/device/{device_name}/outputs/{deployment_id}
+///
+/// Lists the output files a device has uploaded for the given deployment, newest first.
+pub async fn get_execution_outputs(config: web::Data<Config>, path: web::Path<(String, String)>) -> Result<impl Responder, ApiError> {
+    let (device_key, deployment_id) = path.into_inner();
+    let device_name = device_name_for(&config, &device_key).await?;
+    let deployment_oid = ObjectId::parse_str(&deployment_id)
+        .map_err(|_| ApiError::bad_request(format!("invalid deployment id '{}'", deployment_id)))?;
+
+    let coll = get_collection::<ExecutionOutputDoc>(&config.database, COLL_EXECUTION_OUTPUTS).await;
+    let outputs: Vec<ExecutionOutputDoc> = coll
+        .find(doc! { "deploymentId": &deployment_oid, "device": &device_name })
+        .sort(doc! { "dateReceived": -1 })
+        .await
+        .map_err(ApiError::db)?
+        .try_collect()
+        .await
+        .map_err(ApiError::db)?;
+    ok_json(&outputs)
+}
+
+
+/// GET /file/device/{device_name}/outputs/{deployment_id}/{output_id}
+///
+/// Returns a single uploaded output file.
+pub async fn get_execution_output_file(config: web::Data<Config>, path: web::Path<(String, String, String)>) -> Result<NamedFile, ApiError> {
+    let (device_key, deployment_id, output_id) = path.into_inner();
+    let device_name = device_name_for(&config, &device_key).await?;
+    let deployment_oid = ObjectId::parse_str(&deployment_id)
+        .map_err(|_| ApiError::bad_request(format!("invalid deployment id '{}'", deployment_id)))?;
+    let output_oid = ObjectId::parse_str(&output_id)
+        .map_err(|_| ApiError::bad_request(format!("invalid output id '{}'", output_id)))?;
+
+    let output = find_one::<ExecutionOutputDoc>(
+        &config.database,
+        COLL_EXECUTION_OUTPUTS,
+        doc! { "_id": &output_oid, "deploymentId": &deployment_oid, "device": &device_name },
+    )
+    .await
+    .map_err(ApiError::db)?
+    .ok_or_else(|| ApiError::not_found(format!("no output matches id '{}'", output_id)))?;
+
+    let named = NamedFile::open(&output.path)
+        .map_err(|_| ApiError::not_found("File not found on disk"))?;
+    let content_type = output
+        .content_type
+        .parse()
+        .unwrap_or(mime_guess::mime::APPLICATION_OCTET_STREAM);
+    Ok(named.set_content_type(content_type))
+}
+
+
+/// DELETE /file/device/{device_name}/outputs/{deployment_id}
+///
+/// Deletes the output files a device has uploaded for the given deployment.
+pub async fn delete_execution_outputs(config: web::Data<Config>, path: web::Path<(String, String)>) -> Result<impl Responder, ApiError> {
+    let (device_key, deployment_id) = path.into_inner();
+    let device_name = device_name_for(&config, &device_key).await?;
+    let deployment_oid = ObjectId::parse_str(&deployment_id)
+        .map_err(|_| ApiError::bad_request(format!("invalid deployment id '{}'", deployment_id)))?;
+    let deleted = remove_outputs(&config, doc! { "deploymentId": &deployment_oid, "device": &device_name })
+        .await
+        .map_err(ApiError::db)?;
+    Ok(HttpResponse::Ok().json(json!({ "deletedCount": deleted })))
+}
+
+
+/// Removes all stored outputs of a deployment. Used when the deployment itself is deleted.
+pub async fn remove_deployment_outputs(config: &Config, deployment_id: Option<&ObjectId>) -> Result<u64, String> {
+    let filter = match deployment_id {
+        Some(id) => doc! { "deploymentId": id },
+        None => doc! {},
+    };
+    remove_outputs(config, filter).await
+}
+
+
+/// Helper function that deletes the output files and documents matching the given filter
+async fn remove_outputs(config: &Config, filter: mongodb::bson::Document) -> Result<u64, String> {
+    let coll = get_collection::<ExecutionOutputDoc>(&config.database, COLL_EXECUTION_OUTPUTS).await;
+    let outputs: Vec<ExecutionOutputDoc> = coll
+        .find(filter.clone())
+        .await
+        .map_err(|e| format!("outputs.find error: {e}"))?
+        .try_collect()
+        .await
+        .map_err(|e| format!("outputs cursor error: {e}"))?;
+    for output in &outputs {
+        match fs::remove_file(&output.path).await {
+            Ok(()) => debug!("🗑️ Deleted execution output: {}", output.path),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to delete execution output '{}': {}", output.path, e),
+        }
+    }
+    let res = coll
+        .delete_many(filter)
+        .await
+        .map_err(|e| format!("outputs.delete error: {e}"))?;
+    Ok(res.deleted_count)
+}
+
+
+/// Deletes stored outputs that are older than the output max age. Archived outputs are kept.
+/// Returns the number of deleted outputs.
+pub async fn sweep_execution_outputs(config: &Config) -> Result<u64, String> {
+    let cutoff = Utc::now() - chrono::Duration::seconds(config.execution.output_max_age_s as i64);
+    let deleted = remove_outputs(config, doc! {
+        "dateReceived": { "$lt": mongodb::bson::DateTime::from_chrono(cutoff) },
+        "archivedAt": { "$exists": false },
+    }).await?;
+    if deleted > 0 {
+        info!("🗑️ Execution output sweep deleted {} old outputs", deleted);
+    }
+    Ok(deleted)
+}
+
+
+/// Moves outputs older than the archive age to the archive directory, and updates their stored
+/// paths so that they can still be downloaded. Does nothing if no archive directory is
+/// configured. Returns the number of archived outputs.
+pub async fn archive_execution_outputs(config: &Config) -> Result<u64, String> {
+    let Some(archive_dir) = config.execution.archive_dir.as_ref() else {
+        return Ok(0);
+    };
+    let cutoff = Utc::now() - chrono::Duration::days(config.execution.archive_after_days as i64);
+    let coll = get_collection::<ExecutionOutputDoc>(&config.database, COLL_EXECUTION_OUTPUTS).await;
+    let outputs: Vec<ExecutionOutputDoc> = coll
+        .find(doc! {
+            "dateReceived": { "$lt": mongodb::bson::DateTime::from_chrono(cutoff) },
+            "archivedAt": { "$exists": false },
+        })
+        .await
+        .map_err(|e| format!("outputs.find error: {e}"))?
+        .try_collect()
+        .await
+        .map_err(|e| format!("outputs cursor error: {e}"))?;
+
+    let mut archived = 0;
+    for output in outputs {
+        let Some(id) = output.id else { continue };
+        let source = Path::new(&output.path);
+        let Some(file_name) = source.file_name() else { continue };
+        let target = archive_dir.join(output.deployment_id.to_hex()).join(file_name);
+        if let Err(e) = move_file(source, &target).await {
+            warn!("Failed to archive execution output '{}': {}", output.path, e);
+            continue;
+        }
+        coll.update_one(
+            doc! { "_id": &id },
+            doc! { "$set": {
+                "path": target.to_string_lossy().to_string(),
+                "archivedAt": mongodb::bson::DateTime::now(),
+            } },
+        )
+        .await
+        .map_err(|e| format!("outputs.update error: {e}"))?;
+        debug!("📦 Archived execution output '{}' to '{}'", output.path, target.display());
+        archived += 1;
+    }
+    if archived > 0 {
+        info!("📦 Archived {} execution outputs to {}", archived, archive_dir.display());
+    }
+    Ok(archived)
+}
+
+
+/// Moves a file, copying it when the target is on a different filesystem
+async fn move_file(source: &Path, target: &Path) -> std::io::Result<()> {
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    if fs::rename(source, target).await.is_ok() {
+        return Ok(());
+    }
+    fs::copy(source, target).await?;
+    fs::remove_file(source).await
+}
@@ -0,0 +1,209 @@
+//! # execution_results.rs
+//!
+//! Final results of executions, stored by their job id (the id the execution got in the queue,
+//! see lib/execution_queue.rs) so that they can be fetched after the execution, which is the
+//! only way to get the result of an execution started with `?async=true`. Results up to
+//! `execution.resultInlineBytes` are stored in the database, larger ones as files in
+//! EXECUTION_RESULT_DIR. Results older than `execution.resultRetentionDays` are purged along
+//! with the execution input sweep. Output files supervisors uploaded with the job id of the
+//! execution (see api/execution_outputs.rs) are listed with its result.
+
+use std::collections::HashMap;
+use std::path::Path;
+use actix_web::{web, Responder};
+use chrono::Utc;
+use futures::TryStreamExt;
+use log::{debug, info, warn};
+use mongodb::bson::{self, doc, oid::ObjectId, Document};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::fs;
+use crate::api::health_history::parse_time;
+use crate::lib::config::Config;
+use crate::lib::constants::{COLL_EXECUTION_OUTPUTS, COLL_EXECUTION_RESULTS, EXECUTION_RESULT_DIR};
+use crate::lib::errors::ApiError;
+use crate::lib::mongodb::{find_one, get_collection};
+use crate::lib::pagination::{find_page, page_response, Pagination};
+use crate::lib::response::ok_json;
+use crate::structs::execution_outputs::ExecutionOutputDoc;
+use crate::structs::execution_results::ExecutionResultDoc;
+
+
+pub const RESULT_SUCCEEDED: &str = "succeeded";
+pub const RESULT_FAILED: &str = "failed"; // The execution returned an error, or couldnt be started
+pub const RESULT_TIMED_OUT: &str = "timedOut";
+pub const RESULT_CANCELLED: &str = "cancelled";
+
+const RESULT_STATUSES: &[&str] = &[RESULT_SUCCEEDED, RESULT_FAILED, RESULT_TIMED_OUT, RESULT_CANCELLED];
+
+pub const RESULT_SORT_FIELDS: &[(&str, &str)] = &[
+    ("finishedAt", "finishedAt"),
+    ("startedAt", "startedAt"),
+    ("durationMs", "durationMs"),
+];
+
+
+/// Response of `GET /execute/results/{job_id}`, the stored result with the outputs linked to it
+#[derive(Debug, Serialize)]
+struct ExecutionResultResponse {
+    #[serde(flatten)]
+    result: ExecutionResultDoc,
+    outputs: Vec<ExecutionOutputDoc>,
+}
+
+
+/// File a result too large to be stored inline is kept in
+fn result_file(execution_id: u64) -> std::path::PathBuf {
+    Path::new(EXECUTION_RESULT_DIR).join(format!("{}.json", execution_id))
+}
+
+
+/// Stores the result of a finished execution, replacing an earlier one with the same job id.
+/// A result larger than `execution.resultInlineBytes` is written to a file instead.
+pub async fn store_execution_result(config: &Config, mut result: ExecutionResultDoc) -> Result<(), String> {
+    if let Some(value) = &result.result {
+        let bytes = serde_json::to_vec(value).map_err(|e| format!("serializing result failed: {e}"))?;
+        result.result_bytes = bytes.len() as u64;
+        if bytes.len() > config.execution.result_inline_bytes {
+            let path = result_file(result.execution_id);
+            fs::create_dir_all(EXECUTION_RESULT_DIR)
+                .await
+                .map_err(|e| format!("create result dir failed: {e}"))?;
+            fs::write(&path, &bytes)
+                .await
+                .map_err(|e| format!("write result file failed: {e}"))?;
+            result.result = None;
+            result.result_path = Some(path.to_string_lossy().to_string());
+        }
+    }
+
+    let coll = get_collection::<ExecutionResultDoc>(&config.database, COLL_EXECUTION_RESULTS).await;
+    coll.replace_one(doc! { "executionId": result.execution_id as i64 }, &result)
+        .upsert(true)
+        .await
+        .map_err(|e| format!("results.replace error: {e}"))?;
+    debug!(
+        "Stored the result of execution {} ({}, {} bytes{})",
+        result.execution_id,
+        result.status,
+        result.result_bytes,
+        if result.result_path.is_some() { ", as a file" } else { "" }
+    );
+    Ok(())
+}
+
+
+/// GET /execute/results/{job_id}
+///
+/// Returns the stored result of an execution by its job id, with the result read from its file
+/// if it was too large to be stored inline, and the output files uploaded for the execution
+/// under `outputs`, oldest first.
+pub async fn get_execution_result(config: web::Data<Config>, path: web::Path<u64>) -> Result<impl Responder, ApiError> {
+    let execution_id = path.into_inner();
+    let mut result = find_one::<ExecutionResultDoc>(&config.database, COLL_EXECUTION_RESULTS, doc! { "executionId": execution_id as i64 })
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("no stored result for execution {}", execution_id)))?;
+
+    if let Some(file) = result.result_path.take() {
+        let bytes = fs::read(&file)
+            .await
+            .map_err(|_| ApiError::not_found(format!("result file of execution {} not found on disk", execution_id)))?;
+        let value: Value = serde_json::from_slice(&bytes)
+            .map_err(|e| ApiError::internal_error(format!("invalid result file of execution {}: {}", execution_id, e)))?;
+        result.result = Some(value);
+    }
+
+    let outputs: Vec<ExecutionOutputDoc> = get_collection::<ExecutionOutputDoc>(&config.database, COLL_EXECUTION_OUTPUTS)
+        .await
+        .find(doc! { "executionId": execution_id as i64 })
+        .sort(doc! { "dateReceived": 1 })
+        .await
+        .map_err(ApiError::db)?
+        .try_collect()
+        .await
+        .map_err(ApiError::db)?;
+    ok_json(&ExecutionResultResponse { result, outputs })
+}
+
+
+/// GET /execute/results
+///
+/// Lists the stored execution results, newest first. Results stored as files are listed without
+/// the result itself, fetch them one by one instead. Supports query parameters:
+/// - `deployment`: id or name of the deployment
+/// - `status`: succeeded, failed, timedOut or cancelled
+/// - `from`, `to`: RFC3339 bounds of the finishing time
+/// - `limit`, `offset` and `sort` (finishedAt, startedAt, durationMs) like `GET /device/logs`
+pub async fn get_execution_results(config: web::Data<Config>, query: web::Query<HashMap<String, String>>) -> Result<impl Responder, ApiError> {
+    let mut filter = Document::new();
+    if let Some(deployment) = query.get("deployment") {
+        match ObjectId::parse_str(deployment) {
+            Ok(oid) => filter.insert("deploymentId", oid),
+            Err(_) => filter.insert("deploymentName", deployment),
+        };
+    }
+    if let Some(status) = query.get("status") {
+        if !RESULT_STATUSES.contains(&status.as_str()) {
+            return Err(ApiError::bad_request(format!(
+                "invalid status '{}', expected one of {}",
+                status,
+                RESULT_STATUSES.join(", ")
+            )));
+        }
+        filter.insert("status", status);
+    }
+    let mut finished = Document::new();
+    if let Some(from) = parse_time(&query, "from")? {
+        finished.insert("$gte", bson::DateTime::from_chrono(from));
+    }
+    if let Some(to) = parse_time(&query, "to")? {
+        finished.insert("$lte", bson::DateTime::from_chrono(to));
+    }
+    if !finished.is_empty() {
+        filter.insert("finishedAt", finished);
+    }
+
+    let pagination = Pagination::from_query(&query, RESULT_SORT_FIELDS, Some(doc! { "finishedAt": -1 }))?;
+    let coll = get_collection::<ExecutionResultDoc>(&config.database, COLL_EXECUTION_RESULTS).await;
+    let page = find_page(&coll, filter, &pagination).await?;
+    page_response(&page)
+}
+
+
+/// Deletes results that finished more than `execution.resultRetentionDays` ago, along with their
+/// files. Does nothing if the retention is 0. Returns the number of deleted results.
+pub async fn purge_execution_results(config: &Config) -> Result<u64, String> {
+    if config.execution.result_retention_days == 0 {
+        return Ok(0);
+    }
+    let cutoff = Utc::now() - chrono::Duration::days(config.execution.result_retention_days as i64);
+    let filter = doc! { "finishedAt": { "$lt": bson::DateTime::from_chrono(cutoff) } };
+    let coll = get_collection::<ExecutionResultDoc>(&config.database, COLL_EXECUTION_RESULTS).await;
+
+    let mut file_filter = filter.clone();
+    file_filter.insert("resultPath", doc! { "$exists": true });
+    let with_files: Vec<ExecutionResultDoc> = coll
+        .find(file_filter)
+        .await
+        .map_err(|e| format!("results.find error: {e}"))?
+        .try_collect()
+        .await
+        .map_err(|e| format!("results cursor error: {e}"))?;
+    for path in with_files.iter().filter_map(|r| r.result_path.as_ref()) {
+        match fs::remove_file(path).await {
+            Ok(()) => debug!("🗑️ Deleted execution result file: {}", path),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to delete execution result file '{}': {}", path, e),
+        }
+    }
+
+    let res = coll
+        .delete_many(filter)
+        .await
+        .map_err(|e| format!("results.delete error: {e}"))?;
+    if res.deleted_count > 0 {
+        info!("🗑️ Purged {} execution results older than {} days", res.deleted_count, config.execution.result_retention_days);
+    }
+    Ok(res.deleted_count)
+}
@@ -0,0 +1,209 @@
+//! # health_history.rs
+//!
+//! History of device health checks. Every successful health check is stored as a sample in a
+//! time series collection (the `health` field of a device only holds the latest report), and
+//! samples older than the retention of the health check settings are removed by MongoDB. The history can be
+//! queried either as raw samples or averaged into fixed size time steps for drawing charts.
+
+use std::collections::HashMap;
+use actix_web::{web, Responder};
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use log::{error, info, warn};
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::options::{TimeseriesGranularity, TimeseriesOptions};
+use serde_json::json;
+use crate::api::device::device_filter;
+use crate::lib::config::Config;
+use crate::lib::constants::{COLL_DEVICE, COLL_HEALTH_HISTORY};
+use crate::lib::errors::ApiError;
+use crate::lib::mongodb::{find_one, get_collection, get_database};
+use crate::lib::response::ok_json;
+use crate::structs::device::{DeviceDoc, Health, HealthReport};
+use crate::structs::health_history::HealthSample;
+
+
+/// Time range returned when the query doesnt give one
+const DEFAULT_RANGE_S: i64 = 3600;
+
+/// Maximum number of raw samples returned, use `step` for longer ranges
+const MAX_RAW_SAMPLES: i64 = 10_000;
+
+
+/// Creates the health history time series collection if it doesnt exist, and applies the
+/// configured retention to it. Called at startup.
+pub async fn ensure_health_history_collection(config: &Config) {
+    let db = get_database(&config.database).await;
+    let exists = match db.list_collection_names().await {
+        Ok(names) => names.iter().any(|n| n == COLL_HEALTH_HISTORY),
+        Err(e) => {
+            error!("❌ Failed to list collections for the health history: {}", e);
+            return;
+        }
+    };
+    if !exists {
+        let timeseries = TimeseriesOptions::builder()
+            .time_field("time".to_string())
+            .meta_field(Some("deviceId".to_string()))
+            .granularity(Some(TimeseriesGranularity::Seconds))
+            .build();
+        if let Err(e) = db.create_collection(COLL_HEALTH_HISTORY).timeseries(timeseries).await {
+            error!("❌ Failed to create the health history collection: {}", e);
+            return;
+        }
+    }
+
+    // Applied on every startup, so that changing the retention takes effect for an existing collection
+    let expire: Bson = match config.health_checks.history_retention_days {
+        0 => Bson::String("off".to_string()),
+        days => Bson::Int64((days * 24 * 60 * 60) as i64),
+    };
+    if let Err(e) = db.run_command(doc! { "collMod": COLL_HEALTH_HISTORY, "expireAfterSeconds": expire }).await {
+        warn!("Failed to set the retention of the health history: {}", e);
+    }
+    info!("... Device health history ready (retention {} days, 0 means forever).", config.health_checks.history_retention_days);
+}
+
+
+/// Stores a sample of a successful health check. `previous` is the health of the previous
+/// check of the device, used for the network deltas and the interval between the samples.
+pub async fn record_health_sample(config: &Config, device: &DeviceDoc, previous: Option<&Health>, health: &Health) {
+    let Some(device_id) = device.id else { return };
+    let (network_down_bytes, network_up_bytes) = match previous {
+        Some(prev) => network_deltas(&prev.report, &health.report),
+        None => (None, None),
+    };
+    let interval_s = previous
+        .filter(|_| network_down_bytes.is_some() || network_up_bytes.is_some())
+        .map(|prev| (health.time_of_query - prev.time_of_query).num_milliseconds() as f64 / 1000.0)
+        .filter(|s| *s > 0.0);
+    let sample = HealthSample {
+        id: None,
+        device_id,
+        time: mongodb::bson::DateTime::from_chrono(health.time_of_query),
+        cpu_usage: health.report.cpu_usage,
+        memory_usage: health.report.memory_usage,
+        latency_ms: health.latency_ms,
+        network_down_bytes,
+        network_up_bytes,
+        interval_s,
+    };
+    let coll = get_collection::<HealthSample>(&config.database, COLL_HEALTH_HISTORY).await;
+    if let Err(e) = coll.insert_one(&sample).await {
+        warn!("Failed to store health sample of device '{}': {}", device.name, e);
+    }
+}
+
+
+/// Bytes received and sent over all interfaces between two reports. None if a counter went
+/// backwards, which means the device restarted in between.
+fn network_deltas(previous: &HealthReport, current: &HealthReport) -> (Option<u64>, Option<u64>) {
+    let total = |r: &HealthReport| {
+        r.network_usage.values().fold((0u64, 0u64), |(down, up), u| (down + u.down_bytes, up + u.up_bytes))
+    };
+    let (prev_down, prev_up) = total(previous);
+    let (down, up) = total(current);
+    (down.checked_sub(prev_down), up.checked_sub(prev_up))
+}
+
+
+/// GET /file/device/{device_id}/health/history
+///
+/// Returns the health samples of a device (by id or name). Supports query parameters:
+/// - `from` / `to`: RFC3339 times, by default the last hour
+/// - `step`: seconds, if given the samples are averaged into steps of this size (network
+///   deltas are summed), with the number of samples in each step in `samples`
+pub async fn get_health_history(config: web::Data<Config>, path: web::Path<String>, query: web::Query<HashMap<String, String>>) -> Result<impl Responder, ApiError> {
+    let device_key = path.into_inner();
+    let device = find_one::<DeviceDoc>(&config.database, COLL_DEVICE, device_filter(&device_key))
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("no device matches '{}'", device_key)))?;
+    let device_id = device.id.ok_or_else(|| ApiError::internal_error("device has no id"))?;
+
+    let to = parse_time(&query, "to")?.unwrap_or_else(Utc::now);
+    let from = parse_time(&query, "from")?.unwrap_or(to - chrono::Duration::seconds(DEFAULT_RANGE_S));
+    if from > to {
+        return Err(ApiError::bad_request("from must be before to"));
+    }
+    let step = query
+        .get("step")
+        .map(|s| s.parse::<u64>().ok().filter(|s| *s > 0).ok_or_else(|| ApiError::bad_request("step must be a positive number of seconds")))
+        .transpose()?;
+
+    let filter = doc! {
+        "deviceId": device_id,
+        "time": {
+            "$gte": mongodb::bson::DateTime::from_chrono(from),
+            "$lte": mongodb::bson::DateTime::from_chrono(to),
+        },
+    };
+    let coll = get_collection::<Document>(&config.database, COLL_HEALTH_HISTORY).await;
+    let samples: Vec<Document> = match step {
+        None => coll
+            .find(filter)
+            .sort(doc! { "time": 1 })
+            .limit(MAX_RAW_SAMPLES)
+            .projection(doc! { "_id": 0, "deviceId": 0 })
+            .await
+            .map_err(ApiError::db)?
+            .try_collect()
+            .await
+            .map_err(ApiError::db)?,
+        Some(step) => {
+            let step_ms = (step * 1000) as i64;
+            let bucket = doc! { "$toDate": { "$subtract": [{ "$toLong": "$time" }, { "$mod": [{ "$toLong": "$time" }, step_ms] }] } };
+            let pipeline = vec![
+                doc! { "$match": filter },
+                doc! { "$group": {
+                    "_id": bucket,
+                    "cpuUsage": { "$avg": "$cpuUsage" },
+                    "memoryUsage": { "$avg": "$memoryUsage" },
+                    "latencyMs": { "$avg": "$latencyMs" },
+                    "networkDownBytes": { "$sum": "$networkDownBytes" },
+                    "networkUpBytes": { "$sum": "$networkUpBytes" },
+                    "samples": { "$sum": 1 },
+                } },
+                doc! { "$sort": { "_id": 1 } },
+                doc! { "$project": {
+                    "_id": 0,
+                    "time": "$_id",
+                    "cpuUsage": 1,
+                    "memoryUsage": 1,
+                    "latencyMs": 1,
+                    "networkDownBytes": 1,
+                    "networkUpBytes": 1,
+                    "samples": 1,
+                } },
+            ];
+            coll.aggregate(pipeline)
+                .await
+                .map_err(ApiError::db)?
+                .try_collect()
+                .await
+                .map_err(ApiError::db)?
+        }
+    };
+
+    ok_json(&json!({
+        "device": device.name,
+        "deviceId": device_id.to_hex(),
+        "from": from,
+        "to": to,
+        "step": step,
+        "samples": samples,
+    }))
+}
+
+
+/// Parses an optional RFC3339 time query parameter
+pub fn parse_time(query: &HashMap<String, String>, name: &str) -> Result<Option<DateTime<Utc>>, ApiError> {
+    query
+        .get(name)
+        .map(|v| {
+            DateTime::parse_from_rfc3339(v)
+                .map(|dt| dt.with_timezone(&Utc))
+                .map_err(|_| ApiError::bad_request(format!("invalid {} '{}', expected an RFC3339 time", name, v)))
+        })
+        .transpose()
+}
@@ -2,12 +2,16 @@ use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
 use serde_json::{json, Value};
 use mongodb::bson::{self, doc, Document};
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{web, FromRequest, HttpRequest, HttpResponse, Responder};
+use actix_web::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use futures::StreamExt;
+use crate::lib::config::Config;
+use crate::lib::response::to_normalized_value;
 use crate::lib::mongodb::{get_collection};
-use futures::stream::TryStreamExt;
 use actix_web::web::Form;
 use crate::structs::logs::SupervisorLog;
 use crate::lib::errors::ApiError;
+use crate::lib::pagination::{find_page, page_response, Pagination};
 use log::{debug, error};
 use crate::lib::constants::COLL_LOGS;
 
@@ -36,97 +40,285 @@ pub struct LogData {
 }
 
 
+/// Most logs accepted in a single request
+const MAX_LOG_BATCH: usize = 1000;
+
+
 /// POST /device/logs
 /// 
-/// Endpoint to receive and save supervisor logs
-pub async fn post_supervisor_log(form: Form<std::collections::HashMap<String, String>>) -> Result<impl Responder, ApiError> {
-    if let Some(log_data_str) = form.get("logData") {
-        let log_data: Value = match serde_json::from_str(log_data_str) {
-            Ok(val) => val,
-            Err(e) => {
-                error!("Failed to parse logData as JSON: {}", e);
-                return Err(ApiError::bad_request("Invalid logData JSON"));
-            }
-        };
-        debug!("Received supervisor log: {:?}", log_data);
-
-        // Verify the log data structure
-        let verified_supervisor_log: LogData = match serde_json::from_value::<LogData>(log_data.clone()) {
-            Ok(log) => log, 
-            Err(e) => {
-                error!("Failed to convert log_data to SupervisorLog: \n{}\nReceived supervisor log: {:?}", e, log_data.clone());
-                return Err(ApiError::bad_request("Invalid logData structure"));
-            }
-        };
+/// Endpoint to receive and save supervisor logs. The log is sent either as stringified JSON in
+/// the `logData` field of an urlencoded form, or directly as `application/json`. Either way a
+/// JSON array of logs can be sent instead of a single log, to save them with one request. A
+/// batch is saved only if all of its logs are valid.
+pub async fn post_supervisor_log(config: web::Data<Config>, req: HttpRequest, payload: web::Payload) -> Result<impl Responder, ApiError> {
+    let is_json = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|ct| ct.to_ascii_lowercase().starts_with("application/json"))
+        .unwrap_or(false);
 
-        // Convert the timestamp in log data into datetime
-        let timestamp_str = log_data.get("timestamp")
-            .and_then(|v| v.as_str())
-            .unwrap_or("");
-        let timestamp = match DateTime::parse_from_rfc3339(timestamp_str) {
-            Ok(dt) => dt.with_timezone(&Utc),
-            Err(e) => {
-                error!("Failed to parse timestamp: {}", e);
-                return Err(ApiError::bad_request("Invalid timestamp format in logData"));
-            }
+    let log_data: Value = if is_json {
+        web::Json::<Value>::from_request(&req, &mut payload.into_inner())
+            .await
+            .map_err(|e| {
+                error!("Failed to parse log body as JSON: {}", e);
+                ApiError::bad_request("Invalid log JSON")
+            })?
+            .into_inner()
+    } else {
+        let form = Form::<std::collections::HashMap<String, String>>::from_request(&req, &mut payload.into_inner())
+            .await
+            .map_err(|e| ApiError::bad_request(format!("Invalid form: {}", e)))?;
+        let Some(log_data_str) = form.get("logData") else {
+            return Err(ApiError::bad_request("Missing logData field"));
         };
+        serde_json::from_str(log_data_str).map_err(|e| {
+            error!("Failed to parse logData as JSON: {}", e);
+            ApiError::bad_request("Invalid logData JSON")
+        })?
+    };
+    debug!("Received supervisor log: {:?}", log_data);
 
-        // Save the log data in the database in correct format
-        let supervisor_log = SupervisorLog {
-            id: None,
-            device_ip: verified_supervisor_log.device_ip,
-            device_name: verified_supervisor_log.device_name,
-            func_name: verified_supervisor_log.func_name,
-            log_level: verified_supervisor_log.log_level,
-            message: verified_supervisor_log.message,
-            request_id: verified_supervisor_log.request_id,
-            deployment_id: verified_supervisor_log.deployment_id,
-            module_name: verified_supervisor_log.module_name,
-            timestamp,
-            date_received: Utc::now(),
-        };
-        let doc: Document = bson::to_document(&supervisor_log).unwrap();
-        let collection = get_collection::<Document>(COLL_LOGS).await;
-        match collection.insert_one(doc).await {
-            Ok(_) => Ok(HttpResponse::Ok().json(json!({ "message": "Log received and saved" }))),
-            Err(e) => {
-                error!("❌ Failed to insert supervisor log: {}", e);
-                Err(ApiError::internal_error("Log not saved"))
-            }
+    let (entries, batch) = match log_data {
+        Value::Array(entries) => (entries, true),
+        single => (vec![single], false),
+    };
+    if entries.is_empty() {
+        return Err(ApiError::bad_request("No logs given"));
+    }
+    if entries.len() > MAX_LOG_BATCH {
+        return Err(ApiError::bad_request(format!("At most {} logs can be sent at once", MAX_LOG_BATCH)));
+    }
+
+    let mut docs = Vec::with_capacity(entries.len());
+    for (i, entry) in entries.into_iter().enumerate() {
+        let supervisor_log = parse_log_entry(entry).map_err(|e| {
+            if batch { ApiError::bad_request(format!("Log {}: {}", i, e)) } else { ApiError::bad_request(e) }
+        })?;
+        docs.push(bson::to_document(&supervisor_log).map_err(|e| ApiError::internal_error(format!("Log not saved: {}", e)))?);
+    }
+
+    // Save the logs in the database in correct format
+    let count = docs.len();
+    let collection = get_collection::<Document>(&config.database, COLL_LOGS).await;
+    match collection.insert_many(docs).await {
+        Ok(_) if batch => Ok(HttpResponse::Ok().json(json!({ "message": "Logs received and saved", "count": count }))),
+        Ok(_) => Ok(HttpResponse::Ok().json(json!({ "message": "Log received and saved" }))),
+        Err(e) => {
+            error!("❌ Failed to insert supervisor log: {}", e);
+            Err(ApiError::internal_error("Log not saved"))
         }
-    } else {
-        Err(ApiError::bad_request("Missing logData field"))
     }
 }
 
 
+/// Verifies a single log sent by a supervisor and converts it into the format its saved in
+fn parse_log_entry(log_data: Value) -> Result<SupervisorLog, &'static str> {
+    // Verify the log data structure
+    let verified_supervisor_log: LogData = match serde_json::from_value::<LogData>(log_data.clone()) {
+        Ok(log) => log, 
+        Err(e) => {
+            error!("Failed to convert log_data to SupervisorLog: \n{}\nReceived supervisor log: {:?}", e, log_data);
+            return Err("Invalid logData structure");
+        }
+    };
+
+    // Convert the timestamp in log data into datetime
+    let timestamp = match DateTime::parse_from_rfc3339(&verified_supervisor_log.timestamp) {
+        Ok(dt) => dt.with_timezone(&Utc),
+        Err(e) => {
+            error!("Failed to parse timestamp: {}", e);
+            return Err("Invalid timestamp format in logData");
+        }
+    };
+
+    Ok(SupervisorLog {
+        id: None,
+        device_ip: verified_supervisor_log.device_ip,
+        device_name: verified_supervisor_log.device_name,
+        func_name: verified_supervisor_log.func_name,
+        log_level: verified_supervisor_log.log_level,
+        message: verified_supervisor_log.message,
+        request_id: verified_supervisor_log.request_id,
+        deployment_id: verified_supervisor_log.deployment_id,
+        module_name: verified_supervisor_log.module_name,
+        timestamp,
+        date_received: Utc::now(),
+    })
+}
+
+
+/// Sort keys accepted by the supervisor log listings
+pub const LOG_SORT_FIELDS: &[(&str, &str)] = &[
+    ("dateReceived", "dateReceived"),
+    ("deviceName", "deviceName"),
+    ("timestamp", "timestamp"),
+];
+
+
 /// GET /device/logs
 /// 
-/// Endpoint to retrieve supervisor logs with optional filtering 
-pub async fn get_supervisor_logs(query: web::Query<std::collections::HashMap<String, String>>) -> Result<impl Responder, ApiError> {
+/// Endpoint to retrieve supervisor logs with optional filtering. Supports `limit`, `offset`
+/// and `sort` (`dateReceived`, `deviceName`, `timestamp`), see `Pagination`. Filters:
+/// - `deviceName`, `level`, `moduleName`, `deploymentId`: exact matches, several values can
+///   be given separated by commas (levels are matched in any case)
+/// - `requestId`: the logs of a single orchestrator request (the `X-Request-Id` of its
+///   response), sorted by timestamp unless another sort is given
+/// - `after`, `before` (exclusive), `from`, `to` (inclusive): RFC 3339 bounds on when the log
+///   was received
+/// - `search`: words in the message
+pub async fn get_supervisor_logs(config: web::Data<Config>, query: web::Query<std::collections::HashMap<String, String>>) -> Result<impl Responder, ApiError> {
+    let default_sort = query.contains_key("requestId").then(|| doc! { "timestamp": 1 });
+    let pagination = Pagination::from_query(&query, LOG_SORT_FIELDS, default_sort)?;
+    let filter = log_filter(&query)?;
 
-    // Optional time filter
-    let mut filter = doc! {};
-    if let Some(after) = query.get("after") {
-        if let Ok(dt) = DateTime::parse_from_rfc3339(after) {
-            let dt_utc = dt.with_timezone(&Utc);
-            filter = doc! { "dateReceived": { "$gt": mongodb::bson::DateTime::from_chrono(dt_utc) } };
+    let collection = get_collection::<Document>(&config.database, COLL_LOGS).await;
+
+    match find_page(&collection, filter, &pagination).await {
+        Ok(page) => page_response(&page),
+        Err(e) => {
+            error!("❌ Failed to fetch supervisor logs: {}", e);
+            Err(ApiError::internal_error("Failed to fetch logs"))
         }
     }
+}
+
+
+/// Columns of the CSV export, as (header, log field)
+const CSV_COLUMNS: &[(&str, &str)] = &[
+    ("dateReceived", "dateReceived"),
+    ("timestamp", "timestamp"),
+    ("deviceName", "deviceName"),
+    ("deviceIP", "deviceIP"),
+    ("loglevel", "loglevel"),
+    ("funcName", "funcName"),
+    ("moduleName", "module_name"),
+    ("deploymentId", "deployment_id"),
+    ("requestId", "request_id"),
+    ("message", "message"),
+];
+
+
+/// GET /device/logs/export
+///
+/// Downloads the supervisor logs as newline delimited JSON (`format=ndjson`, the default) or as
+/// CSV (`format=csv`). Takes the same filters, `sort` and `limit` as `GET /device/logs`, and is
+/// sorted by `dateReceived` by default. The logs are streamed from the database as they are
+/// written out, so that big exports are not held in memory.
+pub async fn export_supervisor_logs(config: web::Data<Config>, query: web::Query<std::collections::HashMap<String, String>>) -> Result<impl Responder, ApiError> {
+    let csv = match query.get("format").map(String::as_str) {
+        None | Some("ndjson") => false,
+        Some("csv") => true,
+        Some(other) => return Err(ApiError::bad_request(format!("unknown format '{}', expected ndjson or csv", other))),
+    };
+    let pagination = Pagination::from_query(&query, LOG_SORT_FIELDS, Some(doc! { "dateReceived": 1 }))?;
+    let filter = log_filter(&query)?;
+
+    let collection = get_collection::<Document>(&config.database, COLL_LOGS).await;
+    let mut find = collection.find(filter).skip(pagination.offset);
+    if let Some(sort) = pagination.sort {
+        find = find.sort(sort);
+    }
+    if let Some(limit) = pagination.limit {
+        find = find.limit(limit as i64);
+    }
+    let cursor = find.await.map_err(|e| {
+        error!("❌ Failed to export supervisor logs: {}", e);
+        ApiError::internal_error("Failed to export logs")
+    })?;
+
+    let lines = cursor.map(move |doc| {
+        let doc = doc.map_err(|e| {
+            error!("❌ Supervisor log export interrupted: {}", e);
+            actix_web::error::ErrorInternalServerError("log export interrupted")
+        })?;
+        let value = to_normalized_value(&doc).map_err(actix_web::error::ErrorInternalServerError)?;
+        let line = if csv { csv_row(CSV_COLUMNS.iter().map(|(_, field)| csv_field(value.get(field)))) } else { value.to_string() };
+        Ok::<_, actix_web::Error>(web::Bytes::from(line + "\n"))
+    });
+
+    let (extension, content_type) = if csv { ("csv", "text/csv; charset=utf-8") } else { ("ndjson", "application/x-ndjson") };
+    let filename = format!("logs-{}.{}", Utc::now().format("%Y%m%dT%H%M%SZ"), extension);
+    let header = csv.then(|| csv_row(CSV_COLUMNS.iter().map(|(name, _)| name.to_string())) + "\n");
+    let body = futures::stream::iter(header.map(|h| Ok::<_, actix_web::Error>(web::Bytes::from(h)))).chain(lines);
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header((CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename)))
+        .streaming(body))
+}
+
+
+fn csv_field(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(s)) => s.clone(),
+        Some(other) => other.to_string(),
+    }
+}
+
 
-    let collection = get_collection::<Document>(COLL_LOGS).await;
+/// Comma separated row, with fields containing separators, quotes or line breaks quoted
+fn csv_row(fields: impl Iterator<Item = String>) -> String {
+    fields
+        .map(|f| {
+            if f.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", f.replace('"', "\"\""))
+            } else {
+                f
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
 
-    match collection.find(filter).await {
-        Ok(cursor) => {
-            let logs: Vec<Document> = cursor.try_collect().await.unwrap_or_default();
-            let mut v = serde_json::to_value(&logs).map_err(ApiError::internal_error)?;
-            crate::lib::utils::normalize_object_ids(&mut v);
-            Ok(HttpResponse::Ok().json(v))
+
+/// Mongo filter for the query parameters of `GET /device/logs`
+fn log_filter(query: &std::collections::HashMap<String, String>) -> Result<Document, ApiError> {
+    let mut filter = doc! {};
+    for (param, field) in [("deviceName", "deviceName"), ("moduleName", "module_name"), ("deploymentId", "deployment_id")] {
+        if let Some(values) = query.get(param) {
+            filter.insert(field, doc! { "$in": split_values(values) });
         }
-        Err(e) => {
-            error!("❌ Failed to fetch supervisor logs: {}", e);
-            Err(ApiError::internal_error("Failed to fetch logs"))
+    }
+    if let Some(levels) = query.get("level") {
+        // Supervisors dont agree on the case of levels, so each is matched in the usual cases
+        // instead of with a case insensitive regex, which couldnt use the index
+        let variants: Vec<String> = split_values(levels)
+            .iter()
+            .flat_map(|l| {
+                let lower = l.to_lowercase();
+                let mut chars = lower.chars();
+                let capitalized: String = chars.next().map(|c| c.to_uppercase().chain(chars).collect()).unwrap_or_default();
+                [lower.clone(), l.to_uppercase(), capitalized]
+            })
+            .collect();
+        filter.insert("loglevel", doc! { "$in": variants });
+    }
+    if let Some(request_id) = query.get("requestId") {
+        filter.insert("request_id", request_id.as_str());
+    }
+
+    let mut received = doc! {};
+    for (param, op) in [("after", "$gt"), ("before", "$lt"), ("from", "$gte"), ("to", "$lte")] {
+        if let Some(value) = query.get(param) {
+            let dt = DateTime::parse_from_rfc3339(value)
+                .map_err(|_| ApiError::bad_request(format!("{} must be an RFC 3339 timestamp", param)))?;
+            received.insert(op, mongodb::bson::DateTime::from_chrono(dt.with_timezone(&Utc)));
         }
     }
+    if !received.is_empty() {
+        filter.insert("dateReceived", received);
+    }
+
+    if let Some(search) = query.get("search").map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        filter.insert("$text", doc! { "$search": search });
+    }
+    Ok(filter)
 }
 
+
+/// Non-empty values of a comma separated query parameter
+pub(crate) fn split_values(values: &str) -> Vec<String> {
+    values.split(',').map(str::trim).filter(|v| !v.is_empty()).map(str::to_string).collect()
+}
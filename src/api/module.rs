@@ -1,25 +1,34 @@
-use crate::lib::constants::{COLL_MODULE, MODULE_DIR, MOUNT_DIR, WASMIOT_INIT_FUNCTION_NAME};
-use crate::lib::mongodb::{insert_one, get_collection};
-use crate::api::module_cards::{delete_all_module_cards, delete_module_card_by_id};
+use crate::lib::constants::{COLL_DEPLOYMENT, COLL_DEVICE, COLL_MODULE, MODULE_DIR, MOUNT_DIR, WASMIOT_INIT_FUNCTION_NAME};
+use crate::lib::mongodb::{insert_one, get_collection, is_duplicate_key};
 use crate::structs::openapi::{OpenApiDocument, OpenApiEncodingObject, OpenApiFormat, OpenApiInfo, OpenApiMediaTypeObject, OpenApiOperation, OpenApiParameterEnum, OpenApiParameterIn, OpenApiParameterObject, OpenApiPathItemObject, OpenApiRequestBodyObject, OpenApiResponseObject, OpenApiSchemaEnum, OpenApiSchemaObject, OpenApiServerObject, OpenApiServerVariableObject, OpenApiTagObject, OpenApiVersion, RequestBodyEnum, ResponseEnum};
 use actix_web::{web, HttpRequest, HttpResponse, Responder, Result};
 use serde_json::{json, Value, Map};
 use mongodb::bson::{self, Bson, doc, oid::ObjectId, Document};
 use actix_multipart::Multipart;
-use futures_util::stream::StreamExt;
-use futures::stream::TryStreamExt;
+use futures_util::stream::{StreamExt, TryStreamExt};
 use std::io::Write;
 use std::path::Path;
-use log::{error, warn, debug};
+use log::{error, warn, debug, info};
 use serde::{Serialize, Deserialize};
 use std::fs;
 use std::collections::{HashMap, HashSet};
 use actix_files::NamedFile;
 use wasmparser::{ExternalKind, Parser, Payload, TypeRef, ValType as WValType};
 use crate::structs::module::{
-    ModuleDoc, WasmBinaryInfo, WasmExport, WasmRequirement
+    CompatibilityWarning, ModuleDoc, ModuleMetadataUpdate, WasmBinaryInfo, WasmExport, WasmRequirement
 };
+use crate::lib::config::Config;
 use crate::lib::errors::ApiError;
+use crate::api::trash::{trash_module, trashed_files};
+use crate::structs::deployment::DeploymentDoc;
+use crate::lib::response::ok_json;
+use crate::lib::revisions;
+use crate::lib::concurrency::{claim_revision, expected_revision, revision_etag, stale_revision_response, RevisionClaim};
+use crate::lib::utils::escape_regex;
+use mongodb::options::ReturnDocument;
+use crate::lib::pagination::{find_page, Pagination};
+use crate::api::storage::{check_quota, storage_report};
+use actix_web::http::{header::ETAG, StatusCode};
 
 
 // TODO: Module updates (and their notifications if they are already deployed)
@@ -97,7 +106,7 @@ pub struct FunctionSpec {
 /// contain multiple files and fields. It processes the request body, extracts the
 /// separate fields into json, and saves files to disk while adding saved file information
 /// on the returned json as well.
-async fn handle_multipart_request(mut payload: Multipart) -> Result<MultipartSummary, ApiError> {
+async fn handle_multipart_request(config: &Config, mut payload: Multipart) -> Result<MultipartSummary, ApiError> {
 
     // Ensure the module directory exists
     if let Err(e) = std::fs::create_dir_all(MODULE_DIR) {
@@ -110,6 +119,11 @@ async fn handle_multipart_request(mut payload: Multipart) -> Result<MultipartSum
         fields: Vec::new(),
         files: Vec::new(),
     };
+
+    // Bytes used before this request, and bytes written by this request so far (for quota checks)
+    let used_bytes = storage_report(&config.storage).await?.total_bytes;
+    let mut written_bytes = 0u64;
+    check_quota(&config.storage, used_bytes, 0)?;
     while let Some(Ok(mut field)) = payload.next().await {
 
         let mut multipart_field = MultipartField {
@@ -184,6 +198,19 @@ async fn handle_multipart_request(mut payload: Multipart) -> Result<MultipartSum
         };
 
         while let Some(Ok(chunk)) = field.next().await {
+            written_bytes += chunk.len() as u64;
+            if let Err(e) = check_quota(&config.storage, used_bytes, written_bytes) {
+                // Remove everything saved by this request so a rejected upload doesnt use up space
+                drop(f);
+                let mut files_deleted = 0usize;
+                let mut file_errors: Vec<String> = Vec::new();
+                try_delete_file(&filepath, &mut files_deleted, &mut file_errors);
+                for uploaded in &summary.files {
+                    try_delete_file(&uploaded.path, &mut files_deleted, &mut file_errors);
+                }
+                warn!("⚠️ Upload rejected due to storage quota, removed {} partially saved files", files_deleted);
+                return Err(e);
+            }
             if let Err(e) = f.write_all(&chunk) {
                 error!("❌ Failed to write file: {e}");
                 return Err(ApiError::internal_error("Failed to write file to disk."));
@@ -230,20 +257,23 @@ fn module_filter(x: &str) -> Document {
 /// 
 /// Endpoint for creating a new module. Extracts the description and wasm module
 /// from the request body, and returns the id of the newly created module entry.
-pub async fn create_module(payload: Multipart) -> Result<impl Responder, ApiError> {
+pub async fn create_module(config: web::Data<Config>, payload: Multipart) -> Result<impl Responder, ApiError> {
     // Ensure the target directory exists
     if let Err(e) = std::fs::create_dir_all(MODULE_DIR) {
         error!("❌ Failed to create module directory: {e}");
         return Err(ApiError::internal_error("Failed to create module directory"));
     }
 
-    let summary = match handle_multipart_request(payload).await {
+    let summary = match handle_multipart_request(&config, payload).await {
         Ok(s) => s,
+        Err(e) if e.status == StatusCode::INSUFFICIENT_STORAGE => return Err(e),
         Err(e) => {
             error!("❌ Failed to process multipart request: {}", e);
             return Err(ApiError::internal_error("Failed to process multipart request"));
         }
     };
+    // Remove the saved files if creating the module fails from here on
+    let uploads = UploadedFilesGuard::new(&summary);
 
     // Get the first file that is a wasm module
     let wasm_upload = match summary.files.iter().find(|f| f.mimetype == "application/wasm") {
@@ -289,20 +319,29 @@ pub async fn create_module(payload: Multipart) -> Result<impl Responder, ApiErro
         description: None,
         mounts: None,
         is_core_module: false,
+        compatibility_warnings: None,
+        missing_files: None,
+        tags: Vec::new(),
+        description_text: None,
+        revision: 0,
     };
 
     let wasm_document = bson::to_document(&wasm_doc).unwrap();
     debug!("📄 Final module document before saving:\n{:?}", wasm_document);
     // Save the document to the database
-    let inserted_id = insert_one(COLL_MODULE, &wasm_document).await;
+    let inserted_id = insert_one(&config.database, COLL_MODULE, &wasm_document).await;
     let module_id = match inserted_id {
         Ok(Bson::ObjectId(id)) => id,
+        Err(e) if is_duplicate_key(&e) => {
+            return Err(ApiError::conflict(format!("a module named '{}' already exists", wasm_doc.name)));
+        }
         _ => {
             error!("❌ Failed to convert the id returned by mongodb into an objectId: {:?}", inserted_id);
             return Err(ApiError::db("Database failure, check server logs"));
         }
     };
     debug!("✅ Module document saved to database, _id={:?}", module_id);    
+    uploads.keep(&[wasm_doc.wasm.path.as_str()]);
 
     Ok(HttpResponse::Created().json(json!({ "id": module_id.to_hex() })))
 
@@ -462,18 +501,6 @@ fn wasmparser_valtype(t: &WValType) -> String {
 }
 
 
-/// Helper function for collecting paths to all mounted files related to a single module
-fn collect_datafile_paths(doc: &ModuleDoc) -> Vec<String> {
-    let mut out = Vec::new();
-    if let Some(data_files) = &doc.data_files {
-        for (_k, v) in data_files.iter() {
-            out.push(v.path.clone());
-        }
-    }
-    out
-}
-
-
 /// Helper function for deleting files related to a single module
 fn try_delete_file(path: &str, files_deleted: &mut usize, file_errors: &mut Vec<String>) {
     match fs::remove_file(path) {
@@ -492,94 +519,199 @@ fn try_delete_file(path: &str, files_deleted: &mut usize, file_errors: &mut Vec<
 }
 
 
-/// Helper function for deleting all files in a single folder 
-/// (for purposes of deleting all modules and their files)
-fn delete_all_files_in_dir(dir: &str) -> (usize, Vec<String>) {
-    let mut deleted = 0usize;
-    let mut errors = Vec::new();
+/// Removes the files saved by `handle_multipart_request` when dropped, unless they are kept
+/// with `keep`. Used so that a request failing after its files were saved (for example when
+/// saving the module document fails) doesnt leave them stranded on disk.
+struct UploadedFilesGuard {
+    paths: Vec<String>,
+}
 
-    // Get every item in a given directory
-    let path = Path::new(dir);
-    let entries = match fs::read_dir(path) {
-        Ok(it) => it,
-        Err(e) => {
-            if e.kind() != std::io::ErrorKind::NotFound {
-                errors.push(format!("read_dir('{}'): {}", dir, e));
+impl UploadedFilesGuard {
+    fn new(summary: &MultipartSummary) -> Self {
+        Self { paths: summary.files.iter().map(|f| f.path.clone()).collect() }
+    }
+
+    /// Keeps the given files, and removes the rest of the saved files (not used by the module)
+    fn keep(mut self, paths: &[&str]) {
+        self.paths.retain(|p| !paths.contains(&p.as_str()));
+    }
+}
+
+impl Drop for UploadedFilesGuard {
+    fn drop(&mut self) {
+        let mut files_deleted = 0usize;
+        let mut file_errors: Vec<String> = Vec::new();
+        for path in &self.paths {
+            try_delete_file(path, &mut files_deleted, &mut file_errors);
+        }
+        if files_deleted > 0 {
+            debug!("🗑️ Removed {} uploaded files not used by any module", files_deleted);
+        }
+    }
+}
+
+
+/// Checks at startup that the files of every module exist, and removes files in the module
+/// and mount directories that no module refers to (left behind by uploads interrupted by a
+/// crash). A module whose file isnt at its stored path but is found in the module or mount
+/// directory by its file name gets its path repaired, and modules with files still missing
+/// are flagged with `missingFiles` (cleared once the files are back), so they cant be deployed.
+pub async fn check_module_consistency(config: &Config) {
+    let coll = get_collection::<ModuleDoc>(&config.database, COLL_MODULE).await;
+    let modules: Vec<ModuleDoc> = match coll.find(doc! {}).await {
+        Ok(cursor) => match cursor.try_collect().await {
+            Ok(m) => m,
+            Err(e) => {
+                error!("❌ Module consistency check failed to read modules: {}", e);
+                return;
             }
-            return (deleted, errors);
+        },
+        Err(e) => {
+            error!("❌ Module consistency check failed to read modules: {}", e);
+            return;
         }
     };
+    // Paths are compared in canonical form, since they may be stored relative or absolute
+    let canonical = |p: &Path| fs::canonicalize(p).unwrap_or_else(|_| p.to_path_buf());
+
+    let mut referenced: HashSet<std::path::PathBuf> = HashSet::new();
+    // Files of deleted modules are kept until the trash is purged
+    let trash_read = match trashed_files(config).await {
+        Ok(files) => {
+            referenced.extend(files.iter().map(|f| canonical(Path::new(f))));
+            true
+        }
+        Err(e) => {
+            error!("❌ Module consistency check failed to read the trash, not removing unreferenced files: {}", e);
+            false
+        }
+    };
+    let (mut repaired, mut flagged) = (0usize, 0usize);
+    for module in &modules {
+        let Some(id) = module.id else { continue };
+        let mut set = Document::new();
+        let mut missing: Vec<String> = Vec::new();
+
+        let mut files: Vec<(String, &str, &str)> = vec![("wasm.path".to_string(), module.wasm.path.as_str(), module.wasm.file_name.as_str())];
+        for (key, f) in module.data_files.iter().flatten() {
+            files.push((format!("dataFiles.{}.path", key), f.path.as_str(), f.file_name.as_str()));
+        }
+        for (field, path, file_name) in files {
+            if Path::new(path).is_file() {
+                referenced.insert(canonical(Path::new(path)));
+                continue;
+            }
+            let found = [MODULE_DIR, MOUNT_DIR]
+                .iter()
+                .map(|dir| Path::new(dir).join(file_name))
+                .find(|p| !file_name.is_empty() && p.is_file());
+            match found {
+                Some(p) => {
+                    warn!("🔧 Module '{}' file '{}' found at '{}', repairing its path", module.name, path, p.display());
+                    set.insert(field, p.to_string_lossy().to_string());
+                    referenced.insert(canonical(&p));
+                }
+                None => missing.push(path.to_string()),
+            }
+        }
 
-    // Iterate over each item, deleting them if they are files (but not if they are folders etc)
-    for entry in entries {
-        let entry = match entry {
-            Ok(e) => e,
-            Err(e) => { errors.push(format!("iterating '{}': {}", dir, e)); continue; }
-        };
-
-        let p = entry.path();
-        let file_type = match entry.file_type() {
-            Ok(t) => t,
-            Err(e) => { errors.push(format!("file_type '{}': {}", p.display(), e)); continue; }
-        };
+        let mut update = Document::new();
+        if !missing.is_empty() {
+            warn!("⚠️ Module '{}' is missing files: {:?}", module.name, missing);
+            set.insert("missingFiles", missing);
+            flagged += 1;
+        } else if module.missing_files.is_some() {
+            update.insert("$unset", doc! { "missingFiles": "" });
+        }
+        if !set.is_empty() {
+            repaired += 1;
+            update.insert("$set", set);
+        }
+        if update.is_empty() {
+            continue;
+        }
+        if let Err(e) = coll.update_one(doc! { "_id": id }, update).await {
+            error!("❌ Failed to update module '{}' after consistency check: {}", module.name, e);
+        }
+    }
+    if repaired > 0 || flagged > 0 {
+        revisions::bump(COLL_MODULE);
+    }
 
-        if file_type.is_file() || file_type.is_symlink() {
-            match fs::remove_file(&p) {
-                Ok(()) => { debug!("🗑️ deleted {}", p.display()); deleted += 1; }
-                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-                    debug!("already missing (ok): {}", p.display());
-                }
-                Err(e) => { errors.push(format!("remove_file '{}': {}", p.display(), e)); }
+    // Remove files that no module refers to
+    let mut orphans_removed = 0usize;
+    let mut file_errors: Vec<String> = Vec::new();
+    for dir in [MODULE_DIR, MOUNT_DIR].into_iter().filter(|_| trash_read) {
+        let Ok(entries) = fs::read_dir(dir) else { continue };
+        for entry in entries.flatten() {
+            let p = entry.path();
+            if p.is_file() && !referenced.contains(&canonical(&p)) {
+                warn!("🗑️ Removing '{}', no module refers to it", p.display());
+                try_delete_file(&p.to_string_lossy(), &mut orphans_removed, &mut file_errors);
             }
-        } else {
-            debug!("skipping non-file in {}: {}", dir, p.display());
         }
     }
 
-    (deleted, errors)
+    info!(
+        "... Module consistency check done: {} modules, {} updated, {} with missing files, {} unreferenced files removed.",
+        modules.len(), repaired, flagged, orphans_removed
+    );
 }
 
 
 /// DELETE /file/module
 /// 
-/// Endpoint for deleting all modules. Also removes related modulecards, wasm modules and mounted files.
-pub async fn delete_all_modules() -> Result<impl Responder, ApiError> {
-
-    // Delete all module docs from database
-    let coll = get_collection::<ModuleDoc>(COLL_MODULE).await;
-    let deleted = match coll.delete_many(doc! {}).await {
-        Ok(res) => res.deleted_count,
+/// Endpoint for deleting all modules. The modules are moved to the trash with their module
+/// cards, and their wasm modules and mounted files are removed when the trash is purged.
+pub async fn delete_all_modules(config: web::Data<Config>) -> Result<impl Responder, ApiError> {
+    let modules: Vec<ModuleDoc> = match get_collection::<ModuleDoc>(&config.database, COLL_MODULE).await.find(doc! {}).await {
+        Ok(cursor) => cursor.try_collect().await.map_err(ApiError::db)?,
         Err(e) => {
-            error!("Failed to delete module documents: {e}");
-            return Err(ApiError::internal_error("Failed to delete module documents"));
+            error!("Failed to read module documents: {e}");
+            return Err(ApiError::internal_error("Failed to read module documents"));
         }
     };
 
-    // Delete all wasm files and mounted files
-    let (wasm_deleted, mut wasm_errs) = delete_all_files_in_dir(MODULE_DIR);
-    debug!("wasm files deleted: {}, errors: {:?}", wasm_deleted, wasm_errs);
-    let (mounts_deleted, mounts_errs) = delete_all_files_in_dir(MOUNT_DIR);
-    debug!("mount files deleted: {}, errors: {:?}", mounts_deleted, mounts_errs);
-    wasm_errs.extend(mounts_errs);
-
-    // Delete all module cards
-    let _ = delete_all_module_cards().await;
+    // Each module goes to the trash with its card, files are kept until purged
+    let mut deleted = 0u64;
+    let mut errors: Vec<String> = Vec::new();
+    for module in &modules {
+        match trash_module(&config, module).await {
+            Ok(()) => deleted += 1,
+            Err(e) => {
+                error!("Failed to delete module '{}': {}", module.name, e);
+                errors.push(format!("{}: {}", module.name, e));
+            }
+        }
+    }
 
     Ok(HttpResponse::Ok().json(json!({
-        "message": "Deleted all modules",
+        "message": "Moved all modules to the trash",
         "docs_deleted": deleted,
-        "files_deleted": wasm_deleted + mounts_deleted,
-        "file_errors": wasm_errs
+        "errors": errors
     })))
 }
 
 
 /// DELETE /file/module/{module_id}
 /// 
-/// Deletes a single module by its id or name. Also removes all files related to it.
-pub async fn delete_module_by_id(path: web::Path<String>) -> Result<impl Responder, ApiError> {
+/// Deletes a single module by its id or name, moving it to the trash with its module card.
+/// Its files are removed when the trash is purged. Responds with 409 and the deployments that
+/// use the module if there are any. With `force=true` the module is deleted anyway and those
+/// deployments are deactivated.
+pub async fn delete_module_by_id(
+    config: web::Data<Config>,
+    path: web::Path<String>,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, ApiError> {
     let key = path.into_inner();
-    let coll = get_collection::<ModuleDoc>(COLL_MODULE).await;
+    let force = match query.get("force") {
+        Some(v) => v
+            .parse::<bool>()
+            .map_err(|_| ApiError::bad_request(format!("invalid value for force '{}', expected true or false", v)))?,
+        None => false,
+    };
+    let coll = get_collection::<ModuleDoc>(&config.database, COLL_MODULE).await;
 
     // Get the module document
     let filter = module_filter(&key);
@@ -605,71 +737,169 @@ pub async fn delete_module_by_id(path: web::Path<String>) -> Result<impl Respond
         }
     };
 
-    // Delete related module card if id was found
-    if !module_oid_hex.is_empty() {
-        let _ = delete_module_card_by_id(web::Path::<String>::from(module_oid_hex.clone())).await;
+    // Deployments that use the module would fail to download it
+    let dependents: Vec<Document> = get_collection::<Document>(&config.database, COLL_DEPLOYMENT).await
+        .find(doc! { "sequence.module": doc.id })
+        .projection(doc! { "_id": 1, "name": 1, "active": 1 })
+        .await
+        .map_err(ApiError::db)?
+        .try_collect()
+        .await
+        .map_err(ApiError::db)?;
+    let deployments: Vec<Value> = dependents
+        .iter()
+        .map(|d| json!({
+            "deploymentId": d.get_object_id("_id").map(|id| id.to_hex()).ok(),
+            "name": d.get_str("name").unwrap_or_default(),
+            "active": d.get_bool("active").unwrap_or(false),
+        }))
+        .collect();
+    if !dependents.is_empty() && !force {
+        return Ok(HttpResponse::Conflict().json(json!({
+            "error": format!("conflict: module '{}' is used by {} deployments, delete with force=true to deactivate them", doc.name, deployments.len()),
+            "deployments": deployments,
+        })));
     }
-
-    // Delete all files related to the module
-    let wasm_path = doc.wasm.path.clone();
-    let mut files_deleted = 0usize;
-    let mut file_errors: Vec<String> = Vec::new();
-    try_delete_file(&wasm_path, &mut files_deleted, &mut file_errors);
-    for p in collect_datafile_paths(&doc) {
-        try_delete_file(&p, &mut files_deleted, &mut file_errors);
+    if !dependents.is_empty() {
+        let ids: Vec<Bson> = dependents.iter().filter_map(|d| d.get_object_id("_id").ok()).map(Bson::ObjectId).collect();
+        get_collection::<Document>(&config.database, COLL_DEPLOYMENT).await
+            .update_many(doc! { "_id": { "$in": ids }, "active": true }, doc! { "$set": { "active": false } })
+            .await
+            .map_err(ApiError::db)?;
+        revisions::bump(COLL_DEPLOYMENT);
+        warn!("Deactivated {} deployments that used deleted module '{}'", dependents.len(), doc.name);
     }
 
-    // Delete the module doc
-    match coll.delete_one(filter).await {
-        Ok(res) if res.deleted_count == 1 => Ok(HttpResponse::Ok().json(json!({
-            "message":"Module deleted",
-            "query": key,
-            "files_deleted": files_deleted,
-            "file_errors": file_errors
-        }))),
-        Ok(_) => Err(ApiError::not_found(format!("Module not found during delete, query: {}", key))),
-        Err(e) => {
-            error!("Failed to delete module doc '{}': {}", key, e);
-            Err(ApiError::internal_error(format!("Failed to delete module document, query: {}", key)))
-        }
-    }
+    // The module, its card and files are kept in the trash until purged
+    trash_module(&config, &doc).await.map_err(|e| {
+        error!("Failed to delete module '{}': {}", key, e);
+        ApiError::internal_error(format!("Failed to delete module, query: {}", key))
+    })?;
+    Ok(HttpResponse::Ok().json(json!({
+        "message": "Module moved to the trash",
+        "query": key,
+        "moduleId": module_oid_hex,
+        "deactivatedDeployments": deployments
+    })))
 }
 
 
 /// GET /file/module
 /// 
-/// Endpoint for getting all module docs from database
-pub async fn get_all_modules() -> Result<impl Responder, ApiError> {
-    let coll = get_collection::<ModuleDoc>(COLL_MODULE).await;
-    let mut cursor = match coll.find(doc! {}).await {
-        Ok(c) => c,
-        Err(e) => {
+/// Endpoint for getting all module docs from database. Supports `limit`, `offset`
+/// and `sort` (`name`), see `Pagination`, and the filters
+/// - `tag`: only modules with the tag, or with all of a comma separated list of tags
+/// - `exportsFunction`: only modules that export a function with the given name
+/// - `name`: only modules whose name contains the given string (case insensitive)
+pub async fn get_all_modules(config: web::Data<Config>, req: HttpRequest, query: web::Query<HashMap<String, String>>) -> Result<impl Responder, ApiError> {
+    let pagination = Pagination::from_query(&query, &[("name", "name")], None)?;
+    let filter = module_query_filter(&query);
+    revisions::cached_json(&req, &[COLL_MODULE], || async move {
+        let coll = get_collection::<ModuleDoc>(&config.database, COLL_MODULE).await;
+        find_page(&coll, filter, &pagination).await.map_err(|e| {
             error!("Error querying modules: {}", e);
-            return Err(ApiError::db(format!("Error querying modules: {}", e)));
+            e
+        })
+    }).await
+}
+
+
+/// Builds the database filter for the module listing query parameters
+fn module_query_filter(query: &HashMap<String, String>) -> Document {
+    let mut filter = doc! {};
+    if let Some(tags) = query.get("tag") {
+        let tags = normalize_tags(tags.split(','));
+        if !tags.is_empty() {
+            filter.insert("tags", doc! { "$all": tags });
         }
-    };
-    let mut out: Vec<ModuleDoc> = Vec::new();
-    while let Some(doc) = cursor.try_next().await.map_err(ApiError::db)? {
-        out.push(doc);
     }
-    let mut v = serde_json::to_value(&out).map_err(ApiError::internal_error)?;
-    crate::lib::utils::normalize_object_ids(&mut v);
-    Ok(HttpResponse::Ok().json(v))
+    if let Some(function) = query.get("exportsFunction") {
+        filter.insert("exports.name", function);
+    }
+    if let Some(name) = query.get("name") {
+        filter.insert("name", doc! { "$regex": escape_regex(name), "$options": "i" });
+    }
+    filter
+}
+
+
+/// Trims the tags and drops empty and duplicate ones, keeping the order
+fn normalize_tags<'a>(tags: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    tags.into_iter()
+        .map(str::trim)
+        .filter(|t| !t.is_empty() && seen.insert(t.to_string()))
+        .map(str::to_string)
+        .collect()
+}
+
+
+/// PATCH /file/module/{module_id}
+/// 
+/// Updates the tags and the description text of a module (by id or name). Fields left out of
+/// the body are kept, an empty `descriptionText` removes the description. Returns the updated
+/// module. The revision read by the client can be sent in If-Match, see lib/concurrency.rs.
+pub async fn update_module_metadata(
+    config: web::Data<Config>,
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<ModuleMetadataUpdate>,
+) -> Result<impl Responder, ApiError> {
+    let key = path.into_inner();
+    let update = body.into_inner();
+    let expected = expected_revision(&req)?;
+    let mut set_doc = doc! {};
+    let mut unset_doc = doc! {};
+    if let Some(tags) = &update.tags {
+        set_doc.insert("tags", normalize_tags(tags.iter().map(String::as_str)));
+    }
+    match update.description_text.as_deref().map(str::trim) {
+        Some("") => { unset_doc.insert("descriptionText", ""); }
+        Some(text) => { set_doc.insert("descriptionText", text); }
+        None => {}
+    }
+    if set_doc.is_empty() && unset_doc.is_empty() {
+        return Err(ApiError::bad_request("nothing to update, expected tags or descriptionText"));
+    }
+
+    let mut update_doc = doc! {};
+    if !set_doc.is_empty() {
+        update_doc.insert("$set", set_doc);
+    }
+    if !unset_doc.is_empty() {
+        update_doc.insert("$unset", unset_doc);
+    }
+    let coll = get_collection::<ModuleDoc>(&config.database, COLL_MODULE).await;
+    let module_id = coll
+        .find_one(module_filter(&key))
+        .await
+        .map_err(ApiError::db)?
+        .and_then(|m| m.id)
+        .ok_or_else(|| ApiError::not_found(format!("Module not found for query: {}", key)))?;
+    if let RevisionClaim::Stale { current } = claim_revision(&config, COLL_MODULE, &module_id, expected).await? {
+        return Ok(stale_revision_response(expected.unwrap_or_default(), current));
+    }
+    let updated = coll
+        .find_one_and_update(doc! { "_id": module_id }, update_doc)
+        .return_document(ReturnDocument::After)
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("Module not found for query: {}", key)))?;
+    revisions::bump(COLL_MODULE);
+    Ok(HttpResponse::Ok().insert_header((ETAG, revision_etag(updated.revision))).json(&updated))
 }
 
 
 /// GET /file/module/{module_id}
 /// 
 /// Endpoint for getting one module doc by its name/id from database.
-pub async fn get_module_by_id(path: web::Path<String>) -> Result<impl Responder, ApiError> {
+pub async fn get_module_by_id(config: web::Data<Config>, path: web::Path<String>) -> Result<impl Responder, ApiError> {
     let id_str = path.into_inner();
-    let coll = get_collection::<ModuleDoc>(COLL_MODULE).await;
+    let coll = get_collection::<ModuleDoc>(&config.database, COLL_MODULE).await;
     let filter = module_filter(&id_str);
     match coll.find_one(filter).await {
         Ok(Some(doc)) => {
-            let mut v = serde_json::to_value(&doc).map_err(ApiError::internal_error)?;
-            crate::lib::utils::normalize_object_ids(&mut v);
-            Ok(HttpResponse::Ok().json(vec![v]))
+            ok_json(&vec![doc])
         }
         Ok(None) => Ok(HttpResponse::Ok().json(Vec::<Document>::new())), // []
         Err(e) => Ok(HttpResponse::InternalServerError().json(json!({
@@ -680,15 +910,96 @@ pub async fn get_module_by_id(path: web::Path<String>) -> Result<impl Responder,
 }
 
 
+/// GET /file/module/{module_id}/usage
+/// 
+/// Lists the deployments that use the module (by id or name), with the devices and functions
+/// of the module in each, whether they are active and when they were last executed.
+pub async fn get_module_usage(config: web::Data<Config>, path: web::Path<String>) -> Result<impl Responder, ApiError> {
+    let key = path.into_inner();
+    let module = get_collection::<ModuleDoc>(&config.database, COLL_MODULE).await
+        .find_one(module_filter(&key))
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("Module not found for query: {}", key)))?;
+    let Some(module_id) = module.id else {
+        return Err(ApiError::internal_error("Module document missing valid id!"));
+    };
+
+    let deployments: Vec<DeploymentDoc> = get_collection::<DeploymentDoc>(&config.database, COLL_DEPLOYMENT).await
+        .find(doc! { "sequence.module": module_id })
+        .await
+        .map_err(ApiError::db)?
+        .try_collect()
+        .await
+        .map_err(ApiError::db)?;
+    // Names of the devices, for readability
+    let device_ids: Vec<ObjectId> = deployments.iter()
+        .flat_map(|d| d.sequence.iter().filter(|s| s.module == module_id).map(|s| s.device))
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    let device_names: HashMap<ObjectId, String> = get_collection::<Document>(&config.database, COLL_DEVICE).await
+        .find(doc! { "_id": { "$in": device_ids } })
+        .projection(doc! { "name": 1 })
+        .await
+        .map_err(ApiError::db)?
+        .try_collect::<Vec<Document>>()
+        .await
+        .map_err(ApiError::db)?
+        .into_iter()
+        .filter_map(|d| Some((d.get_object_id("_id").ok()?, d.get_str("name").ok()?.to_string())))
+        .collect();
+
+    let mut active_count = 0;
+    let usage: Vec<Value> = deployments.iter().map(|deployment| {
+        let active = deployment.active.unwrap_or(false);
+        if active {
+            active_count += 1;
+        }
+        // Functions of the module by device, in the order of the sequence
+        let mut devices: Vec<(ObjectId, Vec<String>)> = Vec::new();
+        for step in deployment.sequence.iter().filter(|s| s.module == module_id) {
+            match devices.iter_mut().find(|(id, _)| *id == step.device) {
+                Some((_, funcs)) => funcs.push(step.func.clone()),
+                None => devices.push((step.device, vec![step.func.clone()])),
+            }
+        }
+        json!({
+            "deploymentId": deployment.id.map(|id| id.to_hex()),
+            "name": deployment.name,
+            "active": active,
+            "lastExecutedAt": deployment.last_executed_at.and_then(|t| t.try_to_rfc3339_string().ok()),
+            "devices": devices.iter().map(|(id, funcs)| json!({
+                "deviceId": id.to_hex(),
+                "name": device_names.get(id),
+                "functions": funcs,
+            })).collect::<Vec<_>>(),
+        })
+    }).collect();
+
+    Ok(HttpResponse::Ok().json(json!({
+        "moduleId": module_id.to_hex(),
+        "name": module.name,
+        "deploymentCount": usage.len(),
+        "activeDeploymentCount": active_count,
+        "deployments": usage,
+    })))
+}
+
+
 /// POST /file/module/{module_id}/upload
 /// 
 /// Endpoint that takes the module description as an html form (multipart request), and
 /// creates an openapi documentation for the related module from that. 
-/// Note that this expects the form to have a very specific format.
+/// Note that this expects the form to have a very specific format. The revision read by the
+/// client can be sent in If-Match, see lib/concurrency.rs.
 pub async fn describe_module(
+    config: web::Data<Config>,
+    req: HttpRequest,
     path: web::Path<String>,
     payload: Multipart,
 ) -> Result<impl Responder, ApiError> {
+    let expected = expected_revision(&req)?;
 
     // TODO: Switch to using json instead of multipart for sending descriptions. That way you can have some clear
     // definition of what the description should contain (easy to update etc).
@@ -697,19 +1008,22 @@ pub async fn describe_module(
 
     // Get the multipart summary first. This is helpful because handle_multipart_request
     // handles correctly saving files that came with the request.
-    let summary = match handle_multipart_request(payload).await {
+    let summary = match handle_multipart_request(&config, payload).await {
         Ok(s) => s,
+        Err(e) if e.status == StatusCode::INSUFFICIENT_STORAGE => return Err(e),
         Err(e) => {
             error!("❌ multipart handling failed: {e}");
             return Err(ApiError::internal_error("Failed to process multipart"));
         }
     };
+    // Remove the saved files if describing the module fails from here on
+    let uploads = UploadedFilesGuard::new(&summary);
 
     // After handling the incoming multipart request, find the module that the mounts and description
     // that were sent with the request are related to. Fail miserably if the module is not found.
     let key = path.into_inner();
     let filter = module_filter(&key);
-    let coll = get_collection::<ModuleDoc>(COLL_MODULE).await;
+    let coll = get_collection::<ModuleDoc>(&config.database, COLL_MODULE).await;
     let module_doc = match coll.find_one(filter.clone()).await {
         Ok(Some(d)) => d,
         Ok(None) => return Err(ApiError::not_found("Module not found")),
@@ -719,6 +1033,13 @@ pub async fn describe_module(
         }
     };
     let module_name = module_doc.name.clone();
+    let module_id = module_doc
+        .id
+        .ok_or_else(|| ApiError::internal_error(format!("module '{}' has no id", module_name)))?;
+    let revision = match claim_revision(&config, COLL_MODULE, &module_id, expected).await? {
+        RevisionClaim::Claimed(revision) => revision,
+        RevisionClaim::Stale { current } => return Ok(stale_revision_response(expected.unwrap_or_default(), current)),
+    };
 
     // Parse the description field by field
     let description_json = {
@@ -910,6 +1231,12 @@ pub async fn describe_module(
 
     // -------------- End of multipart/description parsing -----------------
 
+    // Check that the described functions can actually be called with the described parameters
+    let compatibility_warnings = check_function_compatibility(&functions, &module_doc.exports);
+    for w in &compatibility_warnings {
+        warn!("⚠️ Module '{}': {}", module_name, w.message);
+    }
+
     // TODO: When you switch away from multipart requests, change this part too.
     // Generate a listing of all datafiles related to this module
     let mut update_doc = Document::new();
@@ -931,6 +1258,8 @@ pub async fn describe_module(
     let openapi_json = module_endpoint_descriptions(&module_name, &functions);
     let description_doc: Document = bson::to_document(&openapi_json).unwrap_or_else(|_| Document::new());
     update_doc.insert("description", Bson::Document(description_doc));
+    let warnings_bson = bson::to_bson(&compatibility_warnings).unwrap_or(Bson::Array(vec![]));
+    update_doc.insert("compatibilityWarnings", warnings_bson);
 
     // Update the entry related to the current module with the openapi description, mount listing and datafile list.
     let update = doc! { "$set": update_doc };
@@ -938,7 +1267,70 @@ pub async fn describe_module(
         error!("Failed to update module with mounts/description: {e}");
         return Err(ApiError::db("update failed"));
     }
-    Ok(HttpResponse::Ok().json(json!({ "description": openapi_json })))
+    revisions::bump(COLL_MODULE);
+    let data_file_paths: Vec<&str> = summary.files.iter().filter(|f| f.mimetype != "application/wasm").map(|f| f.path.as_str()).collect();
+    uploads.keep(&data_file_paths);
+    Ok(HttpResponse::Ok()
+        .insert_header((ETAG, revision_etag(revision)))
+        .json(json!({ "description": openapi_json, "compatibilityWarnings": compatibility_warnings, "revision": revision })))
+}
+
+
+/// Compares the described parameters of each function against the wasm export of the same name.
+/// Supervisors pass the described parameters as the call arguments, so a count mismatch (or a
+/// missing export) makes the call fail and is blocking. Type mismatches only produce warnings,
+/// since supervisors convert the arguments before calling.
+fn check_function_compatibility(
+    functions: &HashMap<String, FunctionSpec>,
+    exports: &[WasmExport],
+) -> Vec<CompatibilityWarning> {
+    let mut warnings = Vec::new();
+    let mut names: Vec<&String> = functions.keys().collect();
+    names.sort();
+    for name in names {
+        let spec = &functions[name];
+        let declared = spec.parameters.len();
+        let Some(export) = exports.iter().find(|e| &e.name == name) else {
+            warnings.push(CompatibilityWarning {
+                function: name.clone(),
+                declared_params: declared,
+                export_params: None,
+                blocking: true,
+                message: format!("function '{}' is described but not exported by the wasm module", name),
+            });
+            continue;
+        };
+        if export.parameter_count != declared {
+            warnings.push(CompatibilityWarning {
+                function: name.clone(),
+                declared_params: declared,
+                export_params: Some(export.parameter_count),
+                blocking: true,
+                message: format!(
+                    "function '{}' is described with {} parameter(s) but the wasm export takes {}",
+                    name, declared, export.parameter_count
+                ),
+            });
+            continue;
+        }
+        for (param, wasm_ty) in spec.parameters.iter().zip(&export.params) {
+            let integer_to_float = param.ty == "integer" && (wasm_ty == "f32" || wasm_ty == "f64");
+            let number_to_int = param.ty == "number" && (wasm_ty == "i32" || wasm_ty == "i64");
+            if integer_to_float || number_to_int {
+                warnings.push(CompatibilityWarning {
+                    function: name.clone(),
+                    declared_params: declared,
+                    export_params: Some(export.parameter_count),
+                    blocking: false,
+                    message: format!(
+                        "parameter '{}' of function '{}' is described as {} but the wasm export takes {}",
+                        param.name, name, param.ty, wasm_ty
+                    ),
+                });
+            }
+        }
+    }
+    warnings
 }
 
 
@@ -1206,17 +1598,15 @@ fn functions_output_mount_mediatype(mounts: &std::collections::HashMap<String, M
 /// GET /file/module/{module_id}/description
 /// 
 /// Endpoint for getting a modules description by its id/name
-pub async fn get_module_description_by_id(path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+pub async fn get_module_description_by_id(config: web::Data<Config>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
     let id_str = path.into_inner();
-    let coll = get_collection::<ModuleDoc>(COLL_MODULE).await;
+    let coll = get_collection::<ModuleDoc>(&config.database, COLL_MODULE).await;
     let filter = module_filter(&id_str);
     match coll.find_one(filter).await {
         Ok(Some(doc)) => {
             match &doc.description {
                 Some(desc) => {
-                    let mut v = serde_json::to_value(&desc).map_err(ApiError::internal_error)?;
-                    crate::lib::utils::normalize_object_ids(&mut v);
-                    Ok(HttpResponse::Ok().json(v))
+                    ok_json(&desc)
                 },
                 None       => Ok(HttpResponse::Ok().json(serde_json::Value::Object(serde_json::Map::new()))),
             }
@@ -1235,11 +1625,12 @@ pub async fn get_module_description_by_id(path: web::Path<String>) -> Result<Htt
 /// The name must match the key for that file in the database, not the actual filename it has
 /// in the filesystem. For module, accepts either modules id, or its name.
 pub async fn get_module_datafile(
+    config: web::Data<Config>,
     _req: HttpRequest,
     path: web::Path<(String, String)>,
 ) -> Result<NamedFile, ApiError> {
     let (id_str, datafile_key) = path.into_inner();
-    let coll = get_collection::<ModuleDoc>(COLL_MODULE).await;
+    let coll = get_collection::<ModuleDoc>(&config.database, COLL_MODULE).await;
     let filter = module_filter(&id_str);
 
     // Load module doc
@@ -1283,11 +1674,12 @@ pub async fn get_module_datafile(
 /// 
 /// Endpoint for returning a wasm module (the binary file itself) by a modules id or name
 pub async fn get_module_wasm(
+    config: web::Data<Config>,
     _req: HttpRequest,
     path: web::Path<String>,
 ) -> Result<NamedFile> {
     let id_str = path.into_inner();
-    let coll = get_collection::<ModuleDoc>(COLL_MODULE).await;
+    let coll = get_collection::<ModuleDoc>(&config.database, COLL_MODULE).await;
     let filter = module_filter(&id_str);
 
     // Get the path to the module
@@ -3,85 +3,70 @@ use bson::oid::ObjectId;
 use serde_json::{Value, json};
 use chrono::{DateTime, Utc};
 use mongodb::bson::doc;
-use crate::lib::mongodb::get_collection;
-use futures::stream::TryStreamExt;
+use mongodb::options::ReturnDocument;
+use serde::Deserialize;
+use crate::lib::mongodb::{find_one, get_collection, is_duplicate_key};
 use log::{debug, info, error};
 use crate::structs::module_cards::ModuleCard;
+use crate::structs::module::ModuleDoc;
 use crate::lib::errors::ApiError;
-use crate::lib::constants::COLL_MODULE_CARDS;
+use crate::api::revalidation::{trigger_revalidation, RevalidationScope};
+use crate::lib::response::ok_json;
+use crate::lib::pagination::{find_page, page_response, Pagination, CARD_SORT_FIELDS};
+use crate::lib::odrl::{allow_unregistered, check_reference, invalid_document_response, is_dry_run, parse_module_card};
+use crate::lib::config::Config;
+use crate::lib::constants::{COLL_MODULE, COLL_MODULE_CARDS};
 
 
 /// POST /moduleCards
 /// 
-/// Endpoint for creating a new module card
-pub async fn create_module_card(body: web::Json<Value>) -> Result<impl Responder, ApiError> {
+/// Endpoint for creating a new module card. With `?dryRun=true` the document is only validated.
+/// The referenced module must exist, unless `?allowUnregistered=true` is given.
+pub async fn create_module_card(config: web::Data<Config>, body: web::Json<Value>, query: web::Query<std::collections::HashMap<String, String>>) -> Result<impl Responder, ApiError> {
     debug!("Received module card data: {:?}", body);
 
-    // Check that permission exists in received document
-    let perm = match body.get("permission").and_then(|p| p.as_array()).and_then(|a| a.get(0)) {
-        Some(p) => p,
-        None => {
-            return Err(ApiError::bad_request("Invalid ODRL document: Missing or invalid 'permission' section."));
+    // Validate the document, and list every problem in it if invalid
+    let fields = match parse_module_card(&body) {
+        Ok(f) => f,
+        Err(errors) => {
+            error!("Invalid module card document: {:?}", errors);
+            return Ok(invalid_document_response("module card", &errors));
         }
     };
 
-    // Check that the permission contains fields 'target', 'action', and 'constraint'
-    let target = match perm.get("target").and_then(|v| v.as_str()) {
-        Some(t) => t,
-        None => return Err(ApiError::bad_request("Invalid permission: missing 'target'")),
-    };
-    let action = match perm.get("action").and_then(|v| v.as_str()) {
-        Some(a) => a,
-        None => return Err(ApiError::bad_request("Invalid permission: missing 'action'")),
-    };
-    let constraints = match perm.get("constraint").and_then(|v| v.as_array()) {
-        Some(c) => c,
-        None => return Err(ApiError::bad_request("Invalid permission: missing 'constraint' array")),
-    };
-
-    // Map the constraints.
-    // TODO: Should the operator be ignored, or is it always 'eq'?
-    let mut risk_level: Option<String> = None;
-    let mut input_type: Option<String> = None;
-    let mut output_risk: Option<String> = None;
-    for c in constraints {
-        let left = c.get("leftOperand").and_then(|v| v.as_str());
-        let right = c.get("rightOperand").and_then(|v| v.as_str());
-        if let (Some(l), Some(r)) = (left, right) {
-            match l {
-                "risk-level" => risk_level = Some(r.to_string()),
-                "input-type" => input_type = Some(r.to_string()),
-                "output-risk" => output_risk = Some(r.to_string()),
-                _ => {}
-            }
+    // Check that the referenced module exists, unless explicitly allowed not to
+    if !allow_unregistered(&query) {
+        if let Some(err) = check_reference(&config, COLL_MODULE, doc! { "_id": fields.moduleid }, "permission[0].target", "module").await? {
+            return Ok(invalid_document_response("module card", &[err]));
         }
     }
 
-    // Parse moduleid as ObjectId
-    let moduleid = match ObjectId::parse_str(target) {
-        Ok(oid) => oid,
-        Err(_) => {
-            return Err(ApiError::bad_request("Invalid 'target': must be a valid MongoDB ObjectId string"));
-        }
-    };
-
     // Create the ModuleCard, serialize it, and save it to database
     let module_card = ModuleCard {
         id: None,
-        moduleid,
-        name: action.to_string(),
-        risk_level: risk_level.unwrap_or_default(),
-        input_type: input_type.unwrap_or_default(),
-        output_risk: output_risk.unwrap_or_default(),
+        moduleid: fields.moduleid,
+        name: fields.name,
+        risk_level: fields.risk_level,
+        input_type: fields.input_type,
+        output_risk: fields.output_risk,
         date_received: Utc::now(),
+        last_updated: None,
     };
+    if is_dry_run(&query) {
+        return ok_json(&json!({ "message": "Module card is valid (dry run, not saved)", "moduleCard": module_card }));
+    }
 
-    let coll = get_collection::<ModuleCard>(COLL_MODULE_CARDS).await;
+    let coll = get_collection::<ModuleCard>(&config.database, COLL_MODULE_CARDS).await;
     match coll.insert_one(&module_card).await {
         Ok(_) => {
             info!("Module card received and saved successfully. Saved card:\n{:?}", module_card);
-            Ok(HttpResponse::Ok().json(json!({ "message": "Module card received and saved", "moduleCard": module_card })))
+            trigger_revalidation(&config, RevalidationScope::Module(module_card.moduleid), "module card update");
+            ok_json(&json!({ "message": "Module card received and saved", "moduleCard": module_card }))
         },
+        Err(e) if is_duplicate_key(&e) => Err(ApiError::conflict(format!(
+            "a module card already exists for module '{}', update it with PUT /moduleCards/{{card_id}}",
+            module_card.moduleid.to_hex()
+        ))),
         Err(e) => {
             error!("Error inserting module card: {}", e);
             Err(ApiError::db("Error while saving module card"))
@@ -92,10 +77,12 @@ pub async fn create_module_card(body: web::Json<Value>) -> Result<impl Responder
 
 /// GET /moduleCards
 /// 
-/// Endpoint for getting module cards. Accepts optional query parameters (e.g., after)
+/// Endpoint for getting module cards. Accepts optional query parameters (e.g., after), as well as
+/// `limit`, `offset` and `sort` (`dateReceived`), see `Pagination`.
 /// Example: GET /modulecards?after=2025-08-12T12:00:00Z
-pub async fn get_module_cards(query: web::Query<std::collections::HashMap<String, String>>) -> Result<impl Responder, ApiError> {
-    let coll = get_collection::<ModuleCard>(COLL_MODULE_CARDS).await;
+pub async fn get_module_cards(config: web::Data<Config>, query: web::Query<std::collections::HashMap<String, String>>) -> Result<impl Responder, ApiError> {
+    let coll = get_collection::<ModuleCard>(&config.database, COLL_MODULE_CARDS).await;
+    let pagination = Pagination::from_query(&query, CARD_SORT_FIELDS, None)?;
 
     // Optional time filter
     let mut filter = doc! {};
@@ -112,30 +99,24 @@ pub async fn get_module_cards(query: web::Query<std::collections::HashMap<String
     }
 
     // Get the matching module cards, if any, and return them
-    let mut cursor = match coll.find(filter).await {
-        Ok(c) => c,
-        Err(e) => {
-            error!("Error querying module cards: {}", e);
-            return Err(ApiError::internal_error("Error querying module cards"));
-        }
-    };
-    let mut out: Vec<ModuleCard> = Vec::new();
-    while let Some(doc) = cursor.try_next().await.unwrap_or(None) {
-        out.push(doc);
-    }
-    let mut v = serde_json::to_value(&out).map_err(ApiError::internal_error)?;
-    crate::lib::utils::normalize_object_ids(&mut v);
-    Ok(HttpResponse::Ok().json(v))
+    let page = find_page(&coll, filter, &pagination).await.map_err(|e| {
+        error!("Error querying module cards: {}", e);
+        ApiError::internal_error("Error querying module cards")
+    })?;
+    page_response(&page)
 }
 
 
 /// DELETE /moduleCards
 /// 
 /// Endpoint for deleting all module cards
-pub async fn delete_all_module_cards() -> Result<impl Responder, ApiError> {
-    let coll = get_collection::<ModuleCard>(COLL_MODULE_CARDS).await;
+pub async fn delete_all_module_cards(config: web::Data<Config>) -> Result<impl Responder, ApiError> {
+    let coll = get_collection::<ModuleCard>(&config.database, COLL_MODULE_CARDS).await;
     match coll.delete_many(doc! {}).await {
-        Ok(res) => Ok(HttpResponse::Ok().json(json!({ "deleted_count": res.deleted_count }))),
+        Ok(res) => {
+            trigger_revalidation(&config, RevalidationScope::All, "module card deletion");
+            Ok(HttpResponse::Ok().json(json!({ "deleted_count": res.deleted_count })))
+        },
         Err(e) => {
             error!("Failed to delete all module cards: {}", e);
             Err(ApiError::internal_error("Failed to delete module cards"))
@@ -147,7 +128,7 @@ pub async fn delete_all_module_cards() -> Result<impl Responder, ApiError> {
 /// DELETE /moduleCards/{card_id}
 /// 
 /// Endpoint for deleting a single module card by its moduleid
-pub async fn delete_module_card_by_id(path: web::Path<String>) -> Result<impl Responder, ApiError> {
+pub async fn delete_module_card_by_id(config: web::Data<Config>, path: web::Path<String>) -> Result<impl Responder, ApiError> {
     let moduleid_str = path.into_inner();
     let moduleid = match ObjectId::parse_str(&moduleid_str) {
         Ok(oid) => oid,
@@ -155,9 +136,10 @@ pub async fn delete_module_card_by_id(path: web::Path<String>) -> Result<impl Re
             return Err(ApiError::bad_request(format!("Invalid moduleid: must be ObjectId hex string, moduleid: {}", moduleid_str)));
         }
     };
-    let coll = get_collection::<ModuleCard>(COLL_MODULE_CARDS).await;
+    let coll = get_collection::<ModuleCard>(&config.database, COLL_MODULE_CARDS).await;
     match coll.delete_one(doc! { "moduleid": &moduleid }).await {
         Ok(res) if res.deleted_count == 1 => {
+            trigger_revalidation(&config, RevalidationScope::Module(moduleid), "module card deletion");
             Ok(HttpResponse::Ok().json(json!({ "message":"Module card deleted", "moduleid": moduleid })))
         }
         Ok(_) => Err(ApiError::not_found(format!("Module card not found, moduleid: {:?}", moduleid))),
@@ -167,3 +149,92 @@ pub async fn delete_module_card_by_id(path: web::Path<String>) -> Result<impl Re
         }
     }
 }
+
+
+/// Fields of a module card that can be changed after creation. Missing fields are left as they are.
+#[derive(Debug, Deserialize)]
+pub struct ModuleCardUpdate {
+    pub name: Option<String>,
+    #[serde(rename = "risk-level")]
+    pub risk_level: Option<String>,
+    #[serde(rename = "input-type")]
+    pub input_type: Option<String>,
+    #[serde(rename = "output-risk")]
+    pub output_risk: Option<String>,
+}
+
+
+/// PUT /moduleCards/{card_id}
+/// 
+/// Endpoint for partially updating a module card by its card id (the `_id` of the card, not the moduleid).
+/// The received date is kept, and the time of the update is stored in `lastUpdated`.
+pub async fn update_module_card(config: web::Data<Config>, path: web::Path<String>, body: web::Json<ModuleCardUpdate>) -> Result<impl Responder, ApiError> {
+    let card_id = path.into_inner();
+    let oid = match ObjectId::parse_str(&card_id) {
+        Ok(oid) => oid,
+        Err(_) => {
+            return Err(ApiError::bad_request(format!("Invalid card id: must be ObjectId hex string, id: {}", card_id)));
+        }
+    };
+
+    let mut set = doc! {};
+    if let Some(name) = &body.name {
+        set.insert("name", name);
+    }
+    if let Some(risk_level) = &body.risk_level {
+        set.insert("risk-level", risk_level);
+    }
+    if let Some(input_type) = &body.input_type {
+        set.insert("input-type", input_type);
+    }
+    if let Some(output_risk) = &body.output_risk {
+        set.insert("output-risk", output_risk);
+    }
+    if set.is_empty() {
+        return Err(ApiError::bad_request("Nothing to update, expected at least one of: name, risk-level, input-type, output-risk"));
+    }
+    set.insert("lastUpdated", mongodb::bson::DateTime::now());
+
+    let coll = get_collection::<ModuleCard>(&config.database, COLL_MODULE_CARDS).await;
+    match coll
+        .find_one_and_update(doc! { "_id": &oid }, doc! { "$set": set })
+        .return_document(ReturnDocument::After)
+        .await
+    {
+        Ok(Some(module_card)) => {
+            info!("Module card {} updated", card_id);
+            trigger_revalidation(&config, RevalidationScope::Module(module_card.moduleid), "module card update");
+            ok_json(&json!({ "message": "Module card updated", "moduleCard": module_card }))
+        }
+        Ok(None) => Err(ApiError::not_found(format!("Module card not found, id: {}", card_id))),
+        Err(e) => {
+            error!("Failed to update module card {}: {}", card_id, e);
+            Err(ApiError::internal_error(format!("Failed to update module card, id: {}", card_id)))
+        }
+    }
+}
+
+
+/// GET /moduleCards/{card_id}
+/// 
+/// Endpoint for getting a single module card by its card id, along with the module it refers to
+/// (null if the module isnt uploaded).
+pub async fn get_module_card_by_id(config: web::Data<Config>, path: web::Path<String>) -> Result<impl Responder, ApiError> {
+    let card_id = path.into_inner();
+    let oid = match ObjectId::parse_str(&card_id) {
+        Ok(oid) => oid,
+        Err(_) => {
+            return Err(ApiError::bad_request(format!("Invalid card id: must be ObjectId hex string, id: {}", card_id)));
+        }
+    };
+
+    let module_card = find_one::<ModuleCard>(&config.database, COLL_MODULE_CARDS, doc! { "_id": &oid })
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("Module card not found, id: {}", card_id)))?;
+    let module = find_one::<ModuleDoc>(&config.database, COLL_MODULE, doc! { "_id": &module_card.moduleid })
+        .await
+        .map_err(ApiError::db)?;
+
+    ok_json(&json!({ "moduleCard": module_card, "module": module }))
+}
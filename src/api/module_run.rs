@@ -0,0 +1,190 @@
+//! # module_run.rs
+//!
+//! Quick test runs of a single function of a module, without building a manifest first. The
+//! function is deployed on its own on the chosen device (or one picked like for any deployment)
+//! as a deployment named after the module, function, device and module revision, so that later
+//! runs reuse it until the module is updated. The run goes through the execution queue like any
+//! other execution, and the deployment can be removed right after it.
+
+use std::collections::HashMap;
+use std::time::Duration;
+use actix_web::{web, Responder};
+use actix_web::http::StatusCode;
+use log::{info, warn};
+use mongodb::bson::{doc, oid::ObjectId, Bson};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use crate::api::deployment::{deploy, rejected_response, solve, strict_validation, ApiSequenceStep, Sequence, SolveResult};
+use crate::api::device::device_filter;
+use crate::api::execution::{execute_json, result_callback_base};
+use crate::lib::config::Config;
+use crate::lib::constants::{COLL_DEPLOYMENT, COLL_DEPLOYMENT_CERTS, COLL_DEVICE, COLL_MODULE, SUPPORTED_FILE_TYPES};
+use crate::lib::errors::ApiError;
+use crate::lib::mongodb::{find_one, get_collection, update_field};
+use crate::lib::response::json_response;
+use crate::lib::revisions;
+use crate::lib::zeroconf::get_listening_address;
+use crate::structs::deployment::DeploymentDoc;
+use crate::structs::device::DeviceDoc;
+use crate::structs::module::ModuleDoc;
+
+
+/// Prefix of the names of deployments created for test runs
+pub const RUN_DEPLOYMENT_PREFIX: &str = "run:";
+
+
+/// Body of POST /file/module/{module_id}/run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModuleRunRequest {
+    pub function: String,
+    #[serde(default)]
+    pub device: String, // Id or name of the device, empty to let the orchestrator pick one
+    #[serde(default)]
+    pub inputs: Option<Value>, // Arguments as a JSON object, array or single value, see lib/parameter_validation.rs
+    #[serde(default)]
+    pub cleanup: bool, // Remove the deployment after the run instead of keeping it for the next one
+    #[serde(default)]
+    pub timeout_s: Option<u64>,
+}
+
+
+/// POST /file/module/{module_id}/run
+///
+/// Runs a single exported function of a module (by id or name) with the given inputs, and
+/// returns its result along with the deployment and execution it ran in. The function is
+/// deployed first unless an earlier run of the same revision of the module left its deployment
+/// active. With `cleanup` the deployment is removed after the run. Supports `strict` like
+/// `POST /file/manifest`.
+pub async fn run_module_function(
+    config: web::Data<Config>,
+    path: web::Path<String>,
+    query: web::Query<HashMap<String, String>>,
+    body: web::Json<ModuleRunRequest>,
+) -> Result<impl Responder, ApiError> {
+    let module_key = path.into_inner();
+    let request = body.into_inner();
+    let strict = strict_validation(&config, &query)?;
+
+    let module_filter = match ObjectId::parse_str(&module_key) {
+        Ok(oid) => doc! { "_id": oid },
+        Err(_) => doc! { "name": &module_key },
+    };
+    let module = find_one::<ModuleDoc>(&config.database, COLL_MODULE, module_filter)
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("no module matches '{}'", module_key)))?;
+    let module_id = module.id.ok_or_else(|| ApiError::internal_error("module has no id"))?;
+    if !module.exports.iter().any(|e| e.name == request.function) {
+        let exports: Vec<&str> = module.exports.iter().map(|e| e.name.as_str()).collect();
+        return Err(ApiError::bad_request(format!(
+            "module '{}' doesnt export '{}', expected one of {}",
+            module.name,
+            request.function,
+            exports.join(", ")
+        )));
+    }
+
+    let device = match request.device.trim() {
+        "" => None,
+        key => Some(
+            find_one::<DeviceDoc>(&config.database, COLL_DEVICE, device_filter(key))
+                .await
+                .map_err(ApiError::db)?
+                .ok_or_else(|| ApiError::not_found(format!("no device matches '{}'", key)))?,
+        ),
+    };
+    let device_id = device.as_ref().and_then(|d| d.id).map(|id| id.to_hex()).unwrap_or_default();
+    let name = format!(
+        "{}{}.{}@{}#{}",
+        RUN_DEPLOYMENT_PREFIX,
+        module.name,
+        request.function,
+        device.as_ref().map(|d| d.name.as_str()).unwrap_or("any"),
+        module.revision
+    );
+
+    // Reuse the deployment of an earlier run if it is still active
+    let existing = find_one::<DeploymentDoc>(&config.database, COLL_DEPLOYMENT, doc! { "name": &name })
+        .await
+        .map_err(ApiError::db)?;
+    let reused = existing.as_ref().is_some_and(|d| d.active == Some(true));
+    let deployment = match existing {
+        Some(deployment) => deployment,
+        None => {
+            let sequence = Sequence {
+                id: None,
+                name: name.clone(),
+                sequence: vec![ApiSequenceStep {
+                    device: device_id,
+                    module: module_id.to_hex(),
+                    func: request.function.clone(),
+                    method: None,
+                }],
+                config: None,
+                polling: None,
+                execution_timeout_s: None,
+            };
+            let (orchestrator_host, _) = get_listening_address(&config.server);
+            let package_manager_base_url = config.server.package_manager_base_url(&orchestrator_host);
+            let deployment_id = match solve(&config, &sequence, false, None, strict, &package_manager_base_url, SUPPORTED_FILE_TYPES).await {
+                Ok(SolveResult::DeploymentId(id)) => id,
+                Ok(SolveResult::Rejected(cert)) => return rejected_response(&cert),
+                Ok(SolveResult::Solution(..) | SolveResult::Stale { .. }) => return Err(ApiError::internal_error("deployment was updated instead of created")),
+                Err(e) => return Err(ApiError::bad_request(format!("deploying '{}' failed: {}", request.function, e))),
+            };
+            find_one::<DeploymentDoc>(&config.database, COLL_DEPLOYMENT, doc! { "_id": deployment_id })
+                .await
+                .map_err(ApiError::db)?
+                .ok_or_else(|| ApiError::internal_error(format!("deployment '{}' disappeared after it was created", name)))?
+        }
+    };
+    let deployment_id = deployment.id.ok_or_else(|| ApiError::internal_error("deployment has no id"))?;
+
+    if !reused {
+        if let Err(e) = deploy(&config, &deployment).await {
+            if request.cleanup {
+                remove_run_deployment(&config, &deployment_id).await;
+            }
+            return Err(e);
+        }
+        update_field::<DeploymentDoc>(&config.database, COLL_DEPLOYMENT, doc! { "_id": deployment_id }, "active", Bson::Boolean(true))
+            .await
+            .map_err(ApiError::db)?;
+    }
+    info!("🧪 Running '{}' of module '{}' in deployment '{}' ({})", request.function, module.name, name, if reused { "reused" } else { "deployed" });
+
+    let timeout = request.timeout_s.filter(|t| *t > 0).map(Duration::from_secs);
+    let callback_base = result_callback_base(&config);
+    let outcome = execute_json(&config, &deployment, request.inputs, timeout, callback_base.as_deref()).await;
+    if request.cleanup {
+        remove_run_deployment(&config, &deployment_id).await;
+    }
+    let (execution_id, status, result) = outcome?;
+
+    let status = if status.is_success() { StatusCode::OK } else { status };
+    json_response(status, &json!({
+        "deploymentId": deployment_id.to_hex(),
+        "deploymentName": name,
+        "reused": reused,
+        "executionId": execution_id,
+        "result": result,
+        "cleanedUp": request.cleanup,
+    }))
+}
+
+
+/// Removes a deployment created for a test run along with its certificates. It is never
+/// executed again, so it isnt kept in the trash.
+async fn remove_run_deployment(config: &Config, deployment_id: &ObjectId) {
+    let deployments = get_collection::<DeploymentDoc>(&config.database, COLL_DEPLOYMENT).await;
+    if let Err(e) = deployments.delete_one(doc! { "_id": deployment_id }).await {
+        warn!("Failed to remove test run deployment '{}': {}", deployment_id, e);
+        return;
+    }
+    let certificates = get_collection::<mongodb::bson::Document>(&config.database, COLL_DEPLOYMENT_CERTS).await;
+    if let Err(e) = certificates.delete_many(doc! { "deploymentId": deployment_id }).await {
+        warn!("Failed to remove the certificates of test run deployment '{}': {}", deployment_id, e);
+    }
+    revisions::bump(COLL_DEPLOYMENT);
+}
@@ -1,58 +1,58 @@
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use actix_web::http::{header, StatusCode};
 use serde_json::{Value, json};
 use chrono::{DateTime, Utc};
-use mongodb::bson::doc;
-use crate::lib::mongodb::get_collection;
-use futures::stream::TryStreamExt;
-use log::{info, error};
+use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::options::ReturnDocument;
+use serde::Deserialize;
+use crate::lib::mongodb::{find_one, get_collection};
+use log::{debug, info, error};
 use crate::lib::errors::ApiError;
-use crate::lib::constants::COLL_NODE_CARDS;
+use crate::api::revalidation::{trigger_revalidation, RevalidationScope};
+use crate::lib::response::ok_json;
+use crate::lib::revisions;
+use crate::lib::pagination::{find_page, page_response, Pagination, CARD_SORT_FIELDS};
+use crate::lib::odrl::{allow_unregistered, check_reference, invalid_document_response, is_dry_run, parse_node_card, FieldError};
+use crate::api::device::device_filter;
+use crate::lib::card_auth::{authenticate_card_submitter, authorize_card};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use crate::lib::config::{CardConfig, Config};
+use crate::lib::constants::{COLL_DEVICE, COLL_NODE_CARDS};
 use crate::structs::node_cards::NodeCard;
+use crate::structs::device::DeviceDoc;
 
 
-/// GET /nodeCards
+/// POST /nodeCards
 /// 
-/// Endpoint to create a node card
-pub async fn create_node_card(card: web::Json<Value>) -> Result<impl Responder, ApiError> {
+/// Endpoint to create a node card. With `?dryRun=true` the document is only validated.
+/// The referenced device must exist, unless `?allowUnregistered=true` is given. The card must be
+/// of a device the card token of the request covers, see `authorize_card`.
+pub async fn create_node_card(config: web::Data<Config>, req: HttpRequest, card: web::Json<Value>, query: web::Query<HashMap<String, String>>) -> Result<impl Responder, ApiError> {
     info!("Received node card data: {:?}", card);
+    let submitter = authenticate_card_submitter(&config, &req).await?;
 
-    // Extract the first asset from the asset array
-    let asset = card.get("asset")
-        .and_then(|a| a.as_array())
-        .and_then(|arr| arr.get(0));
-    if asset.is_none() {
-        error!("Invalid metadata: Missing asset data");
-        return Err(ApiError::bad_request("Invalid metadata: Missing asset data"));
-    }
-    let asset = asset.unwrap();
-
-    // Extract zone information from relations
-    let zone = asset.get("relation")
-        .and_then(|r| r.as_array())
-        .and_then(|arr| arr.iter().find(|rel| rel.get("type").and_then(|t| t.as_str()) == Some("memberOf")))
-        .and_then(|rel| rel.get("value").and_then(|v| v.as_str()))
-        .unwrap_or("unknown")
-        .to_string();
-
-    // Create a new NodeCard instance
-    let node_card = NodeCard {
-        id: None,
-        name: asset.get("title").and_then(|v| v.as_str()).unwrap_or("unknown").to_string(),
-        nodeid: asset.get("uid").and_then(|v| v.as_str()).unwrap_or("unknown").to_string(),
-        zone,
-        date_received: Utc::now(),
+    // Validate the document, and list every problem in it if invalid
+    let mut node_card = match validate_node_card(&config, &card, allow_unregistered(&query)).await? {
+        Ok(c) => c,
+        Err(errors) => {
+            error!("Invalid node card document: {:?}", errors);
+            return Ok(invalid_document_response("node card", &errors));
+        }
     };
+    authorize_card(&config, &submitter, &node_card.nodeid, Some(&node_card.zone)).await?;
+    node_card.submitted_by = Some(submitter);
+    if is_dry_run(&query) {
+        return ok_json(&json!({ "message": "Node card is valid (dry run, not saved)", "nodeCard": node_card }));
+    }
 
     // Save the new card to MongoDB. Replace if entry with same nodeid exists already.
-    let collection = get_collection::<NodeCard>(COLL_NODE_CARDS).await;
-    
-    let filter = doc! { "nodeid": &node_card.nodeid };
-
-    match collection.find_one_and_replace(filter, &node_card).upsert(true).await {
-        Ok(_) => Ok(HttpResponse::Ok().json(json!({
+    match save_node_card(&config, &node_card).await {
+        Ok(()) => ok_json(&json!({
             "message": "Node card saved (created or updated)",
             "nodeCard": node_card
-        }))),
+        })),
         Err(e) => {
             error!("Error creating/updating node card: {}", e);
             Err(ApiError::internal_error("Error creating/updating Node card"))
@@ -62,11 +62,189 @@ pub async fn create_node_card(card: web::Json<Value>) -> Result<impl Responder,
 }
 
 
-/// POST /nodeCards
+/// Validates a node card document and builds the card from it. Unless `allow_unregistered`
+/// is set, the referenced device must exist.
+async fn validate_node_card(config: &Config, card: &Value, allow_unregistered: bool) -> Result<Result<NodeCard, Vec<FieldError>>, ApiError> {
+    let fields = match parse_node_card(card) {
+        Ok(f) => f,
+        Err(errors) => return Ok(Err(errors)),
+    };
+    if !allow_unregistered {
+        if let Some(err) = check_reference(config, COLL_DEVICE, device_filter(&fields.nodeid), "asset[0].uid", "device").await? {
+            return Ok(Err(vec![err]));
+        }
+    }
+    Ok(Ok(NodeCard {
+        id: None,
+        name: fields.name,
+        nodeid: fields.nodeid,
+        zone: fields.zone,
+        date_received: Utc::now(),
+        last_updated: None,
+        submitted_by: None,
+    }))
+}
+
+
+/// POST /nodeCards/bulk
+///
+/// Endpoint to create many node cards at once. The body is either a JSON array of ODRL node
+/// card documents, or a CSV (`Content-Type: text/csv`) with `device,zone` lines where device is
+/// the id or name of a registered device. Every entry is validated and saved on its own, and
+/// the result of each is returned in the order of the entries. Supports `?dryRun=true` and
+/// `?allowUnregistered=true` (JSON only) like `POST /nodeCards`. Entries of devices that the card
+/// token of the request doesnt cover are refused with the status `forbidden`.
+pub async fn create_node_cards_bulk(config: web::Data<Config>, req: HttpRequest, body: web::Bytes, query: web::Query<HashMap<String, String>>) -> Result<impl Responder, ApiError> {
+    let submitter = authenticate_card_submitter(&config, &req).await?;
+    let is_csv = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.starts_with("text/csv"))
+        .unwrap_or(false);
+
+    let entries: Vec<Result<NodeCard, Vec<FieldError>>> = if is_csv {
+        let text = std::str::from_utf8(&body).map_err(|_| ApiError::bad_request("CSV body is not valid UTF-8"))?;
+        let mut entries = Vec::new();
+        for (line_no, line) in csv_rows(text) {
+            entries.push(node_card_from_csv_row(&config, line_no, line).await?);
+        }
+        entries
+    } else {
+        let docs: Vec<Value> = serde_json::from_slice(&body)
+            .map_err(|e| ApiError::bad_request(format!("expected a JSON array of node card documents: {}", e)))?;
+        let allow = allow_unregistered(&query);
+        let mut entries = Vec::with_capacity(docs.len());
+        for doc in &docs {
+            entries.push(validate_node_card(&config, doc, allow).await?);
+        }
+        entries
+    };
+    if entries.is_empty() {
+        return Err(ApiError::bad_request("No node cards in the request"));
+    }
+
+    let dry_run = is_dry_run(&query);
+    let collection = get_collection::<NodeCard>(&config.database, COLL_NODE_CARDS).await;
+    let mut results = Vec::with_capacity(entries.len());
+    let (mut saved, mut failed) = (0, 0);
+    for (index, entry) in entries.into_iter().enumerate() {
+        let entry = match entry {
+            Ok(mut node_card) => match authorize_card(&config, &submitter, &node_card.nodeid, Some(&node_card.zone)).await {
+                Ok(()) => {
+                    node_card.submitted_by = Some(submitter.clone());
+                    Ok(node_card)
+                }
+                Err(e) if e.status == StatusCode::FORBIDDEN => {
+                    failed += 1;
+                    results.push(json!({ "index": index, "status": "forbidden", "error": e.msg }));
+                    continue;
+                }
+                Err(e) => return Err(e),
+            },
+            Err(errors) => Err(errors),
+        };
+        let result = match entry {
+            Err(errors) => {
+                failed += 1;
+                json!({ "index": index, "status": "invalid", "errors": errors })
+            }
+            Ok(node_card) if dry_run => json!({ "index": index, "status": "valid", "nodeCard": node_card }),
+            Ok(node_card) => match collection
+                .find_one_and_replace(doc! { "nodeid": &node_card.nodeid }, &node_card)
+                .upsert(true)
+                .await
+            {
+                Ok(_) => {
+                    saved += 1;
+                    json!({ "index": index, "status": "saved", "nodeCard": node_card })
+                }
+                Err(e) => {
+                    error!("Error saving node card for nodeid {}: {}", node_card.nodeid, e);
+                    failed += 1;
+                    json!({ "index": index, "status": "error", "error": "Error creating/updating node card" })
+                }
+            },
+        };
+        results.push(result);
+    }
+
+    // Revalidate once for the whole batch instead of once per card
+    if saved > 0 {
+        revisions::bump(COLL_NODE_CARDS);
+        trigger_revalidation(&config, RevalidationScope::All, "bulk node card upload");
+    }
+    info!("📄 Bulk node card upload: {} saved, {} failed{}", saved, failed, if dry_run { " (dry run)" } else { "" });
+    ok_json(&json!({
+        "saved": saved,
+        "failed": failed,
+        "dryRun": dry_run,
+        "results": results,
+    }))
+}
+
+
+/// Non-empty, non-comment lines of a CSV body with their line numbers. A leading
+/// `device,zone` header line is skipped.
+fn csv_rows(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .filter(|(i, line)| !(*i == 1 && line.eq_ignore_ascii_case("device,zone")))
+}
+
+
+/// Builds a node card from a `device,zone` CSV line. The device must be registered, as its
+/// id and name are taken from the device document.
+async fn node_card_from_csv_row(config: &Config, line_no: usize, line: &str) -> Result<Result<NodeCard, Vec<FieldError>>, ApiError> {
+    let field = |name: &str| format!("line[{}].{}", line_no, name);
+    let cols: Vec<&str> = line.split(',').map(|c| c.trim().trim_matches('"')).collect();
+    let (device_key, zone) = match cols.as_slice() {
+        [device, zone] if !device.is_empty() && !zone.is_empty() => (*device, *zone),
+        _ => {
+            return Ok(Err(vec![FieldError {
+                field: format!("line[{}]", line_no),
+                message: "expected two non-empty columns: device,zone".to_string(),
+            }]));
+        }
+    };
+    let Some(device) = find_one::<DeviceDoc>(&config.database, COLL_DEVICE, device_filter(device_key)).await.map_err(ApiError::db)? else {
+        return Ok(Err(vec![FieldError {
+            field: field("device"),
+            message: format!("no device exists with id or name '{}'", device_key),
+        }]));
+    };
+    Ok(Ok(NodeCard {
+        id: None,
+        name: device.name,
+        nodeid: device.id.map(|id| id.to_hex()).unwrap_or_default(),
+        zone: zone.to_string(),
+        date_received: Utc::now(),
+        last_updated: None,
+        submitted_by: None,
+    }))
+}
+
+
+/// Saves a node card, replacing the existing card with the same nodeid, and revalidates
+/// the deployments affected by it.
+async fn save_node_card(config: &Arc<Config>, node_card: &NodeCard) -> mongodb::error::Result<()> {
+    let collection = get_collection::<NodeCard>(&config.database, COLL_NODE_CARDS).await;
+    let filter = doc! { "nodeid": &node_card.nodeid };
+    collection.find_one_and_replace(filter, node_card).upsert(true).await?;
+    revisions::bump(COLL_NODE_CARDS);
+    trigger_revalidation(&config, RevalidationScope::for_node(&node_card.nodeid), "node card update");
+    Ok(())
+}
+
+
+/// GET /nodeCards
 /// 
-/// Endpoint to get node cards
-pub async fn get_node_cards(query: web::Query<std::collections::HashMap<String, String>>) -> Result<impl Responder, ApiError> {
-    let collection = get_collection::<NodeCard>(COLL_NODE_CARDS).await;
+/// Endpoint to get node cards. Supports `limit`, `offset` and `sort` (`dateReceived`), see `Pagination`.
+pub async fn get_node_cards(config: web::Data<Config>, query: web::Query<HashMap<String, String>>) -> Result<impl Responder, ApiError> {
+    let collection = get_collection::<NodeCard>(&config.database, COLL_NODE_CARDS).await;
+    let pagination = Pagination::from_query(&query, CARD_SORT_FIELDS, None)?;
 
     // Optional time filter
     let mut filter = doc! {};
@@ -78,34 +256,25 @@ pub async fn get_node_cards(query: web::Query<std::collections::HashMap<String,
     }
 
     // Get and return the results
-    let cursor = match collection.find(filter).await {
-        Ok(cursor) => cursor,
-        Err(e) => {
-            error!("Error querying node cards: {}", e);
-            return Err(ApiError::internal_error("Error querying node cards"));
-        }
-    };
-    let results: Vec<NodeCard> = match cursor.try_collect().await {
-        Ok(v) => v,
-        Err(e) => {
-            error!("Failed to collect node cards: {}", e);
-            return Err(ApiError::db("Failed to collect node cards"));
-        }
-    };
-
-    let mut v = serde_json::to_value(&results).map_err(ApiError::internal_error)?;
-    crate::lib::utils::normalize_object_ids(&mut v);
-    Ok(HttpResponse::Ok().json(v))
+    let page = find_page(&collection, filter, &pagination).await.map_err(|e| {
+        error!("Error querying node cards: {}", e);
+        ApiError::internal_error("Error querying node cards")
+    })?;
+    page_response(&page)
 }
 
 
 /// DELETE /nodeCards
 /// 
 /// Endpoint to delete all node cards
-pub async fn delete_all_node_cards() -> Result<impl Responder, ApiError> {
-    let collection = get_collection::<NodeCard>(COLL_NODE_CARDS).await;
+pub async fn delete_all_node_cards(config: web::Data<Config>) -> Result<impl Responder, ApiError> {
+    let collection = get_collection::<NodeCard>(&config.database, COLL_NODE_CARDS).await;
     match collection.delete_many(doc! {}).await {
-        Ok(result) => Ok(HttpResponse::Ok().json(json!({ "deleted_count": result.deleted_count }))),
+        Ok(result) => {
+            revisions::bump(COLL_NODE_CARDS);
+            trigger_revalidation(&config, RevalidationScope::All, "node card deletion");
+            Ok(HttpResponse::Ok().json(json!({ "deleted_count": result.deleted_count })))
+        },
         Err(e) => {
             error!("Failed to delete all node cards: {}", e);
             Err(ApiError::internal_error("Failed to delete node cards"))
@@ -117,12 +286,14 @@ pub async fn delete_all_node_cards() -> Result<impl Responder, ApiError> {
 /// DELETE /nodeCards/{card_id}
 /// 
 /// Endpoint to delete a specific node card by nodeid
-pub async fn delete_node_card_by_id(path: web::Path<String>) -> Result<impl Responder, ApiError> {
+pub async fn delete_node_card_by_id(config: web::Data<Config>, path: web::Path<String>) -> Result<impl Responder, ApiError> {
     let nodeid = path.into_inner();
-    let collection = get_collection::<NodeCard>(COLL_NODE_CARDS).await;
+    let collection = get_collection::<NodeCard>(&config.database, COLL_NODE_CARDS).await;
     match collection.delete_one(doc! { "nodeid": &nodeid }).await {
         Ok(result) => {
             if result.deleted_count == 1 {
+                revisions::bump(COLL_NODE_CARDS);
+                trigger_revalidation(&config, RevalidationScope::for_node(&nodeid), "node card deletion");
                 Ok(HttpResponse::Ok().json(json!({ "message": "Node card deleted", "nodeid": nodeid })))
             } else {
                 Err(ApiError::not_found(format!("Node card not found, nodeid: {}", nodeid)))
@@ -134,3 +305,216 @@ pub async fn delete_node_card_by_id(path: web::Path<String>) -> Result<impl Resp
         }
     }
 }
+
+
+/// Fields of a node card that can be changed after creation. Missing fields are left as they are.
+#[derive(Debug, Deserialize)]
+pub struct NodeCardUpdate {
+    pub name: Option<String>,
+    pub zone: Option<String>,
+}
+
+
+/// PUT /nodeCards/{card_id}
+/// 
+/// Endpoint to partially update a node card by its card id (the `_id` of the card, not the nodeid).
+/// The received date is kept, and the time of the update is stored in `lastUpdated`.
+pub async fn update_node_card(config: web::Data<Config>, path: web::Path<String>, body: web::Json<NodeCardUpdate>) -> Result<impl Responder, ApiError> {
+    let card_id = path.into_inner();
+    let oid = ObjectId::parse_str(&card_id)
+        .map_err(|_| ApiError::bad_request(format!("Invalid card id: must be ObjectId hex string, id: {}", card_id)))?;
+
+    let mut set = doc! {};
+    if let Some(name) = &body.name {
+        set.insert("name", name);
+    }
+    if let Some(zone) = &body.zone {
+        set.insert("zone", zone);
+    }
+    if set.is_empty() {
+        return Err(ApiError::bad_request("Nothing to update, expected at least one of: name, zone"));
+    }
+    set.insert("lastUpdated", mongodb::bson::DateTime::now());
+
+    let collection = get_collection::<NodeCard>(&config.database, COLL_NODE_CARDS).await;
+    match collection
+        .find_one_and_update(doc! { "_id": &oid }, doc! { "$set": set })
+        .return_document(ReturnDocument::After)
+        .await
+    {
+        Ok(Some(node_card)) => {
+            revisions::bump(COLL_NODE_CARDS);
+            info!("Node card {} updated", card_id);
+            trigger_revalidation(&config, RevalidationScope::for_node(&node_card.nodeid), "node card update");
+            ok_json(&json!({ "message": "Node card updated", "nodeCard": node_card }))
+        }
+        Ok(None) => Err(ApiError::not_found(format!("Node card not found, id: {}", card_id))),
+        Err(e) => {
+            error!("Failed to update node card {}: {}", card_id, e);
+            Err(ApiError::internal_error(format!("Failed to update node card, id: {}", card_id)))
+        }
+    }
+}
+
+
+/// GET /nodeCards/{card_id}
+/// 
+/// Endpoint to get a single node card by its card id, along with the device it refers to
+/// (null if the device isnt registered).
+pub async fn get_node_card_by_id(config: web::Data<Config>, path: web::Path<String>) -> Result<impl Responder, ApiError> {
+    let card_id = path.into_inner();
+    let oid = ObjectId::parse_str(&card_id)
+        .map_err(|_| ApiError::bad_request(format!("Invalid card id: must be ObjectId hex string, id: {}", card_id)))?;
+
+    let node_card = find_one::<NodeCard>(&config.database, COLL_NODE_CARDS, doc! { "_id": &oid })
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("Node card not found, id: {}", card_id)))?;
+    let device = find_one::<DeviceDoc>(&config.database, COLL_DEVICE, device_filter(&node_card.nodeid))
+        .await
+        .map_err(ApiError::db)?;
+
+    ok_json(&json!({ "nodeCard": node_card, "device": device }))
+}
+
+
+/// Picks the zone of a device using the zone rules of the card settings. Rules are checked in
+/// order, and are either subnets (containing a '/') matched against the device addresses, or
+/// device name patterns where `*` matches anything.
+pub fn zone_for_device(config: &CardConfig, device: &DeviceDoc) -> Option<String> {
+    config.zone_rules.iter().find_map(|rule| {
+        let matches = if rule.pattern.contains('/') {
+            device.communication.addresses.iter().any(|a| in_subnet(a, &rule.pattern))
+        } else {
+            name_matches(&device.name, &rule.pattern)
+        };
+        matches.then(|| rule.zone.clone())
+    })
+}
+
+
+/// Whether the address belongs to the subnet given in CIDR notation
+fn in_subnet(addr: &str, cidr: &str) -> bool {
+    let Some((net, prefix)) = cidr.split_once('/') else { return false };
+    let (Ok(addr), Ok(net), Ok(prefix)) = (addr.parse::<IpAddr>(), net.parse::<IpAddr>(), prefix.parse::<u32>()) else {
+        return false;
+    };
+    match (addr, net) {
+        (IpAddr::V4(a), IpAddr::V4(n)) if prefix <= 32 => {
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            u32::from(a) & mask == u32::from(n) & mask
+        }
+        (IpAddr::V6(a), IpAddr::V6(n)) if prefix <= 128 => {
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            u128::from(a) & mask == u128::from(n) & mask
+        }
+        _ => false,
+    }
+}
+
+
+/// Whether the name matches a pattern where `*` matches any number of characters
+fn name_matches(name: &str, pattern: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return name == pattern;
+    }
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if !name.starts_with(first) || name.len() < first.len() + last.len() || !name.ends_with(last) {
+        return false;
+    }
+    let mut rest = &name[first.len()..name.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+
+/// Creates a default node card for the device, with the zone picked by `zone_for_device`.
+/// Returns None if no zone rule matches the device.
+pub fn default_node_card(config: &CardConfig, device: &DeviceDoc) -> Option<NodeCard> {
+    let nodeid = device.id.as_ref()?.to_hex();
+    let zone = zone_for_device(config, device)?;
+    Some(NodeCard {
+        id: None,
+        name: device.name.clone(),
+        nodeid,
+        zone,
+        date_received: Utc::now(),
+        last_updated: None,
+        submitted_by: None,
+    })
+}
+
+
+/// Generates and saves a node card for a newly discovered device, if it doesnt have one yet.
+/// Used as a discovery hook when `autoGenerate` of the card settings is enabled.
+pub async fn generate_node_card_for_new_device(config: &Arc<Config>, device_name: &str) {
+    let device = match find_one::<DeviceDoc>(&config.database, COLL_DEVICE, doc! { "name": device_name }).await {
+        Ok(Some(d)) => d,
+        Ok(None) => return,
+        Err(e) => {
+            error!("Failed to load device '{}' for node card generation: {}", device_name, e);
+            return;
+        }
+    };
+    let Some(id) = device.id.as_ref() else { return };
+    match find_one::<NodeCard>(&config.database, COLL_NODE_CARDS, doc! { "nodeid": id.to_hex() }).await {
+        Ok(None) => {}
+        Ok(Some(_)) => return,
+        Err(e) => {
+            error!("Failed to check existing node card of device '{}': {}", device_name, e);
+            return;
+        }
+    }
+    let Some(node_card) = default_node_card(&config.cards, &device) else {
+        debug!("No zone rule matches device '{}', node card not generated", device_name);
+        return;
+    };
+    match save_node_card(&config, &node_card).await {
+        Ok(()) => info!("📄 Generated node card for device '{}' in zone '{}'", device_name, node_card.zone),
+        Err(e) => error!("Failed to save generated node card for device '{}': {}", device_name, e),
+    }
+}
+
+
+/// POST /nodeCards/generate/{device_id}
+/// 
+/// Endpoint to create a default node card for a device (by id or name), with the zone picked
+/// from the zone rules of the card settings. An existing card is only replaced with `?overwrite=true`.
+pub async fn generate_node_card(
+    config: web::Data<Config>,
+    path: web::Path<String>,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<impl Responder, ApiError> {
+    let device_key = path.into_inner();
+    let device = find_one::<DeviceDoc>(&config.database, COLL_DEVICE, device_filter(&device_key))
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("no device matches '{}'", device_key)))?;
+    let nodeid = device.id.map(|id| id.to_hex()).unwrap_or_default();
+
+    let overwrite = query.get("overwrite").map(|v| v == "true").unwrap_or(false);
+    let existing = find_one::<NodeCard>(&config.database, COLL_NODE_CARDS, doc! { "nodeid": &nodeid })
+        .await
+        .map_err(ApiError::db)?;
+    if existing.is_some() && !overwrite {
+        return Err(ApiError::bad_request(format!(
+            "Node card already exists for device '{}', use ?overwrite=true to replace it", device.name
+        )));
+    }
+
+    let node_card = default_node_card(&config.cards, &device).ok_or_else(|| {
+        ApiError::bad_request(format!("No zone rule (NODE_CARD_ZONE_RULES) matches device '{}'", device.name))
+    })?;
+    if let Err(e) = save_node_card(&config, &node_card).await {
+        error!("Error saving generated node card: {}", e);
+        return Err(ApiError::internal_error("Error saving generated node card"));
+    }
+    info!("📄 Generated node card for device '{}' in zone '{}'", device.name, node_card.zone);
+    ok_json(&json!({ "message": "Node card generated", "nodeCard": node_card }))
+}
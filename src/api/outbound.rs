@@ -0,0 +1,17 @@
+//! # outbound.rs
+//!
+//! Monitoring endpoint for the limiter of outgoing supervisor requests (see lib/outbound.rs).
+
+use actix_web::{web, HttpResponse, Responder};
+use crate::lib::config::Config;
+use crate::lib::errors::ApiError;
+use crate::lib::outbound::stats;
+
+
+/// GET /admin/outbound
+///
+/// Returns how many requests to supervisors are currently in flight and queued,
+/// along with counters collected since startup.
+pub async fn get_outbound_stats(config: web::Data<Config>) -> Result<impl Responder, ApiError> {
+    Ok(HttpResponse::Ok().json(stats(&config.http_client)))
+}
@@ -0,0 +1,185 @@
+//! # policy_engine.rs
+//!
+//! Deployment validation is done by a set of validators, whose validation logs are all
+//! merged into the deployment certificate. The built-in validator checks the zones and
+//! node, module and data source cards. Additional validators can be registered at startup
+//! with `register_validator`, and an external OPA (Rego) policy is consulted when the
+//! deployment settings have its url.
+
+use std::sync::Arc;
+use futures::future::BoxFuture;
+use log::debug;
+use mongodb::bson::oid::ObjectId;
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use serde_json::{json, Value};
+use crate::api::deployment::CreateSolutionResult;
+use crate::api::deployment_certificates::evaluate_step;
+use crate::api::zones_and_risk_levels::ZonePolicies;
+use crate::lib::config::Config;
+use crate::lib::http_client::{self, Operation};
+use crate::lib::telemetry::send_traced;
+use crate::structs::deployment_certificates::ValidationLog;
+
+
+/// A validator taking part in deployment validation. Returned logs end up in the deployment
+/// certificate, and the deployment is valid only if every log of every validator is valid.
+/// An error means the validation could not be completed at all.
+pub trait DeploymentValidator: Send + Sync {
+    /// Name of the validator, stored in the logs it produces
+    fn name(&self) -> &str;
+
+    fn validate<'a>(
+        &'a self,
+        config: &'a Config,
+        deployment_id: &'a ObjectId,
+        solution: &'a CreateSolutionResult,
+    ) -> BoxFuture<'a, Result<Vec<ValidationLog>, String>>;
+}
+
+
+static VALIDATORS: Lazy<RwLock<Vec<Arc<dyn DeploymentValidator>>>> =
+    Lazy::new(|| RwLock::new(vec![Arc::new(ZoneCardValidator), Arc::new(OpaValidator)]));
+
+
+/// Registers an additional validator that is run for every deployment validation
+pub fn register_validator(validator: Arc<dyn DeploymentValidator>) {
+    debug!("Registered deployment validator '{}'", validator.name());
+    VALIDATORS.write().push(validator);
+}
+
+
+/// Runs all registered validators against the solution and returns their merged logs
+pub async fn run_validators(
+    config: &Config,
+    deployment_id: &ObjectId,
+    solution: &CreateSolutionResult,
+) -> Result<Vec<ValidationLog>, String> {
+    let validators: Vec<Arc<dyn DeploymentValidator>> = VALIDATORS.read().clone();
+    let mut logs = Vec::new();
+    for validator in validators {
+        let mut validator_logs = validator
+            .validate(config, deployment_id, solution)
+            .await
+            .map_err(|e| format!("validator '{}' failed: {}", validator.name(), e))?;
+        for log in &mut validator_logs {
+            log.validator.get_or_insert_with(|| validator.name().to_string());
+        }
+        logs.append(&mut validator_logs);
+    }
+    Ok(logs)
+}
+
+
+/// Built-in validator, checks each step against the zones and the node, module and data source cards
+pub struct ZoneCardValidator;
+
+impl DeploymentValidator for ZoneCardValidator {
+    fn name(&self) -> &str {
+        "zones"
+    }
+
+    fn validate<'a>(
+        &'a self,
+        config: &'a Config,
+        _deployment_id: &'a ObjectId,
+        solution: &'a CreateSolutionResult,
+    ) -> BoxFuture<'a, Result<Vec<ValidationLog>, String>> {
+        Box::pin(async move {
+            // Load the zone definitions, used to check which risk levels are allowed in which zone
+            let zone_policies = ZonePolicies::load(config).await?;
+
+            let mut output_risk = "none".to_string();
+            let mut logs: Vec<ValidationLog> = Vec::new();
+
+            // Validate each step in the deployment separately
+            for step in &solution.sequence {
+                if step.func.is_empty() {
+                    return Err("Device, module, or function missing in the step.".into());
+                }
+                let evaluation = evaluate_step(config, &step.device, &step.module, &step.func, None, &output_risk, &zone_policies).await?;
+                output_risk = evaluation.next_input_risk;
+                logs.push(evaluation.log);
+            }
+            Ok(logs)
+        })
+    }
+}
+
+
+/// Validator that sends the deployment as input to the OPA server at the policy url of the
+/// deployment settings, and expects the decision as either a plain boolean result, or
+/// `{ "allow": bool, "reasons": [string] }`. Without the url it has nothing to say.
+pub struct OpaValidator;
+
+impl DeploymentValidator for OpaValidator {
+    fn name(&self) -> &str {
+        "opa"
+    }
+
+    fn validate<'a>(
+        &'a self,
+        config: &'a Config,
+        deployment_id: &'a ObjectId,
+        solution: &'a CreateSolutionResult,
+    ) -> BoxFuture<'a, Result<Vec<ValidationLog>, String>> {
+        Box::pin(async move {
+            let Some(url) = config.deployments.policy_opa_url.as_ref() else {
+                return Ok(Vec::new());
+            };
+            let input = json!({
+                "input": {
+                    "deploymentId": deployment_id.to_hex(),
+                    "sequence": solution.sequence.iter().map(|s| json!({
+                        "device": s.device.to_hex(),
+                        "module": s.module.to_hex(),
+                        "func": s.func,
+                    })).collect::<Vec<_>>(),
+                }
+            });
+            let request = http_client::client(&config.http_client)
+                .post(url)
+                .json(&input)
+                .timeout(http_client::timeout(&config.http_client, Operation::PolicyDecision));
+            let res = send_traced(request, "policy decision")
+                .await
+                .map_err(|e| format!("request error to OPA: {e}"))?;
+            if !res.status().is_success() {
+                return Err(format!("HTTP {} from OPA", res.status().as_u16()));
+            }
+            let body: Value = res
+                .json()
+                .await
+                .map_err(|e| format!("invalid JSON from OPA: {e}"))?;
+
+            let result = body.get("result").ok_or("OPA returned no result (undefined decision)")?;
+            let (allow, mut reasons) = match result {
+                Value::Bool(allow) => (*allow, Vec::new()),
+                Value::Object(obj) => (
+                    obj.get("allow").and_then(Value::as_bool).unwrap_or(false),
+                    obj.get("reasons")
+                        .and_then(Value::as_array)
+                        .map(|a| a.iter().filter_map(|r| r.as_str().map(String::from)).collect())
+                        .unwrap_or_default(),
+                ),
+                _ => return Err("unexpected OPA result format".into()),
+            };
+            if reasons.is_empty() {
+                reasons.push(if allow { "Allowed by OPA policy." } else { "Denied by OPA policy." }.to_string());
+            }
+
+            Ok(vec![ValidationLog {
+                device: "none".into(),
+                module: "none".into(),
+                func: "none".into(),
+                node_zone: "none".into(),
+                module_risk: "none".into(),
+                input_risk: "none".into(),
+                output_risk: "none".into(),
+                valid: allow,
+                reasons,
+                validator: None,
+            }])
+        })
+    }
+}
@@ -0,0 +1,150 @@
+//! # revalidation.rs
+//!
+//! Re-runs deployment validation for already solved, active deployments when the
+//! policy inputs (node cards, module cards, data source cards or zones) change.
+//! Each revalidation issues a fresh deployment certificate. Deployments that became
+//! non-compliant are flagged with a `validationError`, and deactivated if the deployment
+//! settings say so.
+
+use std::sync::Arc;
+use actix_web::{web, HttpResponse, Responder};
+use futures::TryStreamExt;
+use log::{debug, error, info, warn};
+use mongodb::bson::{doc, oid::ObjectId, Document};
+use serde::Serialize;
+use crate::api::deployment::CreateSolutionResult;
+use crate::api::deployment_certificates::validate_deployment_solution;
+use crate::lib::config::Config;
+use crate::lib::constants::COLL_DEPLOYMENT;
+use crate::lib::errors::ApiError;
+use crate::lib::mongodb::get_collection;
+use crate::lib::revisions;
+use crate::lib::shutdown;
+use crate::api::deployment::emit_validation_failed;
+use crate::structs::deployment::DeploymentDoc;
+
+
+/// Which deployments are affected by a policy change
+#[derive(Debug, Clone)]
+pub enum RevalidationScope {
+    /// Deployments that use the given device (node and data source cards)
+    Device(ObjectId),
+    /// Deployments that use the given module (module cards)
+    Module(ObjectId),
+    /// All active deployments (zones, or changes that cant be tied to a single device or module)
+    All,
+}
+
+impl RevalidationScope {
+    /// Scope for a change of a card identified by a node id. Node ids that arent
+    /// device ObjectIds cant be tied to a deployment, so all deployments are checked.
+    pub fn for_node(nodeid: &str) -> Self {
+        match ObjectId::parse_str(nodeid) {
+            Ok(oid) => RevalidationScope::Device(oid),
+            Err(_) => RevalidationScope::All,
+        }
+    }
+
+    fn filter(&self) -> Document {
+        match self {
+            RevalidationScope::Device(id) => doc! {
+                "active": true,
+                format!("fullManifest.{}", id.to_hex()): { "$exists": true }
+            },
+            RevalidationScope::Module(id) => doc! { "active": true, "sequence.module": id },
+            RevalidationScope::All => doc! { "active": true },
+        }
+    }
+}
+
+
+/// Results of a single revalidation run
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RevalidationSummary {
+    pub checked: usize,
+    pub valid: Vec<String>,
+    pub invalid: Vec<String>,
+    pub deactivated: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+
+/// Revalidates all active deployments that fall in the given scope.
+pub async fn revalidate_deployments(config: &Config, scope: &RevalidationScope, reason: &str) -> Result<RevalidationSummary, String> {
+    let coll = get_collection::<DeploymentDoc>(&config.database, COLL_DEPLOYMENT).await;
+    let deployments: Vec<DeploymentDoc> = coll
+        .find(scope.filter())
+        .await
+        .map_err(|e| format!("deployment.find error: {e}"))?
+        .try_collect()
+        .await
+        .map_err(|e| format!("deployment cursor error: {e}"))?;
+
+    debug!("Revalidating {} active deployment(s) ({})", deployments.len(), reason);
+    let mut summary = RevalidationSummary::default();
+    let raw_coll = get_collection::<Document>(&config.database, COLL_DEPLOYMENT).await;
+
+    for deployment in deployments {
+        let Some(id) = deployment.id else { continue };
+        summary.checked += 1;
+        let solution = CreateSolutionResult {
+            full_manifest: deployment.full_manifest,
+            sequence: deployment.sequence,
+        };
+
+        let update = match validate_deployment_solution(config, &id, &solution).await {
+            Ok(()) => {
+                summary.valid.push(id.to_hex());
+                doc! { "$unset": { "validationError": "" } }
+            }
+            Err(err) => {
+                warn!("⚠️ Deployment '{}' is no longer compliant after {}: {}", deployment.name, reason, err);
+                emit_validation_failed(&id, &deployment.name, &err, "revalidation");
+                summary.invalid.push(id.to_hex());
+                if config.deployments.revalidation_deactivate {
+                    summary.deactivated.push(id.to_hex());
+                    doc! { "$set": { "validationError": err, "active": false } }
+                } else {
+                    doc! { "$set": { "validationError": err } }
+                }
+            }
+        };
+        if let Err(e) = raw_coll.update_one(doc! { "_id": &id }, update).await {
+            error!("Failed to store revalidation result of deployment '{}': {}", deployment.name, e);
+            summary.errors.push(format!("{}: {}", id.to_hex(), e));
+        }
+        revisions::bump(COLL_DEPLOYMENT);
+    }
+
+    if !summary.invalid.is_empty() {
+        info!(
+            "Revalidation after {}: {} checked, {} non-compliant, {} deactivated",
+            reason, summary.checked, summary.invalid.len(), summary.deactivated.len()
+        );
+    }
+    Ok(summary)
+}
+
+
+/// Starts revalidation of the affected deployments in the background, so that the
+/// request that changed the policy doesnt have to wait for it. Shutdown waits for it, so
+/// that deployments arent left half deactivated.
+pub fn trigger_revalidation(config: &Arc<Config>, scope: RevalidationScope, reason: impl Into<String>) {
+    let (config, reason) = (config.clone(), reason.into());
+    shutdown::spawn_tracked(async move {
+        if let Err(e) = revalidate_deployments(&config, &scope, &reason).await {
+            error!("❌ Revalidation of deployments after {} failed: {}", reason, e);
+        }
+    });
+}
+
+
+/// POST /deploymentCertificates/revalidate
+///
+/// Revalidates all active deployments immediately and returns a summary of the results.
+pub async fn revalidate_all_deployments(config: web::Data<Config>) -> Result<impl Responder, ApiError> {
+    let summary = revalidate_deployments(&config, &RevalidationScope::All, "manual revalidation request")
+        .await
+        .map_err(ApiError::internal_error)?;
+    Ok(HttpResponse::Ok().json(summary))
+}
@@ -0,0 +1,190 @@
+//! # stats.rs
+//!
+//! Aggregated counts for the dashboard, so that the frontend doesnt have to pull whole
+//! collections to compute them. `/stats` has the counts, `/dashboard` everything the front
+//! page of the UI shows in a single payload.
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use actix_web::{web, Responder};
+use chrono::Utc;
+use futures::TryStreamExt;
+use mongodb::bson::{doc, Bson, Document};
+use serde_json::json;
+use crate::api::device::last_health_check_round;
+use crate::lib::config::Config;
+use crate::lib::constants::{COLL_DEPLOYMENT, COLL_DEVICE, COLL_LOGS, COLL_MODULE};
+use crate::lib::errors::ApiError;
+use crate::lib::execution_queue;
+use crate::lib::mongodb::get_collection;
+use crate::lib::response::ok_json;
+use crate::lib::discovery;
+
+
+/// Error logs returned by the dashboard unless the query asks for another amount
+const DEFAULT_DASHBOARD_ERRORS: i64 = 10;
+const MAX_DASHBOARD_ERRORS: i64 = 100;
+
+
+/// GET /stats
+///
+/// Returns device counts by status, failed health checks, module and deployment counts,
+/// executions started in the last 24 hours (since the orchestrator was started) along with
+/// the running and queued ones, and the latest log timestamps overall and by log level.
+pub async fn get_stats(config: web::Data<Config>) -> Result<impl Responder, ApiError> {
+    let devices = get_collection::<Document>(&config.database, COLL_DEVICE).await;
+    let (device_total, by_status) = device_status_counts(&devices).await?;
+    let failing: Vec<Document> = devices
+        .aggregate(vec![
+            doc! { "$match": { "failed_health_check_count": { "$gt": 0 } } },
+            doc! { "$group": { "_id": Bson::Null, "devices": { "$sum": 1 }, "failedChecks": { "$sum": "$failed_health_check_count" } } },
+        ])
+        .await
+        .map_err(ApiError::db)?
+        .try_collect()
+        .await
+        .map_err(ApiError::db)?;
+    let failing = failing.first();
+
+    let modules = get_collection::<Document>(&config.database, COLL_MODULE).await;
+    let module_count = modules.count_documents(doc! {}).await.map_err(ApiError::db)?;
+
+    let deployments = get_collection::<Document>(&config.database, COLL_DEPLOYMENT).await;
+    let deployment_count = deployments.count_documents(doc! {}).await.map_err(ApiError::db)?;
+    let active_deployments = deployments.count_documents(doc! { "active": true }).await.map_err(ApiError::db)?;
+    let invalid_deployments = deployments
+        .count_documents(doc! { "validationError": { "$exists": true, "$ne": Bson::Null } })
+        .await
+        .map_err(ApiError::db)?;
+
+    let queue = execution_queue::snapshot(&config.execution);
+
+    let logs = get_collection::<Document>(&config.database, COLL_LOGS).await;
+    let latest_logs: Vec<Document> = logs
+        .aggregate(vec![
+            doc! { "$group": { "_id": "$loglevel", "latest": { "$max": "$dateReceived" } } },
+        ])
+        .await
+        .map_err(ApiError::db)?
+        .try_collect()
+        .await
+        .map_err(ApiError::db)?;
+    let mut latest_by_level = BTreeMap::new();
+    for entry in &latest_logs {
+        if let (Ok(level), Ok(latest)) = (entry.get_str("_id"), entry.get_datetime("latest")) {
+            latest_by_level.insert(level.to_string(), latest.to_chrono());
+        }
+    }
+    let latest_log = latest_by_level.values().max().copied();
+
+    ok_json(&json!({
+        "devices": {
+            "total": device_total,
+            "byStatus": by_status,
+            "withFailedHealthChecks": failing.and_then(|d| number(d, "devices")).unwrap_or(0),
+            "failedHealthChecks": failing.and_then(|d| number(d, "failedChecks")).unwrap_or(0),
+        },
+        "modules": module_count,
+        "deployments": {
+            "total": deployment_count,
+            "active": active_deployments,
+            "withValidationError": invalid_deployments,
+        },
+        "executions": {
+            "last24h": execution_queue::started_within(Duration::from_secs(24 * 60 * 60)),
+            "running": queue.running.len(),
+            "pending": queue.pending.len(),
+        },
+        "logs": {
+            "latest": latest_log,
+            "latestByLevel": latest_by_level,
+        },
+    }))
+}
+
+
+/// GET /dashboard
+///
+/// Returns in a single payload what the front page of the UI shows: device counts by status,
+/// deployment counts, the running, queued and latest finished executions, the latest error
+/// logs (`?errors=<n>`, 10 by default), and when discovery and health checks last ran.
+pub async fn get_dashboard(config: web::Data<Config>, query: web::Query<HashMap<String, String>>) -> Result<impl Responder, ApiError> {
+    let error_limit = match query.get("errors") {
+        Some(n) => n.parse::<i64>().ok().filter(|n| (1..=MAX_DASHBOARD_ERRORS).contains(n)).ok_or_else(|| {
+            ApiError::bad_request(format!("errors must be a number between 1 and {}", MAX_DASHBOARD_ERRORS))
+        })?,
+        None => DEFAULT_DASHBOARD_ERRORS,
+    };
+
+    let devices = get_collection::<Document>(&config.database, COLL_DEVICE).await;
+    let deployments = get_collection::<Document>(&config.database, COLL_DEPLOYMENT).await;
+    let logs = get_collection::<Document>(&config.database, COLL_LOGS).await;
+    let recent_errors = async {
+        logs.find(doc! { "loglevel": { "$regex": "^error$", "$options": "i" } })
+            .sort(doc! { "dateReceived": -1 })
+            .limit(error_limit)
+            .await?
+            .try_collect::<Vec<Document>>()
+            .await
+    };
+    let ((device_total, by_status), deployment_count, active_deployments, recent_errors) = futures::try_join!(
+        device_status_counts(&devices),
+        async { deployments.count_documents(doc! {}).await.map_err(ApiError::db) },
+        async { deployments.count_documents(doc! { "active": true }).await.map_err(ApiError::db) },
+        async { recent_errors.await.map_err(ApiError::db) },
+    )?;
+    let queue = execution_queue::snapshot(&config.execution);
+
+    ok_json(&json!({
+        "devices": {
+            "total": device_total,
+            "byStatus": by_status,
+        },
+        "deployments": {
+            "total": deployment_count,
+            "active": active_deployments,
+        },
+        "executions": {
+            "running": queue.running,
+            "pending": queue.pending,
+            "recent": execution_queue::recent_executions(),
+        },
+        "recentErrors": recent_errors,
+        "lastDiscoveryScan": discovery::last_scan(),
+        "lastHealthCheck": last_health_check_round(),
+        "generatedAt": Utc::now(),
+    }))
+}
+
+
+/// Number of devices in total and by status
+async fn device_status_counts(devices: &mongodb::Collection<Document>) -> Result<(i64, BTreeMap<String, i64>), ApiError> {
+    let by_status = group_counts(devices, doc! { "$ifNull": ["$status", "unknown"] }).await?;
+    Ok((by_status.values().sum(), by_status))
+}
+
+
+/// Number of documents in the collection for each value of the grouping expression
+async fn group_counts(coll: &mongodb::Collection<Document>, key: Document) -> Result<BTreeMap<String, i64>, ApiError> {
+    let groups: Vec<Document> = coll
+        .aggregate(vec![doc! { "$group": { "_id": key, "count": { "$sum": 1 } } }])
+        .await
+        .map_err(ApiError::db)?
+        .try_collect()
+        .await
+        .map_err(ApiError::db)?;
+    Ok(groups
+        .iter()
+        .filter_map(|g| Some((g.get_str("_id").ok()?.to_string(), number(g, "count")?)))
+        .collect())
+}
+
+
+/// Integer field of an aggregation result, which can be either 32 or 64 bits
+fn number(doc: &Document, key: &str) -> Option<i64> {
+    match doc.get(key)? {
+        Bson::Int32(n) => Some(*n as i64),
+        Bson::Int64(n) => Some(*n),
+        _ => None,
+    }
+}
@@ -0,0 +1,125 @@
+//! # storage.rs
+//!
+//! Contains disk usage reporting for files stored by the orchestrator
+//! (wasm modules, mounts, execution inputs, outputs and results), and the quota check
+//! used to reject uploads once the configured limit has been reached.
+
+use std::fs;
+use std::path::Path;
+use actix_web::{web, HttpResponse, Responder};
+use serde::Serialize;
+use log::{debug, warn};
+use crate::lib::constants::{
+    MODULE_DIR,
+    MOUNT_DIR,
+    EXECUTION_INPUT_DIR,
+    EXECUTION_INPUT_TMP_DIR,
+    EXECUTION_OUTPUT_DIR,
+    EXECUTION_RESULT_DIR
+};
+use crate::lib::config::{Config, StorageConfig};
+use crate::lib::errors::ApiError;
+
+
+/// Bytes used by each category of stored files, and the configured quota.
+#[derive(Debug, Clone, Serialize)]
+pub struct StorageReport {
+    #[serde(rename = "wasmBytes")]
+    pub wasm_bytes: u64,
+    #[serde(rename = "mountBytes")]
+    pub mount_bytes: u64,
+    #[serde(rename = "executionInputBytes")]
+    pub execution_input_bytes: u64, // Uploads of running executions, and inputs stored for rerunning them
+    #[serde(rename = "executionOutputBytes")]
+    pub execution_output_bytes: u64,
+    #[serde(rename = "executionResultBytes")]
+    pub execution_result_bytes: u64, // Results too large to be stored inline
+    #[serde(rename = "totalBytes")]
+    pub total_bytes: u64,
+    #[serde(rename = "quotaBytes")]
+    pub quota_bytes: Option<u64>, // None when no quota has been configured
+    #[serde(rename = "quotaExceeded")]
+    pub quota_exceeded: bool,
+}
+
+
+/// Recursively calculates the size of all files in a directory.
+/// Missing directories are counted as empty.
+pub fn dir_size(path: &Path) -> u64 {
+    let entries = match fs::read_dir(path) {
+        Ok(it) => it,
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("Failed to read directory '{}' for size calculation: {}", path.display(), e);
+            }
+            return 0;
+        }
+    };
+    let mut total = 0u64;
+    for entry in entries.flatten() {
+        match entry.file_type() {
+            Ok(t) if t.is_dir() => total += dir_size(&entry.path()),
+            Ok(t) if t.is_file() => total += entry.metadata().map(|m| m.len()).unwrap_or(0),
+            _ => {}
+        }
+    }
+    total
+}
+
+
+/// Gathers the current disk usage of all files stored by the orchestrator. The directories are
+/// walked on the blocking thread pool so that large stores dont stall the async workers.
+pub async fn storage_report(config: &StorageConfig) -> Result<StorageReport, ApiError> {
+    let quota_bytes = (config.quota_bytes > 0).then_some(config.quota_bytes);
+    web::block(move || measure_storage(quota_bytes))
+        .await
+        .map_err(ApiError::internal_error)
+}
+
+
+/// Helper function that walks the storage directories, see `storage_report`
+fn measure_storage(quota_bytes: Option<u64>) -> StorageReport {
+    let wasm_bytes = dir_size(Path::new(MODULE_DIR));
+    let mount_bytes = dir_size(Path::new(MOUNT_DIR));
+    let execution_input_bytes = dir_size(&EXECUTION_INPUT_TMP_DIR) + dir_size(Path::new(EXECUTION_INPUT_DIR));
+    let execution_output_bytes = dir_size(Path::new(EXECUTION_OUTPUT_DIR));
+    let execution_result_bytes = dir_size(Path::new(EXECUTION_RESULT_DIR));
+    let total_bytes = wasm_bytes + mount_bytes + execution_input_bytes + execution_output_bytes + execution_result_bytes;
+    StorageReport {
+        wasm_bytes,
+        mount_bytes,
+        execution_input_bytes,
+        execution_output_bytes,
+        execution_result_bytes,
+        total_bytes,
+        quota_bytes,
+        quota_exceeded: quota_bytes.map(|q| total_bytes > q).unwrap_or(false),
+    }
+}
+
+
+/// Checks that storing `incoming_bytes` more on top of the current usage (`used_bytes`)
+/// would not exceed the configured quota. Always succeeds if no quota is configured.
+pub fn check_quota(config: &StorageConfig, used_bytes: u64, incoming_bytes: u64) -> Result<(), ApiError> {
+    let quota = config.quota_bytes;
+    if quota == 0 {
+        return Ok(());
+    }
+    if used_bytes + incoming_bytes > quota {
+        debug!("Storage quota check failed: used {} + incoming {} > quota {}", used_bytes, incoming_bytes, quota);
+        return Err(ApiError::insufficient_storage(format!(
+            "storage quota of {} bytes exceeded (currently used: {} bytes), delete modules or raise STORAGE_QUOTA_BYTES",
+            quota, used_bytes
+        )));
+    }
+    Ok(())
+}
+
+
+/// GET /admin/storage
+///
+/// Returns how many bytes the orchestrator uses for wasm modules, mounts,
+/// execution inputs and outputs, as well as the configured quota.
+pub async fn get_storage_usage(config: web::Data<Config>) -> Result<impl Responder, ApiError> {
+    Ok(HttpResponse::Ok().json(storage_report(&config.storage).await?))
+}
@@ -0,0 +1,293 @@
+//! # trash.rs
+//!
+//! Deleted modules and deployments are moved to the trash collection instead of being removed
+//! right away, so that they can be restored. Documents deleted along with them (module cards,
+//! deployment certificates) are kept in the same entry, and files of the module stay on disk.
+//! Entries are purged with their files after the trash retention of the storage settings by
+//! `run_trash_purge_loop`.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use actix_web::{web, HttpResponse, Responder};
+use chrono::Utc;
+use futures::TryStreamExt;
+use log::{debug, error, info, warn};
+use mongodb::bson::{doc, oid::ObjectId, Document};
+use crate::api::execution_outputs::remove_deployment_outputs;
+use crate::api::revalidation::{trigger_revalidation, RevalidationScope};
+use crate::lib::constants::{
+    COLL_DEPLOYMENT,
+    COLL_DEPLOYMENT_CERTS,
+    COLL_MODULE,
+    COLL_MODULE_CARDS,
+    COLL_TRASH
+};
+use crate::lib::config::Config;
+use crate::lib::errors::ApiError;
+use crate::lib::mongodb::{get_collection, is_duplicate_key};
+use crate::lib::pagination::{find_page, page_response, Pagination};
+use crate::lib::revisions;
+use crate::lib::watchdog;
+use crate::structs::module::ModuleDoc;
+use crate::structs::trash::{TrashEntry, TrashKind, TrashedDocument};
+
+
+/// Sort keys accepted by the trash listing
+const TRASH_SORT_FIELDS: &[(&str, &str)] = &[
+    ("deletedAt", "deletedAt"),
+    ("purgeAt", "purgeAt"),
+    ("name", "name"),
+];
+
+
+/// Moves a module and its card to the trash. The files of the module are kept until the
+/// entry is purged.
+pub async fn trash_module(config: &Arc<Config>, module: &ModuleDoc) -> Result<(), String> {
+    let id = module.id.ok_or_else(|| format!("module '{}' has no id", module.name))?;
+    let document = get_collection::<Document>(&config.database, COLL_MODULE).await
+        .find_one(doc! { "_id": id })
+        .await
+        .map_err(|e| format!("module.findOne error: {e}"))?
+        .ok_or_else(|| format!("module '{}' not found", module.name))?;
+    let related = find_related(config, COLL_MODULE_CARDS, doc! { "moduleid": id }).await?;
+
+    let mut files = vec![module.wasm.path.clone()];
+    files.extend(module.data_files.iter().flatten().map(|(_, f)| f.path.clone()));
+
+    move_to_trash(config, TrashKind::Module, id, &module.name, document, related, files).await?;
+    revisions::bump(COLL_MODULE);
+    revisions::bump(COLL_MODULE_CARDS);
+    trigger_revalidation(&config, RevalidationScope::Module(id), "module deletion");
+    Ok(())
+}
+
+
+/// Moves a deployment and its certificates to the trash. Execution outputs of the
+/// deployment are kept until the entry is purged. Returns how many certificates were moved.
+pub async fn trash_deployment(config: &Config, document: Document) -> Result<usize, String> {
+    let id = document
+        .get_object_id("_id")
+        .map_err(|e| format!("deployment has no id: {e}"))?;
+    let name = document.get_str("name").unwrap_or_default().to_string();
+    let related = find_related(config, COLL_DEPLOYMENT_CERTS, doc! { "deploymentId": id }).await?;
+    let certificates = related.len();
+
+    move_to_trash(config, TrashKind::Deployment, id, &name, document, related, Vec::new()).await?;
+    revisions::bump(COLL_DEPLOYMENT);
+    Ok(certificates)
+}
+
+
+/// GET /trash
+///
+/// Lists the deleted modules and deployments that can still be restored. Supports `kind`
+/// (`module` or `deployment`), and `limit`, `offset` and `sort` (`deletedAt`, `purgeAt` or
+/// `name`, newest deletions first by default), see `Pagination`.
+pub async fn get_trash(config: web::Data<Config>, query: web::Query<HashMap<String, String>>) -> Result<impl Responder, ApiError> {
+    let pagination = Pagination::from_query(&query, TRASH_SORT_FIELDS, Some(doc! { "deletedAt": -1 }))?;
+    let mut filter = doc! {};
+    if let Some(kind) = query.get("kind") {
+        if kind != "module" && kind != "deployment" {
+            return Err(ApiError::bad_request(format!("invalid kind '{}', expected module or deployment", kind)));
+        }
+        filter.insert("kind", kind);
+    }
+    let coll = get_collection::<TrashEntry>(&config.database, COLL_TRASH).await;
+    let page = find_page(&coll, filter, &pagination).await?;
+    page_response(&page)
+}
+
+
+/// POST /trash/{trash_id}/restore
+///
+/// Restores a deleted module or deployment with its original id, along with the documents
+/// deleted with it. Responds with 409 if a module or deployment with the same id or name has
+/// been created since. Restored deployments are left inactive.
+pub async fn restore_trash_entry(config: web::Data<Config>, path: web::Path<String>) -> Result<impl Responder, ApiError> {
+    let trash_id = path.into_inner();
+    let oid = ObjectId::parse_str(&trash_id)
+        .map_err(|_| ApiError::bad_request(format!("invalid trash id '{}'", trash_id)))?;
+    let coll = get_collection::<TrashEntry>(&config.database, COLL_TRASH).await;
+    let entry = coll
+        .find_one(doc! { "_id": oid })
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("no trash entry matches id '{}'", trash_id)))?;
+
+    let collection = kind_collection(entry.kind);
+    let mut document = entry.document.clone();
+    if entry.kind == TrashKind::Deployment {
+        // Devices may have changed while it was deleted, so it has to be deployed again
+        document.insert("active", false);
+    }
+    match get_collection::<Document>(&config.database, collection).await.insert_one(document).await {
+        Ok(_) => {}
+        Err(e) if is_duplicate_key(&e) => {
+            return Err(ApiError::conflict(format!(
+                "a {} with the id or name of '{}' already exists",
+                collection, entry.name
+            )));
+        }
+        Err(e) => return Err(ApiError::db(e)),
+    }
+    revisions::bump(collection);
+
+    for related in &entry.related {
+        match get_collection::<Document>(&config.database, &related.collection).await.insert_one(related.document.clone()).await {
+            Ok(_) => revisions::bump(&related.collection),
+            Err(e) if is_duplicate_key(&e) => {
+                warn!("Not restoring a document of '{}' in '{}', it has been replaced: {}", entry.name, related.collection, e);
+            }
+            Err(e) => error!("Failed to restore a document of '{}' in '{}': {}", entry.name, related.collection, e),
+        }
+    }
+    if entry.kind == TrashKind::Module {
+        trigger_revalidation(&config, RevalidationScope::Module(entry.original_id), "module restore");
+    }
+
+    coll.delete_one(doc! { "_id": oid }).await.map_err(ApiError::db)?;
+    revisions::bump(COLL_TRASH);
+    info!("♻️ Restored {} '{}' from the trash", collection, entry.name);
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "kind": entry.kind,
+        "id": entry.original_id.to_hex(),
+        "name": entry.name,
+        "restoredRelated": entry.related.len(),
+    })))
+}
+
+
+/// Purges the entries whose retention has passed, with their files and execution outputs.
+/// Returns how many were purged.
+pub async fn purge_trash(config: &Config) -> Result<usize, String> {
+    let coll = get_collection::<TrashEntry>(&config.database, COLL_TRASH).await;
+    let expired: Vec<TrashEntry> = coll
+        .find(doc! { "purgeAt": { "$lte": mongodb::bson::DateTime::now() } })
+        .await
+        .map_err(|e| format!("trash.find error: {e}"))?
+        .try_collect()
+        .await
+        .map_err(|e| format!("trash cursor error: {e}"))?;
+
+    let mut purged = 0;
+    for entry in expired {
+        for file in &entry.files {
+            match tokio::fs::remove_file(file).await {
+                Ok(()) => debug!("🗑️ Deleted file: {}", file),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => warn!("Failed to delete file '{}' of purged {:?} '{}': {}", file, entry.kind, entry.name, e),
+            }
+        }
+        if entry.kind == TrashKind::Deployment {
+            if let Err(e) = remove_deployment_outputs(config, Some(&entry.original_id)).await {
+                warn!("Failed deleting execution outputs of purged deployment '{}': {}", entry.name, e);
+            }
+        }
+        match coll.delete_one(doc! { "_id": entry.id }).await {
+            Ok(_) => purged += 1,
+            Err(e) => error!("Failed to purge trash entry of '{}': {}", entry.name, e),
+        }
+    }
+    if purged > 0 {
+        revisions::bump(COLL_TRASH);
+    }
+    Ok(purged)
+}
+
+
+/// Continous loop purging expired trash entries
+pub async fn run_trash_purge_loop(config: Arc<Config>) {
+    loop {
+        match purge_trash(&config).await {
+            Ok(0) => debug!("✅ Trash purge done, nothing to purge"),
+            Ok(n) => info!("🗑️ Trash purge removed {} entries", n),
+            Err(e) => error!("Trash purge failed: {}", e),
+        }
+        watchdog::heartbeat(watchdog::LOOP_TRASH_PURGE);
+        tokio::time::sleep(std::time::Duration::from_secs(config.storage.trash_purge_interval_s)).await;
+    }
+}
+
+
+/// Files of modules in the trash, which the startup consistency check must leave alone
+pub async fn trashed_files(config: &Config) -> Result<Vec<String>, String> {
+    let entries: Vec<TrashEntry> = get_collection::<TrashEntry>(&config.database, COLL_TRASH).await
+        .find(doc! { "kind": "module" })
+        .await
+        .map_err(|e| format!("trash.find error: {e}"))?
+        .try_collect()
+        .await
+        .map_err(|e| format!("trash cursor error: {e}"))?;
+    Ok(entries.into_iter().flat_map(|e| e.files).collect())
+}
+
+
+/// Documents of the collection matching the filter, to be trashed along with another
+async fn find_related(config: &Config, collection: &str, filter: Document) -> Result<Vec<TrashedDocument>, String> {
+    let documents: Vec<Document> = get_collection::<Document>(&config.database, collection).await
+        .find(filter)
+        .await
+        .map_err(|e| format!("{collection}.find error: {e}"))?
+        .try_collect()
+        .await
+        .map_err(|e| format!("{collection} cursor error: {e}"))?;
+    Ok(documents
+        .into_iter()
+        .map(|document| TrashedDocument { collection: collection.to_string(), document })
+        .collect())
+}
+
+
+/// Collection the documents of the kind are deleted from and restored to
+fn kind_collection(kind: TrashKind) -> &'static str {
+    match kind {
+        TrashKind::Module => COLL_MODULE,
+        TrashKind::Deployment => COLL_DEPLOYMENT,
+    }
+}
+
+
+/// Stores the trash entry, then deletes the document and the related documents. The entry is
+/// removed again if deleting the document fails, so nothing ends up in both places.
+async fn move_to_trash(
+    config: &Config,
+    kind: TrashKind,
+    id: ObjectId,
+    name: &str,
+    document: Document,
+    related: Vec<TrashedDocument>,
+    files: Vec<String>,
+) -> Result<(), String> {
+    let collection = kind_collection(kind);
+    let now = Utc::now();
+    let entry = TrashEntry {
+        id: None,
+        kind,
+        original_id: id,
+        name: name.to_string(),
+        deleted_at: now,
+        purge_at: now + chrono::Duration::days(config.storage.trash_retention_days as i64),
+        document,
+        related,
+        files,
+    };
+    let trash = get_collection::<TrashEntry>(&config.database, COLL_TRASH).await;
+    let inserted = trash
+        .insert_one(&entry)
+        .await
+        .map_err(|e| format!("trash.insertOne error: {e}"))?;
+
+    if let Err(e) = get_collection::<Document>(&config.database, collection).await.delete_one(doc! { "_id": id }).await {
+        let _ = trash.delete_one(doc! { "_id": inserted.inserted_id }).await;
+        return Err(format!("{collection}.deleteOne error: {e}"));
+    }
+    for related in &entry.related {
+        let Ok(related_id) = related.document.get_object_id("_id") else { continue };
+        if let Err(e) = get_collection::<Document>(&config.database, &related.collection).await.delete_one(doc! { "_id": related_id }).await {
+            warn!("Failed to delete a document of '{}' from '{}': {}", name, related.collection, e);
+        }
+    }
+    revisions::bump(COLL_TRASH);
+    info!("🗑️ Moved {} '{}' to the trash", collection, name);
+    Ok(())
+}
@@ -0,0 +1,230 @@
+use actix_web::{web, HttpRequest, Responder};
+use chrono::Utc;
+use futures::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId, Document};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use log::{info, error};
+use crate::lib::card_auth::bearer_token;
+use crate::lib::config::Config;
+use crate::lib::constants::COLL_USERS;
+use crate::lib::errors::ApiError;
+use crate::lib::mongodb::{find_one, get_collection, is_duplicate_key};
+use crate::lib::response::{ok_json, to_normalized_value};
+use crate::lib::user_auth::{decode_token, hash_password, issue_token, token_user, verify_password};
+use crate::structs::users::{User, UserRole};
+
+
+/// Shortest password accepted for users
+const MIN_PASSWORD_LEN: usize = 8;
+
+
+/// Body of a login request
+#[derive(Debug, Deserialize)]
+pub struct LoginRequest {
+    pub username: String,
+    pub password: String,
+}
+
+
+/// Body of a user creation request
+#[derive(Debug, Deserialize)]
+pub struct UserRequest {
+    pub username: String,
+    pub password: String,
+    pub role: UserRole,
+}
+
+
+/// Body of a user update request, fields not given are kept
+#[derive(Debug, Deserialize)]
+pub struct UserUpdateRequest {
+    pub password: Option<String>,
+    pub role: Option<UserRole>,
+}
+
+
+/// POST /auth/login
+///
+/// Checks the username and password, and returns a JWT to send as `Authorization: Bearer`
+/// along with when it expires.
+pub async fn login(config: web::Data<Config>, body: web::Json<LoginRequest>) -> Result<impl Responder, ApiError> {
+    let body = body.into_inner();
+    let user = find_one::<User>(&config.database, COLL_USERS, doc! { "username": body.username.as_str() })
+        .await
+        .map_err(ApiError::db)?;
+    // Hashing is slow on purpose, so it is kept off the server threads
+    let password_hash = user.as_ref().map(|u| u.password_hash.clone());
+    let valid = web::block(move || password_hash.is_some_and(|hash| verify_password(&body.password, &hash)))
+        .await
+        .map_err(ApiError::internal_error)?;
+    let Some(user) = user.filter(|_| valid) else {
+        return Err(ApiError::unauthorized("invalid username or password"));
+    };
+
+    let (token, expires_at) = issue_token(&config.auth, &user)?;
+    if let Some(id) = user.id {
+        let collection = get_collection::<User>(&config.database, COLL_USERS).await;
+        let update = doc! { "$set": { "lastLoginAt": mongodb::bson::DateTime::from_chrono(Utc::now()) } };
+        if let Err(e) = collection.update_one(doc! { "_id": id }, update).await {
+            error!("Failed to update last login of user '{}': {}", user.username, e);
+        }
+    }
+    info!("👤 User '{}' logged in", user.username);
+    ok_json(&json!({ "token": token, "expiresAt": expires_at, "user": without_password(&user)? }))
+}
+
+
+/// GET /auth/me
+///
+/// Returns the user of the token in the `Authorization: Bearer` header.
+pub async fn get_current_user(config: web::Data<Config>, req: HttpRequest) -> Result<impl Responder, ApiError> {
+    let claims = bearer_token(&req)
+        .and_then(|token| decode_token(&config.auth, token))
+        .ok_or_else(|| ApiError::unauthorized("a valid user token is needed (Authorization: Bearer <token>)"))?;
+    let user = token_user(&config, &claims).await?;
+    ok_json(&without_password(&user)?)
+}
+
+
+/// GET /users
+///
+/// Lists the users, without their password hashes.
+pub async fn get_users(config: web::Data<Config>) -> Result<impl Responder, ApiError> {
+    let collection = get_collection::<User>(&config.database, COLL_USERS).await;
+    let users: Vec<User> = collection
+        .find(doc! {})
+        .sort(doc! { "username": 1 })
+        .await
+        .map_err(ApiError::db)?
+        .try_collect()
+        .await
+        .map_err(ApiError::db)?;
+    let users = users.iter().map(without_password).collect::<Result<Vec<_>, _>>()?;
+    ok_json(&users)
+}
+
+
+/// POST /users
+///
+/// Creates a user with the role `viewer`, `operator` or `admin`.
+pub async fn create_user(config: web::Data<Config>, body: web::Json<UserRequest>) -> Result<impl Responder, ApiError> {
+    let body = body.into_inner();
+    let username = body.username.trim().to_string();
+    if username.is_empty() {
+        return Err(ApiError::bad_request("username must not be empty"));
+    }
+    check_password(&body.password)?;
+    let password = body.password;
+    let password_hash = web::block(move || hash_password(&password)).await.map_err(ApiError::internal_error)??;
+
+    let mut user = User {
+        id: None,
+        username,
+        password_hash,
+        role: body.role,
+        created_at: Utc::now(),
+        last_login_at: None,
+    };
+    let collection = get_collection::<User>(&config.database, COLL_USERS).await;
+    let result = collection.insert_one(&user).await.map_err(|e| {
+        if is_duplicate_key(&e) {
+            return ApiError::conflict(format!("a user named '{}' already exists", user.username));
+        }
+        error!("Failed to save user: {}", e);
+        ApiError::db("Failed to save user")
+    })?;
+    user.id = result.inserted_id.as_object_id();
+    info!("👤 User '{}' created with the {} role", user.username, user.role.as_str());
+    ok_json(&without_password(&user)?)
+}
+
+
+/// PUT /users/{user_id}
+///
+/// Changes the password or role of a user. The last admin cant be demoted.
+pub async fn update_user(config: web::Data<Config>, path: web::Path<String>, body: web::Json<UserUpdateRequest>) -> Result<impl Responder, ApiError> {
+    let oid = parse_user_id(&path)?;
+    let body = body.into_inner();
+    let user = find_one::<User>(&config.database, COLL_USERS, doc! { "_id": oid })
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("User with id {} not found", oid)))?;
+
+    let mut set = Document::new();
+    if let Some(role) = body.role {
+        if user.role == UserRole::Admin && role != UserRole::Admin {
+            ensure_other_admins(&config, oid).await?;
+        }
+        set.insert("role", role.as_str());
+    }
+    if let Some(password) = body.password {
+        check_password(&password)?;
+        let password_hash = web::block(move || hash_password(&password)).await.map_err(ApiError::internal_error)??;
+        set.insert("passwordHash", password_hash);
+    }
+    if set.is_empty() {
+        return Err(ApiError::bad_request("expected at least one of: password, role"));
+    }
+
+    let collection = get_collection::<User>(&config.database, COLL_USERS).await;
+    collection.update_one(doc! { "_id": oid }, doc! { "$set": set }).await.map_err(ApiError::db)?;
+    info!("👤 User '{}' updated", user.username);
+    ok_json(&json!({ "message": "User updated", "id": oid.to_hex() }))
+}
+
+
+/// DELETE /users/{user_id}
+///
+/// Deletes a user. Tokens of the user stop working right away. The last admin cant be deleted.
+pub async fn delete_user(config: web::Data<Config>, path: web::Path<String>) -> Result<impl Responder, ApiError> {
+    let oid = parse_user_id(&path)?;
+    let user = find_one::<User>(&config.database, COLL_USERS, doc! { "_id": oid })
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("User with id {} not found", oid)))?;
+    if user.role == UserRole::Admin {
+        ensure_other_admins(&config, oid).await?;
+    }
+    let collection = get_collection::<User>(&config.database, COLL_USERS).await;
+    collection.delete_one(doc! { "_id": oid }).await.map_err(ApiError::db)?;
+    info!("👤 User '{}' deleted", user.username);
+    ok_json(&json!({ "message": "User deleted", "id": oid.to_hex() }))
+}
+
+
+fn parse_user_id(user_id: &str) -> Result<ObjectId, ApiError> {
+    ObjectId::parse_str(user_id).map_err(|_| ApiError::bad_request("Invalid user id (expected ObjectId hex string)"))
+}
+
+
+fn check_password(password: &str) -> Result<(), ApiError> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(ApiError::bad_request(format!("password must be at least {} characters", MIN_PASSWORD_LEN)));
+    }
+    Ok(())
+}
+
+
+/// Fails if the user is the only admin, so that the API cant be locked out of user management
+async fn ensure_other_admins(config: &Config, user_id: ObjectId) -> Result<(), ApiError> {
+    let collection = get_collection::<User>(&config.database, COLL_USERS).await;
+    let others = collection
+        .count_documents(doc! { "role": UserRole::Admin.as_str(), "_id": { "$ne": user_id } })
+        .await
+        .map_err(ApiError::db)?;
+    if others == 0 {
+        return Err(ApiError::conflict("the last admin cant be removed or demoted"));
+    }
+    Ok(())
+}
+
+
+/// The user as JSON without the password hash
+fn without_password(user: &User) -> Result<Value, ApiError> {
+    let mut value = to_normalized_value(user)?;
+    if let Some(fields) = value.as_object_mut() {
+        fields.remove("passwordHash");
+    }
+    Ok(value)
+}
@@ -0,0 +1,123 @@
+//! # watchdog.rs
+//!
+//! Liveness and readiness probes of the orchestrator itself, for Kubernetes and docker-compose
+//! healthchecks, and the status of the background loops kept running by the watchdog (see
+//! lib/watchdog.rs). These are separate from `/health`, which is the health report the
+//! orchestrator serves as a device.
+
+use std::time::{Duration, Instant};
+use actix_web::{web, HttpResponse, Responder};
+use mongodb::bson::doc;
+use serde::Serialize;
+use serde_json::json;
+use crate::api::ws_logs::{self, WsServerState};
+use crate::lib::config::Config;
+use crate::lib::errors::ApiError;
+use crate::lib::mongodb::get_database;
+use crate::lib::watchdog::{self, loop_statuses};
+use crate::lib::zeroconf;
+
+
+/// How long the database has to answer the readiness ping
+const DATABASE_PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+
+/// Status of a single dependency in the readiness report
+#[derive(Debug, Serialize)]
+pub struct DependencyStatus {
+    pub status: &'static str, // up, down or disabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(rename = "latencyMs", skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+}
+
+impl DependencyStatus {
+    fn up() -> Self {
+        DependencyStatus { status: "up", detail: None, latency_ms: None }
+    }
+
+    fn down(detail: impl Into<String>) -> Self {
+        DependencyStatus { status: "down", detail: Some(detail.into()), latency_ms: None }
+    }
+
+    fn disabled() -> Self {
+        DependencyStatus { status: "disabled", detail: None, latency_ms: None }
+    }
+
+    fn is_down(&self) -> bool {
+        self.status == "down"
+    }
+}
+
+
+/// GET /healthz
+///
+/// Responds with 200 as long as the process is alive and serving requests.
+pub async fn get_liveness() -> Result<impl Responder, ApiError> {
+    Ok(HttpResponse::Ok().json(json!({ "status": "alive", "version": env!("CARGO_PKG_VERSION") })))
+}
+
+
+/// GET /readyz
+///
+/// Responds with 200 when MongoDB answers, the orchestrator is advertised over mDNS, the
+/// health check loop is running and the websocket server is up (unless it is disabled), along
+/// with all the other background loops (discovery, execution input sweeper). Responds with 503
+/// when any of them is down. The status of each is in `checks`, and of the loops in `loops`.
+pub async fn get_readiness(config: web::Data<Config>) -> Result<impl Responder, ApiError> {
+    let loops = loop_statuses(&config.watchdog);
+    let health_loop = match loops.iter().find(|l| l.name == watchdog::LOOP_HEALTH_CHECKS) {
+        Some(l) if l.alive => DependencyStatus::up(),
+        Some(l) => DependencyStatus::down(l.last_restart_reason.clone().unwrap_or_else(|| "not iterating".to_string())),
+        None => DependencyStatus::down("not started"),
+    };
+    let mdns = if zeroconf::is_advertising() {
+        DependencyStatus::up()
+    } else {
+        DependencyStatus::down("not advertised")
+    };
+    let websocket = match ws_logs::server_state() {
+        WsServerState::Disabled => DependencyStatus::disabled(),
+        WsServerState::Starting => DependencyStatus::down("starting"),
+        WsServerState::Listening => DependencyStatus::up(),
+        WsServerState::Stopped => DependencyStatus::down("stopped"),
+    };
+    let checks = [
+        ("mongo", ping_database(&config).await),
+        ("mdnsAdvertisement", mdns),
+        ("healthLoop", health_loop),
+        ("websocketHub", websocket),
+    ];
+
+    let ready = checks.iter().all(|(_, c)| !c.is_down()) && loops.iter().all(|l| l.alive);
+    let checks: serde_json::Map<String, serde_json::Value> = checks
+        .into_iter()
+        .map(|(name, check)| (name.to_string(), json!(check)))
+        .collect();
+    if ready {
+        Ok(HttpResponse::Ok().json(json!({ "status": "ready", "checks": checks, "loops": loops })))
+    } else {
+        Ok(HttpResponse::ServiceUnavailable().json(json!({ "status": "not ready", "checks": checks, "loops": loops })))
+    }
+}
+
+
+/// GET /admin/loops
+///
+/// Returns iteration and restart counters of the background loops since startup, along with
+/// why each was last restarted and when a failed loop is restarted next.
+pub async fn get_loop_stats(config: web::Data<Config>) -> Result<impl Responder, ApiError> {
+    Ok(HttpResponse::Ok().json(loop_statuses(&config.watchdog)))
+}
+
+
+async fn ping_database(config: &Config) -> DependencyStatus {
+    let started = Instant::now();
+    let ping = async { get_database(&config.database).await.run_command(doc! { "ping": 1 }).await };
+    match tokio::time::timeout(DATABASE_PING_TIMEOUT, ping).await {
+        Ok(Ok(_)) => DependencyStatus { latency_ms: Some(started.elapsed().as_millis() as u64), ..DependencyStatus::up() },
+        Ok(Err(e)) => DependencyStatus::down(e.to_string()),
+        Err(_) => DependencyStatus::down(format!("no answer in {} s", DATABASE_PING_TIMEOUT.as_secs())),
+    }
+}
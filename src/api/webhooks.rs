@@ -0,0 +1,163 @@
+//! # webhooks.rs
+//!
+//! Registration of webhooks and handling of the events that couldnt be delivered to them
+//! (see lib/webhooks.rs for the delivery).
+
+use actix_web::{web, Responder};
+use chrono::Utc;
+use futures::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId};
+use serde::Deserialize;
+use serde_json::json;
+use log::{info, error};
+use crate::lib::config::Config;
+use crate::lib::constants::{COLL_WEBHOOKS, COLL_WEBHOOK_DEAD_LETTERS};
+use crate::lib::errors::ApiError;
+use crate::lib::mongodb::{find_one, get_collection};
+use crate::lib::response::ok_json;
+use crate::lib::webhooks::{retry_dead_letter, WEBHOOK_EVENTS};
+use crate::structs::webhooks::{Webhook, WebhookDeadLetter};
+
+
+/// Body of a webhook registration
+#[derive(Debug, Deserialize)]
+pub struct WebhookRequest {
+    pub url: String,
+    pub secret: Option<String>, // Generated if not given
+    #[serde(default)]
+    pub events: Vec<String>, // Empty means all events
+    pub description: Option<String>,
+}
+
+
+/// POST /webhooks
+///
+/// Registers a webhook. The secret used to sign the payloads is only returned in this
+/// response, and is generated unless given.
+pub async fn create_webhook(config: web::Data<Config>, body: web::Json<WebhookRequest>) -> Result<impl Responder, ApiError> {
+    let body = body.into_inner();
+    if !(body.url.starts_with("http://") || body.url.starts_with("https://")) {
+        return Err(ApiError::bad_request("url must be an http or https URL"));
+    }
+    if let Some(unknown) = body.events.iter().find(|e| !WEBHOOK_EVENTS.contains(&e.as_str())) {
+        return Err(ApiError::bad_request(format!(
+            "unknown event '{}', expected some of: {}", unknown, WEBHOOK_EVENTS.join(", ")
+        )));
+    }
+
+    let mut webhook = Webhook {
+        id: None,
+        url: body.url,
+        secret: body.secret.filter(|s| !s.is_empty()).unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string()),
+        events: body.events,
+        description: body.description,
+        created_at: Utc::now(),
+    };
+    let collection = get_collection::<Webhook>(&config.database, COLL_WEBHOOKS).await;
+    let result = collection.insert_one(&webhook).await.map_err(|e| {
+        error!("Failed to save webhook: {}", e);
+        ApiError::db("Failed to save webhook")
+    })?;
+    webhook.id = result.inserted_id.as_object_id();
+    info!("🪝 Webhook registered for {}", webhook.url);
+    ok_json(&json!({ "message": "Webhook registered, store the secret now as it is not shown again", "webhook": webhook }))
+}
+
+
+/// GET /webhooks
+///
+/// Lists the registered webhooks, secrets hidden, along with the events they can subscribe to.
+pub async fn get_webhooks(config: web::Data<Config>) -> Result<impl Responder, ApiError> {
+    let collection = get_collection::<Webhook>(&config.database, COLL_WEBHOOKS).await;
+    let webhooks: Vec<Webhook> = collection
+        .find(doc! {})
+        .sort(doc! { "createdAt": 1 })
+        .await
+        .map_err(ApiError::db)?
+        .try_collect()
+        .await
+        .map_err(ApiError::db)?;
+    let webhooks: Vec<Webhook> = webhooks
+        .into_iter()
+        .map(|mut w| {
+            w.secret = "…".to_string();
+            w
+        })
+        .collect();
+    ok_json(&json!({ "webhooks": webhooks, "events": WEBHOOK_EVENTS }))
+}
+
+
+/// DELETE /webhooks/{webhook_id}
+///
+/// Removes a webhook along with its dead letters.
+pub async fn delete_webhook(config: web::Data<Config>, path: web::Path<String>) -> Result<impl Responder, ApiError> {
+    let webhook_id = path.into_inner();
+    let oid = parse_id(&webhook_id)?;
+    let collection = get_collection::<Webhook>(&config.database, COLL_WEBHOOKS).await;
+    let result = collection.delete_one(doc! { "_id": oid }).await.map_err(ApiError::db)?;
+    if result.deleted_count == 0 {
+        return Err(ApiError::not_found(format!("Webhook with id {} not found", webhook_id)));
+    }
+    let dead_letters = get_collection::<WebhookDeadLetter>(&config.database, COLL_WEBHOOK_DEAD_LETTERS).await;
+    let removed = dead_letters.delete_many(doc! { "webhookId": oid }).await.map_err(ApiError::db)?;
+    info!("🪝 Webhook {} removed", webhook_id);
+    ok_json(&json!({ "message": "Webhook removed", "id": webhook_id, "deadLettersRemoved": removed.deleted_count }))
+}
+
+
+/// GET /webhooks/deadLetters
+///
+/// Lists the events that couldnt be delivered, newest first. Can be limited to a single
+/// webhook with `?webhook=<id>`.
+pub async fn get_webhook_dead_letters(config: web::Data<Config>, query: web::Query<std::collections::HashMap<String, String>>) -> Result<impl Responder, ApiError> {
+    let filter = match query.get("webhook") {
+        Some(id) => doc! { "webhookId": parse_id(id)? },
+        None => doc! {},
+    };
+    let collection = get_collection::<WebhookDeadLetter>(&config.database, COLL_WEBHOOK_DEAD_LETTERS).await;
+    let dead_letters: Vec<WebhookDeadLetter> = collection
+        .find(filter)
+        .sort(doc! { "failedAt": -1 })
+        .await
+        .map_err(ApiError::db)?
+        .try_collect()
+        .await
+        .map_err(ApiError::db)?;
+    ok_json(&dead_letters)
+}
+
+
+/// POST /webhooks/deadLetters/{dead_letter_id}/retry
+///
+/// Delivers a dead letter again. It is removed if the delivery succeeds.
+pub async fn retry_webhook_dead_letter(config: web::Data<Config>, path: web::Path<String>) -> Result<impl Responder, ApiError> {
+    let id = path.into_inner();
+    let dead_letter = find_one::<WebhookDeadLetter>(&config.database, COLL_WEBHOOK_DEAD_LETTERS, doc! { "_id": parse_id(&id)? })
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("Dead letter with id {} not found", id)))?;
+    match retry_dead_letter(&config, &dead_letter).await {
+        Ok(()) => ok_json(&json!({ "message": "Dead letter delivered", "id": id })),
+        Err(e) => Err(ApiError::bad_gateway(format!("delivery failed again: {e}"))),
+    }
+}
+
+
+/// DELETE /webhooks/deadLetters/{dead_letter_id}
+///
+/// Discards a dead letter.
+pub async fn delete_webhook_dead_letter(config: web::Data<Config>, path: web::Path<String>) -> Result<impl Responder, ApiError> {
+    let id = path.into_inner();
+    let collection = get_collection::<WebhookDeadLetter>(&config.database, COLL_WEBHOOK_DEAD_LETTERS).await;
+    let result = collection.delete_one(doc! { "_id": parse_id(&id)? }).await.map_err(ApiError::db)?;
+    if result.deleted_count == 0 {
+        return Err(ApiError::not_found(format!("Dead letter with id {} not found", id)));
+    }
+    ok_json(&json!({ "message": "Dead letter discarded", "id": id }))
+}
+
+
+fn parse_id(id: &str) -> Result<ObjectId, ApiError> {
+    ObjectId::parse_str(id).map_err(|_| ApiError::bad_request(format!("Invalid id (expected ObjectId hex string): {}", id)))
+}
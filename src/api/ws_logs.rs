@@ -11,13 +11,40 @@ use tokio_tungstenite::{
     accept_hdr_async,
     tungstenite::{
         handshake::server::{Request, Response},
+        protocol::{frame::coding::CloseCode, CloseFrame},
         Message,
         http,
     }
 };
 use chrono::{DateTime, Utc};
 use log::{error, info};
+use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
 use crate::structs::logs::SupervisorLog;
+use crate::lib::events;
+use crate::lib::log_forwarding::ORCHESTRATOR_LOG_DEVICE;
+use crate::lib::response::to_normalized_value;
+use crate::lib::shutdown;
+use crate::lib::connections;
+
+
+/// Hub of the running websocket server, set when the server is started
+static HUB: OnceCell<WsHub> = OnceCell::new();
+
+/// State of the websocket server, reported by /readyz
+static SERVER_STATE: Mutex<WsServerState> = Mutex::new(WsServerState::Disabled);
+
+const LOGS_PATH: &str = "/ws/logs";
+const EVENTS_PATH: &str = "/ws/events";
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WsServerState {
+    Disabled, // WASMIOT_USE_WEB_SOCKETS is not true
+    Starting,
+    Listening,
+    Stopped, // Failed or shut down
+}
 
 
 #[derive(Clone)]
@@ -37,19 +64,51 @@ impl WsHub {
     }
 }
 
-/// Start a WebSocket server that serves at /ws/logs.
+/// Sends the message to all connected WebSocket clients, if the WebSocket server is running
+pub fn broadcast(msg: String) {
+    if let Some(hub) = HUB.get() {
+        hub.send(msg);
+    }
+}
+
+/// State of the websocket server
+pub fn server_state() -> WsServerState {
+    *SERVER_STATE.lock()
+}
+
+/// Start a WebSocket server that serves logs at /ws/logs and the events of the event bus
+/// (see lib/events.rs) at /ws/events.
 pub async fn run_ws_logs_server(addr: SocketAddr, coll: Collection<SupervisorLog>) -> Result<()> {
+    *SERVER_STATE.lock() = WsServerState::Starting;
+    let result = serve(addr, coll).await;
+    *SERVER_STATE.lock() = WsServerState::Stopped;
+    result
+}
+
+
+async fn serve(addr: SocketAddr, coll: Collection<SupervisorLog>) -> Result<()> {
     let listener = TcpListener::bind(addr).await?;
     info!("WebSocket server listening on {}", addr);
-    let hub = WsHub::new(1024);
+    *SERVER_STATE.lock() = WsServerState::Listening;
+    let hub = HUB.get_or_init(|| WsHub::new(1024)).clone();
     tokio::spawn(start_mongo_poller(coll.clone(), hub.clone()));
+    let events_hub = WsHub::new(1024);
+    start_event_relay(events_hub.clone());
 
     loop {
-        let (stream, peer) = listener.accept().await?;
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = shutdown::requested() => {
+                info!("WebSocket server stopped");
+                return Ok(());
+            }
+        };
         let hub_clone = hub.clone();
+        let events_hub_clone = events_hub.clone();
 
-        tokio::spawn(async move {
-            if let Err(e) = handle_ws_conn(stream, peer, hub_clone).await {
+        // Tracked, so that shutdown waits for the clients to be sent a close frame
+        shutdown::spawn_tracked(async move {
+            if let Err(e) = handle_ws_conn(stream, peer, hub_clone, events_hub_clone).await {
                 error!("WS connection error ({}): {:?}", peer, e);
             }
         });
@@ -58,21 +117,25 @@ pub async fn run_ws_logs_server(addr: SocketAddr, coll: Collection<SupervisorLog
 }
 
 
-/// Accept a single WebSocket connection and stream broadcast messages to it.
-async fn handle_ws_conn(stream: TcpStream, peer: SocketAddr, hub: WsHub) -> Result<()> {
+/// Accept a single WebSocket connection and stream broadcast messages to it, logs or events
+/// depending on the path connected to.
+async fn handle_ws_conn(stream: TcpStream, peer: SocketAddr, hub: WsHub, events_hub: WsHub) -> Result<()> {
 
+    let mut path = String::new();
     let callback = |req: &Request, mut resp: Response|
         -> std::result::Result<Response, http::Response<Option<String>>> {
-        if req.uri().path() != "/ws/logs" {
+        path = req.uri().path().to_string();
+        if path != LOGS_PATH && path != EVENTS_PATH {
             *resp.status_mut() = http::StatusCode::NOT_FOUND;
         }
         Ok(resp)
     };
 
     let ws_stream = accept_hdr_async(stream, callback).await?;
-    info!("WS connected: {}", peer);
+    info!("WS connected: {} ({})", peer, path);
+    let _connection = connections::websocket_connected();
     let (mut sink, _source) = ws_stream.split();
-    let mut rx = hub.subscribe();
+    let mut rx = if path == EVENTS_PATH { events_hub.subscribe() } else { hub.subscribe() };
 
     loop {
         tokio::select! {
@@ -90,6 +153,13 @@ async fn handle_ws_conn(stream: TcpStream, peer: SocketAddr, hub: WsHub) -> Resu
                     Err(broadcast::error::RecvError::Closed) => break,
                 }
             }
+            _ = shutdown::requested() => {
+                let frame = CloseFrame { code: CloseCode::Away, reason: "orchestrator shutting down".into() };
+                if let Err(e) = sink.send(Message::Close(Some(frame))).await {
+                    error!("WS close error to {}: {}", peer, e);
+                }
+                break;
+            }
         }
     }
 
@@ -98,13 +168,29 @@ async fn handle_ws_conn(stream: TcpStream, peer: SocketAddr, hub: WsHub) -> Resu
 }
 
 
+/// Relay the events published on the event bus to the clients connected to /ws/events.
+fn start_event_relay(hub: WsHub) {
+    events::listen("Event websocket", move |event| {
+        let hub = hub.clone();
+        async move {
+            match serde_json::to_string(&event) {
+                Ok(json) => hub.send(json),
+                Err(e) => error!("Failed to serialize event to JSON: {}", e),
+            }
+        }
+    });
+}
+
+
 /// Poll MongoDB for new logs and broadcast them to all connected WebSocket clients.
+/// The orchestrators own logs are broadcast when they are forwarded, see lib/log_forwarding.rs.
 async fn start_mongo_poller(coll: Collection<SupervisorLog>, hub: WsHub) {
     let mut last_checked: DateTime<Utc> = Utc::now();
 
     loop {
         let filter = doc! {
-            "dateReceived": { "$gt": BsonDateTime::from_chrono(last_checked) }
+            "dateReceived": { "$gt": BsonDateTime::from_chrono(last_checked) },
+            "deviceName": { "$ne": ORCHESTRATOR_LOG_DEVICE },
         };
         match coll.find(filter).await {
             Ok(mut cursor) => {
@@ -117,8 +203,8 @@ async fn start_mongo_poller(coll: Collection<SupervisorLog>, hub: WsHub) {
                     }
 
                     // Broadcast
-                    match serde_json::to_string(&doc) {
-                        Ok(json) => hub.send(json),
+                    match to_normalized_value(&doc) {
+                        Ok(json) => hub.send(json.to_string()),
                         Err(e) => error!("Failed to serialize log to JSON: {}", e),
                     }
                 }
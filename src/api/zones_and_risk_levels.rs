@@ -1,36 +1,159 @@
 use actix_web::{web, HttpResponse, Responder};
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
+use std::collections::HashMap;
 use chrono::Utc;
-use mongodb::bson::doc;
+use mongodb::bson::{doc, Document};
 use futures::stream::TryStreamExt;
 use crate::lib::mongodb::get_collection;
-use crate::structs::zones::Zones;
+use crate::structs::zones::{ZoneChange, Zones};
+use crate::lib::config::Config;
 use crate::lib::errors::ApiError;
+use crate::api::revalidation::{trigger_revalidation, RevalidationScope};
 use crate::lib::constants::COLL_ZONES;
-use log::{debug, error};
+use log::{debug, error, info};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ZoneRiskMapping {
     pub zone: String,
     pub allowed_risk_levels: Vec<String>,
+    #[serde(rename = "maxRiskLevel", default, skip_serializing_if = "Option::is_none")]
+    pub max_risk_level: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RiskLevelsMetadata {
     pub levels: Vec<String>,
     pub last_updated: chrono::DateTime<Utc>,
+    #[serde(default)]
+    pub hierarchical: bool,
+}
+
+
+/// Zone definitions loaded from the database. Used for checking whether a risk level
+/// is allowed in a zone. If the risk levels were declared as an ordered hierarchy, anything
+/// at or below the zones highest allowed level is allowed, otherwise levels must match exactly.
+#[derive(Debug, Clone, Default)]
+pub struct ZonePolicies {
+    zones: HashMap<String, (Vec<String>, Option<String>)>, // zone -> (allowed levels, max level)
+    order: Option<Vec<String>>, // Risk levels from lowest to highest, if hierarchical
+}
+
+impl ZonePolicies {
+    pub async fn load(config: &Config) -> Result<Self, String> {
+        let collection = get_collection::<Zones>(&config.database, COLL_ZONES).await;
+        let docs: Vec<Zones> = collection
+            .find(doc! {})
+            .await
+            .map_err(|e| format!("zones.find error: {e}"))?
+            .try_collect()
+            .await
+            .map_err(|e| format!("zones cursor error: {e}"))?;
+        Ok(Self::from_docs(docs))
+    }
+
+    /// Builds the policies from the zone documents and the risk level document of COLL_ZONES
+    pub fn from_docs(docs: impl IntoIterator<Item = Zones>) -> Self {
+        let mut policies = ZonePolicies::default();
+        for z in docs {
+            if let Some(name) = z.zone {
+                // Deleted zones only keep their history
+                if let Some(allowed) = z.allowed_risk_levels {
+                    policies.zones.insert(name, (allowed, z.max_risk_level));
+                }
+            } else if z.r#type.as_deref() == Some("riskLevels") && z.hierarchical == Some(true) {
+                policies.order = z.levels;
+            }
+        }
+        policies
+    }
+
+    /// Whether the given risk level is allowed in the given zone
+    pub fn allows(&self, zone: &str, level: &str) -> bool {
+        let Some((allowed, max)) = self.zones.get(zone) else {
+            return false;
+        };
+        if allowed.iter().any(|x| x == level) {
+            return true;
+        }
+        match (&self.order, max) {
+            (Some(order), Some(max)) => match (rank(order, level), rank(order, max)) {
+                (Some(l), Some(m)) => l <= m,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Describes the definition of the given zone, used when explaining policy decisions
+    pub fn describe(&self, zone: &str) -> ZoneDescription {
+        let (allowed, max) = match self.zones.get(zone) {
+            Some((allowed, max)) => (Some(allowed.clone()), max.clone()),
+            None => (None, None),
+        };
+        ZoneDescription {
+            zone: zone.to_string(),
+            defined: allowed.is_some(),
+            allowed_risk_levels: allowed.unwrap_or_default(),
+            max_risk_level: max,
+            risk_level_order: self.order.clone(),
+        }
+    }
+}
+
+
+/// Definition of a single zone as it was seen by the validator
+#[derive(Debug, Clone, Serialize)]
+pub struct ZoneDescription {
+    pub zone: String,
+    pub defined: bool,
+    #[serde(rename = "allowedRiskLevels")]
+    pub allowed_risk_levels: Vec<String>,
+    #[serde(rename = "maxRiskLevel", skip_serializing_if = "Option::is_none")]
+    pub max_risk_level: Option<String>,
+    #[serde(rename = "riskLevelOrder", skip_serializing_if = "Option::is_none")]
+    pub risk_level_order: Option<Vec<String>>,
+}
+
+
+/// Position of a risk level in the hierarchy (0 is the lowest)
+fn rank(order: &[String], level: &str) -> Option<usize> {
+    order.iter().position(|x| x == level)
+}
+
+
+/// Highest of the given levels in the hierarchy, ignoring levels that arent part of it
+fn highest_level(order: &[String], levels: &[String]) -> Option<String> {
+    levels
+        .iter()
+        .filter_map(|l| rank(order, l).map(|r| (r, l)))
+        .max_by_key(|(r, _)| *r)
+        .map(|(_, l)| l.clone())
+}
+
+
+/// Update that appends an entry to the change history of a zone
+fn push_zone_change(action: &str, allowed: Option<&[String]>, max: Option<&str>, now: chrono::DateTime<Utc>) -> Document {
+    let change = ZoneChange {
+        action: action.to_string(),
+        allowed_risk_levels: allowed.map(|a| a.to_vec()),
+        max_risk_level: max.map(|m| m.to_string()),
+        timestamp: now,
+    };
+    doc! { "history": mongodb::bson::to_bson(&change).expect("serialize zone change") }
 }
 
 
 /// POST /zoneRiskLevels
 /// 
-/// Endpoint for receiving and parsing a json that contains the zone and risk level definitions
-pub async fn parse_zones_and_risk_levels(card: web::Json<Value>) -> Result<impl Responder, ApiError> {
+/// Endpoint for receiving and parsing a json that contains the zone and risk level definitions.
+/// Risk levels can optionally be declared as an ordered hierarchy with a top level `riskLevelOrder`
+/// array (lowest risk first), in which case each zone allows anything up to its highest allowed level.
+pub async fn parse_zones_and_risk_levels(config: web::Data<Config>, card: web::Json<Value>) -> Result<impl Responder, ApiError> {
     debug!("Received zone and risk-level definitions: {:?}", card);
 
-    let (zone_risk_mappings, risk_levels) = extract_zone_and_risk_level_mappings(&card);
-    let collection = get_collection::<Zones>(COLL_ZONES).await;
+    let (zone_risk_mappings, risk_levels, hierarchical) = extract_zone_and_risk_level_mappings(&card);
+    let collection = get_collection::<Zones>(&config.database, COLL_ZONES).await;
     let now = Utc::now();
 
     for zone in &zone_risk_mappings {
@@ -38,15 +161,25 @@ pub async fn parse_zones_and_risk_levels(card: web::Json<Value>) -> Result<impl
             id: None,
             zone: Some(zone.zone.clone()),
             allowed_risk_levels: Some(zone.allowed_risk_levels.clone()),
+            max_risk_level: zone.max_risk_level.clone(),
             r#type: None,
             last_updated: now,
             levels: None,
+            hierarchical: None,
+            history: None,
         };
         let set_doc = mongodb::bson::to_document(&z).expect("serialize zone doc");
+        let mut update = doc! {
+            "$set": set_doc,
+            "$push": push_zone_change("set", Some(zone.allowed_risk_levels.as_slice()), zone.max_risk_level.as_deref(), now),
+        };
+        if z.max_risk_level.is_none() {
+            update.insert("$unset", doc! { "maxRiskLevel": "" });
+        }
         let _ = collection
             .update_one(
                 doc! { "zone": &zone.zone },
-                doc! { "$set": set_doc }
+                update
             )
             .upsert(true)
             .await;
@@ -59,6 +192,9 @@ pub async fn parse_zones_and_risk_levels(card: web::Json<Value>) -> Result<impl
         r#type: Some("riskLevels".to_string()),
         last_updated: now,
         levels: Some(risk_levels.clone()),
+        max_risk_level: None,
+        hierarchical: Some(hierarchical),
+        history: None,
     };
     let set_doc = mongodb::bson::to_document(&risk_levels_doc).expect("serialize riskLevels doc");
     let _ = collection
@@ -69,16 +205,19 @@ pub async fn parse_zones_and_risk_levels(card: web::Json<Value>) -> Result<impl
         .upsert(true)
         .await;
 
+    trigger_revalidation(&config, RevalidationScope::All, "zone definition update");
+
     Ok(HttpResponse::Ok().json(json!({
         "message": "Zone and risk-level definitions parsed and saved successfully",
         "zones": zone_risk_mappings,
-        "riskLevels": RiskLevelsMetadata { levels: risk_levels, last_updated: now },
+        "riskLevels": RiskLevelsMetadata { levels: risk_levels, last_updated: now, hierarchical },
     })))
 }
 
 
-/// Helper function for extracting the zones and risk levels from a given json
-fn extract_zone_and_risk_level_mappings(card: &Value) -> (Vec<ZoneRiskMapping>, Vec<String>) {
+/// Helper function for extracting the zones and risk levels from a given json.
+/// Returns the zone mappings, all risk levels and whether the risk levels are ordered.
+fn extract_zone_and_risk_level_mappings(card: &Value) -> (Vec<ZoneRiskMapping>, Vec<String>, bool) {
     let mut zone_risk_mappings: Vec<ZoneRiskMapping> = Vec::new();
     let mut risk_levels_set = std::collections::BTreeSet::new();
 
@@ -108,6 +247,7 @@ fn extract_zone_and_risk_level_mappings(card: &Value) -> (Vec<ZoneRiskMapping>,
                                 zone_risk_mappings.push(ZoneRiskMapping {
                                     zone,
                                     allowed_risk_levels: vec![risk_level.clone()],
+                                    max_risk_level: None,
                                 });
                             }
                         }
@@ -117,16 +257,33 @@ fn extract_zone_and_risk_level_mappings(card: &Value) -> (Vec<ZoneRiskMapping>,
         }
     }
 
-    let risk_levels: Vec<String> = risk_levels_set.into_iter().collect();
-    (zone_risk_mappings, risk_levels)
+    // Optional ordering of the risk levels, from lowest to highest
+    let order: Vec<String> = card.get("riskLevelOrder")
+        .and_then(|o| o.as_array())
+        .map(|arr| arr.iter().filter_map(|l| l.as_str().map(|s| s.to_string())).collect())
+        .unwrap_or_default();
+    if order.is_empty() {
+        let risk_levels: Vec<String> = risk_levels_set.into_iter().collect();
+        return (zone_risk_mappings, risk_levels, false);
+    }
+
+    // Each zone allows anything up to the highest level it was given permission for
+    for mapping in zone_risk_mappings.iter_mut() {
+        mapping.max_risk_level = highest_level(&order, &mapping.allowed_risk_levels);
+    }
+
+    // Levels that are not part of the ordering are kept, but can only be matched exactly
+    let mut risk_levels = order.clone();
+    risk_levels.extend(risk_levels_set.into_iter().filter(|l| !order.contains(l)));
+    (zone_risk_mappings, risk_levels, true)
 }
 
 
 /// GET /zoneRiskLevels
 /// 
 /// Endpoint for getting the zone and risk level definitions
-pub async fn get_zones_and_risk_levels() -> Result<impl Responder, ApiError> {
-    let collection = get_collection::<Zones>(COLL_ZONES).await;
+pub async fn get_zones_and_risk_levels(config: web::Data<Config>) -> Result<impl Responder, ApiError> {
+    let collection = get_collection::<Zones>(&config.database, COLL_ZONES).await;
     let mut cursor = match collection.find(doc! { "zone": { "$exists": true } }).await {
         Ok(cursor) => cursor,
         Err(e) => {
@@ -140,11 +297,12 @@ pub async fn get_zones_and_risk_levels() -> Result<impl Responder, ApiError> {
             zones_out.push(ZoneRiskMapping {
                 zone,
                 allowed_risk_levels: allowed,
+                max_risk_level: doc.max_risk_level.clone(),
             });
         }
     }
 
-    let risk_levels_doc = get_collection::<Zones>(COLL_ZONES)
+    let risk_levels_doc = get_collection::<Zones>(&config.database, COLL_ZONES)
         .await
         .find_one(doc! { "type": "riskLevels" })
         .await
@@ -153,6 +311,7 @@ pub async fn get_zones_and_risk_levels() -> Result<impl Responder, ApiError> {
     let risk_levels = risk_levels_doc.as_ref().map(|z| RiskLevelsMetadata {
         levels: z.levels.clone().unwrap_or_default(),
         last_updated: z.last_updated,
+        hierarchical: z.hierarchical.unwrap_or(false),
     });
 
     Ok(HttpResponse::Ok().json(json!({
@@ -165,13 +324,153 @@ pub async fn get_zones_and_risk_levels() -> Result<impl Responder, ApiError> {
 /// DELETE /zoneRiskLevels
 /// 
 /// Endpoint for deleting all zones and risk levels
-pub async fn delete_all_zones_and_risk_levels() -> Result<impl Responder, ApiError> {
-    let collection = get_collection::<Zones>(COLL_ZONES).await;
+pub async fn delete_all_zones_and_risk_levels(config: web::Data<Config>) -> Result<impl Responder, ApiError> {
+    let collection = get_collection::<Zones>(&config.database, COLL_ZONES).await;
     match collection.delete_many(doc! {}).await {
-        Ok(result) => Ok(HttpResponse::Ok().json(json!({ "deleted_count": result.deleted_count }))),
+        Ok(result) => {
+            trigger_revalidation(&config, RevalidationScope::All, "zone definition deletion");
+            Ok(HttpResponse::Ok().json(json!({ "deleted_count": result.deleted_count })))
+        },
         Err(e) => {
             error!("Failed to delete all zones and risk levels: {}", e);
             Err(ApiError::internal_error("Failed to delete zones and risk levels"))
         }
     }
 }
+
+
+/// GET /zoneRiskLevels/{zone}
+///
+/// Endpoint for getting the definition of a single zone, along with its change history.
+/// Deleted zones are returned with `deleted` set to true, as long as their history exists.
+pub async fn get_zone(config: web::Data<Config>, path: web::Path<String>) -> Result<impl Responder, ApiError> {
+    let zone = path.into_inner();
+    let doc = get_collection::<Zones>(&config.database, COLL_ZONES)
+        .await
+        .find_one(doc! { "zone": &zone })
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("Zone not found, zone: {}", zone)))?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "zone": zone,
+        "allowedRiskLevels": doc.allowed_risk_levels,
+        "maxRiskLevel": doc.max_risk_level,
+        "deleted": doc.allowed_risk_levels.is_none(),
+        "lastUpdated": doc.last_updated,
+        "history": doc.history.unwrap_or_default(),
+    })))
+}
+
+
+/// Body of a single zone update
+#[derive(Debug, Deserialize)]
+pub struct ZoneUpdate {
+    #[serde(rename = "allowedRiskLevels", alias = "allowed_risk_levels")]
+    pub allowed_risk_levels: Vec<String>,
+    #[serde(rename = "maxRiskLevel", default)]
+    pub max_risk_level: Option<String>,
+}
+
+
+/// PUT /zoneRiskLevels/{zone}
+///
+/// Endpoint for creating or replacing the definition of a single zone. If the risk levels are
+/// ordered, `maxRiskLevel` defaults to the highest of the allowed levels and must be part of the
+/// ordering. Levels that havent been seen before are added to the known risk levels.
+pub async fn update_zone(config: web::Data<Config>, path: web::Path<String>, body: web::Json<ZoneUpdate>) -> Result<impl Responder, ApiError> {
+    let zone = path.into_inner();
+    let update = body.into_inner();
+    let collection = get_collection::<Zones>(&config.database, COLL_ZONES).await;
+
+    let risk_levels_doc = collection
+        .find_one(doc! { "type": "riskLevels" })
+        .await
+        .map_err(ApiError::db)?;
+    let order = risk_levels_doc
+        .as_ref()
+        .filter(|z| z.hierarchical == Some(true))
+        .and_then(|z| z.levels.clone());
+
+    let max_risk_level = match (&order, update.max_risk_level) {
+        (Some(order), Some(max)) if rank(order, &max).is_none() => {
+            return Err(ApiError::bad_request(format!("maxRiskLevel '{}' is not part of the risk level order {:?}", max, order)));
+        }
+        (Some(order), None) => highest_level(order, &update.allowed_risk_levels),
+        (Some(_), Some(max)) => Some(max),
+        (None, Some(_)) => {
+            return Err(ApiError::bad_request("maxRiskLevel can only be given when risk levels are ordered"));
+        }
+        (None, None) => None,
+    };
+
+    let now = Utc::now();
+    let mut set_doc = doc! {
+        "allowedRiskLevels": update.allowed_risk_levels.clone(),
+        "lastUpdated": mongodb::bson::DateTime::from_chrono(now),
+    };
+    let mut zone_update = doc! {
+        "$push": push_zone_change("set", Some(update.allowed_risk_levels.as_slice()), max_risk_level.as_deref(), now),
+    };
+    match &max_risk_level {
+        Some(max) => { set_doc.insert("maxRiskLevel", max.clone()); }
+        None => { zone_update.insert("$unset", doc! { "maxRiskLevel": "" }); }
+    }
+    zone_update.insert("$set", set_doc);
+    collection
+        .update_one(doc! { "zone": &zone }, zone_update)
+        .upsert(true)
+        .await
+        .map_err(ApiError::db)?;
+
+    if risk_levels_doc.is_some() {
+        collection
+            .update_one(
+                doc! { "type": "riskLevels" },
+                doc! { "$addToSet": { "levels": { "$each": update.allowed_risk_levels.clone() } } }
+            )
+            .await
+            .map_err(ApiError::db)?;
+    }
+
+    info!("Zone '{}' updated", zone);
+    trigger_revalidation(&config, RevalidationScope::All, "zone update");
+
+    Ok(HttpResponse::Ok().json(json!({
+        "message": "Zone updated",
+        "zone": ZoneRiskMapping {
+            zone,
+            allowed_risk_levels: update.allowed_risk_levels,
+            max_risk_level,
+        },
+    })))
+}
+
+
+/// DELETE /zoneRiskLevels/{zone}
+///
+/// Endpoint for deleting a single zone. The zone stops allowing any risk levels,
+/// but its change history is kept.
+pub async fn delete_zone(config: web::Data<Config>, path: web::Path<String>) -> Result<impl Responder, ApiError> {
+    let zone = path.into_inner();
+    let now = Utc::now();
+    let result = get_collection::<Zones>(&config.database, COLL_ZONES)
+        .await
+        .update_one(
+            doc! { "zone": &zone, "allowedRiskLevels": { "$exists": true } },
+            doc! {
+                "$unset": { "allowedRiskLevels": "", "maxRiskLevel": "" },
+                "$set": { "lastUpdated": mongodb::bson::DateTime::from_chrono(now) },
+                "$push": push_zone_change("delete", None, None, now),
+            }
+        )
+        .await
+        .map_err(ApiError::db)?;
+    if result.matched_count == 0 {
+        return Err(ApiError::not_found(format!("Zone not found, zone: {}", zone)));
+    }
+
+    info!("Zone '{}' deleted", zone);
+    trigger_revalidation(&config, RevalidationScope::All, "zone deletion");
+    Ok(HttpResponse::Ok().json(json!({ "message": "Zone deleted", "zone": zone })))
+}
@@ -0,0 +1,353 @@
+//! # client.rs
+//!
+//! Typed async client for the orchestrator API, enabled with the `client` feature. Covers
+//! devices, modules, manifests (deployments) and execution, so that supervisors, CLI tools and
+//! tests can talk to an orchestrator without building the requests by hand. The request and
+//! response types are the same ones the orchestrator itself uses.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), orchestrator::client::ClientError> {
+//! use orchestrator::client::OrchestratorClient;
+//!
+//! let client = OrchestratorClient::new("http://localhost:3000");
+//! for device in client.list_devices().await? {
+//!     println!("{} is {:?}", device.name, device.status);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use reqwest::{multipart, Method, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use crate::api::deployment::Sequence;
+use crate::api::device::ManualDeviceRegistration;
+use crate::api::module_run::ModuleRunRequest;
+use crate::structs::deployment::DeploymentDoc;
+use crate::structs::device::DeviceDoc;
+use crate::structs::module::ModuleDoc;
+
+
+/// Error returned by the client
+#[derive(Debug)]
+pub enum ClientError {
+    /// The request couldnt be sent or the response couldnt be read
+    Http(reqwest::Error),
+    /// The orchestrator responded with an error status, `message` is its `error` field
+    Api { status: StatusCode, message: String },
+    /// The response wasnt in the expected format
+    Decode(String),
+}
+
+impl std::fmt::Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientError::Http(e) => write!(f, "request failed: {}", e),
+            ClientError::Api { status, message } => write!(f, "{}: {}", status, message),
+            ClientError::Decode(e) => write!(f, "unexpected response: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> Self {
+        ClientError::Http(e)
+    }
+}
+
+
+/// A file sent in a multipart request
+#[derive(Debug, Clone)]
+pub struct UploadFile {
+    pub field: String, // Name of the multipart field
+    pub filename: String,
+    pub content_type: String,
+    pub bytes: Vec<u8>,
+}
+
+
+/// Client for a single orchestrator
+#[derive(Debug, Clone)]
+pub struct OrchestratorClient {
+    base_url: String,
+    http: reqwest::Client,
+    token: Option<String>,
+}
+
+impl OrchestratorClient {
+    /// Client for the orchestrator at `base_url` (e.g. `http://localhost:3000`)
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self::with_http_client(base_url, reqwest::Client::new())
+    }
+
+    /// Client using the given reqwest client, for custom timeouts or TLS settings
+    pub fn with_http_client(base_url: impl Into<String>, http: reqwest::Client) -> Self {
+        let base_url = base_url.into().trim_end_matches('/').to_string();
+        OrchestratorClient { base_url, http, token: None }
+    }
+
+    /// Sends the token in `Authorization: Bearer` with every request (card tokens)
+    pub fn with_bearer_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+
+    // Devices
+
+    /// GET /file/device
+    pub async fn list_devices(&self) -> Result<Vec<DeviceDoc>, ClientError> {
+        self.json(self.request(Method::GET, "/file/device")).await
+    }
+
+    /// GET /file/device/{device_id}, by id or name
+    pub async fn get_device(&self, device: &str) -> Result<DeviceDoc, ClientError> {
+        self.json(self.request(Method::GET, &format!("/file/device/{}", device))).await
+    }
+
+    /// POST /file/device/discovery/register
+    pub async fn register_device(&self, registration: &ManualDeviceRegistration) -> Result<(), ClientError> {
+        self.empty(self.request(Method::POST, "/file/device/discovery/register").json(registration)).await
+    }
+
+    /// POST /file/device/discovery/register/batch, returns the outcome per device
+    pub async fn register_devices(&self, registrations: &[ManualDeviceRegistration]) -> Result<Value, ClientError> {
+        self.json(self.request(Method::POST, "/file/device/discovery/register/batch").json(registrations)).await
+    }
+
+    /// POST /file/device/discovery/reset, runs a new discovery scan
+    pub async fn rescan_devices(&self) -> Result<(), ClientError> {
+        self.empty(self.request(Method::POST, "/file/device/discovery/reset")).await
+    }
+
+    /// DELETE /file/device/{device_id}
+    pub async fn delete_device(&self, device: &str) -> Result<(), ClientError> {
+        self.empty(self.request(Method::DELETE, &format!("/file/device/{}", device))).await
+    }
+
+    /// DELETE /file/device?names=..., by ids or names, returns the outcome per device
+    pub async fn delete_devices(&self, devices: &[&str]) -> Result<Value, ClientError> {
+        self.json(self.request(Method::DELETE, "/file/device").query(&[("names", devices.join(","))])).await
+    }
+
+    /// DELETE /file/device
+    pub async fn delete_all_devices(&self) -> Result<(), ClientError> {
+        self.empty(self.request(Method::DELETE, "/file/device")).await
+    }
+
+
+    // Modules
+
+    /// GET /file/module
+    pub async fn list_modules(&self) -> Result<Vec<ModuleDoc>, ClientError> {
+        self.json(self.request(Method::GET, "/file/module")).await
+    }
+
+    /// GET /file/module/{module_id}, by id or name
+    pub async fn get_module(&self, module: &str) -> Result<ModuleDoc, ClientError> {
+        // The orchestrator returns the module in an array, empty if it doesnt exist
+        let modules: Vec<ModuleDoc> = self.json(self.request(Method::GET, &format!("/file/module/{}", module))).await?;
+        modules.into_iter().next().ok_or_else(|| ClientError::Api {
+            status: StatusCode::NOT_FOUND,
+            message: format!("no module matches '{}'", module),
+        })
+    }
+
+    /// POST /file/module, returns the id of the new module
+    pub async fn create_module(&self, name: &str, wasm_filename: &str, wasm: Vec<u8>) -> Result<String, ClientError> {
+        let wasm = UploadFile {
+            field: "wasm".to_string(),
+            filename: wasm_filename.to_string(),
+            content_type: "application/wasm".to_string(),
+            bytes: wasm,
+        };
+        let form = multipart_form(&HashMap::from([("name".to_string(), name.to_string())]), vec![wasm])?;
+        let created: Value = self.json(self.request(Method::POST, "/file/module").multipart(form)).await?;
+        created
+            .get("id")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .ok_or_else(|| ClientError::Decode("module id missing from the response".to_string()))
+    }
+
+    /// POST /file/module/{module_id}/upload, describes the functions of a module with the
+    /// form fields the orchestrator UI sends, and uploads its data files
+    pub async fn describe_module(&self, module_id: &str, fields: &HashMap<String, String>, files: Vec<UploadFile>) -> Result<Value, ClientError> {
+        let form = multipart_form(fields, files)?;
+        self.json(self.request(Method::POST, &format!("/file/module/{}/upload", module_id)).multipart(form)).await
+    }
+
+    /// GET /file/module/{module_id}/wasm
+    pub async fn get_module_wasm(&self, module_id: &str) -> Result<Vec<u8>, ClientError> {
+        let response = self.send(self.request(Method::GET, &format!("/file/module/{}/wasm", module_id))).await?;
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    /// DELETE /file/module/{module_id}
+    pub async fn delete_module(&self, module: &str) -> Result<(), ClientError> {
+        self.empty(self.request(Method::DELETE, &format!("/file/module/{}", module))).await
+    }
+
+    /// POST /file/module/{module_id}/run, test runs a single function of a module and returns
+    /// its result along with the deployment and execution it ran in
+    pub async fn run_module_function(&self, module: &str, run: &ModuleRunRequest) -> Result<Value, ClientError> {
+        self.json(self.request(Method::POST, &format!("/file/module/{}/run", module)).json(run)).await
+    }
+
+
+    // Manifests
+
+    /// GET /file/manifest
+    pub async fn list_deployments(&self) -> Result<Vec<DeploymentDoc>, ClientError> {
+        self.json(self.request(Method::GET, "/file/manifest")).await
+    }
+
+    /// GET /file/manifest/{deployment_id}
+    pub async fn get_deployment(&self, deployment_id: &str) -> Result<DeploymentDoc, ClientError> {
+        self.json(self.request(Method::GET, &format!("/file/manifest/{}", deployment_id))).await
+    }
+
+    /// POST /file/manifest, returns the id of the new deployment
+    pub async fn create_deployment(&self, sequence: &Sequence) -> Result<String, ClientError> {
+        // The id is returned as a quoted string
+        let id: String = self.json(self.request(Method::POST, "/file/manifest").json(sequence)).await?;
+        Ok(id)
+    }
+
+    /// PUT /file/manifest/{deployment_id}, solves the deployment again from the sequence.
+    /// Active deployments are deployed again, and their device responses returned.
+    pub async fn update_deployment(&self, deployment_id: &str, sequence: &Sequence) -> Result<Option<HashMap<String, Value>>, ClientError> {
+        let response = self.send(self.request(Method::PUT, &format!("/file/manifest/{}", deployment_id)).json(sequence)).await?;
+        if response.status() == StatusCode::NO_CONTENT {
+            return Ok(None);
+        }
+        device_responses(decode(response).await?).map(Some)
+    }
+
+    /// POST /file/manifest/{deployment_id}, sends the deployment to its devices and returns
+    /// the response of each device
+    pub async fn deploy(&self, deployment_id: &str) -> Result<HashMap<String, Value>, ClientError> {
+        let deployed: Value = self.json(self.request(Method::POST, &format!("/file/manifest/{}", deployment_id))).await?;
+        device_responses(deployed)
+    }
+
+    /// DELETE /file/manifest/{deployment_id}
+    pub async fn delete_deployment(&self, deployment_id: &str) -> Result<(), ClientError> {
+        self.empty(self.request(Method::DELETE, &format!("/file/manifest/{}", deployment_id))).await
+    }
+
+
+    // Execution
+
+    /// POST /execute/{deployment_id} with the arguments as a JSON object, returns the result
+    pub async fn execute(&self, deployment_id: &str, args: &HashMap<String, String>) -> Result<Value, ClientError> {
+        self.json(self.request(Method::POST, &format!("/execute/{}", deployment_id)).json(args)).await
+    }
+
+    /// POST /execute/{deployment_id} with the arguments and input files as a multipart form
+    pub async fn execute_with_files(&self, deployment_id: &str, args: &HashMap<String, String>, files: Vec<UploadFile>) -> Result<Value, ClientError> {
+        let form = multipart_form(args, files)?;
+        self.json(self.request(Method::POST, &format!("/execute/{}", deployment_id)).multipart(form)).await
+    }
+
+    /// POST /execute/{deployment_id}?async=true, returns the id and queue position of the execution
+    pub async fn execute_async(&self, deployment_id: &str, args: &HashMap<String, String>) -> Result<Value, ClientError> {
+        self.json(self.request(Method::POST, &format!("/execute/{}?async=true", deployment_id)).json(args)).await
+    }
+
+    /// GET /execute/queue, the running and pending executions
+    pub async fn execution_queue(&self) -> Result<Value, ClientError> {
+        self.json(self.request(Method::GET, "/execute/queue")).await
+    }
+
+    /// GET /execute/queue/{execution_id}, a queued or recently finished execution
+    pub async fn queued_execution(&self, execution_id: u64) -> Result<Value, ClientError> {
+        self.json(self.request(Method::GET, &format!("/execute/queue/{}", execution_id))).await
+    }
+
+    /// DELETE /execute/jobs/{job_id}, cancels a pending or running execution
+    pub async fn cancel_execution(&self, execution_id: u64) -> Result<(), ClientError> {
+        self.empty(self.request(Method::DELETE, &format!("/execute/jobs/{}", execution_id))).await
+    }
+
+    /// POST /execute/jobs/{job_id}/rerun, runs an execution again with its stored inputs and
+    /// returns the result
+    pub async fn rerun_execution(&self, execution_id: u64) -> Result<Value, ClientError> {
+        self.json(self.request(Method::POST, &format!("/execute/jobs/{}/rerun", execution_id))).await
+    }
+
+    /// GET /execute/results/{job_id}, the stored result of a finished execution
+    pub async fn execution_result(&self, execution_id: u64) -> Result<Value, ClientError> {
+        self.json(self.request(Method::GET, &format!("/execute/results/{}", execution_id))).await
+    }
+
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let request = self.http.request(method, format!("{}{}", self.base_url, path));
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    /// Sends the request, turning error statuses into `ClientError::Api`
+    async fn send(&self, request: RequestBuilder) -> Result<Response, ClientError> {
+        let response = request.send().await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let body = response.text().await.unwrap_or_default();
+        let message = serde_json::from_str::<Value>(&body)
+            .ok()
+            .and_then(|v| v.get("error").and_then(Value::as_str).map(str::to_string))
+            .unwrap_or(body);
+        Err(ClientError::Api { status, message })
+    }
+
+    async fn json<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T, ClientError> {
+        decode(self.send(request).await?).await
+    }
+
+    async fn empty(&self, request: RequestBuilder) -> Result<(), ClientError> {
+        self.send(request).await.map(|_| ())
+    }
+}
+
+
+async fn decode<T: DeserializeOwned>(response: Response) -> Result<T, ClientError> {
+    let bytes = response.bytes().await?;
+    serde_json::from_slice(&bytes).map_err(|e| ClientError::Decode(e.to_string()))
+}
+
+
+/// The `deviceResponses` of a deploy response
+fn device_responses(deployed: Value) -> Result<HashMap<String, Value>, ClientError> {
+    let responses = deployed
+        .get("deviceResponses")
+        .cloned()
+        .ok_or_else(|| ClientError::Decode("deviceResponses missing from the response".to_string()))?;
+    serde_json::from_value(responses).map_err(|e| ClientError::Decode(e.to_string()))
+}
+
+
+fn multipart_form(fields: &HashMap<String, String>, files: Vec<UploadFile>) -> Result<multipart::Form, ClientError> {
+    let mut form = multipart::Form::new();
+    for (name, value) in fields {
+        form = form.text(name.clone(), value.clone());
+    }
+    for file in files {
+        let part = multipart::Part::bytes(file.bytes)
+            .file_name(file.filename)
+            .mime_str(&file.content_type)?;
+        form = form.part(file.field, part);
+    }
+    Ok(form)
+}
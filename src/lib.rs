@@ -3,13 +3,33 @@ pub mod api {
     pub mod deployment_certificates;
     pub mod deployment;
     pub mod device;
+    pub mod device_drain;
     pub mod execution;
+    pub mod execution_inputs;
+    pub mod execution_outputs;
+    pub mod execution_results;
     pub mod logs;
     pub mod module_cards;
     pub mod module;
+    pub mod module_run;
     pub mod node_cards;
+    pub mod outbound;
+    pub mod policy_engine;
+    pub mod revalidation;
+    pub mod storage;
     pub mod zones_and_risk_levels;
     pub mod ws_logs;
+    pub mod health_history;
+    pub mod device_stats;
+    pub mod card_tokens;
+    pub mod webhooks;
+    pub mod stats;
+    pub mod watchdog;
+    pub mod events;
+    pub mod audit_log;
+    pub mod api_keys;
+    pub mod users;
+    pub mod trash;
 }
 
 pub mod lib {
@@ -19,6 +39,40 @@ pub mod lib {
     pub mod utils;
     pub mod initializer;
     pub mod errors;
+    pub mod response;
+    pub mod log_escalation;
+    pub mod outbound;
+    pub mod odrl;
+    pub mod pagination;
+    pub mod revisions;
+    pub mod indexes;
+    pub mod resources;
+    pub mod execution_queue;
+    pub mod card_auth;
+    pub mod webhooks;
+    pub mod telemetry;
+    pub mod watchdog;
+    pub mod request_id;
+    pub mod log_forwarding;
+    pub mod events;
+    pub mod audit_log;
+    pub mod api_auth;
+    pub mod user_auth;
+    pub mod config;
+    pub mod shutdown;
+    pub mod connections;
+    pub mod http_client;
+    pub mod circuit_breaker;
+    pub mod concurrency;
+    pub mod idempotency;
+    pub mod reachability;
+    pub mod inventory;
+    pub mod discovery;
+    pub mod ssdp;
+    pub mod compatibility;
+    pub mod parameter_validation;
+    pub mod result_callbacks;
+    pub mod openapi_refs;
 }
 
 pub mod structs {
@@ -26,10 +80,24 @@ pub mod structs {
     pub mod deployment_certificates;
     pub mod deployment;
     pub mod device;
+    pub mod execution_inputs;
+    pub mod execution_outputs;
+    pub mod execution_results;
     pub mod module_cards;
     pub mod module;
     pub mod node_cards;
     pub mod openapi;
     pub mod zones;
     pub mod logs;
-}
\ No newline at end of file
+    pub mod health_history;
+    pub mod card_tokens;
+    pub mod webhooks;
+    pub mod audit_log;
+    pub mod api_keys;
+    pub mod users;
+    pub mod trash;
+    pub mod idempotency;
+}
+
+#[cfg(feature = "client")]
+pub mod client;
@@ -0,0 +1,178 @@
+//! # api_auth.rs
+//!
+//! Authentication of the management API, with API keys or user tokens. Keys are sent in the
+//! `X-API-Key` header and stored hashed in COLL_API_KEYS, each with a scope (read-only, deploy
+//! or admin). Users send the JWT they got from `POST /auth/login` as `Authorization: Bearer`,
+//! and their role (viewer, operator, admin) gives the matching scope, see lib/user_auth.rs.
+//! What is enforced depends on `auth.apiKeyAuth` (API_KEY_AUTH):
+//! - `off` (default): nothing, the API stays open as in the original version
+//! - `mutating`: POST, PUT, PATCH and DELETE need a key or a token, as do the reads of the
+//!   audit log, exports, API keys and users
+//! - `all`: additionally every other read of the API needs at least the read-only scope
+//!
+//! Module, deployment and execution routes need the deploy scope, other mutating routes and
+//! deleting all modules or deployments the admin scope. The well-known discovery routes,
+//! health checks, login, the frontend and the routes called by supervisors (log and output
+//! uploads, registration, card submissions which have their own card tokens) are always left
+//! open. The key in `auth.adminKey` (API_ADMIN_KEY) has the admin scope, and can be used to create the first
+//! keys and users.
+
+use actix_web::{web, HttpMessage};
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use chrono::Utc;
+use log::warn;
+use mongodb::bson::doc;
+use sha2::{Digest, Sha256};
+use crate::lib::config::Config;
+use crate::lib::constants::COLL_API_KEYS;
+use crate::lib::errors::ApiError;
+use crate::lib::card_auth::bearer_token;
+use crate::lib::mongodb::{find_one, get_collection};
+use crate::lib::shutdown;
+use crate::lib::user_auth::{decode_token, token_user};
+use crate::structs::api_keys::{ApiKey, ApiKeyScope};
+
+
+pub const API_KEY_HEADER: &str = "X-API-Key";
+
+/// Routes that are open whatever `auth.apiKeyAuth` is, as (method, route pattern)
+const OPEN_ROUTES: &[(&str, &str)] = &[
+    ("GET", "/.well-known/wasmiot-device-description"),
+    ("GET", "/.well-known/wot-thing-description"),
+    ("GET", "/health"),
+    ("GET", "/healthz"),
+    ("GET", "/readyz"),
+    ("POST", "/auth/login"),
+    // Called by supervisors, which have no API keys or user tokens. Registration is how a
+    // supervisor that discovery misses (another network, mDNS blocked) announces itself, so it
+    // stays open like mDNS advertisements are: it can only add or update a device record, as
+    // any supervisor on the network could by advertising itself. The batch registration is
+    // for operators and needs a key.
+    ("POST", "/device/logs"),
+    ("POST", "/file/device/discovery/register"),
+    ("POST", "/file/device/{device_name}/health"),
+    ("POST", "/file/device/{device_name}/outputs/{deployment_id}"),
+    ("POST", "/nodeCards"),
+    ("POST", "/nodeCards/bulk"),
+    ("POST", "/dataSourceCards"),
+    ("POST", "/postResult"),
+    ("POST", "/postResult/{job_id}"),
+];
+
+/// Reads that need the admin scope whenever authentication is enforced
+const ADMIN_READ_ROUTES: &[&str] = &["/apiKeys", "/users", "/auditLog", "/export", "/import", "/admin/export/diff"];
+
+/// Mutating routes under DEPLOY_ROUTE_PREFIXES that still need the admin scope
+const ADMIN_ROUTES: &[(&str, &str)] = &[
+    ("DELETE", "/file/module"),
+    ("DELETE", "/file/manifest"),
+];
+
+/// Route prefixes of the module, deployment and execution routes, which need the deploy scope
+const DEPLOY_ROUTE_PREFIXES: &[&str] = &["/file/module", "/file/manifest", "/execute"];
+
+
+/// Who was authenticated by an API key or a user token and the scope they have, stored in the
+/// request extensions for the audit log and for handlers that allow more to admins
+#[derive(Debug, Clone)]
+pub struct AuthenticatedActor(pub String, pub ApiKeyScope);
+
+
+/// Hex of the SHA-256 of a key, as stored in COLL_API_KEYS
+pub fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+
+/// Middleware checking the API key or user token of the request, used with
+/// `actix_web::middleware::from_fn`
+pub async fn require_credentials(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let config = req.extract::<web::Data<Config>>().await?;
+    let required = match config.auth.api_key_auth.as_str() {
+        "mutating" => required_scope(req.method(), req.match_pattern().as_deref(), false),
+        "all" => required_scope(req.method(), req.match_pattern().as_deref(), true),
+        _ => None,
+    };
+    if let Some(required) = required {
+        if let Err(e) = authenticate(&config, &req, required).await {
+            return Ok(req.error_response(e).map_into_right_body());
+        }
+    }
+    Ok(next.call(req).await?.map_into_left_body())
+}
+
+
+/// Scope needed for the route, None if it is open. Requests not matching a named route (the
+/// frontend files) are open.
+fn required_scope(method: &Method, route: Option<&str>, reads: bool) -> Option<ApiKeyScope> {
+    let route = route?;
+    if OPEN_ROUTES.iter().any(|(m, r)| *m == method.as_str() && *r == route) {
+        return None;
+    }
+    if ADMIN_READ_ROUTES.contains(&route) || ADMIN_ROUTES.iter().any(|(m, r)| *m == method.as_str() && *r == route) {
+        return Some(ApiKeyScope::Admin);
+    }
+    if matches!(*method, Method::POST | Method::PUT | Method::PATCH | Method::DELETE) {
+        if DEPLOY_ROUTE_PREFIXES.iter().any(|p| route.starts_with(p)) {
+            return Some(ApiKeyScope::Deploy);
+        }
+        return Some(ApiKeyScope::Admin);
+    }
+    (reads && *method != Method::OPTIONS).then_some(ApiKeyScope::ReadOnly)
+}
+
+
+async fn authenticate(config: &Config, req: &ServiceRequest, required: ApiKeyScope) -> Result<(), ApiError> {
+    // Bearer tokens that arent user tokens are left for the routes using card tokens
+    if let Some(claims) = bearer_token(req.request()).and_then(|token| decode_token(&config.auth, token)) {
+        let user = token_user(config, &claims).await?;
+        let scope = user.role.scope();
+        req.extensions_mut().insert(AuthenticatedActor(format!("user:{}", user.username), scope));
+        if scope < required {
+            return Err(ApiError::forbidden(format!("the {} role isnt allowed to do this", user.role.as_str())));
+        }
+        return Ok(());
+    }
+
+    let key = req
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|k| !k.is_empty())
+        .ok_or_else(|| ApiError::unauthorized(format!("an API key ({}: <key>) or a user token (Authorization: Bearer <token>) is needed", API_KEY_HEADER)))?;
+
+    // The admin key is compared by its hash, so that the time taken doesnt tell how much of it
+    // was guessed right
+    let key_hash = hash_key(key);
+    let (actor, scope) = if config.auth.admin_key.as_deref().is_some_and(|admin| hash_key(admin) == key_hash) {
+        ("adminKey".to_string(), ApiKeyScope::Admin)
+    } else {
+        let found = find_one::<ApiKey>(&config.database, COLL_API_KEYS, doc! { "keyHash": key_hash, "revokedAt": null })
+            .await
+            .map_err(ApiError::db)?
+            .ok_or_else(|| ApiError::unauthorized("unknown or revoked API key"))?;
+        let id = found.id.ok_or_else(|| ApiError::db("API key missing _id"))?;
+        let database = config.database.clone();
+        shutdown::spawn_tracked(async move {
+            let collection = get_collection::<ApiKey>(&database, COLL_API_KEYS).await;
+            let update = doc! { "$set": { "lastUsedAt": mongodb::bson::DateTime::from_chrono(Utc::now()) } };
+            if let Err(e) = collection.update_one(doc! { "_id": id }, update).await {
+                warn!("Failed to update last use of API key {}: {}", id, e);
+            }
+        });
+        (format!("apiKey:{}", id.to_hex()), found.scope)
+    };
+
+    req.extensions_mut().insert(AuthenticatedActor(actor, scope));
+    if scope < required {
+        return Err(ApiError::forbidden(format!("the API key has the {} scope, {} is needed", scope.as_str(), required.as_str())));
+    }
+    Ok(())
+}
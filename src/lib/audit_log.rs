@@ -0,0 +1,147 @@
+//! # audit_log.rs
+//!
+//! Audit log of the mutating API calls. Every POST, PUT, PATCH and DELETE is recorded into
+//! COLL_AUDIT_LOG with who made it (from the API key, user token or bearer token), the route
+//! and its target, a summary of what was sent and the outcome. Entries are written in the
+//! background once the response is ready, so auditing doesnt slow down the calls. Routes
+//! called by supervisors at a high rate can be left out with `logs.auditExcludedRoutes`.
+
+use std::sync::Arc;
+use std::time::Instant;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::{header, Method};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpMessage};
+use chrono::Utc;
+use log::error;
+use mongodb::bson::doc;
+use serde_json::Value;
+use crate::lib::api_auth::AuthenticatedActor;
+use crate::lib::card_auth::bearer_token;
+use crate::lib::config::Config;
+use crate::lib::constants::{COLL_AUDIT_LOG, COLL_CARD_TOKENS};
+use crate::lib::mongodb::{find_one, get_collection};
+use crate::lib::request_id;
+use crate::lib::shutdown;
+use crate::structs::audit_log::{AuditLogEntry, AuditRequestSummary};
+use crate::structs::card_tokens::CardToken;
+
+
+/// Largest JSON body included in the entry, larger bodies are only summarized by their length
+const MAX_AUDITED_BODY: u64 = 4096;
+
+/// Body fields whose values are replaced with REDACTED, matched as parts of the field name
+const SECRET_FIELDS: &[&str] = &["secret", "token", "password"];
+const REDACTED: &str = "<redacted>";
+
+
+/// Middleware recording the mutating calls, used with `actix_web::middleware::from_fn`
+pub async fn audit_requests(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if !matches!(*req.method(), Method::POST | Method::PUT | Method::PATCH | Method::DELETE) {
+        return next.call(req).await;
+    }
+
+    let started = Instant::now();
+    let config = req.extract::<web::Data<Config>>().await?;
+    let token = bearer_token(req.request()).map(str::to_string);
+    let admin_token = config.cards.admin_token.clone();
+    let client_ip = req.connection_info().realip_remote_addr().map(str::to_string);
+    let method = req.method().to_string();
+    let path = req.path().to_string();
+    let query = Some(req.query_string().to_string()).filter(|q| !q.is_empty());
+    let content_type = req.headers().get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).map(str::to_string);
+    let content_length = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+
+    // Small JSON bodies are read for the entry and put back for the handler
+    let mut body = None;
+    let is_json = content_type.as_deref().is_some_and(|t| t.starts_with("application/json"));
+    if is_json && content_length.is_some_and(|l| l > 0 && l <= MAX_AUDITED_BODY) {
+        let bytes = req.extract::<web::Bytes>().await?;
+        body = serde_json::from_slice::<Value>(&bytes).ok().map(redact);
+        req.set_payload(Payload::from(bytes));
+    }
+
+    let res = next.call(req).await?;
+
+    let route = res.request().match_pattern().unwrap_or_else(|| path.clone());
+    if config.logs.audit_excluded_routes.contains(&route) {
+        return Ok(res);
+    }
+    let status = res.status();
+    let authenticated = res.request().extensions().get::<AuthenticatedActor>().map(|a| a.0.clone());
+    let entry = AuditLogEntry {
+        id: None,
+        actor: authenticated.unwrap_or_default(), // Otherwise resolved when saved, as it may need a database lookup
+        client_ip,
+        method,
+        route,
+        path,
+        target_id: res.request().match_info().iter().last().map(|(_, value)| value.to_string()),
+        request: AuditRequestSummary { query, content_type, content_length, body },
+        status: status.as_u16(),
+        outcome: if status.is_client_error() || status.is_server_error() { "failure" } else { "success" }.to_string(),
+        error: res.response().error().map(|e| e.to_string()),
+        request_id: request_id::current(),
+        duration_ms: started.elapsed().as_millis() as u64,
+        timestamp: Utc::now(),
+    };
+    shutdown::spawn_tracked(save_entry(config.into_inner(), entry, token, admin_token));
+    Ok(res)
+}
+
+
+async fn save_entry(config: Arc<Config>, mut entry: AuditLogEntry, token: Option<String>, admin_token: Option<String>) {
+    if entry.actor.is_empty() {
+        entry.actor = actor(&config, token.as_deref(), admin_token.as_deref()).await;
+    }
+    let collection = get_collection::<AuditLogEntry>(&config.database, COLL_AUDIT_LOG).await;
+    if let Err(e) = collection.insert_one(&entry).await {
+        error!("❌ Failed to save audit log entry of {} {}: {}", entry.method, entry.path, e);
+    }
+}
+
+
+/// Who made the call when it wasnt authenticated with an API key or user token, identified
+/// by the bearer token. Tokens themselves are never stored.
+async fn actor(config: &Config, token: Option<&str>, admin_token: Option<&str>) -> String {
+    let Some(token) = token else {
+        return "anonymous".to_string();
+    };
+    if admin_token == Some(token) {
+        return "admin".to_string();
+    }
+    match find_one::<CardToken>(&config.database, COLL_CARD_TOKENS, doc! { "token": token }).await {
+        Ok(Some(CardToken { id: Some(id), .. })) => format!("cardToken:{}", id.to_hex()),
+        _ => "unknownToken".to_string(),
+    }
+}
+
+
+/// Replaces the values of secret looking fields, at any depth
+fn redact(value: Value) -> Value {
+    match value {
+        Value::Object(fields) => Value::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| {
+                    let lower = key.to_lowercase();
+                    if SECRET_FIELDS.iter().any(|s| lower.contains(s)) {
+                        (key, Value::String(REDACTED.to_string()))
+                    } else {
+                        (key, redact(value))
+                    }
+                })
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(redact).collect()),
+        other => other,
+    }
+}
@@ -0,0 +1,125 @@
+//! # card_auth.rs
+//!
+//! Authentication of node and data source card submissions. A submitter authenticates with a
+//! card token (`Authorization: Bearer <token>`), and may only submit cards of the devices the
+//! token covers: a device token the cards of its own device, and a zone token the cards of
+//! devices in its zone. The admin token (`cards.adminToken`) may submit any card. Unless
+//! `cards.authRequired` is set, cards can still be submitted without a token.
+
+use actix_web::HttpRequest;
+use actix_web::http::header;
+use mongodb::bson::{doc, oid::ObjectId};
+use crate::api::device::device_filter;
+use crate::lib::config::{CardConfig, Config};
+use crate::lib::constants::{COLL_CARD_TOKENS, COLL_DEVICE, COLL_NODE_CARDS};
+use crate::lib::errors::ApiError;
+use crate::lib::mongodb::find_one;
+use crate::structs::card_tokens::{CardSubmitter, CardToken, CardTokenScope};
+use crate::structs::device::DeviceDoc;
+use crate::structs::node_cards::NodeCard;
+
+
+/// Token given in the `Authorization: Bearer` header
+pub fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+}
+
+
+/// Checks that the request may manage card tokens. When the admin token isnt set, tokens can
+/// be managed by anyone unless card authentication is required (then not at all).
+pub fn require_card_admin(config: &CardConfig, req: &HttpRequest) -> Result<(), ApiError> {
+    match config.admin_token.as_deref() {
+        Some(expected) if bearer_token(req) == Some(expected) => Ok(()),
+        Some(_) => Err(ApiError::unauthorized("the card admin token is needed to manage card tokens")),
+        None if config.auth_required => Err(ApiError::forbidden("set CARD_ADMIN_TOKEN to manage card tokens")),
+        None => Ok(()),
+    }
+}
+
+
+/// Identifies the submitter of a card from the request
+pub async fn authenticate_card_submitter(config: &Config, req: &HttpRequest) -> Result<CardSubmitter, ApiError> {
+    let Some(token) = bearer_token(req) else {
+        return if config.cards.auth_required {
+            Err(ApiError::unauthorized("a card token is needed to submit cards (Authorization: Bearer <token>)"))
+        } else {
+            Ok(CardSubmitter::Anonymous)
+        };
+    };
+    if config.cards.admin_token.as_deref() == Some(token) {
+        return Ok(CardSubmitter::Admin);
+    }
+    let found = find_one::<CardToken>(&config.database, COLL_CARD_TOKENS, doc! { "token": token })
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::unauthorized("unknown card token"))?;
+    let token_id = found.id.ok_or_else(|| ApiError::db("card token missing _id"))?;
+    Ok(match found.scope {
+        CardTokenScope::Device { device } => CardSubmitter::Device { device, token_id },
+        CardTokenScope::Zone { zone } => CardSubmitter::Zone { zone, token_id },
+    })
+}
+
+
+/// Checks that the submitter may submit a card of the device `nodeid` (device id or name).
+/// `zone` is the zone a node card places the device in, None for other cards. A zone token
+/// may not move a device out of another zone, and other cards of a device need the device
+/// to have a node card in the zone of the token.
+pub async fn authorize_card(config: &Config, submitter: &CardSubmitter, nodeid: &str, zone: Option<&str>) -> Result<(), ApiError> {
+    match submitter {
+        CardSubmitter::Anonymous | CardSubmitter::Admin => Ok(()),
+        CardSubmitter::Device { device, .. } => {
+            let matches = match ObjectId::parse_str(nodeid) {
+                Ok(id) => id == *device,
+                Err(_) => find_one::<DeviceDoc>(&config.database, COLL_DEVICE, doc! { "name": nodeid })
+                    .await
+                    .map_err(ApiError::db)?
+                    .and_then(|d| d.id)
+                    == Some(*device),
+            };
+            if matches {
+                Ok(())
+            } else {
+                Err(ApiError::forbidden(format!(
+                    "the card token only allows cards of device {}, not '{}'", device.to_hex(), nodeid
+                )))
+            }
+        }
+        CardSubmitter::Zone { zone: token_zone, .. } => {
+            if let Some(zone) = zone.filter(|z| z != token_zone) {
+                return Err(ApiError::forbidden(format!(
+                    "the card token only allows cards in zone '{}', not '{}'", token_zone, zone
+                )));
+            }
+            match (current_zone(config, nodeid).await?, zone) {
+                (Some(current), _) if current != *token_zone => Err(ApiError::forbidden(format!(
+                    "device '{}' is in zone '{}', the card token only allows cards in zone '{}'", nodeid, current, token_zone
+                ))),
+                (None, None) => Err(ApiError::forbidden(format!(
+                    "device '{}' has no node card in zone '{}'", nodeid, token_zone
+                ))),
+                _ => Ok(()),
+            }
+        }
+    }
+}
+
+
+/// Zone of the node card of a device, looked up by any of the ids and names of the device
+/// since node cards can refer to devices by either
+async fn current_zone(config: &Config, nodeid: &str) -> Result<Option<String>, ApiError> {
+    let mut keys = vec![nodeid.to_string()];
+    if let Some(device) = find_one::<DeviceDoc>(&config.database, COLL_DEVICE, device_filter(nodeid)).await.map_err(ApiError::db)? {
+        keys.extend(device.id.map(|id| id.to_hex()));
+        keys.push(device.name);
+    }
+    let card = find_one::<NodeCard>(&config.database, COLL_NODE_CARDS, doc! { "nodeid": { "$in": keys } })
+        .await
+        .map_err(ApiError::db)?;
+    Ok(card.map(|c| c.zone))
+}
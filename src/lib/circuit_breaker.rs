@@ -0,0 +1,181 @@
+//! # circuit_breaker.rs
+//!
+//! Circuit breakers of the requests to supervisors, one per device, so that requests to a device
+//! that is offline fail right away instead of each waiting for its timeout. After the threshold
+//! of consecutive connection failures (refused, unreachable or timed out) in the `httpClient`
+//! settings the breaker of the device opens, and `check` turns requests to it down for the
+//! cooldown of the settings. After the cooldown a single trial request is let through:
+//! if the device answers the breaker closes, otherwise it opens for another cooldown. Any answer
+//! counts, error statuses too, since the device was reachable.
+//!
+//! The breakers are kept in memory, and each change of state is also stored in the device
+//! document as `circuitBreaker`, for the UI.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use chrono::Utc;
+use log::{info, warn};
+use mongodb::bson::{self, doc};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use crate::lib::config::{Config, HttpClientConfig};
+use crate::lib::constants::COLL_DEVICE;
+use crate::lib::mongodb::update_field;
+use crate::lib::shutdown;
+use crate::structs::device::{BreakerState, CircuitBreakerStatus, DeviceDoc};
+
+
+struct Breaker {
+    status: CircuitBreakerStatus,
+    retry_at: Option<Instant>, // While open, when the next trial is let through
+    trial_started: Option<Instant>, // While half open
+}
+
+/// Breakers by device name
+static BREAKERS: Lazy<Mutex<HashMap<String, Breaker>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn cooldown(settings: &HttpClientConfig) -> Duration {
+    Duration::from_secs(settings.circuit_breaker_cooldown_s)
+}
+
+
+/// Whether a request to the device may be sent. The error tells why not, and until when.
+pub fn check(config: &Config, device: &DeviceDoc) -> Result<(), String> {
+    if config.http_client.circuit_breaker_threshold == 0 {
+        return Ok(());
+    }
+    let cooldown = cooldown(&config.http_client);
+    let mut breakers = BREAKERS.lock();
+    let Some(breaker) = breakers.get_mut(&device.name) else { return Ok(()) };
+    let now = Instant::now();
+    match breaker.status.state {
+        BreakerState::Closed => Ok(()),
+        // A trial whose result was never recorded (e.g. its caller timed out) is given up after a cooldown
+        BreakerState::HalfOpen if breaker.trial_started.is_some_and(|t| now.duration_since(t) < cooldown) => {
+            Err(format!("circuit breaker of device '{}' is waiting for a trial request", device.name))
+        }
+        BreakerState::Open if breaker.retry_at.is_some_and(|r| now < r) => Err(format!(
+            "circuit breaker of device '{}' is open until {}",
+            device.name,
+            breaker.status.retry_at.map(|r| r.to_rfc3339()).unwrap_or_default(),
+        )),
+        BreakerState::Open | BreakerState::HalfOpen => {
+            breaker.status.state = BreakerState::HalfOpen;
+            breaker.trial_started = Some(now);
+            let status = breaker.status.clone();
+            drop(breakers);
+            info!("Circuit breaker of device '{}' lets a trial request through", device.name);
+            store(config, device, status);
+            Ok(())
+        }
+    }
+}
+
+
+/// Records the result of a request to the device. Only connection failures count against the
+/// device, any response means it was reachable.
+pub fn record<T>(config: &Config, device: &DeviceDoc, result: &reqwest::Result<T>) {
+    match result {
+        Ok(_) => record_success(config, device),
+        Err(e) if e.is_connect() || e.is_timeout() => record_failure(config, device),
+        Err(_) => {}
+    }
+}
+
+
+fn record_success(config: &Config, device: &DeviceDoc) {
+    if config.http_client.circuit_breaker_threshold == 0 {
+        return;
+    }
+    let removed = BREAKERS.lock().remove(&device.name);
+    let was_open = match removed {
+        Some(breaker) => breaker.status.state != BreakerState::Closed,
+        // The document may still show a breaker that was open before a restart
+        None => device.circuit_breaker.as_ref().is_some_and(|b| b.state != BreakerState::Closed),
+    };
+    // Failures below the threshold arent stored, so there is nothing to reset
+    if !was_open {
+        return;
+    }
+    info!("✅ Circuit breaker of device '{}' closed, the device answered", device.name);
+    store(config, device, CircuitBreakerStatus {
+        state: BreakerState::Closed,
+        consecutive_failures: 0,
+        opened_at: None,
+        retry_at: None,
+    });
+}
+
+
+fn record_failure(config: &Config, device: &DeviceDoc) {
+    let threshold = config.http_client.circuit_breaker_threshold;
+    if threshold == 0 {
+        return;
+    }
+    let cooldown = cooldown(&config.http_client);
+    let mut breakers = BREAKERS.lock();
+    let breaker = breakers.entry(device.name.clone()).or_insert_with(|| Breaker {
+        status: CircuitBreakerStatus {
+            state: BreakerState::Closed,
+            consecutive_failures: 0,
+            opened_at: None,
+            retry_at: None,
+        },
+        retry_at: None,
+        trial_started: None,
+    });
+    breaker.status.consecutive_failures += 1;
+    let opens = match breaker.status.state {
+        BreakerState::Closed => breaker.status.consecutive_failures >= threshold,
+        BreakerState::HalfOpen => true,
+        BreakerState::Open => false,
+    };
+    if !opens {
+        return;
+    }
+    let now = Utc::now();
+    breaker.status.state = BreakerState::Open;
+    breaker.status.opened_at = Some(now);
+    breaker.status.retry_at = chrono::Duration::from_std(cooldown).ok().map(|c| now + c);
+    breaker.retry_at = Some(Instant::now() + cooldown);
+    breaker.trial_started = None;
+    let status = breaker.status.clone();
+    drop(breakers);
+    warn!(
+        "🔌 Circuit breaker of device '{}' opened after {} connection failures, retrying in {} s",
+        device.name, status.consecutive_failures, cooldown.as_secs()
+    );
+    store(config, device, status);
+}
+
+
+/// Moves the breaker of a renamed device to its new name
+pub fn rename(old_name: &str, new_name: &str) {
+    let mut breakers = BREAKERS.lock();
+    if let Some(breaker) = breakers.remove(old_name) {
+        breakers.insert(new_name.to_string(), breaker);
+    }
+}
+
+
+/// Stores the state in the device document in the background
+fn store(config: &Config, device: &DeviceDoc, status: CircuitBreakerStatus) {
+    let filter = match device.id {
+        Some(id) => doc! { "_id": id },
+        None => doc! { "name": &device.name },
+    };
+    let name = device.name.clone();
+    let database = config.database.clone();
+    shutdown::spawn_tracked(async move {
+        let value = match bson::to_bson(&status) {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("Failed to serialize the circuit breaker of device '{}': {}", name, e);
+                return;
+            }
+        };
+        if let Err(e) = update_field::<DeviceDoc>(&database, COLL_DEVICE, filter, "circuitBreaker", value).await {
+            warn!("Failed to store the circuit breaker of device '{}': {}", name, e);
+        }
+    });
+}
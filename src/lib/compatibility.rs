@@ -0,0 +1,101 @@
+//! # compatibility.rs
+//!
+//! Which supervisor versions support the features a deployment can use. Supervisors report their
+//! version in the `supervisorVersion` field of their description, or in the
+//! `X-Supervisor-Version` header of the description response, and it is stored along with the
+//! description of the device. Before a deployment is sent, the features its manifest for each
+//! device uses are compared to SUPERVISOR_FEATURES. With SUPERVISOR_COMPATIBILITY_MODE=refuse
+//! (`deployments.supervisorCompatibilityMode` in the config file) a deployment to a supervisor
+//! that is too old is refused, and with warn (the default) it is only logged. Supervisors that dont report a version are assumed to support everything.
+
+use log::warn;
+use crate::lib::config::DeploymentConfig;
+use crate::lib::errors::ApiError;
+use crate::structs::deployment::DeploymentNode;
+use crate::structs::device::DeviceDoc;
+
+
+/// Header a supervisor can report its version in
+pub const SUPERVISOR_VERSION_HEADER: &str = "X-Supervisor-Version";
+
+pub const FEATURE_CHAINING: &str = "chaining"; // Steps forwarding their result to the next step, with the chained request headers
+pub const FEATURE_MOUNTS: &str = "mounts"; // Files mounted for the modules
+pub const FEATURE_DEPLOYMENT_CONFIG: &str = "deploymentConfig"; // Deployment specific configuration
+
+/// Lowest supervisor version that supports each feature
+pub const SUPERVISOR_FEATURES: &[(&str, &str)] = &[
+    (FEATURE_CHAINING, "0.2.0"),
+    (FEATURE_MOUNTS, "0.3.0"),
+    (FEATURE_DEPLOYMENT_CONFIG, "0.4.0"),
+];
+
+
+/// Major, minor and patch of a version like `1.2.3`, `v1.2` or `1.2.3-beta`. Missing parts are 0.
+pub fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let version = version.trim().trim_start_matches(['v', 'V']);
+    let core = version.split(['-', '+']).next()?;
+    let mut parts = core.split('.').map(|p| p.parse::<u64>());
+    let major = parts.next()?.ok()?;
+    let minor = parts.next().transpose().ok()?.unwrap_or(0);
+    let patch = parts.next().transpose().ok()?.unwrap_or(0);
+    Some((major, minor, patch))
+}
+
+
+/// Features the manifest of a device needs from its supervisor
+pub fn required_features(node: &DeploymentNode) -> Vec<&'static str> {
+    let mut features = Vec::new();
+    let chained = node.instructions.modules.values().flat_map(|funcs| funcs.values()).any(|i| i.to.is_some());
+    if chained {
+        features.push(FEATURE_CHAINING);
+    }
+    let mounted = node.mounts.values().flat_map(|funcs| funcs.values()).any(|m| {
+        !m.execution.is_empty() || !m.deployment.is_empty() || !m.output.is_empty()
+    });
+    if mounted {
+        features.push(FEATURE_MOUNTS);
+    }
+    if !node.config.is_empty() {
+        features.push(FEATURE_DEPLOYMENT_CONFIG);
+    }
+    features
+}
+
+
+/// The features a supervisor of the given version doesnt support, with the version each needs.
+/// Empty if the version cant be parsed.
+pub fn unsupported_features(version: &str, features: &[&'static str]) -> Vec<(&'static str, &'static str)> {
+    let Some(version) = parse_version(version) else {
+        return Vec::new();
+    };
+    SUPERVISOR_FEATURES
+        .iter()
+        .filter(|(feature, _)| features.contains(feature))
+        .filter(|(_, minimum)| parse_version(minimum).is_some_and(|minimum| version < minimum))
+        .copied()
+        .collect()
+}
+
+
+/// Checks that the supervisor of the device supports what its manifest needs. Fails only in
+/// the refuse mode, the warn mode logs the unsupported features.
+pub fn check_supervisor(config: &DeploymentConfig, device: &DeviceDoc, node: &DeploymentNode) -> Result<(), ApiError> {
+    let Some(version) = device.description.supervisor_version.as_deref() else {
+        return Ok(());
+    };
+    let unsupported = unsupported_features(version, &required_features(node));
+    if unsupported.is_empty() {
+        return Ok(());
+    }
+    let listed = unsupported
+        .iter()
+        .map(|(feature, minimum)| format!("{} (needs {})", feature, minimum))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let message = format!("supervisor {} of device '{}' doesnt support {}", version, device.name, listed);
+    if config.supervisor_compatibility_mode == "refuse" {
+        return Err(ApiError::bad_request(message));
+    }
+    warn!("❗️ Deploying anyway: {}", message);
+    Ok(())
+}
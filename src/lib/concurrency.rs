@@ -0,0 +1,134 @@
+//! # concurrency.rs
+//!
+//! Optimistic concurrency control of the deployment and module documents. Each document has a
+//! `revision` counter, increased by every update made through the API. Clients send the
+//! revision they last read in the `If-Match` header (e.g. `If-Match: "3"`), and the update is
+//! rejected with 409 and the current revision if the document has been changed since. Without
+//! the header the update goes through, unless `server.revisionCheckRequired` is set, in which
+//! case it is rejected with 428.
+//!
+//! The revision is claimed with a single conditional update, so of two concurrent updates with
+//! the same revision only one goes through. Updates that can still fail after the revision is
+//! checked (solving a deployment) claim it in the same update that writes the document, so
+//! that a failed update doesnt leave the revision increased.
+
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::{web, HttpRequest, HttpResponse};
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use mongodb::options::ReturnDocument;
+use serde_json::json;
+use crate::lib::config::Config;
+use crate::lib::errors::ApiError;
+use crate::lib::mongodb::get_collection;
+
+
+/// Result of claiming a revision
+pub enum RevisionClaim {
+    /// The update may go ahead, the document now has this revision
+    Claimed(u64),
+    /// The document has been changed since the given revision was read
+    Stale { current: u64 },
+}
+
+
+/// Revision sent in the If-Match header, None if the header is missing or `*`
+pub fn expected_revision(req: &HttpRequest) -> Result<Option<u64>, ApiError> {
+    let Some(value) = req.headers().get(header::IF_MATCH) else {
+        let required = req
+            .app_data::<web::Data<Config>>()
+            .is_some_and(|config| config.server.revision_check_required);
+        if required {
+            return Err(ApiError::precondition_required("the If-Match header with the revision of the document is needed"));
+        }
+        return Ok(None);
+    };
+    let value = value
+        .to_str()
+        .map_err(|_| ApiError::bad_request("invalid If-Match header"))?
+        .trim();
+    if value == "*" {
+        return Ok(None);
+    }
+    let revision = value.trim_start_matches("W/").trim_matches('"');
+    revision
+        .parse::<u64>()
+        .map(Some)
+        .map_err(|_| ApiError::bad_request(format!("invalid If-Match '{}', expected the revision of the document", value)))
+}
+
+
+/// Increases the revision of the document, if it still has the expected one. Documents saved
+/// before revisions were added count as revision 0.
+pub async fn claim_revision(config: &Config, collection: &str, id: &ObjectId, expected: Option<u64>) -> Result<RevisionClaim, ApiError> {
+    update_with_revision(config, collection, id, expected, Document::new()).await
+}
+
+
+/// Applies the update to the document and increases its revision in the same operation, if the
+/// document still has the expected revision. The update must not change `revision` itself.
+pub async fn update_with_revision(
+    config: &Config,
+    collection: &str,
+    id: &ObjectId,
+    expected: Option<u64>,
+    mut update: Document,
+) -> Result<RevisionClaim, ApiError> {
+    let coll = get_collection::<Document>(&config.database, collection).await;
+    let mut filter = doc! { "_id": id };
+    if let Some(expected) = expected {
+        if expected == 0 {
+            filter.insert("revision", doc! { "$in": [0_i64, Bson::Null] });
+        } else {
+            filter.insert("revision", expected as i64);
+        }
+    }
+    update.insert("$inc", doc! { "revision": 1_i64 });
+    let claimed = coll
+        .find_one_and_update(filter, update)
+        .return_document(ReturnDocument::After)
+        .projection(doc! { "revision": 1 })
+        .await
+        .map_err(ApiError::db)?;
+    if let Some(document) = claimed {
+        return Ok(RevisionClaim::Claimed(revision_of(&document)));
+    }
+
+    // Either the revision didnt match or the document is gone
+    let current = coll
+        .find_one(doc! { "_id": id })
+        .projection(doc! { "revision": 1 })
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::not_found(format!("no document in '{}' matches id '{}'", collection, id)))?;
+    Ok(RevisionClaim::Stale { current: revision_of(&current) })
+}
+
+
+/// The 409 response to an update of a document that has been changed since it was read
+pub fn stale_revision_response(expected: u64, current: u64) -> HttpResponse {
+    HttpResponse::Conflict()
+        .insert_header((header::ETAG, revision_etag(current)))
+        .json(json!({
+            "error": format!(
+                "conflict: the document has been changed since revision {}, its current revision is {}",
+                expected, current
+            ),
+            "currentRevision": current,
+        }))
+}
+
+
+/// ETag header value of a revision, which can be sent back as If-Match
+pub fn revision_etag(revision: u64) -> HeaderValue {
+    HeaderValue::from_str(&format!("\"{}\"", revision)).unwrap_or_else(|_| HeaderValue::from_static("\"0\""))
+}
+
+
+/// Revision of the document, 0 for documents saved before revisions were added
+pub fn revision_of(document: &Document) -> u64 {
+    match document.get("revision") {
+        Some(Bson::Int64(r)) => *r as u64,
+        Some(Bson::Int32(r)) => *r as u64,
+        _ => 0,
+    }
+}
@@ -0,0 +1,982 @@
+//! # config.rs
+//!
+//! Typed configuration of the orchestrator, loaded once at startup. Main hands it to the
+//! request handlers as `web::Data<Config>` and to the background loops as `Arc<Config>`.
+//!
+//! Values come from, in increasing precedence: the defaults, a configuration file, the
+//! environment variables documented in .env.example, and the command line flags. The file is
+//! the one given with `--config` or ORCHESTRATOR_CONFIG_FILE, or `orchestrator.toml` in the
+//! config folder when it exists, and can be TOML, YAML or JSON (by its extension). The result is
+//! validated before anything is started, and all the problems found are reported at once.
+//!
+//! Example `orchestrator.toml`:
+//! ```toml
+//! [server]
+//! bindHost = "0.0.0.0"
+//! port = 3000
+//! workers = 4
+//!
+//! [discovery]
+//! scanIntervalS = 60
+//! backends = ["mdns", "ssdp"]
+//!
+//! [healthChecks]
+//! intervalS = 15
+//! failedThreshold = 5
+//!
+//! [database]
+//! host = "mongo"
+//!
+//! [httpClient]
+//! proxy = "http://proxy.local:3128"
+//! deploymentTimeoutS = 60
+//!
+//! [execution]
+//! maxConcurrent = 8
+//! pollIntervalMs = 2000
+//!
+//! [storage]
+//! initDir = "./init"
+//! autoInitialize = true
+//!
+//! [auth]
+//! apiKeyAuth = "mutating"
+//!
+//! [[cards.zoneRules]]
+//! pattern = "greenhouse-*"
+//! zone = "greenhouse"
+//! ```
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use clap::Parser;
+use log::LevelFilter;
+use serde::{Deserialize, Serialize};
+use crate::lib::constants::{CONFIG_PATH, DEFAULT_URL_SCHEME, ORCHESTRATOR_DEFAULT_NAME, PUBLIC_PORT};
+use crate::lib::discovery::{BACKEND_MDNS, BACKEND_NAMES};
+use crate::lib::utils::base_url;
+
+
+/// Url schemes accepted for the orchestrator and devices
+const URL_SCHEMES: &[&str] = &["http", "https"];
+
+/// Values of auth.apiKeyAuth, see lib/api_auth.rs
+const API_KEY_AUTH_MODES: &[&str] = &["off", "mutating", "all"];
+
+/// Values of deployments.supervisorCompatibilityMode, see lib/compatibility.rs
+const SUPERVISOR_COMPATIBILITY_MODES: &[&str] = &["refuse", "warn"];
+
+
+/// Command line flags, these take precedence over the file and the environment
+#[derive(Debug, Default, Parser)]
+#[command(name = "orchestrator", version, about = "Orchestrator for WasmIoT supervisors")]
+pub struct Cli {
+    /// Configuration file (.toml, .yaml, .yml or .json)
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+    /// Address the HTTP server binds to
+    #[arg(long)]
+    pub bind_host: Option<String>,
+    /// Port the HTTP server listens on and advertises
+    #[arg(short, long)]
+    pub port: Option<u16>,
+    /// Scheme (http or https) the orchestrator is reached with
+    #[arg(long)]
+    pub url_scheme: Option<String>,
+    /// Number of HTTP workers, 0 for one per CPU core
+    #[arg(long)]
+    pub workers: Option<usize>,
+    /// Seconds between device discovery scans
+    #[arg(long)]
+    pub scan_interval_s: Option<u64>,
+    /// Seconds between device health checks
+    #[arg(long)]
+    pub health_check_interval_s: Option<u64>,
+    /// Host of MongoDB
+    #[arg(long)]
+    pub mongo_host: Option<String>,
+    /// Port of MongoDB
+    #[arg(long)]
+    pub mongo_port: Option<u16>,
+}
+
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub discovery: DiscoveryConfig,
+    pub health_checks: HealthCheckConfig,
+    pub database: DatabaseConfig,
+    pub http_client: HttpClientConfig,
+    pub execution: ExecutionConfig,
+    pub deployments: DeploymentConfig,
+    pub storage: StorageConfig,
+    pub cards: CardConfig,
+    pub logs: LogConfig,
+    pub webhooks: WebhookConfig,
+    pub watchdog: WatchdogConfig,
+    pub auth: AuthConfig,
+}
+
+
+/// Settings of the HTTP server. The port and url scheme are also what the orchestrator
+/// advertises over mDNS and registers to supervisors with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub name: String, // ORCHESTRATOR_NAME, the service name advertised over mDNS
+    pub bind_host: String, // BIND_HOST
+    pub port: u16, // PUBLIC_PORT
+    pub url_scheme: String, // PREFERRED_URL_SCHEME, http or https
+    pub public_host: Option<String>, // PUBLIC_HOST, defaults to the advertised address
+    pub advertise_addresses: Vec<String>, // ORCHESTRATOR_ADVERTISE_ADDRESSES, comma separated in the environment, interface names or addresses in order of preference
+    pub package_manager_base_url: Option<String>, // PACKAGE_MANAGER_BASE_URL
+    pub workers: Option<usize>, // HTTP_WORKERS, defaults to the number of CPU cores
+    pub json_limit_bytes: usize, // HTTP_JSON_LIMIT_BYTES
+    pub payload_limit_bytes: usize, // HTTP_PAYLOAD_LIMIT_BYTES, raw and text bodies
+    pub form_limit_bytes: usize, // HTTP_FORM_LIMIT_BYTES, url encoded forms
+    pub client_request_timeout_ms: u64, // HTTP_CLIENT_REQUEST_TIMEOUT_MS, for receiving the request head, 0 disables
+    pub keep_alive_s: u64, // HTTP_KEEP_ALIVE_S, 0 disables keep-alive
+    pub shutdown_timeout_s: u64, // SHUTDOWN_TIMEOUT_S, for background work and for requests in progress
+    pub revision_check_required: bool, // REVISION_CHECK_REQUIRED, updates of deployments and modules need If-Match, see lib/concurrency.rs
+    pub idempotency_key_ttl_s: u64, // IDEMPOTENCY_KEY_TTL_S, how long responses are kept for repeats, see lib/idempotency.rs
+}
+
+impl Default for ServerConfig {
+    /// Defaults of the original version and actix-web
+    fn default() -> Self {
+        ServerConfig {
+            name: ORCHESTRATOR_DEFAULT_NAME.to_string(),
+            bind_host: "0.0.0.0".to_string(),
+            port: PUBLIC_PORT,
+            url_scheme: DEFAULT_URL_SCHEME.to_string(),
+            public_host: None,
+            advertise_addresses: Vec::new(),
+            package_manager_base_url: None,
+            workers: None,
+            json_limit_bytes: 2 * 1024 * 1024,
+            payload_limit_bytes: 256 * 1024,
+            form_limit_bytes: 16 * 1024,
+            client_request_timeout_ms: 5000,
+            keep_alive_s: 5,
+            shutdown_timeout_s: 30,
+            revision_check_required: false,
+            idempotency_key_ttl_s: 86400,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn client_request_timeout(&self) -> Duration {
+        Duration::from_millis(self.client_request_timeout_ms)
+    }
+
+    pub fn keep_alive(&self) -> Option<Duration> {
+        (self.keep_alive_s > 0).then(|| Duration::from_secs(self.keep_alive_s))
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout_s)
+    }
+
+    /// Url supervisors download modules from, `<scheme>://<host>:<port>` of this orchestrator
+    /// unless package_manager_base_url is set
+    pub fn package_manager_base_url(&self, host: &str) -> String {
+        self.package_manager_base_url
+            .clone()
+            .unwrap_or_else(|| base_url(&self.url_scheme, host, self.port))
+    }
+}
+
+
+/// Settings of the mDNS discovery of supervisors
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct DiscoveryConfig {
+    pub scan_duration_s: u64, // DEVICE_SCAN_DURATION_S
+    pub scan_interval_s: u64, // DEVICE_SCAN_INTERVAL_S, the wait begins after the scan ends
+    pub default_device_port: u16, // DEFAULT_DEVICE_PORT, for devices registered without one
+    pub default_device_scheme: String, // DEFAULT_DEVICE_SCHEME, for devices that advertise none
+    pub backends: Vec<String>, // DISCOVERY_BACKENDS, comma separated in the environment, mdns and/or ssdp
+    pub interface_probe: bool, // DEVICE_INTERFACE_PROBE, ask devices whose description lists no interfaces for them
+    pub interface_probe_path: String, // DEVICE_INTERFACE_PROBE_PATH
+    pub address_probe_timeout_ms: u64, // DEVICE_ADDRESS_PROBE_TIMEOUT_MS, connecting to each address of multi-homed devices, see lib/reachability.rs
+    pub disappeared_after_scans: u32, // DEVICE_DISAPPEARED_AFTER_SCANS, missed scans after which a device is marked inactive, 0 never
+    pub inventory_file: PathBuf, // DEVICE_INVENTORY_FILE, static device inventory, see lib/inventory.rs
+    pub inventory_poll_s: u64, // DEVICE_INVENTORY_POLL_S, how often the inventory file is checked for changes
+    pub ssdp_search_target: String, // SSDP_SEARCH_TARGET, search target of the SSDP scans, see lib/ssdp.rs
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        DiscoveryConfig {
+            scan_duration_s: 5,
+            scan_interval_s: 60,
+            default_device_port: 5000,
+            default_device_scheme: DEFAULT_URL_SCHEME.to_string(),
+            backends: vec![BACKEND_MDNS.to_string()],
+            interface_probe: false,
+            interface_probe_path: "/.well-known/wasmiot-supervisor-interfaces".to_string(),
+            address_probe_timeout_ms: 1000,
+            disappeared_after_scans: 2,
+            inventory_file: CONFIG_PATH.join("devices.yaml"),
+            inventory_poll_s: 10,
+            ssdp_search_target: "urn:wasmiot:service:supervisor:1".to_string(),
+        }
+    }
+}
+
+
+/// Settings of the periodic health checks of the devices
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct HealthCheckConfig {
+    pub interval_s: u64, // DEVICE_HEALTH_CHECK_INTERVAL_S
+    pub failed_threshold: u32, // DEVICE_HEALTHCHECK_FAILED_THRESHOLD, failures before a device is marked inactive
+    pub concurrency: usize, // DEVICE_HEALTH_CHECK_CONCURRENCY
+    pub timeout_s: u64, // DEVICE_HEALTH_CHECK_TIMEOUT_S
+    pub push_fresh_s: u64, // DEVICE_HEALTH_PUSH_FRESH_S, how long a pushed health report spares the device from polling, 0 always polls
+    pub history_retention_days: u64, // HEALTH_HISTORY_RETENTION_DAYS, 0 keeps the history forever
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        HealthCheckConfig {
+            interval_s: 15,
+            failed_threshold: 5,
+            concurrency: 8,
+            timeout_s: 5,
+            push_fresh_s: 30,
+            history_retention_days: 7,
+        }
+    }
+}
+
+
+/// Connection to MongoDB
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub host: String, // MONGO_HOST
+    pub port: u16, // MONGO_PORT
+    pub username: String, // MONGO_ROOT_USERNAME
+    pub password: String, // MONGO_ROOT_PASSWORD
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            host: "localhost".to_string(),
+            port: 27017,
+            username: "root".to_string(),
+            password: "example".to_string(),
+        }
+    }
+}
+
+impl DatabaseConfig {
+    pub fn uri(&self) -> String {
+        format!("mongodb://{}:{}@{}:{}/?authSource=admin", self.username, self.password, self.host, self.port)
+    }
+}
+
+
+/// Settings of the HTTP client shared by all requests to supervisors and other services (see
+/// lib/http_client.rs). `timeout_s` applies to requests that have no timeout of their own below.
+/// Health checks use the timeout of the health check settings instead, and webhooks
+/// WEBHOOK_TIMEOUT_S.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct HttpClientConfig {
+    pub connect_timeout_s: u64, // HTTP_CLIENT_CONNECT_TIMEOUT_S
+    pub timeout_s: u64, // HTTP_CLIENT_TIMEOUT_S
+    pub pool_idle_timeout_s: u64, // HTTP_CLIENT_POOL_IDLE_TIMEOUT_S, how long unused connections are kept open
+    pub pool_max_idle_per_host: usize, // HTTP_CLIENT_POOL_MAX_IDLE_PER_HOST
+    pub proxy: Option<String>, // HTTP_CLIENT_PROXY, url of a proxy for all requests
+    pub description_timeout_s: u64, // HTTP_CLIENT_DESCRIPTION_TIMEOUT_S, device descriptions and interface probes
+    pub registration_timeout_s: u64, // HTTP_CLIENT_REGISTRATION_TIMEOUT_S, registering the orchestrator to supervisors
+    pub deployment_timeout_s: u64, // HTTP_CLIENT_DEPLOYMENT_TIMEOUT_S, sending deployments to supervisors
+    pub execution_timeout_s: u64, // HTTP_CLIENT_EXECUTION_TIMEOUT_S, each execution request and result poll
+    pub log_level_timeout_s: u64, // HTTP_CLIENT_LOG_LEVEL_TIMEOUT_S, reading and setting supervisor log levels
+    pub policy_decision_timeout_s: u64, // HTTP_CLIENT_POLICY_DECISION_TIMEOUT_S, requests to the policy engine
+    pub max_concurrent_supervisor_requests: usize, // MAX_CONCURRENT_SUPERVISOR_REQUESTS, see lib/outbound.rs
+    pub circuit_breaker_threshold: u32, // DEVICE_CIRCUIT_BREAKER_THRESHOLD, connection failures that open the breaker of a device, 0 disables, see lib/circuit_breaker.rs
+    pub circuit_breaker_cooldown_s: u64, // DEVICE_CIRCUIT_BREAKER_COOLDOWN_S
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        HttpClientConfig {
+            connect_timeout_s: 5,
+            timeout_s: 30,
+            pool_idle_timeout_s: 90,
+            pool_max_idle_per_host: 8,
+            proxy: None,
+            description_timeout_s: 10,
+            registration_timeout_s: 10,
+            deployment_timeout_s: 20,
+            execution_timeout_s: 60,
+            log_level_timeout_s: 5,
+            policy_decision_timeout_s: 10,
+            max_concurrent_supervisor_requests: 32,
+            circuit_breaker_threshold: 3,
+            circuit_breaker_cooldown_s: 60,
+        }
+    }
+}
+
+
+/// Settings of executions: the queue in front of them (see lib/execution_queue.rs), polling
+/// supervisors for results, and how long their inputs and outputs are kept
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct ExecutionConfig {
+    pub max_concurrent: usize, // MAX_CONCURRENT_EXECUTIONS, 0 for no limit
+    pub max_concurrent_per_device: usize, // MAX_CONCURRENT_EXECUTIONS_PER_DEVICE, 0 for no limit
+    pub max_queued: usize, // MAX_QUEUED_EXECUTIONS, waiting executions before callers are refused, 0 for no limit
+    pub timeout_s: u64, // EXECUTION_TIMEOUT_S, time an execution may take in total unless its deployment says otherwise, 0 for no limit
+    pub poll_max_retries: u32, // EXECUTION_POLL_MAX_RETRIES
+    pub poll_interval_ms: u64, // EXECUTION_POLL_INTERVAL_MS
+    pub poll_backoff: f64, // EXECUTION_POLL_BACKOFF, factor the interval grows by after each poll, at least 1
+    pub poll_deadline_s: u64, // EXECUTION_POLL_DEADLINE_S, 0 for none
+    pub poll_max_depth: u32, // EXECUTION_POLL_MAX_DEPTH, how many result urls are followed
+    pub input_max_age_s: u64, // EXECUTION_INPUT_MAX_AGE_S, input files of unfinished executions older than this are removed
+    pub input_sweep_interval_s: u64, // EXECUTION_INPUT_SWEEP_INTERVAL_S
+    pub output_max_age_s: u64, // EXECUTION_OUTPUT_MAX_AGE_S
+    pub archive_dir: Option<PathBuf>, // EXECUTION_ARCHIVE_DIR, outputs are deleted instead of archived without it
+    pub archive_after_days: u64, // EXECUTION_ARCHIVE_AFTER_DAYS
+    pub result_inline_bytes: usize, // EXECUTION_RESULT_INLINE_BYTES, larger results are stored as files, see api/execution_results.rs
+    pub result_retention_days: u64, // EXECUTION_RESULT_RETENTION_DAYS, also for execution inputs, 0 keeps them
+    pub result_callbacks: bool, // EXECUTION_RESULT_CALLBACKS, let supervisors post results back, see lib/result_callbacks.rs
+    pub result_callback_wait_s: u64, // EXECUTION_RESULT_CALLBACK_WAIT_S, polling starts after this, 0 for no limit
+    pub policy_gate: bool, // EXECUTION_POLICY_GATE, check deployments against the policies before executing them
+    pub policy_override_token: Option<String>, // EXECUTION_POLICY_OVERRIDE_TOKEN
+}
+
+impl Default for ExecutionConfig {
+    fn default() -> Self {
+        ExecutionConfig {
+            max_concurrent: 0,
+            max_concurrent_per_device: 0,
+            max_queued: 0,
+            timeout_s: 0,
+            poll_max_retries: 5,
+            poll_interval_ms: 5000,
+            poll_backoff: 1.0,
+            poll_deadline_s: 0,
+            poll_max_depth: 5,
+            input_max_age_s: 3600,
+            input_sweep_interval_s: 600,
+            output_max_age_s: 604800,
+            archive_dir: None,
+            archive_after_days: 7,
+            result_inline_bytes: 65536,
+            result_retention_days: 7,
+            result_callbacks: true,
+            result_callback_wait_s: 300,
+            policy_gate: true,
+            policy_override_token: None,
+        }
+    }
+}
+
+
+/// Settings of validating deployments
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct DeploymentConfig {
+    pub validation_strict: bool, // DEPLOYMENT_VALIDATION_STRICT, default of `?strict`
+    pub revalidation_deactivate: bool, // REVALIDATION_DEACTIVATE, deactivate deployments that no longer pass validation
+    pub policy_opa_url: Option<String>, // POLICY_OPA_URL, Open Policy Agent asked about each deployment
+    pub supervisor_compatibility_mode: String, // SUPERVISOR_COMPATIBILITY_MODE, refuse or warn, see lib/compatibility.rs
+}
+
+impl Default for DeploymentConfig {
+    fn default() -> Self {
+        DeploymentConfig {
+            validation_strict: false,
+            revalidation_deactivate: false,
+            policy_opa_url: None,
+            supervisor_compatibility_mode: "warn".to_string(),
+        }
+    }
+}
+
+
+/// Where files are kept and how much space they may take
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub init_dir: PathBuf, // WASMIOT_INIT_FOLDER, where `GET /export` saves the setup and `GET /import` reads it from
+    pub auto_initialize: bool, // AUTO_INITIALIZE, import the init folder at startup
+    pub snapshot_dir: PathBuf, // WASMIOT_SNAPSHOT_FOLDER
+    pub quota_bytes: u64, // STORAGE_QUOTA_BYTES, 0 for no quota
+    pub trash_retention_days: u64, // TRASH_RETENTION_DAYS, how long deleted modules and deployments can be restored, see api/trash.rs
+    pub trash_purge_interval_s: u64, // TRASH_PURGE_INTERVAL_S
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            init_dir: PathBuf::from("./init"),
+            auto_initialize: false,
+            snapshot_dir: PathBuf::from("./snapshots"),
+            quota_bytes: 0,
+            trash_retention_days: 7,
+            trash_purge_interval_s: 3600,
+        }
+    }
+}
+
+
+/// Settings of node and datasource cards, see lib/card_auth.rs
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct CardConfig {
+    pub auto_generate: bool, // NODE_CARD_AUTO_GENERATE, generate node cards for discovered devices
+    pub zone_rules: Vec<ZoneRule>, // NODE_CARD_ZONE_RULES, `pattern=zone` pairs separated by commas in the environment
+    pub auth_required: bool, // CARD_AUTH_REQUIRED
+    pub admin_token: Option<String>, // CARD_ADMIN_TOKEN
+}
+
+/// Devices matching `pattern`, a subnet like `10.0.1.0/24` or a device name where `*` matches
+/// anything, get their node card in `zone`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ZoneRule {
+    pub pattern: String,
+    pub zone: String,
+}
+
+
+/// Settings of logs: the orchestrators own, supervisor logs, and the audit log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub forward_level: String, // LOG_FORWARD_LEVEL, orchestrator records saved with the supervisor logs, off disables
+    pub supervisor_log_ttl_days: u64, // SUPERVISOR_LOG_TTL_DAYS, 0 keeps them forever
+    pub escalation_level: String, // LOG_ESCALATION_LEVEL, level devices are escalated to
+    pub escalation_duration_s: u64, // LOG_ESCALATION_DURATION_S
+    pub supervisor_log_level_path: String, // SUPERVISOR_LOG_LEVEL_PATH, supervisor endpoint for GET and PUT of {"level": ...}
+    pub audit_excluded_routes: Vec<String>, // AUDIT_LOG_EXCLUDED_ROUTES, comma separated in the environment
+    pub web_socket: bool, // WASMIOT_USE_WEB_SOCKETS, stream new supervisor logs over a WebSocket server
+    pub web_socket_port: u16, // WASMIOT_WEB_SOCKET_PORT
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            forward_level: "warn".to_string(),
+            supervisor_log_ttl_days: 0,
+            escalation_level: "debug".to_string(),
+            escalation_duration_s: 300,
+            supervisor_log_level_path: "/logging/level".to_string(),
+            audit_excluded_routes: vec!["/device/logs".to_string()],
+            web_socket: false,
+            web_socket_port: 3001,
+        }
+    }
+}
+
+impl LogConfig {
+    pub fn forward_level(&self) -> LevelFilter {
+        self.forward_level.parse().unwrap_or(LevelFilter::Warn)
+    }
+}
+
+
+/// Delivery of webhooks, see lib/webhooks.rs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub max_attempts: u32, // WEBHOOK_MAX_ATTEMPTS
+    pub retry_base_ms: u64, // WEBHOOK_RETRY_BASE_MS, doubled after each failed attempt
+    pub timeout_s: u64, // WEBHOOK_TIMEOUT_S
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        WebhookConfig {
+            max_attempts: 5,
+            retry_base_ms: 1000,
+            timeout_s: 10,
+        }
+    }
+}
+
+
+/// Settings of the watchdog of the background loops, see lib/watchdog.rs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct WatchdogConfig {
+    pub interval_s: u64, // WATCHDOG_INTERVAL_S
+    pub grace_s: u64, // WATCHDOG_GRACE_S, how late a loop may be before it is restarted
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        WatchdogConfig {
+            interval_s: 30,
+            grace_s: 300,
+        }
+    }
+}
+
+
+/// Authentication of the API with keys and user tokens, see lib/api_auth.rs and lib/user_auth.rs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub api_key_auth: String, // API_KEY_AUTH, off, mutating or all
+    pub admin_key: Option<String>, // API_ADMIN_KEY
+    pub jwt_secret: Option<String>, // JWT_SECRET, a random one is used without it
+    pub jwt_ttl_s: u64, // JWT_TTL_S
+    pub initial_admin_username: Option<String>, // INITIAL_ADMIN_USERNAME
+    pub initial_admin_password: Option<String>, // INITIAL_ADMIN_PASSWORD
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            api_key_auth: "off".to_string(),
+            admin_key: None,
+            jwt_secret: None,
+            jwt_ttl_s: 28800,
+            initial_admin_username: None,
+            initial_admin_password: None,
+        }
+    }
+}
+
+
+impl Config {
+    /// Loads the configuration from the file, environment and `cli`, and validates it. The
+    /// error lists every problem found, each naming the setting and where it was read from.
+    pub fn load(cli: &Cli) -> Result<Config, Vec<String>> {
+        let mut errors = Vec::new();
+        let mut config = match Self::from_file(cli.config.as_deref()) {
+            Ok(config) => config,
+            Err(e) => {
+                errors.push(e);
+                Config::default()
+            }
+        };
+        config.override_from_env(&mut errors);
+        config.override_from_cli(cli);
+        config.normalize();
+        config.validate(&mut errors);
+        if errors.is_empty() { Ok(config) } else { Err(errors) }
+    }
+
+    /// The configuration file if there is one. A file named with `--config` or
+    /// ORCHESTRATOR_CONFIG_FILE has to exist, the default one doesnt.
+    fn from_file(cli_path: Option<&Path>) -> Result<Config, String> {
+        let explicit = cli_path
+            .map(Path::to_path_buf)
+            .or_else(|| env::var("ORCHESTRATOR_CONFIG_FILE").ok().filter(|p| !p.is_empty()).map(PathBuf::from));
+        let path = match explicit {
+            Some(path) => path,
+            None => {
+                let path = CONFIG_PATH.join("orchestrator.toml");
+                if !path.exists() {
+                    return Ok(Config::default());
+                }
+                path
+            }
+        };
+
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| format!("cant read the configuration file {}: {}", path.display(), e))?;
+        let extension = path.extension().and_then(|e| e.to_str()).map(str::to_lowercase);
+        let parsed: Result<Config, String> = match extension.as_deref() {
+            Some("toml") => toml::from_str(&contents).map_err(|e| e.to_string()),
+            Some("yaml") | Some("yml") => serde_yaml::from_str(&contents).map_err(|e| e.to_string()),
+            Some("json") => serde_json::from_str(&contents).map_err(|e| e.to_string()),
+            _ => Err("unknown format, expected a .toml, .yaml, .yml or .json file".to_string()),
+        };
+        let config = parsed.map_err(|e| format!("invalid configuration file {}: {}", path.display(), e))?;
+        println!("... Loaded configuration from {}", path.display());
+        Ok(config)
+    }
+
+    fn override_from_env(&mut self, errors: &mut Vec<String>) {
+        let server = &mut self.server;
+        override_from_env(&mut server.name, "ORCHESTRATOR_NAME", errors);
+        override_from_env(&mut server.bind_host, "BIND_HOST", errors);
+        override_from_env(&mut server.port, "PUBLIC_PORT", errors);
+        override_from_env(&mut server.url_scheme, "PREFERRED_URL_SCHEME", errors);
+        override_optional_from_env(&mut server.public_host, "PUBLIC_HOST", errors);
+        override_list_from_env(&mut server.advertise_addresses, "ORCHESTRATOR_ADVERTISE_ADDRESSES");
+        override_optional_from_env(&mut server.package_manager_base_url, "PACKAGE_MANAGER_BASE_URL", errors);
+        override_optional_from_env(&mut server.workers, "HTTP_WORKERS", errors);
+        override_from_env(&mut server.json_limit_bytes, "HTTP_JSON_LIMIT_BYTES", errors);
+        override_from_env(&mut server.payload_limit_bytes, "HTTP_PAYLOAD_LIMIT_BYTES", errors);
+        override_from_env(&mut server.form_limit_bytes, "HTTP_FORM_LIMIT_BYTES", errors);
+        override_from_env(&mut server.client_request_timeout_ms, "HTTP_CLIENT_REQUEST_TIMEOUT_MS", errors);
+        override_from_env(&mut server.keep_alive_s, "HTTP_KEEP_ALIVE_S", errors);
+        override_from_env(&mut server.shutdown_timeout_s, "SHUTDOWN_TIMEOUT_S", errors);
+        override_from_env(&mut server.revision_check_required, "REVISION_CHECK_REQUIRED", errors);
+        override_from_env(&mut server.idempotency_key_ttl_s, "IDEMPOTENCY_KEY_TTL_S", errors);
+
+        let discovery = &mut self.discovery;
+        override_from_env(&mut discovery.scan_duration_s, "DEVICE_SCAN_DURATION_S", errors);
+        override_from_env(&mut discovery.scan_interval_s, "DEVICE_SCAN_INTERVAL_S", errors);
+        override_from_env(&mut discovery.default_device_port, "DEFAULT_DEVICE_PORT", errors);
+        override_from_env(&mut discovery.default_device_scheme, "DEFAULT_DEVICE_SCHEME", errors);
+        override_list_from_env(&mut discovery.backends, "DISCOVERY_BACKENDS");
+        override_from_env(&mut discovery.interface_probe, "DEVICE_INTERFACE_PROBE", errors);
+        override_from_env(&mut discovery.interface_probe_path, "DEVICE_INTERFACE_PROBE_PATH", errors);
+        override_from_env(&mut discovery.address_probe_timeout_ms, "DEVICE_ADDRESS_PROBE_TIMEOUT_MS", errors);
+        override_from_env(&mut discovery.disappeared_after_scans, "DEVICE_DISAPPEARED_AFTER_SCANS", errors);
+        override_from_env(&mut discovery.inventory_file, "DEVICE_INVENTORY_FILE", errors);
+        override_from_env(&mut discovery.inventory_poll_s, "DEVICE_INVENTORY_POLL_S", errors);
+        override_from_env(&mut discovery.ssdp_search_target, "SSDP_SEARCH_TARGET", errors);
+
+        let health_checks = &mut self.health_checks;
+        override_from_env(&mut health_checks.interval_s, "DEVICE_HEALTH_CHECK_INTERVAL_S", errors);
+        override_from_env(&mut health_checks.failed_threshold, "DEVICE_HEALTHCHECK_FAILED_THRESHOLD", errors);
+        override_from_env(&mut health_checks.concurrency, "DEVICE_HEALTH_CHECK_CONCURRENCY", errors);
+        override_from_env(&mut health_checks.timeout_s, "DEVICE_HEALTH_CHECK_TIMEOUT_S", errors);
+        override_from_env(&mut health_checks.push_fresh_s, "DEVICE_HEALTH_PUSH_FRESH_S", errors);
+        override_from_env(&mut health_checks.history_retention_days, "HEALTH_HISTORY_RETENTION_DAYS", errors);
+
+        let database = &mut self.database;
+        override_from_env(&mut database.host, "MONGO_HOST", errors);
+        override_from_env(&mut database.port, "MONGO_PORT", errors);
+        override_from_env(&mut database.username, "MONGO_ROOT_USERNAME", errors);
+        override_from_env(&mut database.password, "MONGO_ROOT_PASSWORD", errors);
+
+        let http_client = &mut self.http_client;
+        override_from_env(&mut http_client.connect_timeout_s, "HTTP_CLIENT_CONNECT_TIMEOUT_S", errors);
+        override_from_env(&mut http_client.timeout_s, "HTTP_CLIENT_TIMEOUT_S", errors);
+        override_from_env(&mut http_client.pool_idle_timeout_s, "HTTP_CLIENT_POOL_IDLE_TIMEOUT_S", errors);
+        override_from_env(&mut http_client.pool_max_idle_per_host, "HTTP_CLIENT_POOL_MAX_IDLE_PER_HOST", errors);
+        override_optional_from_env(&mut http_client.proxy, "HTTP_CLIENT_PROXY", errors);
+        override_from_env(&mut http_client.description_timeout_s, "HTTP_CLIENT_DESCRIPTION_TIMEOUT_S", errors);
+        override_from_env(&mut http_client.registration_timeout_s, "HTTP_CLIENT_REGISTRATION_TIMEOUT_S", errors);
+        override_from_env(&mut http_client.deployment_timeout_s, "HTTP_CLIENT_DEPLOYMENT_TIMEOUT_S", errors);
+        override_from_env(&mut http_client.execution_timeout_s, "HTTP_CLIENT_EXECUTION_TIMEOUT_S", errors);
+        override_from_env(&mut http_client.log_level_timeout_s, "HTTP_CLIENT_LOG_LEVEL_TIMEOUT_S", errors);
+        override_from_env(&mut http_client.policy_decision_timeout_s, "HTTP_CLIENT_POLICY_DECISION_TIMEOUT_S", errors);
+        override_from_env(&mut http_client.max_concurrent_supervisor_requests, "MAX_CONCURRENT_SUPERVISOR_REQUESTS", errors);
+        override_from_env(&mut http_client.circuit_breaker_threshold, "DEVICE_CIRCUIT_BREAKER_THRESHOLD", errors);
+        override_from_env(&mut http_client.circuit_breaker_cooldown_s, "DEVICE_CIRCUIT_BREAKER_COOLDOWN_S", errors);
+
+        let execution = &mut self.execution;
+        override_from_env(&mut execution.max_concurrent, "MAX_CONCURRENT_EXECUTIONS", errors);
+        override_from_env(&mut execution.max_concurrent_per_device, "MAX_CONCURRENT_EXECUTIONS_PER_DEVICE", errors);
+        override_from_env(&mut execution.max_queued, "MAX_QUEUED_EXECUTIONS", errors);
+        override_from_env(&mut execution.timeout_s, "EXECUTION_TIMEOUT_S", errors);
+        override_from_env(&mut execution.poll_max_retries, "EXECUTION_POLL_MAX_RETRIES", errors);
+        override_from_env(&mut execution.poll_interval_ms, "EXECUTION_POLL_INTERVAL_MS", errors);
+        override_from_env(&mut execution.poll_backoff, "EXECUTION_POLL_BACKOFF", errors);
+        override_from_env(&mut execution.poll_deadline_s, "EXECUTION_POLL_DEADLINE_S", errors);
+        override_from_env(&mut execution.poll_max_depth, "EXECUTION_POLL_MAX_DEPTH", errors);
+        override_from_env(&mut execution.input_max_age_s, "EXECUTION_INPUT_MAX_AGE_S", errors);
+        override_from_env(&mut execution.input_sweep_interval_s, "EXECUTION_INPUT_SWEEP_INTERVAL_S", errors);
+        override_from_env(&mut execution.output_max_age_s, "EXECUTION_OUTPUT_MAX_AGE_S", errors);
+        override_optional_from_env(&mut execution.archive_dir, "EXECUTION_ARCHIVE_DIR", errors);
+        override_from_env(&mut execution.archive_after_days, "EXECUTION_ARCHIVE_AFTER_DAYS", errors);
+        override_from_env(&mut execution.result_inline_bytes, "EXECUTION_RESULT_INLINE_BYTES", errors);
+        override_from_env(&mut execution.result_retention_days, "EXECUTION_RESULT_RETENTION_DAYS", errors);
+        override_from_env(&mut execution.result_callbacks, "EXECUTION_RESULT_CALLBACKS", errors);
+        override_from_env(&mut execution.result_callback_wait_s, "EXECUTION_RESULT_CALLBACK_WAIT_S", errors);
+        override_from_env(&mut execution.policy_gate, "EXECUTION_POLICY_GATE", errors);
+        override_optional_from_env(&mut execution.policy_override_token, "EXECUTION_POLICY_OVERRIDE_TOKEN", errors);
+
+        let deployments = &mut self.deployments;
+        override_from_env(&mut deployments.validation_strict, "DEPLOYMENT_VALIDATION_STRICT", errors);
+        override_from_env(&mut deployments.revalidation_deactivate, "REVALIDATION_DEACTIVATE", errors);
+        override_optional_from_env(&mut deployments.policy_opa_url, "POLICY_OPA_URL", errors);
+        override_from_env(&mut deployments.supervisor_compatibility_mode, "SUPERVISOR_COMPATIBILITY_MODE", errors);
+
+        let storage = &mut self.storage;
+        override_from_env(&mut storage.init_dir, "WASMIOT_INIT_FOLDER", errors);
+        override_from_env(&mut storage.auto_initialize, "AUTO_INITIALIZE", errors);
+        override_from_env(&mut storage.snapshot_dir, "WASMIOT_SNAPSHOT_FOLDER", errors);
+        override_from_env(&mut storage.quota_bytes, "STORAGE_QUOTA_BYTES", errors);
+        override_from_env(&mut storage.trash_retention_days, "TRASH_RETENTION_DAYS", errors);
+        override_from_env(&mut storage.trash_purge_interval_s, "TRASH_PURGE_INTERVAL_S", errors);
+
+        let cards = &mut self.cards;
+        override_from_env(&mut cards.auto_generate, "NODE_CARD_AUTO_GENERATE", errors);
+        if let Some(raw) = env_value("NODE_CARD_ZONE_RULES") {
+            cards.zone_rules.clear();
+            for rule in raw.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+                match rule.split_once('=') {
+                    Some((pattern, zone)) => cards.zone_rules.push(ZoneRule {
+                        pattern: pattern.trim().to_string(),
+                        zone: zone.trim().to_string(),
+                    }),
+                    None => errors.push(format!("NODE_CARD_ZONE_RULES: '{}' is not of the form pattern=zone", rule)),
+                }
+            }
+        }
+        override_from_env(&mut cards.auth_required, "CARD_AUTH_REQUIRED", errors);
+        override_optional_from_env(&mut cards.admin_token, "CARD_ADMIN_TOKEN", errors);
+
+        let logs = &mut self.logs;
+        override_from_env(&mut logs.forward_level, "LOG_FORWARD_LEVEL", errors);
+        override_from_env(&mut logs.supervisor_log_ttl_days, "SUPERVISOR_LOG_TTL_DAYS", errors);
+        override_from_env(&mut logs.escalation_level, "LOG_ESCALATION_LEVEL", errors);
+        override_from_env(&mut logs.escalation_duration_s, "LOG_ESCALATION_DURATION_S", errors);
+        override_from_env(&mut logs.supervisor_log_level_path, "SUPERVISOR_LOG_LEVEL_PATH", errors);
+        override_list_from_env(&mut logs.audit_excluded_routes, "AUDIT_LOG_EXCLUDED_ROUTES");
+        override_from_env(&mut logs.web_socket, "WASMIOT_USE_WEB_SOCKETS", errors);
+        override_from_env(&mut logs.web_socket_port, "WASMIOT_WEB_SOCKET_PORT", errors);
+
+        let webhooks = &mut self.webhooks;
+        override_from_env(&mut webhooks.max_attempts, "WEBHOOK_MAX_ATTEMPTS", errors);
+        override_from_env(&mut webhooks.retry_base_ms, "WEBHOOK_RETRY_BASE_MS", errors);
+        override_from_env(&mut webhooks.timeout_s, "WEBHOOK_TIMEOUT_S", errors);
+
+        let watchdog = &mut self.watchdog;
+        override_from_env(&mut watchdog.interval_s, "WATCHDOG_INTERVAL_S", errors);
+        override_from_env(&mut watchdog.grace_s, "WATCHDOG_GRACE_S", errors);
+
+        let auth = &mut self.auth;
+        override_from_env(&mut auth.api_key_auth, "API_KEY_AUTH", errors);
+        override_optional_from_env(&mut auth.admin_key, "API_ADMIN_KEY", errors);
+        override_optional_from_env(&mut auth.jwt_secret, "JWT_SECRET", errors);
+        override_from_env(&mut auth.jwt_ttl_s, "JWT_TTL_S", errors);
+        override_optional_from_env(&mut auth.initial_admin_username, "INITIAL_ADMIN_USERNAME", errors);
+        override_optional_from_env(&mut auth.initial_admin_password, "INITIAL_ADMIN_PASSWORD", errors);
+    }
+
+    fn override_from_cli(&mut self, cli: &Cli) {
+        if let Some(bind_host) = &cli.bind_host {
+            self.server.bind_host = bind_host.clone();
+        }
+        if let Some(port) = cli.port {
+            self.server.port = port;
+        }
+        if let Some(url_scheme) = &cli.url_scheme {
+            self.server.url_scheme = url_scheme.clone();
+        }
+        if let Some(workers) = cli.workers {
+            self.server.workers = Some(workers);
+        }
+        if let Some(interval) = cli.scan_interval_s {
+            self.discovery.scan_interval_s = interval;
+        }
+        if let Some(interval) = cli.health_check_interval_s {
+            self.health_checks.interval_s = interval;
+        }
+        if let Some(host) = &cli.mongo_host {
+            self.database.host = host.clone();
+        }
+        if let Some(port) = cli.mongo_port {
+            self.database.port = port;
+        }
+    }
+
+    fn normalize(&mut self) {
+        self.server.url_scheme = self.server.url_scheme.to_lowercase();
+        self.server.workers = self.server.workers.filter(|w| *w > 0);
+        self.discovery.default_device_scheme = self.discovery.default_device_scheme.to_lowercase();
+        for backend in self.discovery.backends.iter_mut() {
+            *backend = backend.to_lowercase();
+        }
+        self.logs.forward_level = self.logs.forward_level.to_lowercase();
+        self.auth.api_key_auth = self.auth.api_key_auth.to_lowercase();
+        self.deployments.supervisor_compatibility_mode = self.deployments.supervisor_compatibility_mode.trim().to_lowercase();
+    }
+
+    fn validate(&self, errors: &mut Vec<String>) {
+        let mut check = |ok: bool, message: &str| {
+            if !ok {
+                errors.push(message.to_string());
+            }
+        };
+        let server = &self.server;
+        check(!server.name.trim().is_empty(), "server.name (ORCHESTRATOR_NAME) must not be empty");
+        check(!server.bind_host.trim().is_empty(), "server.bindHost (BIND_HOST, --bind-host) must not be empty");
+        check(server.port > 0, "server.port (PUBLIC_PORT, --port) must be between 1 and 65535");
+        check(
+            URL_SCHEMES.contains(&server.url_scheme.as_str()),
+            "server.urlScheme (PREFERRED_URL_SCHEME, --url-scheme) must be http or https",
+        );
+        check(server.json_limit_bytes > 0, "server.jsonLimitBytes (HTTP_JSON_LIMIT_BYTES) must be greater than 0");
+        check(server.payload_limit_bytes > 0, "server.payloadLimitBytes (HTTP_PAYLOAD_LIMIT_BYTES) must be greater than 0");
+        check(server.form_limit_bytes > 0, "server.formLimitBytes (HTTP_FORM_LIMIT_BYTES) must be greater than 0");
+        check(server.idempotency_key_ttl_s > 0, "server.idempotencyKeyTtlS (IDEMPOTENCY_KEY_TTL_S) must be greater than 0");
+
+        let discovery = &self.discovery;
+        check(discovery.scan_duration_s > 0, "discovery.scanDurationS (DEVICE_SCAN_DURATION_S) must be greater than 0");
+        check(discovery.default_device_port > 0, "discovery.defaultDevicePort (DEFAULT_DEVICE_PORT) must be between 1 and 65535");
+        check(
+            URL_SCHEMES.contains(&discovery.default_device_scheme.as_str()),
+            "discovery.defaultDeviceScheme (DEFAULT_DEVICE_SCHEME) must be http or https",
+        );
+        check(
+            discovery.backends.iter().all(|b| BACKEND_NAMES.contains(&b.as_str())),
+            "discovery.backends (DISCOVERY_BACKENDS) must only list mdns and ssdp",
+        );
+        check(
+            discovery.interface_probe_path.starts_with('/'),
+            "discovery.interfaceProbePath (DEVICE_INTERFACE_PROBE_PATH) must start with /",
+        );
+        check(
+            discovery.address_probe_timeout_ms > 0,
+            "discovery.addressProbeTimeoutMs (DEVICE_ADDRESS_PROBE_TIMEOUT_MS) must be greater than 0",
+        );
+        check(discovery.inventory_poll_s > 0, "discovery.inventoryPollS (DEVICE_INVENTORY_POLL_S) must be greater than 0");
+        check(
+            !discovery.ssdp_search_target.trim().is_empty(),
+            "discovery.ssdpSearchTarget (SSDP_SEARCH_TARGET) must not be empty",
+        );
+
+        let health_checks = &self.health_checks;
+        check(
+            health_checks.interval_s > 0,
+            "healthChecks.intervalS (DEVICE_HEALTH_CHECK_INTERVAL_S, --health-check-interval-s) must be greater than 0",
+        );
+        check(
+            health_checks.failed_threshold > 0,
+            "healthChecks.failedThreshold (DEVICE_HEALTHCHECK_FAILED_THRESHOLD) must be greater than 0",
+        );
+        check(health_checks.concurrency > 0, "healthChecks.concurrency (DEVICE_HEALTH_CHECK_CONCURRENCY) must be greater than 0");
+        check(health_checks.timeout_s > 0, "healthChecks.timeoutS (DEVICE_HEALTH_CHECK_TIMEOUT_S) must be greater than 0");
+
+        let database = &self.database;
+        check(!database.host.trim().is_empty(), "database.host (MONGO_HOST, --mongo-host) must not be empty");
+        check(database.port > 0, "database.port (MONGO_PORT, --mongo-port) must be between 1 and 65535");
+
+        let http_client = &self.http_client;
+        let timeouts = [
+            (http_client.connect_timeout_s, "httpClient.connectTimeoutS (HTTP_CLIENT_CONNECT_TIMEOUT_S)"),
+            (http_client.timeout_s, "httpClient.timeoutS (HTTP_CLIENT_TIMEOUT_S)"),
+            (http_client.description_timeout_s, "httpClient.descriptionTimeoutS (HTTP_CLIENT_DESCRIPTION_TIMEOUT_S)"),
+            (http_client.registration_timeout_s, "httpClient.registrationTimeoutS (HTTP_CLIENT_REGISTRATION_TIMEOUT_S)"),
+            (http_client.deployment_timeout_s, "httpClient.deploymentTimeoutS (HTTP_CLIENT_DEPLOYMENT_TIMEOUT_S)"),
+            (http_client.execution_timeout_s, "httpClient.executionTimeoutS (HTTP_CLIENT_EXECUTION_TIMEOUT_S)"),
+            (http_client.log_level_timeout_s, "httpClient.logLevelTimeoutS (HTTP_CLIENT_LOG_LEVEL_TIMEOUT_S)"),
+            (http_client.policy_decision_timeout_s, "httpClient.policyDecisionTimeoutS (HTTP_CLIENT_POLICY_DECISION_TIMEOUT_S)"),
+        ];
+        for (timeout_s, name) in timeouts {
+            check(timeout_s > 0, &format!("{} must be greater than 0", name));
+        }
+        if let Some(proxy) = &http_client.proxy {
+            check(
+                reqwest::Proxy::all(proxy.as_str()).is_ok(),
+                &format!("httpClient.proxy (HTTP_CLIENT_PROXY): '{}' is not a valid proxy url", proxy),
+            );
+        }
+        check(
+            http_client.max_concurrent_supervisor_requests > 0,
+            "httpClient.maxConcurrentSupervisorRequests (MAX_CONCURRENT_SUPERVISOR_REQUESTS) must be greater than 0",
+        );
+        check(
+            http_client.circuit_breaker_cooldown_s > 0,
+            "httpClient.circuitBreakerCooldownS (DEVICE_CIRCUIT_BREAKER_COOLDOWN_S) must be greater than 0",
+        );
+
+        let execution = &self.execution;
+        check(
+            execution.poll_backoff.is_finite() && execution.poll_backoff >= 1.0,
+            "execution.pollBackoff (EXECUTION_POLL_BACKOFF) must be at least 1",
+        );
+        check(
+            execution.input_sweep_interval_s > 0,
+            "execution.inputSweepIntervalS (EXECUTION_INPUT_SWEEP_INTERVAL_S) must be greater than 0",
+        );
+
+        let storage = &self.storage;
+        check(
+            !storage.init_dir.as_os_str().is_empty(),
+            "storage.initDir (WASMIOT_INIT_FOLDER) must not be empty",
+        );
+        check(
+            storage.trash_purge_interval_s > 0,
+            "storage.trashPurgeIntervalS (TRASH_PURGE_INTERVAL_S) must be greater than 0",
+        );
+
+        if let Some(url) = &self.deployments.policy_opa_url {
+            check(
+                reqwest::Url::parse(url).is_ok(),
+                &format!("deployments.policyOpaUrl (POLICY_OPA_URL): '{}' is not a valid url", url),
+            );
+        }
+        check(
+            SUPERVISOR_COMPATIBILITY_MODES.contains(&self.deployments.supervisor_compatibility_mode.as_str()),
+            "deployments.supervisorCompatibilityMode (SUPERVISOR_COMPATIBILITY_MODE) must be refuse or warn",
+        );
+
+        check(
+            self.cards.zone_rules.iter().all(|r| !r.pattern.trim().is_empty() && !r.zone.trim().is_empty()),
+            "cards.zoneRules (NODE_CARD_ZONE_RULES) must not have empty patterns or zones",
+        );
+
+        let logs = &self.logs;
+        check(
+            logs.forward_level.parse::<LevelFilter>().is_ok(),
+            "logs.forwardLevel (LOG_FORWARD_LEVEL) must be off, error, warn, info, debug or trace",
+        );
+        check(!logs.escalation_level.trim().is_empty(), "logs.escalationLevel (LOG_ESCALATION_LEVEL) must not be empty");
+        check(
+            logs.supervisor_log_level_path.starts_with('/'),
+            "logs.supervisorLogLevelPath (SUPERVISOR_LOG_LEVEL_PATH) must start with /",
+        );
+        if logs.web_socket {
+            check(logs.web_socket_port > 0, "logs.webSocketPort (WASMIOT_WEB_SOCKET_PORT) must be between 1 and 65535");
+            check(
+                logs.web_socket_port != server.port,
+                "logs.webSocketPort (WASMIOT_WEB_SOCKET_PORT) must differ from server.port (PUBLIC_PORT, --port)",
+            );
+        }
+
+        let webhooks = &self.webhooks;
+        check(webhooks.max_attempts > 0, "webhooks.maxAttempts (WEBHOOK_MAX_ATTEMPTS) must be greater than 0");
+        check(webhooks.timeout_s > 0, "webhooks.timeoutS (WEBHOOK_TIMEOUT_S) must be greater than 0");
+
+        check(self.watchdog.interval_s > 0, "watchdog.intervalS (WATCHDOG_INTERVAL_S) must be greater than 0");
+
+        let auth = &self.auth;
+        check(
+            API_KEY_AUTH_MODES.contains(&auth.api_key_auth.as_str()),
+            "auth.apiKeyAuth (API_KEY_AUTH) must be off, mutating or all",
+        );
+        check(auth.jwt_ttl_s > 0, "auth.jwtTtlS (JWT_TTL_S) must be greater than 0");
+        check(
+            auth.initial_admin_username.is_some() == auth.initial_admin_password.is_some(),
+            "auth.initialAdminUsername and auth.initialAdminPassword (INITIAL_ADMIN_USERNAME, INITIAL_ADMIN_PASSWORD) must be set together",
+        );
+    }
+}
+
+
+/// Trimmed and unquoted value of the environment variable, None if it is not set or empty
+fn env_value(name: &str) -> Option<String> {
+    let raw = env::var(name).ok()?;
+    let raw = raw.trim().trim_matches('"');
+    (!raw.is_empty()).then(|| raw.to_string())
+}
+
+
+fn override_from_env<T: FromStr>(value: &mut T, name: &str, errors: &mut Vec<String>) {
+    let Some(raw) = env_value(name) else { return };
+    match raw.parse() {
+        Ok(parsed) => *value = parsed,
+        Err(_) => errors.push(format!("{}: '{}' is not a valid value", name, raw)),
+    }
+}
+
+
+fn override_optional_from_env<T: FromStr>(value: &mut Option<T>, name: &str, errors: &mut Vec<String>) {
+    let Some(raw) = env_value(name) else { return };
+    match raw.parse() {
+        Ok(parsed) => *value = Some(parsed),
+        Err(_) => errors.push(format!("{}: '{}' is not a valid value", name, raw)),
+    }
+}
+
+
+/// Comma separated list, with the entries trimmed and empty ones left out
+fn override_list_from_env(value: &mut Vec<String>, name: &str) {
+    let Some(raw) = env_value(name) else { return };
+    *value = raw.split(',').map(|v| v.trim().to_string()).filter(|v| !v.is_empty()).collect();
+}
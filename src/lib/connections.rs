@@ -0,0 +1,62 @@
+//! # connections.rs
+//!
+//! Counts of the connections currently open to the orchestrator, reported by /health. HTTP
+//! connections are counted by the server's connect hook, websocket clients by the websocket
+//! server and server-sent event clients by the /events stream. Each connection holds a guard
+//! that takes it off the count when dropped.
+
+use std::any::Any;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use actix_web::dev::Extensions;
+use crate::structs::device::ConnectionCounts;
+
+
+static HTTP: AtomicUsize = AtomicUsize::new(0);
+static WEBSOCKET: AtomicUsize = AtomicUsize::new(0);
+static EVENT_STREAM: AtomicUsize = AtomicUsize::new(0);
+
+
+/// Counts a connection as open until dropped
+pub struct ConnectionGuard(&'static AtomicUsize);
+
+impl ConnectionGuard {
+    fn new(counter: &'static AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(counter)
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+
+/// Connect hook of the HTTP server. The guard lives in the connection data, which is dropped
+/// when the connection is closed.
+pub fn track_http_connection(_connection: &dyn Any, data: &mut Extensions) {
+    data.insert(ConnectionGuard::new(&HTTP));
+}
+
+
+/// Counts a websocket client for as long as the guard is held
+pub fn websocket_connected() -> ConnectionGuard {
+    ConnectionGuard::new(&WEBSOCKET)
+}
+
+
+/// Counts a server-sent event client for as long as the guard is held
+pub fn event_stream_connected() -> ConnectionGuard {
+    ConnectionGuard::new(&EVENT_STREAM)
+}
+
+
+/// Connections currently open
+pub fn connection_counts() -> ConnectionCounts {
+    ConnectionCounts {
+        http: HTTP.load(Ordering::Relaxed),
+        websocket: WEBSOCKET.load(Ordering::Relaxed),
+        event_stream: EVENT_STREAM.load(Ordering::Relaxed),
+    }
+}
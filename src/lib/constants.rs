@@ -8,7 +8,7 @@ use const_format::concatcp;
 use std::env;
 use once_cell::sync::Lazy;
 use parking_lot::Mutex;
-use sysinfo::{System, Networks, Disks};
+use sysinfo::{System, Networks, Disks, RefreshKind, CpuRefreshKind, MemoryRefreshKind};
 
 /// Default port used when running the service.
 pub const PUBLIC_PORT: u16 = 3000;
@@ -25,13 +25,22 @@ pub const FILE_ROOT_DIR: &str = "./files";
 /// Default directory where modules are stored
 pub const MODULE_DIR: &str = concatcp!(FILE_ROOT_DIR, "/wasm");
 
-/// Directory where execution input files are stored
+/// Directory where the input files of executions are stored for running them again
 pub const EXECUTION_INPUT_DIR: &str = concatcp!(FILE_ROOT_DIR, "/exec");
 
+/// Directory where output files pushed by supervisors are stored
+pub const EXECUTION_OUTPUT_DIR: &str = concatcp!(FILE_ROOT_DIR, "/outputs");
+
+/// Directory where execution results too large to be stored inline are kept
+pub const EXECUTION_RESULT_DIR: &str = concatcp!(FILE_ROOT_DIR, "/results");
+
 /// Directory where files given for module execution in advance are stored
 /// (Essentially deployment mounts)
 pub const MOUNT_DIR: &str = concatcp!(FILE_ROOT_DIR, "/mounts");
 
+/// How many of the latest health check latencies are used for the rolling average of a device
+pub const DEVICE_LATENCY_WINDOW: usize = 10;
+
 /// Name of the initialization function for Wasm modules
 pub const WASMIOT_INIT_FUNCTION_NAME: &str = "_wasmiot_init";
 
@@ -45,6 +54,18 @@ pub const COLL_MODULE_CARDS: &str = "modulecards";
 pub const COLL_NODE_CARDS: &str = "nodecards";
 pub const COLL_ZONES: &str = "zones";
 pub const COLL_LOGS: &str = "supervisorLogs";
+pub const COLL_EXECUTION_OUTPUTS: &str = "executionoutputs";
+pub const COLL_EXECUTION_RESULTS: &str = "executionresults";
+pub const COLL_EXECUTION_INPUTS: &str = "executioninputs";
+pub const COLL_HEALTH_HISTORY: &str = "devicehealthhistory";
+pub const COLL_CARD_TOKENS: &str = "cardtokens";
+pub const COLL_WEBHOOKS: &str = "webhooks";
+pub const COLL_WEBHOOK_DEAD_LETTERS: &str = "webhookdeadletters";
+pub const COLL_AUDIT_LOG: &str = "auditLog";
+pub const COLL_API_KEYS: &str = "apikeys";
+pub const COLL_USERS: &str = "users";
+pub const COLL_TRASH: &str = "trash";
+pub const COLL_IDEMPOTENCY_KEYS: &str = "idempotencykeys";
 
 // TODO: Is this kind of filtering necessary?
 pub const SUPPORTED_FILE_TYPES: &[&str] = &[
@@ -58,12 +79,15 @@ pub const SUPPORTED_FILE_TYPES: &[&str] = &[
 lazy_static! {
     pub static ref INSTANCE_PATH: PathBuf = env::current_dir().unwrap().join("instance");
     pub static ref CONFIG_PATH: PathBuf = env::current_dir().unwrap().join("instance/config");
-    pub static ref DEVICE_HEALTH_CHECK_INTERVAL_S: u64 = env::var("DEVICE_HEALTH_CHECK_INTERVAL_S").ok().and_then(|u| u.parse().ok()).unwrap();
-    pub static ref DEVICE_HEALTHCHECK_FAILED_THRESHOLD: u32 = env::var("DEVICE_HEALTHCHECK_FAILED_THRESHOLD").ok().and_then(|u| u.parse().ok()).unwrap();
-    pub static ref DEVICE_SCAN_DURATION_S: u64 = env::var("DEVICE_SCAN_DURATION_S").ok().and_then(|u| u.parse().ok()).unwrap();
-    pub static ref DEVICE_SCAN_INTERVAL_S: u64 = env::var("DEVICE_SCAN_INTERVAL_S").ok().and_then(|u| u.parse().ok()).unwrap();
+    pub static ref EXECUTION_INPUT_TMP_DIR: PathBuf = env::temp_dir().join("exec_inputs");
 }
 
-pub(crate) static SYSTEM: Lazy<Mutex<System>> = Lazy::new(|| Mutex::new(System::new_all()));
+// Shared by the health report and the device description, refreshed behind the mutex. Only CPU
+// and memory are loaded up front, processes are refreshed individually when needed.
+pub(crate) static SYSTEM: Lazy<Mutex<System>> = Lazy::new(|| Mutex::new(System::new_with_specifics(
+    RefreshKind::nothing()
+        .with_cpu(CpuRefreshKind::everything())
+        .with_memory(MemoryRefreshKind::everything())
+)));
 pub(crate) static NETWORKS: Lazy<Mutex<Networks>> = Lazy::new(|| Mutex::new(Networks::new_with_refreshed_list()));
 pub(crate) static DISKS: Lazy<Mutex<Disks>> = Lazy::new(|| Mutex::new(Disks::new_with_refreshed_list()));
@@ -0,0 +1,230 @@
+//! # discovery.rs
+//!
+//! Periodic discovery of supervisors. Each scan runs the discovery backends of the discovery
+//! settings (DISCOVERY_BACKENDS) at the same time: mDNS (lib/zeroconf.rs) and SSDP
+//! (lib/ssdp.rs), for networks where multicast DNS is blocked. The supervisors found by the
+//! backends are merged by name and handed to `process_discovered_devices`, and each device
+//! records the backend that found it in `discoveredBy`. The properties a supervisor advertises
+//! are stored in `properties` of the device, and its `path` property is used as the base path
+//! of the requests to it.
+//!
+//! Neither backend reports devices that go away. A device that disappears from the network is
+//! instead noticed by comparing scans: once a device found by an earlier scan has been missing
+//! from `disappearedAfterScans` of the discovery settings scans in a row, it is flagged as disappeared and marked
+//! inactive (see `mark_device_disappeared`). Manually registered devices are left to the
+//! health checks.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use futures::future::{join_all, BoxFuture};
+use log::error;
+use mongodb::bson::{doc, Bson};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use crate::api::device::{mark_device_disappeared, process_discovered_devices};
+use crate::lib::config::{Config, DiscoveryConfig};
+use crate::lib::constants::COLL_DEVICE;
+use crate::lib::mongodb::update_field;
+use crate::lib::ssdp::SsdpBackend;
+use crate::lib::utils::{base_path, default_device_description};
+use crate::lib::watchdog;
+use crate::lib::zeroconf::MdnsBackend;
+use crate::structs::device::{DeviceCommunication, DeviceDoc, StatusEnum, StatusLogEntry};
+
+
+pub const BACKEND_MDNS: &str = "mdns";
+pub const BACKEND_SSDP: &str = "ssdp";
+
+/// Names of all discovery backends, as accepted in DISCOVERY_BACKENDS
+pub const BACKEND_NAMES: &[&str] = &[BACKEND_MDNS, BACKEND_SSDP];
+
+
+static LAST_SCAN: Lazy<Mutex<Option<DateTime<Utc>>>> = Lazy::new(|| Mutex::new(None));
+
+/// Devices found by earlier scans, with the number of scans in a row they have been missing from
+static MISSED_SCANS: Lazy<Mutex<HashMap<String, u32>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Name this orchestrator was found with as a device, once a scan has found it
+static OWN_DEVICE_NAME: Lazy<Mutex<Option<String>>> = Lazy::new(|| Mutex::new(None));
+
+
+/// A supervisor found during a scan, with every address it was found at
+#[derive(Debug, Clone, Default)]
+pub struct DiscoveredService {
+    pub port: u16,
+    pub scheme: Option<String>, // None if the supervisor didnt tell whether it uses TLS
+    pub addresses: Vec<String>,
+    pub own_instance: bool, // Whether this is the advertisement of this orchestrator
+    pub properties: BTreeMap<String, String>, // Advertised properties, such as the TXT record over mDNS
+}
+
+
+/// A way of finding supervisors on the network
+pub trait DiscoveryBackend: Send + Sync {
+    /// Name of the backend, one of BACKEND_NAMES
+    fn name(&self) -> &'static str;
+
+    /// Looks for supervisors for the given duration, and returns the ones found by name
+    fn scan(&self, duration: Duration) -> BoxFuture<'static, Result<HashMap<String, DiscoveredService>, String>>;
+}
+
+
+/// The backend with the given name
+fn backend(name: &str, config: &DiscoveryConfig) -> Option<Box<dyn DiscoveryBackend>> {
+    match name {
+        BACKEND_MDNS => Some(Box::new(MdnsBackend)),
+        BACKEND_SSDP => Some(Box::new(SsdpBackend { search_target: config.ssdp_search_target.clone() })),
+        _ => None,
+    }
+}
+
+
+/// Runs a single scan with all the backends of the discovery settings, and saves the devices
+/// found to database. Fails only if every backend failed.
+pub async fn run_single_scan(config: Arc<Config>, scan_duration_secs: u64) -> Result<(), String> {
+    let backends: Vec<Box<dyn DiscoveryBackend>> = config.discovery.backends.iter().filter_map(|b| backend(b, &config.discovery)).collect();
+    if backends.is_empty() {
+        return Ok(());
+    }
+
+    let duration = Duration::from_secs(scan_duration_secs);
+    let results = join_all(backends.iter().map(|b| b.scan(duration))).await;
+
+    // Merged in the order of the backends, so the first backend that found a device decides its port and scheme
+    let mut found: HashMap<String, (DiscoveredService, &'static str)> = HashMap::new();
+    let mut errors = Vec::new();
+    for (backend, result) in backends.iter().zip(results) {
+        let services = match result {
+            Ok(services) => services,
+            Err(e) => {
+                error!("❌ Device scan with {} failed: {}", backend.name(), e);
+                errors.push(format!("{}: {}", backend.name(), e));
+                continue;
+            }
+        };
+        for (name, service) in services.into_iter().filter(|(_, s)| !s.addresses.is_empty()) {
+            let (merged, _) = found.entry(name).or_insert_with(|| {
+                let first = DiscoveredService { port: service.port, scheme: service.scheme.clone(), ..Default::default() };
+                (first, backend.name())
+            });
+            merged.own_instance |= service.own_instance;
+            for (key, value) in service.properties {
+                merged.properties.entry(key).or_insert(value);
+            }
+            for address in service.addresses {
+                if !merged.addresses.contains(&address) {
+                    merged.addresses.push(address);
+                }
+            }
+            merged.addresses.sort_by_key(|a| a.contains(':')); // IPv4 first, the sort is stable
+        }
+    }
+    if errors.len() == backends.len() {
+        return Err(errors.join(", "));
+    }
+    *LAST_SCAN.lock() = Some(Utc::now());
+
+    // A device found only by a failed backend would look missing
+    if errors.is_empty() {
+        for name in missing_devices(&found, config.discovery.disappeared_after_scans) {
+            if let Err(e) = mark_device_disappeared(&config, &name).await {
+                error!("❌ Failed to mark device '{}' as disappeared: {:?}", name, e);
+            }
+        }
+    }
+
+    for (name, (service, backend_name)) in found {
+        if service.own_instance {
+            *OWN_DEVICE_NAME.lock() = Some(name.clone());
+        }
+
+        // A known device that has switched to or from TLS is talked to with the advertised scheme from now on
+        if let Some(scheme) = &service.scheme {
+            let filter = doc! { "name": &name, "communication.scheme": { "$ne": scheme } };
+            if let Err(e) = update_field::<DeviceDoc>(&config.database, COLL_DEVICE, filter, "communication.scheme", Bson::String(scheme.clone())).await {
+                error!("❌ Failed to update the url scheme of device '{}': {:?}", name, e);
+            }
+        }
+
+        let scheme = service.scheme.unwrap_or_else(|| config.discovery.default_device_scheme.clone());
+        let path = service.properties.get("path").and_then(|p| base_path(p));
+        let device = DeviceDoc {
+            id: None,
+            name,
+            communication: DeviceCommunication { addresses: service.addresses, port: service.port, scheme, path },
+            description: default_device_description(),
+            status: StatusEnum::Active,
+            ok_health_check_count: 0,
+            failed_health_check_count: 0,
+            status_log: Some(vec![StatusLogEntry {
+                status: StatusEnum::Active,
+                time: Utc::now(),
+            }]),
+            health: None,
+            latency: None,
+            deployment_latency: None,
+            drain: None,
+            circuit_breaker: None,
+            disappeared_at: None,
+            discovered_by: Some(backend_name.to_string()),
+            properties: (!service.properties.is_empty()).then_some(service.properties),
+            health_pushed_at: None,
+        };
+        tokio::spawn(process_discovered_devices(vec![device], config.clone()));
+    }
+    Ok(())
+}
+
+
+/// Updates the missed scan counts with the devices found by a scan, and returns the ones that
+/// have now been missing from `after_scans` scans (never if it is 0). They are forgotten until
+/// they are found again.
+fn missing_devices<T>(found: &HashMap<String, T>, after_scans: u32) -> Vec<String> {
+    let mut missed_scans = MISSED_SCANS.lock();
+    for name in found.keys() {
+        missed_scans.insert(name.clone(), 0);
+    }
+    if after_scans == 0 {
+        return Vec::new();
+    }
+
+    let mut missing = Vec::new();
+    missed_scans.retain(|name, missed| {
+        if found.contains_key(name) {
+            return true;
+        }
+        *missed += 1;
+        if *missed >= after_scans {
+            missing.push(name.clone());
+            return false;
+        }
+        true
+    });
+    missing
+}
+
+
+/// Whether the device is this orchestrator, as found by discovery
+pub fn is_own_device(name: &str) -> bool {
+    OWN_DEVICE_NAME.lock().as_deref() == Some(name)
+}
+
+
+/// When the latest discovery scan finished, None if no scan has finished since startup
+pub fn last_scan() -> Option<DateTime<Utc>> {
+    *LAST_SCAN.lock()
+}
+
+
+/// Starts an endless loop for continously scanning for new devices with
+/// predefined intervals
+pub async fn browse_services(config: Arc<Config>) {
+    loop {
+        // Run a single scan and sleep for a predefined time before next scan
+        let _ = run_single_scan(config.clone(), config.discovery.scan_duration_s).await;
+        watchdog::heartbeat(watchdog::LOOP_DISCOVERY);
+        tokio::time::sleep(Duration::from_secs(config.discovery.scan_interval_s)).await;
+    }
+}
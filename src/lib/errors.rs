@@ -13,12 +13,36 @@ impl ApiError {
     pub fn bad_request(e: impl std::fmt::Display) -> Self {
         Self { status: StatusCode::BAD_REQUEST, msg: format!("bad request: {e}") }
     }
+    pub fn unauthorized(e: impl std::fmt::Display) -> Self {
+        Self { status: StatusCode::UNAUTHORIZED, msg: format!("unauthorized: {e}") }
+    }
+    pub fn forbidden(e: impl std::fmt::Display) -> Self {
+        Self { status: StatusCode::FORBIDDEN, msg: format!("forbidden: {e}") }
+    }
     pub fn not_found(e: impl std::fmt::Display) -> Self {
         Self { status: StatusCode::NOT_FOUND, msg: format!("not found: {e}") }
     }
+    pub fn conflict(e: impl std::fmt::Display) -> Self {
+        Self { status: StatusCode::CONFLICT, msg: format!("conflict: {e}") }
+    }
+    pub fn precondition_required(e: impl std::fmt::Display) -> Self {
+        Self { status: StatusCode::PRECONDITION_REQUIRED, msg: format!("precondition required: {e}") }
+    }
     pub fn internal_error(e: impl std::fmt::Display) -> Self {
         Self { status: StatusCode::INTERNAL_SERVER_ERROR, msg: format!("internal server error: {e}") }
     }
+    pub fn insufficient_storage(e: impl std::fmt::Display) -> Self {
+        Self { status: StatusCode::INSUFFICIENT_STORAGE, msg: format!("insufficient storage: {e}") }
+    }
+    pub fn bad_gateway(e: impl std::fmt::Display) -> Self {
+        Self { status: StatusCode::BAD_GATEWAY, msg: format!("bad gateway: {e}") }
+    }
+    pub fn service_unavailable(e: impl std::fmt::Display) -> Self {
+        Self { status: StatusCode::SERVICE_UNAVAILABLE, msg: format!("service unavailable: {e}") }
+    }
+    pub fn gateway_timeout(e: impl std::fmt::Display) -> Self {
+        Self { status: StatusCode::GATEWAY_TIMEOUT, msg: format!("gateway timeout: {e}") }
+    }
     pub fn db(e: impl std::fmt::Display) -> Self {
         Self { status: StatusCode::INTERNAL_SERVER_ERROR, msg: format!("db error: {e}") }
     }
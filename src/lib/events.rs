@@ -0,0 +1,249 @@
+//! # events.rs
+//!
+//! Internal event bus. Discovery, health checks, deployment and execution publish what
+//! happens to them as typed events, and the subsystems reacting to those (webhooks, log
+//! escalation, the event websocket and `GET /events`, and the event log) subscribe to the bus
+//! instead of being called directly by the publishers. Events are only kept in memory, a
+//! subscriber that falls behind by more than BUS_CAPACITY events misses the oldest ones.
+
+use std::future::Future;
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+
+/// Events buffered for each subscriber
+const BUS_CAPACITY: usize = 1024;
+
+static BUS: Lazy<broadcast::Sender<Event>> = Lazy::new(|| broadcast::channel(BUS_CAPACITY).0);
+
+
+pub const EVENT_DEVICE_DISCOVERED: &str = "device.discovered";
+pub const EVENT_DEVICE_ACTIVE: &str = "device.active";
+pub const EVENT_DEVICE_INACTIVE: &str = "device.inactive";
+pub const EVENT_DEVICE_DRAINED: &str = "device.drained";
+pub const EVENT_DEVICE_DISAPPEARED: &str = "device.disappeared";
+pub const EVENT_HEALTH_CHECKS_FINISHED: &str = "healthChecks.finished";
+pub const EVENT_DEPLOYMENT_DEPLOYED: &str = "deployment.deployed";
+pub const EVENT_DEPLOYMENT_FAILED: &str = "deployment.failed";
+pub const EVENT_DEPLOYMENT_VALIDATION_FAILED: &str = "deployment.validationFailed";
+pub const EVENT_EXECUTION_STARTED: &str = "execution.started";
+pub const EVENT_EXECUTION_PROGRESS: &str = "execution.progress";
+pub const EVENT_EXECUTION_FINISHED: &str = "execution.finished";
+
+/// Names of all events, as in the `type` field of a serialized event
+pub const EVENT_NAMES: &[&str] = &[
+    EVENT_DEVICE_DISCOVERED,
+    EVENT_DEVICE_ACTIVE,
+    EVENT_DEVICE_INACTIVE,
+    EVENT_DEVICE_DRAINED,
+    EVENT_DEVICE_DISAPPEARED,
+    EVENT_HEALTH_CHECKS_FINISHED,
+    EVENT_DEPLOYMENT_DEPLOYED,
+    EVENT_DEPLOYMENT_FAILED,
+    EVENT_DEPLOYMENT_VALIDATION_FAILED,
+    EVENT_EXECUTION_STARTED,
+    EVENT_EXECUTION_PROGRESS,
+    EVENT_EXECUTION_FINISHED,
+];
+
+
+/// Something that happened in the orchestrator. Serialized with the event name in `type`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub enum Event {
+    /// A device was found by discovery and saved
+    #[serde(rename = "device.discovered")]
+    DeviceDiscovered {
+        #[serde(rename = "deviceName")]
+        device_name: String,
+        addresses: Vec<String>,
+        port: u16,
+    },
+    /// A device changed to active after enough successful health checks
+    #[serde(rename = "device.active")]
+    DeviceActive {
+        #[serde(rename = "deviceId")]
+        device_id: Option<String>,
+        #[serde(rename = "deviceName")]
+        device_name: String,
+        time: DateTime<Utc>,
+    },
+    /// A device changed to inactive after enough failed health checks
+    #[serde(rename = "device.inactive")]
+    DeviceInactive {
+        #[serde(rename = "deviceId")]
+        device_id: Option<String>,
+        #[serde(rename = "deviceName")]
+        device_name: String,
+        time: DateTime<Utc>,
+    },
+    /// Draining the deployments off a device finished, `failed` lists the ones that couldnt be moved
+    #[serde(rename = "device.drained")]
+    DeviceDrained {
+        #[serde(rename = "deviceId")]
+        device_id: Option<String>,
+        #[serde(rename = "deviceName")]
+        device_name: String,
+        migrated: Vec<String>,
+        failed: Vec<String>,
+    },
+    /// A discovered device has been missing from the discovery scans for long enough, see lib/discovery.rs
+    #[serde(rename = "device.disappeared")]
+    DeviceDisappeared {
+        #[serde(rename = "deviceId")]
+        device_id: Option<String>,
+        #[serde(rename = "deviceName")]
+        device_name: String,
+        time: DateTime<Utc>,
+    },
+    /// A round of health checks on all devices finished
+    #[serde(rename = "healthChecks.finished")]
+    HealthChecksFinished {
+        succeeded: usize,
+        failed: usize,
+        inactive: usize,
+    },
+    /// A deployment was sent to all of its devices
+    #[serde(rename = "deployment.deployed")]
+    DeploymentDeployed {
+        #[serde(rename = "deploymentId")]
+        deployment_id: Option<String>,
+        #[serde(rename = "deploymentName")]
+        deployment_name: String,
+    },
+    /// Sending a deployment to its devices failed
+    #[serde(rename = "deployment.failed")]
+    DeploymentFailed {
+        #[serde(rename = "deploymentId")]
+        deployment_id: Option<String>,
+        #[serde(rename = "deploymentName")]
+        deployment_name: String,
+        device: String,
+        error: String,
+    },
+    /// A deployment failed validation, either when solved or when revalidated
+    #[serde(rename = "deployment.validationFailed")]
+    DeploymentValidationFailed {
+        #[serde(rename = "deploymentId")]
+        deployment_id: String,
+        #[serde(rename = "deploymentName")]
+        deployment_name: String,
+        error: String,
+        stage: String, // "solve" or "revalidation"
+    },
+    /// An execution got its turn in the execution queue and was started
+    #[serde(rename = "execution.started")]
+    ExecutionStarted {
+        #[serde(rename = "executionId")]
+        execution_id: u64,
+        #[serde(rename = "deploymentId")]
+        deployment_id: String,
+        #[serde(rename = "deploymentName")]
+        deployment_name: String,
+        #[serde(rename = "requestId")]
+        request_id: Option<String>,
+    },
+    /// A step of a running execution finished, as reported by its supervisor. `step` is the index
+    /// of the step in the sequence of the deployment, and `error` is set if the step failed.
+    #[serde(rename = "execution.progress")]
+    ExecutionProgress {
+        #[serde(rename = "executionId")]
+        execution_id: u64,
+        #[serde(rename = "deploymentId")]
+        deployment_id: String,
+        #[serde(rename = "deploymentName")]
+        deployment_name: String,
+        #[serde(rename = "requestId")]
+        request_id: Option<String>,
+        step: usize,
+        steps: usize,
+        device: String,
+        module: String,
+        function: String,
+        error: Option<String>,
+    },
+    /// An execution finished, `error` is set if it failed
+    #[serde(rename = "execution.finished")]
+    ExecutionFinished {
+        #[serde(rename = "executionId")]
+        execution_id: u64,
+        #[serde(rename = "deploymentId")]
+        deployment_id: String,
+        #[serde(rename = "deploymentName")]
+        deployment_name: String,
+        #[serde(rename = "requestId")]
+        request_id: Option<String>,
+        #[serde(rename = "durationMs")]
+        duration_ms: u64,
+        error: Option<String>,
+        #[serde(rename = "supervisorError")]
+        supervisor_error: bool, // The execution failed on the side of the supervisors
+    },
+}
+
+impl Event {
+    /// Name of the event, one of EVENT_NAMES
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::DeviceDiscovered { .. } => EVENT_DEVICE_DISCOVERED,
+            Event::DeviceActive { .. } => EVENT_DEVICE_ACTIVE,
+            Event::DeviceInactive { .. } => EVENT_DEVICE_INACTIVE,
+            Event::DeviceDrained { .. } => EVENT_DEVICE_DRAINED,
+            Event::DeviceDisappeared { .. } => EVENT_DEVICE_DISAPPEARED,
+            Event::HealthChecksFinished { .. } => EVENT_HEALTH_CHECKS_FINISHED,
+            Event::DeploymentDeployed { .. } => EVENT_DEPLOYMENT_DEPLOYED,
+            Event::DeploymentFailed { .. } => EVENT_DEPLOYMENT_FAILED,
+            Event::DeploymentValidationFailed { .. } => EVENT_DEPLOYMENT_VALIDATION_FAILED,
+            Event::ExecutionStarted { .. } => EVENT_EXECUTION_STARTED,
+            Event::ExecutionProgress { .. } => EVENT_EXECUTION_PROGRESS,
+            Event::ExecutionFinished { .. } => EVENT_EXECUTION_FINISHED,
+        }
+    }
+}
+
+
+/// Publishes the event to all current subscribers
+pub fn publish(event: Event) {
+    // Fails only when there are no subscribers
+    let _ = BUS.send(event);
+}
+
+
+/// Receiver of the events published from now on
+pub fn subscribe() -> broadcast::Receiver<Event> {
+    BUS.subscribe()
+}
+
+
+/// Runs the handler for each published event in a background task. `name` identifies the
+/// subscriber in the warnings about missed events.
+pub fn listen<F, Fut>(name: &'static str, mut handler: F)
+where
+    F: FnMut(Event) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    let mut rx = subscribe();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => handler(event).await,
+                Err(RecvError::Lagged(missed)) => warn!("{} fell behind and missed {} events", name, missed),
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+
+/// Writes every event into the orchestrator log
+pub fn log_events() {
+    listen("Event log", |event| async move {
+        match serde_json::to_string(&event) {
+            Ok(json) => info!("📣 {}", json),
+            Err(e) => warn!("Failed to serialize event '{}': {}", event.name(), e),
+        }
+    });
+}
@@ -0,0 +1,652 @@
+//! # execution_queue.rs
+//!
+//! Queue in front of deployment executions. With the settings given to `enqueue`, at most
+//! `execution.maxConcurrent` executions run at the same time (0 means no limit), and at most
+//! `execution.maxConcurrentPerDevice` of them on any one device, so that small devices arent
+//! overwhelmed. The rest wait in the queue. Waiting executions are started by priority (higher
+//! first), and in arrival order within the same priority. An execution whose devices are all busy is passed by the ones
+//! after it that can run. Queued executions can be reprioritized or dropped while they wait.
+//!
+//! Callers waiting for their result are refused once `execution.maxQueued` executions are
+//! waiting, so that they back off instead of piling up. Executions run in the background are
+//! always queued, and can be followed by their id.
+//!
+//! Supervisors report each step of a chained execution as it finishes (see `POST /postResult`),
+//! which is recorded as the progress of the running execution.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::Serialize;
+use tokio::sync::oneshot;
+use log::debug;
+use crate::lib::config::ExecutionConfig;
+
+
+static QUEUE: Lazy<Mutex<QueueState>> = Lazy::new(|| Mutex::new(QueueState::default()));
+// Ids continue from the startup time in milliseconds, so that they stay unique across restarts
+// and can be used as the job ids of stored results
+static NEXT_ID: Lazy<AtomicU64> = Lazy::new(|| AtomicU64::new(Utc::now().timestamp_millis().max(1) as u64));
+
+/// How long start times of executions are remembered for `started_within`
+const STARTED_HISTORY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How many finished executions are kept for `recent_executions`
+const RECENT_EXECUTIONS: usize = 20;
+
+
+/// What is being executed, shown in the queue listing
+#[derive(Debug, Clone)]
+pub struct ExecutionInfo {
+    pub deployment_id: String,
+    pub deployment_name: String,
+    pub devices: Vec<String>, // Devices taking part in the deployment
+    pub priority: i32,
+    pub request_id: Option<String>, // Id of the request that started the execution, see lib/request_id.rs
+    pub steps: Vec<ExecutionStep>, // Sequence of the deployment
+}
+
+
+/// A step in the sequence of an execution
+#[derive(Debug, Clone, Serialize)]
+pub struct ExecutionStep {
+    pub device: String,
+    pub module: String,
+    pub function: String,
+}
+
+
+#[derive(Debug)]
+struct QueueEntry {
+    info: ExecutionInfo,
+    enqueued_at: Instant,
+    enqueued_at_utc: DateTime<Utc>,
+    started_at: Option<Instant>,
+    started_at_utc: Option<DateTime<Utc>>,
+    waker: Option<oneshot::Sender<()>>, // Set while the entry is waiting
+    canceller: Option<oneshot::Sender<()>>, // Taken when the running execution is cancelled
+    completed_steps: usize, // Steps the supervisors have reported as finished
+    last_report_at: Option<DateTime<Utc>>,
+}
+
+impl QueueEntry {
+    fn progress(&self) -> ExecutionProgress {
+        let steps = self.info.steps.len();
+        let step = (self.completed_steps < steps).then_some(self.completed_steps);
+        ExecutionProgress {
+            steps,
+            completed_steps: self.completed_steps,
+            step,
+            current: step.map(|i| self.info.steps[i].clone()),
+            last_report_at: self.last_report_at,
+        }
+    }
+}
+
+
+/// How far a running execution has got in its sequence, as reported by the supervisors
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecutionProgress {
+    pub steps: usize,
+    pub completed_steps: usize,
+    pub step: Option<usize>, // Index of the step executing now, None once every step has finished
+    pub current: Option<ExecutionStep>,
+    pub last_report_at: Option<DateTime<Utc>>,
+}
+
+
+/// Concurrency limits of the execution settings, 0 for no limit
+#[derive(Debug, Default, Clone, Copy)]
+struct Limits {
+    max_concurrent: usize,
+    max_concurrent_per_device: usize,
+}
+
+
+#[derive(Debug, Default)]
+struct QueueState {
+    entries: HashMap<u64, QueueEntry>,
+    limits: Limits, // From the settings given to the latest `enqueue`, followed also when executions leave the queue
+    started: VecDeque<Instant>, // Start times of executions within STARTED_HISTORY, oldest first
+    recent: VecDeque<FinishedExecution>, // Latest finished executions, newest first
+}
+
+impl QueueState {
+    fn running_count(&self) -> usize {
+        self.entries.values().filter(|e| e.started_at.is_some()).count()
+    }
+
+    /// Ids of the waiting entries in the order they will be started
+    fn pending_order(&self) -> Vec<u64> {
+        let mut pending: Vec<(&u64, &QueueEntry)> = self.entries.iter().filter(|(_, e)| e.started_at.is_none()).collect();
+        pending.sort_by(|(a_id, a), (b_id, b)| b.info.priority.cmp(&a.info.priority).then(a_id.cmp(b_id)));
+        pending.into_iter().map(|(id, _)| *id).collect()
+    }
+
+    fn pending_count(&self) -> usize {
+        self.entries.values().filter(|e| e.started_at.is_none()).count()
+    }
+
+    /// Number of running executions on each device
+    fn running_per_device(&self) -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+        for entry in self.entries.values().filter(|e| e.started_at.is_some()) {
+            for device in &entry.info.devices {
+                *counts.entry(device.clone()).or_insert(0) += 1;
+            }
+        }
+        counts
+    }
+
+    /// Starts waiting entries while there is room for them, skipping the ones with a device
+    /// that is already running its limit of executions
+    fn dispatch(&mut self) {
+        let Limits { max_concurrent: limit, max_concurrent_per_device: device_limit } = self.limits;
+        let mut running_per_device = self.running_per_device();
+        for id in self.pending_order() {
+            if limit > 0 && self.running_count() >= limit {
+                break;
+            }
+            if let Some(entry) = self.entries.get_mut(&id) {
+                let devices = &entry.info.devices;
+                if device_limit > 0 && devices.iter().any(|d| running_per_device.get(d).is_some_and(|n| *n >= device_limit)) {
+                    continue;
+                }
+                for device in devices {
+                    *running_per_device.entry(device.clone()).or_insert(0) += 1;
+                }
+                let now = Instant::now();
+                entry.started_at = Some(now);
+                entry.started_at_utc = Some(Utc::now());
+                if let Some(waker) = entry.waker.take() {
+                    let _ = waker.send(());
+                }
+                self.started.push_back(now);
+            }
+        }
+        self.forget_old_starts();
+    }
+
+    fn forget_old_starts(&mut self) {
+        while self.started.front().is_some_and(|t| t.elapsed() > STARTED_HISTORY) {
+            self.started.pop_front();
+        }
+    }
+}
+
+
+/// A place in the execution queue. The execution may run while this is held, and the place
+/// is released when it is dropped (also when the request is cancelled while still waiting).
+pub struct ExecutionSlot {
+    id: u64,
+    outcome: Option<Result<(), String>>,
+    cancel: Option<oneshot::Receiver<()>>,
+}
+
+impl ExecutionSlot {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Resolves when the execution is cancelled with `cancel`, never otherwise
+    pub async fn cancelled(&mut self) {
+        if let Some(cancel) = self.cancel.take() {
+            if cancel.await.is_ok() {
+                return;
+            }
+        }
+        std::future::pending::<()>().await
+    }
+
+    /// Records how the execution ended, shown in `recent_executions` once the slot is released
+    pub fn finish(&mut self, outcome: Result<(), String>) {
+        self.outcome = Some(outcome);
+    }
+}
+
+impl Drop for ExecutionSlot {
+    fn drop(&mut self) {
+        let mut state = QUEUE.lock();
+        if let Some(entry) = state.entries.remove(&self.id) {
+            if let (Some(started_at), Some(started_at_utc)) = (entry.started_at, entry.started_at_utc) {
+                let (status, error) = match self.outcome.take() {
+                    Some(Ok(())) => ("succeeded", None),
+                    Some(Err(e)) => ("failed", Some(e)),
+                    None => ("cancelled", None),
+                };
+                state.recent.push_front(FinishedExecution {
+                    id: self.id,
+                    deployment_id: entry.info.deployment_id,
+                    deployment_name: entry.info.deployment_name,
+                    request_id: entry.info.request_id,
+                    started_at: started_at_utc,
+                    duration_ms: started_at.elapsed().as_millis() as u64,
+                    status,
+                    error,
+                });
+                state.recent.truncate(RECENT_EXECUTIONS);
+            }
+        }
+        state.dispatch();
+    }
+}
+
+
+/// An execution that has finished, see `recent_executions`
+#[derive(Debug, Clone, Serialize)]
+pub struct FinishedExecution {
+    pub id: u64,
+    #[serde(rename = "deploymentId")]
+    pub deployment_id: String,
+    #[serde(rename = "deploymentName")]
+    pub deployment_name: String,
+    #[serde(rename = "requestId", skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(rename = "startedAt")]
+    pub started_at: DateTime<Utc>,
+    #[serde(rename = "durationMs")]
+    pub duration_ms: u64,
+    pub status: &'static str, // "succeeded", "failed" or "cancelled" (the request went away)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+
+/// Returned when a waiting execution was dropped from the queue
+#[derive(Debug)]
+pub struct DroppedFromQueue;
+
+
+/// Returned when an execution isnt queued because `execution.maxQueued` executions are
+/// already waiting
+#[derive(Debug)]
+pub struct QueueFull {
+    pub pending: usize,
+}
+
+
+/// An execution that has been queued, see `enqueue`
+pub struct QueuedSlot {
+    slot: ExecutionSlot,
+    position: Option<usize>,
+    started: Option<oneshot::Receiver<()>>, // None if the execution started right away
+}
+
+impl QueuedSlot {
+    pub fn id(&self) -> u64 {
+        self.slot.id
+    }
+
+    /// Place among the pending executions when queued, None if the execution started right away
+    pub fn position(&self) -> Option<usize> {
+        self.position
+    }
+
+    /// Waits until the execution can run
+    pub async fn wait(self) -> Result<ExecutionSlot, DroppedFromQueue> {
+        let Some(started) = self.started else {
+            return Ok(self.slot);
+        };
+        match started.await {
+            Ok(()) => Ok(self.slot),
+            Err(_) => Err(DroppedFromQueue),
+        }
+    }
+}
+
+
+/// Queues an execution. With `bounded` the execution is refused if `execution.maxQueued`
+/// executions are already waiting. Dropping the returned slot leaves the queue.
+pub fn enqueue(settings: &ExecutionConfig, info: ExecutionInfo, bounded: bool) -> Result<QueuedSlot, QueueFull> {
+    let mut state = QUEUE.lock();
+    state.limits = Limits {
+        max_concurrent: settings.max_concurrent,
+        max_concurrent_per_device: settings.max_concurrent_per_device,
+    };
+    let pending = state.pending_count();
+    let max_queued = settings.max_queued;
+    if bounded && max_queued > 0 && pending >= max_queued {
+        return Err(QueueFull { pending });
+    }
+
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let (tx, rx) = oneshot::channel();
+    let (canceller, cancel) = oneshot::channel();
+    state.entries.insert(id, QueueEntry {
+        info,
+        enqueued_at: Instant::now(),
+        enqueued_at_utc: Utc::now(),
+        started_at: None,
+        started_at_utc: None,
+        waker: Some(tx),
+        canceller: Some(canceller),
+        completed_steps: 0,
+        last_report_at: None,
+    });
+    state.dispatch();
+    let slot = ExecutionSlot { id, outcome: None, cancel: Some(cancel) };
+    if state.entries.get(&id).is_some_and(|e| e.started_at.is_some()) {
+        return Ok(QueuedSlot { slot, position: None, started: None });
+    }
+    let position = state.pending_order().iter().position(|p| *p == id);
+    debug!("Execution {} queued at position {:?}", id, position);
+    Ok(QueuedSlot { slot, position, started: Some(rx) })
+}
+
+
+/// A single execution in the queue listing
+#[derive(Debug, Clone, Serialize)]
+pub struct QueuedExecution {
+    pub id: u64,
+    #[serde(rename = "deploymentId")]
+    pub deployment_id: String,
+    #[serde(rename = "deploymentName")]
+    pub deployment_name: String,
+    pub devices: Vec<String>,
+    pub priority: i32,
+    #[serde(rename = "requestId", skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    pub state: &'static str, // "running" or "pending"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<usize>, // Place among the pending executions, 0 starts next
+    #[serde(rename = "enqueuedAt")]
+    pub enqueued_at: DateTime<Utc>,
+    #[serde(rename = "ageMs")]
+    pub age_ms: u64, // Time since the execution was queued
+    #[serde(rename = "waitedMs")]
+    pub waited_ms: u64, // Time spent waiting before starting (so far, if still pending)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<ExecutionProgress>, // Set while running
+}
+
+
+/// Number of running and pending executions of a deployment or device
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct QueueCounts {
+    pub running: usize,
+    pub pending: usize,
+}
+
+
+/// Snapshot of the execution queue
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueSnapshot {
+    #[serde(rename = "maxConcurrent")]
+    pub max_concurrent: usize, // 0 means no limit
+    #[serde(rename = "maxConcurrentPerDevice")]
+    pub max_concurrent_per_device: usize, // 0 means no limit
+    #[serde(rename = "maxQueued")]
+    pub max_queued: usize, // Pending executions before callers waiting for the result are refused, 0 means no limit
+    pub running: Vec<QueuedExecution>,
+    pub pending: Vec<QueuedExecution>,
+    #[serde(rename = "byDeployment")]
+    pub by_deployment: BTreeMap<String, QueueCounts>,
+    #[serde(rename = "byDevice")]
+    pub by_device: BTreeMap<String, QueueCounts>,
+}
+
+
+/// Returns the running and pending executions, pending ones in the order they will be started
+pub fn snapshot(settings: &ExecutionConfig) -> QueueSnapshot {
+    let (running, pending) = listing();
+    let mut by_deployment: BTreeMap<String, QueueCounts> = BTreeMap::new();
+    let mut by_device: BTreeMap<String, QueueCounts> = BTreeMap::new();
+    for e in running.iter().chain(pending.iter()) {
+        let is_running = e.state == "running";
+        let count = |c: &mut QueueCounts| if is_running { c.running += 1 } else { c.pending += 1 };
+        count(by_deployment.entry(e.deployment_id.clone()).or_default());
+        for device in &e.devices {
+            count(by_device.entry(device.clone()).or_default());
+        }
+    }
+
+    QueueSnapshot {
+        max_concurrent: settings.max_concurrent,
+        max_concurrent_per_device: settings.max_concurrent_per_device,
+        max_queued: settings.max_queued,
+        running,
+        pending,
+        by_deployment,
+        by_device,
+    }
+}
+
+
+/// The running and the pending executions, pending ones in the order they will be started
+fn listing() -> (Vec<QueuedExecution>, Vec<QueuedExecution>) {
+    let state = QUEUE.lock();
+    let now = Instant::now();
+    let listed = |id: u64, position: Option<usize>| {
+        let e = &state.entries[&id];
+        QueuedExecution {
+            id,
+            deployment_id: e.info.deployment_id.clone(),
+            deployment_name: e.info.deployment_name.clone(),
+            devices: e.info.devices.clone(),
+            priority: e.info.priority,
+            request_id: e.info.request_id.clone(),
+            state: if e.started_at.is_some() { "running" } else { "pending" },
+            position,
+            enqueued_at: e.enqueued_at_utc,
+            age_ms: now.duration_since(e.enqueued_at).as_millis() as u64,
+            waited_ms: e.started_at.unwrap_or(now).duration_since(e.enqueued_at).as_millis() as u64,
+            progress: e.started_at.map(|_| e.progress()),
+        }
+    };
+
+    let mut running_ids: Vec<u64> = state.entries.iter().filter(|(_, e)| e.started_at.is_some()).map(|(id, _)| *id).collect();
+    running_ids.sort();
+    let running: Vec<QueuedExecution> = running_ids.into_iter().map(|id| listed(id, None)).collect();
+    let pending: Vec<QueuedExecution> = state
+        .pending_order()
+        .into_iter()
+        .enumerate()
+        .map(|(position, id)| listed(id, Some(position)))
+        .collect();
+    (running, pending)
+}
+
+
+/// A running or pending execution, or one that has recently finished
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum ExecutionState {
+    Queued(QueuedExecution),
+    Finished(FinishedExecution),
+}
+
+
+/// The execution with the given id, if it is still in the queue or among the recently finished ones
+pub fn execution_state(id: u64) -> Option<ExecutionState> {
+    let (running, pending) = listing();
+    let queued = running.into_iter().chain(pending).find(|e| e.id == id);
+    match queued {
+        Some(execution) => Some(ExecutionState::Queued(execution)),
+        None => QUEUE.lock().recent.iter().find(|f| f.id == id).cloned().map(ExecutionState::Finished),
+    }
+}
+
+
+/// Why a queued execution couldnt be changed
+#[derive(Debug)]
+pub enum QueueChangeError {
+    NotFound,
+    AlreadyRunning,
+}
+
+
+/// Changes the priority of a waiting execution. With `to_front` the execution gets a priority
+/// above all other waiting executions, so it is started next.
+pub fn reprioritize(id: u64, priority: Option<i32>, to_front: bool) -> Result<QueuedExecution, QueueChangeError> {
+    {
+        let mut state = QUEUE.lock();
+        let highest = state
+            .entries
+            .iter()
+            .filter(|(other, e)| **other != id && e.started_at.is_none())
+            .map(|(_, e)| e.info.priority)
+            .max();
+        let entry = state.entries.get_mut(&id).ok_or(QueueChangeError::NotFound)?;
+        if entry.started_at.is_some() {
+            return Err(QueueChangeError::AlreadyRunning);
+        }
+        if let Some(p) = priority {
+            entry.info.priority = p;
+        }
+        if to_front {
+            entry.info.priority = entry.info.priority.max(highest.map(|h| h.saturating_add(1)).unwrap_or(i32::MIN));
+        }
+    }
+    listing().1.into_iter().find(|e| e.id == id).ok_or(QueueChangeError::NotFound)
+}
+
+
+/// Drops a waiting execution from the queue. Its request gets an error response.
+pub fn drop_queued(id: u64) -> Result<(), QueueChangeError> {
+    let mut state = QUEUE.lock();
+    match state.entries.get(&id) {
+        None => Err(QueueChangeError::NotFound),
+        Some(e) if e.started_at.is_some() => Err(QueueChangeError::AlreadyRunning),
+        Some(_) => {
+            // Dropping the waker wakes the waiting request with an error
+            state.entries.remove(&id);
+            Ok(())
+        }
+    }
+}
+
+
+/// Cancels an execution. A waiting execution is dropped from the queue like with `drop_queued`,
+/// and a running one is stopped by whoever runs it (see `ExecutionSlot::cancelled`).
+pub fn cancel(id: u64) -> Result<(), QueueChangeError> {
+    let mut state = QUEUE.lock();
+    let entry = state.entries.get_mut(&id).ok_or(QueueChangeError::NotFound)?;
+    if entry.started_at.is_none() {
+        state.entries.remove(&id);
+        return Ok(());
+    }
+    // Cancelling twice is the same as cancelling once
+    if let Some(canceller) = entry.canceller.take() {
+        let _ = canceller.send(());
+    }
+    Ok(())
+}
+
+
+/// Id of the running execution of the deployment with the given request id. Without a request
+/// id, the only running execution of the deployment.
+pub fn running_execution(deployment_id: &str, request_id: Option<&str>) -> Option<u64> {
+    let state = QUEUE.lock();
+    let mut running = state.entries.iter().filter(|(_, e)| e.started_at.is_some() && e.info.deployment_id == deployment_id);
+    match request_id {
+        Some(request_id) => running.find(|(_, e)| e.info.request_id.as_deref() == Some(request_id)).map(|(id, _)| *id),
+        None => match (running.next(), running.next()) {
+            (Some((id, _)), None) => Some(*id),
+            _ => None,
+        },
+    }
+}
+
+
+/// Records that a step of a running execution of the deployment has finished. A step given by
+/// its function (and module) is looked up from the steps that havent finished yet.
+pub fn record_step(id: u64, deployment_id: &str, step: StepRef<'_>) -> Result<RecordedStep, StepError> {
+    let mut state = QUEUE.lock();
+    let entry = state.entries.get_mut(&id).ok_or(StepError::NotRunning)?;
+    if entry.started_at.is_none() || entry.info.deployment_id != deployment_id {
+        return Err(StepError::NotRunning);
+    }
+    let index = match step {
+        StepRef::Index(index) if index < entry.info.steps.len() => index,
+        StepRef::Index(_) => return Err(StepError::UnknownStep),
+        StepRef::Function { module, function } => entry
+            .info
+            .steps
+            .iter()
+            .enumerate()
+            .skip(entry.completed_steps)
+            .find(|(_, s)| s.function == function && module.is_none_or(|m| s.module == m))
+            .map(|(i, _)| i)
+            .ok_or(StepError::UnknownStep)?,
+    };
+    // Reports can arrive out of order, a late report of an earlier step doesnt move the progress back
+    entry.completed_steps = entry.completed_steps.max(index + 1);
+    entry.last_report_at = Some(Utc::now());
+    debug!("Execution {} finished step {} of {}", id, index + 1, entry.info.steps.len());
+    Ok(RecordedStep {
+        index,
+        step: entry.info.steps[index].clone(),
+        deployment_id: entry.info.deployment_id.clone(),
+        deployment_name: entry.info.deployment_name.clone(),
+        request_id: entry.info.request_id.clone(),
+        progress: entry.progress(),
+    })
+}
+
+
+/// A finished step recorded with `record_step`, and the progress of its execution after it
+#[derive(Debug, Clone)]
+pub struct RecordedStep {
+    pub index: usize,
+    pub step: ExecutionStep,
+    pub deployment_id: String,
+    pub deployment_name: String,
+    pub request_id: Option<String>,
+    pub progress: ExecutionProgress,
+}
+
+
+/// A step of an execution as reported by a supervisor
+pub enum StepRef<'a> {
+    Index(usize),
+    Function { module: Option<&'a str>, function: &'a str },
+}
+
+
+/// Why a finished step couldnt be recorded
+#[derive(Debug)]
+pub enum StepError {
+    NotRunning,
+    UnknownStep,
+}
+
+
+/// Number of executions started within the given time (at most the last 24 hours, and only
+/// since the orchestrator was started)
+pub fn started_within(within: Duration) -> usize {
+    let mut state = QUEUE.lock();
+    state.forget_old_starts();
+    state.started.iter().rev().take_while(|t| t.elapsed() <= within).count()
+}
+
+
+/// Latest finished executions since the orchestrator was started, newest first
+pub fn recent_executions() -> Vec<FinishedExecution> {
+    QUEUE.lock().recent.iter().cloned().collect()
+}
+
+
+/// Request id of the given execution of the deployment, or of its latest started execution
+/// when no execution id is given. Looks at the running and the recently finished executions.
+pub fn execution_request_id(deployment_id: &str, execution_id: Option<u64>) -> Option<String> {
+    let state = QUEUE.lock();
+    let matches = |id: u64, deployment: &str| deployment == deployment_id && (execution_id.is_none() || execution_id == Some(id));
+    let running = state
+        .entries
+        .iter()
+        .filter(|(id, e)| e.started_at.is_some() && matches(**id, &e.info.deployment_id))
+        .max_by_key(|(_, e)| e.started_at)
+        .map(|(_, e)| e.info.request_id.clone());
+    match running {
+        Some(request_id) => request_id,
+        None => state
+            .recent
+            .iter()
+            .find(|f| matches(f.id, &f.deployment_id))
+            .and_then(|f| f.request_id.clone()),
+    }
+}
@@ -0,0 +1,62 @@
+//! # http_client.rs
+//!
+//! The HTTP client shared by all outgoing requests (supervisors, the policy engine, webhooks),
+//! so that connections are pooled and kept alive between requests instead of reconnecting for
+//! each. Built on first use from the `httpClient` settings the callers pass. Every request
+//! gets the default timeout of the settings, and operations with a timeout of their own set it
+//! on the request with `timeout`.
+
+use std::time::Duration;
+use once_cell::sync::OnceCell;
+use crate::lib::config::HttpClientConfig;
+
+
+/// The connection pool, shared by every request
+static CLIENT: OnceCell<reqwest::Client> = OnceCell::new();
+
+
+/// Operations with a timeout of their own
+#[derive(Debug, Clone, Copy)]
+pub enum Operation {
+    DeviceDescription, // Also interface probes
+    Registration,
+    Deployment,
+    Execution,
+    LogLevel,
+    PolicyDecision,
+}
+
+
+/// The shared client, built from the settings by the first request. The settings come from
+/// the one configuration of the orchestrator, so every caller passes the same ones.
+pub fn client(settings: &HttpClientConfig) -> &'static reqwest::Client {
+    CLIENT.get_or_init(|| build(settings).expect("the proxy url is checked when the configuration is loaded"))
+}
+
+
+/// Timeout of the operation, to set on its requests with `RequestBuilder::timeout`
+pub fn timeout(settings: &HttpClientConfig, operation: Operation) -> Duration {
+    let seconds = match operation {
+        Operation::DeviceDescription => settings.description_timeout_s,
+        Operation::Registration => settings.registration_timeout_s,
+        Operation::Deployment => settings.deployment_timeout_s,
+        Operation::Execution => settings.execution_timeout_s,
+        Operation::LogLevel => settings.log_level_timeout_s,
+        Operation::PolicyDecision => settings.policy_decision_timeout_s,
+    };
+    Duration::from_secs(seconds)
+}
+
+
+fn build(settings: &HttpClientConfig) -> Result<reqwest::Client, reqwest::Error> {
+    let mut builder = reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(settings.connect_timeout_s))
+        .timeout(Duration::from_secs(settings.timeout_s))
+        .pool_idle_timeout(Duration::from_secs(settings.pool_idle_timeout_s))
+        .pool_max_idle_per_host(settings.pool_max_idle_per_host)
+        .tcp_keepalive(Duration::from_secs(60));
+    if let Some(proxy) = &settings.proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy.as_str())?);
+    }
+    builder.build()
+}
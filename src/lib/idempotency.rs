@@ -0,0 +1,203 @@
+//! # idempotency.rs
+//!
+//! Idempotency keys of the creation endpoints. A client can send a unique `Idempotency-Key`
+//! header with POST /file/manifest or POST /file/module, and retry the request with the same
+//! key after a timeout without creating a duplicate. The key is stored in COLL_IDEMPOTENCY_KEYS
+//! when the request arrives, the response is stored along with it once the request finishes,
+//! and repeats get that response back with the `Idempotent-Replayed: true` header. Keys expire
+//! after `server.idempotencyKeyTtlS`.
+//!
+//! A repeat arriving while the first request is still being handled is rejected with 409.
+//! Server errors (5xx) are not stored, so the request can be retried with the same key.
+
+use actix_web::body::{self, BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use log::{debug, error};
+use mongodb::bson::{self, doc};
+use crate::lib::config::Config;
+use crate::lib::constants::COLL_IDEMPOTENCY_KEYS;
+use crate::lib::errors::ApiError;
+use crate::lib::mongodb::{get_collection, is_duplicate_key};
+use crate::structs::idempotency::{IdempotencyRecord, IdempotencyState};
+
+
+/// Header with the idempotency key of the request
+pub const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+
+/// Header set on responses that are returned again for a repeated key
+pub const IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+/// Longest accepted idempotency key
+const MAX_KEY_LENGTH: usize = 255;
+
+/// How long a key can stay pending before a repeat may take it over, in case the orchestrator
+/// was stopped while handling the first request
+const PENDING_TAKEOVER_S: i64 = 600;
+
+
+/// Middleware making POST requests with an Idempotency-Key header safe to retry, used with
+/// `actix_web::middleware::from_fn` on the creation routes
+pub async fn idempotent_requests(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    if *req.method() != Method::POST || !req.headers().contains_key(IDEMPOTENCY_KEY) {
+        return next.call(req).await.map(ServiceResponse::map_into_boxed_body);
+    }
+    let key = match idempotency_key(&req) {
+        Ok(key) => key,
+        Err(e) => return Ok(req.error_response(e)),
+    };
+    let route = format!("{} {}", req.method(), req.match_pattern().unwrap_or_else(|| req.path().to_string()));
+    let config = req.extract::<web::Data<Config>>().await?;
+
+    match claim_key(&config, &key, &route, config.server.idempotency_key_ttl_s).await {
+        Ok(None) => {}
+        Ok(Some(record)) => {
+            debug!("Replaying the response to {} for idempotency key '{}'", route, key);
+            return Ok(req.into_response(replay(&record)));
+        }
+        Err(e) => return Ok(req.error_response(e)),
+    }
+
+    let res = match next.call(req).await {
+        Ok(res) => res,
+        Err(e) => {
+            release_key(&config, &key).await;
+            return Err(e);
+        }
+    };
+    if res.status().is_server_error() {
+        release_key(&config, &key).await;
+        return Ok(res.map_into_boxed_body());
+    }
+
+    // The body is read to be stored, and sent on as it was
+    let (http_req, http_res) = res.into_parts();
+    let (head, res_body) = http_res.into_parts();
+    let bytes = match body::to_bytes(res_body).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            release_key(&config, &key).await;
+            let e: Box<dyn std::error::Error> = e.into();
+            return Err(actix_web::error::ErrorInternalServerError(e.to_string()));
+        }
+    };
+    let content_type = head
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    complete_key(&config, &key, head.status(), content_type, String::from_utf8_lossy(&bytes).into_owned()).await;
+    Ok(ServiceResponse::new(http_req, head.set_body(BoxBody::new(bytes))))
+}
+
+
+/// The idempotency key of the request, which must be printable and at most MAX_KEY_LENGTH long
+fn idempotency_key(req: &ServiceRequest) -> Result<String, ApiError> {
+    let key = req
+        .headers()
+        .get(IDEMPOTENCY_KEY)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .unwrap_or_default();
+    if key.is_empty() || key.len() > MAX_KEY_LENGTH {
+        return Err(ApiError::bad_request(format!(
+            "invalid Idempotency-Key header, expected a non-empty key of at most {} characters",
+            MAX_KEY_LENGTH
+        )));
+    }
+    Ok(key.to_string())
+}
+
+
+/// Stores the key as pending. Returns the stored record if a request with the key has already
+/// completed, and a conflict if one is still pending or the key was used on another route.
+async fn claim_key(config: &Config, key: &str, route: &str, ttl_s: u64) -> Result<Option<IdempotencyRecord>, ApiError> {
+    let coll = get_collection::<IdempotencyRecord>(&config.database, COLL_IDEMPOTENCY_KEYS).await;
+    let now = Utc::now();
+    let record = IdempotencyRecord {
+        key: key.to_string(),
+        route: route.to_string(),
+        state: IdempotencyState::Pending,
+        created_at: now,
+        expires_at: now + chrono::Duration::seconds(ttl_s as i64),
+        status: None,
+        content_type: None,
+        body: None,
+    };
+    match coll.insert_one(&record).await {
+        Ok(_) => return Ok(None),
+        Err(e) if is_duplicate_key(&e) => {}
+        Err(e) => return Err(ApiError::db(e)),
+    }
+
+    let existing = coll
+        .find_one(doc! { "_id": key })
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::conflict(format!("the request with Idempotency-Key '{}' has just expired, retry it", key)))?;
+    if existing.route != route {
+        return Err(ApiError::conflict(format!("Idempotency-Key '{}' has already been used with {}", key, existing.route)));
+    }
+    if existing.state == IdempotencyState::Completed {
+        return Ok(Some(existing));
+    }
+
+    // A pending key left behind by a request that never finished can be taken over
+    let stale_before = now - chrono::Duration::seconds(PENDING_TAKEOVER_S);
+    let taken = coll
+        .update_one(
+            doc! { "_id": key, "state": "pending", "createdAt": { "$lt": bson::DateTime::from_chrono(stale_before) } },
+            doc! { "$set": {
+                "createdAt": bson::DateTime::from_chrono(now),
+                "expiresAt": bson::DateTime::from_chrono(record.expires_at),
+            } },
+        )
+        .await
+        .map_err(ApiError::db)?;
+    if taken.modified_count == 1 {
+        return Ok(None);
+    }
+    Err(ApiError::conflict(format!("a request with Idempotency-Key '{}' is still in progress", key)))
+}
+
+
+/// Stores the response of the request that claimed the key
+async fn complete_key(config: &Config, key: &str, status: StatusCode, content_type: Option<String>, body: String) {
+    let coll = get_collection::<IdempotencyRecord>(&config.database, COLL_IDEMPOTENCY_KEYS).await;
+    let mut set_doc = doc! { "state": "completed", "status": status.as_u16() as i32, "body": body };
+    if let Some(content_type) = content_type {
+        set_doc.insert("contentType", content_type);
+    }
+    if let Err(e) = coll.update_one(doc! { "_id": key }, doc! { "$set": set_doc }).await {
+        error!("❌ Failed to store the response for idempotency key '{}': {}", key, e);
+        release_key(config, key).await;
+    }
+}
+
+
+/// Removes a pending key, so that the request can be retried with it
+async fn release_key(config: &Config, key: &str) {
+    let coll = get_collection::<IdempotencyRecord>(&config.database, COLL_IDEMPOTENCY_KEYS).await;
+    if let Err(e) = coll.delete_one(doc! { "_id": key, "state": "pending" }).await {
+        error!("❌ Failed to release idempotency key '{}': {}", key, e);
+    }
+}
+
+
+/// The stored response of a completed request
+fn replay(record: &IdempotencyRecord) -> HttpResponse {
+    let status = record.status.and_then(|s| StatusCode::from_u16(s).ok()).unwrap_or(StatusCode::OK);
+    let mut builder = HttpResponse::build(status);
+    if let Some(content_type) = record.content_type.as_deref().and_then(|c| HeaderValue::from_str(c).ok()) {
+        builder.insert_header((header::CONTENT_TYPE, content_type));
+    }
+    builder.insert_header((IDEMPOTENT_REPLAYED, HeaderValue::from_static("true")));
+    builder.body(record.body.clone().unwrap_or_default())
+}
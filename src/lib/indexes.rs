@@ -0,0 +1,168 @@
+//! # indexes.rs
+//!
+//! Indexes created at startup. Unique indexes keep duplicate devices, modules and cards
+//! from being inserted (the insert paths turn the resulting duplicate key errors into
+//! conflicts), and `dateReceived` is indexed for the `after` filters and sorting of the
+//! log and card listings. Supervisor logs can optionally expire through a TTL index, and
+//! idempotency keys always do.
+
+use std::time::Duration;
+use futures::TryStreamExt;
+use log::{error, info, warn};
+use mongodb::IndexModel;
+use mongodb::bson::{doc, Document};
+use mongodb::error::ErrorKind;
+use mongodb::options::IndexOptions;
+use crate::lib::constants::{
+    COLL_API_KEYS,
+    COLL_AUDIT_LOG,
+    COLL_CARD_TOKENS,
+    COLL_DATASOURCE_CARDS,
+    COLL_DEVICE,
+    COLL_EXECUTION_INPUTS,
+    COLL_EXECUTION_OUTPUTS,
+    COLL_EXECUTION_RESULTS,
+    COLL_IDEMPOTENCY_KEYS,
+    COLL_LOGS,
+    COLL_MODULE,
+    COLL_MODULE_CARDS,
+    COLL_NODE_CARDS,
+    COLL_TRASH,
+    COLL_USERS
+};
+use crate::lib::config::Config;
+use crate::lib::mongodb::{get_collection, is_duplicate_key};
+
+
+/// Error codes returned when an index with the same name or keys but different options exists
+const INDEX_OPTIONS_CONFLICT: i32 = 85;
+const INDEX_KEY_SPECS_CONFLICT: i32 = 86;
+
+
+/// An index to create on a collection
+struct IndexSpec {
+    collection: &'static str,
+    name: &'static str,
+    keys: Document,
+    unique: bool,
+    ttl: Option<Duration>,
+}
+
+
+/// Indexes that should exist in the database
+fn index_specs(config: &Config) -> Vec<IndexSpec> {
+    let ttl_days = config.logs.supervisor_log_ttl_days;
+    let log_ttl = (ttl_days > 0).then(|| Duration::from_secs(ttl_days * 24 * 60 * 60));
+    vec![
+        IndexSpec { collection: COLL_DEVICE, name: "name_unique", keys: doc! { "name": 1 }, unique: true, ttl: None },
+        // Modules dont carry a version yet, so until they do this keeps module names unique
+        IndexSpec { collection: COLL_MODULE, name: "name_version_unique", keys: doc! { "name": 1, "version": 1 }, unique: true, ttl: None },
+        // Filters of GET /file/module
+        IndexSpec { collection: COLL_MODULE, name: "tags", keys: doc! { "tags": 1 }, unique: false, ttl: None },
+        IndexSpec { collection: COLL_MODULE, name: "exports_name", keys: doc! { "exports.name": 1 }, unique: false, ttl: None },
+        IndexSpec { collection: COLL_NODE_CARDS, name: "nodeid_unique", keys: doc! { "nodeid": 1 }, unique: true, ttl: None },
+        IndexSpec { collection: COLL_MODULE_CARDS, name: "moduleid_unique", keys: doc! { "moduleid": 1 }, unique: true, ttl: None },
+        IndexSpec { collection: COLL_CARD_TOKENS, name: "token_unique", keys: doc! { "token": 1 }, unique: true, ttl: None },
+        IndexSpec { collection: COLL_NODE_CARDS, name: "dateReceived", keys: doc! { "dateReceived": 1 }, unique: false, ttl: None },
+        IndexSpec { collection: COLL_MODULE_CARDS, name: "dateReceived", keys: doc! { "dateReceived": 1 }, unique: false, ttl: None },
+        IndexSpec { collection: COLL_DATASOURCE_CARDS, name: "dateReceived", keys: doc! { "dateReceived": 1 }, unique: false, ttl: None },
+        IndexSpec { collection: COLL_LOGS, name: "dateReceived", keys: doc! { "dateReceived": 1 }, unique: false, ttl: log_ttl },
+        // Filters of GET /device/logs
+        IndexSpec { collection: COLL_LOGS, name: "deviceName_dateReceived", keys: doc! { "deviceName": 1, "dateReceived": 1 }, unique: false, ttl: None },
+        IndexSpec { collection: COLL_LOGS, name: "loglevel_dateReceived", keys: doc! { "loglevel": 1, "dateReceived": 1 }, unique: false, ttl: None },
+        IndexSpec { collection: COLL_LOGS, name: "module_name_dateReceived", keys: doc! { "module_name": 1, "dateReceived": 1 }, unique: false, ttl: None },
+        IndexSpec { collection: COLL_LOGS, name: "deployment_id_timestamp", keys: doc! { "deployment_id": 1, "timestamp": 1 }, unique: false, ttl: None },
+        IndexSpec { collection: COLL_LOGS, name: "request_id_timestamp", keys: doc! { "request_id": 1, "timestamp": 1 }, unique: false, ttl: None },
+        IndexSpec { collection: COLL_LOGS, name: "message_text", keys: doc! { "message": "text" }, unique: false, ttl: None },
+        // Purge of expired trash entries
+        IndexSpec { collection: COLL_TRASH, name: "purgeAt", keys: doc! { "purgeAt": 1 }, unique: false, ttl: None },
+        // Expiry of idempotency keys, each record carries its own expiry time
+        IndexSpec { collection: COLL_IDEMPOTENCY_KEYS, name: "expiresAt", keys: doc! { "expiresAt": 1 }, unique: false, ttl: Some(Duration::ZERO) },
+        IndexSpec { collection: COLL_API_KEYS, name: "keyHash_unique", keys: doc! { "keyHash": 1 }, unique: true, ttl: None },
+        IndexSpec { collection: COLL_USERS, name: "username_unique", keys: doc! { "username": 1 }, unique: true, ttl: None },
+        // Filters of GET /auditLog
+        IndexSpec { collection: COLL_AUDIT_LOG, name: "timestamp", keys: doc! { "timestamp": 1 }, unique: false, ttl: None },
+        IndexSpec { collection: COLL_AUDIT_LOG, name: "actor_timestamp", keys: doc! { "actor": 1, "timestamp": 1 }, unique: false, ttl: None },
+        IndexSpec { collection: COLL_AUDIT_LOG, name: "targetId_timestamp", keys: doc! { "targetId": 1, "timestamp": 1 }, unique: false, ttl: None },
+        // Lookups of GET /execute/results/{job_id}, and the filters and purge of GET /execute/results
+        IndexSpec { collection: COLL_EXECUTION_RESULTS, name: "executionId_unique", keys: doc! { "executionId": 1 }, unique: true, ttl: None },
+        IndexSpec { collection: COLL_EXECUTION_RESULTS, name: "finishedAt", keys: doc! { "finishedAt": 1 }, unique: false, ttl: None },
+        IndexSpec { collection: COLL_EXECUTION_RESULTS, name: "deploymentId_finishedAt", keys: doc! { "deploymentId": 1, "finishedAt": 1 }, unique: false, ttl: None },
+        // Outputs listed with GET /execute/results/{job_id}
+        IndexSpec { collection: COLL_EXECUTION_OUTPUTS, name: "executionId", keys: doc! { "executionId": 1 }, unique: false, ttl: None },
+        // Lookups of POST /execute/jobs/{job_id}/rerun, and the purge of stored inputs
+        IndexSpec { collection: COLL_EXECUTION_INPUTS, name: "executionId_unique", keys: doc! { "executionId": 1 }, unique: true, ttl: None },
+        IndexSpec { collection: COLL_EXECUTION_INPUTS, name: "storedAt", keys: doc! { "storedAt": 1 }, unique: false, ttl: None },
+    ]
+}
+
+
+/// Creates the indexes, replacing existing indexes on the same keys whose options changed
+/// (for example when SUPERVISOR_LOG_TTL_DAYS is changed). Failures are logged but dont stop
+/// the startup, so that an existing database with duplicates can still be cleaned up via the API.
+pub async fn ensure_indexes(config: &Config) {
+    let mut created = 0;
+    for spec in index_specs(config) {
+        match create_index(config, &spec).await {
+            Ok(()) => created += 1,
+            Err(e) if is_options_conflict(&e) => {
+                warn!("Index '{}' on '{}' exists with different options, recreating it", spec.name, spec.collection);
+                let result = match drop_indexes_on_keys(config, spec.collection, &spec.keys).await {
+                    Ok(()) => create_index(config, &spec).await,
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok(()) => created += 1,
+                    Err(e) => error!("❌ Failed to recreate index '{}' on '{}': {}", spec.name, spec.collection, e),
+                }
+            }
+            Err(e) if is_duplicate_key(&e) => {
+                error!(
+                    "❌ Unique index '{}' on '{}' could not be created because the collection has duplicates, remove them and restart: {}",
+                    spec.name, spec.collection, e
+                );
+            }
+            Err(e) => error!("❌ Failed to create index '{}' on '{}': {}", spec.name, spec.collection, e),
+        }
+    }
+    info!("... Database indexes ensured ({} ok).", created);
+}
+
+
+/// Creates a single index
+async fn create_index(config: &Config, spec: &IndexSpec) -> mongodb::error::Result<()> {
+    let coll = get_collection::<Document>(&config.database, spec.collection).await;
+    let options = IndexOptions::builder()
+        .name(spec.name.to_string())
+        .unique(spec.unique.then_some(true))
+        .expire_after(spec.ttl)
+        .build();
+    let model = IndexModel::builder()
+        .keys(spec.keys.clone())
+        .options(options)
+        .build();
+    coll.create_index(model).await?;
+    Ok(())
+}
+
+
+/// Drops every index of the collection that has exactly the given keys
+async fn drop_indexes_on_keys(config: &Config, collection: &str, keys: &Document) -> mongodb::error::Result<()> {
+    let coll = get_collection::<Document>(&config.database, collection).await;
+    let existing: Vec<IndexModel> = coll.list_indexes().await?.try_collect().await?;
+    for index in existing.into_iter().filter(|i| &i.keys == keys) {
+        if let Some(name) = index.options.and_then(|o| o.name) {
+            coll.drop_index(name).await?;
+        }
+    }
+    Ok(())
+}
+
+
+/// Whether creating an index failed because a conflicting index already exists
+fn is_options_conflict(e: &mongodb::error::Error) -> bool {
+    matches!(
+        e.kind.as_ref(),
+        ErrorKind::Command(c) if c.code == INDEX_OPTIONS_CONFLICT || c.code == INDEX_KEY_SPECS_CONFLICT
+    )
+}
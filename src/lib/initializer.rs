@@ -1,11 +1,15 @@
-use std::{env, fs, io};
+use std::{fs, io};
 use std::path::{Path, PathBuf};
 use log::{error, info, warn};
 use mongodb::{bson::doc, Collection};
 use futures::TryStreamExt;
 use crate::lib::mongodb as db;
+use crate::lib::revisions;
 use crate::structs::logs::SupervisorLog;
-use actix_web::{HttpResponse, Responder};
+use actix_web::{web, HttpResponse, Responder};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 use crate::structs::data_source_cards::DatasourceCard;
 use crate::structs::deployment_certificates::DeploymentCertificate;
@@ -15,14 +19,17 @@ use crate::structs::module_cards::ModuleCard;
 use crate::structs::module::ModuleDoc;
 use crate::structs::node_cards::NodeCard;
 use crate::structs::zones::Zones;
+use crate::lib::config::Config;
 use crate::lib::errors::ApiError;
+use crate::lib::response::normalize_extended_json;
 
 use crate::lib::constants::{ 
     COLL_DATASOURCE_CARDS, COLL_DEPLOYMENT, COLL_DEPLOYMENT_CERTS, COLL_DEVICE, COLL_LOGS, COLL_MODULE, COLL_MODULE_CARDS, COLL_NODE_CARDS, COLL_ZONES, FILE_ROOT_DIR
 };
 
 
-/// This function will save the current orchestrators entire setup into the ./init folder.
+/// This function will save the current orchestrators entire setup into the init folder of the
+/// storage settings (./init by default).
 /// Will export all other database collections except for logs. Will also save the contents of
 /// the ./files folder into ./init/files
 /// 
@@ -30,19 +37,24 @@ use crate::lib::constants::{
 /// it was exported. Note that this doesnt mean it would also initialize supervisors as they
 /// were, so if you want to export an entire orchestrator/supervisor setup, then you need 
 /// to also create a docker compose file to maintain consistent enviroment.
-pub async fn export_orchestrator_setup() -> anyhow::Result<()> {
-    
-    let datasourcecard_collection = db::get_collection::<DatasourceCard>(COLL_DATASOURCE_CARDS).await;
-    let deployment_certificate_collection = db::get_collection::<DeploymentCertificate>(COLL_DEPLOYMENT_CERTS).await;
-    let deployment_collection = db::get_collection::<DeploymentDoc>(COLL_DEPLOYMENT).await;
-    let device_collection = db::get_collection::<DeviceDoc>(COLL_DEVICE).await;
-    let modulecard_collection = db::get_collection::<ModuleCard>(COLL_MODULE_CARDS).await;
-    let module_collection = db::get_collection::<ModuleDoc>(COLL_MODULE).await;
-    let node_cards_collection = db::get_collection::<NodeCard>(COLL_NODE_CARDS).await;
-    let zones_and_risk_levels_collection = db::get_collection::<Zones>(COLL_ZONES).await;
+pub async fn export_orchestrator_setup(config: &Config) -> anyhow::Result<()> {
+    export_orchestrator_setup_into(config, &config.storage.init_dir.to_string_lossy()).await
+}
+
+
+/// Saves the current orchestrator setup into the given folder, see `export_orchestrator_setup`.
+pub async fn export_orchestrator_setup_into(config: &Config, init_folder: &str) -> anyhow::Result<()> {
+
+    let datasourcecard_collection = db::get_collection::<DatasourceCard>(&config.database, COLL_DATASOURCE_CARDS).await;
+    let deployment_certificate_collection = db::get_collection::<DeploymentCertificate>(&config.database, COLL_DEPLOYMENT_CERTS).await;
+    let deployment_collection = db::get_collection::<DeploymentDoc>(&config.database, COLL_DEPLOYMENT).await;
+    let device_collection = db::get_collection::<DeviceDoc>(&config.database, COLL_DEVICE).await;
+    let modulecard_collection = db::get_collection::<ModuleCard>(&config.database, COLL_MODULE_CARDS).await;
+    let module_collection = db::get_collection::<ModuleDoc>(&config.database, COLL_MODULE).await;
+    let node_cards_collection = db::get_collection::<NodeCard>(&config.database, COLL_NODE_CARDS).await;
+    let zones_and_risk_levels_collection = db::get_collection::<Zones>(&config.database, COLL_ZONES).await;
 
     // Recreate init folder to clear it out
-    let init_folder = env::var("WASMIOT_INIT_FOLDER").unwrap_or_else(|_| "./init".to_string());
     delete_folder_contents(&init_folder)?;
     create_folder(&init_folder)?;
 
@@ -174,9 +186,20 @@ pub async fn export_orchestrator_setup() -> anyhow::Result<()> {
 }
 
 
-/// Endpoint for triggering orchestrator setup export
-pub async fn handle_orchestrator_export() -> Result<impl Responder, ApiError> {
-    if let Err(e) = export_orchestrator_setup().await {
+/// Endpoint for triggering orchestrator setup export. With `?snapshot=<name>` the setup is saved
+/// as a named snapshot into the snapshot folder instead of the init folder.
+pub async fn handle_orchestrator_export(
+    config: web::Data<Config>,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<impl Responder, ApiError> {
+    let res = match query.get("snapshot") {
+        Some(name) => {
+            let folder = snapshot_folder(&config, name)?;
+            export_orchestrator_setup_into(&config, &folder.to_string_lossy()).await
+        }
+        None => export_orchestrator_setup(&config).await,
+    };
+    if let Err(e) = res {
         error!("Failed to export orchestrator setup: {}", e);
         return Err(ApiError::internal_error(format!("Failed to export orchestrator setup: {}", e)));
     }
@@ -185,9 +208,152 @@ pub async fn handle_orchestrator_export() -> Result<impl Responder, ApiError> {
 }
 
 
+/// Differences between one collection in the database and in a snapshot
+#[derive(Debug, Default, Serialize)]
+pub struct CollectionDiff {
+    pub added: Vec<String>, // Ids of documents that exist in the database but not in the snapshot
+    pub removed: Vec<String>, // Ids of documents that exist in the snapshot but not in the database
+    pub changed: Vec<ChangedDocument>,
+    pub unchanged: usize,
+}
+
+
+/// A document that exists in both the database and the snapshot, but with different content
+#[derive(Debug, Serialize)]
+pub struct ChangedDocument {
+    pub id: String,
+    pub fields: Vec<String>, // Top level fields that differ
+}
+
+
+/// GET /admin/export/diff?against=<snapshot>
+///
+/// Compares the current database state with a stored snapshot and reports added, removed and
+/// changed documents per collection. `against` is the name of a snapshot created with
+/// `GET /export?snapshot=<name>`, or `init` for the init folder (the default).
+pub async fn handle_snapshot_diff(
+    config: web::Data<Config>,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<impl Responder, ApiError> {
+    let against = query.get("against").map(|s| s.as_str()).unwrap_or("init");
+    let folder = if against == "init" {
+        config.storage.init_dir.clone()
+    } else {
+        snapshot_folder(&config, against)?
+    };
+    if !folder.is_dir() {
+        return Err(ApiError::not_found(format!("no snapshot named '{}'", against)));
+    }
+
+    let mut collections: BTreeMap<&str, CollectionDiff> = BTreeMap::new();
+    let diff = async {
+        collections.insert(COLL_DATASOURCE_CARDS, diff_collection::<DatasourceCard>(&config, &folder, COLL_DATASOURCE_CARDS).await?);
+        collections.insert(COLL_DEPLOYMENT_CERTS, diff_collection::<DeploymentCertificate>(&config, &folder, COLL_DEPLOYMENT_CERTS).await?);
+        collections.insert(COLL_DEPLOYMENT, diff_collection::<DeploymentDoc>(&config, &folder, COLL_DEPLOYMENT).await?);
+        collections.insert(COLL_DEVICE, diff_collection::<DeviceDoc>(&config, &folder, COLL_DEVICE).await?);
+        collections.insert(COLL_MODULE_CARDS, diff_collection::<ModuleCard>(&config, &folder, COLL_MODULE_CARDS).await?);
+        collections.insert(COLL_MODULE, diff_collection::<ModuleDoc>(&config, &folder, COLL_MODULE).await?);
+        collections.insert(COLL_NODE_CARDS, diff_collection::<NodeCard>(&config, &folder, COLL_NODE_CARDS).await?);
+        collections.insert(COLL_ZONES, diff_collection::<Zones>(&config, &folder, COLL_ZONES).await?);
+        anyhow::Ok(())
+    };
+    if let Err(e) = diff.await {
+        error!("Failed to diff orchestrator state against snapshot '{}': {}", against, e);
+        return Err(ApiError::internal_error(format!("Failed to diff against snapshot '{}': {}", against, e)));
+    }
+
+    Ok(HttpResponse::Ok().json(json!({
+        "snapshot": against,
+        "collections": collections,
+    })))
+}
+
+
+/// Helper function that compares one collection in the database with its folder in a snapshot.
+/// Both sides are compared in the form they are exported in (typed documents serialized to JSON).
+async fn diff_collection<T>(config: &Config, snapshot: &Path, coll_name: &str) -> anyhow::Result<CollectionDiff>
+where
+    T: serde::de::DeserializeOwned + serde::Serialize + Unpin + Send + Sync,
+{
+    // Current state of the collection, keyed by id
+    let coll: Collection<T> = db::get_collection(&config.database, coll_name).await;
+    let docs: Vec<T> = coll.find(doc! {}).await?.try_collect().await?;
+    let mut current: HashMap<String, Value> = HashMap::new();
+    for d in &docs {
+        let mut v = serde_json::to_value(d)?;
+        normalize_extended_json(&mut v);
+        if let Some(id) = v.get("_id").and_then(Value::as_str) {
+            current.insert(id.to_string(), v.clone());
+        }
+    }
+
+    // State of the collection in the snapshot, keyed by id
+    let mut stored: HashMap<String, Value> = HashMap::new();
+    let folder = snapshot.join(coll_name);
+    if folder.is_dir() {
+        for entry in fs::read_dir(&folder)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") { continue; }
+            let raw = match fs::read_to_string(&path) {
+                Ok(s) => s,
+                Err(e) => { warn!("Failed to read {:?}: {}", path, e); continue; }
+            };
+            let mut v: Value = match serde_json::from_str(&raw) {
+                Ok(v) => v,
+                Err(e) => { warn!("File {:?} is not valid JSON: {}", path, e); continue; }
+            };
+            normalize_extended_json(&mut v);
+            if let Some(id) = v.get("_id").and_then(Value::as_str) {
+                stored.insert(id.to_string(), v.clone());
+            }
+        }
+    }
+
+    let mut diff = CollectionDiff::default();
+    for (id, cur) in &current {
+        match stored.get(id) {
+            None => diff.added.push(id.clone()),
+            Some(old) if old == cur => diff.unchanged += 1,
+            Some(old) => diff.changed.push(ChangedDocument { id: id.clone(), fields: changed_fields(old, cur) }),
+        }
+    }
+    diff.removed = stored.keys().filter(|id| !current.contains_key(*id)).cloned().collect();
+    diff.added.sort();
+    diff.removed.sort();
+    diff.changed.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(diff)
+}
+
+
+/// Helper function that lists the top level fields that differ between two JSON objects
+fn changed_fields(old: &Value, new: &Value) -> Vec<String> {
+    let empty = serde_json::Map::new();
+    let old = old.as_object().unwrap_or(&empty);
+    let new = new.as_object().unwrap_or(&empty);
+    let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    keys.into_iter()
+        .filter(|k| old.get(*k) != new.get(*k))
+        .cloned()
+        .collect()
+}
+
+
+/// Helper function that resolves the folder of a named snapshot. Names are restricted
+/// so that they cant point outside of the snapshot folder.
+fn snapshot_folder(config: &Config, name: &str) -> Result<PathBuf, ApiError> {
+    let valid = !name.is_empty()
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        && !name.starts_with('.');
+    if !valid {
+        return Err(ApiError::bad_request(format!("invalid snapshot name '{}'", name)));
+    }
+    Ok(config.storage.snapshot_dir.join(name))
+}
+
+
 /// Endpoint for triggering orchestrator setup import
-pub async fn handle_orchestrator_import() -> Result<impl Responder, ApiError> {
-    if let Err(e) = add_initial_data().await {
+pub async fn handle_orchestrator_import(config: web::Data<Config>) -> Result<impl Responder, ApiError> {
+    if let Err(e) = add_initial_data(&config).await {
         error!("Failed to import orchestrator setup from init folder. Error: {:?}", e);
         Err(ApiError::internal_error(format!("Failed to import orchestrator setup from init folder, check logs for details")))
     } else {
@@ -197,13 +363,14 @@ pub async fn handle_orchestrator_import() -> Result<impl Responder, ApiError> {
 }
 
 
-/// This function imports an exported orchestrator setup from ./init/*
+/// This function imports an exported orchestrator setup from the init folder of the storage
+/// settings (./init/* by default)
 /// - Clears existing collections (and logs) from database
 /// - Replaces ./files with ./init/files (if present)
 /// - Imports each saved collection to database
-pub async fn add_initial_data() -> anyhow::Result<()> {
-    let init_folder = env::var("WASMIOT_INIT_FOLDER").unwrap_or_else(|_| "./init".to_string());
-    let init_path = Path::new(&init_folder);
+pub async fn add_initial_data(config: &Config) -> anyhow::Result<()> {
+    let init_path = config.storage.init_dir.as_path();
+    let init_folder = init_path.display();
 
     if !init_path.exists() {
         info!("Init folder '{}' not found. Skipping import.", init_folder);
@@ -225,25 +392,25 @@ pub async fn add_initial_data() -> anyhow::Result<()> {
     }
 
     // 2) Clear collections (including logs)
-    clear_collection::<DatasourceCard>(COLL_DATASOURCE_CARDS).await;
-    clear_collection::<DeploymentCertificate>(COLL_DEPLOYMENT_CERTS).await;
-    clear_collection::<DeploymentDoc>(COLL_DEPLOYMENT).await;
-    clear_collection::<DeviceDoc>(COLL_DEVICE).await;
-    clear_collection::<ModuleCard>(COLL_MODULE_CARDS).await;
-    clear_collection::<ModuleDoc>(COLL_MODULE).await;
-    clear_collection::<NodeCard>(COLL_NODE_CARDS).await;
-    clear_collection::<Zones>(COLL_ZONES).await;
-    clear_collection::<SupervisorLog>(COLL_LOGS).await;
+    clear_collection::<DatasourceCard>(config, COLL_DATASOURCE_CARDS).await;
+    clear_collection::<DeploymentCertificate>(config, COLL_DEPLOYMENT_CERTS).await;
+    clear_collection::<DeploymentDoc>(config, COLL_DEPLOYMENT).await;
+    clear_collection::<DeviceDoc>(config, COLL_DEVICE).await;
+    clear_collection::<ModuleCard>(config, COLL_MODULE_CARDS).await;
+    clear_collection::<ModuleDoc>(config, COLL_MODULE).await;
+    clear_collection::<NodeCard>(config, COLL_NODE_CARDS).await;
+    clear_collection::<Zones>(config, COLL_ZONES).await;
+    clear_collection::<SupervisorLog>(config, COLL_LOGS).await;
 
     // 3) Import each collection from ./init/<collection>/*.json
-    import_folder::<DatasourceCard>(init_path.join(COLL_DATASOURCE_CARDS), COLL_DATASOURCE_CARDS).await?;
-    import_folder::<DeploymentCertificate>(init_path.join(COLL_DEPLOYMENT_CERTS), COLL_DEPLOYMENT_CERTS).await?;
-    import_folder::<DeploymentDoc>(init_path.join(COLL_DEPLOYMENT), COLL_DEPLOYMENT).await?;
-    import_folder::<DeviceDoc>(init_path.join(COLL_DEVICE), COLL_DEVICE).await?;
-    import_folder::<ModuleCard>(init_path.join(COLL_MODULE_CARDS), COLL_MODULE_CARDS).await?;
-    import_folder::<ModuleDoc>(init_path.join(COLL_MODULE), COLL_MODULE).await?;
-    import_folder::<NodeCard>(init_path.join(COLL_NODE_CARDS), COLL_NODE_CARDS).await?;
-    import_folder::<Zones>(init_path.join(COLL_ZONES), COLL_ZONES).await?;
+    import_folder::<DatasourceCard>(config, init_path.join(COLL_DATASOURCE_CARDS), COLL_DATASOURCE_CARDS).await?;
+    import_folder::<DeploymentCertificate>(config, init_path.join(COLL_DEPLOYMENT_CERTS), COLL_DEPLOYMENT_CERTS).await?;
+    import_folder::<DeploymentDoc>(config, init_path.join(COLL_DEPLOYMENT), COLL_DEPLOYMENT).await?;
+    import_folder::<DeviceDoc>(config, init_path.join(COLL_DEVICE), COLL_DEVICE).await?;
+    import_folder::<ModuleCard>(config, init_path.join(COLL_MODULE_CARDS), COLL_MODULE_CARDS).await?;
+    import_folder::<ModuleDoc>(config, init_path.join(COLL_MODULE), COLL_MODULE).await?;
+    import_folder::<NodeCard>(config, init_path.join(COLL_NODE_CARDS), COLL_NODE_CARDS).await?;
+    import_folder::<Zones>(config, init_path.join(COLL_ZONES), COLL_ZONES).await?;
 
     info!("Import completed.");
     Ok(())
@@ -251,13 +418,14 @@ pub async fn add_initial_data() -> anyhow::Result<()> {
 
 
 /// Deletes *all* docs from a collection. 
-async fn clear_collection<T: serde::de::DeserializeOwned + Unpin + Send + Sync>(name: &str) {
-    let coll: Collection<T> = db::get_collection(name).await;
+async fn clear_collection<T: serde::de::DeserializeOwned + Unpin + Send + Sync>(config: &Config, name: &str) {
+    let coll: Collection<T> = db::get_collection(&config.database, name).await;
     if let Err(e) = coll.delete_many(doc!{}).await {
         error!("Failed to clear collection '{}': {}", name, e);
     } else {
         info!("Cleared collection '{}'", name);
     }
+    revisions::bump(name);
 }
 
 
@@ -265,11 +433,11 @@ async fn clear_collection<T: serde::de::DeserializeOwned + Unpin + Send + Sync>(
 /// - Skips hidden files and non-JSON
 /// - Skips files that fail to parse as the target struct
 /// - Requires `_id` to be present in the JSON
-async fn import_folder<T>(folder: PathBuf, coll_name: &str) -> anyhow::Result<()>
+async fn import_folder<T>(config: &Config, folder: PathBuf, coll_name: &str) -> anyhow::Result<()>
 where
     T: serde::de::DeserializeOwned + serde::Serialize + Unpin + Send + Sync + std::fmt::Debug,
 {
-    let coll: Collection<T> = db::get_collection(coll_name).await;
+    let coll: Collection<T> = db::get_collection(&config.database, coll_name).await;
 
     if !folder.exists() {
         info!("No '{}' folder in snapshot. Skipping.", coll_name);
@@ -324,6 +492,7 @@ where
         }
     }
 
+    revisions::bump(coll_name);
     info!("Imported {} '{}' docs (skipped {}).", ok_count, coll_name, skip_count);
     Ok(())
 }
@@ -0,0 +1,190 @@
+//! # inventory.rs
+//!
+//! Static device inventory, for networks where multicast and so mDNS discovery isnt available.
+//! The supervisors listed in the inventory file of the discovery settings (YAML or JSON, by its extension) are
+//! registered as if discovery had found them: the orchestrator registers itself to them, and
+//! their descriptions and first health checks are fetched. Like discovery scans, the inventory
+//! is gone through again every scan interval of the discovery settings, which completes the
+//! devices that couldnt be reached before.
+//!
+//! The file is checked for changes every `inventoryPollS` seconds and reloaded when it
+//! has been modified. A file that cant be read or is invalid is reported and the previously
+//! loaded inventory is kept. Devices removed from the file stay registered until they are
+//! deleted through the API.
+//!
+//! Example `devices.yaml`:
+//! ```yaml
+//! devices:
+//!   - name: raspi-1
+//!     addresses: ["192.168.1.20"]
+//!     port: 5000
+//!   - name: raspi-2
+//!     host: raspi-2.lan
+//!     scheme: https
+//! ```
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use chrono::Utc;
+use futures::stream::{self, StreamExt};
+use log::{error, info, warn};
+use serde::Deserialize;
+use crate::api::device::bootstrap_inventory_device;
+use crate::lib::config::Config;
+use crate::lib::utils::{base_path, default_device_description};
+use crate::lib::watchdog;
+use crate::structs::device::{DeviceCommunication, DeviceDoc, StatusEnum, StatusLogEntry};
+
+
+/// Contents of the inventory file
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Inventory {
+    #[serde(default)]
+    pub devices: Vec<InventoryDevice>,
+}
+
+/// A supervisor listed in the inventory. Reached at `addresses`, or at `host` if there are none,
+/// with the default device port and scheme of the discovery settings unless given.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InventoryDevice {
+    pub name: String,
+    #[serde(default)]
+    pub addresses: Vec<String>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub scheme: Option<String>,
+    pub path: Option<String>, // Base path of the supervisor, as in the `path` TXT property
+}
+
+
+/// Loads and checks the inventory file. The error lists every problem found.
+pub fn load_inventory(path: &Path) -> Result<Inventory, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("cant read the device inventory {}: {}", path.display(), e))?;
+    let extension = path.extension().and_then(|e| e.to_str()).map(str::to_lowercase);
+    let parsed: Result<Inventory, String> = match extension.as_deref() {
+        Some("yaml") | Some("yml") => serde_yaml::from_str(&contents).map_err(|e| e.to_string()),
+        Some("json") => serde_json::from_str(&contents).map_err(|e| e.to_string()),
+        _ => Err("unknown format, expected a .yaml, .yml or .json file".to_string()),
+    };
+    let inventory = parsed.map_err(|e| format!("invalid device inventory {}: {}", path.display(), e))?;
+
+    let mut errors = Vec::new();
+    let mut names = HashSet::new();
+    for (i, device) in inventory.devices.iter().enumerate() {
+        if device.name.trim().is_empty() {
+            errors.push(format!("device {} has no name", i + 1));
+        } else if !names.insert(device.name.as_str()) {
+            errors.push(format!("device '{}' is listed more than once", device.name));
+        }
+        if device.addresses.is_empty() && device.host.as_deref().is_none_or(|h| h.trim().is_empty()) {
+            errors.push(format!("device '{}' has neither addresses nor a host", device.name));
+        }
+        if let Some(scheme) = &device.scheme {
+            if !matches!(scheme.to_lowercase().as_str(), "http" | "https") {
+                errors.push(format!("device '{}' has an unsupported scheme '{}', expected http or https", device.name, scheme));
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return Err(format!("invalid device inventory {}: {}", path.display(), errors.join(", ")));
+    }
+    Ok(inventory)
+}
+
+
+impl InventoryDevice {
+    /// The device as discovery would save it
+    fn to_device_doc(&self, config: &Config) -> DeviceDoc {
+        let addresses = if self.addresses.is_empty() {
+            self.host.iter().map(|h| h.trim().to_string()).collect()
+        } else {
+            self.addresses.clone()
+        };
+        let scheme = self
+            .scheme
+            .as_ref()
+            .map(|s| s.to_lowercase())
+            .unwrap_or_else(|| config.discovery.default_device_scheme.clone());
+        DeviceDoc {
+            id: None,
+            name: self.name.clone(),
+            communication: DeviceCommunication {
+                addresses,
+                port: self.port.unwrap_or(config.discovery.default_device_port),
+                scheme,
+                path: self.path.as_deref().and_then(base_path),
+            },
+            description: default_device_description(),
+            status: StatusEnum::Active,
+            ok_health_check_count: 0,
+            failed_health_check_count: 0,
+            status_log: Some(vec![StatusLogEntry {
+                status: StatusEnum::Active,
+                time: Utc::now(),
+            }]),
+            health: None,
+            latency: None,
+            deployment_latency: None,
+            drain: None,
+            circuit_breaker: None,
+            disappeared_at: None,
+            discovered_by: None,
+            properties: None,
+            health_pushed_at: None,
+        }
+    }
+}
+
+
+/// Registers the devices of the inventory, as many at a time as there are concurrent health checks
+async fn bootstrap_inventory(inventory: &Inventory, config: &Arc<Config>) {
+    stream::iter(inventory.devices.iter().map(|d| d.to_device_doc(config)))
+        .for_each_concurrent(config.health_checks.concurrency, |device| bootstrap_inventory_device(device, config.clone()))
+        .await;
+}
+
+
+/// Endless loop reloading the inventory file when it changes and registering its devices every
+/// scan interval of the discovery settings
+pub async fn run_inventory_loop(config: Arc<Config>) {
+    let path = config.discovery.inventory_file.as_path();
+    let bootstrap_interval = Duration::from_secs(config.discovery.scan_interval_s);
+    let mut loaded_version: Option<SystemTime> = None;
+    let mut inventory = Inventory::default();
+    let mut last_bootstrap: Option<Instant> = None;
+
+    loop {
+        let version = fs::metadata(path).and_then(|m| m.modified()).ok();
+        if version != loaded_version {
+            loaded_version = version;
+            if version.is_none() {
+                if !inventory.devices.is_empty() {
+                    warn!("Device inventory {} was removed, its devices stay registered", path.display());
+                }
+                inventory = Inventory::default();
+            } else {
+                match load_inventory(path) {
+                    Ok(loaded) => {
+                        info!("📒 Loaded {} devices from the device inventory {}", loaded.devices.len(), path.display());
+                        inventory = loaded;
+                        last_bootstrap = None;
+                    }
+                    Err(e) => error!("❌ {}, keeping the previous inventory", e),
+                }
+            }
+        }
+
+        if !inventory.devices.is_empty() && last_bootstrap.is_none_or(|t| t.elapsed() >= bootstrap_interval) {
+            bootstrap_inventory(&inventory, &config).await;
+            last_bootstrap = Some(Instant::now());
+        }
+        watchdog::heartbeat(watchdog::LOOP_INVENTORY);
+        tokio::time::sleep(Duration::from_secs(config.discovery.inventory_poll_s)).await;
+    }
+}
@@ -0,0 +1,274 @@
+//! # log_escalation.rs
+//!
+//! Temporarily raises the log level of supervisors when something goes wrong,
+//! so that diagnostics are captured exactly when incidents happen.
+//!
+//! Escalation is triggered by events on the event bus (see lib/events.rs), when a device
+//! transitions to inactive, or when a deployment fails to deploy or execute. The involved
+//! supervisors are asked to switch to a verbose log level through their log level endpoint, and after a bounded period the level
+//! that was active before the escalation is restored.
+//!
+//! The endpoint is at logs.supervisorLogLevelPath (SUPERVISOR_LOG_LEVEL_PATH) on the supervisor. A GET on it must
+//! answer `{"level": "<level>"}` with the current level, and a PUT with the body `{"level": "<level>"}` must set the
+//! level and answer with any 2xx status. Supervisors without the endpoint fail these requests, which is logged and
+//! otherwise ignored.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+use mongodb::bson::{doc, oid::ObjectId};
+use futures::TryStreamExt;
+use log::{debug, info, warn};
+use crate::lib::config::Config;
+use crate::lib::constants::{COLL_DEPLOYMENT, COLL_DEVICE};
+use crate::lib::events::{self, Event};
+use crate::lib::circuit_breaker;
+use crate::lib::http_client::{self, Operation};
+use crate::lib::mongodb::get_collection;
+use crate::lib::telemetry::send_traced;
+use crate::structs::deployment::DeploymentDoc;
+use crate::structs::device::DeviceDoc;
+
+
+/// Log level that is restored if the supervisor didnt report its level before escalation
+const FALLBACK_LOG_LEVEL: &str = "info";
+
+
+/// State of a single escalated device
+#[derive(Debug, Clone)]
+struct Escalation {
+    previous_level: String,
+    until: Instant,
+}
+
+
+/// Currently escalated devices, keyed by device name
+static ESCALATIONS: Lazy<Mutex<HashMap<String, Escalation>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+
+/// Reads the current log level of a supervisor.
+pub async fn get_device_log_level(config: &Config, device: &DeviceDoc) -> Result<String, String> {
+    let base_url = device
+        .communication
+        .base_url()
+        .ok_or_else(|| format!("device '{}' has no address", device.name))?;
+    let request = http_client::client(&config.http_client)
+        .get(format!("{}{}", base_url, config.logs.supervisor_log_level_path))
+        .timeout(http_client::timeout(&config.http_client, Operation::LogLevel));
+    circuit_breaker::check(config, device)?;
+    let result = send_traced(request, "get log level").await;
+    circuit_breaker::record(config, device, &result);
+    let res = result
+        .map_err(|e| format!("request error to device '{}': {e}", device.name))?;
+    if !res.status().is_success() {
+        return Err(format!("HTTP {} from device '{}'", res.status().as_u16(), device.name));
+    }
+    let body: Value = res
+        .json()
+        .await
+        .map_err(|e| format!("invalid log level JSON from device '{}': {e}", device.name))?;
+    body.get("level")
+        .and_then(Value::as_str)
+        .map(|s| s.to_string())
+        .ok_or_else(|| format!("device '{}' did not report a log level", device.name))
+}
+
+
+/// Sets the log level of a supervisor.
+pub async fn set_device_log_level(config: &Config, device: &DeviceDoc, level: &str) -> Result<(), String> {
+    let base_url = device
+        .communication
+        .base_url()
+        .ok_or_else(|| format!("device '{}' has no address", device.name))?;
+    let request = http_client::client(&config.http_client)
+        .put(format!("{}{}", base_url, config.logs.supervisor_log_level_path))
+        .json(&json!({ "level": level }))
+        .timeout(http_client::timeout(&config.http_client, Operation::LogLevel));
+    circuit_breaker::check(config, device)?;
+    let result = send_traced(request, "set log level").await;
+    circuit_breaker::record(config, device, &result);
+    let res = result
+        .map_err(|e| format!("request error to device '{}': {e}", device.name))?;
+    if !res.status().is_success() {
+        return Err(format!("HTTP {} from device '{}'", res.status().as_u16(), device.name));
+    }
+    Ok(())
+}
+
+
+/// Raises the log level of a single device for the configured period. If the device
+/// is already escalated, the period is extended instead.
+pub async fn escalate_device(config: &Arc<Config>, device: DeviceDoc, reason: &str) {
+    let duration = Duration::from_secs(config.logs.escalation_duration_s);
+    if duration.is_zero() {
+        return;
+    }
+
+    // Extend an ongoing escalation instead of starting a new one
+    {
+        let mut escalations = ESCALATIONS.lock();
+        if let Some(e) = escalations.get_mut(&device.name) {
+            e.until = Instant::now() + duration;
+            debug!("Extended log level escalation of device '{}' ({})", device.name, reason);
+            return;
+        }
+    }
+
+    let previous_level = match get_device_log_level(config, &device).await {
+        Ok(level) => level,
+        Err(e) => {
+            debug!("Could not read log level of device '{}', restoring to '{}' later: {}", device.name, FALLBACK_LOG_LEVEL, e);
+            FALLBACK_LOG_LEVEL.to_string()
+        }
+    };
+    if let Err(e) = set_device_log_level(config, &device, &config.logs.escalation_level).await {
+        warn!("❗️ Failed to escalate log level of device '{}': {}", device.name, e);
+        return;
+    }
+
+    {
+        let mut escalations = ESCALATIONS.lock();
+        if escalations.contains_key(&device.name) {
+            // Another escalation started while this one was waiting for the device, let that one restore the level
+            return;
+        }
+        escalations.insert(device.name.clone(), Escalation {
+            previous_level,
+            until: Instant::now() + duration,
+        });
+    }
+    info!("🔎 Escalated log level of device '{}' to '{}' for {}s ({})", device.name, config.logs.escalation_level, duration.as_secs(), reason);
+
+    // Restore the previous level once the (possibly extended) escalation period has passed
+    let config = config.clone();
+    tokio::spawn(async move {
+        loop {
+            let until = match ESCALATIONS.lock().get(&device.name) {
+                Some(e) => e.until,
+                None => return,
+            };
+            tokio::time::sleep_until(until.into()).await;
+
+            let restore_to = {
+                let mut escalations = ESCALATIONS.lock();
+                match escalations.get(&device.name) {
+                    Some(e) if e.until <= Instant::now() => escalations.remove(&device.name).map(|e| e.previous_level),
+                    Some(_) => None,
+                    None => return,
+                }
+            };
+            if let Some(level) = restore_to {
+                match set_device_log_level(&config, &device, &level).await {
+                    Ok(()) => info!("🔎 Restored log level of device '{}' to '{}'", device.name, level),
+                    Err(e) => warn!("❗️ Failed to restore log level of device '{}': {}", device.name, e),
+                }
+                return;
+            }
+        }
+    });
+}
+
+
+/// Escalates on the events published on the event bus: devices becoming inactive, deployments
+/// failing to deploy, and executions failing on the side of the supervisors.
+pub fn listen_to_events(config: Arc<Config>) {
+    events::listen("Log escalation", move |event| {
+        let config = config.clone();
+        async move {
+            // Escalation waits for the supervisors, so it is done aside from the event listener
+            match event {
+                Event::DeviceInactive { device_id: Some(device_id), .. } => {
+                    let Ok(id) = ObjectId::parse_str(&device_id) else { return };
+                    tokio::spawn(async move {
+                        if let Some(device) = find_devices(&config, &[id]).await.into_iter().next() {
+                            escalate_for_inactive_device(&config, &device).await;
+                        }
+                    });
+                }
+                Event::DeploymentFailed { deployment_id: Some(deployment_id), deployment_name, .. } => {
+                    tokio::spawn(async move {
+                        let reason = format!("deployment '{}' failed to deploy", deployment_name);
+                        escalate_for_deployment_id(&config, &deployment_id, &reason).await;
+                    });
+                }
+                Event::ExecutionFinished { supervisor_error: true, deployment_id, deployment_name, .. } => {
+                    tokio::spawn(async move {
+                        let reason = format!("execution of deployment '{}' failed", deployment_name);
+                        escalate_for_deployment_id(&config, &deployment_id, &reason).await;
+                    });
+                }
+                _ => {}
+            }
+        }
+    });
+}
+
+
+/// Escalates the log level of all devices that take part in the deployment with the given id.
+async fn escalate_for_deployment_id(config: &Arc<Config>, deployment_id: &str, reason: &str) {
+    let Ok(id) = ObjectId::parse_str(deployment_id) else { return };
+    let coll = get_collection::<DeploymentDoc>(&config.database, COLL_DEPLOYMENT).await;
+    match coll.find_one(doc! { "_id": id }).await {
+        Ok(Some(deployment)) => escalate_for_deployment(config, &deployment, reason).await,
+        Ok(None) => {}
+        Err(e) => warn!("Failed to find deployment '{}' for log level escalation: {}", deployment_id, e),
+    }
+}
+
+
+/// Escalates the log level of all devices that take part in the given deployment.
+pub async fn escalate_for_deployment(config: &Arc<Config>, deployment: &DeploymentDoc, reason: &str) {
+    let device_ids: Vec<ObjectId> = deployment
+        .full_manifest
+        .keys()
+        .filter_map(|k| ObjectId::parse_str(k).ok())
+        .collect();
+    for device in find_devices(config, &device_ids).await {
+        escalate_device(config, device, reason).await;
+    }
+}
+
+
+/// Escalates the log level of a device that became inactive, as well as all other devices
+/// that share an active deployment with it.
+pub async fn escalate_for_inactive_device(config: &Arc<Config>, device: &DeviceDoc) {
+    let reason = format!("device '{}' became inactive", device.name);
+    let mut device_ids: HashSet<ObjectId> = HashSet::new();
+    if let Some(id) = device.id {
+        device_ids.insert(id);
+        let coll = get_collection::<DeploymentDoc>(&config.database, COLL_DEPLOYMENT).await;
+        let filter = doc! { "active": true, format!("fullManifest.{}", id.to_hex()): { "$exists": true } };
+        match coll.find(filter).await {
+            Ok(cursor) => {
+                let deployments: Vec<DeploymentDoc> = cursor.try_collect().await.unwrap_or_default();
+                for d in deployments {
+                    device_ids.extend(d.full_manifest.keys().filter_map(|k| ObjectId::parse_str(k).ok()));
+                }
+            }
+            Err(e) => warn!("Failed to find deployments of inactive device '{}': {}", device.name, e),
+        }
+    }
+    let device_ids: Vec<ObjectId> = device_ids.into_iter().collect();
+    for d in find_devices(config, &device_ids).await {
+        escalate_device(config, d, &reason).await;
+    }
+}
+
+
+/// Helper function that fetches the device documents matching the given ids
+async fn find_devices(config: &Config, ids: &[ObjectId]) -> Vec<DeviceDoc> {
+    if ids.is_empty() {
+        return Vec::new();
+    }
+    let coll = get_collection::<DeviceDoc>(&config.database, COLL_DEVICE).await;
+    match coll.find(doc! { "_id": { "$in": ids } }).await {
+        Ok(cursor) => cursor.try_collect().await.unwrap_or_default(),
+        Err(e) => {
+            warn!("Failed to fetch devices for log level escalation: {}", e);
+            Vec::new()
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! # log_forwarding.rs
+//!
+//! Forwards the orchestrators own log records into the same pipeline as supervisor logs:
+//! records at the forward level of the log settings or above are saved into COLL_LOGS with the device name
+//! "orchestrator", and broadcast to the log websocket clients. Records are still written by
+//! env_logger as before. Forwarding happens in the background through a bounded buffer, and
+//! records are dropped while the buffer is full, so a burst of logs cant stall the orchestrator.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use chrono::Utc;
+use log::{LevelFilter, Log, Metadata, Record};
+use once_cell::sync::OnceCell;
+use tokio::sync::mpsc;
+use crate::api::ws_logs;
+use crate::lib::config::{Config, DatabaseConfig, LogConfig};
+use crate::lib::constants::COLL_LOGS;
+use crate::lib::mongodb::get_collection;
+use crate::lib::request_id;
+use crate::lib::response::to_normalized_value;
+use crate::lib::zeroconf;
+use crate::structs::logs::SupervisorLog;
+
+
+/// Device name of the forwarded logs
+pub const ORCHESTRATOR_LOG_DEVICE: &str = "orchestrator";
+
+/// Records waiting to be saved before new ones are dropped
+const BUFFER_SIZE: usize = 1024;
+
+/// Most records saved with a single insert
+const MAX_BATCH: usize = 100;
+
+static SENDER: OnceCell<mpsc::Sender<SupervisorLog>> = OnceCell::new();
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+
+struct ForwardingLogger {
+    inner: env_logger::Logger,
+    forward_level: LevelFilter,
+}
+
+impl Log for ForwardingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata) || metadata.level() <= self.forward_level
+    }
+
+    fn log(&self, record: &Record) {
+        if self.inner.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+        if record.level() <= self.forward_level && is_forwarded(record.target()) {
+            forward(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+
+/// Installs env_logger (configured in `builder`) as the logger, with the records at
+/// the forward level of `config` or above forwarded once `start_log_forwarding` has been called
+pub fn init_logging(mut builder: env_logger::Builder, config: &LogConfig) {
+    let inner = builder.build();
+    let forward_level = config.forward_level();
+    log::set_max_level(inner.filter().max(forward_level));
+    let logger = ForwardingLogger { inner, forward_level };
+    if let Err(e) = log::set_boxed_logger(Box::new(logger)) {
+        eprintln!("Failed to set the logger: {}", e);
+    }
+}
+
+
+/// Starts saving and broadcasting the forwarded records, needs a tokio runtime. The records
+/// are saved with the public host of the server settings as their device address.
+pub fn start_log_forwarding(config: &Config) {
+    if config.logs.forward_level() == LevelFilter::Off {
+        return;
+    }
+    let (tx, rx) = mpsc::channel(BUFFER_SIZE);
+    if SENDER.set(tx).is_ok() {
+        tokio::spawn(save_forwarded_logs(config.database.clone(), rx, zeroconf::public_host(&config.server)));
+    }
+}
+
+
+/// Only the orchestrators own records are forwarded, and not the ones about forwarding (which
+/// would feed themselves)
+fn is_forwarded(target: &str) -> bool {
+    target.starts_with("orchestrator") && !target.starts_with(module_path!())
+}
+
+
+fn forward(record: &Record) {
+    let Some(sender) = SENDER.get() else { return };
+    let now = Utc::now();
+    let entry = SupervisorLog {
+        id: None,
+        device_ip: String::new(), // Filled in when saved, as looking it up may log
+        device_name: ORCHESTRATOR_LOG_DEVICE.to_string(),
+        func_name: record.target().to_string(),
+        log_level: record.level().to_string(),
+        message: record.args().to_string(),
+        request_id: request_id::current(),
+        deployment_id: None,
+        module_name: None,
+        timestamp: now,
+        date_received: now,
+    };
+    if sender.try_send(entry).is_err() {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+
+async fn save_forwarded_logs(database: DatabaseConfig, mut rx: mpsc::Receiver<SupervisorLog>, device_ip: String) {
+    let collection = get_collection::<SupervisorLog>(&database, COLL_LOGS).await;
+    let mut batch = Vec::with_capacity(MAX_BATCH);
+    while rx.recv_many(&mut batch, MAX_BATCH).await > 0 {
+        for entry in batch.iter_mut() {
+            entry.device_ip = device_ip.clone();
+            if let Ok(json) = to_normalized_value(&*entry) {
+                ws_logs::broadcast(json.to_string());
+            }
+        }
+        if let Err(e) = collection.insert_many(batch.drain(..)).await {
+            // Written to stderr, logging it would be forwarded again
+            eprintln!("Failed to save forwarded orchestrator logs: {}", e);
+        }
+        let dropped = DROPPED.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            eprintln!("{} orchestrator log records were not forwarded, the buffer was full", dropped);
+        }
+    }
+}
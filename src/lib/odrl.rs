@@ -0,0 +1,271 @@
+//! # odrl.rs
+//!
+//! Schema validation of the ODRL documents that node, module and data source cards are
+//! created from. Every missing or invalid field is collected, so that policy authors get
+//! the full list of problems in a single response instead of silently defaulted values.
+
+use std::collections::HashMap;
+use actix_web::HttpResponse;
+use mongodb::bson::{oid::ObjectId, Document};
+use serde::Serialize;
+use serde_json::{json, Value};
+use crate::lib::config::Config;
+use crate::lib::errors::ApiError;
+use crate::lib::mongodb::find_one;
+
+
+/// A single problem found in an ODRL document
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: String, // Path to the field, for example "asset[0].title"
+    pub message: String,
+}
+
+
+/// Fields of a node card document
+#[derive(Debug, Clone)]
+pub struct NodeCardFields {
+    pub name: String,
+    pub nodeid: String,
+    pub zone: String,
+}
+
+
+/// Fields of a module card document
+#[derive(Debug, Clone)]
+pub struct ModuleCardFields {
+    pub moduleid: ObjectId,
+    pub name: String,
+    pub risk_level: String,
+    pub input_type: String,
+    pub output_risk: String,
+}
+
+
+/// Fields of a data source card document
+#[derive(Debug, Clone)]
+pub struct DatasourceCardFields {
+    pub name: String,
+    pub ds_type: String,
+    pub risk_level: String,
+    pub nodeid: ObjectId,
+}
+
+
+/// Whether the request asked only for validation, without saving anything (`?dryRun=true`)
+pub fn is_dry_run(query: &HashMap<String, String>) -> bool {
+    query.get("dryRun").map(|v| v == "true").unwrap_or(false)
+}
+
+
+/// Whether the request allows cards that refer to devices or modules that dont exist yet
+/// (`?allowUnregistered=true`), for creating cards before the device or module is registered
+pub fn allow_unregistered(query: &HashMap<String, String>) -> bool {
+    query.get("allowUnregistered").map(|v| v == "true").unwrap_or(false)
+}
+
+
+/// Returns an error for the given field if no document in the collection matches the filter
+pub async fn check_reference(config: &Config, collection: &str, filter: Document, field: &str, what: &str) -> Result<Option<FieldError>, ApiError> {
+    let exists = find_one::<Document>(&config.database, collection, filter)
+        .await
+        .map_err(ApiError::db)?
+        .is_some();
+    Ok((!exists).then(|| error(field, format!("no {} exists with this id (use ?allowUnregistered=true to create the card anyway)", what))))
+}
+
+
+/// Builds the 400 response listing every problem found in a document
+pub fn invalid_document_response(kind: &str, errors: &[FieldError]) -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({
+        "error": format!("bad request: invalid ODRL document for a {}", kind),
+        "errors": errors,
+    }))
+}
+
+
+/// Validates a node card document: `asset[0]` with `title`, `uid` and a `memberOf` relation (the zone)
+pub fn parse_node_card(doc: &Value) -> Result<NodeCardFields, Vec<FieldError>> {
+    let mut errors = Vec::new();
+    let Some(asset) = first_item(doc, "asset", &mut errors) else {
+        return Err(errors);
+    };
+    let name = required_str(asset, "title", "asset[0].title", &mut errors);
+    let nodeid = required_str(asset, "uid", "asset[0].uid", &mut errors);
+    let relations = relations(asset, &mut errors);
+    let zone = relation_value(&relations, "memberOf", &mut errors);
+
+    match (name, nodeid, zone) {
+        (Some(name), Some(nodeid), Some(zone)) if errors.is_empty() => Ok(NodeCardFields { name, nodeid, zone }),
+        _ => Err(errors),
+    }
+}
+
+
+/// Validates a module card document: `permission[0]` with `target` (module id), `action` and
+/// `constraint`s for `risk-level`, `input-type` and `output-risk`
+pub fn parse_module_card(doc: &Value) -> Result<ModuleCardFields, Vec<FieldError>> {
+    let mut errors = Vec::new();
+    let Some(perm) = first_item(doc, "permission", &mut errors) else {
+        return Err(errors);
+    };
+    let moduleid = required_str(perm, "target", "permission[0].target", &mut errors)
+        .and_then(|t| object_id(&t, "permission[0].target", &mut errors));
+    let name = required_str(perm, "action", "permission[0].action", &mut errors);
+
+    let mut constraints: HashMap<String, String> = HashMap::new();
+    match perm.get("constraint").and_then(Value::as_array) {
+        Some(arr) => {
+            for (i, c) in arr.iter().enumerate() {
+                let path = format!("permission[0].constraint[{}]", i);
+                let left = required_str(c, "leftOperand", &format!("{path}.leftOperand"), &mut errors);
+                let right = required_str(c, "rightOperand", &format!("{path}.rightOperand"), &mut errors);
+                if let Some(op) = c.get("operator").and_then(Value::as_str) {
+                    if op != "eq" {
+                        errors.push(error(&format!("{path}.operator"), format!("unsupported operator '{}', only 'eq' is supported", op)));
+                    }
+                }
+                if let (Some(l), Some(r)) = (left, right) {
+                    constraints.insert(l, r);
+                }
+            }
+        }
+        None => errors.push(error("permission[0].constraint", "missing or not an array")),
+    }
+    let mut constraint = |key: &str| -> Option<String> {
+        let value = constraints.get(key).cloned();
+        if value.is_none() {
+            errors.push(error(&format!("permission[0].constraint[leftOperand={}]", key), "missing constraint"));
+        }
+        value
+    };
+    let risk_level = constraint("risk-level");
+    let input_type = constraint("input-type");
+    let output_risk = constraint("output-risk");
+
+    match (moduleid, name, risk_level, input_type, output_risk) {
+        (Some(moduleid), Some(name), Some(risk_level), Some(input_type), Some(output_risk)) if errors.is_empty() => {
+            Ok(ModuleCardFields { moduleid, name, risk_level, input_type, output_risk })
+        }
+        _ => Err(errors),
+    }
+}
+
+
+/// Validates a data source card document: `asset[0]` with `title` and `type`, `risk-level`
+/// and `nodeid` (device id) relations
+pub fn parse_data_source_card(doc: &Value) -> Result<DatasourceCardFields, Vec<FieldError>> {
+    let mut errors = Vec::new();
+    let Some(asset) = first_item(doc, "asset", &mut errors) else {
+        return Err(errors);
+    };
+    let name = required_str(asset, "title", "asset[0].title", &mut errors);
+    let relations = relations(asset, &mut errors);
+    let ds_type = relation_value(&relations, "type", &mut errors);
+    let risk_level = relation_value(&relations, "risk-level", &mut errors);
+    let nodeid = relation_value(&relations, "nodeid", &mut errors)
+        .and_then(|n| object_id(&n, "asset[0].relation[type=nodeid].value", &mut errors));
+
+    match (name, ds_type, risk_level, nodeid) {
+        (Some(name), Some(ds_type), Some(risk_level), Some(nodeid)) if errors.is_empty() => {
+            Ok(DatasourceCardFields { name, ds_type, risk_level, nodeid })
+        }
+        _ => Err(errors),
+    }
+}
+
+
+fn error(field: &str, message: impl Into<String>) -> FieldError {
+    FieldError { field: field.to_string(), message: message.into() }
+}
+
+
+/// Returns the first item of the array under `key`
+fn first_item<'a>(doc: &'a Value, key: &str, errors: &mut Vec<FieldError>) -> Option<&'a Value> {
+    match doc.get(key).and_then(Value::as_array) {
+        Some(arr) => match arr.first() {
+            Some(item) if item.is_object() => Some(item),
+            Some(_) => {
+                errors.push(error(&format!("{key}[0]"), "not an object"));
+                None
+            }
+            None => {
+                errors.push(error(key, "empty array"));
+                None
+            }
+        },
+        None => {
+            errors.push(error(key, "missing or not an array"));
+            None
+        }
+    }
+}
+
+
+/// Returns the non-empty string under `key`
+fn required_str(obj: &Value, key: &str, path: &str, errors: &mut Vec<FieldError>) -> Option<String> {
+    match obj.get(key) {
+        Some(Value::String(s)) if !s.trim().is_empty() => Some(s.clone()),
+        Some(Value::String(_)) => {
+            errors.push(error(path, "empty string"));
+            None
+        }
+        Some(_) => {
+            errors.push(error(path, "not a string"));
+            None
+        }
+        None => {
+            errors.push(error(path, "missing"));
+            None
+        }
+    }
+}
+
+
+fn object_id(value: &str, path: &str, errors: &mut Vec<FieldError>) -> Option<ObjectId> {
+    match ObjectId::parse_str(value) {
+        Ok(oid) => Some(oid),
+        Err(_) => {
+            errors.push(error(path, format!("'{}' is not a valid ObjectId hex string", value)));
+            None
+        }
+    }
+}
+
+
+/// Returns the relations of an asset as (type, value) pairs
+fn relations(asset: &Value, errors: &mut Vec<FieldError>) -> Vec<(String, Value)> {
+    let Some(arr) = asset.get("relation").and_then(Value::as_array) else {
+        errors.push(error("asset[0].relation", "missing or not an array"));
+        return Vec::new();
+    };
+    let mut out = Vec::new();
+    for (i, r) in arr.iter().enumerate() {
+        match r.get("type").and_then(Value::as_str) {
+            Some(t) => out.push((t.to_string(), r.get("value").cloned().unwrap_or(Value::Null))),
+            None => errors.push(error(&format!("asset[0].relation[{}].type", i), "missing or not a string")),
+        }
+    }
+    out
+}
+
+
+/// Returns the string value of the relation with the given type
+fn relation_value(relations: &[(String, Value)], rel_type: &str, errors: &mut Vec<FieldError>) -> Option<String> {
+    let path = format!("asset[0].relation[type={}].value", rel_type);
+    match relations.iter().find(|(t, _)| t == rel_type) {
+        Some((_, Value::String(s))) if !s.trim().is_empty() => Some(s.clone()),
+        Some((_, Value::Null)) => {
+            errors.push(error(&path, "missing"));
+            None
+        }
+        Some(_) => {
+            errors.push(error(&path, "not a non-empty string"));
+            None
+        }
+        None => {
+            errors.push(error(&format!("asset[0].relation[type={}]", rel_type), "missing relation"));
+            None
+        }
+    }
+}
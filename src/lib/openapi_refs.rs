@@ -0,0 +1,204 @@
+//! # openapi_refs.rs
+//!
+//! Resolves `$ref`s in module descriptions against the components of the description, so that
+//! descriptions written elsewhere can be deployed without inlining them by hand. Only local
+//! references to components (`#/components/{schemas,responses,parameters,requestBodies}/{name}`)
+//! are supported. References may point to other references, and schemas are resolved all the way
+//! down through their properties. A reference that leads back to itself is an error.
+
+use std::collections::HashMap;
+use crate::structs::openapi::{
+    OpenApiComponents,
+    OpenApiDocument,
+    OpenApiMediaTypeObject,
+    OpenApiParameterEnum,
+    OpenApiParameterObject,
+    OpenApiRequestBodyObject,
+    OpenApiResponseObject,
+    OpenApiSchemaEnum,
+    OpenApiSchemaObject,
+    RequestBodyEnum,
+    ResponseEnum
+};
+
+
+const COMPONENTS_PREFIX: &str = "#/components/";
+
+
+/// Something that is either an object or a reference to a component of the same kind
+trait Component: Sized {
+    type Object;
+    const KIND: &'static str; // Key of the components of this kind under `components`
+    /// The object, or the reference if this is one
+    fn object(&self) -> Result<&Self::Object, &str>;
+    fn components(components: &OpenApiComponents) -> &HashMap<String, Self>;
+}
+
+impl Component for OpenApiSchemaEnum {
+    type Object = OpenApiSchemaObject;
+    const KIND: &'static str = "schemas";
+    fn object(&self) -> Result<&Self::Object, &str> {
+        match self {
+            OpenApiSchemaEnum::OpenApiSchemaObject(object) => Ok(object),
+            OpenApiSchemaEnum::OpenApiReferenceObject(r) => Err(&r.r#ref),
+        }
+    }
+    fn components(components: &OpenApiComponents) -> &HashMap<String, Self> {
+        &components.schemas
+    }
+}
+
+impl Component for OpenApiParameterEnum {
+    type Object = OpenApiParameterObject;
+    const KIND: &'static str = "parameters";
+    fn object(&self) -> Result<&Self::Object, &str> {
+        match self {
+            OpenApiParameterEnum::OpenApiParameterObject(object) => Ok(object),
+            OpenApiParameterEnum::OpenApiReferenceObject(r) => Err(&r.r#ref),
+        }
+    }
+    fn components(components: &OpenApiComponents) -> &HashMap<String, Self> {
+        &components.parameters
+    }
+}
+
+impl Component for RequestBodyEnum {
+    type Object = OpenApiRequestBodyObject;
+    const KIND: &'static str = "requestBodies";
+    fn object(&self) -> Result<&Self::Object, &str> {
+        match self {
+            RequestBodyEnum::OpenApiRequestBodyObject(object) => Ok(object),
+            RequestBodyEnum::OpenApiReferenceObject(r) => Err(&r.r#ref),
+        }
+    }
+    fn components(components: &OpenApiComponents) -> &HashMap<String, Self> {
+        &components.request_bodies
+    }
+}
+
+impl Component for ResponseEnum {
+    type Object = OpenApiResponseObject;
+    const KIND: &'static str = "responses";
+    fn object(&self) -> Result<&Self::Object, &str> {
+        match self {
+            ResponseEnum::OpenApiResponseObject(object) => Ok(object),
+            ResponseEnum::OpenApiReferenceObject(r) => Err(&r.r#ref),
+        }
+    }
+    fn components(components: &OpenApiComponents) -> &HashMap<String, Self> {
+        &components.responses
+    }
+}
+
+
+/// Resolves references against the components of a single description
+pub struct RefResolver<'a> {
+    components: Option<&'a OpenApiComponents>,
+}
+
+impl<'a> RefResolver<'a> {
+    pub fn new(document: &'a OpenApiDocument) -> Self {
+        RefResolver { components: document.components.as_ref() }
+    }
+
+    /// The schema with references resolved, in it and in its properties
+    pub fn schema(&self, schema: &OpenApiSchemaEnum) -> Result<OpenApiSchemaObject, String> {
+        self.schema_in(schema, &mut Vec::new())
+    }
+
+    /// The parameter with references resolved, in it and in its schemas
+    pub fn parameter(&self, parameter: &OpenApiParameterEnum) -> Result<OpenApiParameterObject, String> {
+        let mut resolved = self.follow(parameter, &mut Vec::new())?.clone();
+        if let Some(schema) = &resolved.schema {
+            resolved.schema = Some(OpenApiSchemaEnum::OpenApiSchemaObject(self.schema(schema)?));
+        }
+        if let Some(content) = &resolved.content {
+            resolved.content = Some(self.content(content)?);
+        }
+        Ok(resolved)
+    }
+
+    /// The request body with references resolved, in it and in its schemas
+    pub fn request_body(&self, request_body: &RequestBodyEnum) -> Result<OpenApiRequestBodyObject, String> {
+        let mut resolved = self.follow(request_body, &mut Vec::new())?.clone();
+        resolved.content = self.content(&resolved.content)?;
+        Ok(resolved)
+    }
+
+    /// The response with references resolved, in it and in its schemas
+    pub fn response(&self, response: &ResponseEnum) -> Result<OpenApiResponseObject, String> {
+        let mut resolved = self.follow(response, &mut Vec::new())?.clone();
+        if let Some(content) = &resolved.content {
+            resolved.content = Some(self.content(content)?);
+        }
+        Ok(resolved)
+    }
+
+    fn schema_in(&self, schema: &OpenApiSchemaEnum, visiting: &mut Vec<String>) -> Result<OpenApiSchemaObject, String> {
+        let depth = visiting.len();
+        let object = self.follow(schema, visiting)?;
+        let mut resolved = object.clone();
+        if let Some(properties) = &object.properties {
+            let mut resolved_properties = HashMap::with_capacity(properties.len());
+            for (name, property) in properties {
+                let property = self.schema_in(property, visiting)?;
+                resolved_properties.insert(name.clone(), OpenApiSchemaEnum::OpenApiSchemaObject(property));
+            }
+            resolved.properties = Some(resolved_properties);
+        }
+        // The schema may be referred to again elsewhere, just not from within itself
+        visiting.truncate(depth);
+        Ok(resolved)
+    }
+
+    fn content(&self, content: &HashMap<String, OpenApiMediaTypeObject>) -> Result<HashMap<String, OpenApiMediaTypeObject>, String> {
+        let mut resolved = HashMap::with_capacity(content.len());
+        for (media_type, media) in content {
+            let mut media = media.clone();
+            if let Some(schema) = &media.schema {
+                media.schema = Some(OpenApiSchemaEnum::OpenApiSchemaObject(self.schema(schema)?));
+            }
+            resolved.insert(media_type.clone(), media);
+        }
+        Ok(resolved)
+    }
+
+    /// Follows references until an object. The references followed are added to `visiting`.
+    fn follow<'r, T: Component>(&self, mut current: &'r T, visiting: &mut Vec<String>) -> Result<&'r T::Object, String>
+    where
+        'a: 'r,
+    {
+        loop {
+            let reference = match current.object() {
+                Ok(object) => return Ok(object),
+                Err(reference) => reference,
+            };
+            if visiting.iter().any(|r| r == reference) {
+                return Err(format!("$ref cycle: {} -> {}", visiting.join(" -> "), reference));
+            }
+            visiting.push(reference.to_string());
+
+            let name = component_name(reference, T::KIND)?;
+            let components = self
+                .components
+                .ok_or_else(|| format!("$ref '{}' cant be resolved, the description has no components", reference))?;
+            current = T::components(components)
+                .get(&name)
+                .ok_or_else(|| format!("$ref '{}' points to a missing component", reference))?;
+        }
+    }
+}
+
+
+/// Name of the component a reference points to, if it points to a component of the given kind
+fn component_name(reference: &str, kind: &str) -> Result<String, String> {
+    let (ref_kind, name) = reference
+        .strip_prefix(COMPONENTS_PREFIX)
+        .and_then(|rest| rest.split_once('/'))
+        .ok_or_else(|| format!("$ref '{}' isnt supported, only references to '{}...' are", reference, COMPONENTS_PREFIX))?;
+    if ref_kind != kind {
+        return Err(format!("$ref '{}' should point to '{}{}'", reference, COMPONENTS_PREFIX, kind));
+    }
+    // JSON pointer escapes
+    Ok(name.replace("~1", "/").replace("~0", "~"))
+}
@@ -0,0 +1,118 @@
+//! # outbound.rs
+//!
+//! Global limit for concurrent outgoing requests to supervisors. Fan-out operations
+//! (deploying to many devices, health checks on a big fleet) acquire a permit before
+//! each request, and wait in a queue once the limit of the `httpClient` settings they pass is
+//! reached. Counters are kept for monitoring the limiter.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Instant;
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use log::debug;
+use crate::lib::config::HttpClientConfig;
+
+
+/// Created with the limit of the settings by the first request
+static LIMITER: OnceCell<Limiter> = OnceCell::new();
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+static QUEUED: AtomicUsize = AtomicUsize::new(0);
+static PEAK_QUEUED: AtomicUsize = AtomicUsize::new(0);
+static TOTAL_REQUESTS: AtomicU64 = AtomicU64::new(0);
+static TOTAL_QUEUED: AtomicU64 = AtomicU64::new(0);
+static TOTAL_WAIT_MS: AtomicU64 = AtomicU64::new(0);
+
+
+struct Limiter {
+    max_concurrent: usize,
+    semaphore: Semaphore,
+}
+
+impl Limiter {
+    fn new(max_concurrent: usize) -> Self {
+        Limiter { max_concurrent, semaphore: Semaphore::new(max_concurrent) }
+    }
+}
+
+
+/// The limiter. The settings come from the one configuration of the orchestrator, so every
+/// caller passes the same limit.
+fn limiter(settings: &HttpClientConfig) -> &'static Limiter {
+    LIMITER.get_or_init(|| Limiter::new(settings.max_concurrent_supervisor_requests))
+}
+
+
+/// Permission to send one request to a supervisor. The slot is released when this is dropped.
+pub struct OutboundPermit {
+    _permit: SemaphorePermit<'static>,
+}
+
+impl Drop for OutboundPermit {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+
+/// Snapshot of the limiter state and counters
+#[derive(Debug, Clone, Serialize)]
+pub struct OutboundStats {
+    #[serde(rename = "maxConcurrent")]
+    pub max_concurrent: usize,
+    #[serde(rename = "inFlight")]
+    pub in_flight: usize,
+    pub queued: usize,
+    #[serde(rename = "peakQueued")]
+    pub peak_queued: usize,
+    #[serde(rename = "totalRequests")]
+    pub total_requests: u64,
+    #[serde(rename = "totalQueued")]
+    pub total_queued: u64, // How many requests had to wait for a free slot
+    #[serde(rename = "averageWaitMs")]
+    pub average_wait_ms: f64, // Average wait of the requests that had to wait
+}
+
+
+/// Waits until a request to a supervisor can be sent. `purpose` is only used for logging.
+pub async fn acquire(settings: &HttpClientConfig, purpose: &str) -> OutboundPermit {
+    TOTAL_REQUESTS.fetch_add(1, Ordering::Relaxed);
+
+    // Fast path, no need to queue
+    if let Ok(permit) = limiter(settings).semaphore.try_acquire() {
+        IN_FLIGHT.fetch_add(1, Ordering::Relaxed);
+        return OutboundPermit { _permit: permit };
+    }
+
+    let queued = QUEUED.fetch_add(1, Ordering::Relaxed) + 1;
+    PEAK_QUEUED.fetch_max(queued, Ordering::Relaxed);
+    TOTAL_QUEUED.fetch_add(1, Ordering::Relaxed);
+    debug!("Outbound request limit reached, queueing {} ({} waiting)", purpose, queued);
+
+    let started = Instant::now();
+    let permit = limiter(settings)
+        .semaphore
+        .acquire()
+        .await
+        .expect("outbound request limiter is never closed");
+    QUEUED.fetch_sub(1, Ordering::Relaxed);
+    IN_FLIGHT.fetch_add(1, Ordering::Relaxed);
+    TOTAL_WAIT_MS.fetch_add(started.elapsed().as_millis() as u64, Ordering::Relaxed);
+    OutboundPermit { _permit: permit }
+}
+
+
+/// Returns the current state of the outbound request limiter
+pub fn stats(settings: &HttpClientConfig) -> OutboundStats {
+    let total_queued = TOTAL_QUEUED.load(Ordering::Relaxed);
+    let total_wait_ms = TOTAL_WAIT_MS.load(Ordering::Relaxed);
+    OutboundStats {
+        max_concurrent: limiter(settings).max_concurrent,
+        in_flight: IN_FLIGHT.load(Ordering::Relaxed),
+        queued: QUEUED.load(Ordering::Relaxed),
+        peak_queued: PEAK_QUEUED.load(Ordering::Relaxed),
+        total_requests: TOTAL_REQUESTS.load(Ordering::Relaxed),
+        total_queued,
+        average_wait_ms: if total_queued > 0 { total_wait_ms as f64 / total_queued as f64 } else { 0.0 },
+    }
+}
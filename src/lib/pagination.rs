@@ -0,0 +1,125 @@
+//! # pagination.rs
+//!
+//! Shared `limit`, `offset` and `sort` query parameters for list endpoints. Lists are still
+//! returned as plain JSON arrays (so existing clients keep working), and the total number of
+//! matching documents before `limit` and `offset` are applied is returned in the
+//! `X-Total-Count` header.
+
+use std::collections::HashMap;
+use actix_web::HttpResponse;
+use actix_web::http::header::{HeaderName, HeaderValue};
+use futures::stream::TryStreamExt;
+use mongodb::Collection;
+use mongodb::bson::{doc, Document};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use crate::lib::errors::ApiError;
+use crate::lib::response::ok_json;
+
+
+/// Name of the header carrying the total number of matching documents
+pub const TOTAL_COUNT_HEADER: &str = "x-total-count";
+
+
+/// Sort keys accepted by the card listings
+pub const CARD_SORT_FIELDS: &[(&str, &str)] = &[
+    ("dateReceived", "dateReceived"),
+    ("name", "name"),
+];
+
+
+/// Pagination and sorting requested for a list
+#[derive(Debug, Clone, Default)]
+pub struct Pagination {
+    pub limit: Option<u64>,
+    pub offset: u64,
+    pub sort: Option<Document>,
+}
+
+impl Pagination {
+    /// Parses `limit`, `offset` and `sort` from the query parameters. `sort_fields` maps the
+    /// sort keys accepted by the endpoint to document fields, and a key can be prefixed with `-`
+    /// for descending order. `default_sort` is used when no sort is given.
+    pub fn from_query(
+        query: &HashMap<String, String>,
+        sort_fields: &[(&str, &str)],
+        default_sort: Option<Document>,
+    ) -> Result<Self, ApiError> {
+        let sort = match query.get("sort") {
+            Some(s) => Some(parse_sort(s, sort_fields)?),
+            None => default_sort,
+        };
+        Ok(Pagination {
+            limit: parse_count_param(query, "limit")?,
+            offset: parse_count_param(query, "offset")?.unwrap_or(0),
+            sort,
+        })
+    }
+}
+
+
+/// A single page of a list, along with the number of all matching documents
+#[derive(Debug, Clone)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: u64,
+}
+
+
+/// Finds the requested page of documents matching the filter
+pub async fn find_page<T>(coll: &Collection<T>, filter: Document, pagination: &Pagination) -> Result<Page<T>, ApiError>
+where
+    T: DeserializeOwned + Unpin + Send + Sync,
+{
+    let total = coll.count_documents(filter.clone()).await.map_err(ApiError::db)?;
+    let mut find = coll.find(filter).skip(pagination.offset);
+    if let Some(sort) = &pagination.sort {
+        find = find.sort(sort.clone());
+    }
+    if let Some(limit) = pagination.limit {
+        find = find.limit(limit as i64);
+    }
+    let items: Vec<T> = find
+        .await
+        .map_err(ApiError::db)?
+        .try_collect()
+        .await
+        .map_err(ApiError::db)?;
+    Ok(Page { items, total })
+}
+
+
+/// Returns the items of the page as a normalized JSON array, with the total count header set
+pub fn page_response<T: Serialize>(page: &Page<T>) -> Result<HttpResponse, ApiError> {
+    let mut resp = ok_json(&page.items)?;
+    resp.headers_mut().insert(HeaderName::from_static(TOTAL_COUNT_HEADER), HeaderValue::from(page.total));
+    Ok(resp)
+}
+
+
+/// Sort document for a `sort` query parameter
+fn parse_sort(sort: &str, sort_fields: &[(&str, &str)]) -> Result<Document, ApiError> {
+    let (key, direction) = match sort.strip_prefix('-') {
+        Some(key) => (key, -1),
+        None => (sort, 1),
+    };
+    match sort_fields.iter().find(|(k, _)| *k == key) {
+        Some((_, field)) => {
+            let field: &str = field;
+            Ok(doc! { field: direction })
+        }
+        None => {
+            let keys: Vec<&str> = sort_fields.iter().map(|(k, _)| *k).collect();
+            Err(ApiError::bad_request(format!("invalid sort '{}', expected one of: {}", sort, keys.join(", "))))
+        }
+    }
+}
+
+
+/// Parses an optional non-negative integer query parameter
+fn parse_count_param(query: &HashMap<String, String>, name: &str) -> Result<Option<u64>, ApiError> {
+    query
+        .get(name)
+        .map(|v| v.parse::<u64>().map_err(|_| ApiError::bad_request(format!("invalid {} '{}', expected a non-negative integer", name, v))))
+        .transpose()
+}
@@ -0,0 +1,217 @@
+//! # parameter_validation.rs
+//!
+//! Checks the fields and files of an execution request against the OpenAPI operation of the
+//! first step of the deployment (the `OperationRequest` stored in its manifest), before anything
+//! is sent to the supervisor. Every problem found is listed, so that the caller can fix them
+//! all at once:
+//! - required parameters that are missing, and fields that match no parameter
+//! - values that dont parse as the `type` (and integer `format`) of their parameter schema
+//! - a missing request body, and files whose names arent mounts of the request body schema
+//!
+//! JSON bodies are turned into the fields first (see `json_arguments`), so that functions that
+//! take only primitives can be called with their arguments as a JSON array or a single value,
+//! without knowing the names of the generated parameters.
+
+use std::collections::HashMap;
+use serde::Serialize;
+use serde_json::Value;
+use crate::structs::deployment::OperationRequest;
+use crate::structs::openapi::{OpenApiFormat, OpenApiParameterIn, OpenApiSchemaEnum, OpenApiSchemaObject};
+
+
+/// Location of a field that is in no parameter
+pub const LOCATION_UNKNOWN: &str = "unknown";
+pub const LOCATION_REQUEST_BODY: &str = "requestBody";
+
+
+/// A single problem with an execution request
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ParameterViolation {
+    pub name: String,
+    #[serde(rename = "in")]
+    pub location: &'static str, // Where the parameter is: path, query, header, cookie, requestBody or unknown
+    pub message: String,
+}
+
+
+/// Name of a parameter location as in OpenAPI
+fn location_name(location: &OpenApiParameterIn) -> &'static str {
+    match location {
+        OpenApiParameterIn::Query => "query",
+        OpenApiParameterIn::Header => "header",
+        OpenApiParameterIn::Path => "path",
+        OpenApiParameterIn::Cookie => "cookie",
+        OpenApiParameterIn::RequestBody => LOCATION_REQUEST_BODY,
+    }
+}
+
+
+/// Checks the submitted fields and the names of the submitted files against the operation.
+/// Empty if the request is valid.
+pub fn validate_execution_input(request: &OperationRequest, fields: &HashMap<String, String>, files: &[&str]) -> Vec<ParameterViolation> {
+    let mut violations = Vec::new();
+
+    for param in &request.parameters {
+        let location = location_name(&param.r#in);
+        let violation = |message: String| ParameterViolation { name: param.name.clone(), location, message };
+        let Some(value) = fields.get(&param.name) else {
+            // Path parameters are part of the url, so they cant be left out
+            if param.required || matches!(param.r#in, OpenApiParameterIn::Path) {
+                violations.push(violation("required parameter is missing".to_string()));
+            }
+            continue;
+        };
+        if value.is_empty() && param.allow_empty_value != Some(true) && param.required {
+            violations.push(violation("required parameter is empty".to_string()));
+            continue;
+        }
+        if let Some(OpenApiSchemaEnum::OpenApiSchemaObject(schema)) = &param.schema {
+            if let Err(message) = check_type(value, schema) {
+                violations.push(violation(message));
+            }
+        }
+    }
+
+    let mut unknown: Vec<&String> = fields
+        .keys()
+        .filter(|name| !request.parameters.iter().any(|p| p.name == **name))
+        .collect();
+    unknown.sort();
+    for name in unknown {
+        violations.push(ParameterViolation {
+            name: name.clone(),
+            location: LOCATION_UNKNOWN,
+            message: "the operation has no such parameter".to_string(),
+        });
+    }
+
+    match &request.request_body {
+        None => {
+            for name in files {
+                violations.push(ParameterViolation {
+                    name: name.to_string(),
+                    location: LOCATION_REQUEST_BODY,
+                    message: "the operation takes no request body".to_string(),
+                });
+            }
+        }
+        Some(_) if files.is_empty() => {
+            violations.push(ParameterViolation {
+                name: LOCATION_REQUEST_BODY.to_string(),
+                location: LOCATION_REQUEST_BODY,
+                message: "the operation requires a request body, but no files were sent".to_string(),
+            });
+        }
+        Some(body) => {
+            // The properties of the request body schema are the mounts of the operation
+            let mounts = body.schema.as_ref().and_then(|s| s.properties.as_ref());
+            if let Some(mounts) = mounts {
+                let mut unknown: Vec<&str> = files.iter().copied().filter(|f| !mounts.contains_key(*f)).collect();
+                unknown.sort();
+                for name in unknown {
+                    violations.push(ParameterViolation {
+                        name: name.to_string(),
+                        location: LOCATION_REQUEST_BODY,
+                        message: format!("not a mount of the operation, expected one of {}", sorted_names(mounts.keys())),
+                    });
+                }
+                let mut missing: Vec<&String> = mounts.keys().filter(|m| !files.contains(&m.as_str())).collect();
+                missing.sort();
+                for name in missing {
+                    violations.push(ParameterViolation {
+                        name: name.clone(),
+                        location: LOCATION_REQUEST_BODY,
+                        message: "mount is missing from the request body".to_string(),
+                    });
+                }
+            }
+        }
+    }
+    violations
+}
+
+
+/// Checks that the value parses as the type of the schema. Types that arent checked pass.
+fn check_type(value: &str, schema: &OpenApiSchemaObject) -> Result<(), String> {
+    let Some(ty) = schema.r#type.as_deref() else {
+        return Ok(());
+    };
+    match ty {
+        "integer" => {
+            let parsed = value.trim().parse::<i64>().map_err(|_| format!("'{}' is not an integer", value))?;
+            if matches!(schema.format, Some(OpenApiFormat::Int32)) && i32::try_from(parsed).is_err() {
+                return Err(format!("{} is out of range for int32", parsed));
+            }
+            Ok(())
+        }
+        "number" | "float" => match value.trim().parse::<f64>() {
+            Ok(n) if n.is_finite() => Ok(()),
+            _ => Err(format!("'{}' is not a number", value)),
+        },
+        "boolean" => match value.trim() {
+            "true" | "false" => Ok(()),
+            _ => Err(format!("'{}' is not a boolean, expected true or false", value)),
+        },
+        "array" => match serde_json::from_str::<serde_json::Value>(value) {
+            Ok(serde_json::Value::Array(_)) => Ok(()),
+            _ => Err(format!("'{}' is not a JSON array", value)),
+        },
+        "object" => match serde_json::from_str::<serde_json::Value>(value) {
+            Ok(serde_json::Value::Object(_)) => Ok(()),
+            _ => Err(format!("'{}' is not a JSON object", value)),
+        },
+        _ => Ok(()),
+    }
+}
+
+
+/// Fields of a JSON execution body. An object gives its members as they are. For operations
+/// without a request body, the items of an array are the parameters in order (param0, param1,
+/// ...), and a single value is the only parameter. Anything else is kept whole under `body`.
+pub fn json_arguments(request: &OperationRequest, body: Value) -> HashMap<String, String> {
+    if let Value::Object(map) = body {
+        return map.into_iter().map(|(k, v)| (k, field_value(v))).collect();
+    }
+    let names = if request.request_body.is_none() { positional_parameters(request) } else { Vec::new() };
+    match body {
+        // Items beyond the parameters are named by their index, and reported as unknown by the validation
+        Value::Array(items) if !names.is_empty() => items
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (names.get(i).map(|n| n.to_string()).unwrap_or_else(|| i.to_string()), field_value(v)))
+            .collect(),
+        value if names.len() == 1 && !value.is_array() => HashMap::from([(names[0].to_string(), field_value(value))]),
+        other => HashMap::from([("body".to_string(), other.to_string())]),
+    }
+}
+
+
+/// Names of the path and query parameters in the order of the function arguments. The generated
+/// `paramN` parameters are ordered by their number (param10 after param9), others keep their order.
+fn positional_parameters(request: &OperationRequest) -> Vec<&str> {
+    let mut names: Vec<&str> = request
+        .parameters
+        .iter()
+        .filter(|p| matches!(p.r#in, OpenApiParameterIn::Query | OpenApiParameterIn::Path))
+        .map(|p| p.name.as_str())
+        .collect();
+    names.sort_by_key(|name| name.strip_prefix("param").and_then(|n| n.parse::<usize>().ok()).unwrap_or(usize::MAX));
+    names
+}
+
+
+/// A JSON value as a field value, strings without their quotes
+fn field_value(value: Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s,
+        other => other.to_string(),
+    }
+}
+
+
+fn sorted_names<'a>(names: impl Iterator<Item = &'a String>) -> String {
+    let mut names: Vec<&str> = names.map(String::as_str).collect();
+    names.sort();
+    names.join(", ")
+}
@@ -0,0 +1,72 @@
+//! # reachability.rs
+//!
+//! Multi-homed supervisors advertise several addresses, not all of which the orchestrator can
+//! reach. Requests to a device go to the first of its addresses (see
+//! `DeviceCommunication::base_url`), so the addresses are probed with a TCP connection to the
+//! port of the device and reordered with the reachable ones first. Devices are probed when they
+//! are discovered, and again when a health check of a device with several addresses fails.
+
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use futures::future::join_all;
+use log::{info, warn};
+use mongodb::bson::{doc, Bson};
+use tokio::net::TcpStream;
+use crate::lib::config::{Config, DiscoveryConfig};
+use crate::lib::constants::COLL_DEVICE;
+use crate::lib::mongodb::update_field;
+use crate::structs::device::DeviceDoc;
+
+
+/// Orders the addresses with the ones that accept a connection on the port first. The order is
+/// otherwise kept, so unreachable addresses stay in the order they were given. Each address gets
+/// the probe timeout of the discovery settings.
+pub async fn order_by_reachability(config: &DiscoveryConfig, addresses: &[String], port: u16) -> Vec<String> {
+    let timeout = Duration::from_millis(config.address_probe_timeout_ms);
+    let reachable = join_all(addresses.iter().map(|address| is_reachable(address, port, timeout))).await;
+    let (mut ordered, unreachable): (Vec<_>, Vec<_>) = addresses
+        .iter()
+        .cloned()
+        .zip(reachable)
+        .partition(|(_, reachable)| *reachable);
+    ordered.extend(unreachable);
+    ordered.into_iter().map(|(address, _)| address).collect()
+}
+
+
+/// Probes the addresses of a device, and stores them reordered if the first one isnt reachable
+/// and another one is. Returns the new order if it changed.
+pub async fn reorder_device_addresses(config: &Config, device: &DeviceDoc) -> Option<Vec<String>> {
+    let addresses = &device.communication.addresses;
+    if addresses.len() < 2 {
+        return None;
+    }
+    let ordered = order_by_reachability(&config.discovery, addresses, device.communication.port).await;
+    if ordered == *addresses {
+        return None;
+    }
+
+    let filter = match device.id {
+        Some(id) => doc! { "_id": id },
+        None => doc! { "name": &device.name },
+    };
+    let value = Bson::Array(ordered.iter().cloned().map(Bson::String).collect());
+    if let Err(e) = update_field::<DeviceDoc>(&config.database, COLL_DEVICE, filter, "communication.addresses", value).await {
+        warn!("Failed to store the reordered addresses of device '{}': {}", device.name, e);
+        return None;
+    }
+    info!("🔀 Device '{}' is now reached at {} (addresses {:?})", device.name, ordered[0], ordered);
+    Some(ordered)
+}
+
+
+async fn is_reachable(address: &str, port: u16, timeout: Duration) -> bool {
+    let Ok(ip) = address.parse::<IpAddr>() else {
+        // Host names are left for the requests themselves to resolve
+        return true;
+    };
+    matches!(
+        tokio::time::timeout(timeout, TcpStream::connect(SocketAddr::new(ip, port))).await,
+        Ok(Ok(_))
+    )
+}
@@ -0,0 +1,88 @@
+//! # request_id.rs
+//!
+//! Request ids for correlating orchestrator activity with supervisor logs. Every incoming
+//! request gets an `X-Request-Id` (the one sent by the client if it is usable, a new UUID
+//! otherwise), which is returned in the response, included in the log lines written while
+//! handling the request, and passed on to supervisors with the deployment and execution
+//! requests. Supervisors include it as `request_id` in the logs they send back.
+
+use std::io::Write;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Longest request id accepted from clients
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+
+/// Id of the request being handled, None outside request handling (background loops, and
+/// tasks spawned from handlers)
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+
+/// Runs the future with the given request id as the current one, for work spawned from a
+/// request that should still be traced with its id
+pub async fn scope<F: std::future::Future>(id: Option<String>, f: F) -> F::Output {
+    match id {
+        Some(id) => REQUEST_ID.scope(id, f).await,
+        None => f.await,
+    }
+}
+
+
+/// Middleware assigning the request id, used with `actix_web::middleware::from_fn`
+pub async fn assign_request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| is_valid(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+
+    let mut res = REQUEST_ID.scope(id.clone(), next.call(req)).await?;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        res.headers_mut().insert(HeaderName::from_static("x-request-id"), value);
+    }
+    Ok(res)
+}
+
+
+/// Adds the id of the current request to an outgoing request
+pub fn propagate(request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    match current() {
+        Some(id) => request.header(REQUEST_ID_HEADER, id),
+        None => request,
+    }
+}
+
+
+/// Log line format of env_logger with the request id added, e.g.
+/// `[2025-01-01T12:00:00Z INFO orchestrator::api::deployment request_id=...] message`
+pub fn format_log_record(buf: &mut env_logger::fmt::Formatter, record: &log::Record) -> std::io::Result<()> {
+    let level_style = buf.default_level_style(record.level());
+    write!(buf, "[{} {level_style}{:<5}{level_style:#} {}", buf.timestamp(), record.level(), record.target())?;
+    if let Some(id) = current() {
+        write!(buf, " request_id={}", id)?;
+    }
+    writeln!(buf, "] {}", record.args())
+}
+
+
+/// Ids are echoed in headers and logs, so only short printable ones are accepted
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.chars().all(|c| c.is_ascii_graphic())
+}
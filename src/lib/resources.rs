@@ -0,0 +1,187 @@
+//! # resources.rs
+//!
+//! Resource accounting for placing the steps of a deployment onto devices. Each step needs
+//! the memory and storage of its module on the device it runs on, and when several steps end
+//! up on the same device their needs are added together (a module used by multiple steps on
+//! the same device is only counted once, since the supervisor loads it once). Devices that
+//! havent reported their memory or storage are not limited by it.
+
+use std::collections::{HashMap, HashSet};
+use log::warn;
+use mongodb::bson::oid::ObjectId;
+use serde::Serialize;
+use wasmparser::{Parser, Payload, TypeRef};
+use crate::structs::device::DeviceDoc;
+use crate::structs::module::ModuleDoc;
+
+
+/// Default wasm page size (64 KiB), used unless the memory declares a custom one
+const WASM_PAGE_SIZE_LOG2: u32 = 16;
+
+
+/// Memory and storage needed by a module on the device it is deployed to
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ResourceNeeds {
+    #[serde(rename = "memoryBytes")]
+    pub memory_bytes: u64,
+    #[serde(rename = "storageBytes")]
+    pub storage_bytes: u64,
+}
+
+impl ResourceNeeds {
+    /// Needs of a module: the initial memory declared in its wasm binary, and the size of the
+    /// binary and its data files. Files that cant be read count as zero.
+    pub fn of_module(module: &ModuleDoc) -> Self {
+        let memory_bytes = wasm_initial_memory(&module.wasm.path).unwrap_or_else(|e| {
+            warn!("Could not read the memory requirement of module '{}': {}", module.name, e);
+            0
+        });
+        let files = std::iter::once(module.wasm.path.as_str())
+            .chain(module.data_files.iter().flatten().map(|(_, f)| f.path.as_str()));
+        let storage_bytes = files
+            .filter_map(|p| std::fs::metadata(p).ok())
+            .map(|m| m.len())
+            .sum();
+        ResourceNeeds { memory_bytes, storage_bytes }
+    }
+
+    fn add(&mut self, other: &ResourceNeeds) {
+        self.memory_bytes += other.memory_bytes;
+        self.storage_bytes += other.storage_bytes;
+    }
+}
+
+
+/// Memory and storage a device has available, None where the device hasnt reported it
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DeviceCapacity {
+    pub memory_bytes: Option<u64>,
+    pub storage_bytes: Option<u64>,
+}
+
+impl DeviceCapacity {
+    /// Free memory and storage of a device, based on its description and the usage
+    /// percentages of its latest health report (all of it is free if there is no report)
+    pub fn of_device(device: &DeviceDoc) -> Self {
+        let platform = &device.description.platform;
+        let report = device.health.as_ref().map(|h| &h.report);
+        let free = |total: u64, usage_pct: f32| (total as f64 * (1.0 - (usage_pct as f64 / 100.0).clamp(0.0, 1.0))) as u64;
+
+        let memory_bytes = (platform.memory.total_bytes > 0).then(|| {
+            free(platform.memory.total_bytes, report.map(|r| r.memory_usage).unwrap_or(0.0))
+        });
+        let storage_bytes = (!platform.storage.is_empty()).then(|| {
+            platform
+                .storage
+                .iter()
+                .map(|(name, total)| {
+                    let usage = report.and_then(|r| r.storage_usage.get(name)).copied().unwrap_or(0.0);
+                    free(*total, usage)
+                })
+                .sum()
+        });
+        DeviceCapacity { memory_bytes, storage_bytes }
+    }
+}
+
+
+/// Resources reserved on each device by the steps of a deployment placed so far
+#[derive(Debug, Default)]
+pub struct ResourceLedger {
+    reserved: HashMap<String, ResourceNeeds>,
+    modules: HashMap<String, HashSet<ObjectId>>,
+    needs: HashMap<ObjectId, ResourceNeeds>,
+}
+
+impl ResourceLedger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks that the module fits on the device together with everything already reserved
+    /// on it. Returns a description of the shortage if it doesnt.
+    pub fn check(&mut self, device: &DeviceDoc, module: &ModuleDoc) -> Result<(), String> {
+        let Some(total) = self.total_with(device, module) else {
+            return Ok(());
+        };
+        let capacity = DeviceCapacity::of_device(device);
+        let mut shortages = Vec::new();
+        if let Some(free) = capacity.memory_bytes.filter(|free| total.memory_bytes > *free) {
+            shortages.push(format!("memory {} bytes needed, {} bytes free", total.memory_bytes, free));
+        }
+        if let Some(free) = capacity.storage_bytes.filter(|free| total.storage_bytes > *free) {
+            shortages.push(format!("storage {} bytes needed, {} bytes free", total.storage_bytes, free));
+        }
+        if shortages.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "device '{}' cannot fit module '{}' together with the other steps placed on it: {}",
+                device.name, module.name, shortages.join(", ")
+            ))
+        }
+    }
+
+    /// Reserves the resources of the module on the device
+    pub fn reserve(&mut self, device: &DeviceDoc, module: &ModuleDoc) {
+        if let Some(total) = self.total_with(device, module) {
+            let key = device.name.clone();
+            self.reserved.insert(key.clone(), total);
+            if let Some(id) = module.id {
+                self.modules.entry(key).or_default().insert(id);
+            }
+        }
+    }
+
+    /// Resources reserved on each device, by device name
+    pub fn reserved(&self) -> &HashMap<String, ResourceNeeds> {
+        &self.reserved
+    }
+
+    /// What would be reserved on the device if the module was added to it,
+    /// None if the module is already there
+    fn total_with(&mut self, device: &DeviceDoc, module: &ModuleDoc) -> Option<ResourceNeeds> {
+        let key = device.name.clone();
+        if let Some(id) = module.id {
+            if self.modules.get(&key).is_some_and(|m| m.contains(&id)) {
+                return None;
+            }
+        }
+        let needs = match module.id {
+            Some(id) => *self.needs.entry(id).or_insert_with(|| ResourceNeeds::of_module(module)),
+            None => ResourceNeeds::of_module(module),
+        };
+        let mut total = self.reserved.get(&key).copied().unwrap_or_default();
+        total.add(&needs);
+        Some(total)
+    }
+}
+
+
+/// Initial linear memory of a wasm module in bytes, including imported memories
+pub fn wasm_initial_memory(path: &str) -> Result<u64, Box<dyn std::error::Error>> {
+    let bytes = std::fs::read(path)?;
+    let mut total: u64 = 0;
+    let mut add = |ty: wasmparser::MemoryType| {
+        let page_size = 1u64 << ty.page_size_log2.unwrap_or(WASM_PAGE_SIZE_LOG2);
+        total = total.saturating_add(ty.initial.saturating_mul(page_size));
+    };
+    for payload in Parser::new(0).parse_all(&bytes) {
+        match payload? {
+            Payload::ImportSection(reader) => {
+                for import in reader {
+                    if let TypeRef::Memory(ty) = import?.ty {
+                        add(ty);
+                    }
+                }
+            }
+            Payload::MemorySection(reader) => {
+                for memory in reader {
+                    add(memory?);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(total)
+}
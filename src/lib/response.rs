@@ -0,0 +1,82 @@
+//! # response.rs
+//!
+//! Serialization of JSON responses. Documents read from mongodb contain ObjectIds and
+//! datetimes, which serialize into Extended JSON (`{"$oid": "..."}` and `{"$date": ...}`)
+//! that the frontend doesnt know how to handle. All handlers returning database content
+//! should build their responses with the helpers here, so that the conversion happens in one place.
+
+use actix_web::HttpResponse;
+use actix_web::http::StatusCode;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use crate::lib::errors::ApiError;
+
+
+/// Serializes the given body and returns it as a `200 OK` JSON response with
+/// ObjectIds and datetimes normalized.
+pub fn ok_json<T: Serialize + ?Sized>(body: &T) -> Result<HttpResponse, ApiError> {
+    json_response(StatusCode::OK, body)
+}
+
+
+/// Serializes the given body and returns it as a JSON response with the given status,
+/// with ObjectIds and datetimes normalized.
+pub fn json_response<T: Serialize + ?Sized>(status: StatusCode, body: &T) -> Result<HttpResponse, ApiError> {
+    let v = to_normalized_value(body)?;
+    Ok(HttpResponse::build(status).json(v))
+}
+
+
+/// Serializes the given value into JSON with ObjectIds and datetimes normalized.
+pub fn to_normalized_value<T: Serialize + ?Sized>(body: &T) -> Result<Value, ApiError> {
+    let mut v = serde_json::to_value(body).map_err(ApiError::internal_error)?;
+    normalize_extended_json(&mut v);
+    Ok(v)
+}
+
+
+/// Recursively converts Extended JSON values into plain JSON:
+/// - ObjectIds `{"$oid": "…"}` into plain strings `"…"`
+/// - Datetimes `{"$date": …}` (either canonical `{"$numberLong": "…"}`, relaxed string or plain millis)
+///   into RFC3339 strings
+pub fn normalize_extended_json(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            if map.len() == 1 {
+                if let Some(s) = map.get("$oid").and_then(Value::as_str) {
+                    *value = Value::String(s.to_string());
+                    return;
+                }
+                if let Some(d) = map.get("$date") {
+                    if let Some(s) = extended_date_to_rfc3339(d) {
+                        *value = Value::String(s);
+                        return;
+                    }
+                }
+            }
+            for v in map.values_mut() {
+                normalize_extended_json(v);
+            }
+        }
+        Value::Array(arr) => {
+            for v in arr {
+                normalize_extended_json(v);
+            }
+        }
+        _ => {}
+    }
+}
+
+
+/// Helper function that converts the inner value of an Extended JSON `$date` into an RFC3339 string
+fn extended_date_to_rfc3339(d: &Value) -> Option<String> {
+    let millis = match d {
+        Value::Object(inner) => inner.get("$numberLong")?.as_str()?.parse::<i64>().ok()?,
+        Value::Number(n) => n.as_i64()?,
+        Value::String(s) => return Some(s.clone()), // Relaxed format is already an ISO-8601 string
+        _ => return None,
+    };
+    DateTime::<Utc>::from_timestamp_millis(millis)
+        .map(|dt| dt.to_rfc3339_opts(SecondsFormat::Millis, true))
+}
@@ -0,0 +1,99 @@
+//! # result_callbacks.rs
+//!
+//! Completion of executions by callback instead of polling. The scheduling request of an
+//! execution carries a callback url (`/postResult/{job_id}?token=...`) in the
+//! `X-Result-Callback` header. A supervisor that will post the final result there answers with
+//! the same header, and the execution waits for the result to be posted instead of polling the
+//! result urls. Supervisors that dont answer with the header are polled as before, and so are
+//! the ones that dont post within `execution.resultCallbackWaitS`. Each callback url has a
+//! random token, so that results can only be posted by the supervisors it was given to.
+
+use std::collections::HashMap;
+use std::time::Duration;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde_json::Value;
+use tokio::sync::oneshot;
+use log::debug;
+
+
+/// Header the callback url is sent in, and that supervisors accepting it answer with
+pub const RESULT_CALLBACK_HEADER: &str = "X-Result-Callback";
+
+static WAITING: Lazy<Mutex<HashMap<u64, Waiting>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+
+struct Waiting {
+    token: String,
+    sender: oneshot::Sender<Value>,
+}
+
+
+/// An execution waiting for its result to be posted. Stops waiting when dropped.
+pub struct ResultCallback {
+    id: u64,
+    url: String,
+    receiver: Option<oneshot::Receiver<Value>>,
+}
+
+impl ResultCallback {
+    /// Url the result of the execution is posted to
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Waits for the result to be posted, at most for the given time. None if it wasnt posted
+    /// in time, after which the callback no longer accepts the result.
+    pub async fn wait(&mut self, within: Option<Duration>) -> Option<Value> {
+        let receiver = self.receiver.take()?;
+        let posted = match within {
+            Some(within) => tokio::time::timeout(within, receiver).await.ok()?.ok(),
+            None => receiver.await.ok(),
+        };
+        if posted.is_none() {
+            WAITING.lock().remove(&self.id);
+        }
+        posted
+    }
+}
+
+impl Drop for ResultCallback {
+    fn drop(&mut self) {
+        WAITING.lock().remove(&self.id);
+    }
+}
+
+
+/// Starts waiting for the result of the execution with the given id, to be posted under the
+/// given orchestrator base url
+pub fn register(id: u64, base_url: &str) -> ResultCallback {
+    let token = uuid::Uuid::new_v4().simple().to_string();
+    let (sender, receiver) = oneshot::channel();
+    let url = format!("{}/postResult/{}?token={}", base_url.trim_end_matches('/'), id, token);
+    WAITING.lock().insert(id, Waiting { token, sender });
+    ResultCallback { id, url, receiver: Some(receiver) }
+}
+
+
+/// Why a posted result wasnt accepted
+#[derive(Debug)]
+pub enum CallbackError {
+    NotWaiting, // No execution with the id is waiting for its result, or it was already posted
+    InvalidToken,
+}
+
+
+/// Hands a posted result to the execution waiting for it
+pub fn deliver(id: u64, token: &str, result: Value) -> Result<(), CallbackError> {
+    let mut waiting = WAITING.lock();
+    match waiting.get(&id) {
+        None => return Err(CallbackError::NotWaiting),
+        Some(w) if w.token != token => return Err(CallbackError::InvalidToken),
+        Some(_) => {}
+    }
+    let Some(w) = waiting.remove(&id) else {
+        return Err(CallbackError::NotWaiting);
+    };
+    debug!("Result of execution {} was posted back", id);
+    w.sender.send(result).map_err(|_| CallbackError::NotWaiting)
+}
@@ -0,0 +1,74 @@
+//! # revisions.rs
+//!
+//! In-memory revision counters for collections, bumped whenever a collection is written to.
+//! List endpoints derive their ETags from these, so that the frontend polling them can send
+//! `If-None-Match` and get a `304 Not Modified` instead of re-downloading an unchanged list.
+
+use std::collections::HashMap;
+use actix_web::{HttpRequest, HttpResponse};
+use actix_web::http::header;
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::Serialize;
+use crate::lib::errors::ApiError;
+use crate::lib::pagination::{page_response, Page};
+
+
+static REVISIONS: Lazy<Mutex<HashMap<String, u64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Startup time, included in ETags so that tags handed out before a restart never match
+static EPOCH: Lazy<i64> = Lazy::new(|| Utc::now().timestamp_millis());
+
+
+/// Marks the given collection as changed
+pub fn bump(collection: &str) {
+    *REVISIONS.lock().entry(collection.to_string()).or_insert(0) += 1;
+}
+
+
+/// Current revision of the given collection
+pub fn revision(collection: &str) -> u64 {
+    REVISIONS.lock().get(collection).copied().unwrap_or(0)
+}
+
+
+/// ETag for a response built from the given collections
+pub fn etag(collections: &[&str]) -> String {
+    let revs: Vec<String> = collections.iter().map(|c| revision(c).to_string()).collect();
+    format!("\"{:x}-{}\"", *EPOCH, revs.join("."))
+}
+
+
+/// Whether the `If-None-Match` header of the request matches the given ETag
+pub fn matches(req: &HttpRequest, etag: &str) -> bool {
+    req.headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.split(',').map(str::trim).any(|t| t == "*" || t.trim_start_matches("W/") == etag))
+        .unwrap_or(false)
+}
+
+
+/// Returns `304 Not Modified` if the client already has the current version of the list,
+/// otherwise the page of the list as a JSON response (see `page_response`) with the ETag set.
+/// The page is only fetched when it is actually needed.
+pub async fn cached_json<T, F, Fut>(req: &HttpRequest, collections: &[&str], build: F) -> Result<HttpResponse, ApiError>
+where
+    T: Serialize,
+    F: FnOnce() -> Fut,
+    Fut: std::future::Future<Output = Result<Page<T>, ApiError>>,
+{
+    // Read the revision before querying, so that writes during the query result in a new tag
+    let tag = etag(collections);
+    if matches(req, &tag) {
+        return Ok(HttpResponse::NotModified()
+            .insert_header((header::ETAG, tag))
+            .finish());
+    }
+    let mut resp = page_response(&build().await?)?;
+    let headers = resp.headers_mut();
+    headers.insert(header::ETAG, header::HeaderValue::from_str(&tag).map_err(ApiError::internal_error)?);
+    headers.insert(header::CACHE_CONTROL, header::HeaderValue::from_static("no-cache"));
+    Ok(resp)
+}
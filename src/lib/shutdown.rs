@@ -0,0 +1,139 @@
+//! # shutdown.rs
+//!
+//! Graceful shutdown of the orchestrator. On ctrl-c or SIGTERM the background loops are
+//! stopped, the tracked background tasks (database writes such as the deployment status
+//! changes of revalidation, and websocket connections) are given time to finish, and the HTTP
+//! server is stopped last, once it has answered the requests in progress. Waiting for the
+//! background work is bounded by the shutdown timeout of the server settings, and so is
+//! waiting for the requests.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use actix_web::dev::ServerHandle;
+use log::{error, info, warn};
+use once_cell::sync::Lazy;
+use tokio::sync::{watch, Notify};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use crate::lib::watchdog;
+
+
+/// Set to true when shutdown begins
+static SHUTDOWN: Lazy<watch::Sender<bool>> = Lazy::new(|| watch::channel(false).0);
+
+/// Number of tracked tasks still running, and the notification of it reaching zero
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+static DRAINED: Lazy<Notify> = Lazy::new(Notify::new);
+
+
+/// Counts a tracked task as finished when dropped, so that panicking tasks are counted too
+struct TaskGuard;
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        if IN_FLIGHT.fetch_sub(1, Ordering::AcqRel) == 1 {
+            DRAINED.notify_waiters();
+        }
+    }
+}
+
+
+/// Whether shutdown has begun
+pub fn is_shutting_down() -> bool {
+    *SHUTDOWN.borrow()
+}
+
+
+/// Resolves once shutdown has begun, for long running tasks to stop at
+pub async fn requested() {
+    let mut rx = SHUTDOWN.subscribe();
+    // The sender is never dropped, so this only returns when shutdown begins
+    let _ = rx.wait_for(|stopping| *stopping).await;
+}
+
+
+/// Spawns a task that shutdown waits for, for background work that shouldnt be cut off
+/// halfway (e.g. database writes)
+pub fn spawn_tracked<F>(task: F) -> JoinHandle<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    IN_FLIGHT.fetch_add(1, Ordering::AcqRel);
+    let guard = TaskGuard;
+    tokio::spawn(async move {
+        let _guard = guard;
+        task.await;
+    })
+}
+
+
+/// Shuts the orchestrator down on ctrl-c or SIGTERM, stopping `server` last. The server has to
+/// be started with its own signal handling disabled.
+pub fn handle_signals(server: ServerHandle, timeout: Duration) {
+    tokio::spawn(async move {
+        wait_for_signal().await;
+        shutdown(server, timeout).await;
+    });
+}
+
+
+async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => info!("Received ctrl-c"),
+                    _ = sigterm.recv() => info!("Received SIGTERM"),
+                }
+                return;
+            }
+            Err(e) => error!("Failed to listen for SIGTERM: {}", e),
+        }
+    }
+    match tokio::signal::ctrl_c().await {
+        Ok(()) => info!("Received ctrl-c"),
+        Err(e) => {
+            error!("Failed to listen for ctrl-c, graceful shutdown is not available: {}", e);
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+
+async fn shutdown(server: ServerHandle, timeout: Duration) {
+    info!("🛑 Shutting down, waiting up to {} s for background work to finish", timeout.as_secs());
+    let deadline = Instant::now() + timeout;
+    SHUTDOWN.send_replace(true);
+
+    watchdog::stop_loops(deadline).await;
+    if !drain(deadline).await {
+        warn!(
+            "{} background tasks were still running at the shutdown deadline",
+            IN_FLIGHT.load(Ordering::Acquire)
+        );
+    }
+
+    // Waits for the requests in progress, up to the shutdown timeout of the server
+    server.stop(true).await;
+    info!("✅ Orchestrator stopped");
+}
+
+
+/// Waits for the tracked tasks to finish, false if some were still running at the deadline
+async fn drain(deadline: Instant) -> bool {
+    loop {
+        let drained = DRAINED.notified();
+        tokio::pin!(drained);
+        // Registered before checking the count, so that reaching zero in between isnt missed
+        drained.as_mut().enable();
+        if IN_FLIGHT.load(Ordering::Acquire) == 0 {
+            return true;
+        }
+        if tokio::time::timeout_at(deadline, drained).await.is_err() {
+            return false;
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! # ssdp.rs
+//!
+//! SSDP (UPnP) backend of device discovery, for networks where mDNS is blocked. A scan
+//! multicasts an M-SEARCH for the search target of the discovery settings to 239.255.255.250:1900 and collects the
+//! answers until the scan duration has passed. A supervisor is reached at the host, port and
+//! scheme of the LOCATION url of its answer, and named by its `X-Device-Name` header, or by
+//! the host of the url if it has none.
+
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::Duration;
+use futures::future::BoxFuture;
+use log::{debug, warn};
+use tokio::net::UdpSocket;
+use crate::lib::discovery::{DiscoveredService, DiscoveryBackend, BACKEND_SSDP};
+
+
+/// Multicast address and port of SSDP
+const SSDP_MULTICAST: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(239, 255, 255, 250), 1900);
+
+/// Header with the name of the supervisor in its answer
+const DEVICE_NAME_HEADER: &str = "x-device-name";
+
+/// Times the search is sent, since UDP datagrams may be lost
+const SEARCH_REPEATS: usize = 2;
+
+
+/// Device discovery over SSDP
+pub struct SsdpBackend {
+    pub search_target: String,
+}
+
+impl DiscoveryBackend for SsdpBackend {
+    fn name(&self) -> &'static str {
+        BACKEND_SSDP
+    }
+
+    fn scan(&self, duration: Duration) -> BoxFuture<'static, Result<HashMap<String, DiscoveredService>, String>> {
+        Box::pin(search(self.search_target.clone(), duration))
+    }
+}
+
+
+async fn search(search_target: String, duration: Duration) -> Result<HashMap<String, DiscoveredService>, String> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .await
+        .map_err(|e| format!("cant open the SSDP socket: {}", e))?;
+    socket
+        .set_multicast_ttl_v4(2)
+        .map_err(|e| format!("cant set the SSDP multicast TTL: {}", e))?;
+
+    // Devices wait a random time of up to MX seconds before answering
+    let request = format!(
+        "M-SEARCH * HTTP/1.1\r\nHOST: {}\r\nMAN: \"ssdp:discover\"\r\nMX: {}\r\nST: {}\r\n\r\n",
+        SSDP_MULTICAST,
+        duration.as_secs().clamp(1, 5),
+        search_target
+    );
+    for _ in 0..SEARCH_REPEATS {
+        socket
+            .send_to(request.as_bytes(), SocketAddr::V4(SSDP_MULTICAST))
+            .await
+            .map_err(|e| format!("cant send the SSDP search: {}", e))?;
+    }
+
+    let mut services: HashMap<String, DiscoveredService> = HashMap::new();
+    let deadline = tokio::time::Instant::now() + duration;
+    let mut buf = [0u8; 2048];
+    loop {
+        let (len, from) = match tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+            Err(_) => break,
+            Ok(Ok(received)) => received,
+            Ok(Err(e)) => {
+                warn!("SSDP scan stopped early on a receive error: {}", e);
+                break;
+            }
+        };
+        let Some((name, service)) = parse_response(&buf[..len], &search_target) else {
+            debug!("Ignoring an SSDP answer from {}", from);
+            continue;
+        };
+        debug!("Device scan found a device over SSDP: {} {:?}", name, service);
+        let entry = services.entry(name).or_default();
+        entry.port = service.port;
+        entry.scheme = service.scheme;
+        for address in service.addresses {
+            if !entry.addresses.contains(&address) {
+                entry.addresses.push(address);
+            }
+        }
+    }
+    Ok(services)
+}
+
+
+/// The supervisor in an answer to the search, None if the answer isnt for `search_target` or
+/// has no usable LOCATION
+fn parse_response(bytes: &[u8], search_target: &str) -> Option<(String, DiscoveredService)> {
+    let text = std::str::from_utf8(bytes).ok()?;
+    let mut lines = text.split("\r\n");
+    if !lines.next()?.starts_with("HTTP/1.1 200") {
+        return None;
+    }
+    let headers: HashMap<String, String> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_lowercase(), value.trim().to_string()))
+        .collect();
+    if headers.get("st").is_some_and(|st| st != search_target) {
+        return None;
+    }
+
+    let location = reqwest::Url::parse(headers.get("location")?).ok()?;
+    let scheme = match location.scheme() {
+        "http" | "https" => location.scheme().to_string(),
+        _ => return None,
+    };
+    let host = location.host_str()?.trim_start_matches('[').trim_end_matches(']').to_string();
+    let port = location.port_or_known_default()?;
+    let name = headers
+        .get(DEVICE_NAME_HEADER)
+        .filter(|n| !n.is_empty())
+        .cloned()
+        .unwrap_or_else(|| host.clone());
+    Some((name, DiscoveredService { port, scheme: Some(scheme), addresses: vec![host], ..Default::default() }))
+}
@@ -0,0 +1,189 @@
+//! # telemetry.rs
+//!
+//! OpenTelemetry tracing of the API and of the requests sent to supervisors. Every handled
+//! request gets a server span (continuing the trace of the caller if it sent a `traceparent`
+//! header), and outgoing requests sent with `send_traced` get a client span whose context is
+//! passed on to the supervisor in the W3C trace context headers.
+//!
+//! Spans are exported over OTLP/HTTP when OTEL_EXPORTER_OTLP_ENDPOINT (or
+//! OTEL_EXPORTER_OTLP_TRACES_ENDPOINT) is set, and the other standard OTEL_EXPORTER_OTLP_*
+//! variables are honored by the exporter. Without an endpoint tracing is disabled and the
+//! spans are no-ops.
+
+use std::env;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use log::{error, info};
+use once_cell::sync::OnceCell;
+use opentelemetry::global;
+use opentelemetry::propagation::{Extractor, Injector};
+use opentelemetry::trace::{FutureExt, SpanKind, Status, TraceContextExt, Tracer};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_otlp::SpanExporter;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+
+
+const TRACER_NAME: &str = "orchestrator";
+const DEFAULT_SERVICE_NAME: &str = "orchestrator";
+
+static PROVIDER: OnceCell<SdkTracerProvider> = OnceCell::new();
+
+
+/// Sets up the OTLP exporter and the trace context propagator, if an OTLP endpoint is configured
+pub fn init_tracing() {
+    let configured = ["OTEL_EXPORTER_OTLP_ENDPOINT", "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT"]
+        .iter()
+        .any(|var| env::var(var).map(|v| !v.is_empty()).unwrap_or(false));
+    if !configured {
+        info!("OTEL_EXPORTER_OTLP_ENDPOINT not set, tracing disabled.");
+        return;
+    }
+
+    let exporter = match SpanExporter::builder().with_http().build() {
+        Ok(exporter) => exporter,
+        Err(e) => {
+            error!("Failed to create the OTLP span exporter, tracing disabled: {}", e);
+            return;
+        }
+    };
+    let service_name = env::var("OTEL_SERVICE_NAME")
+        .ok()
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| DEFAULT_SERVICE_NAME.to_string());
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(service_name.clone()).build())
+        .build();
+
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    global::set_tracer_provider(provider.clone());
+    let _ = PROVIDER.set(provider);
+    info!("Tracing enabled, exporting spans of '{}' over OTLP", service_name);
+}
+
+
+/// Exports the spans still buffered, called when the server stops
+pub fn shutdown_tracing() {
+    if let Some(provider) = PROVIDER.get() {
+        if let Err(e) = provider.shutdown() {
+            error!("Failed to shut down tracing: {}", e);
+        }
+    }
+}
+
+
+/// Middleware creating a server span for each request, used with
+/// `actix_web::middleware::from_fn`. The span is named after the matched route
+/// (e.g. `GET /file/device/{device_id}`) so that requests to the same endpoint are grouped.
+pub async fn trace_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let parent = global::get_text_map_propagator(|p| p.extract(&ActixHeaderExtractor(req.headers())));
+    let method = req.method().to_string();
+    let tracer = global::tracer(TRACER_NAME);
+    let span = tracer
+        .span_builder(method.clone())
+        .with_kind(SpanKind::Server)
+        .with_attributes(vec![
+            KeyValue::new("http.request.method", method.clone()),
+            KeyValue::new("url.path", req.path().to_string()),
+        ])
+        .start_with_context(&tracer, &parent);
+    let cx = parent.with_span(span);
+
+    let result = next.call(req).with_context(cx.clone()).await;
+
+    let span = cx.span();
+    match &result {
+        Ok(res) => {
+            // Routing happens inside the app, so the route is known only after the handler
+            if let Some(route) = res.request().match_pattern() {
+                span.update_name(format!("{} {}", method, route));
+                span.set_attribute(KeyValue::new("http.route", route));
+            }
+            let status = res.status();
+            span.set_attribute(KeyValue::new("http.response.status_code", status.as_u16() as i64));
+            if status.is_server_error() {
+                span.set_status(Status::error(status.to_string()));
+            }
+        }
+        Err(e) => {
+            span.set_status(Status::error(e.to_string()));
+        }
+    }
+    span.end();
+    result
+}
+
+
+/// Sends the request in a client span named after the operation (e.g. "deploy" or
+/// "health check"), with the trace context headers added so that the supervisor can
+/// continue the trace.
+pub async fn send_traced(request: reqwest::RequestBuilder, operation: &str) -> reqwest::Result<reqwest::Response> {
+    let (client, request) = request.build_split();
+    let mut request = request?;
+    let tracer = global::tracer(TRACER_NAME);
+    let span = tracer
+        .span_builder(format!("{} {}", request.method(), operation))
+        .with_kind(SpanKind::Client)
+        .with_attributes(vec![
+            KeyValue::new("http.request.method", request.method().to_string()),
+            KeyValue::new("url.full", request.url().to_string()),
+            KeyValue::new("orchestrator.operation", operation.to_string()),
+        ])
+        .start(&tracer);
+    let cx = Context::current_with_span(span);
+    global::get_text_map_propagator(|p| p.inject_context(&cx, &mut ReqwestHeaderInjector(request.headers_mut())));
+
+    let result = client.execute(request).with_context(cx.clone()).await;
+
+    let span = cx.span();
+    match &result {
+        Ok(res) => {
+            let status = res.status();
+            span.set_attribute(KeyValue::new("http.response.status_code", status.as_u16() as i64));
+            if status.is_client_error() || status.is_server_error() {
+                span.set_status(Status::error(status.to_string()));
+            }
+        }
+        Err(e) => {
+            span.record_error(e);
+            span.set_status(Status::error(e.to_string()));
+        }
+    }
+    span.end();
+    result
+}
+
+
+// actix-web and reqwest use different versions of the http crate, so the header maps need
+// their own carriers
+
+struct ActixHeaderExtractor<'a>(&'a actix_web::http::header::HeaderMap);
+
+impl Extractor for ActixHeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+struct ReqwestHeaderInjector<'a>(&'a mut reqwest::header::HeaderMap);
+
+impl Injector for ReqwestHeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            reqwest::header::HeaderName::from_bytes(key.as_bytes()),
+            reqwest::header::HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
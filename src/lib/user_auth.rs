@@ -0,0 +1,131 @@
+//! # user_auth.rs
+//!
+//! User accounts for the management API. Users log in with `POST /auth/login` and get a JWT
+//! (HS256, signed with the JWT secret of the auth settings, and valid for their JWT lifetime),
+//! which they send as
+//! `Authorization: Bearer <token>`. The role of a user (viewer, operator or admin) gives the
+//! same permissions as the corresponding API key scope, see lib/api_auth.rs. Passwords are
+//! stored as Argon2 hashes. The first admin can be created from the initial admin of the auth
+//! settings at startup.
+
+use argon2::Argon2;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use log::{error, info, warn};
+use mongodb::bson::{doc, oid::ObjectId};
+use once_cell::sync::Lazy;
+use crate::lib::config::{Config, AuthConfig};
+use crate::lib::constants::COLL_USERS;
+use crate::lib::errors::ApiError;
+use crate::lib::mongodb::{find_one, get_collection};
+use crate::structs::users::{User, UserClaims, UserRole};
+
+
+/// Key the JWTs are signed with when JWT_SECRET isnt set. It is random for each run, so
+/// tokens stop working when the orchestrator is restarted.
+static RANDOM_JWT_KEY: Lazy<Vec<u8>> = Lazy::new(|| {
+    warn!("JWT_SECRET is not set, user tokens will be invalid after a restart");
+    format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple()).into_bytes()
+});
+
+
+/// Key the JWTs are signed with, the JWT secret of the settings if there is one
+fn jwt_key(settings: &AuthConfig) -> &[u8] {
+    match settings.jwt_secret.as_deref() {
+        Some(secret) => secret.as_bytes(),
+        None => &RANDOM_JWT_KEY,
+    }
+}
+
+
+/// Argon2 hash of the password, in the PHC string format
+pub fn hash_password(password: &str) -> Result<String, ApiError> {
+    let salt = SaltString::encode_b64(uuid::Uuid::new_v4().as_bytes())
+        .map_err(|e| ApiError::internal_error(format!("failed to create a salt: {}", e)))?;
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| ApiError::internal_error(format!("failed to hash the password: {}", e)))
+}
+
+
+/// Checks the password against a hash made by `hash_password`
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash)
+        .is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+}
+
+
+/// Signed token for the user, and when it expires
+pub fn issue_token(settings: &AuthConfig, user: &User) -> Result<(String, DateTime<Utc>), ApiError> {
+    let id = user.id.ok_or_else(|| ApiError::db("user missing _id"))?;
+    let now = Utc::now();
+    let expires_at = now + Duration::seconds(settings.jwt_ttl_s as i64);
+    let claims = UserClaims {
+        sub: id.to_hex(),
+        username: user.username.clone(),
+        role: user.role,
+        iat: now.timestamp(),
+        exp: expires_at.timestamp(),
+    };
+    let token = encode(&Header::default(), &claims, &EncodingKey::from_secret(jwt_key(settings)))
+        .map_err(|e| ApiError::internal_error(format!("failed to sign the token: {}", e)))?;
+    Ok((token, expires_at))
+}
+
+
+/// Claims of a valid, unexpired token signed by this orchestrator, None for anything else
+/// (e.g. card tokens, which are sent in the same header)
+pub fn decode_token(settings: &AuthConfig, token: &str) -> Option<UserClaims> {
+    decode::<UserClaims>(token, &DecodingKey::from_secret(jwt_key(settings)), &Validation::default())
+        .ok()
+        .map(|data| data.claims)
+}
+
+
+/// User of a token from `decode_token`, as currently saved, so that deleted users and
+/// changed roles take effect before their tokens expire
+pub async fn token_user(config: &Config, claims: &UserClaims) -> Result<User, ApiError> {
+    let id = ObjectId::parse_str(&claims.sub).map_err(|_| ApiError::unauthorized("invalid token"))?;
+    find_one::<User>(&config.database, COLL_USERS, doc! { "_id": id })
+        .await
+        .map_err(ApiError::db)?
+        .ok_or_else(|| ApiError::unauthorized("the user of the token no longer exists"))
+}
+
+
+/// Creates the initial admin user of `config` if it is set and there are no users yet
+pub async fn ensure_initial_admin(config: &Config) {
+    let (Some(username), Some(password)) = (config.auth.initial_admin_username.as_deref(), config.auth.initial_admin_password.as_deref()) else {
+        return;
+    };
+    let collection = get_collection::<User>(&config.database, COLL_USERS).await;
+    match collection.count_documents(doc! {}).await {
+        Ok(0) => {}
+        Ok(_) => return,
+        Err(e) => {
+            error!("Failed to check for existing users: {}", e);
+            return;
+        }
+    }
+    let password_hash = match hash_password(password) {
+        Ok(hash) => hash,
+        Err(e) => {
+            error!("Failed to create the initial admin: {}", e);
+            return;
+        }
+    };
+    let user = User {
+        id: None,
+        username: username.to_string(),
+        password_hash,
+        role: UserRole::Admin,
+        created_at: Utc::now(),
+        last_login_at: None,
+    };
+    match collection.insert_one(&user).await {
+        Ok(_) => info!("👤 Created the initial admin user '{}'", username),
+        Err(e) => error!("Failed to create the initial admin: {}", e),
+    }
+}
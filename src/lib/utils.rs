@@ -1,34 +1,6 @@
-use serde_json::Value;
 use crate::structs::device::{DeviceDescription, PlatformInfo, CpuInfo, MemoryInfo, OsInfo};
 use std::collections::HashMap;
 
-/// Recursively converts Extended JSON ObjectId objects {"$oid":"…"} into plain strings "…"
-/// (Mongodb returns ObjectsIds in a format that frontend doesnt know how to handle, this fixes that)
-pub fn normalize_object_ids(value: &mut Value) {
-    match value {
-        Value::Object(map) => {
-            if map.len() == 1 {
-                if let Some(v) = map.get("$oid") {
-                    if let Some(s) = v.as_str() {
-                        *value = Value::String(s.to_string());
-                        return;
-                    }
-                }
-            }
-            for v in map.values_mut() {
-                normalize_object_ids(v);
-            }
-        }
-        Value::Array(arr) => {
-            for v in arr {
-                normalize_object_ids(v);
-            }
-        }
-        _ => {}
-    }
-}
-
-
 /// Build a minimal placeholder description when a device hasn't reported one yet.
 pub fn default_device_description() -> DeviceDescription {
     DeviceDescription {
@@ -50,5 +22,44 @@ pub fn default_device_description() -> DeviceDescription {
             },
         },
         supervisor_interfaces: Vec::new(),
+        supervisor_version: None,
+    }
+}
+
+
+/// Escapes characters that have a special meaning in regular expressions
+pub fn escape_regex(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if "\\.+*?()|[]{}^$".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
     }
+    out
+}
+
+
+/// Host part of a url for an address. IPv6 literals are put in brackets (`fd00::2` becomes
+/// `[fd00::2]`, with a zone id escaped as `%25`), IPv4 addresses and host names are kept as is.
+pub fn url_host(address: &str) -> String {
+    let address = address.trim();
+    if address.starts_with('[') || !address.contains(':') {
+        return address.to_string();
+    }
+    format!("[{}]", address.replacen('%', "%25", 1))
+}
+
+
+/// Base path advertised by a supervisor (the `path` TXT property), as `/<path>` without a
+/// trailing slash. None if the supervisor is served from the root.
+pub fn base_path(path: &str) -> Option<String> {
+    let path = path.trim().trim_matches('/');
+    (!path.is_empty()).then(|| format!("/{}", path))
+}
+
+
+/// `<scheme>://<host>:<port>`, with IPv6 literals in brackets
+pub fn base_url(scheme: &str, host: &str, port: u16) -> String {
+    format!("{}://{}:{}", scheme, url_host(host), port)
 }
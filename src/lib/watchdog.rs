@@ -0,0 +1,288 @@
+//! # watchdog.rs
+//!
+//! Supervision of the background loops (health checks, device discovery, device inventory,
+//! execution input sweeper, trash purge). Each loop runs as a task on the main runtime, started by a
+//! supervisor task of its own, and reports every iteration with `heartbeat`. When a loop panics or returns, the
+//! supervisor logs why and restarts it after a backoff, which doubles with each consecutive
+//! failure from RESTART_BACKOFF_MIN up to RESTART_BACKOFF_MAX. The watchdog aborts a loop that
+//! hasnt reported an iteration within its interval plus the grace of the watchdog settings, which is taken to be
+//! stuck, and it is then restarted the same way. Liveness of the loops is reported by /readyz
+//! and /admin/loops. On shutdown the loops are stopped with `stop_loops`, and arent restarted
+//! after that.
+
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use futures::future::{BoxFuture, FutureExt};
+use log::{error, info, warn};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::Serialize;
+use tokio::task::AbortHandle;
+use crate::lib::config::WatchdogConfig;
+
+
+pub const LOOP_HEALTH_CHECKS: &str = "healthChecks";
+pub const LOOP_DISCOVERY: &str = "discovery";
+pub const LOOP_INVENTORY: &str = "inventory";
+pub const LOOP_EXECUTION_SWEEPER: &str = "executionSweeper";
+pub const LOOP_TRASH_PURGE: &str = "trashPurge";
+
+/// Wait before restarting a failed loop, doubled for each consecutive failure
+const RESTART_BACKOFF_MIN: Duration = Duration::from_secs(1);
+const RESTART_BACKOFF_MAX: Duration = Duration::from_secs(300);
+
+
+type LoopFactory = Arc<dyn Fn() -> BoxFuture<'static, ()> + Send + Sync>;
+
+struct SupervisedLoop {
+    interval: Duration,
+    supervisor: AbortHandle,
+    task: Option<AbortHandle>, // None while waiting to be restarted
+    generation: u64, // Heartbeats from tasks of earlier generations are ignored
+    started_at: DateTime<Utc>,
+    last_beat: Instant,
+    last_iteration: Option<DateTime<Utc>>,
+    iterations: u64,
+    iterated: bool, // Whether the current generation has completed an iteration
+    restarts: u32,
+    consecutive_failures: u32,
+    last_restart: Option<DateTime<Utc>>,
+    last_restart_reason: Option<String>,
+    next_restart: Option<DateTime<Utc>>,
+    stuck: Option<String>, // Why the watchdog aborted the task, reported as the reason of the restart
+}
+
+static LOOPS: Lazy<Mutex<BTreeMap<&'static str, SupervisedLoop>>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+/// Set by `start_watchdog`, so that the loops are checked by one thread only
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+tokio::task_local! {
+    /// Generation of the loop running in this task
+    static GENERATION: u64;
+}
+
+
+/// Liveness of a background loop
+#[derive(Debug, Clone, Serialize)]
+pub struct LoopStatus {
+    pub name: String,
+    pub alive: bool, // Task running and iterating within its interval plus the grace period
+    pub running: bool, // False while waiting to be restarted
+    #[serde(rename = "intervalS")]
+    pub interval_s: u64,
+    #[serde(rename = "startedAt")]
+    pub started_at: DateTime<Utc>,
+    #[serde(rename = "lastIteration")]
+    pub last_iteration: Option<DateTime<Utc>>,
+    #[serde(rename = "secondsSinceIteration")]
+    pub seconds_since_iteration: u64, // Since the task was started if there hasnt been any iterations
+    pub iterations: u64,
+    pub restarts: u32,
+    #[serde(rename = "consecutiveFailures")]
+    pub consecutive_failures: u32,
+    #[serde(rename = "lastRestart")]
+    pub last_restart: Option<DateTime<Utc>>,
+    #[serde(rename = "lastRestartReason")]
+    pub last_restart_reason: Option<String>,
+    #[serde(rename = "nextRestart")]
+    pub next_restart: Option<DateTime<Utc>>,
+}
+
+
+/// Starts the loop as a supervised task and keeps it running. `interval` is how long the loop
+/// sleeps between iterations, and `run` creates the loop, again on each restart. Needs a tokio
+/// runtime.
+pub fn supervise<F, Fut>(name: &'static str, interval: Duration, run: F)
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let factory: LoopFactory = Arc::new(move || run().boxed());
+    // Locked until inserted, so that the supervisor finds the loop
+    let mut loops = LOOPS.lock();
+    let supervisor = tokio::spawn(run_supervisor(name, factory)).abort_handle();
+    loops.insert(name, SupervisedLoop {
+        interval,
+        supervisor,
+        task: None,
+        generation: 0,
+        started_at: Utc::now(),
+        last_beat: Instant::now(),
+        last_iteration: None,
+        iterations: 0,
+        iterated: false,
+        restarts: 0,
+        consecutive_failures: 0,
+        last_restart: None,
+        last_restart_reason: None,
+        next_restart: None,
+        stuck: None,
+    });
+}
+
+
+/// Records an iteration of the loop, called by the loops themselves
+pub fn heartbeat(name: &str) {
+    let Ok(generation) = GENERATION.try_with(|g| *g) else { return };
+    let mut loops = LOOPS.lock();
+    let Some(supervised) = loops.get_mut(name) else { return };
+    if supervised.generation != generation {
+        return;
+    }
+    supervised.last_beat = Instant::now();
+    supervised.last_iteration = Some(Utc::now());
+    supervised.iterations += 1;
+    supervised.iterated = true;
+}
+
+
+/// Starts the thread checking the supervised loops at the interval of the settings
+pub fn start_watchdog(config: &WatchdogConfig) {
+    if STARTED.swap(true, Ordering::SeqCst) {
+        warn!("The watchdog was already started, keeping the earlier settings");
+        return;
+    }
+    let settings = config.clone();
+    std::thread::spawn(move || loop {
+        std::thread::sleep(Duration::from_secs(settings.interval_s));
+        check_loops(&settings);
+    });
+    info!("... Watchdog started for {} background loops", LOOPS.lock().len());
+}
+
+
+/// Stops the supervised loops at their next await point and waits until their tasks have
+/// ended, or the deadline has passed. Stopped loops are no longer supervised.
+pub async fn stop_loops(deadline: tokio::time::Instant) {
+    let loops = std::mem::take(&mut *LOOPS.lock());
+    for supervised in loops.values() {
+        supervised.supervisor.abort();
+        if let Some(task) = &supervised.task {
+            task.abort();
+        }
+    }
+    for (name, supervised) in loops {
+        let Some(task) = supervised.task else { continue };
+        while !task.is_finished() {
+            if tokio::time::Instant::now() >= deadline {
+                warn!("Background loop '{}' didnt stop before the shutdown deadline", name);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+    info!("... Background loops stopped");
+}
+
+
+/// Liveness of the supervised loops, ordered by name
+pub fn loop_statuses(settings: &WatchdogConfig) -> Vec<LoopStatus> {
+    LOOPS
+        .lock()
+        .iter()
+        .map(|(name, l)| LoopStatus {
+            name: name.to_string(),
+            alive: l.task.is_some() && l.stuck.is_none() && silence(settings, l).is_none(),
+            running: l.task.is_some(),
+            interval_s: l.interval.as_secs(),
+            started_at: l.started_at,
+            last_iteration: l.last_iteration,
+            seconds_since_iteration: l.last_beat.elapsed().as_secs(),
+            iterations: l.iterations,
+            restarts: l.restarts,
+            consecutive_failures: l.consecutive_failures,
+            last_restart: l.last_restart,
+            last_restart_reason: l.last_restart_reason.clone(),
+            next_restart: l.next_restart,
+        })
+        .collect()
+}
+
+
+/// Runs the loop and restarts it whenever it ends, until the loop is no longer supervised
+async fn run_supervisor(name: &'static str, factory: LoopFactory) {
+    loop {
+        let task = {
+            let mut loops = LOOPS.lock();
+            let Some(supervised) = loops.get_mut(name) else { return };
+            supervised.generation += 1;
+            let task = tokio::spawn(GENERATION.scope(supervised.generation, factory()));
+            supervised.task = Some(task.abort_handle());
+            supervised.started_at = Utc::now();
+            supervised.last_beat = Instant::now();
+            supervised.iterated = false;
+            supervised.next_restart = None;
+            task
+        };
+
+        let ended = match task.await {
+            Ok(()) => "returned".to_string(),
+            Err(e) if e.is_panic() => format!("panicked: {}", panic_message(e.into_panic())),
+            Err(_) => "aborted".to_string(),
+        };
+
+        let backoff = {
+            let mut loops = LOOPS.lock();
+            let Some(supervised) = loops.get_mut(name) else { return };
+            let reason = supervised.stuck.take().unwrap_or(ended);
+            // A loop that got through an iteration before failing starts the backoff over
+            supervised.consecutive_failures = if supervised.iterated { 1 } else { supervised.consecutive_failures + 1 };
+            let backoff = restart_backoff(supervised.consecutive_failures);
+            error!("❌ Background loop '{}' stopped ({}), restarting it in {} s", name, reason, backoff.as_secs());
+            supervised.task = None;
+            supervised.restarts += 1;
+            supervised.last_restart = Some(Utc::now());
+            supervised.last_restart_reason = Some(reason);
+            supervised.next_restart = chrono::Duration::from_std(backoff).ok().map(|b| Utc::now() + b);
+            backoff
+        };
+        tokio::time::sleep(backoff).await;
+    }
+}
+
+
+fn restart_backoff(consecutive_failures: u32) -> Duration {
+    let exponent = consecutive_failures.saturating_sub(1).min(16);
+    RESTART_BACKOFF_MIN.saturating_mul(1 << exponent).min(RESTART_BACKOFF_MAX)
+}
+
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+
+/// How long the loop has been without iterations past its deadline, None if its fine
+fn silence(settings: &WatchdogConfig, supervised: &SupervisedLoop) -> Option<Duration> {
+    let deadline = supervised.interval + Duration::from_secs(settings.grace_s);
+    let silent = supervised.last_beat.elapsed();
+    (silent > deadline).then_some(silent)
+}
+
+
+/// Aborts the loops that are stuck, their supervisors then restart them
+fn check_loops(settings: &WatchdogConfig) {
+    let mut loops = LOOPS.lock();
+    for (name, supervised) in loops.iter_mut() {
+        if supervised.stuck.is_some() {
+            continue;
+        }
+        let Some(task) = &supervised.task else { continue };
+        let Some(silent) = silence(settings, supervised) else { continue };
+        let reason = format!("no iteration in {} s", silent.as_secs());
+        error!("❌ Background loop '{}' is stuck ({}), aborting it", name, reason);
+        // Takes effect at the next await point of the loop
+        task.abort();
+        supervised.stuck = Some(reason);
+    }
+}
@@ -0,0 +1,192 @@
+//! # webhooks.rs
+//!
+//! Delivery of events to the webhooks registered by operators. Each event published on the
+//! event bus (see lib/events.rs) is posted as JSON to every webhook subscribed to it, signed
+//! with the secret of the webhook (`X-Webhook-Signature: sha256=<hex HMAC-SHA256 of the body>`). Failed deliveries are
+//! retried with exponential backoff, and events that still couldnt be delivered after
+//! the attempts of the webhook settings are stored as dead letters, from where they can be
+//! retried.
+
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::Utc;
+use futures::TryStreamExt;
+use hmac::{Hmac, Mac};
+use log::{debug, error, info, warn};
+use mongodb::bson::doc;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use crate::lib::config::Config;
+use crate::lib::constants::{COLL_WEBHOOKS, COLL_WEBHOOK_DEAD_LETTERS};
+use crate::lib::events::{self, EVENT_NAMES};
+use crate::lib::http_client;
+use crate::lib::mongodb::get_collection;
+use crate::lib::telemetry::send_traced;
+use crate::structs::webhooks::{Webhook, WebhookDeadLetter};
+
+
+/// Events that webhooks can subscribe to
+pub const WEBHOOK_EVENTS: &[&str] = EVENT_NAMES;
+
+/// Posts the events published on the event bus to the subscribed webhooks. The payload
+/// `data` is the event without its `type`, which is sent as `event` instead.
+pub fn listen_to_events(config: Arc<Config>) {
+    events::listen("Webhook delivery", move |event| {
+        let config = config.clone();
+        async move {
+            let mut data = match serde_json::to_value(&event) {
+                Ok(data) => data,
+                Err(e) => {
+                    error!("❌ Failed to serialize webhook event '{}': {}", event.name(), e);
+                    return;
+                }
+            };
+            if let Some(fields) = data.as_object_mut() {
+                fields.remove("type");
+            }
+            // Deliveries are retried with backoff, so they are done aside from the event listener
+            let name = event.name();
+            tokio::spawn(async move {
+                if let Err(e) = dispatch(config, name, data).await {
+                    error!("❌ Failed to dispatch webhook event '{}': {}", name, e);
+                }
+            });
+        }
+    });
+}
+
+
+/// Starts a delivery of the event to each webhook subscribed to it
+async fn dispatch(config: Arc<Config>, event: &str, data: Value) -> mongodb::error::Result<()> {
+    let coll = get_collection::<Webhook>(&config.database, COLL_WEBHOOKS).await;
+    let webhooks: Vec<Webhook> = coll
+        .find(doc! { "$or": [{ "events": { "$size": 0 } }, { "events": event }] })
+        .await?
+        .try_collect()
+        .await?;
+    if webhooks.is_empty() {
+        return Ok(());
+    }
+
+    let delivery_id = uuid::Uuid::new_v4().to_string();
+    let payload = json!({
+        "id": delivery_id,
+        "event": event,
+        "time": Utc::now(),
+        "data": data,
+    })
+    .to_string();
+    debug!("Delivering webhook event '{}' ({}) to {} webhook(s)", event, delivery_id, webhooks.len());
+    for webhook in webhooks {
+        let (config, event, delivery_id, payload) = (config.clone(), event.to_string(), delivery_id.clone(), payload.clone());
+        tokio::spawn(async move {
+            deliver_with_retries(&config, &webhook, &event, &delivery_id, &payload).await;
+        });
+    }
+    Ok(())
+}
+
+
+/// Delivers the payload, retrying with exponential backoff, and stores a dead letter if
+/// every attempt fails
+async fn deliver_with_retries(config: &Config, webhook: &Webhook, event: &str, delivery_id: &str, payload: &str) {
+    let max_attempts = config.webhooks.max_attempts;
+    let mut delay = Duration::from_millis(config.webhooks.retry_base_ms);
+    let mut last_error = String::new();
+    for attempt in 1..=max_attempts {
+        match deliver(config, webhook, event, delivery_id, payload).await {
+            Ok(()) => {
+                debug!("Webhook event '{}' delivered to {} (attempt {})", event, webhook.url, attempt);
+                return;
+            }
+            Err(e) => {
+                warn!("Webhook delivery of '{}' to {} failed (attempt {}/{}): {}", event, webhook.url, attempt, max_attempts, e);
+                last_error = e;
+            }
+        }
+        if attempt < max_attempts {
+            tokio::time::sleep(delay).await;
+            delay = delay.saturating_mul(2);
+        }
+    }
+
+    let Some(webhook_id) = webhook.id else { return };
+    let dead_letter = WebhookDeadLetter {
+        id: None,
+        webhook_id,
+        url: webhook.url.clone(),
+        event: event.to_string(),
+        delivery_id: delivery_id.to_string(),
+        payload: payload.to_string(),
+        attempts: max_attempts,
+        last_error,
+        failed_at: Utc::now(),
+    };
+    let coll = get_collection::<WebhookDeadLetter>(&config.database, COLL_WEBHOOK_DEAD_LETTERS).await;
+    match coll.insert_one(&dead_letter).await {
+        Ok(_) => error!("❌ Webhook event '{}' could not be delivered to {}, stored as a dead letter", event, webhook.url),
+        Err(e) => error!("❌ Failed to store dead letter of webhook event '{}' for {}: {}", event, webhook.url, e),
+    }
+}
+
+
+/// Sends the payload to the webhook once
+pub async fn deliver(config: &Config, webhook: &Webhook, event: &str, delivery_id: &str, payload: &str) -> Result<(), String> {
+    let request = http_client::client(&config.http_client)
+        .post(&webhook.url)
+        .timeout(Duration::from_secs(config.webhooks.timeout_s))
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header("X-Webhook-Event", event)
+        .header("X-Webhook-Delivery", delivery_id)
+        .header("X-Webhook-Signature", format!("sha256={}", sign(&webhook.secret, payload)))
+        .body(payload.to_string());
+    let response = send_traced(request, "webhook")
+        .await
+        .map_err(|e| e.to_string())?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("webhook responded with status {}", response.status()))
+    }
+}
+
+
+/// Hex encoded HMAC-SHA256 of the payload with the secret as the key
+pub fn sign(secret: &str, payload: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(payload.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+
+/// Delivers a dead letter again to its webhook (with the current secret of the webhook).
+/// The dead letter is removed if the delivery succeeds, and its error updated if not.
+pub async fn retry_dead_letter(config: &Config, dead_letter: &WebhookDeadLetter) -> Result<(), String> {
+    let webhooks = get_collection::<Webhook>(&config.database, COLL_WEBHOOKS).await;
+    let webhook = webhooks
+        .find_one(doc! { "_id": dead_letter.webhook_id })
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("webhook {} no longer exists", dead_letter.webhook_id.to_hex()))?;
+
+    let coll = get_collection::<WebhookDeadLetter>(&config.database, COLL_WEBHOOK_DEAD_LETTERS).await;
+    let Some(id) = dead_letter.id else {
+        return Err("dead letter missing _id".to_string());
+    };
+    match deliver(config, &webhook, &dead_letter.event, &dead_letter.delivery_id, &dead_letter.payload).await {
+        Ok(()) => {
+            info!("Dead letter of webhook event '{}' delivered to {}", dead_letter.event, webhook.url);
+            coll.delete_one(doc! { "_id": id }).await.map_err(|e| e.to_string())?;
+            Ok(())
+        }
+        Err(e) => {
+            coll.update_one(doc! { "_id": id }, doc! {
+                "$set": { "lastError": &e, "failedAt": mongodb::bson::DateTime::now() },
+                "$inc": { "attempts": 1 },
+            })
+            .await
+            .map_err(|e| e.to_string())?;
+            Err(e)
+        }
+    }
+}
@@ -6,14 +6,30 @@
 //! Advertising in this case means the orchestrator advertises itself to itself,
 //! and browsing means it periodically gets all available supervisors (and itself)
 //! to populate the device list.
+//!
+//! Both are done over IPv4 and IPv6: services are resolved to their A and AAAA records, and
+//! the orchestrator advertises a routable IPv6 address of the host (if it has one) in the
+//! `address6` TXT property next to `address`.
+//!
+//! The orchestrator finds its own advertisement when browsing. It is recognized by the
+//! instance id advertised in the `instance` TXT property, registered as a device only at its
+//! non-loopback addresses, and not registered to itself.
+//!
+//! Browsing is the mDNS backend of device discovery (see lib/discovery.rs). The mDNS browser
+//! only reports services that appear, not the goodbye packets or expired records of services
+//! that go away, so devices that disappear are noticed by comparing scans there.
 
 
-use log::{error, debug};
+use log::{error, debug, warn};
 use local_ip_address;
 use std::time::{Duration, Instant};
-use std::env;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use serde::Serialize;
-use chrono::Utc;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
 use zeroconf::prelude::*;
 use zeroconf::{
     MdnsBrowser, 
@@ -21,21 +37,20 @@ use zeroconf::{
     MdnsService, 
     TxtRecord
 };
-use crate::lib::constants::{
-    DEFAULT_URL_SCHEME,
-    ORCHESTRATOR_DEFAULT_NAME,
-    PUBLIC_PORT,
-    DEVICE_SCAN_DURATION_S,
-    DEVICE_SCAN_INTERVAL_S
-};
-use crate::api::device::process_discovered_devices;
-use crate::structs::device::{
-    DeviceCommunication,
-    DeviceDoc,
-    StatusEnum,
-    StatusLogEntry,
-};
-use crate::lib::utils::default_device_description;
+use futures::future::BoxFuture;
+use crate::lib::config::ServerConfig;
+use crate::lib::discovery::{DiscoveredService, DiscoveryBackend, BACKEND_MDNS};
+use crate::lib::shutdown;
+
+
+/// Whether the orchestrator is currently advertised over mDNS
+static ADVERTISING: AtomicBool = AtomicBool::new(false);
+
+/// Id of this orchestrator process, advertised so that browsing can tell it apart from others
+static INSTANCE_ID: Lazy<String> = Lazy::new(|| uuid::Uuid::new_v4().to_string());
+
+/// TXT property with the instance id
+const INSTANCE_TXT_KEY: &str = "instance";
 
 
 /// Represents a service that is advertised on the network.
@@ -55,16 +70,14 @@ pub struct WebthingZeroconf {
 }
 
 impl WebthingZeroconf {
-    /// Constructs a new service representation using env vars or defaults.
+    /// Constructs a new service representation from the server settings.
     ///
-    /// Populates host and port using `get_listening_address()`, reads environment variables
-    /// like `PREFERRED_URL_SCHEME` and `ORCHESTRATOR_NAME`, and sets standard `_webthing._tcp`
-    /// service type.
-    pub fn new() -> Self {
-        let (host, port) = get_listening_address();
-        let preferred_url_scheme = env::var("PREFERRED_URL_SCHEME")
-            .unwrap_or_else(|_| DEFAULT_URL_SCHEME.to_string());
-        let tls_flag = if preferred_url_scheme.to_lowercase() == "https" {
+    /// Populates host and port using `get_listening_address()`, the tls flag from the url scheme
+    /// and the service name from the name of the server settings, and sets standard
+    /// `_webthing._tcp` service type.
+    pub fn new(server: &ServerConfig) -> Self {
+        let (host, port) = get_listening_address(server);
+        let tls_flag = if server.url_scheme == "https" {
             "1"
         } else {
             "0"
@@ -72,14 +85,18 @@ impl WebthingZeroconf {
 
         let service_type = "webthing".to_string();
         let service_protocol = "tcp".to_string();
-        let service_name = env::var("ORCHESTRATOR_NAME")
-            .unwrap_or_else(|_| ORCHESTRATOR_DEFAULT_NAME.to_string());
+        let service_name = server.name.clone();
 
-        let properties = vec![
+        let mut properties = vec![
             ("path".to_string(), "/".to_string()),
             ("tls".to_string(), tls_flag.to_string()),
             ("address".to_string(), host.clone()),
+            (INSTANCE_TXT_KEY.to_string(), instance_id().to_string()),
         ];
+        // Lets supervisors on IPv6 networks reach the orchestrator too
+        if let Some(address6) = advertised_ipv6().filter(|a| *a != host) {
+            properties.push(("address6".to_string(), address6));
+        }
         WebthingZeroconf {
             service_name,
             service_type,
@@ -107,60 +124,144 @@ pub struct ZeroconfRegistrationData<'a> {
 
 
 /// Determines the IP address and port this orchestrator instance is bound to.
-/// Defaults to 127.0.0.1 and port 3000
-pub fn get_listening_address() -> (String, u16) {
-    let host = local_ip_address::local_ip()
+/// The address is picked with `advertised_ip`, and the port is the one of the server settings.
+pub fn get_listening_address(server: &ServerConfig) -> (String, u16) {
+    (advertised_ip(server), server.port)
+}
+
+
+/// Picks the IP address the orchestrator advertises itself with. Goes through
+/// the advertise addresses of the server settings in order, where each entry is either an interface name
+/// (its first IPv4 address is preferred) or an IP address assigned to this host. Falls back
+/// to the address the server is bound to (BIND_HOST) if it is a single address, and otherwise
+/// to the default local IP if none of them are found.
+pub fn advertised_ip(server: &ServerConfig) -> String {
+    let fallback = || match server.bind_host.parse::<IpAddr>() {
+        Ok(ip) if !ip.is_unspecified() && !ip.is_loopback() => ip.to_string(),
+        _ => local_ip_address::local_ip()
             .map(|ip| ip.to_string())
-            .unwrap_or_else(|_| "127.0.0.1".to_string());
-    let port_str = env::var("PUBLIC_PORT")
-        .unwrap_or_else(|_| PUBLIC_PORT.to_string());
-    let port: u16 = port_str.parse().unwrap_or(PUBLIC_PORT);
-    (host, port)
+            .unwrap_or_else(|_| "127.0.0.1".to_string()),
+    };
+    if server.advertise_addresses.is_empty() {
+        return fallback();
+    }
+
+    let interfaces = match local_ip_address::list_afinet_netifas() {
+        Ok(i) => i,
+        Err(e) => {
+            warn!("Failed to list network interfaces, using default address: {}", e);
+            return fallback();
+        }
+    };
+    for wanted in server.advertise_addresses.iter() {
+        if let Ok(ip) = wanted.parse::<IpAddr>() {
+            if interfaces.iter().any(|(_, addr)| *addr == ip) {
+                return ip.to_string();
+            }
+            continue;
+        }
+        let mut addrs: Vec<IpAddr> = interfaces
+            .iter()
+            .filter(|(name, _)| name == wanted)
+            .map(|(_, addr)| *addr)
+            .collect();
+        addrs.sort_by_key(|a| !a.is_ipv4());
+        if let Some(ip) = addrs.first() {
+            return ip.to_string();
+        }
+    }
+
+    let ip = fallback();
+    warn!(
+        "None of the advertised addresses {:?} were found on this host, using {}",
+        server.advertise_addresses, ip
+    );
+    ip
+}
+
+
+/// Unique id of this orchestrator instance, advertised in the `instance` TXT property
+pub fn instance_id() -> &'static str {
+    INSTANCE_ID.as_str()
+}
+
+
+/// Routable IPv6 address of this host, advertised next to the address of `advertised_ip`. None
+/// if the host has only loopback or link-local IPv6 addresses.
+pub fn advertised_ipv6() -> Option<String> {
+    let interfaces = local_ip_address::list_afinet_netifas().ok()?;
+    interfaces.into_iter().find_map(|(_, addr)| match addr {
+        IpAddr::V6(ip) if !ip.is_loopback() && !ip.is_unspecified() && !is_link_local_v6(&ip) => Some(ip.to_string()),
+        _ => None,
+    })
+}
+
+
+/// Hostname or address supervisors should use to reach the orchestrator: the public host of
+/// the server settings (PUBLIC_HOST) if set, otherwise the advertised IP address.
+pub fn public_host(server: &ServerConfig) -> String {
+    server.public_host.clone().unwrap_or_else(|| advertised_ip(server))
 }
 
 
-/// Runs a single scan for new devices, and saves them to database if it finds any.
-pub async fn run_single_mdns_scan(scan_duration_secs: u64) -> zeroconf::Result<()> {
+/// Device discovery over mDNS. The mDNS event loop blocks, so the scan is run on a blocking
+/// thread of the runtime. The service is resolved once per network interface and protocol, so
+/// the addresses of all of them are collected, IPv4 first.
+pub struct MdnsBackend;
+
+impl DiscoveryBackend for MdnsBackend {
+    fn name(&self) -> &'static str {
+        BACKEND_MDNS
+    }
+
+    fn scan(&self, duration: Duration) -> BoxFuture<'static, Result<HashMap<String, DiscoveredService>, String>> {
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || scan_blocking(duration))
+                .await
+                .map_err(|e| format!("device scan failed: {}", e))?
+                .map_err(|e| e.to_string())
+        })
+    }
+}
+
+
+fn scan_blocking(scan_duration: Duration) -> zeroconf::Result<HashMap<String, DiscoveredService>> {
     let service_type = ServiceType::new("webthing", "tcp").unwrap();
     let mut browser = MdnsBrowser::new(service_type);
+    let services: Arc<Mutex<HashMap<String, DiscoveredService>>> = Arc::new(Mutex::new(HashMap::new()));
 
+    let found = services.clone();
     browser.set_service_discovered_callback(Box::new(move |result, _| {
         if let Ok(service) = result {
             debug!("Device scan found a device: {:?}", service);
-            tokio::spawn(async move {
-                let name = service.name().to_string();
-                let port = *service.port();
-                let addresses = vec![service.address().clone()];
+            let name = service.name().to_string();
+            let address = service.address().trim().to_string();
 
-                if addresses.is_empty() {
-                    return;
-                }
-
-                if name == "orchestrator" && addresses[0] == "127.0.0.1" {
-                    // Special case to prevent orchestrator detecting itself twice.
-                    // TODO: Find a smarter way to prevent this
-                    return;
-                }
+            // This orchestrator is recognized by its instance id whatever its name and addresses
+            // are, and kept only at addresses other devices can reach it at
+            let own_instance = service.txt().as_ref().and_then(|txt| txt.get(INSTANCE_TXT_KEY)).as_deref() == Some(instance_id());
+            if own_instance && address.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback()) {
+                return;
+            }
+            if !is_usable_address(&address) {
+                debug!("Ignoring address '{}' of discovered device '{}'", address, name);
+                return;
+            }
 
-                let device = DeviceDoc {
-                    id: None,
-                    name,
-                    communication: DeviceCommunication { addresses, port },
-                    description: default_device_description(),
-                    status: StatusEnum::Active,
-                    ok_health_check_count: 0,
-                    failed_health_check_count: 0,
-                    status_log: Some(vec![StatusLogEntry {
-                        status: StatusEnum::Active,
-                        time: Utc::now(),
-                    }]),
-                    health: None,
-                };
-
-                let devices = vec![device];
-                let _ = process_discovered_devices(devices).await;
-            });
-            
+            let mut services = found.lock();
+            let entry = services.entry(name).or_default();
+            entry.port = *service.port();
+            entry.own_instance |= own_instance;
+            if let Some(scheme) = scheme_from_txt(service.txt().as_ref()) {
+                entry.scheme = Some(scheme);
+            }
+            if let Some(txt) = service.txt() {
+                entry.properties.extend(txt.iter());
+            }
+            if !entry.addresses.contains(&address) {
+                entry.addresses.push(address);
+                entry.addresses.sort_by_key(|a| a.contains(':')); // IPv4 first, the sort is stable
+            }
         } else {
             error!("❌ Discovery error.");
         }
@@ -175,23 +276,52 @@ pub async fn run_single_mdns_scan(scan_duration_secs: u64) -> zeroconf::Result<(
     };
 
     let start = Instant::now();
-    while start.elapsed() < Duration::from_secs(scan_duration_secs) {
+    while start.elapsed() < scan_duration {
         if let Err(e) = event_loop.poll(Duration::from_millis(100)) {
             error!("❌ Poll error: {:?}", e);
         }
     }
-    Ok(())
+    let found = std::mem::take(&mut *services.lock());
+    Ok(found)
 }
 
 
-/// Starts an endless loop for continously scanning for new devices with
-/// predefined intervals
-pub async fn browse_services() -> zeroconf::Result<()> {
-    loop {
-        // Run a single scan and sleep for a predefined time before next scan
-        let _ = run_single_mdns_scan(*DEVICE_SCAN_DURATION_S).await;
-        tokio::time::sleep(Duration::from_secs(*DEVICE_SCAN_INTERVAL_S)).await;
-    };
+/// Whether an advertised address can be used in urls. Link-local IPv6 addresses need the
+/// interface they were found on, which isnt known, so they are left out.
+fn is_usable_address(address: &str) -> bool {
+    match address.parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) => !is_link_local_v6(&ip),
+        Ok(IpAddr::V4(_)) => true,
+        Err(_) => !address.is_empty() && !address.contains('%'),
+    }
+}
+
+
+/// Whether the address is a link-local IPv6 address (fe80::/10)
+fn is_link_local_v6(ip: &Ipv6Addr) -> bool {
+    (ip.segments()[0] & 0xffc0) == 0xfe80
+}
+
+
+/// Url scheme advertised in the `tls` TXT property of a supervisor, None if it advertises none
+/// (the scheme of the device is then the default device scheme of the configuration)
+fn scheme_from_txt(txt: Option<&TxtRecord>) -> Option<String> {
+    let tls = txt?.get("tls")?;
+    match tls.trim().to_lowercase().as_str() {
+        "1" | "true" | "yes" => Some("https".to_string()),
+        "0" | "false" | "no" => Some("http".to_string()),
+        other => {
+            warn!("Ignoring unknown tls TXT property '{}' of a discovered device", other);
+            None
+        }
+    }
+}
+
+
+/// Whether the orchestrator is advertised over mDNS, false until the registration has been
+/// confirmed and after the advertisement has stopped
+pub fn is_advertising() -> bool {
+    ADVERTISING.load(Ordering::Relaxed)
 }
 
 
@@ -211,15 +341,25 @@ pub fn register_service(zc: WebthingZeroconf) -> anyhow::Result<()> {
         service.set_txt_record(txt_record);
 
         service.set_registered_callback(Box::new(|r, _| {
-            if let Ok(svc) = r {
-                debug!("✅ Orchestrator responded to mDNS query with: {:?}", svc);
+            match r {
+                Ok(svc) => {
+                    ADVERTISING.store(true, Ordering::Relaxed);
+                    debug!("✅ Orchestrator responded to mDNS query with: {:?}", svc);
+                }
+                Err(e) => error!("❌ mDNS advertisement failed: {:?}", e),
             }
         }));
 
+        // The service is unregistered when it is dropped on shutdown
         let event_loop = service.register().unwrap();
-        loop {
-            event_loop.poll(Duration::from_secs(1)).unwrap();
+        while !shutdown::is_shutting_down() {
+            if let Err(e) = event_loop.poll(Duration::from_secs(1)) {
+                error!("❌ mDNS advertisement stopped on a poll error: {:?}", e);
+                break;
+            }
         }
+        ADVERTISING.store(false, Ordering::Relaxed);
+        debug!("mDNS advertisement stopped");
     });
     Ok(())
 }
\ No newline at end of file
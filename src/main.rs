@@ -1,8 +1,9 @@
 use std::net::SocketAddr;
-use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use std::sync::Arc;
+use std::time::Duration;
+use actix_web::{web, App, HttpServer};
 use orchestrator::lib::constants::COLL_LOGS;
 use orchestrator::lib::mongodb::get_collection;
-use serde_json::json;
 use actix_cors::Cors;
 use orchestrator::api::device::{
     wasmiot_device_description, 
@@ -12,35 +13,57 @@ use orchestrator::api::device::{
     reset_device_discovery,
     get_all_devices,
     get_device_by_name,
+    get_device_deployments,
     delete_all_devices,
     delete_device_by_name,
-    register_device
+    register_device,
+    register_devices_batch,
+    update_device,
+    probe_device_interfaces,
+    push_device_health
 };
+use orchestrator::api::health_history::get_health_history;
+use orchestrator::api::device_stats::get_device_stats;
+use orchestrator::api::device_drain::{drain_device, undrain_device};
+use orchestrator::api::module_run::run_module_function;
+use orchestrator::api::card_tokens::{create_card_token, delete_card_token, get_card_tokens};
 use orchestrator::api::logs::{
     post_supervisor_log, 
-    get_supervisor_logs
+    get_supervisor_logs,
+    export_supervisor_logs
 };
 use orchestrator::api::data_source_cards::{
     get_data_source_card, 
     create_data_source_card,
     delete_all_data_source_cards,
-    delete_data_source_card_by_nodeid
+    delete_data_source_card_by_nodeid,
+    update_data_source_card,
+    get_data_source_card_by_id
 };
 use orchestrator::api::node_cards::{
     create_node_card, 
+    create_node_cards_bulk,
     get_node_cards, 
     delete_all_node_cards, 
-    delete_node_card_by_id
+    delete_node_card_by_id,
+    update_node_card,
+    get_node_card_by_id,
+    generate_node_card
 };
 use orchestrator::api::zones_and_risk_levels::{
     parse_zones_and_risk_levels, 
     get_zones_and_risk_levels, 
-    delete_all_zones_and_risk_levels
+    delete_all_zones_and_risk_levels,
+    get_zone,
+    update_zone,
+    delete_zone
 };
 use orchestrator::api::module::{
     create_module,
     delete_all_modules,
     delete_module_by_id,
+    get_module_usage,
+    update_module_metadata,
     get_all_modules,
     get_module_by_id,
     describe_module,
@@ -52,7 +75,9 @@ use orchestrator::api::module_cards::{
     create_module_card, 
     get_module_cards,
     delete_all_module_cards, 
-    delete_module_card_by_id
+    delete_module_card_by_id,
+    update_module_card,
+    get_module_card_by_id
 };
 use orchestrator::api::deployment::{
     get_deployments,
@@ -61,33 +86,73 @@ use orchestrator::api::deployment::{
     update_deployment,
     delete_deployments,
     delete_deployment,
-    http_deploy
+    http_deploy,
+    update_deployment_config
+};
+use orchestrator::api::execution::{
+    execute,
+    run_execution_input_sweeper_loop,
+    get_execution_queue,
+    get_execution_logs,
+    get_queued_execution,
+    update_queued_execution,
+    delete_queued_execution,
+    cancel_execution,
+    rerun_execution,
+    post_step_result,
+    post_execution_result
+};
+use orchestrator::api::execution_results::{get_execution_result, get_execution_results};
+use orchestrator::api::execution_outputs::{
+    upload_execution_outputs,
+    get_execution_outputs,
+    get_execution_output_file,
+    delete_execution_outputs
+};
+use orchestrator::api::storage::get_storage_usage;
+use orchestrator::api::trash::{get_trash, restore_trash_entry, run_trash_purge_loop};
+use orchestrator::api::outbound::get_outbound_stats;
+use orchestrator::api::watchdog::{get_liveness, get_loop_stats, get_readiness};
+use orchestrator::api::events::get_event_stream;
+use orchestrator::api::audit_log::get_audit_log;
+use orchestrator::api::api_keys::{create_api_key, get_api_keys, revoke_api_key};
+use orchestrator::api::users::{create_user, delete_user, get_current_user, get_users, login, update_user};
+use orchestrator::api::stats::{get_dashboard, get_stats};
+use orchestrator::api::webhooks::{
+    create_webhook, delete_webhook, delete_webhook_dead_letter, get_webhook_dead_letters, get_webhooks, retry_webhook_dead_letter
 };
-use orchestrator::api::execution::execute;
 use orchestrator::api::deployment_certificates::{
     delete_all_deployment_certificates,
-    delete_deployment_certificate,
+    delete_deployment_certificates_of_deployment,
+    explain_policy,
+    get_deployment_certificate,
     get_deployment_certificates
 };
+use orchestrator::api::revalidation::revalidate_all_deployments;
 use orchestrator::lib::zeroconf;
+use orchestrator::lib::discovery;
+use orchestrator::lib::watchdog;
+use orchestrator::lib::shutdown;
+use orchestrator::lib::connections;
 use log::{error, debug, info};
-use actix_web::middleware::NormalizePath;
+use actix_web::middleware::{from_fn, NormalizePath};
+use orchestrator::lib::telemetry::{shutdown_tracing, trace_requests};
+use orchestrator::lib::request_id::{assign_request_id, format_log_record};
+use orchestrator::lib::audit_log::audit_requests;
+use orchestrator::lib::idempotency::idempotent_requests;
+use orchestrator::lib::api_auth::require_credentials;
+use orchestrator::lib::log_forwarding::{init_logging, start_log_forwarding};
+use orchestrator::lib::config::{Cli, Config};
+use clap::Parser;
 use orchestrator::lib::initializer::{
     handle_orchestrator_export,
     handle_orchestrator_import,
+    handle_snapshot_diff,
     add_initial_data
 };
 use orchestrator::api::ws_logs::{run_ws_logs_server};
 use orchestrator::structs::logs::SupervisorLog;
 
-// Placeholder handler
-async fn placeholder(req: HttpRequest) -> impl Responder {
-    let match_name = req.match_name().unwrap_or("<no match name>");
-    let match_pattern = req.match_pattern().unwrap_or("<no match pattern>".to_string());
-    debug!("{}, {}, {}", req.full_url().as_str(), match_name, match_pattern);
-    HttpResponse::Ok().json(json!([]))
-}
-
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
 
@@ -98,31 +163,60 @@ async fn main() -> std::io::Result<()> {
         Ok(path) => println!("... Loaded .env from {:?}", path),
         Err(err) => println!("Could not load .env file: {:?}", err),
     }
-    let port: u16 = std::env::var("PUBLIC_PORT")
-        .unwrap_or(orchestrator::lib::constants::PUBLIC_PORT.to_string())
-        .parse()
-        .expect("PUBLIC_PORT must be a valid u16!");
-
-    // Initialize logging with default level = info (unless overridden by env)
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
-
-    // Initialize the database with data from init folder, if init folder exists and AUTO_INITIALIZE env var is set to true
-    let initialize = std::env::var("AUTO_INITIALIZE").unwrap_or_else(|_| "false".to_string());
-    if initialize.to_ascii_lowercase() == "true" {
-        if let Err(e) = add_initial_data().await { error!("Initialization failed: {:?}", e); }
+
+    // Load the configuration from the file, env and command line flags, and stop if it is invalid
+    let cli = Cli::parse();
+    let config = match Config::load(&cli) {
+        Ok(config) => Arc::new(config),
+        Err(errors) => {
+            eprintln!("Invalid configuration:");
+            for e in errors {
+                eprintln!("  - {}", e);
+            }
+            std::process::exit(2);
+        }
+    };
+
+    // Initialize logging with default level = info (unless overridden by env), with the id of the request being handled in each line
+    // Records at the forward level of the log settings or above are also saved along with the supervisor logs
+    let mut log_builder = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"));
+    log_builder.format(format_log_record);
+    init_logging(log_builder, &config.logs);
+    start_log_forwarding(&config);
+
+    // Export traces over OTLP if OTEL_EXPORTER_OTLP_ENDPOINT is set
+    orchestrator::lib::telemetry::init_tracing();
+    if let Some(url) = &config.deployments.policy_opa_url {
+        info!("Using OPA policy at {} for deployment validation", url);
+    }
+
+    // Create the unique and dateReceived indexes, before anything is written to the database
+    orchestrator::lib::indexes::ensure_indexes(&config).await;
+    orchestrator::api::health_history::ensure_health_history_collection(&config).await;
+
+    // Create the initial admin user of the auth settings if there are no users
+    orchestrator::lib::user_auth::ensure_initial_admin(&config).await;
+
+    // Initialize the database with data from the init folder, if it exists and autoInitialize of the storage settings is on
+    if config.storage.auto_initialize {
+        if let Err(e) = add_initial_data(&config).await { error!("Initialization failed: {:?}", e); }
     } else {
         info!("Skipping automatic initialization from init folder.");
     }
 
-    // Use websockets if WASMIOT_USE_WEB_SOCKETS env var is set to true
-    let use_ws = std::env::var("WASMIOT_USE_WEB_SOCKETS")
-        .ok()
-        .map(|v| v == "true")
-        .unwrap_or(false);
-    let ws_port = std::env::var("WASMIOT_WEB_SOCKET_PORT").unwrap_or("3001".to_string());
-    if use_ws {
-        let logs_coll = get_collection::<SupervisorLog>(COLL_LOGS).await;
-        let ws_addr: SocketAddr = format!("0.0.0.0:{}",ws_port).parse().unwrap();
+    // Subscribe webhooks, log escalation and the orchestrator log to the internal event bus,
+    // before any of the subsystems publishing events are started
+    orchestrator::lib::webhooks::listen_to_events(config.clone());
+    orchestrator::lib::log_escalation::listen_to_events(config.clone());
+    orchestrator::lib::events::log_events();
+
+    // Repair or flag modules whose files are missing, and remove files left behind by failed uploads
+    orchestrator::api::module::check_module_consistency(&config).await;
+
+    // Stream supervisor logs over websockets if webSocket of the log settings is on
+    if config.logs.web_socket {
+        let logs_coll = get_collection::<SupervisorLog>(&config.database, COLL_LOGS).await;
+        let ws_addr = SocketAddr::from(([0, 0, 0, 0], config.logs.web_socket_port));
         tokio::spawn(async move {
             if let Err(e) = run_ws_logs_server(ws_addr, logs_coll).await {
                 error!("WebSocket server failed: {e:?}");
@@ -130,60 +224,135 @@ async fn main() -> std::io::Result<()> {
         });
     }
 
-    // Start mdns browser to start polling for available supervisors
-    std::thread::spawn(|| {
-        let rt = tokio::runtime::Runtime::new().unwrap();
-        let _ = rt.block_on(zeroconf::browse_services());
-    });
+    // Start polling for available supervisors with the discovery backends (mDNS, SSDP), kept running by the watchdog
+    watchdog::supervise(
+        watchdog::LOOP_DISCOVERY,
+        Duration::from_secs(config.discovery.scan_duration_s + config.discovery.scan_interval_s),
+        {
+            let config = config.clone();
+            move || discovery::browse_services(config.clone())
+        },
+    );
 
     // Start advertising orchestrator to itself via mdns
-    let zc = zeroconf::WebthingZeroconf::new();
+    let zc = zeroconf::WebthingZeroconf::new(&config.server);
     if let Err(e) = zeroconf::register_service(zc) {
         error!("Failed to start mDNS advertisement: {}", e);
     } else {
         debug!("Mdns advertisement started succesfully.");
     }
 
+    // Register the supervisors of the static device inventory, reloaded when the file changes
+    watchdog::supervise(
+        watchdog::LOOP_INVENTORY,
+        Duration::from_secs(config.discovery.inventory_poll_s),
+        {
+            let config = config.clone();
+            move || orchestrator::lib::inventory::run_inventory_loop(config.clone())
+        },
+    );
+
     info!("... Device discovery setup done.");
 
-    // Start a separate loop to perform continous healthchecks on known devices
-    std::thread::spawn(|| {
-        let rt = tokio::runtime::Runtime::new().unwrap();
-        rt.block_on(run_health_check_loop());
-    });
+    // Start a supervised task to perform continous healthchecks on known devices
+    watchdog::supervise(
+        watchdog::LOOP_HEALTH_CHECKS,
+        Duration::from_secs(config.health_checks.interval_s),
+        {
+            let config = config.clone();
+            move || run_health_check_loop(config.clone())
+        },
+    );
 
     info!("... Healthcheck loop started");
 
+    // Start a supervised task that removes execution input files left behind by unfinished executions
+    watchdog::supervise(
+        watchdog::LOOP_EXECUTION_SWEEPER,
+        Duration::from_secs(config.execution.input_sweep_interval_s),
+        {
+            let config = config.clone();
+            move || run_execution_input_sweeper_loop(config.clone())
+        },
+    );
+
+    info!("... Execution input sweeper started");
+
+    // Start a supervised task that purges deleted modules and deployments once their retention has passed
+    watchdog::supervise(
+        watchdog::LOOP_TRASH_PURGE,
+        Duration::from_secs(config.storage.trash_purge_interval_s),
+        {
+            let config = config.clone();
+            move || run_trash_purge_loop(config.clone())
+        },
+    );
+
+    info!("... Trash purge started");
+
+    // Abort the loops above if they get stuck, their supervisors restart them as they do after panics
+    watchdog::start_watchdog(&config.watchdog);
+
     info!("✅ Initialization tasks done, starting server ...\n");
 
-    HttpServer::new(move || {
+    let server_config = config.server.clone();
+    let json_limit = server_config.json_limit_bytes;
+    let payload_limit = server_config.payload_limit_bytes;
+    let form_limit = server_config.form_limit_bytes;
+    let app_config = web::Data::from(config);
+    let mut server = HttpServer::new(move || {
         App::new()
-            // Add cors and a logger
+            .app_data(app_config.clone())
+            .app_data(web::JsonConfig::default().limit(json_limit))
+            .app_data(web::PayloadConfig::new(payload_limit))
+            .app_data(web::FormConfig::default().limit(form_limit))
+            // Add a logger
             .wrap(
-                Cors::default()
-                    .allow_any_origin()
-                    .allow_any_method()
-                    .allow_any_header()
-                    .max_age(3600)
+                actix_web::middleware::Logger::default()
             )
             .wrap(
-                actix_web::middleware::Logger::default()
+                from_fn(require_credentials)
             )
             .wrap(
                 NormalizePath::trim()
             )
+            .wrap(
+                from_fn(audit_requests)
+            )
+            .wrap(
+                from_fn(trace_requests)
+            )
+            .wrap(
+                from_fn(assign_request_id)
+            )
+            // Cors is added last so that it wraps everything else, and the responses the
+            // middlewares above return early (401 and 403 included) get its headers too
+            .wrap(
+                Cors::default()
+                    .allow_any_origin()
+                    .allow_any_method()
+                    .allow_any_header()
+                    .expose_headers(["ETag", "X-Total-Count", "X-Request-Id", "Idempotent-Replayed"]) // Let the frontend read cache tags, list total counts, request ids and replayed responses
+                    .max_age(3600)
+            )
 
             // Basic routes related to device information and health status
             // Status of implementations:
             // ✅ GET /.well-known/wasmiot-device-description
             // ✅ GET /.well-known/wot-thing-description
             // ✅ GET /health
+            // ✅ GET /healthz
+            // ✅ GET /readyz
             .service(web::resource("/.well-known/wasmiot-device-description").name("/.well-known/wasmiot-device-description")
                 .route(web::get().to(wasmiot_device_description))) // Get device description
             .service(web::resource("/.well-known/wot-thing-description").name("/.well-known/wot-thing-description")
                 .route(web::get().to(thingi_description))) // Get device wot description (doesnt appear to be implemented in original)
             .service(web::resource("/health").name("/health")
                 .route(web::get().to(thingi_health))) // Get device current health
+            .service(web::resource("/healthz").name("/healthz")
+                .route(web::get().to(get_liveness))) // Liveness probe of the orchestrator process (Doesnt exist in original version)
+            .service(web::resource("/readyz").name("/readyz")
+                .route(web::get().to(get_readiness))) // Readiness probe with the status of MongoDB, mDNS, the background loops and the websocket server, 503 if any is down (Doesnt exist in original version)
 
             // Device related routes (file: routes/device)
             // Status of implementations:
@@ -191,26 +360,64 @@ async fn main() -> std::io::Result<()> {
             // ✅ DELETE /file/device
             // ✅ GET /file/device/{device_id}
             // ✅ DELETE /file/device/{device_id}
+            // ✅ PATCH /file/device/{device_id}
             // ✅ POST /file/device/discovery/reset
             // ✅ POST /file/device/discovery/register
+            // ✅ POST /file/device/discovery/register/batch
+            // ✅ POST /file/device/{device_id}/interfaces/probe
+            // ✅ POST /file/device/{device_id}/health
+            // ✅ GET /file/device/{device_id}/health/history
+            // ✅ GET /file/device/{device_id}/stats
+            // ✅ GET /file/device/{device_id}/deployments
+            // ✅ POST /file/device/{device_id}/drain
+            // ✅ DELETE /file/device/{device_id}/drain
+            // ✅ POST /file/device/{device_name}/outputs/{deployment_id}
+            // ✅ GET /file/device/{device_name}/outputs/{deployment_id}
+            // ✅ DELETE /file/device/{device_name}/outputs/{deployment_id}
+            // ✅ GET /file/device/{device_name}/outputs/{deployment_id}/{output_id}
             .service(web::resource("/file/device").name("/file/device")
-                .route(web::get().to(get_all_devices)) // Get all devices
-                .route(web::delete().to(delete_all_devices))) // Delete all devices
+                .route(web::get().to(get_all_devices)) // Get all devices, filterable by status, namePrefix, zone and interface, with sort, limit and offset
+                .route(web::delete().to(delete_all_devices))) // Delete all devices, or the ones listed in names
             .service(web::resource("/file/device/{device_name}").name("/file/device/{device_name}")
                 .route(web::get().to(get_device_by_name)) // Get device info on specific device. (Doesnt exist in original.)
-                .route(web::delete().to(delete_device_by_name))) // Delete a specific device. (Doesnt exist in original.)
+                .route(web::patch().to(update_device)) // Rename a device, moving the logs, outputs and cards that refer to it by name (Doesnt exist in original version)
+                .route(web::delete().to(delete_device_by_name))) // Delete a specific device, 409 if active deployments use it unless cascade=true. (Doesnt exist in original.)
             .service(web::resource("/file/device/discovery/reset").name("/file/device/discovery/reset")
                 .route(web::post().to(reset_device_discovery))) // Forces the start of a new device scan without waiting for the next one (they happen at regular intervals)
             .service(web::resource("/file/device/discovery/register").name("/file/device/discovery/register")
                 .route(web::post().to(register_device))) // Supervisors can force device registration through this endpoint
+            .service(web::resource("/file/device/discovery/register/batch").name("/file/device/discovery/register/batch")
+                .route(web::post().to(register_devices_batch))) // Registers an array of devices, with the outcome per device (Doesnt exist in original version)
+            .service(web::resource("/file/device/{device_name}/health").name("/file/device/{device_name}/health")
+                .route(web::post().to(push_device_health))) // Health report pushed by the supervisor of a device (Doesnt exist in original version)
+            .service(web::resource("/file/device/{device_name}/health/history").name("/file/device/{device_name}/health/history")
+                .route(web::get().to(get_health_history))) // Health samples of a device between from and to, optionally averaged into steps (Doesnt exist in original version)
+            .service(web::resource("/file/device/{device_name}/stats").name("/file/device/{device_name}/stats")
+                .route(web::get().to(get_device_stats))) // Resource usage statistics of a device over a window, downsampled for charts (Doesnt exist in original version)
+            .service(web::resource("/file/device/{device_name}/interfaces/probe").name("/file/device/{device_name}/interfaces/probe")
+                .route(web::post().to(probe_device_interfaces))) // Probe the supervisor interfaces of a device whose description lists none (Doesnt exist in original version)
+            .service(web::resource("/file/device/{device_name}/deployments").name("/file/device/{device_name}/deployments")
+                .route(web::get().to(get_device_deployments))) // Deployments placed on a device with the functions they run on it (Doesnt exist in original version)
+            .service(web::resource("/file/device/{device_name}/drain").name("/file/device/{device_name}/drain")
+                .route(web::post().to(drain_device)) // Move the active deployments of a device to other devices and mark it as drained (Doesnt exist in original version)
+                .route(web::delete().to(undrain_device))) // Let a drained device take deployments again (Doesnt exist in original version)
+            .service(web::resource("/file/device/{device_name}/outputs/{deployment_id}").name("/file/device/{device_name}/outputs/{deployment_id}")
+                .route(web::post().to(upload_execution_outputs)) // Supervisors can push output files produced during execution through this endpoint
+                .route(web::get().to(get_execution_outputs)) // List output files a device has pushed for a deployment
+                .route(web::delete().to(delete_execution_outputs))) // Delete output files a device has pushed for a deployment
+            .service(web::resource("/file/device/{device_name}/outputs/{deployment_id}/{output_id}").name("/file/device/{device_name}/outputs/{deployment_id}/{output_id}")
+                .route(web::get().to(get_execution_output_file))) // Download a single pushed output file
 
             // Log related routes (file: routes/logs)
             // Status of implementations:
             // ✅ GET /device/logs
             // ✅ POST /device/logs
+            // ✅ GET /device/logs/export
             .service(web::resource("/device/logs").name("/device/logs")
                 .route(web::get().to(get_supervisor_logs)) // Get all supervisor logs from database
-                .route(web::post().to(post_supervisor_log))) // Save a supervisor log to database
+                .route(web::post().to(post_supervisor_log))) // Save a supervisor log, or a batch of them, to database (as a form or as JSON)
+            .service(web::resource("/device/logs/export").name("/device/logs/export")
+                .route(web::get().to(export_supervisor_logs))) // Download the filtered logs as NDJSON or CSV (Doesnt exist in original version)
 
             // Module related routes (file: routes/modules)
             // Status of implementations:
@@ -219,21 +426,30 @@ async fn main() -> std::io::Result<()> {
             // ✅ DELETE /file/module
             // ✅ GET /file/module/{module_id}
             // ✅ DELETE /file/module/{module_id}
+            // ✅ PATCH /file/module/{module_id}
             // ✅ POST /file/module/{module_id}/upload
             // ✅ GET /file/module/{module_id}/description
+            // ✅ GET /file/module/{module_id}/usage
+            // ✅ POST /file/module/{module_id}/run
             // ✅ GET /file/module/{module_id}/{file_name}
             // ✅ GET /file/module/{module_id}/wasm
             .service(web::resource("/file/module").name("/file/module")
+                .wrap(from_fn(idempotent_requests)) // Repeats of a POST with the same Idempotency-Key get the original response (Doesnt exist in original version)
                 .route(web::post().to(create_module)) // Post a new module (requires file upload)
-                .route(web::get().to(get_all_modules)) // Get a list of all modules
+                .route(web::get().to(get_all_modules)) // Get a list of all modules, filterable by tag, exportsFunction and name
                 .route(web::delete().to(delete_all_modules))) // Delete all modules
             .service(web::resource("/file/module/{module_id}").name("/file/module/{module_id}")
                 .route(web::get().to(get_module_by_id)) // Gets a specific module
-                .route(web::delete().to(delete_module_by_id))) // Deletes a specific module
+                .route(web::patch().to(update_module_metadata)) // Updates the tags and description text of a module (Doesnt exist in original version)
+                .route(web::delete().to(delete_module_by_id))) // Deletes a specific module, 409 if deployments use it unless force=true
             .service(web::resource("/file/module/{module_id}/upload").name("/file/module/{module_id}/upload")
                 .route(web::post().to(describe_module))) // Uploads module description for a specific module?
             .service(web::resource("/file/module/{module_id}/description").name("/file/module/{module_id}/description")
                 .route(web::get().to(get_module_description_by_id))) // Gets the module description of a specific module
+            .service(web::resource("/file/module/{module_id}/usage").name("/file/module/{module_id}/usage")
+                .route(web::get().to(get_module_usage))) // Deployments that use the module, with their devices and latest execution (Doesnt exist in original version)
+            .service(web::resource("/file/module/{module_id}/run").name("/file/module/{module_id}/run")
+                .route(web::post().to(run_module_function))) // Test runs a single function of the module on a device (Doesnt exist in original version)
             .service(web::resource("/file/module/{module_id}/wasm").name("/file/module/{module_id}/wasm")
                 .route(web::get().to(get_module_wasm))) // Gets the wasm file related to the module
             .service(web::resource("/file/module/{module_id}/{file_name}").name("/file/module/{module_id}/{file_name}")
@@ -248,7 +464,9 @@ async fn main() -> std::io::Result<()> {
             // ✅ POST /file/manifest/{deployment_id}
             // ✅ PUT /file/manifest/{deployment_id}
             // ✅ DELETE /file/manifest/{deployment_id}
+            // ✅ PUT /file/manifest/{deployment_id}/config
             .service(web::resource("/file/manifest").name("/file/manifest")
+                .wrap(from_fn(idempotent_requests)) // Repeats of a POST with the same Idempotency-Key get the original response (Doesnt exist in original version)
                 .route(web::get().to(get_deployments)) // Get a list of all deployments/manifests
                 .route(web::post().to(create_deployment)) // Create a new deployment/manifest
                 .route(web::delete().to(delete_deployments))) // Delete all deployments/manifests
@@ -257,12 +475,39 @@ async fn main() -> std::io::Result<()> {
                 .route(web::post().to(http_deploy)) // Deploy a specific deployment/manifest (send necessary files etc to supervisor/s)
                 .route(web::put().to(update_deployment)) // Update a specific deployment/manifest
                 .route(web::delete().to(delete_deployment))) // Delete a specific deployment/manifest
+            .service(web::resource("/file/manifest/{deployment_id}/config").name("/file/manifest/{deployment_id}/config")
+                .route(web::put().to(update_deployment_config))) // Replace the configuration of a deployment, and redeploy it if active (Doesnt exist in original)
 
             // Execution related routes (file: routes/execution)
             // Status of implementations:
             // ✅ POST /execute/{deployment_id}
+            // ✅ GET /execute/queue
+            // ✅ GET /execute/queue/{execution_id}
+            // ✅ PUT /execute/queue/{execution_id}
+            // ✅ DELETE /execute/queue/{execution_id}
+            // ✅ DELETE /execute/jobs/{job_id}
+            // ✅ POST /execute/jobs/{job_id}/rerun
+            // ✅ GET /execute/results
+            // ✅ GET /execute/results/{job_id}
+            // ✅ GET /execute/{deployment_id}/logs
+            .service(web::resource("/execute/queue").name("/execute/queue")
+                .route(web::get().to(get_execution_queue))) // List running and pending executions per deployment and device (Doesnt exist in original version)
+            .service(web::resource("/execute/queue/{execution_id}").name("/execute/queue/{execution_id}")
+                .route(web::get().to(get_queued_execution)) // A queued or recently finished execution, for following async executions (Doesnt exist in original version)
+                .route(web::put().to(update_queued_execution)) // Change the priority of a pending execution or move it to the front (Doesnt exist in original version)
+                .route(web::delete().to(delete_queued_execution))) // Drop a pending execution from the queue (Doesnt exist in original version)
+            .service(web::resource("/execute/jobs/{job_id}").name("/execute/jobs/{job_id}")
+                .route(web::delete().to(cancel_execution))) // Cancel a pending or running execution (Doesnt exist in original version)
+            .service(web::resource("/execute/jobs/{job_id}/rerun").name("/execute/jobs/{job_id}/rerun")
+                .route(web::post().to(rerun_execution))) // Run an execution again with its stored inputs (Doesnt exist in original version)
+            .service(web::resource("/execute/results").name("/execute/results")
+                .route(web::get().to(get_execution_results))) // List stored execution results, filtered by deployment, status and time (Doesnt exist in original version)
+            .service(web::resource("/execute/results/{job_id}").name("/execute/results/{job_id}")
+                .route(web::get().to(get_execution_result))) // Stored result of an execution by its job id (Doesnt exist in original version)
             .service(web::resource("/execute/{deployment_id}").name("/execute/{deployment_id}")
                 .route(web::post().to(execute))) // Execute a specific deployment/manifest (assumes it has been deployed earlier)
+            .service(web::resource("/execute/{deployment_id}/logs").name("/execute/{deployment_id}/logs")
+                .route(web::get().to(get_execution_logs))) // Supervisor logs of an execution, merged across devices (Doesnt exist in original version)
 
             // Data source card related routes (file: routes/dataSourceCards)
             // Status of implementations:
@@ -270,23 +515,39 @@ async fn main() -> std::io::Result<()> {
             // ✅ POST /dataSourceCards
             // ✅ DELETE /dataSourceCards
             // ✅ DELETE /dataSourceCards/{node_id}
+            // ✅ PUT /dataSourceCards/{card_id}
+            // ✅ GET /dataSourceCards/{card_id}
             .service(web::resource("/dataSourceCards").name("/dataSourceCards")
                 .route(web::get().to(get_data_source_card)) // Get all data source cards
                 .route(web::post().to(create_data_source_card)) // Create a new data source card
                 .route(web::delete().to(delete_all_data_source_cards))) // Delete all data source cards (Doesnt exist in original)
             .service(web::resource("/dataSourceCards/{node_id}").name("/dataSourceCards/{node_id}")
+                .route(web::get().to(get_data_source_card_by_id)) // Get a data source card by its card id, with the device it refers to (Doesnt exist in original)
+                .route(web::put().to(update_data_source_card)) // Partially update a data source card by its card id (Doesnt exist in original)
                 .route(web::delete().to(delete_data_source_card_by_nodeid))) // Delete a specific data source card (Doesnt exist in original)
 
             // Deployment certificate related routes (file: routes/deploymentCertificates)
             // Status of implementations:
             // ✅ GET /deploymentCertificates
             // ✅ DELETE /deploymentCertificates
-            // ✅ DELETE /deploymentCertificates/{deployment_id}
+            // ✅ POST /deploymentCertificates/revalidate
+            // ✅ GET /deploymentCertificates/{certificate_id}
+            // ✅ DELETE /deploymentCertificates/deployment/{deployment_id}
             .service(web::resource("/deploymentCertificates").name("/deploymentCertificates")
-                .route(web::get().to(get_deployment_certificates)) // Get a list of all deployment certificates (created by the orchestrator, not the user)
+                .route(web::get().to(get_deployment_certificates)) // Get a list of deployment certificates (created by the orchestrator, not the user), filterable by deploymentId, valid, after and before
                 .route(web::delete().to(delete_all_deployment_certificates))) // Delete all deployment certificates
-            .service(web::resource("/deploymentCertificates/{deployment_id}").name("/deploymentCertificates/{deployment_id}")
-                .route(web::delete().to(delete_deployment_certificate))) // Delete a specific deployment certificate
+            .service(web::resource("/deploymentCertificates/revalidate").name("/deploymentCertificates/revalidate")
+                .route(web::post().to(revalidate_all_deployments))) // Revalidate all active deployments against the current cards and zones
+            .service(web::resource("/deploymentCertificates/{id}").name("/deploymentCertificates/{id}")
+                .route(web::get().to(get_deployment_certificate))) // Get a specific deployment certificate by its own id
+            .service(web::resource("/deploymentCertificates/deployment/{deployment_id}").name("/deploymentCertificates/deployment/{deployment_id}")
+                .route(web::delete().to(delete_deployment_certificates_of_deployment))) // Delete all deployment certificates of a specific deployment (DELETE /deploymentCertificates/{deployment_id} in original)
+
+            // Policy debugging routes (Doesnt exist in original)
+            // Status of implementations:
+            // ✅ POST /policies/explain
+            .service(web::resource("/policies/explain").name("/policies/explain")
+                .route(web::post().to(explain_policy))) // Evaluate a hypothetical device, module and datasource combination and return the full trace
 
             // Module card related routes (file: routes/moduleCards)
             // Status of implementations:
@@ -294,11 +555,15 @@ async fn main() -> std::io::Result<()> {
             // ✅ POST /moduleCards
             // ✅ DELETE /moduleCards
             // ✅ DELETE /moduleCards/{card_id}
+            // ✅ PUT /moduleCards/{card_id}
+            // ✅ GET /moduleCards/{card_id}
             .service(web::resource("/moduleCards").name("/moduleCards")
                 .route(web::get().to(get_module_cards)) // Get all module cards
                 .route(web::post().to(create_module_card)) // Create a new module card
                 .route(web::delete().to(delete_all_module_cards))) // Delete all module cards (Doesnt exist in original version)
             .service(web::resource("/moduleCards/{card_id}").name("/moduleCards/{card_id}")
+                .route(web::get().to(get_module_card_by_id)) // Get a module card by its card id, with the module it refers to (Doesnt exist in original version)
+                .route(web::put().to(update_module_card)) // Partially update a module card by its card id (Doesnt exist in original version)
                 .route(web::delete().to(delete_module_card_by_id))) // Delete a specific module card (Doesnt exist in original version)
 
             // Node card related routes (file: routes/nodeCards)
@@ -307,12 +572,33 @@ async fn main() -> std::io::Result<()> {
             // ✅ POST /nodeCards
             // ✅ DELETE /nodeCards
             // ✅ DELETE /nodeCards/{card_id}
+            // ✅ PUT /nodeCards/{card_id}
+            // ✅ GET /nodeCards/{card_id}
+            // ✅ POST /nodeCards/generate/{device_id}
+            // ✅ POST /nodeCards/bulk
             .service(web::resource("/nodeCards").name("/nodeCards")
                 .route(web::get().to(get_node_cards)) // Get all node cards
                 .route(web::post().to(create_node_card)) // Create a new node card
                 .route(web::delete().to(delete_all_node_cards))) // Delete all node cards (Doesnt exist in original version)
+            .service(web::resource("/nodeCards/bulk").name("/nodeCards/bulk")
+                .route(web::post().to(create_node_cards_bulk))) // Create many node cards from an array of ODRL documents or a device,zone CSV, with per-entry results (Doesnt exist in original version)
             .service(web::resource("/nodeCards/{card_id}").name("/nodeCards/{card_id}")
+                .route(web::get().to(get_node_card_by_id)) // Get a node card by its card id, with the device it refers to (Doesnt exist in original version)
+                .route(web::put().to(update_node_card)) // Partially update a node card by its card id (Doesnt exist in original version)
                 .route(web::delete().to(delete_node_card_by_id))) // Delete a specific node card (Doesnt exist in original version)
+            .service(web::resource("/nodeCards/generate/{device_id}").name("/nodeCards/generate/{device_id}")
+                .route(web::post().to(generate_node_card))) // Generate a default node card for a device, zone picked from NODE_CARD_ZONE_RULES (Doesnt exist in original version)
+
+            // Card token related routes
+            // Status of implementations:
+            // ✅ GET /cardTokens
+            // ✅ POST /cardTokens
+            // ✅ DELETE /cardTokens/{token_id}
+            .service(web::resource("/cardTokens").name("/cardTokens")
+                .route(web::get().to(get_card_tokens)) // List the card tokens, token values hidden (Doesnt exist in original version)
+                .route(web::post().to(create_card_token))) // Create a card token for a device or a zone (Doesnt exist in original version)
+            .service(web::resource("/cardTokens/{token_id}").name("/cardTokens/{token_id}")
+                .route(web::delete().to(delete_card_token))) // Revoke a card token (Doesnt exist in original version)
 
             // Zone and risk level related routes (file: routes/zonesAndRiskLevels)
             // TODO: Should multiple definitions for zones and risk levels be allowed
@@ -320,31 +606,152 @@ async fn main() -> std::io::Result<()> {
             // ✅ GET /zoneRiskLevels
             // ✅ POST /zoneRiskLevels
             // ✅ DELETE /zoneRiskLevels
+            // ✅ GET /zoneRiskLevels/{zone}
+            // ✅ PUT /zoneRiskLevels/{zone}
+            // ✅ DELETE /zoneRiskLevels/{zone}
             .service(web::resource("/zoneRiskLevels").name("/zoneRiskLevels")
                 .route(web::get().to(get_zones_and_risk_levels)) // Get zone and risk level card
                 .route(web::post().to(parse_zones_and_risk_levels)) // Create a new zone and risk level card
                 .route(web::delete().to(delete_all_zones_and_risk_levels))) // Delete all zones and risk levels (Doesnt exist in original version)
+            .service(web::resource("/zoneRiskLevels/{zone}").name("/zoneRiskLevels/{zone}")
+                .route(web::get().to(get_zone)) // Get a single zone and its change history (Doesnt exist in original version)
+                .route(web::put().to(update_zone)) // Create or replace a single zone (Doesnt exist in original version)
+                .route(web::delete().to(delete_zone))) // Delete a single zone, keeping its history (Doesnt exist in original version)
 
             // Routes that can be called to import/export the current orchestrator setup from/to the init folder
             // Status of implementations:
             // ✅ GET /export
             // ✅ GET /import
+            // ✅ GET /admin/export/diff
             .service(web::resource("/export").name("/export")
-                .route(web::get().to(handle_orchestrator_export)))
+                .route(web::get().to(handle_orchestrator_export))) // Export into the init folder, or into a named snapshot with ?snapshot=<name>
             .service(web::resource("/import").name("/import")
                 .route(web::get().to(handle_orchestrator_import)))
+            .service(web::resource("/admin/export/diff").name("/admin/export/diff")
+                .route(web::get().to(handle_snapshot_diff))) // Compare current database state with a snapshot (?against=<name>, defaults to the init folder)
+
+            // Administrative routes (files: api/storage, api/outbound, api/watchdog)
+            // Status of implementations:
+            // ✅ GET /admin/storage
+            // ✅ GET /admin/outbound
+            // ✅ GET /admin/loops
+            .service(web::resource("/admin/storage").name("/admin/storage")
+                .route(web::get().to(get_storage_usage))) // Get disk usage of stored files and the configured quota
+            .service(web::resource("/admin/outbound").name("/admin/outbound")
+                .route(web::get().to(get_outbound_stats))) // Get the state of the limiter for concurrent requests to supervisors
+            .service(web::resource("/admin/loops").name("/admin/loops")
+                .route(web::get().to(get_loop_stats))) // Get iteration and restart counters of the background loops (Doesnt exist in original version)
+
+            // Trash related routes (file: api/trash)
+            // Status of implementations:
+            // ✅ GET /trash
+            // ✅ POST /trash/{trash_id}/restore
+            .service(web::resource("/trash").name("/trash")
+                .route(web::get().to(get_trash))) // List deleted modules and deployments that can still be restored (Doesnt exist in original version)
+            .service(web::resource("/trash/{trash_id}/restore").name("/trash/{trash_id}/restore")
+                .route(web::post().to(restore_trash_entry))) // Restore a deleted module or deployment (Doesnt exist in original version)
+
+            // Dashboard related routes (file: api/stats)
+            // Status of implementations:
+            // ✅ GET /stats
+            // ✅ GET /dashboard
+            .service(web::resource("/stats").name("/stats")
+                .route(web::get().to(get_stats))) // Counts and aggregates of devices, modules, deployments, executions and logs for the dashboard (Doesnt exist in original version)
+            .service(web::resource("/dashboard").name("/dashboard")
+                .route(web::get().to(get_dashboard))) // Everything on the front page of the UI in one payload: device and deployment counts, recent executions and errors, last discovery and health check (Doesnt exist in original version)
+
+            // Webhook related routes (files: api/webhooks, lib/webhooks)
+            // Status of implementations:
+            // ✅ GET /webhooks
+            // ✅ POST /webhooks
+            // ✅ DELETE /webhooks/{webhook_id}
+            // ✅ GET /webhooks/deadLetters
+            // ✅ DELETE /webhooks/deadLetters/{dead_letter_id}
+            // ✅ POST /webhooks/deadLetters/{dead_letter_id}/retry
+            .service(web::resource("/webhooks").name("/webhooks")
+                .route(web::get().to(get_webhooks)) // List the webhooks, secrets hidden (Doesnt exist in original version)
+                .route(web::post().to(create_webhook))) // Register a webhook for device status and deployment failure events (Doesnt exist in original version)
+            .service(web::resource("/webhooks/deadLetters").name("/webhooks/deadLetters")
+                .route(web::get().to(get_webhook_dead_letters))) // List the events that couldnt be delivered after all retries (Doesnt exist in original version)
+            .service(web::resource("/webhooks/deadLetters/{dead_letter_id}").name("/webhooks/deadLetters/{dead_letter_id}")
+                .route(web::delete().to(delete_webhook_dead_letter))) // Discard an undelivered event (Doesnt exist in original version)
+            .service(web::resource("/webhooks/deadLetters/{dead_letter_id}/retry").name("/webhooks/deadLetters/{dead_letter_id}/retry")
+                .route(web::post().to(retry_webhook_dead_letter))) // Deliver an undelivered event again (Doesnt exist in original version)
+            .service(web::resource("/webhooks/{webhook_id}").name("/webhooks/{webhook_id}")
+                .route(web::delete().to(delete_webhook))) // Remove a webhook and its undelivered events (Doesnt exist in original version)
+
+            // Event related routes (files: api/events, lib/events)
+            // Status of implementations:
+            // ✅ GET /events
+            .service(web::resource("/events").name("/events")
+                .route(web::get().to(get_event_stream))) // Stream device, deployment and execution events as server-sent events, ?types= limits the event types (Doesnt exist in original version)
+
+            // Audit log related routes (files: api/audit_log, lib/audit_log)
+            // Status of implementations:
+            // ✅ GET /auditLog
+            .service(web::resource("/auditLog").name("/auditLog")
+                .route(web::get().to(get_audit_log))) // List the recorded POST, PUT, PATCH and DELETE calls, with filters on actor, route, target and time (Doesnt exist in original version)
+
+            // API key related routes (files: api/api_keys, lib/api_auth)
+            // Status of implementations:
+            // ✅ GET /apiKeys
+            // ✅ POST /apiKeys
+            // ✅ DELETE /apiKeys/{key_id}
+            .service(web::resource("/apiKeys").name("/apiKeys")
+                .route(web::get().to(get_api_keys)) // List the API keys, hashes hidden (Doesnt exist in original version)
+                .route(web::post().to(create_api_key))) // Create an API key with the read-only, deploy or admin scope (Doesnt exist in original version)
+            .service(web::resource("/apiKeys/{key_id}").name("/apiKeys/{key_id}")
+                .route(web::delete().to(revoke_api_key))) // Revoke an API key (Doesnt exist in original version)
+
+            // User related routes (files: api/users, lib/user_auth)
+            // Status of implementations:
+            // ✅ POST /auth/login
+            // ✅ GET /auth/me
+            // ✅ GET /users
+            // ✅ POST /users
+            // ✅ PUT /users/{user_id}
+            // ✅ DELETE /users/{user_id}
+            .service(web::resource("/auth/login").name("/auth/login")
+                .route(web::post().to(login))) // Log in with username and password to get a user token (Doesnt exist in original version)
+            .service(web::resource("/auth/me").name("/auth/me")
+                .route(web::get().to(get_current_user))) // Get the user of the token (Doesnt exist in original version)
+            .service(web::resource("/users").name("/users")
+                .route(web::get().to(get_users)) // List the users, password hashes hidden (Doesnt exist in original version)
+                .route(web::post().to(create_user))) // Create a user with the viewer, operator or admin role (Doesnt exist in original version)
+            .service(web::resource("/users/{user_id}").name("/users/{user_id}")
+                .route(web::put().to(update_user)) // Change the password or role of a user (Doesnt exist in original version)
+                .route(web::delete().to(delete_user))) // Delete a user (Doesnt exist in original version)
 
             // Miscellaneous routes, none of these exist in original version, but these are possible improvements for functionality
             // Status of implementations:
-            // ❌ POST /postResult
+            // ✅ POST /postResult
+            // ✅ POST /postResult/{job_id}
             .service(web::resource("/postResult").name("/postResult")
-                .route(web::post().to(placeholder))) // For posting intermediary results in a longer chain of functions/modules
+                .route(web::post().to(post_step_result))) // For reporting the finished steps of a chained execution, tracked as its progress
+            .service(web::resource("/postResult/{job_id}").name("/postResult/{job_id}")
+                .route(web::post().to(post_execution_result))) // For posting the final result of an execution to its callback url
 
             // Serve frontend static files
             .service(actix_files::Files::new("/", "./frontend").index_file("index.html"))
             
     })
-    .bind(("0.0.0.0", port))?
-    .run()
-    .await
+    .client_request_timeout(server_config.client_request_timeout())
+    .keep_alive(server_config.keep_alive())
+    .shutdown_timeout(server_config.shutdown_timeout_s)
+    // Counts the open connections for /health
+    .on_connect(connections::track_http_connection)
+    // Signals are handled by the shutdown coordinator, which stops the server last
+    .disable_signals();
+    if let Some(workers) = server_config.workers {
+        server = server.workers(workers);
+    }
+    info!("Listening on {}:{}", server_config.bind_host, server_config.port);
+    let server = server
+        .bind((server_config.bind_host.as_str(), server_config.port))?
+        .run();
+    shutdown::handle_signals(server.handle(), server_config.shutdown_timeout());
+    server.await?;
+
+    shutdown_tracing();
+    Ok(())
 }
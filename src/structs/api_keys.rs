@@ -0,0 +1,47 @@
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Utc};
+use mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime;
+use mongodb::bson::oid::ObjectId;
+
+
+/// Key for calling the management API, sent in the `X-API-Key` header. Only a hash of the
+/// key is stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    #[serde(rename="_id", skip_serializing_if="Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub name: String,
+    #[serde(rename = "keyHash")]
+    pub key_hash: String, // Hex of the SHA-256 of the key
+    pub prefix: String, // First characters of the key, for telling keys apart
+    pub scope: ApiKeyScope,
+    #[serde(rename = "createdAt", with = "chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<Utc>,
+    #[serde(rename = "lastUsedAt", default, skip_serializing_if = "Option::is_none")]
+    pub last_used_at: Option<mongodb::bson::DateTime>,
+    #[serde(rename = "revokedAt", default, skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<mongodb::bson::DateTime>,
+}
+
+
+/// What an API key may do, each scope includes the ones before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ApiKeyScope {
+    #[serde(rename = "read-only")]
+    ReadOnly, // Reading, when API_KEY_AUTH is "all"
+    #[serde(rename = "deploy")]
+    Deploy, // Managing modules and deployments, and executing them
+    #[serde(rename = "admin")]
+    Admin, // Everything, including managing API keys
+}
+
+impl ApiKeyScope {
+    /// Name of the scope as in the API
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiKeyScope::ReadOnly => "read-only",
+            ApiKeyScope::Deploy => "deploy",
+            ApiKeyScope::Admin => "admin",
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use chrono::{DateTime, Utc};
+use mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime;
+use mongodb::bson::oid::ObjectId;
+
+
+/// A mutating API call (POST, PUT, PATCH or DELETE) and its outcome
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditLogEntry {
+    #[serde(rename="_id", skip_serializing_if="Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub actor: String, // "user:<username>", "apiKey:<key id>", "adminKey", "admin", "cardToken:<token id>", "unknownToken" or "anonymous"
+    #[serde(rename = "clientIp")]
+    pub client_ip: Option<String>,
+    pub method: String,
+    pub route: String, // Route pattern, e.g. /file/device/{device_id}
+    pub path: String,
+    #[serde(rename = "targetId")]
+    pub target_id: Option<String>, // Last path parameter, e.g. the id of the deleted device
+    pub request: AuditRequestSummary,
+    pub status: u16,
+    pub outcome: String, // "success" or "failure"
+    pub error: Option<String>,
+    #[serde(rename = "requestId")]
+    pub request_id: Option<String>,
+    #[serde(rename = "durationMs")]
+    pub duration_ms: u64,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub timestamp: DateTime<Utc>,
+}
+
+
+/// What was sent in an audited request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRequestSummary {
+    pub query: Option<String>,
+    #[serde(rename = "contentType")]
+    pub content_type: Option<String>,
+    #[serde(rename = "contentLength")]
+    pub content_length: Option<u64>,
+    pub body: Option<Value>, // Small JSON bodies only, with secrets redacted
+}
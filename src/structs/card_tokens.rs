@@ -0,0 +1,47 @@
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Utc};
+use mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime;
+use mongodb::bson::oid::ObjectId;
+
+
+/// Token that allows submitting node and data source cards for a single device or a zone
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CardToken {
+    #[serde(rename="_id", skip_serializing_if="Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub token: String,
+    #[serde(flatten)]
+    pub scope: CardTokenScope,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(rename = "createdAt", with = "chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<Utc>,
+}
+
+
+/// What a card token may submit cards for
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum CardTokenScope {
+    Device { device: ObjectId },
+    Zone { zone: String },
+}
+
+
+/// Who submitted a card, stored on the card as `submittedBy`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum CardSubmitter {
+    Anonymous, // No token given while CARD_AUTH_REQUIRED is off
+    Admin,
+    Device {
+        device: ObjectId,
+        #[serde(rename = "tokenId")]
+        token_id: ObjectId,
+    },
+    Zone {
+        zone: String,
+        #[serde(rename = "tokenId")]
+        token_id: ObjectId,
+    },
+}
@@ -1,5 +1,6 @@
 use serde::{Serialize, Deserialize};
 use mongodb::bson::oid::ObjectId;
+use crate::structs::card_tokens::CardSubmitter;
 use mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime;
 use chrono::{DateTime, Utc};
 
@@ -14,5 +15,9 @@ pub struct DatasourceCard {
     pub risk_level: String,
     pub nodeid: ObjectId,
     #[serde(rename="dateReceived", with = "chrono_datetime_as_bson_datetime")]
-    pub date_received: DateTime<Utc>
+    pub date_received: DateTime<Utc>,
+    #[serde(rename = "lastUpdated", default, skip_serializing_if = "Option::is_none")]
+    pub last_updated: Option<mongodb::bson::DateTime>, // Set when the card is changed after creation
+    #[serde(rename = "submittedBy", default, skip_serializing_if = "Option::is_none")]
+    pub submitted_by: Option<CardSubmitter>, // None for cards generated by the orchestrator
 }
\ No newline at end of file
@@ -21,6 +21,33 @@ pub struct DeploymentDoc {
     pub full_manifest: HashMap<String, DeploymentNode>,
     #[serde(skip_serializing_if="Option::is_none")]
     pub active: Option<bool>,
+    #[serde(default, skip_serializing_if="HashMap::is_empty")]
+    pub config: HashMap<String, String>, // Deployment specific configuration delivered to the supervisors
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub polling: Option<PollingConfig>, // How execution results are polled, defaults from EXECUTION_POLL_* if missing
+    #[serde(rename="executionTimeoutS", default, skip_serializing_if="Option::is_none")]
+    pub execution_timeout_s: Option<u64>, // Time an execution may take in total, `execution.timeoutS` (EXECUTION_TIMEOUT_S) if missing, 0 for no limit
+    #[serde(rename="lastExecutedAt", default, skip_serializing_if="Option::is_none")]
+    pub last_executed_at: Option<mongodb::bson::DateTime>, // Set when an execution of the deployment starts
+    #[serde(default)]
+    pub revision: u64, // Increased by each update through the API, see lib/concurrency.rs
+}
+
+
+/// Settings for following the result urls returned by supervisors during execution.
+/// Fields that are not set use the EXECUTION_POLL_* defaults.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PollingConfig {
+    #[serde(rename="maxRetries", default, skip_serializing_if="Option::is_none")]
+    pub max_retries: Option<u32>, // How many times a missing (404) result is retried, in total
+    #[serde(rename="intervalMs", default, skip_serializing_if="Option::is_none")]
+    pub interval_ms: Option<u64>, // Wait before the first retry
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub backoff: Option<f64>, // Multiplier applied to the wait after each retry
+    #[serde(rename="deadlineS", default, skip_serializing_if="Option::is_none")]
+    pub deadline_s: Option<u64>, // Total time allowed for polling, 0 for no deadline
+    #[serde(rename="maxDepth", default, skip_serializing_if="Option::is_none")]
+    pub max_depth: Option<u32>, // How many result urls are followed at most
 }
 
 
@@ -29,6 +56,8 @@ pub struct SequenceStep {
     pub device: ObjectId,
     pub module: ObjectId,
     pub func: String,
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub method: Option<String>, // Operation (get, post etc) of the function that is called, the first declared one if not given
 }
 
 
@@ -40,6 +69,12 @@ pub struct DeploymentNode {
     pub endpoints: HashMap<String, HashMap<String, Endpoint>>,
     pub instructions: Instructions,
     pub mounts: HashMap<String, HashMap<String, StageMounts>>,
+    #[serde(default, skip_serializing_if="HashMap::is_empty")]
+    pub config: HashMap<String, String>, // Key-value configuration the modules of this deployment can read
+    // Every declared operation of the functions that have more than one, by module, function and
+    // method. `endpoints` has the one the sequence calls.
+    #[serde(default, skip_serializing_if="HashMap::is_empty")]
+    pub operations: HashMap<String, HashMap<String, HashMap<String, Endpoint>>>,
 }
 
 
@@ -106,6 +141,9 @@ pub struct Instruction {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Instructions {
     pub modules: HashMap<String, HashMap<String, Instruction>>,
+    // Instructions for each operation in `DeploymentNode.operations`, by module, function and method
+    #[serde(default, skip_serializing_if="HashMap::is_empty")]
+    pub operations: HashMap<String, HashMap<String, HashMap<String, Instruction>>>,
 }
 
 
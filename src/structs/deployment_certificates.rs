@@ -14,6 +14,8 @@ pub struct ValidationLog {
     pub output_risk: String,
     pub valid: bool,
     pub reasons: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validator: Option<String>, // Name of the validator that produced the log
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -1,13 +1,35 @@
 use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use mongodb::bson::oid::ObjectId;
+use crate::lib::constants::DEFAULT_URL_SCHEME;
+use crate::lib::utils::base_url;
 
 
-/// Communication details for a device. Includes addresses and port.
+/// Communication details for a device. Includes addresses, port, the url scheme
+/// (http or https) and the base path used when talking to the device.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeviceCommunication {
     pub addresses: Vec<String>,
     pub port: u16,
+    #[serde(default = "default_scheme")]
+    pub scheme: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>, // Base path of the supervisor (e.g. /supervisor), None if served from the root
+}
+impl DeviceCommunication {
+
+    /// Returns the base url of the device (for example http://172.16.0.3:5000 or
+    /// http://[fd00::3]:5000/supervisor), built from the first address and the base path. None
+    /// if the device has no addresses.
+    pub fn base_url(&self) -> Option<String> {
+        let addr = self.addresses.get(0)?;
+        Some(format!("{}{}", base_url(&self.scheme, addr, self.port), self.path.as_deref().unwrap_or_default()))
+    }
+}
+
+/// Scheme used for devices saved before the scheme was stored in the database
+fn default_scheme() -> String {
+    DEFAULT_URL_SCHEME.to_string()
 }
 
 /// CPU information of a device.
@@ -65,6 +87,8 @@ pub struct DeviceDescription {
     pub platform: PlatformInfo,
     #[serde(rename = "supervisorInterfaces")]
     pub supervisor_interfaces: Vec<String>,
+    #[serde(rename = "supervisorVersion", default, skip_serializing_if = "Option::is_none")]
+    pub supervisor_version: Option<String>, // Reported by the supervisor, see lib/compatibility.rs
 }
 
 /// Represents the status of a device: active or inactive.
@@ -90,6 +114,78 @@ pub struct Health {
     // TODO: Uncomment this if you fix the time_of_query being stored as a string
     // #[serde(with = "chrono_datetime_as_bson_datetime")]
     pub time_of_query: chrono::DateTime<chrono::Utc>,
+    #[serde(rename="latencyMs", default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<f64>, // How long the supervisor took to respond to the health check
+}
+
+/// Rolling statistics of the response latencies of a device, of either health checks or
+/// deployments.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LatencyStats {
+    #[serde(rename="recentMs")]
+    pub recent_ms: Vec<f64>, // Latest latencies, newest last, at most DEVICE_LATENCY_WINDOW of them
+    #[serde(rename="averageMs")]
+    pub average_ms: f64, // Average of the recent latencies
+    #[serde(rename="minMs", default)]
+    pub min_ms: f64, // Lowest of the recent latencies
+    #[serde(rename="p95Ms", default)]
+    pub p95_ms: f64, // 95th percentile of the recent latencies (nearest rank)
+}
+
+/// State of draining the deployments off a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DrainState {
+    Draining, // Deployments are being moved to other devices
+    Drained, // All deployments were moved, the device can be taken down
+    Failed, // Some deployments couldnt be moved and are still on the device
+}
+
+/// Drain of a device, as stored in the device document. Devices that are draining or drained
+/// are left out of automatic device selection, and cant be chosen for deployments.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DrainStatus {
+    pub state: DrainState,
+    #[serde(rename="startedAt")]
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    #[serde(rename="finishedAt", default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failed: Vec<String>, // Ids of the deployments that couldnt be moved
+}
+
+impl DrainStatus {
+    /// Whether the device is kept out of new deployments
+    pub fn excludes_device(&self) -> bool {
+        self.state != DrainState::Failed
+    }
+}
+
+/// Body of PATCH /file/device/{device_id}. Fields that are left out are kept as they are.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceUpdate {
+    pub name: Option<String>,
+}
+
+/// State of the circuit breaker of the requests to a device (see lib/circuit_breaker.rs).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BreakerState {
+    Closed, // Requests are sent
+    Open, // Requests fail right away until retryAt
+    HalfOpen, // A trial request is in progress
+}
+
+/// Circuit breaker of a device, as stored in the device document.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CircuitBreakerStatus {
+    pub state: BreakerState,
+    #[serde(rename="consecutiveFailures")]
+    pub consecutive_failures: u32, // Connection failures since the latest answer from the device
+    #[serde(rename="openedAt")]
+    pub opened_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(rename="retryAt")]
+    pub retry_at: Option<chrono::DateTime<chrono::Utc>>, // When the next trial request is let through
 }
 
 /// Network usage statistics for a single network interface.
@@ -99,6 +195,11 @@ pub struct NetworkInterfaceUsage {
     pub down_bytes: u64,     // Total bytes sent since last system start
     #[serde(rename="upBytes")]
     pub up_bytes: u64, // Total bytes received since last system start
+    // Only reported by the orchestrator
+    #[serde(rename="downBytesDelta", default, skip_serializing_if = "Option::is_none")]
+    pub down_bytes_delta: Option<u64>, // Bytes received since the previous health report
+    #[serde(rename="upBytesDelta", default, skip_serializing_if = "Option::is_none")]
+    pub up_bytes_delta: Option<u64>, // Bytes sent since the previous health report
 }
 
 /// The structure of a health report sent by the supervisor.
@@ -113,6 +214,47 @@ pub struct HealthReport {
     pub uptime: u64,          // Uptime in seconds
     #[serde(rename="networkUsage")]
     pub network_usage: HashMap<String, NetworkInterfaceUsage>, // Network usage per interface
+    // The fields below are only reported by the orchestrator, supervisors leave them out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disks: Option<Vec<DiskUsage>>, // Usage per mount point
+    #[serde(rename="processUptime", default, skip_serializing_if = "Option::is_none")]
+    pub process_uptime: Option<u64>, // Seconds since the process was started
+    #[serde(rename="loadAverage", default, skip_serializing_if = "Option::is_none")]
+    pub load_average: Option<LoadAverage>,
+    #[serde(rename="openConnections", default, skip_serializing_if = "Option::is_none")]
+    pub open_connections: Option<ConnectionCounts>,
+}
+
+/// Usage of a single mounted disk.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiskUsage {
+    #[serde(rename="mountPoint")]
+    pub mount_point: String,
+    pub name: String,
+    #[serde(rename="fileSystem")]
+    pub file_system: String,
+    #[serde(rename="totalBytes")]
+    pub total_bytes: u64,
+    #[serde(rename="availableBytes")]
+    pub available_bytes: u64,
+    pub usage: f32, // Used fraction of the total, 0..1
+}
+
+/// Load average of the system over one, five and fifteen minutes. Zero on Windows.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoadAverage {
+    pub one: f64,
+    pub five: f64,
+    pub fifteen: f64,
+}
+
+/// Connections currently open to the orchestrator, by kind.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionCounts {
+    pub http: usize, // Includes the connections streaming /events
+    pub websocket: usize,
+    #[serde(rename="eventStream")]
+    pub event_stream: usize,
 }
 
 
@@ -130,5 +272,21 @@ pub struct DeviceDoc {
     pub ok_health_check_count: u32,
     pub failed_health_check_count: u32,
     pub status_log: Option<Vec<StatusLogEntry>>, // Optional, since status log may not have been generated yet
-    pub health: Option<Health> // Optional, since health report may not have been fetched yet
+    pub health: Option<Health>, // Optional, since health report may not have been fetched yet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency: Option<LatencyStats>, // Optional, since no health checks may have succeeded yet
+    #[serde(rename = "deploymentLatency", default, skip_serializing_if = "Option::is_none")]
+    pub deployment_latency: Option<LatencyStats>, // Optional, since nothing may have been deployed to the device yet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drain: Option<DrainStatus>, // Set while the device is drained or being drained
+    #[serde(rename = "circuitBreaker", default, skip_serializing_if = "Option::is_none")]
+    pub circuit_breaker: Option<CircuitBreakerStatus>, // Optional, since requests to the device may not have failed yet
+    #[serde(rename = "disappearedAt", default, skip_serializing_if = "Option::is_none")]
+    pub disappeared_at: Option<chrono::DateTime<chrono::Utc>>, // Set while a discovered device is missing from discovery scans
+    #[serde(rename = "discoveredBy", default, skip_serializing_if = "Option::is_none")]
+    pub discovered_by: Option<String>, // Discovery backend (mdns or ssdp) that found the device, None if registered otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub properties: Option<BTreeMap<String, String>>, // Properties the supervisor advertises (mDNS TXT record or registration), such as path and tls
+    #[serde(rename = "healthPushedAt", default, skip_serializing_if = "Option::is_none")]
+    pub health_pushed_at: Option<chrono::DateTime<chrono::Utc>>, // When the supervisor last pushed a health report, None if it only gets polled
 }
\ No newline at end of file
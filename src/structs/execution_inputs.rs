@@ -0,0 +1,34 @@
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime;
+
+
+/// Represents the inputs an execution was started with, stored by its job id so that the
+/// execution can be run again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecutionInputDoc {
+    #[serde(rename="_id", skip_serializing_if="Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub execution_id: u64,
+    pub deployment_id: Option<ObjectId>,
+    pub deployment_name: String,
+    pub fields: HashMap<String, String>,
+    #[serde(default)]
+    pub files: Vec<StoredInputFile>,
+    #[serde(default)]
+    pub rerun_of: Option<u64>, // Job id of the execution whose inputs were replayed
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub stored_at: DateTime<Utc>,
+}
+
+
+/// An uploaded input file of an execution, copied to EXECUTION_INPUT_DIR
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredInputFile {
+    pub name: String, // Name of the multipart field the file was sent in
+    pub path: String,
+    pub size: u64,
+}
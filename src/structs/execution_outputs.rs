@@ -0,0 +1,28 @@
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime;
+
+
+/// Represents an output file that a supervisor has pushed to the orchestrator after
+/// executing a part of a deployment.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecutionOutputDoc {
+    #[serde(rename="_id", skip_serializing_if="Option::is_none")]
+    pub id: Option<ObjectId>,
+    #[serde(rename = "deploymentId")]
+    pub deployment_id: ObjectId,
+    pub device: String, // Name of the device that uploaded the file
+    #[serde(rename = "executionId", default, skip_serializing_if = "Option::is_none")]
+    pub execution_id: Option<u64>, // Job id of the execution that produced the file, when the supervisor gave one
+    pub name: String, // Name of the multipart field the file was sent in
+    pub filename: String, // Original filename given by the supervisor
+    pub path: String,
+    pub size: u64,
+    #[serde(rename = "contentType")]
+    pub content_type: String,
+    #[serde(rename = "dateReceived", with = "chrono_datetime_as_bson_datetime")]
+    pub date_received: DateTime<Utc>,
+    #[serde(rename = "archivedAt", default, skip_serializing_if = "Option::is_none")]
+    pub archived_at: Option<mongodb::bson::DateTime>, // Set when the file has been moved to the archive directory
+}
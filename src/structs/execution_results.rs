@@ -0,0 +1,35 @@
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime;
+
+
+/// Represents the final outcome of an execution, stored by the id the execution got in the queue
+/// (its job id).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecutionResultDoc {
+    #[serde(rename="_id", skip_serializing_if="Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub execution_id: u64,
+    pub deployment_id: Option<ObjectId>,
+    pub deployment_name: String,
+    #[serde(default)]
+    pub request_id: Option<String>,
+    pub status: String, // succeeded, failed, timedOut or cancelled
+    #[serde(default)]
+    pub status_code: Option<u16>, // Status of the response the result was returned with
+    #[serde(default)]
+    pub result: Option<Value>, // The result when it is small enough to be stored inline
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result_path: Option<String>, // File the result is stored in when it is too large to be inline
+    pub result_bytes: u64,
+    #[serde(default)]
+    pub error: Option<String>,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub started_at: DateTime<Utc>,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub finished_at: DateTime<Utc>,
+    pub duration_ms: u64,
+}
@@ -0,0 +1,27 @@
+use serde::{Serialize, Deserialize};
+use mongodb::bson::oid::ObjectId;
+
+
+/// A single health sample of a device, stored in the health history time series collection.
+/// Network usage is stored as the bytes transferred since the previous sample, along with the
+/// seconds between the samples for computing transfer rates.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthSample {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    #[serde(rename = "deviceId")]
+    pub device_id: ObjectId,
+    pub time: mongodb::bson::DateTime,
+    #[serde(rename = "cpuUsage")]
+    pub cpu_usage: f32, // CPU usage percentage
+    #[serde(rename = "memoryUsage")]
+    pub memory_usage: f32, // Memory usage percentage
+    #[serde(rename = "latencyMs", default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<f64>,
+    #[serde(rename = "networkDownBytes", default, skip_serializing_if = "Option::is_none")]
+    pub network_down_bytes: Option<u64>, // None for the first sample, or after the device restarted
+    #[serde(rename = "networkUpBytes", default, skip_serializing_if = "Option::is_none")]
+    pub network_up_bytes: Option<u64>,
+    #[serde(rename = "intervalS", default, skip_serializing_if = "Option::is_none")]
+    pub interval_s: Option<f64>, // Seconds since the previous sample, None when there are no network deltas
+}
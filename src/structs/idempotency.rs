@@ -0,0 +1,33 @@
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Utc};
+use mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime;
+
+
+/// Whether the request that first used an idempotency key has finished
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdempotencyState {
+    Pending, // The request is still being handled
+    Completed, // The response is stored and returned to repeats
+}
+
+
+/// An idempotency key and the response to the request that first used it. Removed by MongoDB
+/// (through a TTL index) once `expiresAt` has passed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdempotencyRecord {
+    #[serde(rename = "_id")]
+    pub key: String,
+    pub route: String, // Method and route pattern, e.g. POST /file/manifest
+    pub state: IdempotencyState,
+    #[serde(rename = "createdAt", with = "chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<Utc>,
+    #[serde(rename = "expiresAt", with = "chrono_datetime_as_bson_datetime")]
+    pub expires_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(rename = "contentType", default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+}
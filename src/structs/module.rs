@@ -70,4 +70,34 @@ pub struct ModuleDoc {
     #[serde(default, skip_serializing_if="Option::is_none")]
     pub mounts: Option<HashMap<String, HashMap<String, ModuleMount>>>,
     pub is_core_module: bool,
+    #[serde(rename = "compatibilityWarnings", default, skip_serializing_if="Option::is_none")]
+    pub compatibility_warnings: Option<Vec<CompatibilityWarning>>,
+    #[serde(rename = "missingFiles", default, skip_serializing_if="Option::is_none")]
+    pub missing_files: Option<Vec<String>>, // Files of the module found missing by the startup consistency check
+    #[serde(default, skip_serializing_if="Vec::is_empty")]
+    pub tags: Vec<String>, // Free-form labels for browsing the module catalog
+    #[serde(rename = "descriptionText", default, skip_serializing_if="Option::is_none")]
+    pub description_text: Option<String>, // Human readable description, unlike `description` which is the OpenAPI document
+    #[serde(default)]
+    pub revision: u64, // Increased by each update through the API, see lib/concurrency.rs
+}
+
+/// Body of PATCH /file/module/{module_id}. Fields that are left out are kept as they are.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModuleMetadataUpdate {
+    pub tags: Option<Vec<String>>,
+    #[serde(rename = "descriptionText")]
+    pub description_text: Option<String>, // Empty string removes the description
+}
+
+/// Mismatch between a function description and the actual wasm export, found when the module is described
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompatibilityWarning {
+    pub function: String,
+    #[serde(rename = "declaredParams")]
+    pub declared_params: usize,
+    #[serde(rename = "exportParams")]
+    pub export_params: Option<usize>, // None if the function isnt exported at all
+    pub blocking: bool, // Whether calling the function on a supervisor would fail, deployments using it are refused
+    pub message: String,
 }
\ No newline at end of file
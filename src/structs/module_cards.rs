@@ -16,5 +16,7 @@ pub struct ModuleCard {
     #[serde(rename = "output-risk")]
     pub output_risk: String,
     #[serde(rename="dateReceived", with = "chrono_datetime_as_bson_datetime")]
-    pub date_received: DateTime<Utc>
+    pub date_received: DateTime<Utc>,
+    #[serde(rename = "lastUpdated", default, skip_serializing_if = "Option::is_none")]
+    pub last_updated: Option<mongodb::bson::DateTime>, // Set when the card is changed after creation
 }
\ No newline at end of file
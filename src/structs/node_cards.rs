@@ -2,6 +2,7 @@ use serde::{Serialize, Deserialize};
 use chrono::{DateTime, Utc};
 use mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime;
 use mongodb::bson::oid::ObjectId;
+use crate::structs::card_tokens::CardSubmitter;
 
 
 /// Represents the structure of a node card stored in the database.
@@ -14,4 +15,8 @@ pub struct NodeCard {
     pub zone: String,
     #[serde(rename = "dateReceived", with = "chrono_datetime_as_bson_datetime")]
     pub date_received: DateTime<Utc>,
+    #[serde(rename = "lastUpdated", default, skip_serializing_if = "Option::is_none")]
+    pub last_updated: Option<mongodb::bson::DateTime>, // Set when the card is changed after creation
+    #[serde(rename = "submittedBy", default, skip_serializing_if = "Option::is_none")]
+    pub submitted_by: Option<CardSubmitter>, // None for cards generated by the orchestrator
 }
\ No newline at end of file
@@ -243,11 +243,13 @@ pub enum OpenApiParameterIn {
     RequestBody
 }
 
+// The reference comes first, as every field of a schema object is optional and a reference
+// would otherwise be read as an empty schema
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum OpenApiSchemaEnum {
-    OpenApiSchemaObject(OpenApiSchemaObject),
-    OpenApiReferenceObject(OpenApiReferenceObject)
+    OpenApiReferenceObject(OpenApiReferenceObject),
+    OpenApiSchemaObject(OpenApiSchemaObject)
 }
 
 /// https://spec.openapis.org/oas/v3.0.3.html#parameter-object
@@ -363,9 +365,18 @@ pub struct OpenApiSchemaObject {
     pub format: Option<OpenApiFormat>
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// https://spec.openapis.org/oas/v3.0.3.html#components-object
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct OpenApiComponents {
-    // TODO: When necessary
+    // NOTE: Only the components that can be referred to from the parts the orchestrator uses are implemented here
+    #[serde(default, skip_serializing_if="HashMap::is_empty")]
+    pub schemas: HashMap<String, OpenApiSchemaEnum>,
+    #[serde(default, skip_serializing_if="HashMap::is_empty")]
+    pub responses: HashMap<String, ResponseEnum>,
+    #[serde(default, skip_serializing_if="HashMap::is_empty")]
+    pub parameters: HashMap<String, OpenApiParameterEnum>,
+    #[serde(rename="requestBodies", default, skip_serializing_if="HashMap::is_empty")]
+    pub request_bodies: HashMap<String, RequestBodyEnum>
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
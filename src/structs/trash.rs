@@ -0,0 +1,44 @@
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Utc};
+use mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime;
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::Document;
+
+
+/// Kind of a deleted document, which tells the collection it is restored to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrashKind {
+    Module,
+    Deployment,
+}
+
+
+/// A document deleted along with the trashed one, e.g. the card of a module
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashedDocument {
+    pub collection: String,
+    pub document: Document,
+}
+
+
+/// A deleted module or deployment, kept in the trash until `purgeAt`. Files of the
+/// document are left on disk until it is purged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashEntry {
+    #[serde(rename="_id", skip_serializing_if="Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub kind: TrashKind,
+    #[serde(rename = "originalId")]
+    pub original_id: ObjectId,
+    pub name: String,
+    #[serde(rename = "deletedAt", with = "chrono_datetime_as_bson_datetime")]
+    pub deleted_at: DateTime<Utc>,
+    #[serde(rename = "purgeAt", with = "chrono_datetime_as_bson_datetime")]
+    pub purge_at: DateTime<Utc>,
+    pub document: Document, // The deleted document as it was
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub related: Vec<TrashedDocument>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<String>, // Removed when the entry is purged
+}
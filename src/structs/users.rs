@@ -0,0 +1,62 @@
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Utc};
+use mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime;
+use mongodb::bson::oid::ObjectId;
+use crate::structs::api_keys::ApiKeyScope;
+
+
+/// User account that logs in with a password and is given JWTs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    #[serde(rename="_id", skip_serializing_if="Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub username: String,
+    #[serde(rename = "passwordHash")]
+    pub password_hash: String, // Argon2 PHC string
+    pub role: UserRole,
+    #[serde(rename = "createdAt", with = "chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<Utc>,
+    #[serde(rename = "lastLoginAt", default, skip_serializing_if = "Option::is_none")]
+    pub last_login_at: Option<mongodb::bson::DateTime>,
+}
+
+
+/// Role of a user, each role includes the ones before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UserRole {
+    Viewer, // Reading modules, devices, deployments and logs
+    Operator, // Managing modules and deployments, and executing them
+    Admin, // Everything, including bulk deletes, import and export, and managing users
+}
+
+impl UserRole {
+    /// API key scope with the same permissions as the role
+    pub fn scope(&self) -> ApiKeyScope {
+        match self {
+            UserRole::Viewer => ApiKeyScope::ReadOnly,
+            UserRole::Operator => ApiKeyScope::Deploy,
+            UserRole::Admin => ApiKeyScope::Admin,
+        }
+    }
+
+    /// Name of the role as in the API
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UserRole::Viewer => "viewer",
+            UserRole::Operator => "operator",
+            UserRole::Admin => "admin",
+        }
+    }
+}
+
+
+/// Claims of the JWTs given to users
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserClaims {
+    pub sub: String, // User id
+    pub username: String,
+    pub role: UserRole, // Role when the token was issued, the current role of the user is enforced
+    pub iat: i64,
+    pub exp: i64,
+}
@@ -0,0 +1,40 @@
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Utc};
+use mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime;
+use mongodb::bson::oid::ObjectId;
+
+
+/// HTTP callback that events are posted to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    #[serde(rename="_id", skip_serializing_if="Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub url: String,
+    pub secret: String, // Key of the HMAC-SHA256 signature in X-Webhook-Signature
+    #[serde(default)]
+    pub events: Vec<String>, // Empty means all events
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(rename = "createdAt", with = "chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<Utc>,
+}
+
+
+/// Event that couldnt be delivered to a webhook after all retries
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookDeadLetter {
+    #[serde(rename="_id", skip_serializing_if="Option::is_none")]
+    pub id: Option<ObjectId>,
+    #[serde(rename = "webhookId")]
+    pub webhook_id: ObjectId,
+    pub url: String,
+    pub event: String,
+    #[serde(rename = "deliveryId")]
+    pub delivery_id: String,
+    pub payload: String, // Exact body that was sent, so a retry has the same signature input
+    pub attempts: u32,
+    #[serde(rename = "lastError")]
+    pub last_error: String,
+    #[serde(rename = "failedAt", with = "chrono_datetime_as_bson_datetime")]
+    pub failed_at: DateTime<Utc>,
+}
@@ -12,10 +12,30 @@ pub struct Zones {
     pub zone: Option<String>,
     #[serde(rename = "allowedRiskLevels", skip_serializing_if="Option::is_none")]
     pub allowed_risk_levels: Option<Vec<String>>,
+    #[serde(rename = "maxRiskLevel", default, skip_serializing_if="Option::is_none")]
+    pub max_risk_level: Option<String>, // Highest allowed risk level, when risk levels are ordered
     #[serde(rename = "type", skip_serializing_if="Option::is_none")]
     pub r#type: Option<String>,
     #[serde(rename = "lastUpdated", with = "chrono_datetime_as_bson_datetime")]
     pub last_updated: DateTime<Utc>,
     #[serde(skip_serializing_if="Option::is_none")]
-    pub levels: Option<Vec<String>>
+    pub levels: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub hierarchical: Option<bool>, // Whether levels are ordered from lowest to highest risk
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub history: Option<Vec<ZoneChange>>, // Changes made to a zone, oldest first
+}
+
+
+/// A single change to a zone definition. Deleted zones keep their document
+/// (without allowed risk levels) so that the history is not lost.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ZoneChange {
+    pub action: String, // "set" or "delete"
+    #[serde(rename = "allowedRiskLevels", default, skip_serializing_if="Option::is_none")]
+    pub allowed_risk_levels: Option<Vec<String>>,
+    #[serde(rename = "maxRiskLevel", default, skip_serializing_if="Option::is_none")]
+    pub max_risk_level: Option<String>,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub timestamp: DateTime<Utc>,
 }
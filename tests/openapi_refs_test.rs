@@ -0,0 +1,69 @@
+//! Resolving `$ref`s in module descriptions against their components.
+
+use serde_json::json;
+use orchestrator::lib::openapi_refs::RefResolver;
+use orchestrator::structs::openapi::{OpenApiDocument, OpenApiSchemaEnum, RequestBodyEnum};
+
+
+fn description(components: serde_json::Value) -> OpenApiDocument {
+    serde_json::from_value(json!({
+        "openapi": "3.0.3",
+        "info": { "title": "test", "version": "0.0.1" },
+        "paths": {},
+        "components": components,
+    }))
+    .expect("valid description")
+}
+
+fn reference(to: &str) -> OpenApiSchemaEnum {
+    serde_json::from_value(json!({ "$ref": to })).expect("valid reference")
+}
+
+
+#[test]
+fn references_are_read_as_references() {
+    assert!(matches!(reference("#/components/schemas/Image"), OpenApiSchemaEnum::OpenApiReferenceObject(_)));
+}
+
+
+#[test]
+fn schemas_are_resolved_through_properties_and_chains() {
+    let doc = description(json!({
+        "schemas": {
+            "Upload": { "type": "object", "properties": { "image": { "$ref": "#/components/schemas/Image" } } },
+            "Image": { "$ref": "#/components/schemas/Binary" },
+            "Binary": { "type": "string", "format": "binary" },
+        },
+        "requestBodies": {
+            "Upload": { "content": { "multipart/form-data": { "schema": { "$ref": "#/components/schemas/Upload" } } } },
+        },
+    }));
+    let refs = RefResolver::new(&doc);
+    let body: RequestBodyEnum = serde_json::from_value(json!({ "$ref": "#/components/requestBodies/Upload" })).unwrap();
+    let resolved = refs.request_body(&body).expect("resolved request body");
+
+    let Some(OpenApiSchemaEnum::OpenApiSchemaObject(upload)) = &resolved.content["multipart/form-data"].schema else {
+        panic!("request body schema wasnt resolved");
+    };
+    let Some(OpenApiSchemaEnum::OpenApiSchemaObject(image)) = upload.properties.as_ref().and_then(|p| p.get("image")) else {
+        panic!("property wasnt resolved");
+    };
+    assert_eq!(image.r#type.as_deref(), Some("string"));
+}
+
+
+#[test]
+fn cycles_and_missing_components_are_errors() {
+    let doc = description(json!({
+        "schemas": {
+            "Node": { "type": "object", "properties": { "next": { "$ref": "#/components/schemas/Node" } } },
+            "Leaf": { "type": "integer" },
+        },
+    }));
+    let refs = RefResolver::new(&doc);
+    assert!(refs.schema(&reference("#/components/schemas/Node")).unwrap_err().contains("cycle"));
+    assert!(refs.schema(&reference("#/components/schemas/Missing")).is_err());
+    assert!(refs.schema(&reference("#/components/parameters/Leaf")).is_err());
+    assert!(refs.schema(&reference("other.yaml#/Leaf")).is_err());
+    assert!(refs.schema(&reference("#/components/schemas/Leaf")).is_ok());
+}
@@ -0,0 +1,104 @@
+//! Checking execution requests against the OpenAPI operation of the first step of a deployment.
+
+use std::collections::HashMap;
+use serde_json::json;
+use orchestrator::lib::parameter_validation::{json_arguments, validate_execution_input, LOCATION_REQUEST_BODY, LOCATION_UNKNOWN};
+use orchestrator::structs::deployment::OperationRequest;
+
+
+fn operation(request_body: bool) -> OperationRequest {
+    let mut request = json!({
+        "parameters": [
+            { "name": "count", "in": "query", "required": true, "schema": { "type": "integer", "format": "int32" } },
+            { "name": "scale", "in": "query", "required": false, "schema": { "type": "number" } },
+            { "name": "verbose", "in": "query", "required": false, "schema": { "type": "boolean" } },
+        ],
+    });
+    if request_body {
+        request["request_body"] = json!({
+            "media_type": "multipart/form-data",
+            "schema": { "type": "object", "properties": { "image": { "type": "string", "format": "binary" } } },
+        });
+    }
+    serde_json::from_value(request).expect("valid operation")
+}
+
+fn fields(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+    pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+}
+
+
+#[test]
+fn valid_requests_have_no_violations() {
+    assert!(validate_execution_input(&operation(false), &fields(&[("count", "3")]), &[]).is_empty());
+    let all = fields(&[("count", "-1"), ("scale", "0.5"), ("verbose", "true")]);
+    assert!(validate_execution_input(&operation(true), &all, &["image"]).is_empty());
+}
+
+
+#[test]
+fn missing_and_mistyped_parameters_are_listed() {
+    let violations = validate_execution_input(&operation(false), &fields(&[("scale", "big"), ("verbose", "yes")]), &[]);
+    let names: Vec<&str> = violations.iter().map(|v| v.name.as_str()).collect();
+    assert_eq!(names, ["count", "scale", "verbose"]);
+    assert!(violations.iter().all(|v| v.location == "query"));
+}
+
+
+#[test]
+fn int32_parameters_are_range_checked() {
+    let violations = validate_execution_input(&operation(false), &fields(&[("count", "3000000000")]), &[]);
+    assert_eq!(violations.len(), 1);
+    assert_eq!(violations[0].name, "count");
+}
+
+
+#[test]
+fn extra_fields_are_listed() {
+    let violations = validate_execution_input(&operation(false), &fields(&[("count", "1"), ("colour", "red")]), &[]);
+    assert_eq!(violations.len(), 1);
+    assert_eq!((violations[0].name.as_str(), violations[0].location), ("colour", LOCATION_UNKNOWN));
+}
+
+
+#[test]
+fn request_body_mounts_are_checked() {
+    let count = fields(&[("count", "1")]);
+    let missing_body = validate_execution_input(&operation(true), &count, &[]);
+    assert_eq!(missing_body.len(), 1);
+    assert_eq!(missing_body[0].location, LOCATION_REQUEST_BODY);
+
+    let wrong_mount = validate_execution_input(&operation(true), &count, &["picture"]);
+    let names: Vec<&str> = wrong_mount.iter().map(|v| v.name.as_str()).collect();
+    assert_eq!(names, ["picture", "image"]);
+
+    let unexpected_body = validate_execution_input(&operation(false), &count, &["image"]);
+    assert_eq!(unexpected_body.len(), 1);
+}
+
+
+#[test]
+fn json_arrays_and_single_values_are_mapped_onto_the_parameters() {
+    let request: OperationRequest = serde_json::from_value(json!({
+        "parameters": (0..11).map(|i| json!({ "name": format!("param{}", i), "in": "query", "required": true })).collect::<Vec<_>>(),
+    }))
+    .expect("valid operation");
+    let args = json_arguments(&request, json!((0..11).collect::<Vec<_>>()));
+    assert_eq!(args["param2"], "2");
+    assert_eq!(args["param10"], "10");
+
+    let single: OperationRequest = serde_json::from_value(json!({
+        "parameters": [{ "name": "param0", "in": "query", "required": true, "schema": { "type": "integer" } }],
+    }))
+    .expect("valid operation");
+    assert_eq!(json_arguments(&single, json!(3)), fields(&[("param0", "3")]));
+    assert_eq!(json_arguments(&single, json!({ "param0": 3 })), fields(&[("param0", "3")]));
+    assert_eq!(json_arguments(&single, json!([3, 5])), fields(&[("param0", "3"), ("1", "5")]));
+}
+
+
+#[test]
+fn json_arrays_are_kept_whole_for_operations_with_a_request_body() {
+    let args = json_arguments(&operation(true), json!([1, 2]));
+    assert_eq!(args, fields(&[("body", "[1,2]")]));
+}
@@ -0,0 +1,80 @@
+//! Construction of the urls used to reach devices and the orchestrator, with IPv4 addresses,
+//! IPv6 literals and host names.
+
+use orchestrator::lib::utils::{base_path, base_url, url_host};
+use orchestrator::structs::device::DeviceCommunication;
+
+
+fn communication(addresses: &[&str]) -> DeviceCommunication {
+    DeviceCommunication {
+        addresses: addresses.iter().map(|a| a.to_string()).collect(),
+        port: 5000,
+        scheme: "http".to_string(),
+        path: None,
+    }
+}
+
+
+#[test]
+fn ipv4_addresses_and_host_names_are_kept() {
+    assert_eq!(url_host("172.16.0.3"), "172.16.0.3");
+    assert_eq!(url_host("supervisor.local"), "supervisor.local");
+    assert_eq!(base_url("http", "172.16.0.3", 5000), "http://172.16.0.3:5000");
+    assert_eq!(base_url("https", "supervisor.local", 443), "https://supervisor.local:443");
+}
+
+
+#[test]
+fn ipv6_literals_are_bracketed() {
+    assert_eq!(url_host("fd00::3"), "[fd00::3]");
+    assert_eq!(url_host("::1"), "[::1]");
+    assert_eq!(base_url("http", "2001:db8::1", 3000), "http://[2001:db8::1]:3000");
+}
+
+
+#[test]
+fn bracketed_ipv6_literals_are_not_bracketed_again() {
+    assert_eq!(url_host("[fd00::3]"), "[fd00::3]");
+    assert_eq!(base_url("http", "[fd00::3]", 5000), "http://[fd00::3]:5000");
+}
+
+
+#[test]
+fn ipv6_zone_ids_are_escaped() {
+    assert_eq!(url_host("fe80::1%eth0"), "[fe80::1%25eth0]");
+}
+
+
+#[test]
+fn device_base_url_uses_the_first_address() {
+    assert_eq!(communication(&["fd00::3", "10.0.0.3"]).base_url().as_deref(), Some("http://[fd00::3]:5000"));
+    assert_eq!(communication(&["10.0.0.3", "fd00::3"]).base_url().as_deref(), Some("http://10.0.0.3:5000"));
+    assert_eq!(communication(&[]).base_url(), None);
+}
+
+
+#[test]
+fn base_urls_parse_as_urls() {
+    for host in ["10.0.0.3", "fd00::3", "supervisor.local"] {
+        let url = reqwest::Url::parse(&base_url("http", host, 5000)).expect("valid url");
+        assert_eq!(url.port(), Some(5000));
+    }
+}
+
+
+#[test]
+fn base_paths_are_normalized() {
+    assert_eq!(base_path("/"), None);
+    assert_eq!(base_path(""), None);
+    assert_eq!(base_path("supervisor"), Some("/supervisor".to_string()));
+    assert_eq!(base_path("/supervisor/"), Some("/supervisor".to_string()));
+    assert_eq!(base_path(" /a/b/ "), Some("/a/b".to_string()));
+}
+
+
+#[test]
+fn device_base_url_ends_with_the_base_path() {
+    let mut device = communication(&["fd00::3"]);
+    device.path = base_path("/supervisor/");
+    assert_eq!(device.base_url().as_deref(), Some("http://[fd00::3]:5000/supervisor"));
+}
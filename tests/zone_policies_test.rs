@@ -0,0 +1,100 @@
+//! Checks of risk levels against zone definitions, with ordered (hierarchical) risk levels
+//! and with exact matching.
+
+use chrono::Utc;
+use orchestrator::api::zones_and_risk_levels::ZonePolicies;
+use orchestrator::structs::zones::Zones;
+
+
+fn levels(names: &[&str]) -> Vec<String> {
+    names.iter().map(|n| n.to_string()).collect()
+}
+
+
+fn zone(name: &str, allowed: &[&str], max: Option<&str>) -> Zones {
+    Zones {
+        id: None,
+        zone: Some(name.to_string()),
+        allowed_risk_levels: Some(levels(allowed)),
+        max_risk_level: max.map(|m| m.to_string()),
+        r#type: None,
+        last_updated: Utc::now(),
+        levels: None,
+        hierarchical: None,
+        history: None,
+    }
+}
+
+
+fn risk_levels(order: &[&str], hierarchical: bool) -> Zones {
+    Zones {
+        id: None,
+        zone: None,
+        allowed_risk_levels: None,
+        max_risk_level: None,
+        r#type: Some("riskLevels".to_string()),
+        last_updated: Utc::now(),
+        levels: Some(levels(order)),
+        hierarchical: Some(hierarchical),
+        history: None,
+    }
+}
+
+
+fn policies(hierarchical: bool) -> ZonePolicies {
+    ZonePolicies::from_docs(vec![
+        zone("factory", &["low"], Some("medium")),
+        risk_levels(&["low", "medium", "high"], hierarchical),
+    ])
+}
+
+
+#[test]
+fn levels_below_and_at_the_max_are_allowed() {
+    let policies = policies(true);
+    assert!(policies.allows("factory", "low"));
+    assert!(policies.allows("factory", "medium"));
+}
+
+
+#[test]
+fn levels_above_the_max_are_rejected() {
+    assert!(!policies(true).allows("factory", "high"));
+}
+
+
+#[test]
+fn levels_missing_from_the_order_are_rejected() {
+    let policies = policies(true);
+    assert!(!policies.allows("factory", "critical"));
+    assert_eq!(policies.describe("factory").risk_level_order, Some(levels(&["low", "medium", "high"])));
+}
+
+
+#[test]
+fn a_max_missing_from_the_order_allows_only_the_listed_levels() {
+    let policies = ZonePolicies::from_docs(vec![
+        zone("factory", &["low"], Some("critical")),
+        risk_levels(&["low", "medium", "high"], true),
+    ]);
+    assert!(policies.allows("factory", "low"));
+    assert!(!policies.allows("factory", "medium"));
+}
+
+
+#[test]
+fn unordered_levels_must_match_exactly() {
+    let policies = policies(false);
+    assert!(policies.allows("factory", "low"));
+    assert!(!policies.allows("factory", "medium"));
+    assert!(!policies.allows("factory", "high"));
+    assert_eq!(policies.describe("factory").risk_level_order, None);
+}
+
+
+#[test]
+fn undefined_zones_allow_nothing() {
+    let policies = policies(true);
+    assert!(!policies.allows("office", "low"));
+    assert!(!policies.describe("office").defined);
+}